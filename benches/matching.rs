@@ -1,33 +1,57 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use rand::{rngs::StdRng, Rng, SeedableRng};
 
 use hypermarket_clob::matching::orderbook::{IncomingOrder, OrderBook};
 use hypermarket_clob::models::{OrderType, Side, TimeInForce};
 
+fn order(rng: &mut StdRng, i: u64) -> IncomingOrder {
+    IncomingOrder {
+        order_id: i + 1,
+        subaccount_id: 1,
+        side: if i.is_multiple_of(2) { Side::Buy } else { Side::Sell },
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks: 100 + rng.gen_range(0..10),
+        qty: 1,
+        reduce_only: false,
+        ingress_seq: i,
+    }
+}
+
 fn bench_matching(c: &mut Criterion) {
     c.bench_function("match_1m_orders", |b| {
-        b.iter(|| {
-            let mut book = OrderBook::new();
-            let mut rng = StdRng::seed_from_u64(42);
-            for i in 0..1_000_000u64 {
-                let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
-                let price = 100 + rng.gen_range(0..10);
-                let order = IncomingOrder {
-                    order_id: i + 1,
-                    subaccount_id: 1,
-                    side,
-                    order_type: OrderType::Limit,
-                    tif: TimeInForce::Gtc,
-                    price_ticks: price,
-                    qty: 1,
-                    reduce_only: false,
-                    ingress_seq: i,
-                };
-                let _ = book.place_order(order, 10);
-            }
-        })
+        b.iter_batched(
+            OrderBook::new,
+            |mut book| {
+                let mut rng = StdRng::seed_from_u64(42);
+                for i in 0..1_000_000u64 {
+                    let _ = book.place_order(order(&mut rng, i), 10);
+                }
+                book
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+/// Books are built in the untimed setup, so the measurement is matching into pre-sized storage.
+fn bench_matching_pooled(c: &mut Criterion) {
+    c.bench_function("match_1m_orders_pooled", |b| {
+        let mut fills = Vec::with_capacity(16);
+        b.iter_batched(
+            || OrderBook::with_capacity(1_000_000),
+            |mut book| {
+                let mut rng = StdRng::seed_from_u64(42);
+                for i in 0..1_000_000u64 {
+                    fills.clear();
+                    let _ = book.place_order_into(order(&mut rng, i), 10, &mut fills);
+                }
+                book
+            },
+            BatchSize::PerIteration,
+        )
     });
 }
 
-criterion_group!(benches, bench_matching);
+criterion_group!(benches, bench_matching, bench_matching_pooled);
 criterion_main!(benches);
@@ -1,6 +1,17 @@
 use std::io::Result;
 
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["proto/engine.proto"], &["proto/"])?;
+    // Without the `proto` feature there is no `models::pb` to generate.
+    #[cfg(feature = "proto")]
+    {
+        // `bytes` fields decode as `Bytes` slices of the input buffer instead of copied `Vec<u8>`s.
+        prost_build::Config::new()
+            .bytes(["."])
+            // serde derives back the JSON wire codec; field and oneof names follow the .proto.
+            .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+            .message_attribute(".", "#[serde(default)]")
+            .enum_attribute(".", "#[serde(rename_all = \"snake_case\")]")
+            .compile_protos(&["proto/engine.proto", "proto/snapshot.proto"], &["proto/"])?;
+    }
     Ok(())
 }
@@ -0,0 +1,274 @@
+use core::cmp::Ordering;
+
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+
+use crate::orderbook::IncomingOrder;
+use crate::models::{Fill, OrderId, OrderType, PriceTicks, Quantity, Side, TimeInForce};
+
+#[derive(Debug, Default)]
+pub struct BatchAuction {
+    pub pending: Vec<IncomingOrder>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClearingResult {
+    pub price: PriceTicks,
+    pub volume: u64,
+}
+
+/// What the auction would clear at if it uncrossed now, published while orders are collected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndicativeAuction {
+    pub price: PriceTicks,
+    pub matched_qty: u64,
+    pub imbalance_qty: u64,
+    /// Side with unmatched interest at `price`; `None` when balanced.
+    pub imbalance_side: Option<Side>,
+}
+
+impl BatchAuction {
+    /// Indicative uncross for the orders collected so far, or `None` if the auction is empty.
+    pub fn indicative(&self, mark_price: PriceTicks) -> Option<IndicativeAuction> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(settle(&self.pending, mark_price).0)
+    }
+
+    pub fn push(&mut self, order: IncomingOrder) {
+        self.pending.push(order);
+    }
+
+    /// Uncrosses every pending order at one price. IOC and FOK orders take part in this clear only,
+    /// like market orders; only the unfilled remainders of GTC and GTD limit orders are handed back
+    /// as resting.
+    pub fn clear(&mut self, mark_price: PriceTicks) -> (ClearingResult, Vec<Fill>, Vec<IncomingOrder>) {
+        let orders = core::mem::take(&mut self.pending);
+        if orders.is_empty() {
+            return (
+                ClearingResult {
+                    price: mark_price,
+                    volume: 0,
+                },
+                Vec::new(),
+                Vec::new(),
+            );
+        }
+
+        let (indicative, fills) = settle(&orders, mark_price);
+        let best = ClearingResult {
+            price: indicative.price,
+            volume: indicative.matched_qty,
+        };
+
+        let mut filled: HashMap<OrderId, Quantity> = HashMap::new();
+        for fill in &fills {
+            *filled.entry(fill.maker_order_id).or_default() += fill.qty;
+            *filled.entry(fill.taker_order_id).or_default() += fill.qty;
+        }
+        let mut resting = Vec::new();
+        for mut order in orders {
+            order.qty = order.qty.saturating_sub(filled.get(&order.order_id).copied().unwrap_or(0));
+            if order.qty > 0 && order.tif.rests() && order.order_type != OrderType::Market {
+                resting.push(order);
+            }
+        }
+
+        (best, fills, resting)
+    }
+}
+
+/// Uncross and allocation of `orders`; the reported matched quantity is what was allocated. A FOK
+/// order executes only if it is filled in full at the clearing price; otherwise the latest such
+/// order is left out and the auction uncrossed again without it, until every FOK order still in is
+/// filled in full.
+fn settle(orders: &[IncomingOrder], mark_price: PriceTicks) -> (IndicativeAuction, Vec<Fill>) {
+    let mut live: Vec<IncomingOrder> = orders.to_vec();
+    loop {
+        let mut indicative = uncross(&live, mark_price);
+        let fills = allocate(&live, indicative.price, indicative.matched_qty);
+        // Self-match prevention can leave part of the uncrossed volume unallocated.
+        indicative.matched_qty = fills.iter().map(|fill| fill.qty).sum();
+        let mut filled: HashMap<OrderId, Quantity> = HashMap::new();
+        for fill in &fills {
+            *filled.entry(fill.maker_order_id).or_default() += fill.qty;
+            *filled.entry(fill.taker_order_id).or_default() += fill.qty;
+        }
+        let short = live
+            .iter()
+            .filter(|order| order.tif == TimeInForce::Fok && filled.get(&order.order_id).copied().unwrap_or(0) < order.qty)
+            .max_by_key(|order| order.ingress_seq)
+            .map(|order| order.order_id);
+        match short {
+            Some(order_id) => live.retain(|order| order.order_id != order_id),
+            None => return (indicative, fills),
+        }
+    }
+}
+
+/// Matches up to `volume` at `price` between the orders marketable at that price, buys and sells
+/// each in arrival order; the sell is the maker of every fill. A buy never trades against a sell of
+/// its own subaccount: it skips them for the next sells, so self-crossing interest can leave the
+/// allocation short of `volume` and simply carries over unfilled.
+fn allocate(orders: &[IncomingOrder], price: PriceTicks, volume: u64) -> Vec<Fill> {
+    let eligible = |side: Side| {
+        let mut eligible: Vec<IncomingOrder> = orders
+            .iter()
+            .filter(|order| order.side == side && crosses(order, price))
+            .cloned()
+            .collect();
+        eligible.sort_by_key(|order| order.ingress_seq);
+        eligible
+    };
+    let buys = eligible(Side::Buy);
+    let mut sells = eligible(Side::Sell);
+
+    let mut fills = Vec::new();
+    let mut remaining = volume;
+    for buy in buys {
+        let mut wanted = buy.qty.min(remaining);
+        for sell in sells.iter_mut().filter(|sell| sell.subaccount_id != buy.subaccount_id) {
+            if wanted == 0 {
+                break;
+            }
+            if sell.qty == 0 {
+                continue;
+            }
+            let trade_qty = wanted.min(sell.qty);
+            sell.qty -= trade_qty;
+            wanted -= trade_qty;
+            remaining -= trade_qty;
+            fills.push(Fill {
+                market_id: 0,
+                maker_order_id: sell.order_id,
+                taker_order_id: buy.order_id,
+                price_ticks: price,
+                qty: trade_qty,
+                maker_fee: 0,
+                taker_fee: 0,
+                engine_seq: 0,
+                ts: 0,
+                block_trade: false,
+                trade_id: 0,
+                maker_subaccount_id: 0,
+                taker_subaccount_id: 0,
+                maker_remaining_qty: 0,
+                taker_remaining_qty: 0,
+            });
+        }
+        if remaining == 0 {
+            break;
+        }
+    }
+    fills
+}
+
+/// Whether `order` would trade at `price`: market orders always, limit orders at or inside their
+/// limit.
+fn crosses(order: &IncomingOrder, price: PriceTicks) -> bool {
+    order.order_type == OrderType::Market
+        || match order.side {
+            Side::Buy => order.price_ticks >= price,
+            Side::Sell => order.price_ticks <= price,
+        }
+}
+
+/// Picks the uncross price: maximum matched volume, then minimum imbalance, then closest to the
+/// mark, then the lower price.
+fn uncross(orders: &[IncomingOrder], mark_price: PriceTicks) -> IndicativeAuction {
+    let mut candidates: Vec<PriceTicks> = orders
+        .iter()
+        .filter(|o| o.order_type != OrderType::Market)
+        .map(|o| o.price_ticks)
+        .collect();
+    candidates.push(mark_price);
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut best = IndicativeAuction {
+        price: mark_price,
+        matched_qty: 0,
+        imbalance_qty: 0,
+        imbalance_side: None,
+    };
+    let mut best_imbalance = u64::MAX;
+    let mut best_distance = u64::MAX;
+
+    for price in candidates {
+        let (buy, sell, volume) = demand_supply(orders, price);
+        let imbalance = buy.max(sell) - volume;
+        let distance = price.abs_diff(mark_price);
+        let better = volume > best.matched_qty
+            || (volume == best.matched_qty && imbalance < best_imbalance)
+            || (volume == best.matched_qty && imbalance == best_imbalance && distance < best_distance)
+            || (volume == best.matched_qty
+                && imbalance == best_imbalance
+                && distance == best_distance
+                && price < best.price);
+        if better {
+            let imbalance_side = match buy.cmp(&sell) {
+                Ordering::Greater => Some(Side::Buy),
+                Ordering::Less => Some(Side::Sell),
+                Ordering::Equal => None,
+            };
+            best = IndicativeAuction {
+                price,
+                matched_qty: volume,
+                imbalance_qty: imbalance,
+                imbalance_side,
+            };
+            best_imbalance = imbalance;
+            best_distance = distance;
+        }
+    }
+
+    best
+}
+
+/// Demand and supply marketable at `price`, and how much of it can trade. A subaccount's buys
+/// never match its own sells, so besides either side running out, the volume is capped for each
+/// subaccount by what all other subaccounts bring to both sides.
+fn demand_supply(orders: &[IncomingOrder], price: PriceTicks) -> (u64, u64, u64) {
+    let mut buy = 0u64;
+    let mut sell = 0u64;
+    let mut by_subaccount: HashMap<u64, (u64, u64)> = HashMap::new();
+    for order in orders.iter().filter(|order| crosses(order, price)) {
+        let (subaccount_buy, subaccount_sell) = by_subaccount.entry(order.subaccount_id).or_default();
+        match order.side {
+            Side::Buy => {
+                buy += order.qty;
+                *subaccount_buy += order.qty;
+            }
+            Side::Sell => {
+                sell += order.qty;
+                *subaccount_sell += order.qty;
+            }
+        }
+    }
+    let volume = by_subaccount
+        .values()
+        .map(|(subaccount_buy, subaccount_sell)| (buy - subaccount_buy) + (sell - subaccount_sell))
+        .fold(buy.min(sell), u64::min);
+    (buy, sell, volume)
+}
+
+impl PartialEq for IncomingOrder {
+    fn eq(&self, other: &Self) -> bool {
+        self.order_id == other.order_id
+    }
+}
+
+impl Eq for IncomingOrder {}
+
+impl Ord for IncomingOrder {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.ingress_seq.cmp(&other.ingress_seq)
+    }
+}
+
+impl PartialOrd for IncomingOrder {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
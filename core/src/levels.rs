@@ -0,0 +1,256 @@
+//! Price-level storage for one side of an [`OrderBook`](super::orderbook::OrderBook).
+//!
+//! `Tree` is the general-purpose ordered map. `Ladder` is a contiguous, price-indexed array for
+//! markets that trade inside a narrow tick range: lookups are direct indexing and the best level
+//! is cached, with a small ordered map absorbing prices outside the configured range.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::Bound::{Excluded, Unbounded};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{PriceTicks, Quantity};
+
+/// Price-level storage for a market's book. `ladder` trades memory for O(1) level access and is
+/// meant for markets whose prices stay inside `[min_price_ticks, max_price_ticks]`; prices outside
+/// the range still work through an overflow map.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum BookLayout {
+    #[default]
+    Tree,
+    Ladder {
+        min_price_ticks: u64,
+        max_price_ticks: u64,
+    },
+}
+
+/// Hard cap on ladder slots so a misconfigured range cannot allocate unbounded memory.
+pub const MAX_LADDER_SLOTS: usize = 1 << 20;
+
+#[derive(Debug, Default)]
+pub(crate) struct Level {
+    pub(crate) head: Option<usize>,
+    pub(crate) tail: Option<usize>,
+    pub(crate) total_qty: Quantity,
+}
+
+#[derive(Debug)]
+pub(crate) struct Ladder {
+    base: PriceTicks,
+    slots: Vec<Option<Level>>,
+    lo: Option<usize>,
+    hi: Option<usize>,
+    outliers: BTreeMap<PriceTicks, Level>,
+}
+
+impl Ladder {
+    fn new(min_price: PriceTicks, max_price: PriceTicks) -> Self {
+        let span = max_price.saturating_sub(min_price).saturating_add(1);
+        let len = usize::try_from(span).unwrap_or(MAX_LADDER_SLOTS).min(MAX_LADDER_SLOTS);
+        let mut slots = Vec::with_capacity(len);
+        slots.resize_with(len, || None);
+        Self {
+            base: min_price,
+            slots,
+            lo: None,
+            hi: None,
+            outliers: BTreeMap::new(),
+        }
+    }
+
+    fn index(&self, price: PriceTicks) -> Option<usize> {
+        let offset = usize::try_from(price.checked_sub(self.base)?).ok()?;
+        (offset < self.slots.len()).then_some(offset)
+    }
+
+    fn price_at(&self, idx: usize) -> PriceTicks {
+        self.base + idx as PriceTicks
+    }
+
+    fn insert_slot(&mut self, idx: usize) {
+        if self.slots[idx].is_none() {
+            self.slots[idx] = Some(Level::default());
+            self.lo = Some(self.lo.map_or(idx, |lo| lo.min(idx)));
+            self.hi = Some(self.hi.map_or(idx, |hi| hi.max(idx)));
+        }
+    }
+
+    fn remove_slot(&mut self, idx: usize) {
+        if self.slots[idx].take().is_none() {
+            return;
+        }
+        let (Some(lo), Some(hi)) = (self.lo, self.hi) else {
+            return;
+        };
+        if lo == hi {
+            self.lo = None;
+            self.hi = None;
+            return;
+        }
+        if idx == lo {
+            self.lo = (lo + 1..=hi).find(|i| self.slots[*i].is_some());
+        }
+        if idx == hi {
+            self.hi = (lo..hi).rev().find(|i| self.slots[*i].is_some());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum PriceLevels {
+    Tree(BTreeMap<PriceTicks, Level>),
+    Ladder(Box<Ladder>),
+}
+
+impl Default for PriceLevels {
+    fn default() -> Self {
+        PriceLevels::Tree(BTreeMap::new())
+    }
+}
+
+impl PriceLevels {
+    pub(crate) fn ladder(min_price: PriceTicks, max_price: PriceTicks) -> Self {
+        PriceLevels::Ladder(Box::new(Ladder::new(min_price, max_price)))
+    }
+
+    pub(crate) fn first_price(&self) -> Option<PriceTicks> {
+        match self {
+            PriceLevels::Tree(tree) => tree.keys().next().copied(),
+            PriceLevels::Ladder(ladder) => {
+                let inner = ladder.lo.map(|idx| ladder.price_at(idx));
+                let outer = ladder.outliers.keys().next().copied();
+                match (inner, outer) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn last_price(&self) -> Option<PriceTicks> {
+        match self {
+            PriceLevels::Tree(tree) => tree.keys().next_back().copied(),
+            PriceLevels::Ladder(ladder) => {
+                let inner = ladder.hi.map(|idx| ladder.price_at(idx));
+                let outer = ladder.outliers.keys().next_back().copied();
+                match (inner, outer) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+        }
+    }
+
+    /// The lowest occupied price above `price`.
+    pub(crate) fn price_above(&self, price: PriceTicks) -> Option<PriceTicks> {
+        match self {
+            PriceLevels::Tree(tree) => tree.range((Excluded(price), Unbounded)).next().map(|(price, _)| *price),
+            PriceLevels::Ladder(ladder) => {
+                let inner = ladder.lo.zip(ladder.hi).and_then(|(lo, hi)| {
+                    let from = match price.checked_sub(ladder.base) {
+                        Some(offset) => usize::try_from(offset).map_or(usize::MAX, |offset| offset.saturating_add(1)).max(lo),
+                        None => lo,
+                    };
+                    (from..=hi).find(|idx| ladder.slots[*idx].is_some()).map(|idx| ladder.price_at(idx))
+                });
+                let outer = ladder.outliers.range((Excluded(price), Unbounded)).next().map(|(price, _)| *price);
+                match (inner, outer) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+        }
+    }
+
+    /// The highest occupied price below `price`.
+    pub(crate) fn price_below(&self, price: PriceTicks) -> Option<PriceTicks> {
+        match self {
+            PriceLevels::Tree(tree) => tree.range(..price).next_back().map(|(price, _)| *price),
+            PriceLevels::Ladder(ladder) => {
+                let inner = ladder.lo.zip(ladder.hi).and_then(|(lo, hi)| {
+                    let offset = price.checked_sub(ladder.base).filter(|offset| *offset > 0)?;
+                    let to = usize::try_from(offset - 1).unwrap_or(usize::MAX).min(hi);
+                    (lo..=to).rev().find(|idx| ladder.slots[*idx].is_some()).map(|idx| ladder.price_at(idx))
+                });
+                let outer = ladder.outliers.range(..price).next_back().map(|(price, _)| *price);
+                match (inner, outer) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, price: PriceTicks) -> Option<&Level> {
+        match self {
+            PriceLevels::Tree(tree) => tree.get(&price),
+            PriceLevels::Ladder(ladder) => match ladder.index(price) {
+                Some(idx) => ladder.slots[idx].as_ref(),
+                None => ladder.outliers.get(&price),
+            },
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, price: PriceTicks) -> Option<&mut Level> {
+        match self {
+            PriceLevels::Tree(tree) => tree.get_mut(&price),
+            PriceLevels::Ladder(ladder) => match ladder.index(price) {
+                Some(idx) => ladder.slots[idx].as_mut(),
+                None => ladder.outliers.get_mut(&price),
+            },
+        }
+    }
+
+    pub(crate) fn get_or_insert(&mut self, price: PriceTicks) -> &mut Level {
+        match self {
+            PriceLevels::Tree(tree) => tree.entry(price).or_default(),
+            PriceLevels::Ladder(ladder) => match ladder.index(price) {
+                Some(idx) => {
+                    ladder.insert_slot(idx);
+                    ladder.slots[idx].get_or_insert_with(Level::default)
+                }
+                None => ladder.outliers.entry(price).or_default(),
+            },
+        }
+    }
+
+    pub(crate) fn remove(&mut self, price: PriceTicks) {
+        match self {
+            PriceLevels::Tree(tree) => {
+                tree.remove(&price);
+            }
+            PriceLevels::Ladder(ladder) => match ladder.index(price) {
+                Some(idx) => ladder.remove_slot(idx),
+                None => {
+                    ladder.outliers.remove(&price);
+                }
+            },
+        }
+    }
+
+    /// Occupied levels in ascending price order (use `.rev()` for descending).
+    pub(crate) fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = (PriceTicks, &Level)> + '_> {
+        match self {
+            PriceLevels::Tree(tree) => Box::new(tree.iter().map(|(price, level)| (*price, level))),
+            PriceLevels::Ladder(ladder) => {
+                let end = ladder.price_at(ladder.slots.len());
+                let below = ladder.outliers.range(..ladder.base);
+                let above = ladder.outliers.range(end..);
+                let inner = ladder
+                    .slots
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(idx, slot)| slot.as_ref().map(|level| (ladder.price_at(idx), level)));
+                Box::new(
+                    below
+                        .map(|(price, level)| (*price, level))
+                        .chain(inner)
+                        .chain(above.map(|(price, level)| (*price, level))),
+                )
+            }
+        }
+    }
+}
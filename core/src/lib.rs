@@ -0,0 +1,17 @@
+//! Dependency-light matching core: the continuous order book, the batch auction and the model
+//! types they trade in. `no_std` with `alloc` and no tokio, NATS or file IO, so clearing logic
+//! can be verified on wasm32 or embedded in other runtimes; the engine re-exports everything here
+//! from `hypermarket_clob::matching` and `hypermarket_clob::models`.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod batch;
+pub mod levels;
+pub mod models;
+pub mod orderbook;
+
+pub use batch::{BatchAuction, ClearingResult, IndicativeAuction};
+pub use levels::BookLayout;
+pub use orderbook::{BookSnapshot, IncomingOrder, OrderBook, OrderView};
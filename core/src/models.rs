@@ -0,0 +1,77 @@
+//! The subset of the engine's domain types that matching reads and produces.
+
+use serde::{Deserialize, Serialize};
+
+pub type MarketId = u64;
+pub type SubaccountId = u64;
+pub type OrderId = u64;
+pub type PriceTicks = u64;
+pub type Quantity = u64;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderType {
+    Limit,
+    Market,
+    PostOnly,
+    Ioc,
+    Fok,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TimeInForce {
+    Gtc,
+    Ioc,
+    Fok,
+    /// Good-til-date: rests like GTC until the engine clock reaches `expires_at`, then expires.
+    Gtd { expires_at: u64 },
+}
+
+impl TimeInForce {
+    /// Whether an unfilled remainder may rest on the book.
+    pub fn rests(self) -> bool {
+        matches!(self, TimeInForce::Gtc | TimeInForce::Gtd { .. })
+    }
+
+    pub fn expires_at(self) -> Option<u64> {
+        match self {
+            TimeInForce::Gtd { expires_at } => Some(expires_at),
+            _ => None,
+        }
+    }
+}
+
+/// A trade between a resting (maker) and an incoming (taker) order. The book and the auction only
+/// fill in the matching fields; the engine stamps market, fees, sequence, time and trade id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub market_id: MarketId,
+    pub maker_order_id: OrderId,
+    pub taker_order_id: OrderId,
+    pub price_ticks: PriceTicks,
+    pub qty: Quantity,
+    pub maker_fee: i64,
+    pub taker_fee: i64,
+    pub engine_seq: u64,
+    pub ts: u64,
+    /// Set for fills produced by a block trade; the maker side is the seller leg.
+    #[serde(default)]
+    pub block_trade: bool,
+    /// Unique across shards and increasing within one.
+    #[serde(default)]
+    pub trade_id: u64,
+    #[serde(default)]
+    pub maker_subaccount_id: SubaccountId,
+    #[serde(default)]
+    pub taker_subaccount_id: SubaccountId,
+    /// Quantity of each order still open right after this fill.
+    #[serde(default)]
+    pub maker_remaining_qty: Quantity,
+    #[serde(default)]
+    pub taker_remaining_qty: Quantity,
+}
@@ -0,0 +1,549 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+use crate::levels::{BookLayout, Level, PriceLevels};
+use crate::models::{Fill, OrderId, OrderType, PriceTicks, Quantity, Side, TimeInForce};
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct IncomingOrder {
+    pub order_id: OrderId,
+    pub subaccount_id: u64,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub tif: TimeInForce,
+    pub price_ticks: PriceTicks,
+    pub qty: Quantity,
+    pub reduce_only: bool,
+    pub ingress_seq: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BookSnapshot {
+    pub bids: Vec<(PriceTicks, Quantity)>,
+    pub asks: Vec<(PriceTicks, Quantity)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrderView {
+    pub order_id: OrderId,
+    pub subaccount_id: u64,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub tif: TimeInForce,
+    pub price_ticks: PriceTicks,
+    pub remaining: Quantity,
+    pub reduce_only: bool,
+    pub ingress_seq: u64,
+}
+
+#[derive(Debug, Clone)]
+struct OrderNode {
+    order_id: OrderId,
+    subaccount_id: u64,
+    side: Side,
+    /// Submitted type, TIF and reduce-only flag; matching ignores them once the order rests, but
+    /// snapshots keep them.
+    order_type: OrderType,
+    tif: TimeInForce,
+    reduce_only: bool,
+    price_ticks: PriceTicks,
+    remaining: Quantity,
+    next: Option<usize>,
+    prev: Option<usize>,
+    ingress_seq: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct OrderBook {
+    bids: PriceLevels,
+    asks: PriceLevels,
+    orders: slab::Slab<OrderNode>,
+    order_index: HashMap<OrderId, usize>,
+    /// Resting order ids of each subaccount, in id order. A set is kept once emptied so a
+    /// subaccount's orders coming and going does not reallocate it.
+    subaccount_index: HashMap<u64, BTreeSet<OrderId>>,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pre-sizes the order slab and id index so up to `orders` resting orders never reallocate;
+    /// slots freed by fills and cancels are reused by the slab. Levels of the default tree layout
+    /// live in map nodes that are allocated and freed as the number of open prices grows and
+    /// shrinks; a ladder layout (see [`with_layout`](Self::with_layout)) holds them in fixed
+    /// slots, so once every trading subaccount has been seen, matching and resting inside its
+    /// range do not allocate.
+    pub fn with_capacity(orders: usize) -> Self {
+        Self {
+            orders: slab::Slab::with_capacity(orders),
+            order_index: HashMap::with_capacity(orders),
+            ..Self::default()
+        }
+    }
+
+    /// Book with level storage chosen by `layout` (see [`BookLayout`]).
+    pub fn with_layout(orders: usize, layout: BookLayout) -> Self {
+        let mut book = Self::with_capacity(orders);
+        if let BookLayout::Ladder {
+            min_price_ticks,
+            max_price_ticks,
+        } = layout
+        {
+            book.bids = PriceLevels::ladder(min_price_ticks, max_price_ticks);
+            book.asks = PriceLevels::ladder(min_price_ticks, max_price_ticks);
+        }
+        book
+    }
+
+    pub fn resting_orders(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn order_capacity(&self) -> usize {
+        self.orders.capacity()
+    }
+
+    /// Occupied bid and ask price levels.
+    pub fn level_counts(&self) -> (usize, usize) {
+        (self.bids.iter().count(), self.asks.iter().count())
+    }
+
+    pub fn snapshot(&self, depth: usize) -> BookSnapshot {
+        let bids = self
+            .bids
+            .iter()
+            .rev()
+            .take(depth)
+            .map(|(price, level)| (price, level.total_qty))
+            .collect();
+        let asks = self
+            .asks
+            .iter()
+            .take(depth)
+            .map(|(price, level)| (price, level.total_qty))
+            .collect();
+        BookSnapshot { bids, asks }
+    }
+
+    pub fn order_views(&self) -> Vec<OrderView> {
+        self.orders.iter().map(|(_, order)| Self::view(order)).collect()
+    }
+
+    pub fn order_view(&self, order_id: OrderId) -> Option<OrderView> {
+        let idx = self.order_index.get(&order_id)?;
+        self.orders.get(*idx).map(Self::view)
+    }
+
+    /// Resting orders of `subaccount_id`, in order-id order, without scanning the book.
+    pub fn subaccount_orders(&self, subaccount_id: u64) -> impl Iterator<Item = OrderView> + '_ {
+        self.subaccount_index
+            .get(&subaccount_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|order_id| self.order_view(*order_id))
+    }
+
+    fn view(order: &OrderNode) -> OrderView {
+        OrderView {
+            order_id: order.order_id,
+            subaccount_id: order.subaccount_id,
+            side: order.side,
+            order_type: order.order_type,
+            tif: order.tif,
+            price_ticks: order.price_ticks,
+            remaining: order.remaining,
+            reduce_only: order.reduce_only,
+            ingress_seq: order.ingress_seq,
+        }
+    }
+
+    pub fn cancel(&mut self, order_id: OrderId) -> bool {
+        let Some(&idx) = self.order_index.get(&order_id) else {
+            return false;
+        };
+        let Some((side, price_ticks, subaccount_id)) = self.orders.get(idx).map(|order| (order.side, order.price_ticks, order.subaccount_id)) else {
+            return false;
+        };
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let mut remove_level = false;
+        if let Some(level) = levels.get_mut(price_ticks) {
+            Self::detach_from_level(idx, &mut self.orders, level);
+            remove_level = level.total_qty == 0;
+        }
+        if remove_level {
+            levels.remove(price_ticks);
+        }
+        self.orders.remove(idx);
+        self.order_index.remove(&order_id);
+        Self::unindex_subaccount(&mut self.subaccount_index, subaccount_id, order_id);
+        true
+    }
+
+    /// Shrinks a resting order to `new_qty` without changing its place in the level queue.
+    /// Returns false if the order is unknown or `new_qty` is not strictly between 0 and its
+    /// current remaining quantity (use [`cancel`](Self::cancel) to remove it).
+    pub fn reduce_qty(&mut self, order_id: OrderId, new_qty: Quantity) -> bool {
+        let Some(&idx) = self.order_index.get(&order_id) else {
+            return false;
+        };
+        let Some(order) = self.orders.get_mut(idx) else {
+            return false;
+        };
+        if new_qty == 0 || new_qty >= order.remaining {
+            return false;
+        }
+        let delta = order.remaining - new_qty;
+        order.remaining = new_qty;
+        let (side, price_ticks) = (order.side, order.price_ticks);
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        if let Some(level) = levels.get_mut(price_ticks) {
+            level.total_qty = level.total_qty.saturating_sub(delta);
+        }
+        true
+    }
+
+    pub fn has_order(&self, order_id: OrderId) -> bool {
+        self.order_index.contains_key(&order_id)
+    }
+
+    pub fn order_remaining(&self, order_id: OrderId) -> Option<Quantity> {
+        let idx = self.order_index.get(&order_id)?;
+        self.orders.get(*idx).map(|order| order.remaining)
+    }
+
+    pub fn place_order(&mut self, incoming: IncomingOrder, max_matches: usize) -> (Vec<Fill>, Option<OrderId>) {
+        let mut fills = Vec::new();
+        let resting_id = self.place_order_into(incoming, max_matches, &mut fills);
+        (fills, resting_id)
+    }
+
+    /// Like [`place_order`](Self::place_order) but appends fills to a caller-owned buffer, so a
+    /// hot loop that reuses the buffer does not allocate per order.
+    ///
+    /// Self-trade prevention: an order never fills against a resting order of its own subaccount.
+    /// It skips them and trades with the next makers in price-time priority instead, the same
+    /// policy batch auctions apply; a remainder that would then rest crossing its own orders is
+    /// cancelled rather than crossing the book.
+    pub fn place_order_into(&mut self, incoming: IncomingOrder, max_matches: usize, fills: &mut Vec<Fill>) -> Option<OrderId> {
+        if incoming.tif == TimeInForce::Fok {
+            let available = self.available_qty(&incoming);
+            if available < incoming.qty {
+                return None;
+            }
+        }
+        let fills_before = fills.len();
+        let mut remaining = incoming.qty;
+        let mut matches = 0usize;
+
+        while remaining > 0 && matches < max_matches {
+            let Some((best_price, maker_idx)) = self.next_maker(&incoming) else {
+                break;
+            };
+            let levels = match incoming.side {
+                Side::Buy => &mut self.asks,
+                Side::Sell => &mut self.bids,
+            };
+            let Some(level) = levels.get_mut(best_price) else { break };
+            let maker = &mut self.orders[maker_idx];
+            let trade_qty = remaining.min(maker.remaining);
+            remaining -= trade_qty;
+            maker.remaining -= trade_qty;
+            let maker_order_id = maker.order_id;
+            let maker_subaccount_id = maker.subaccount_id;
+            let maker_done = maker.remaining == 0;
+            level.total_qty = level.total_qty.saturating_sub(trade_qty);
+            matches += 1;
+
+            fills.push(Fill {
+                market_id: 0,
+                maker_order_id,
+                taker_order_id: incoming.order_id,
+                price_ticks: best_price,
+                qty: trade_qty,
+                maker_fee: 0,
+                taker_fee: 0,
+                engine_seq: 0,
+                ts: 0,
+                block_trade: false,
+                trade_id: 0,
+                maker_subaccount_id: 0,
+                taker_subaccount_id: 0,
+                maker_remaining_qty: 0,
+                taker_remaining_qty: 0,
+            });
+
+            if maker_done {
+                Self::detach_from_level(maker_idx, &mut self.orders, level);
+                self.orders.remove(maker_idx);
+                self.order_index.remove(&maker_order_id);
+                Self::unindex_subaccount(&mut self.subaccount_index, maker_subaccount_id, maker_order_id);
+            }
+            if level.total_qty == 0 {
+                levels.remove(best_price);
+            }
+        }
+
+        if remaining == 0 {
+            return None;
+        }
+
+        if incoming.order_type == OrderType::Market {
+            return None;
+        }
+
+        // Matching stopped with makers left to take, so anything the order still crosses is its own.
+        let crosses_own = matches < max_matches && self.would_cross(incoming.side, incoming.price_ticks);
+        match incoming.tif {
+            TimeInForce::Ioc => None,
+            TimeInForce::Fok => None,
+            TimeInForce::Gtc | TimeInForce::Gtd { .. } => {
+                if (incoming.order_type == OrderType::PostOnly && fills.len() > fills_before) || crosses_own {
+                    None
+                } else {
+                    Some(self.add_resting(incoming, remaining))
+                }
+            }
+        }
+    }
+
+    /// The resting order `incoming` trades with next: the first in price-time priority that it
+    /// crosses and that another subaccount owns.
+    fn next_maker(&self, incoming: &IncomingOrder) -> Option<(PriceTicks, usize)> {
+        let levels = match incoming.side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        let mut price = match incoming.side {
+            Side::Buy => levels.first_price(),
+            Side::Sell => levels.last_price(),
+        };
+        while let Some(price_ticks) = price {
+            if !Self::crosses(incoming.side, incoming.order_type, incoming.price_ticks, price_ticks) {
+                return None;
+            }
+            let mut cursor = levels.get(price_ticks).and_then(|level| level.head);
+            while let Some(idx) = cursor {
+                let order = &self.orders[idx];
+                if order.subaccount_id != incoming.subaccount_id {
+                    return Some((price_ticks, idx));
+                }
+                cursor = order.next;
+            }
+            price = match incoming.side {
+                Side::Buy => levels.price_above(price_ticks),
+                Side::Sell => levels.price_below(price_ticks),
+            };
+        }
+        None
+    }
+
+    pub fn would_cross(&self, side: Side, price_ticks: PriceTicks) -> bool {
+        match side {
+            Side::Buy => self.asks.first_price().map(|best| price_ticks >= best).unwrap_or(false),
+            Side::Sell => self.bids.last_price().map(|best| price_ticks <= best).unwrap_or(false),
+        }
+    }
+
+    fn add_resting(&mut self, incoming: IncomingOrder, remaining: Quantity) -> OrderId {
+        let level = match incoming.side {
+            Side::Buy => self.bids.get_or_insert(incoming.price_ticks),
+            Side::Sell => self.asks.get_or_insert(incoming.price_ticks),
+        };
+        let idx = self.orders.insert(OrderNode {
+            order_id: incoming.order_id,
+            subaccount_id: incoming.subaccount_id,
+            side: incoming.side,
+            order_type: incoming.order_type,
+            tif: incoming.tif,
+            reduce_only: incoming.reduce_only,
+            price_ticks: incoming.price_ticks,
+            remaining,
+            next: None,
+            prev: level.tail,
+            ingress_seq: incoming.ingress_seq,
+        });
+        if let Some(tail) = level.tail {
+            self.orders[tail].next = Some(idx);
+        }
+        if level.head.is_none() {
+            level.head = Some(idx);
+        }
+        level.tail = Some(idx);
+        level.total_qty += remaining;
+        self.order_index.insert(incoming.order_id, idx);
+        self.subaccount_index.entry(incoming.subaccount_id).or_default().insert(incoming.order_id);
+        incoming.order_id
+    }
+
+    fn unindex_subaccount(index: &mut HashMap<u64, BTreeSet<OrderId>>, subaccount_id: u64, order_id: OrderId) {
+        if let Some(orders) = index.get_mut(&subaccount_id) {
+            orders.remove(&order_id);
+        }
+    }
+
+    fn detach_from_level(idx: usize, orders: &mut slab::Slab<OrderNode>, level: &mut Level) {
+        let (prev, next, remaining) = {
+            let order = &orders[idx];
+            (order.prev, order.next, order.remaining)
+        };
+        if level.head == Some(idx) {
+            level.head = next;
+        }
+        if level.tail == Some(idx) {
+            level.tail = prev;
+        }
+        if let Some(prev) = prev {
+            orders[prev].next = next;
+        }
+        if let Some(next) = next {
+            orders[next].prev = prev;
+        }
+        level.total_qty = level.total_qty.saturating_sub(remaining);
+    }
+
+    fn crosses(side: Side, order_type: OrderType, limit_price: PriceTicks, best_price: PriceTicks) -> bool {
+        match order_type {
+            OrderType::Market => true,
+            _ => match side {
+                Side::Buy => limit_price >= best_price,
+                Side::Sell => limit_price <= best_price,
+            },
+        }
+    }
+
+    fn available_qty(&self, incoming: &IncomingOrder) -> Quantity {
+        let mut available = 0u64;
+        match incoming.side {
+            Side::Buy => {
+                for (price, level) in self.asks.iter() {
+                    if !Self::crosses(incoming.side, incoming.order_type, incoming.price_ticks, price) {
+                        break;
+                    }
+                    available = available.saturating_add(level.total_qty);
+                }
+            }
+            Side::Sell => {
+                for (price, level) in self.bids.iter().rev() {
+                    if !Self::crosses(incoming.side, incoming.order_type, incoming.price_ticks, price) {
+                        break;
+                    }
+                    available = available.saturating_add(level.total_qty);
+                }
+            }
+        }
+        // Its own resting orders are skipped, so they cannot fill it.
+        let own: Quantity = self
+            .subaccount_orders(incoming.subaccount_id)
+            .filter(|order| order.side != incoming.side && Self::crosses(incoming.side, incoming.order_type, incoming.price_ticks, order.price_ticks))
+            .map(|order| order.remaining)
+            .sum();
+        available.saturating_sub(own)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_only_rejects_cross() {
+        let mut book = OrderBook::new();
+        let maker = IncomingOrder {
+            order_id: 1,
+            subaccount_id: 1,
+            side: Side::Sell,
+            order_type: OrderType::Limit,
+            tif: TimeInForce::Gtc,
+            price_ticks: 100,
+            qty: 10,
+            reduce_only: false,
+            ingress_seq: 1,
+        };
+        book.place_order(maker, 10);
+
+        let taker = IncomingOrder {
+            order_id: 2,
+            subaccount_id: 2,
+            side: Side::Buy,
+            order_type: OrderType::PostOnly,
+            tif: TimeInForce::Gtc,
+            price_ticks: 110,
+            qty: 5,
+            reduce_only: false,
+            ingress_seq: 2,
+        };
+
+        assert!(book.would_cross(taker.side, taker.price_ticks));
+    }
+
+    #[test]
+    fn subaccount_index_follows_resting_cancels_and_fills() {
+        let order = |order_id, subaccount_id, side, qty| IncomingOrder {
+            order_id,
+            subaccount_id,
+            side,
+            order_type: OrderType::Limit,
+            tif: TimeInForce::Gtc,
+            price_ticks: 100,
+            qty,
+            reduce_only: false,
+            ingress_seq: order_id,
+        };
+        let ids = |book: &OrderBook, subaccount_id| book.subaccount_orders(subaccount_id).map(|order| order.order_id).collect::<Vec<_>>();
+        let mut book = OrderBook::new();
+        book.place_order(order(3, 1, Side::Sell, 5), 10);
+        book.place_order(order(1, 1, Side::Sell, 5), 10);
+        book.place_order(order(2, 2, Side::Sell, 5), 10);
+        assert_eq!(ids(&book, 1), vec![1, 3]);
+
+        assert!(book.cancel(1));
+        assert_eq!(ids(&book, 1), vec![3]);
+        // Fills the first order in the queue (3) and part of the next (2).
+        book.place_order(order(4, 3, Side::Buy, 7), 10);
+        assert!(ids(&book, 1).is_empty());
+        assert_eq!(book.subaccount_orders(2).map(|order| order.remaining).collect::<Vec<_>>(), vec![3]);
+        assert!(ids(&book, 3).is_empty());
+    }
+
+    #[test]
+    fn reduce_qty_keeps_queue_position_and_level_total() {
+        let order = |order_id, side, qty| IncomingOrder {
+            order_id,
+            subaccount_id: order_id,
+            side,
+            order_type: OrderType::Limit,
+            tif: TimeInForce::Gtc,
+            price_ticks: 100,
+            qty,
+            reduce_only: false,
+            ingress_seq: order_id,
+        };
+        let mut book = OrderBook::new();
+        book.place_order(order(1, Side::Sell, 10), 10);
+        book.place_order(order(2, Side::Sell, 5), 10);
+
+        assert!(book.reduce_qty(1, 4));
+        assert_eq!(book.snapshot(1).asks, vec![(100, 9)]);
+        // Zero, growing, unchanged and unknown orders are refused.
+        assert!(!book.reduce_qty(1, 0));
+        assert!(!book.reduce_qty(1, 4));
+        assert!(!book.reduce_qty(2, 6));
+        assert!(!book.reduce_qty(3, 1));
+
+        // The shrunk order is still first in line.
+        let (fills, _) = book.place_order(order(3, Side::Buy, 4), 10);
+        assert_eq!(fills.iter().map(|fill| (fill.maker_order_id, fill.qty)).collect::<Vec<_>>(), vec![(1, 4)]);
+        assert!(!book.has_order(1));
+        assert_eq!(book.snapshot(1).asks, vec![(100, 5)]);
+    }
+}
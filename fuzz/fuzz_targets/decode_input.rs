@@ -0,0 +1,10 @@
+#![no_main]
+
+use bytes::Bytes;
+use libfuzzer_sys::fuzz_target;
+
+use hypermarket_clob::engine::router::decode_input;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_input(Bytes::copy_from_slice(data));
+});
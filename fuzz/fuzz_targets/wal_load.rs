@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use hypermarket_clob::persistence::wal::Wal;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Wal::decode(data);
+});
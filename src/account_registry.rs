@@ -0,0 +1,60 @@
+use futures::TryStreamExt;
+
+use crate::config::AccountConfig;
+
+/// Parent-account hierarchies stored in a JetStream KV bucket (key = parent_id, value =
+/// `AccountConfig` JSON), mirroring [`market_registry`](crate::market_registry).
+pub async fn load_all(nats_url: &str, bucket: &str) -> anyhow::Result<Vec<AccountConfig>> {
+    let client = async_nats::connect(nats_url).await?;
+    let jetstream = async_nats::jetstream::new(client);
+    let kv = jetstream
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: bucket.to_string(),
+            history: 1,
+            storage: async_nats::jetstream::stream::StorageType::File,
+            ..Default::default()
+        })
+        .await?;
+
+    let keys = kv.keys().await?.try_collect::<Vec<String>>().await?;
+    let mut out = Vec::with_capacity(keys.len());
+    for key in keys {
+        if let Some(value) = kv.get(key).await? {
+            let account: AccountConfig = serde_json::from_slice(&value)?;
+            out.push(account);
+        }
+    }
+    Ok(out)
+}
+
+pub async fn watch_updates_tx(
+    nats_url: String,
+    bucket: String,
+    tx: tokio::sync::mpsc::Sender<AccountConfig>,
+) -> anyhow::Result<()> {
+    use futures::StreamExt;
+
+    let client = async_nats::connect(nats_url).await?;
+    let jetstream = async_nats::jetstream::new(client);
+    let kv = jetstream
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket,
+            history: 1,
+            storage: async_nats::jetstream::stream::StorageType::File,
+            ..Default::default()
+        })
+        .await?;
+
+    let mut watch = kv.watch_all().await?;
+    while let Some(entry) = watch.next().await {
+        let entry = entry?;
+        if entry.operation != async_nats::jetstream::kv::Operation::Put {
+            continue;
+        }
+        let account: AccountConfig = serde_json::from_slice(&entry.value)?;
+        if tx.send(account).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
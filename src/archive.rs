@@ -0,0 +1,457 @@
+//! Cold storage for published outputs, so JetStream retention can stay short without losing history.
+//!
+//! The [`Archiver`] batches outputs per shard as they were published, in whatever codec, and writes
+//! each batch as one zstd-compressed segment to an [`ObjectStore`]: length-prefixed payloads under
+//! `<prefix>/shard=<s>/<first_seq>-<last_seq>.zst`, sequences zero-padded to 20 digits. Next to it
+//! goes a JSON [`ArchiveSegment`] under `<prefix>/index/shard=<s>/<first_seq>.json` giving the
+//! segment's `engine_seq` and `ts` ranges; [`ArchiveIndex`] loads those back to find the segments
+//! holding a sequence or a time range.
+//!
+//! [`run`] acks outputs only once the segment holding them is stored, so a crash re-archives at
+//! most the open batches. Redelivered or resent outputs can land in a second segment whose range
+//! overlaps an earlier one; readers dedupe on `(shard_id, output_seq)`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio_stream::StreamExt;
+use tracing::{info, warn};
+
+use crate::bus::{Bus, BusMessage, BusSubscription};
+use crate::config::{ArchiveConfig, ArchiveStoreConfig, WireCodec};
+use crate::engine::router::parse_output;
+use crate::models::ShardId;
+use crate::persistence::MAX_RECORD_LEN;
+
+/// Flat key-value blob storage the archive is written to.
+#[async_trait::async_trait]
+pub trait ObjectStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Bytes) -> anyhow::Result<()>;
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+    /// Every key starting with `prefix`, in no particular order.
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>>;
+}
+
+/// Opens the store `config` names; `nats_url` is only used for `kind: nats`.
+pub async fn open_store(config: &ArchiveStoreConfig, nats_url: &str) -> anyhow::Result<Arc<dyn ObjectStore>> {
+    Ok(match config {
+        ArchiveStoreConfig::Dir { path } => Arc::new(DirObjectStore::new(path)),
+        ArchiveStoreConfig::Nats { bucket } => Arc::new(NatsObjectStore::connect(nats_url, bucket).await?),
+    })
+}
+
+/// Objects as files under a root directory, e.g. a mounted bucket. Each put is written to a
+/// temporary file and renamed, so a reader never sees a partial object.
+#[derive(Debug, Clone)]
+pub struct DirObjectStore {
+    root: PathBuf,
+}
+
+impl DirObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl ObjectStore for DirObjectStore {
+    async fn put(&self, key: &str, bytes: Bytes) -> anyhow::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, &bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        match tokio::fs::read(self.root.join(key)).await {
+            Ok(bytes) => Ok(Some(Bytes::from(bytes))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let root = self.root.clone();
+        let prefix = prefix.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut keys = Vec::new();
+            walk(&root, &root, &mut keys)?;
+            keys.retain(|key| key.starts_with(&prefix));
+            Ok(keys)
+        })
+        .await?
+    }
+}
+
+fn walk(root: &Path, dir: &Path, keys: &mut Vec<String>) -> anyhow::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(root, &path, keys)?;
+        } else if path.extension().is_none_or(|extension| extension != "tmp") {
+            let relative = path.strip_prefix(root)?;
+            let key: Vec<String> = relative.components().map(|part| part.as_os_str().to_string_lossy().into_owned()).collect();
+            keys.push(key.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// A JetStream object store bucket, created if missing.
+pub struct NatsObjectStore {
+    store: async_nats::jetstream::object_store::ObjectStore,
+}
+
+impl NatsObjectStore {
+    pub async fn connect(nats_url: &str, bucket: &str) -> anyhow::Result<Self> {
+        let client = async_nats::connect(nats_url).await?;
+        let jetstream = async_nats::jetstream::new(client);
+        let store = jetstream
+            .create_object_store(async_nats::jetstream::object_store::Config {
+                bucket: bucket.to_string(),
+                storage: async_nats::jetstream::stream::StorageType::File,
+                ..Default::default()
+            })
+            .await?;
+        Ok(Self { store })
+    }
+}
+
+#[async_trait::async_trait]
+impl ObjectStore for NatsObjectStore {
+    async fn put(&self, key: &str, bytes: Bytes) -> anyhow::Result<()> {
+        self.store.put(key, &mut bytes.as_ref()).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        use async_nats::jetstream::object_store::GetErrorKind;
+        use tokio::io::AsyncReadExt;
+
+        let mut object = match self.store.get(key).await {
+            Ok(object) => object,
+            Err(err) if err.kind() == GetErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut bytes = Vec::new();
+        object.read_to_end(&mut bytes).await?;
+        Ok(Some(Bytes::from(bytes)))
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut objects = self.store.list().await?;
+        let mut keys = Vec::new();
+        while let Some(info) = objects.next().await {
+            let info = info?;
+            if !info.deleted && info.name.starts_with(prefix) {
+                keys.push(info.name);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// Objects in memory, for tests and embedders.
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    objects: Mutex<BTreeMap<String, Bytes>>,
+}
+
+impl MemoryObjectStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl ObjectStore for MemoryObjectStore {
+    async fn put(&self, key: &str, bytes: Bytes) -> anyhow::Result<()> {
+        self.objects.lock().insert(key.to_string(), bytes);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        Ok(self.objects.lock().get(key).cloned())
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self.objects.lock().keys().filter(|key| key.starts_with(prefix)).cloned().collect())
+    }
+}
+
+/// Index entry of one stored segment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveSegment {
+    /// Key of the compressed segment.
+    pub key: String,
+    pub shard_id: ShardId,
+    pub first_seq: u64,
+    pub last_seq: u64,
+    pub first_ts: u64,
+    pub last_ts: u64,
+    /// Outputs the segment holds.
+    pub events: usize,
+    pub raw_bytes: usize,
+    pub compressed_bytes: usize,
+    pub codec: WireCodec,
+}
+
+impl ArchiveSegment {
+    pub fn contains_seq(&self, shard_id: ShardId, engine_seq: u64) -> bool {
+        self.shard_id == shard_id && self.first_seq <= engine_seq && engine_seq <= self.last_seq
+    }
+
+    /// Whether any output of the segment may fall in `from_ts..=to_ts`.
+    pub fn overlaps_ts(&self, from_ts: u64, to_ts: u64) -> bool {
+        self.first_ts <= to_ts && from_ts <= self.last_ts
+    }
+}
+
+/// Outputs of one shard waiting to be written.
+struct Batch {
+    payloads: Vec<Bytes>,
+    raw_bytes: usize,
+    first_seq: u64,
+    last_seq: u64,
+    first_ts: u64,
+    last_ts: u64,
+    opened: Instant,
+}
+
+impl Batch {
+    fn new(engine_seq: u64, ts: u64) -> Self {
+        Self {
+            payloads: Vec::new(),
+            raw_bytes: 0,
+            first_seq: engine_seq,
+            last_seq: engine_seq,
+            first_ts: ts,
+            last_ts: ts,
+            opened: Instant::now(),
+        }
+    }
+}
+
+/// Open batches per shard and the store they are written to.
+pub struct Archiver {
+    store: Arc<dyn ObjectStore>,
+    config: ArchiveConfig,
+    codec: WireCodec,
+    batches: BTreeMap<ShardId, Batch>,
+}
+
+impl Archiver {
+    pub fn new(store: Arc<dyn ObjectStore>, config: ArchiveConfig, codec: WireCodec) -> Self {
+        Self {
+            store,
+            config,
+            codec,
+            batches: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a published output to its shard's batch and returns that shard, or an error for a
+    /// payload that does not decode as an output.
+    pub fn push(&mut self, payload: Bytes) -> anyhow::Result<ShardId> {
+        let output = parse_output(self.codec, payload.clone())?;
+        anyhow::ensure!(payload.len() <= MAX_RECORD_LEN, "output of {} bytes exceeds record limit", payload.len());
+        let shard_id = output.shard_id as ShardId;
+        let batch = self.batches.entry(shard_id).or_insert_with(|| Batch::new(output.engine_seq, output.ts));
+        batch.first_seq = batch.first_seq.min(output.engine_seq);
+        batch.last_seq = batch.last_seq.max(output.engine_seq);
+        batch.first_ts = batch.first_ts.min(output.ts);
+        batch.last_ts = batch.last_ts.max(output.ts);
+        batch.raw_bytes += 4 + payload.len();
+        batch.payloads.push(payload);
+        Ok(shard_id)
+    }
+
+    /// Whether `shard_id`'s batch has reached a size limit.
+    pub fn is_full(&self, shard_id: ShardId) -> bool {
+        self.batches.get(&shard_id).is_some_and(|batch| {
+            batch.payloads.len() >= self.config.max_batch_events || batch.raw_bytes >= self.config.max_batch_bytes
+        })
+    }
+
+    /// Shards whose batch has been open for at least `flush_interval_secs`.
+    pub fn expired(&self) -> Vec<ShardId> {
+        let interval = Duration::from_secs(self.config.flush_interval_secs);
+        self.batches
+            .iter()
+            .filter(|(_, batch)| batch.opened.elapsed() >= interval)
+            .map(|(shard_id, _)| *shard_id)
+            .collect()
+    }
+
+    /// Writes `shard_id`'s batch and then its index entry, and returns the entry. A failed write
+    /// keeps the batch for the next attempt.
+    pub async fn flush(&mut self, shard_id: ShardId) -> anyhow::Result<Option<ArchiveSegment>> {
+        let Some(batch) = self.batches.get(&shard_id) else { return Ok(None) };
+        let mut raw = BytesMut::with_capacity(batch.raw_bytes);
+        for payload in &batch.payloads {
+            raw.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            raw.extend_from_slice(payload);
+        }
+        let compressed = zstd::bulk::compress(&raw, self.config.compression_level)?;
+        let segment = ArchiveSegment {
+            key: format!(
+                "{}/shard={shard_id}/{:020}-{:020}.zst",
+                self.config.prefix, batch.first_seq, batch.last_seq
+            ),
+            shard_id,
+            first_seq: batch.first_seq,
+            last_seq: batch.last_seq,
+            first_ts: batch.first_ts,
+            last_ts: batch.last_ts,
+            events: batch.payloads.len(),
+            raw_bytes: raw.len(),
+            compressed_bytes: compressed.len(),
+            codec: self.codec,
+        };
+        // The segment goes first, so every index entry names a stored segment.
+        self.store.put(&segment.key, Bytes::from(compressed)).await?;
+        let index_key = index_key(&self.config.prefix, shard_id, segment.first_seq);
+        self.store.put(&index_key, Bytes::from(serde_json::to_vec(&segment)?)).await?;
+
+        metrics::counter!("archive_segments_total").increment(1);
+        metrics::counter!("archive_events_total").increment(segment.events as u64);
+        metrics::counter!("archive_compressed_bytes_total").increment(segment.compressed_bytes as u64);
+        self.batches.remove(&shard_id);
+        Ok(Some(segment))
+    }
+
+    /// Flushes every open batch.
+    pub async fn flush_all(&mut self) -> anyhow::Result<Vec<ArchiveSegment>> {
+        let shards: Vec<ShardId> = self.batches.keys().copied().collect();
+        let mut flushed = Vec::with_capacity(shards.len());
+        for shard_id in shards {
+            flushed.extend(self.flush(shard_id).await?);
+        }
+        Ok(flushed)
+    }
+}
+
+fn index_key(prefix: &str, shard_id: ShardId, first_seq: u64) -> String {
+    format!("{prefix}/index/shard={shard_id}/{first_seq:020}.json")
+}
+
+/// Archives outputs from `subscription` until it ends, then flushes what is left. Each message is
+/// acked once the segment holding it is stored; outputs that do not decode are acked and skipped,
+/// since they cannot be placed in a shard's sequence.
+pub async fn run(bus: &dyn Bus, mut subscription: BusSubscription, archiver: &mut Archiver) -> anyhow::Result<()> {
+    let mut unacked: BTreeMap<ShardId, Vec<BusMessage>> = BTreeMap::new();
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            message = subscription.stream.next() => {
+                let Some(message) = message else { break };
+                match archiver.push(message.payload.clone()) {
+                    Ok(shard_id) => {
+                        unacked.entry(shard_id).or_default().push(message);
+                        if archiver.is_full(shard_id) {
+                            let segment = archiver.flush(shard_id).await?;
+                            ack_flushed(bus, segment, &mut unacked).await?;
+                        }
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "skipping output the archiver cannot decode");
+                        metrics::counter!("archive_skipped_total").increment(1);
+                        bus.ack(message).await?;
+                    }
+                }
+            }
+            _ = tick.tick() => {
+                for shard_id in archiver.expired() {
+                    let segment = archiver.flush(shard_id).await?;
+                    ack_flushed(bus, segment, &mut unacked).await?;
+                }
+            }
+        }
+    }
+    for segment in archiver.flush_all().await? {
+        ack_flushed(bus, Some(segment), &mut unacked).await?;
+    }
+    Ok(())
+}
+
+async fn ack_flushed(
+    bus: &dyn Bus,
+    segment: Option<ArchiveSegment>,
+    unacked: &mut BTreeMap<ShardId, Vec<BusMessage>>,
+) -> anyhow::Result<()> {
+    let Some(segment) = segment else { return Ok(()) };
+    info!(
+        key = %segment.key,
+        events = segment.events,
+        first_seq = segment.first_seq,
+        last_seq = segment.last_seq,
+        compressed_bytes = segment.compressed_bytes,
+        "archived segment"
+    );
+    for message in unacked.remove(&segment.shard_id).unwrap_or_default() {
+        bus.ack(message).await?;
+    }
+    Ok(())
+}
+
+/// Every segment written under a prefix, by shard and then first sequence.
+#[derive(Debug, Default, Clone)]
+pub struct ArchiveIndex {
+    pub segments: Vec<ArchiveSegment>,
+}
+
+impl ArchiveIndex {
+    pub async fn load(store: &dyn ObjectStore, prefix: &str) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        for key in store.list(&format!("{prefix}/index/")).await? {
+            let Some(bytes) = store.get(&key).await? else { continue };
+            segments.push(serde_json::from_slice::<ArchiveSegment>(&bytes)?);
+        }
+        segments.sort_by_key(|segment| (segment.shard_id, segment.first_seq, segment.last_seq));
+        Ok(Self { segments })
+    }
+
+    /// Segments of `shard_id` holding `engine_seq`.
+    pub fn by_seq(&self, shard_id: ShardId, engine_seq: u64) -> Vec<&ArchiveSegment> {
+        self.segments.iter().filter(|segment| segment.contains_seq(shard_id, engine_seq)).collect()
+    }
+
+    /// Segments of any shard with outputs in `from_ts..=to_ts`.
+    pub fn by_time(&self, from_ts: u64, to_ts: u64) -> Vec<&ArchiveSegment> {
+        self.segments.iter().filter(|segment| segment.overlaps_ts(from_ts, to_ts)).collect()
+    }
+}
+
+/// The outputs of a stored segment, as published; decode them with `segment.codec`.
+pub async fn read_segment(store: &dyn ObjectStore, segment: &ArchiveSegment) -> anyhow::Result<Vec<Bytes>> {
+    let compressed = store
+        .get(&segment.key)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("segment {} is missing", segment.key))?;
+    let raw = zstd::bulk::decompress(&compressed, segment.raw_bytes)?;
+    let mut payloads = Vec::with_capacity(segment.events);
+    let mut offset = 0usize;
+    while offset < raw.len() {
+        anyhow::ensure!(raw.len() - offset >= 4, "segment {} ends in a partial length", segment.key);
+        let len = u32::from_le_bytes(raw[offset..offset + 4].try_into().expect("four bytes")) as usize;
+        anyhow::ensure!(len <= raw.len() - offset - 4, "segment {} record at {offset} overruns it", segment.key);
+        payloads.push(Bytes::copy_from_slice(&raw[offset + 4..offset + 4 + len]));
+        offset += 4 + len;
+    }
+    anyhow::ensure!(payloads.len() == segment.events, "segment {} holds {} outputs, index says {}", segment.key, payloads.len(), segment.events);
+    Ok(payloads)
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+
+use hypermarket_clob::archive::{self, ArchiveIndex, Archiver};
+use hypermarket_clob::bus::nats::JetStreamBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::config::Settings;
+
+/// Copies everything published on the output subject to `persistence.archive`, or with `--index`
+/// lists the segments already archived.
+#[derive(Parser, Debug)]
+#[command(name = "archiver")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    /// Print the archive index instead of archiving.
+    #[arg(long)]
+    index: bool,
+    /// With `--index`, only segments with outputs at or after this ts.
+    #[arg(long, default_value_t = 0)]
+    from_ts: u64,
+    /// With `--index`, only segments with outputs at or before this ts.
+    #[arg(long, default_value_t = u64::MAX)]
+    to_ts: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .json()
+        .init();
+
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let config = settings
+        .persistence
+        .archive
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("persistence.archive is not configured"))?;
+    let store = archive::open_store(&config.store, &settings.bus.nats_url).await?;
+
+    if args.index {
+        let index = ArchiveIndex::load(store.as_ref(), &config.prefix).await?;
+        for segment in index.by_time(args.from_ts, args.to_ts) {
+            println!(
+                "shard={} seq={}..={} ts={}..={} events={} bytes={} key={}",
+                segment.shard_id,
+                segment.first_seq,
+                segment.last_seq,
+                segment.first_ts,
+                segment.last_ts,
+                segment.events,
+                segment.compressed_bytes,
+                segment.key
+            );
+        }
+        return Ok(());
+    }
+
+    // A durable consumer of its own: outputs are acked only once their segment is stored, so a
+    // restart resumes after the last stored segment. Every shard's open batch stays unacked until
+    // then, which the ack window has to cover.
+    let ack_wait = Duration::from_secs(config.flush_interval_secs.saturating_mul(2).max(30));
+    let max_ack_pending = config.max_batch_events.saturating_mul(settings.shard_count).saturating_add(1_000);
+    let bus = JetStreamBus::connect(
+        &settings.bus.nats_url,
+        settings.bus.stream_name.clone(),
+        vec![settings.bus.output_subject.clone()],
+        format!("{}-archiver", settings.bus.durable_name),
+    )
+    .await?
+    .with_ack_window(ack_wait, max_ack_pending);
+    let subscription = bus.subscribe(&settings.bus.output_subject).await?;
+    let mut archiver = Archiver::new(store, config, settings.bus.codec);
+    archive::run(&bus, subscription, &mut archiver).await
+}
@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::Duration;
+
+use clap::Parser;
+use tokio_stream::StreamExt;
+
+use hypermarket_clob::bus::nats::JetStreamBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::config::Settings;
+use hypermarket_clob::engine::router::decode_output_with;
+use hypermarket_clob::models::{BookLevel, Event, Fill, MarketId};
+
+/// Live depth ladder and last trades of one market, redrawn in the terminal from the book deltas
+/// and fills on the output subject and the depth snapshots on the book snapshot subject. It only
+/// reads; quit with Ctrl-C.
+#[derive(Parser, Debug)]
+#[command(name = "bookwatch")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    #[arg(long)]
+    market_id: u64,
+    /// Price levels shown on each side.
+    #[arg(long, default_value_t = 10)]
+    depth: usize,
+    /// Trades shown, newest first.
+    #[arg(long, default_value_t = 15)]
+    trades: usize,
+    #[arg(long, default_value_t = 250)]
+    refresh_ms: u64,
+}
+
+/// What the screen shows; levels are kept as published, best first.
+#[derive(Default)]
+struct View {
+    market_id: MarketId,
+    bids: Vec<BookLevel>,
+    asks: Vec<BookLevel>,
+    engine_seq: u64,
+    ts: u64,
+    trades: VecDeque<Fill>,
+    dirty: bool,
+}
+
+impl View {
+    fn apply(&mut self, event: Event, max_trades: usize) {
+        match event {
+            Event::BookDelta(delta) if delta.market_id == self.market_id => {
+                self.set_levels(delta.bids_levels, delta.asks_levels, delta.engine_seq, delta.ts)
+            }
+            Event::DepthSnapshot(snapshot) if snapshot.market_id == self.market_id => {
+                self.set_levels(snapshot.bids_levels, snapshot.asks_levels, snapshot.engine_seq, snapshot.ts)
+            }
+            Event::Fill(fill) if fill.market_id == self.market_id => {
+                self.trades.push_front(fill);
+                self.trades.truncate(max_trades);
+                self.dirty = true;
+            }
+            _ => {}
+        }
+    }
+
+    /// Both subjects carry full levels, so the newest message by `engine_seq` wins.
+    fn set_levels(&mut self, bids: Vec<BookLevel>, asks: Vec<BookLevel>, engine_seq: u64, ts: u64) {
+        if engine_seq < self.engine_seq {
+            return;
+        }
+        self.bids = bids;
+        self.asks = asks;
+        self.engine_seq = engine_seq;
+        self.ts = ts;
+        self.dirty = true;
+    }
+
+    fn render(&self, depth: usize) -> String {
+        let asks: Vec<&BookLevel> = self.asks.iter().take(depth).collect();
+        let bids: Vec<&BookLevel> = self.bids.iter().take(depth).collect();
+        let widest = asks.iter().chain(&bids).map(|level| level.qty).max().unwrap_or(0).max(1);
+        let bar = |qty: u64| "#".repeat(((qty as u128 * 30).div_ceil(widest as u128)) as usize);
+
+        let mut out = String::new();
+        let _ = writeln!(out, "market {}  engine_seq {}  ts {}", self.market_id, self.engine_seq, self.ts);
+        let _ = writeln!(out, "{:>14} {:>14} {:>14}", "price", "qty", "cumulative");
+        let cumulative: Vec<u64> = asks
+            .iter()
+            .scan(0u64, |total, level| {
+                *total += level.qty;
+                Some(*total)
+            })
+            .collect();
+        for (level, total) in asks.iter().zip(&cumulative).rev() {
+            let _ = writeln!(out, "\x1b[31m{:>14} {:>14} {:>14}  {}\x1b[0m", level.price_ticks, level.qty, total, bar(level.qty));
+        }
+        let spread = match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => format!("spread {}", ask.price_ticks.saturating_sub(bid.price_ticks)),
+            _ => "spread -".to_string(),
+        };
+        let _ = writeln!(out, "{:-^46}", format!(" {spread} "));
+        let mut total = 0u64;
+        for level in &bids {
+            total += level.qty;
+            let _ = writeln!(out, "\x1b[32m{:>14} {:>14} {:>14}  {}\x1b[0m", level.price_ticks, level.qty, total, bar(level.qty));
+        }
+
+        let _ = writeln!(out, "\nlast trades");
+        let _ = writeln!(out, "{:>14} {:>14} {:>20} {:>12}", "price", "qty", "ts", "trade_id");
+        // A tick arrow against the previous trade, since fills do not name the aggressor side.
+        for (idx, fill) in self.trades.iter().enumerate() {
+            let (colour, arrow) = match self.trades.get(idx + 1) {
+                Some(previous) if fill.price_ticks > previous.price_ticks => ("\x1b[32m", "^"),
+                Some(previous) if fill.price_ticks < previous.price_ticks => ("\x1b[31m", "v"),
+                _ => ("", " "),
+            };
+            let block = if fill.block_trade { " block" } else { "" };
+            let _ = writeln!(
+                out,
+                "{colour}{:>13}{arrow} {:>14} {:>20} {:>12}{block}\x1b[0m",
+                fill.price_ticks, fill.qty, fill.ts, fill.trade_id
+            );
+        }
+        out
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let bus = JetStreamBus::connect(
+        &settings.bus.nats_url,
+        settings.bus.stream_name.clone(),
+        vec![settings.bus.output_subject.clone(), settings.bus.book_snapshot_subject.clone()],
+        format!("{}-bookwatch", settings.bus.durable_name),
+    )
+    .await?;
+    // Ephemeral consumers: the viewer starts at the live edge and leaves nothing behind.
+    let mut outputs = bus.subscribe_ephemeral(&settings.bus.output_subject).await?;
+    let mut snapshots = bus.subscribe_ephemeral(&settings.bus.book_snapshot_subject).await?;
+    let codec = settings.bus.codec;
+
+    let mut view = View {
+        market_id: args.market_id,
+        dirty: true,
+        ..View::default()
+    };
+    let mut redraw = tokio::time::interval(Duration::from_millis(args.refresh_ms.max(10)));
+    loop {
+        tokio::select! {
+            message = outputs.stream.next() => {
+                let Some(message) = message else { anyhow::bail!("output subscription closed") };
+                if let Ok(event) = decode_output_with(codec, message.payload) {
+                    view.apply(event, args.trades);
+                }
+            }
+            message = snapshots.stream.next() => {
+                let Some(message) = message else { anyhow::bail!("book snapshot subscription closed") };
+                if let Ok(event) = decode_output_with(codec, message.payload) {
+                    view.apply(event, args.trades);
+                }
+            }
+            _ = redraw.tick() => {
+                if view.dirty {
+                    view.dirty = false;
+                    let mut stdout = std::io::stdout().lock();
+                    write!(stdout, "\x1b[H\x1b[2J{}", view.render(args.depth))?;
+                    stdout.flush()?;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+
+use hypermarket_clob::bus::dead_letter;
+use hypermarket_clob::bus::nats::JetStreamBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::config::Settings;
+
+/// Lists the inputs parked on the dead-letter subject, or replays them onto the input subject.
+#[derive(Parser, Debug)]
+#[command(name = "dead_letters")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    /// Republish every dead-lettered payload on the input subject and remove it from the queue.
+    #[arg(long)]
+    replay: bool,
+    /// Stop once no dead letter has arrived for this long.
+    #[arg(long, default_value_t = 1_000)]
+    idle_ms: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    // A durable consumer of its own, so listing leaves the letters for the next run.
+    let bus = JetStreamBus::connect(
+        &settings.bus.nats_url,
+        settings.bus.stream_name.clone(),
+        vec![settings.bus.input_subject.clone(), settings.bus.dead_letter_subject.clone()],
+        format!("{}-dead-letters", settings.bus.durable_name),
+    )
+    .await?;
+    let bus: Arc<dyn Bus> = Arc::new(bus);
+    let mut subscription = bus.subscribe(&settings.bus.dead_letter_subject).await?;
+    let input_subject = args.replay.then_some(settings.bus.input_subject.as_str());
+    let letters = dead_letter::drain(
+        bus.as_ref(),
+        &mut subscription,
+        settings.bus.codec,
+        input_subject,
+        Duration::from_millis(args.idle_ms),
+    )
+    .await?;
+
+    for letter in &letters {
+        println!(
+            "ts={} stage={} deliveries={} bytes={} error={}",
+            letter.ts,
+            letter.stage,
+            letter.deliveries,
+            letter.payload.len(),
+            letter.error
+        );
+    }
+    let action = if args.replay { "replayed" } else { "pending" };
+    println!("{action}={}", letters.len());
+    Ok(())
+}
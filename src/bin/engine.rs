@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use tracing_subscriber::EnvFilter;
@@ -28,9 +29,23 @@ async fn main() -> anyhow::Result<()> {
     let bus = JetStreamBus::connect(
         &settings.bus.nats_url,
         settings.bus.stream_name.clone(),
-        vec![settings.bus.input_subject.clone(), settings.bus.output_subject.clone()],
+        vec![
+            settings.bus.input_subject.clone(),
+            settings.bus.output_subject.clone(),
+            settings.bus.dead_letter_subject.clone(),
+            settings.bus.resend_subject.clone(),
+            settings.bus.adl_subject.clone(),
+            settings.bus.trades_subject.clone(),
+            settings.bus.equity_subject.clone(),
+            format!("{}.>", settings.bus.account_subject),
+            settings.bus.book_snapshot_subject.clone(),
+        ],
         settings.bus.durable_name.clone(),
     )
-    .await?;
+    .await?
+    .with_redelivery(
+        Duration::from_secs(settings.bus.ack_wait_secs),
+        settings.bus.max_deliver,
+    );
     run_router(settings, Arc::new(bus)).await
 }
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+use hypermarket_clob::bus::nats::JetStreamBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::client::ClobClient;
+use hypermarket_clob::config::Settings;
+use hypermarket_clob::gateway::{self, PrivateStreams, StreamAuth};
+use hypermarket_clob::key_registry;
+
+#[derive(Parser, Debug)]
+#[command(name = "gateway")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    #[arg(long, default_value = "0.0.0.0:8080")]
+    listen: String,
+    /// How long to wait for the engine's ack before answering 504.
+    #[arg(long, default_value_t = 5_000)]
+    ack_timeout_ms: u64,
+    /// JSON object mapping private stream bearer tokens to the subaccount each grants.
+    #[arg(long)]
+    stream_tokens: Option<String>,
+    /// How far a signed private stream request's `ts` may be from this host's clock.
+    #[arg(long, default_value_t = 30)]
+    stream_max_skew_secs: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .json()
+        .init();
+
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    // A durable consumer of its own, separate from the engine's input consumer.
+    let bus = JetStreamBus::connect(
+        &settings.bus.nats_url,
+        settings.bus.stream_name.clone(),
+        vec![
+            settings.bus.input_subject.clone(),
+            settings.bus.output_subject.clone(),
+            format!("{}.>", settings.bus.account_subject),
+        ],
+        format!("{}-gateway", settings.bus.durable_name),
+    )
+    .await?;
+    let bus: Arc<dyn Bus> = Arc::new(bus);
+    let client = ClobClient::connect_with_codec(
+        Arc::clone(&bus),
+        settings.bus.input_subject.clone(),
+        &settings.bus.output_subject,
+        settings.bus.codec,
+    )
+    .await?;
+    if settings.bus.private_acks {
+        client.follow(&format!("{}.*.acks", settings.bus.account_subject)).await?;
+    }
+
+    let tokens: HashMap<String, u64> = match &args.stream_tokens {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => HashMap::new(),
+    };
+    let streams = Arc::new(PrivateStreams {
+        bus,
+        account_subject: settings.bus.account_subject.clone(),
+        codec: settings.bus.codec,
+        auth: StreamAuth::new(tokens, args.stream_max_skew_secs),
+    });
+    // The same signing keys the engine verifies orders with, kept current from the keys bucket.
+    let mut signing_keys = settings.signing_keys.clone();
+    if let Ok(dynamic) = key_registry::load_all(&settings.bus.nats_url, &settings.bus.keys_bucket).await {
+        signing_keys.extend(dynamic);
+    }
+    for key in &signing_keys {
+        if let Err(err) = streams.auth.upsert_key(key) {
+            warn!(subaccount_id = key.subaccount_id, error = %err, "ignoring invalid signing key");
+        }
+    }
+    let (key_tx, mut key_rx) = tokio::sync::mpsc::channel(64);
+    tokio::spawn(key_registry::watch_updates_tx(settings.bus.nats_url.clone(), settings.bus.keys_bucket.clone(), key_tx));
+    let watched = Arc::clone(&streams);
+    tokio::spawn(async move {
+        while let Some(key) = key_rx.recv().await {
+            if let Err(err) = watched.auth.upsert_key(&key) {
+                warn!(subaccount_id = key.subaccount_id, error = %err, "ignoring invalid signing key");
+            }
+        }
+    });
+
+    let app = gateway::router(Arc::new(client), Duration::from_millis(args.ack_timeout_ms)).merge(gateway::private_router(streams));
+
+    let listener = tokio::net::TcpListener::bind(&args.listen).await?;
+    info!(listen = %args.listen, "gateway listening");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use clap::{Parser, Subcommand};
+
+use hypermarket_clob::config::{MarketConfig, Settings};
+use hypermarket_clob::market_registry;
+
+/// Creates, updates, deletes and shows the `MarketConfig` entries of the markets KV bucket.
+/// Every write is validated like a market in the config file before it reaches the bucket.
+#[derive(Parser, Debug)]
+#[command(name = "marketctl")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints every market in the bucket, one JSON object per line.
+    List,
+    /// Prints one market as pretty JSON.
+    Show {
+        #[arg(long)]
+        market_id: u64,
+    },
+    /// Adds a market from a JSON file; fails if the market id is taken.
+    Create {
+        #[arg(long)]
+        file: String,
+    },
+    /// Changes an existing market, either replacing it with a JSON file or setting fields.
+    Update {
+        #[arg(long)]
+        market_id: u64,
+        /// Replacement `MarketConfig` JSON; its market id must match.
+        #[arg(long, conflicts_with = "set")]
+        file: Option<String>,
+        /// `field=value`, repeatable; dotted fields reach nested settings, e.g.
+        /// `funding.interval_secs=3600`.
+        #[arg(long)]
+        set: Vec<String>,
+        /// Print the result without writing it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Removes a market's entry. Running shards keep the market until they restart.
+    Delete {
+        #[arg(long)]
+        market_id: u64,
+    },
+}
+
+fn read_market(path: &str) -> anyhow::Result<MarketConfig> {
+    let market = serde_json::from_str(&std::fs::read_to_string(path)?).map_err(|err| anyhow::anyhow!("{path}: {err}"))?;
+    Ok(market)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let (url, bucket) = (settings.bus.nats_url.as_str(), settings.bus.markets_bucket.as_str());
+    match args.command {
+        Command::List => {
+            let mut markets = market_registry::load_all(url, bucket).await?;
+            markets.sort_by_key(|market| market.market_id);
+            for market in &markets {
+                println!("{}", serde_json::to_string(market)?);
+            }
+        }
+        Command::Show { market_id } => {
+            let market = market_registry::get_market(url, bucket, market_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("market {market_id} is not in bucket {bucket}"))?;
+            println!("{}", serde_json::to_string_pretty(&market)?);
+        }
+        Command::Create { file } => {
+            let market = read_market(&file)?;
+            let revision = market_registry::put_market(url, bucket, &market, true).await?;
+            println!("created market={} revision={revision}", market.market_id);
+        }
+        Command::Update {
+            market_id,
+            file,
+            set,
+            dry_run,
+        } => {
+            let current = market_registry::get_market(url, bucket, market_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("market {market_id} is not in bucket {bucket}; use create"))?;
+            let market = match file {
+                Some(path) => {
+                    let market = read_market(&path)?;
+                    anyhow::ensure!(market.market_id == market_id, "{path} holds market {}, not {market_id}", market.market_id);
+                    market
+                }
+                None => {
+                    anyhow::ensure!(!set.is_empty(), "nothing to update: pass --file or --set");
+                    market_registry::set_fields(&current, &set)?
+                }
+            };
+            if dry_run {
+                market.validate().map_err(|err| anyhow::anyhow!("market {market_id}: {err}"))?;
+                println!("{}", serde_json::to_string_pretty(&market)?);
+                return Ok(());
+            }
+            let revision = market_registry::put_market(url, bucket, &market, false).await?;
+            println!("updated market={market_id} revision={revision}");
+        }
+        Command::Delete { market_id } => {
+            if !market_registry::delete_market(url, bucket, market_id).await? {
+                anyhow::bail!("market {market_id} is not in bucket {bucket}");
+            }
+            println!("deleted market={market_id}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+
+use hypermarket_clob::bus::nats::JetStreamBus;
+use hypermarket_clob::client::ClobClient;
+use hypermarket_clob::config::{Settings, WireCodec};
+use hypermarket_clob::engine::clock::{Clock, SystemClock};
+use hypermarket_clob::engine::router::decode_input_with;
+use hypermarket_clob::models::{pb, CancelOrder, Event, NewOrder, PriceUpdate};
+
+/// Publishes inputs on the engine's input subject and waits for what confirms them: the `OrderAck`
+/// of a new order, the `Cancelled` order update of a cancel. Price updates are only published.
+#[derive(Parser, Debug)]
+#[command(name = "orderctl")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    /// How long to wait for each confirmation.
+    #[arg(long, default_value_t = 5_000)]
+    timeout_ms: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Submits one order and prints its `OrderAck` as JSON.
+    NewOrder(NewOrderArgs),
+    /// Cancels one order and prints the `OrderUpdate` confirming it as JSON.
+    Cancel {
+        #[arg(long)]
+        market_id: u64,
+        #[arg(long)]
+        subaccount_id: u64,
+        #[arg(long)]
+        order_id: u64,
+        #[arg(long)]
+        request_id: Option<String>,
+    },
+    /// Publishes a mark and index price for a market, stamped with the current time.
+    PriceUpdate {
+        #[arg(long)]
+        market_id: u64,
+        #[arg(long)]
+        mark_price: u64,
+        /// Defaults to the mark price.
+        #[arg(long)]
+        index_price: Option<u64>,
+        #[arg(long, default_value = "")]
+        source: String,
+    },
+    /// Sends every `InputEvent` in a JSON file, in the JSON wire codec's layout: one object or an
+    /// array of them, e.g. `[{"payload":{"new_order":{...}}}]`. Orders and single-order cancels
+    /// wait for their confirmations like the commands above; other inputs are only published.
+    File {
+        #[arg(long)]
+        path: String,
+    },
+}
+
+/// Flags of a new order, with the wire codec's enum strings.
+#[derive(ClapArgs, Debug)]
+struct NewOrderArgs {
+    #[arg(long)]
+    market_id: u64,
+    #[arg(long)]
+    subaccount_id: u64,
+    /// BUY or SELL.
+    #[arg(long)]
+    side: String,
+    #[arg(long)]
+    qty: u64,
+    #[arg(long, default_value_t = 0)]
+    price_ticks: u64,
+    /// LIMIT, MARKET, POST_ONLY, IOC or FOK.
+    #[arg(long, default_value = "LIMIT")]
+    order_type: String,
+    /// GTC, IOC, FOK or GTD; derived from the order type when omitted.
+    #[arg(long, default_value = "")]
+    tif: String,
+    #[arg(long)]
+    reduce_only: bool,
+    #[arg(long, default_value_t = 0)]
+    expiry_ts: u64,
+    /// Defaults to the current time in nanoseconds, so repeated runs do not collide.
+    #[arg(long)]
+    nonce: Option<u64>,
+    #[arg(long, default_value_t = 0)]
+    trigger_price: u64,
+    #[arg(long, default_value = "")]
+    trigger_source: String,
+    #[arg(long)]
+    request_id: Option<String>,
+}
+
+fn unique_suffix() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+impl NewOrderArgs {
+    fn into_order(self) -> anyhow::Result<NewOrder> {
+        let nonce = self.nonce.unwrap_or(unique_suffix() as u64);
+        let order = pb::NewOrder {
+            request_id: self.request_id.unwrap_or_else(|| format!("orderctl-{}", unique_suffix())),
+            market_id: self.market_id,
+            subaccount_id: self.subaccount_id,
+            side: self.side.to_uppercase(),
+            order_type: self.order_type.to_uppercase(),
+            tif: self.tif.to_uppercase(),
+            price_ticks: self.price_ticks,
+            qty: self.qty,
+            reduce_only: self.reduce_only,
+            expiry_ts: self.expiry_ts,
+            nonce,
+            signature: Bytes::new(),
+            client_ts: SystemClock.now(),
+            trigger_price: self.trigger_price,
+            trigger_source: self.trigger_source.to_uppercase(),
+        };
+        Ok(NewOrder::try_from(order)?)
+    }
+}
+
+/// Inputs of a JSON file, decoded and checked exactly as the engine would.
+fn read_inputs(path: &str) -> anyhow::Result<Vec<Event>> {
+    let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let inputs = match value {
+        serde_json::Value::Array(inputs) => inputs,
+        input => vec![input],
+    };
+    inputs
+        .into_iter()
+        .enumerate()
+        .map(|(index, input)| {
+            decode_input_with(WireCodec::Json, Bytes::from(serde_json::to_vec(&input)?))
+                .map_err(|err| anyhow::anyhow!("{path}: input {index}: {err}"))
+        })
+        .collect()
+}
+
+async fn send(client: &ClobClient, event: Event, timeout: Duration) -> anyhow::Result<()> {
+    match event {
+        Event::NewOrder(order) => {
+            let ack = client.submit_order(order, timeout).await?;
+            println!("{}", serde_json::to_string(&ack)?);
+        }
+        Event::CancelOrder(cancel) if cancel.order_id.is_some() => {
+            let update = client.cancel_order_confirmed(cancel, timeout).await?;
+            println!("{}", serde_json::to_string(&update)?);
+        }
+        other => {
+            client.publish(other).await?;
+            println!("published");
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let events = match args.command {
+        Command::NewOrder(order) => vec![Event::NewOrder(order.into_order()?)],
+        Command::Cancel {
+            market_id,
+            subaccount_id,
+            order_id,
+            request_id,
+        } => vec![Event::CancelOrder(CancelOrder {
+            request_id: request_id.unwrap_or_else(|| format!("orderctl-{}", unique_suffix())),
+            market_id,
+            subaccount_id,
+            order_id: Some(order_id),
+            nonce_start: None,
+            nonce_end: None,
+        })],
+        Command::PriceUpdate {
+            market_id,
+            mark_price,
+            index_price,
+            source,
+        } => vec![Event::PriceUpdate(PriceUpdate {
+            market_id,
+            mark_price,
+            index_price: index_price.unwrap_or(mark_price),
+            ts: SystemClock.now(),
+            source,
+        })],
+        Command::File { path } => read_inputs(&path)?,
+    };
+
+    // A durable consumer of its own, so confirmations are not taken from the engine or a gateway.
+    let bus = JetStreamBus::connect(
+        &settings.bus.nats_url,
+        settings.bus.stream_name.clone(),
+        vec![
+            settings.bus.input_subject.clone(),
+            settings.bus.output_subject.clone(),
+            format!("{}.>", settings.bus.account_subject),
+        ],
+        format!("{}-orderctl", settings.bus.durable_name),
+    )
+    .await?;
+    let client = ClobClient::connect_with_codec(
+        Arc::new(bus),
+        settings.bus.input_subject.clone(),
+        &settings.bus.output_subject,
+        settings.bus.codec,
+    )
+    .await?;
+    if settings.bus.private_acks {
+        client.follow(&format!("{}.*.acks", settings.bus.account_subject)).await?;
+    }
+    for event in events {
+        send(&client, event, timeout).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+use hypermarket_clob::config::Settings;
+use hypermarket_clob::query::{self, SnapshotView};
+
+/// Serves read-only book depth, open orders and balances from the shards' latest snapshots in
+/// `persistence.snapshot_dir`, re-reading them every `--refresh-secs`.
+#[derive(Parser, Debug)]
+#[command(name = "query_server")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    #[arg(long, default_value = "0.0.0.0:8081")]
+    listen: String,
+    #[arg(long, default_value_t = 5)]
+    refresh_secs: u64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .json()
+        .init();
+
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    anyhow::ensure!(
+        settings.persistence.snapshot_dir.is_some(),
+        "persistence.snapshot_dir is unset, so the engine writes no snapshots to serve"
+    );
+    let (view, errors) = SnapshotView::default().reload(&settings.persistence, settings.shard_count);
+    for (shard_id, err) in &errors {
+        warn!(shard_id, error = %err, "cannot read snapshot");
+    }
+    let (tx, rx) = tokio::sync::watch::channel(Arc::new(view));
+
+    let persistence = settings.persistence.clone();
+    let shard_count = settings.shard_count;
+    tokio::spawn(async move {
+        let mut refresh = tokio::time::interval(Duration::from_secs(args.refresh_secs.max(1)));
+        refresh.tick().await;
+        loop {
+            refresh.tick().await;
+            let current = Arc::clone(&tx.borrow());
+            // Decoding a snapshot is blocking file work; keep it off the request workers.
+            let persistence = persistence.clone();
+            let reloaded = tokio::task::spawn_blocking(move || current.reload(&persistence, shard_count)).await;
+            let Ok((view, errors)) = reloaded else { continue };
+            for (shard_id, err) in &errors {
+                warn!(shard_id, error = %err, "cannot read snapshot; serving the previous one");
+            }
+            tx.send_replace(Arc::new(view));
+        }
+    });
+
+    let listener = tokio::net::TcpListener::bind(&args.listen).await?;
+    info!(listen = %args.listen, "query server listening");
+    axum::serve(listener, query::router(rx)).await?;
+    Ok(())
+}
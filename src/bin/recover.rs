@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+use hypermarket_clob::bus::nats::JetStreamBus;
+use hypermarket_clob::config::{MarketConfig, Settings};
+use hypermarket_clob::engine::dedupe::DedupeWindow;
+use hypermarket_clob::engine::recovery::StreamRecovery;
+use hypermarket_clob::engine::shard::{EngineShard, DEFAULT_DEDUPE_WINDOW_SECS};
+use hypermarket_clob::persistence::snapshot::SnapshotStore;
+use hypermarket_clob::persistence::wal::Wal;
+use hypermarket_clob::risk::RiskEngine;
+use hypermarket_clob::{account_registry, key_registry, market_registry, permission_registry};
+
+/// Rebuilds shards from their snapshots plus the JetStream input stream, for a host that lost its
+/// WAL. Run it with the engine stopped; it writes a fresh WAL of the replayed inputs and a
+/// snapshot per shard, and prints the stream sequence the new snapshots cover.
+#[derive(Parser, Debug)]
+#[command(name = "recover")]
+struct Args {
+    #[arg(long, default_value = "config/example.yaml")]
+    config: String,
+    /// Snapshot to start from (repeatable, matched to shards by their shard id). A shard without
+    /// one starts empty.
+    #[arg(long = "snapshot")]
+    snapshots: Vec<String>,
+    /// Last input stream sequence the snapshots cover; replay starts after it.
+    #[arg(long)]
+    stream_seq: u64,
+    /// Shards to rebuild (repeatable); defaults to all of them.
+    #[arg(long = "shard")]
+    shards: Vec<usize>,
+    /// Where the replayed inputs are logged; defaults to `persistence.wal_path`, which must be
+    /// missing or empty.
+    #[arg(long)]
+    wal: Option<String>,
+    /// Where the rebuilt snapshots go; defaults to `persistence.snapshot_dir`.
+    #[arg(long)]
+    out_dir: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let wal_path = PathBuf::from(args.wal.as_deref().unwrap_or(&settings.persistence.wal_path));
+    anyhow::ensure!(
+        std::fs::metadata(&wal_path).map(|metadata| metadata.len() == 0).unwrap_or(true),
+        "{} is not empty; recovery writes a fresh log",
+        wal_path.display()
+    );
+    let shard_ids: Vec<usize> = if args.shards.is_empty() { (0..settings.shard_count).collect() } else { args.shards.clone() };
+    let out_dir = |shard_id: usize| match &args.out_dir {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => settings.persistence.snapshot_dir(shard_id),
+    };
+    anyhow::ensure!(
+        shard_ids.iter().all(|&shard_id| out_dir(shard_id).is_some()),
+        "persistence.snapshot_dir is unset; pass --out-dir"
+    );
+
+    // Markets, accounts, keys and permissions as the router would load them at startup.
+    let nats_url = &settings.bus.nats_url;
+    let mut markets: BTreeMap<u64, MarketConfig> = settings.markets.iter().map(|market| (market.market_id, market.clone())).collect();
+    for market in market_registry::load_all(nats_url, &settings.bus.markets_bucket).await.unwrap_or_default() {
+        markets.insert(market.market_id, market);
+    }
+    let mut accounts = settings.accounts.clone();
+    accounts.extend(account_registry::load_all(nats_url, &settings.bus.accounts_bucket).await.unwrap_or_default());
+    let mut signing_keys = settings.signing_keys.clone();
+    signing_keys.extend(key_registry::load_all(nats_url, &settings.bus.keys_bucket).await.unwrap_or_default());
+    let mut permissions = settings.permissions.clone();
+    permissions.extend(permission_registry::load_all(nats_url, &settings.bus.permissions_bucket).await.unwrap_or_default());
+
+    let mut snapshots = args
+        .snapshots
+        .iter()
+        .map(|path| SnapshotStore::load(Path::new(path))?.ok_or_else(|| anyhow::anyhow!("snapshot {path} not found")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let dedupe_window = settings
+        .dedupe
+        .window_secs
+        .unwrap_or_else(|| settings.bus.redelivery_horizon_secs().saturating_mul(2).max(DEFAULT_DEDUPE_WINDOW_SECS));
+    let mut shards = BTreeMap::new();
+    for &shard_id in &shard_ids {
+        let shard_markets: Vec<MarketConfig> = markets
+            .values()
+            .filter(|market| (market.market_id as usize) % settings.shard_count == shard_id)
+            .cloned()
+            .collect();
+        let wal = Wal::open(&wal_path)?;
+        let risk = RiskEngine::new(settings.risk.clone());
+        let mut shard = match snapshots.iter().position(|snapshot| snapshot.meta.shard_id == shard_id) {
+            Some(index) => EngineShard::restore(snapshots.swap_remove(index).state, shard_markets, wal, risk)?,
+            None => EngineShard::new(shard_id, shard_markets, wal, risk),
+        }
+        .with_dedupe(DedupeWindow::new(dedupe_window, settings.dedupe.max_entries).for_shard(shard_id))
+        .with_required_signatures(settings.require_signatures)
+        .with_insurance_fund(settings.insurance_fund_subaccount);
+        for account in &accounts {
+            shard.upsert_account(account.clone());
+        }
+        for key in &signing_keys {
+            if let Err(err) = shard.upsert_signing_key(key) {
+                eprintln!("ignoring invalid signing key of subaccount {}: {err}", key.subaccount_id);
+            }
+        }
+        for entry in permissions.iter().filter(|entry| (entry.market_id as usize) % settings.shard_count == shard_id) {
+            shard.upsert_permissions(entry.clone());
+        }
+        shards.insert(shard_id, shard);
+    }
+    anyhow::ensure!(snapshots.is_empty(), "snapshots given for shards not being rebuilt");
+
+    let bus = JetStreamBus::connect(
+        nats_url,
+        settings.bus.stream_name.clone(),
+        vec![settings.bus.input_subject.clone()],
+        format!("{}-recover", settings.bus.durable_name),
+    )
+    .await?;
+    let mut records = bus.read_stream(&settings.bus.input_subject, args.stream_seq + 1).await?;
+    let mut recovery = StreamRecovery::new(shards, settings.shard_count, settings.bus.codec, args.stream_seq);
+    while let Some(record) = records.recv().await {
+        let record = record?;
+        recovery.apply(record.stream_seq, record.ts_ns, record.payload)?;
+    }
+
+    println!(
+        "stream_seq={} applied={} skipped={}",
+        recovery.last_stream_seq(),
+        recovery.applied(),
+        recovery.skipped()
+    );
+    for (shard_id, shard) in recovery.shards() {
+        let dir = out_dir(*shard_id).expect("checked above");
+        let snapshot = SnapshotStore::build(*shard_id, shard.engine_seq, shard.snapshot());
+        let path = SnapshotStore::save_to_dir(&dir, &snapshot)?;
+        println!("shard={shard_id} engine_seq={} snapshot={}", shard.engine_seq, path.display());
+    }
+    Ok(())
+}
@@ -1,10 +1,11 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
 
-use hypermarket_clob::config::Settings;
+use hypermarket_clob::config::{MarketConfig, Settings};
 use hypermarket_clob::engine::shard::EngineShard;
-use hypermarket_clob::persistence::snapshot::SnapshotStore;
+use hypermarket_clob::models::EventEnvelope;
+use hypermarket_clob::persistence::snapshot::{Snapshot, SnapshotStore};
 use hypermarket_clob::persistence::wal::Wal;
 use hypermarket_clob::risk::{RiskConfig, RiskEngine};
 
@@ -15,46 +16,111 @@ struct Args {
     config: String,
     #[arg(long)]
     log: String,
+    /// Snapshot to start from; with `--shard`, repeat once per shard (matched by its shard id).
+    #[arg(long = "snapshot")]
+    snapshots: Vec<String>,
+    /// Stop after the input with this engine_seq, reconstructing the state as of that point.
     #[arg(long)]
-    snapshot: Option<String>,
+    until_seq: Option<u64>,
+    /// Replay only the records this shard wrote, with the markets it owns. Repeat to replay several
+    /// shards of a shared log in parallel.
+    #[arg(long = "shard")]
+    shards: Vec<usize>,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let settings = Settings::load(&args.config)?;
     let log_path = PathBuf::from(&args.log);
+    let events = Wal::load(&log_path)?;
+    let mut snapshots = args
+        .snapshots
+        .iter()
+        .map(|path| SnapshotStore::load(Path::new(path))?.ok_or_else(|| anyhow::anyhow!("snapshot {path} not found")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if args.shards.is_empty() {
+        anyhow::ensure!(snapshots.len() <= 1, "multiple snapshots need --shard");
+        let (_, hash) = replay(None, settings.markets.clone(), &settings.risk, snapshots.pop(), &events, args.until_seq)?;
+        println!("state_hash={}", hash.to_hex());
+        return Ok(());
+    }
 
-    let snapshot = args
-        .snapshot
-        .as_ref()
-        .map(PathBuf::from)
-        .map(|path| SnapshotStore::load(&path))
-        .transpose()?
-        .flatten();
-
-    let replay_path = std::env::temp_dir().join("replay.wal");
-    let wal = Wal::open(&replay_path)?;
-    let risk = RiskEngine::new(RiskConfig {
-        max_slippage_bps: 50,
-        max_leverage: 10,
+    let mut jobs = Vec::with_capacity(args.shards.len());
+    for &shard_id in &args.shards {
+        let markets: Vec<MarketConfig> = settings
+            .markets
+            .iter()
+            .filter(|market| (market.market_id as usize) % settings.shard_count == shard_id)
+            .cloned()
+            .collect();
+        let snapshot = snapshots
+            .iter()
+            .position(|snapshot| snapshot.meta.shard_id == shard_id)
+            .map(|index| snapshots.swap_remove(index));
+        jobs.push((shard_id, markets, snapshot));
+    }
+    let results = std::thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|(shard_id, markets, snapshot)| {
+                let events = &events;
+                let risk = &settings.risk;
+                let until_seq = args.until_seq;
+                scope.spawn(move || (shard_id, replay(Some(shard_id), markets, risk, snapshot, events, until_seq)))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("replay thread panicked"))
+            .collect::<Vec<_>>()
     });
+    for (shard_id, result) in results {
+        let (engine_seq, hash) = result?;
+        println!("shard={shard_id} engine_seq={engine_seq} state_hash={}", hash.to_hex());
+    }
+    Ok(())
+}
 
-    let mut shard = if let Some(snapshot) = snapshot {
-        EngineShard::restore(snapshot.state, settings.markets.clone(), wal, risk)
-    } else {
-        EngineShard::new(0, settings.markets.clone(), wal, risk)
+/// Rebuilds one shard from `snapshot` plus the logged inputs after it, up to `until_seq`. With a
+/// `shard_id`, only that shard's records are applied.
+fn replay(
+    shard_id: Option<usize>,
+    markets: Vec<MarketConfig>,
+    risk: &RiskConfig,
+    snapshot: Option<Snapshot>,
+    events: &[EventEnvelope],
+    until_seq: Option<u64>,
+) -> anyhow::Result<(u64, blake3::Hash)> {
+    let replay_path = std::env::temp_dir().join(format!("replay-{}-{}.wal", std::process::id(), shard_id.unwrap_or(0)));
+    let mut wal = Wal::open(&replay_path)?;
+    wal.truncate()?;
+    let risk = RiskEngine::new(risk.clone());
+
+    let mut last_seq = 0;
+    let mut shard = match snapshot {
+        Some(snapshot) => {
+            last_seq = snapshot.meta.last_seq;
+            EngineShard::restore(snapshot.state, markets, wal, risk)?
+        }
+        None => EngineShard::new(shard_id.unwrap_or(0), markets, wal, risk),
     };
 
-    let events = Wal::load(&log_path)?;
     for envelope in events {
-        if matches!(envelope.event, hypermarket_clob::models::Event::NewOrder(_) | hypermarket_clob::models::Event::CancelOrder(_) | hypermarket_clob::models::Event::PriceUpdate(_) | hypermarket_clob::models::Event::FundingUpdate(_)) {
-            let _ = shard.handle_event(envelope.event, envelope.ts);
+        if shard_id.is_some_and(|shard_id| envelope.shard_id != shard_id) || !envelope.event.is_input() {
+            continue;
         }
+        if envelope.engine_seq <= last_seq {
+            continue;
+        }
+        if until_seq.is_some_and(|until_seq| envelope.engine_seq > until_seq) {
+            continue;
+        }
+        let _ = shard.handle_event_at(envelope.event.clone(), envelope.ts_ns);
     }
 
     let state = shard.snapshot();
-    let state_bytes = bincode::serialize(&state)?;
-    let hash = blake3::hash(&state_bytes);
-    println!("state_hash={}", hash.to_hex());
-    Ok(())
+    let hash = blake3::hash(&bincode::serialize(&state)?);
+    let _ = std::fs::remove_file(&replay_path);
+    Ok((state.engine_seq, hash))
 }
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+use hypermarket_clob::config::{MarketConfig, Settings};
+use hypermarket_clob::engine::reshard::reshard;
+use hypermarket_clob::engine::shard::EngineShard;
+use hypermarket_clob::engine::EngineState;
+use hypermarket_clob::persistence::snapshot::SnapshotStore;
+use hypermarket_clob::persistence::wal::Wal;
+use hypermarket_clob::risk::{RiskConfig, RiskEngine};
+
+/// Rebuilds every existing shard from its snapshot and/or WAL, redistributes markets over a new
+/// shard layout and writes one snapshot per new shard. Run it with the engine stopped.
+#[derive(Parser, Debug)]
+#[command(name = "reshard")]
+struct Args {
+    #[arg(long)]
+    config: String,
+    /// One existing shard as `SNAPSHOT[:WAL]`; either part may be empty. Repeat per shard.
+    #[arg(long = "shard", required = true)]
+    shards: Vec<String>,
+    #[arg(long)]
+    shard_count: usize,
+    /// Optional YAML map of `market_id: shard_id`; unlisted markets use `market_id % shard_count`.
+    #[arg(long)]
+    assignment: Option<String>,
+    #[arg(long)]
+    out_dir: String,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let assignment: HashMap<u64, usize> = match &args.assignment {
+        Some(path) => serde_yaml::from_str(&std::fs::read_to_string(path)?)?,
+        None => HashMap::new(),
+    };
+
+    let old_count = args.shards.len();
+    let mut states = Vec::with_capacity(old_count);
+    for (index, spec) in args.shards.iter().enumerate() {
+        let (snapshot, wal) = spec.split_once(':').unwrap_or((spec.as_str(), ""));
+        // Shards start with the markets the router gives them; migrations are replayed from the WAL.
+        let markets = settings
+            .markets
+            .iter()
+            .filter(|market| (market.market_id as usize) % old_count == index)
+            .cloned()
+            .collect();
+        states.push(load_shard(index, snapshot, wal, markets, &settings.risk)?);
+    }
+
+    let out_dir = PathBuf::from(&args.out_dir);
+    std::fs::create_dir_all(&out_dir)?;
+    for state in reshard(states, args.shard_count, &assignment)? {
+        let shard_id = state.shard_id;
+        let markets = state.markets.len();
+        let orders: usize = state.markets.values().map(|market| market.orders.len()).sum();
+        let snapshot = SnapshotStore::build(shard_id, state.engine_seq, state);
+        let path = out_dir.join(format!("snapshot-{shard_id}.bin"));
+        SnapshotStore::save(&path, &snapshot)?;
+        println!("shard={shard_id} markets={markets} orders={orders} path={}", path.display());
+    }
+    Ok(())
+}
+
+/// State of one existing shard: its snapshot, with any WAL inputs logged after it re-applied.
+fn load_shard(index: usize, snapshot: &str, wal: &str, markets: Vec<MarketConfig>, risk: &RiskConfig) -> anyhow::Result<EngineState> {
+    let snapshot = match snapshot {
+        "" => None,
+        path => Some(SnapshotStore::load(Path::new(path))?.ok_or_else(|| anyhow::anyhow!("snapshot {path} not found"))?),
+    };
+    let replay_path = std::env::temp_dir().join(format!("reshard-{}-{index}.wal", std::process::id()));
+    let mut replay_wal = Wal::open(&replay_path)?;
+    replay_wal.truncate()?;
+    let risk = RiskEngine::new(risk.clone());
+
+    let (mut shard, last_seq) = match snapshot {
+        Some(snapshot) => {
+            let last_seq = snapshot.meta.last_seq;
+            (EngineShard::restore(snapshot.state, markets, replay_wal, risk)?, last_seq)
+        }
+        None => (EngineShard::new(index, markets, replay_wal, risk), 0),
+    };
+    if !wal.is_empty() {
+        for envelope in Wal::load(Path::new(wal))? {
+            if envelope.engine_seq > last_seq && envelope.event.is_input() {
+                shard.handle_event_at(envelope.event, envelope.ts_ns)?;
+            }
+        }
+    }
+    let state = shard.snapshot();
+    let _ = std::fs::remove_file(&replay_path);
+    Ok(state)
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use hypermarket_clob::config::MarketConfig;
+use hypermarket_clob::engine::shard::EngineShard;
+use hypermarket_clob::engine::MemoryLog;
+use hypermarket_clob::models::{BlockTrade, CancelOrder, Event, Fill, FundingUpdate, NewOrder, PriceUpdate, SubaccountId};
+use hypermarket_clob::persistence::wal::Wal;
+use hypermarket_clob::risk::{RiskConfig, RiskEngine, Subaccount};
+
+#[derive(Parser, Debug)]
+#[command(name = "simulate")]
+struct Args {
+    /// Scenario file (YAML, or JSON when the extension is `.json`).
+    #[arg(long)]
+    scenario: String,
+    /// Print every fill as it happens in addition to the final summary.
+    #[arg(long, default_value_t = false)]
+    verbose: bool,
+    /// Log the scenario's inputs to this file, truncating it first; by default they are kept in
+    /// memory, so concurrent runs do not share a log.
+    #[arg(long)]
+    wal: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    markets: Vec<MarketConfig>,
+    #[serde(default)]
+    accounts: Vec<AccountSeed>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountSeed {
+    subaccount_id: SubaccountId,
+    collateral: i64,
+}
+
+/// One scenario input, applied when the virtual clock reaches `at`.
+#[derive(Debug, Deserialize)]
+struct Step {
+    at: u64,
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    NewOrder(NewOrder),
+    CancelOrder(CancelOrder),
+    PriceUpdate(PriceUpdate),
+    FundingUpdate(FundingUpdate),
+    BlockTrade(BlockTrade),
+}
+
+impl From<Action> for Event {
+    fn from(value: Action) -> Self {
+        match value {
+            Action::NewOrder(order) => Event::NewOrder(order),
+            Action::CancelOrder(cancel) => Event::CancelOrder(cancel),
+            Action::PriceUpdate(update) => Event::PriceUpdate(update),
+            Action::FundingUpdate(update) => Event::FundingUpdate(update),
+            Action::BlockTrade(trade) => Event::BlockTrade(trade),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    final_ts: u64,
+    engine_seq: u64,
+    fills: Vec<Fill>,
+    balances: BTreeMap<SubaccountId, Subaccount>,
+    state_hash: String,
+}
+
+fn load_scenario(path: &Path) -> anyhow::Result<Scenario> {
+    let raw = std::fs::read(path)?;
+    let scenario = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_slice(&raw)?,
+        _ => serde_yaml::from_slice(&raw)?,
+    };
+    Ok(scenario)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mut scenario = load_scenario(&PathBuf::from(&args.scenario))?;
+
+    let risk = RiskEngine::new(RiskConfig::default());
+    let mut shard = match &args.wal {
+        Some(path) => {
+            let mut wal = Wal::open(&PathBuf::from(path))?;
+            wal.truncate()?;
+            EngineShard::new(0, scenario.markets.clone(), wal, risk)
+        }
+        None => EngineShard::new(0, scenario.markets.clone(), MemoryLog::new(), risk),
+    };
+    for seed in &scenario.accounts {
+        shard.risk.ensure_subaccount(seed.subaccount_id).collateral = seed.collateral;
+    }
+
+    // Stable sort keeps file order for steps sharing a timestamp, so runs are reproducible.
+    scenario.steps.sort_by_key(|step| step.at);
+
+    let mut clock = 0u64;
+    let mut fills = Vec::new();
+    for step in scenario.steps {
+        clock = clock.max(step.at);
+        let outputs = shard.handle_event(step.action.into(), clock)?;
+        for output in outputs {
+            if let Event::Fill(fill) = output.event {
+                if args.verbose {
+                    println!("{}", serde_json::to_string(&fill)?);
+                }
+                fills.push(fill);
+            }
+        }
+    }
+
+    let state = shard.snapshot();
+    let state_hash = blake3::hash(&bincode::serialize(&state)?).to_hex().to_string();
+    let report = Report {
+        final_ts: clock,
+        engine_seq: shard.engine_seq,
+        fills,
+        balances: shard.risk.state.subaccounts.clone().into_iter().collect(),
+        state_hash,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use clap::Parser;
+
+use hypermarket_clob::config::Settings;
+use hypermarket_clob::persistence::retention::{compact_wal, gc_snapshots};
+
+/// Prunes a snapshot directory to `persistence.retention` and, with `--wal`, drops the WAL records
+/// the remaining snapshots cover. Compact the WAL only with the engine stopped.
+#[derive(Parser, Debug)]
+#[command(name = "snapshot_gc")]
+struct Args {
+    #[arg(long)]
+    config: String,
+    #[arg(long)]
+    dir: String,
+    #[arg(long)]
+    wal: Option<String>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let settings = Settings::load(&args.config)?;
+    let report = gc_snapshots(Path::new(&args.dir), &settings.persistence.retention)?;
+    for path in &report.removed {
+        println!("removed {}", path.display());
+    }
+    println!("snapshots_removed={} reclaimed_bytes={}", report.removed.len(), report.reclaimed_bytes);
+    if let Some(wal) = &args.wal {
+        let reclaimed = compact_wal(Path::new(wal), &report.covered)?;
+        println!("wal_reclaimed_bytes={reclaimed}");
+    }
+    Ok(())
+}
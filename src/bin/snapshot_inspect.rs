@@ -1,5 +1,10 @@
+use std::collections::BTreeMap;
+
 use clap::Parser;
+use serde::Serialize;
 
+use hypermarket_clob::engine::shard::OrderSnapshot;
+use hypermarket_clob::models::Side;
 use hypermarket_clob::persistence::snapshot::SnapshotStore;
 
 #[derive(Parser, Debug)]
@@ -7,15 +12,202 @@ use hypermarket_clob::persistence::snapshot::SnapshotStore;
 struct Args {
     #[arg(long)]
     snapshot: String,
+    /// Read a protobuf snapshot (`proto/snapshot.proto`) instead of bincode.
+    #[arg(long)]
+    proto: bool,
+    /// Print the report as JSON instead of text.
+    #[arg(long)]
+    json: bool,
+    /// Only show these markets (repeatable).
+    #[arg(long = "market")]
+    markets: Vec<u64>,
+    /// Only show orders and balances of these subaccounts (repeatable).
+    #[arg(long = "subaccount")]
+    subaccounts: Vec<u64>,
+    /// Price levels per side in the depth summary.
+    #[arg(long, default_value_t = 10)]
+    depth: usize,
+}
+
+#[derive(Serialize)]
+struct Report {
+    version: u32,
+    shard_id: usize,
+    last_seq: u64,
+    checksum: String,
+    checksum_ok: bool,
+    next_order_id: u64,
+    next_trade_id: u64,
+    markets: Vec<MarketReport>,
+    subaccounts: Vec<SubaccountReport>,
+}
+
+#[derive(Serialize)]
+struct MarketReport {
+    market_id: u64,
+    mark_price: Option<u64>,
+    funding_index: Option<i64>,
+    /// Conditional orders waiting for their trigger.
+    conditional_orders: usize,
+    /// Orders collected for the open batch auction.
+    batch_pending: usize,
+    bids: Vec<DepthLevel>,
+    asks: Vec<DepthLevel>,
+    orders: Vec<OrderSnapshot>,
+}
+
+#[derive(Serialize)]
+struct DepthLevel {
+    price_ticks: u64,
+    qty: u64,
+    orders: usize,
+}
+
+#[derive(Serialize)]
+struct SubaccountReport {
+    subaccount_id: u64,
+    collateral: i64,
+    cross_margin: bool,
+    positions: Vec<PositionReport>,
+}
+
+#[derive(Serialize)]
+struct PositionReport {
+    market_id: u64,
+    size: i64,
+    entry_price: u64,
+    funding_index: i64,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    let snapshot = SnapshotStore::load(std::path::Path::new(&args.snapshot))?
+    let path = std::path::Path::new(&args.snapshot);
+    let snapshot = if args.proto { SnapshotStore::load_proto(path)? } else { SnapshotStore::load_unchecked(path)? }
         .ok_or_else(|| anyhow::anyhow!("snapshot not found"))?;
-    println!("version={}", snapshot.meta.version);
-    println!("shard_id={}", snapshot.meta.shard_id);
-    println!("last_seq={}", snapshot.meta.last_seq);
-    println!("checksum={}", snapshot.meta.checksum);
+    let state = snapshot.state;
+    let checksum = blake3::hash(&bincode::serialize(&state)?).to_hex().to_string();
+    let market_shown = |market_id: &u64| args.markets.is_empty() || args.markets.contains(market_id);
+    let subaccount_shown = |subaccount_id: &u64| args.subaccounts.is_empty() || args.subaccounts.contains(subaccount_id);
+
+    let markets = state
+        .markets
+        .iter()
+        .filter(|(market_id, _)| market_shown(market_id))
+        .map(|(market_id, market)| {
+            // Depth always covers the whole book; the subaccount filter only narrows the order list.
+            let mut orders: Vec<OrderSnapshot> = market.orders.clone();
+            orders.sort_by_key(|order| (order.side == Side::Sell, order.price_ticks, order.ingress_seq));
+            MarketReport {
+                market_id: *market_id,
+                mark_price: market.mark_price.or_else(|| state.risk_state.mark_prices.get(market_id).copied()),
+                funding_index: market.funding_index.or_else(|| state.risk_state.funding_indices.get(market_id).copied()),
+                conditional_orders: market.conditional.len(),
+                batch_pending: market.batch_pending.len(),
+                bids: depth(&orders, Side::Buy, args.depth),
+                asks: depth(&orders, Side::Sell, args.depth),
+                orders: orders.into_iter().filter(|order| subaccount_shown(&order.subaccount_id)).collect(),
+            }
+        })
+        .collect();
+    let subaccounts = state
+        .risk_state
+        .subaccounts
+        .iter()
+        .filter(|(subaccount_id, _)| subaccount_shown(subaccount_id))
+        .map(|(subaccount_id, account)| SubaccountReport {
+            subaccount_id: *subaccount_id,
+            collateral: account.collateral,
+            cross_margin: account.cross_margin,
+            positions: state
+                .positions(*subaccount_id)
+                .into_iter()
+                .filter(|(market_id, _)| market_shown(market_id))
+                .map(|(market_id, position)| PositionReport {
+                    market_id,
+                    size: position.size,
+                    entry_price: position.entry_price,
+                    funding_index: position.funding_index,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let report = Report {
+        version: snapshot.meta.version,
+        shard_id: snapshot.meta.shard_id,
+        last_seq: snapshot.meta.last_seq,
+        checksum_ok: checksum == snapshot.meta.checksum,
+        checksum: snapshot.meta.checksum,
+        next_order_id: state.next_order_id,
+        next_trade_id: state.next_trade_id,
+        markets,
+        subaccounts,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_text(&report);
+    }
     Ok(())
 }
+
+/// Best `levels` prices on `side`, aggregated from the resting orders.
+fn depth(orders: &[OrderSnapshot], side: Side, levels: usize) -> Vec<DepthLevel> {
+    let mut by_price: BTreeMap<u64, (u64, usize)> = BTreeMap::new();
+    for order in orders.iter().filter(|order| order.side == side) {
+        let level = by_price.entry(order.price_ticks).or_default();
+        level.0 += order.remaining;
+        level.1 += 1;
+    }
+    let to_level = |(price_ticks, (qty, orders)): (u64, (u64, usize))| DepthLevel { price_ticks, qty, orders };
+    match side {
+        Side::Buy => by_price.into_iter().rev().take(levels).map(to_level).collect(),
+        Side::Sell => by_price.into_iter().take(levels).map(to_level).collect(),
+    }
+}
+
+fn print_text(report: &Report) {
+    println!("version={}", report.version);
+    println!("shard_id={}", report.shard_id);
+    println!("last_seq={}", report.last_seq);
+    println!("checksum={}", report.checksum);
+    println!("checksum_ok={}", report.checksum_ok);
+    println!("next_order_id={}", report.next_order_id);
+    println!("next_trade_id={}", report.next_trade_id);
+    for market in &report.markets {
+        println!();
+        println!(
+            "market={} mark={} funding_index={} conditional_orders={} batch_pending={}",
+            market.market_id,
+            market.mark_price.map_or("-".to_string(), |price| price.to_string()),
+            market.funding_index.map_or("-".to_string(), |index| index.to_string()),
+            market.conditional_orders,
+            market.batch_pending,
+        );
+        for level in market.asks.iter().rev() {
+            println!("  ask {:>12} {:>12} ({} orders)", level.price_ticks, level.qty, level.orders);
+        }
+        for level in &market.bids {
+            println!("  bid {:>12} {:>12} ({} orders)", level.price_ticks, level.qty, level.orders);
+        }
+        for order in &market.orders {
+            println!(
+                "  order={} subaccount={} side={:?} price={} remaining={} ingress_seq={}",
+                order.order_id, order.subaccount_id, order.side, order.price_ticks, order.remaining, order.ingress_seq
+            );
+        }
+    }
+    for account in &report.subaccounts {
+        println!();
+        println!(
+            "subaccount={} collateral={} cross_margin={}",
+            account.subaccount_id, account.collateral, account.cross_margin
+        );
+        for position in &account.positions {
+            println!(
+                "  market={} size={} entry_price={} funding_index={}",
+                position.market_id, position.size, position.entry_price, position.funding_index
+            );
+        }
+    }
+}
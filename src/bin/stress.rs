@@ -0,0 +1,315 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use clap::Parser;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode};
+use hypermarket_clob::engine::shard::EngineShard;
+use hypermarket_clob::models::{CancelOrder, Event, NewOrder, OrderId, OrderType, Side, TimeInForce};
+use hypermarket_clob::persistence::wal::Wal;
+use hypermarket_clob::risk::{RiskConfig, RiskEngine};
+
+/// System allocator that counts what passes through it, so the report can show allocation
+/// pressure next to resident memory.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+static PEAK_LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size() as u64);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            // Counted as a fresh allocation replacing the old one.
+            DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            LIVE_BYTES.fetch_sub(layout.size() as u64, Ordering::Relaxed);
+            record_alloc(new_size as u64);
+        }
+        new_ptr
+    }
+}
+
+fn record_alloc(size: u64) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed);
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_LIVE_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[derive(Parser, Debug)]
+#[command(name = "stress")]
+struct Args {
+    /// Markets on the shard, with ids 1..=markets.
+    #[arg(long, default_value_t = 4)]
+    markets: u64,
+    /// New orders to submit, spread evenly over the markets.
+    #[arg(long, default_value_t = 1_000_000)]
+    orders: u64,
+    /// Distinct subaccounts placing them.
+    #[arg(long, default_value_t = 1_000)]
+    subaccounts: u64,
+    /// Price levels on each side of the mid that orders are spread over.
+    #[arg(long, default_value_t = 50)]
+    levels: u64,
+    /// Share of orders, in basis points, that are marketable and take liquidity.
+    #[arg(long, default_value_t = 2_000)]
+    cross_bps: u64,
+    /// Cancel a random resting order after every this many new orders; 0 never cancels.
+    #[arg(long, default_value_t = 4)]
+    cancel_every: u64,
+    /// Pre-allocated book capacity per market (see `order_capacity`).
+    #[arg(long, default_value_t = 0)]
+    order_capacity: usize,
+    /// Use a price ladder around the mid instead of a tree for book levels.
+    #[arg(long)]
+    ladder: bool,
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+    /// Print the report as JSON instead of text.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+    inputs: u64,
+    fills: u64,
+    elapsed_ms: u128,
+    /// From `/proc/self/status`; `None` where that is not available.
+    rss_bytes: Option<u64>,
+    peak_rss_bytes: Option<u64>,
+    allocations: AllocationReport,
+    order_owners: usize,
+    dedupe_entries: usize,
+    markets: Vec<BookReport>,
+}
+
+#[derive(Serialize)]
+struct AllocationReport {
+    allocations: u64,
+    deallocations: u64,
+    allocated_bytes: u64,
+    live_bytes: u64,
+    peak_live_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct BookReport {
+    market_id: u64,
+    resting_orders: usize,
+    /// Slots the order slab holds without reallocating.
+    slab_capacity: usize,
+    bid_levels: usize,
+    ask_levels: usize,
+}
+
+const MID_PRICE: u64 = 100_000;
+
+fn market(market_id: u64, args: &Args) -> MarketConfig {
+    MarketConfig {
+        market_id,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 0,
+        maintenance_margin_bps: 0,
+        max_position: i64::MAX,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode: MatchingMode::Continuous,
+        batch_interval_ms: 0,
+        order_capacity: args.order_capacity,
+        book_layout: if args.ladder {
+            BookLayout::Ladder {
+                min_price_ticks: MID_PRICE - args.levels * 2,
+                max_price_ticks: MID_PRICE + args.levels * 2,
+            }
+        } else {
+            BookLayout::Tree
+        },
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    }
+}
+
+/// Resident and peak resident set size in bytes, read from `/proc/self/status`.
+fn resident_memory() -> (Option<u64>, Option<u64>) {
+    let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+        return (None, None);
+    };
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+            .map(|kb| kb * 1024)
+    };
+    (field("VmRSS:"), field("VmHWM:"))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    anyhow::ensure!(args.markets > 0 && args.subaccounts > 0, "--markets and --subaccounts must be at least 1");
+
+    let wal_path = std::env::temp_dir().join(format!("stress-{}.wal", std::process::id()));
+    let mut wal = Wal::open(&wal_path)?;
+    wal.truncate()?;
+    let markets: Vec<MarketConfig> = (1..=args.markets).map(|market_id| market(market_id, &args)).collect();
+    let mut shard = EngineShard::new(0, markets, wal, RiskEngine::new(RiskConfig::default()));
+    for market_id in 1..=args.markets {
+        shard.risk.update_mark(market_id, MID_PRICE);
+    }
+
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut resting: Vec<(u64, u64, OrderId)> = Vec::new();
+    let (mut inputs, mut fills) = (0u64, 0u64);
+    let started = Instant::now();
+    for i in 0..args.orders {
+        let market_id = 1 + i % args.markets;
+        let subaccount_id = 1 + rng.gen_range(0..args.subaccounts);
+        let side = if rng.gen_bool(0.5) { Side::Buy } else { Side::Sell };
+        // Passive orders rest on their own side of the mid; marketable ones reach across it.
+        let offset = 1 + rng.gen_range(0..args.levels.max(1));
+        let marketable = rng.gen_range(0..10_000) < args.cross_bps;
+        let price_ticks = match (side, marketable) {
+            (Side::Buy, false) | (Side::Sell, true) => MID_PRICE - offset,
+            (Side::Sell, false) | (Side::Buy, true) => MID_PRICE + offset,
+        };
+        let order = NewOrder {
+            request_id: format!("stress-{i}"),
+            market_id,
+            subaccount_id,
+            side,
+            order_type: OrderType::Limit,
+            tif: if marketable { TimeInForce::Ioc } else { TimeInForce::Gtc },
+            price_ticks,
+            qty: 1 + rng.gen_range(0..10),
+            reduce_only: false,
+            expiry_ts: 0,
+            nonce: 0,
+            client_ts: 0,
+            trigger: None,
+            signature: Default::default(),
+        };
+        inputs += 1;
+        for output in shard.handle_event(Event::NewOrder(order), i)? {
+            match output.event {
+                Event::Fill(_) => fills += 1,
+                Event::OrderAck(ack) => {
+                    if let Some(order_id) = ack.assigned_order_id.filter(|_| !marketable) {
+                        resting.push((market_id, subaccount_id, order_id));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if args.cancel_every > 0 && (i + 1) % args.cancel_every == 0 && !resting.is_empty() {
+            let (market_id, subaccount_id, order_id) = resting.swap_remove(rng.gen_range(0..resting.len()));
+            let cancel = CancelOrder {
+                request_id: format!("stress-cancel-{i}"),
+                market_id,
+                subaccount_id,
+                order_id: Some(order_id),
+                nonce_start: None,
+                nonce_end: None,
+            };
+            inputs += 1;
+            shard.handle_event(Event::CancelOrder(cancel), i)?;
+        }
+    }
+    let elapsed_ms = started.elapsed().as_millis();
+    let _ = std::fs::remove_file(&wal_path);
+
+    let (rss_bytes, peak_rss_bytes) = resident_memory();
+    let report = Report {
+        inputs,
+        fills,
+        elapsed_ms,
+        rss_bytes,
+        peak_rss_bytes,
+        allocations: AllocationReport {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+            peak_live_bytes: PEAK_LIVE_BYTES.load(Ordering::Relaxed),
+        },
+        order_owners: shard.order_owners.len(),
+        dedupe_entries: shard.dedupe.len(),
+        markets: (1..=args.markets)
+            .filter_map(|market_id| {
+                let book = shard.book(market_id)?;
+                let (bid_levels, ask_levels) = book.level_counts();
+                Some(BookReport {
+                    market_id,
+                    resting_orders: book.resting_orders(),
+                    slab_capacity: book.order_capacity(),
+                    bid_levels,
+                    ask_levels,
+                })
+            })
+            .collect(),
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+    println!("inputs={} fills={} elapsed_ms={}", report.inputs, report.fills, report.elapsed_ms);
+    match (report.rss_bytes, report.peak_rss_bytes) {
+        (Some(rss), Some(peak)) => println!("rss={:.1}MiB peak_rss={:.1}MiB", mib(rss), mib(peak)),
+        _ => println!("rss=unavailable"),
+    }
+    let allocations = &report.allocations;
+    println!(
+        "allocations={} deallocations={} allocated={:.1}MiB live={:.1}MiB peak_live={:.1}MiB",
+        allocations.allocations,
+        allocations.deallocations,
+        mib(allocations.allocated_bytes),
+        mib(allocations.live_bytes),
+        mib(allocations.peak_live_bytes)
+    );
+    println!("order_owners={} dedupe_entries={}", report.order_owners, report.dedupe_entries);
+    for market in &report.markets {
+        println!(
+            "market={} resting_orders={} slab_capacity={} bid_levels={} ask_levels={}",
+            market.market_id, market.resting_orders, market.slab_capacity, market.bid_levels, market.ask_levels
+        );
+    }
+    Ok(())
+}
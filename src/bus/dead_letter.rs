@@ -0,0 +1,85 @@
+//! Dead-letter subject for inputs the engine gives up on.
+//!
+//! Undecodable payloads are dead-lettered straight away. A shard error leaves the input unacked
+//! for redelivery and only dead-letters it on the last delivery attempt; a handler panic is
+//! dead-lettered at once, since redelivery would panic again. [`drain`] reads the subject back and
+//! can replay each payload onto the input subject.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use prost::Message;
+use tokio_stream::StreamExt;
+
+use crate::bus::{Bus, BusSubscription};
+use crate::config::WireCodec;
+use crate::models::{pb, DecodeError};
+
+/// Where an input failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterStage {
+    Decode,
+    Handler,
+}
+
+impl DeadLetterStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Decode => "DECODE",
+            Self::Handler => "HANDLER",
+        }
+    }
+}
+
+pub fn encode(codec: WireCodec, letter: &pb::DeadLetter) -> Bytes {
+    match codec {
+        WireCodec::Protobuf => Bytes::from(letter.encode_to_vec()),
+        WireCodec::Json => Bytes::from(serde_json::to_vec(letter).expect("dead letter serializes")),
+    }
+}
+
+pub fn decode(codec: WireCodec, payload: Bytes) -> Result<pb::DeadLetter, DecodeError> {
+    Ok(match codec {
+        WireCodec::Protobuf => pb::DeadLetter::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    })
+}
+
+/// Dead letter for `payload`, recording the error that stopped it.
+pub fn dead_letter(stage: DeadLetterStage, payload: Bytes, error: &str, deliveries: u64, ts: u64) -> pb::DeadLetter {
+    pb::DeadLetter {
+        payload,
+        stage: stage.as_str().to_string(),
+        error: error.to_string(),
+        deliveries,
+        ts,
+    }
+}
+
+pub async fn publish(bus: &dyn Bus, subject: &str, codec: WireCodec, letter: &pb::DeadLetter) -> anyhow::Result<()> {
+    metrics::counter!("dead_letters_total", "stage" => letter.stage.clone()).increment(1);
+    bus.publish(subject, encode(codec, letter)).await
+}
+
+/// Reads dead letters until none arrives for `idle`. With an `input_subject`, each payload is
+/// republished there and its dead letter acked; otherwise nothing is acked, so the letters are
+/// delivered again to the next reader.
+pub async fn drain(
+    bus: &dyn Bus,
+    subscription: &mut BusSubscription,
+    codec: WireCodec,
+    input_subject: Option<&str>,
+    idle: Duration,
+) -> anyhow::Result<Vec<pb::DeadLetter>> {
+    let mut letters = Vec::new();
+    while let Ok(Some(message)) = tokio::time::timeout(idle, subscription.stream.next()).await {
+        let letter = decode(codec, message.payload.clone())?;
+        if let Some(input_subject) = input_subject {
+            bus.publish(input_subject, letter.payload.clone()).await?;
+            bus.ack(message).await?;
+            metrics::counter!("dead_letters_replayed_total").increment(1);
+        }
+        letters.push(letter);
+    }
+    Ok(letters)
+}
@@ -0,0 +1,109 @@
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::bus::{Bus, BusAck, BusMessage, BusSubscription};
+
+const SUBSCRIBER_CAPACITY: usize = 65_536;
+
+/// Process-local bus for tests and simulations. Every published payload is also recorded so
+/// callers can inspect the full output history per subject.
+#[derive(Default)]
+pub struct InMemoryBus {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    subscribers: Vec<(String, mpsc::Sender<BusMessage>)>,
+    published: Vec<(String, Bytes)>,
+    acked: u64,
+}
+
+impl InMemoryBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish_now(&self, subject: &str, payload: Bytes) -> anyhow::Result<()> {
+        let mut state = self.state.lock();
+        state.published.push((subject.to_string(), payload.clone()));
+        state.subscribers.retain(|(_, tx)| !tx.is_closed());
+        for (filter, tx) in &state.subscribers {
+            if filter == subject {
+                tx.try_send(BusMessage {
+                    payload: payload.clone(),
+                    ack: BusAck::None,
+                })
+                .map_err(|err| anyhow::anyhow!("in-memory subscriber on {subject}: {err}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn subscribe_now(&self, subject: &str) -> mpsc::Receiver<BusMessage> {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CAPACITY);
+        self.state.lock().subscribers.push((subject.to_string(), tx));
+        rx
+    }
+
+    /// Like [`subscribe_now`](Self::subscribe_now), first delivering everything already published
+    /// on `subject`.
+    pub fn subscribe_history_now(&self, subject: &str) -> mpsc::Receiver<BusMessage> {
+        let mut state = self.state.lock();
+        let history: Vec<Bytes> = state
+            .published
+            .iter()
+            .filter(|(s, _)| s == subject)
+            .map(|(_, payload)| payload.clone())
+            .collect();
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CAPACITY.max(history.len() + 1));
+        for payload in history {
+            let _ = tx.try_send(BusMessage {
+                payload,
+                ack: BusAck::None,
+            });
+        }
+        state.subscribers.push((subject.to_string(), tx));
+        rx
+    }
+
+    pub fn published(&self, subject: &str) -> Vec<Bytes> {
+        self.state
+            .lock()
+            .published
+            .iter()
+            .filter(|(s, _)| s == subject)
+            .map(|(_, payload)| payload.clone())
+            .collect()
+    }
+
+    pub fn acked(&self) -> u64 {
+        self.state.lock().acked
+    }
+}
+
+#[async_trait::async_trait]
+impl Bus for InMemoryBus {
+    async fn publish(&self, subject: &str, payload: Bytes) -> anyhow::Result<()> {
+        self.publish_now(subject, payload)
+    }
+
+    async fn subscribe(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+        Ok(BusSubscription {
+            stream: ReceiverStream::new(self.subscribe_now(subject)),
+        })
+    }
+
+    async fn subscribe_history(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+        Ok(BusSubscription {
+            stream: ReceiverStream::new(self.subscribe_history_now(subject)),
+        })
+    }
+
+    async fn ack(&self, _message: BusMessage) -> anyhow::Result<()> {
+        self.state.lock().acked += 1;
+        Ok(())
+    }
+}
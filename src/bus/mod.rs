@@ -5,6 +5,18 @@ pub trait Bus: Send + Sync {
     async fn publish(&self, subject: &str, payload: Bytes) -> anyhow::Result<()>;
     async fn subscribe(&self, subject: &str) -> anyhow::Result<BusSubscription>;
     async fn ack(&self, message: BusMessage) -> anyhow::Result<()>;
+
+    /// Subscribes without a durable consumer, for requests that only matter while the caller is
+    /// running. Messages need no ack.
+    async fn subscribe_ephemeral(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+        self.subscribe(subject).await
+    }
+
+    /// Like [`subscribe_ephemeral`](Self::subscribe_ephemeral), but first delivers every message
+    /// the bus still retains on `subject`.
+    async fn subscribe_history(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+        self.subscribe(subject).await
+    }
 }
 
 pub struct BusMessage {
@@ -12,8 +24,18 @@ pub struct BusMessage {
     pub ack: BusAck,
 }
 
+impl BusMessage {
+    /// How many times the bus has delivered this message, counting this delivery.
+    pub fn deliveries(&self) -> u64 {
+        match &self.ack {
+            BusAck::Nats(message) => message.info().map_or(1, |info| info.delivered.max(1) as u64),
+            BusAck::None => 1,
+        }
+    }
+}
+
 pub enum BusAck {
-    Nats(async_nats::jetstream::Message),
+    Nats(Box<async_nats::jetstream::Message>),
     None,
 }
 
@@ -21,4 +43,6 @@ pub struct BusSubscription {
     pub stream: tokio_stream::wrappers::ReceiverStream<BusMessage>,
 }
 
+pub mod dead_letter;
+pub mod memory;
 pub mod nats;
@@ -1,4 +1,5 @@
 use std::collections::BTreeSet;
+use std::time::Duration;
 
 use async_nats::jetstream;
 use bytes::Bytes;
@@ -8,10 +9,23 @@ use tokio_stream::wrappers::ReceiverStream;
 
 use crate::bus::{Bus, BusAck, BusMessage, BusSubscription};
 
+/// A message as the stream stores it.
+#[derive(Debug, Clone)]
+pub struct StreamRecord {
+    pub stream_seq: u64,
+    /// When the stream stored the message, in nanoseconds since the epoch.
+    pub ts_ns: u64,
+    pub payload: Bytes,
+}
+
 pub struct JetStreamBus {
     jetstream: jetstream::Context,
     stream_name: String,
     durable_name: String,
+    ack_wait: Duration,
+    max_deliver: i64,
+    /// Unacked messages the durable consumer hands out at once; 0 leaves the server default.
+    max_ack_pending: i64,
 }
 
 impl JetStreamBus {
@@ -30,8 +44,120 @@ impl JetStreamBus {
             jetstream,
             stream_name,
             durable_name,
+            ack_wait: Duration::from_secs(30),
+            max_deliver: -1,
+            max_ack_pending: 0,
+        })
+    }
+
+    /// Ephemeral consumer that needs no acks, starting at `deliver_policy`.
+    async fn subscribe_unacked(
+        &self,
+        subject: &str,
+        deliver_policy: jetstream::consumer::DeliverPolicy,
+    ) -> anyhow::Result<BusSubscription> {
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        let consumer = stream
+            .create_consumer(jetstream::consumer::pull::Config {
+                filter_subject: subject.to_string(),
+                deliver_policy,
+                ack_policy: jetstream::consumer::AckPolicy::None,
+                ..Default::default()
+            })
+            .await?;
+
+        let (sender, receiver) = mpsc::channel(1024);
+        tokio::spawn(async move {
+            let mut messages = match consumer.messages().await {
+                Ok(messages) => messages,
+                Err(_) => return,
+            };
+
+            while let Some(message) = messages.next().await {
+                let Ok(message) = message else { break };
+                let payload = message.message.payload.clone();
+                if sender
+                    .send(BusMessage {
+                        payload,
+                        ack: BusAck::None,
+                    })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(BusSubscription {
+            stream: ReceiverStream::new(receiver),
         })
     }
+
+    /// Bounds redelivery so the engine's dedupe window can be sized to cover it.
+    pub fn with_redelivery(mut self, ack_wait: Duration, max_deliver: u32) -> Self {
+        self.ack_wait = ack_wait;
+        self.max_deliver = max_deliver as i64;
+        self
+    }
+
+    /// Every message on `subject` from stream sequence `start_seq` on, in stream order, ending with
+    /// the last one stored when the call was made. Nothing is acked or left behind; an error
+    /// reading the stream is the last item.
+    pub async fn read_stream(&self, subject: &str, start_seq: u64) -> anyhow::Result<mpsc::Receiver<anyhow::Result<StreamRecord>>> {
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        let consumer = stream
+            .create_consumer(jetstream::consumer::pull::Config {
+                filter_subject: subject.to_string(),
+                deliver_policy: jetstream::consumer::DeliverPolicy::ByStartSequence {
+                    start_sequence: start_seq.max(1),
+                },
+                ack_policy: jetstream::consumer::AckPolicy::None,
+                ..Default::default()
+            })
+            .await?;
+
+        let (sender, receiver) = mpsc::channel(1024);
+        if consumer.cached_info().num_pending == 0 {
+            return Ok(receiver);
+        }
+        tokio::spawn(async move {
+            let mut messages = match consumer.messages().await {
+                Ok(messages) => messages,
+                Err(err) => {
+                    let _ = sender.send(Err(err.into())).await;
+                    return;
+                }
+            };
+            while let Some(message) = messages.next().await {
+                let record = message.map_err(anyhow::Error::from).and_then(|message| {
+                    let info = message.info().map_err(|err| anyhow::anyhow!(err.to_string()))?;
+                    let record = StreamRecord {
+                        stream_seq: info.stream_sequence,
+                        ts_ns: u64::try_from(info.published.unix_timestamp_nanos()).unwrap_or_default(),
+                        payload: message.message.payload.clone(),
+                    };
+                    Ok((record, info.pending))
+                });
+                let (item, done) = match record {
+                    Ok((record, pending)) => (Ok(record), pending == 0),
+                    Err(err) => (Err(err), true),
+                };
+                if sender.send(item).await.is_err() || done {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    /// Lets a consumer hold up to `max_ack_pending` messages unacked for up to `ack_wait`, for
+    /// consumers that ack in batches.
+    pub fn with_ack_window(mut self, ack_wait: Duration, max_ack_pending: usize) -> Self {
+        self.ack_wait = ack_wait;
+        self.max_ack_pending = max_ack_pending as i64;
+        self
+    }
 }
 
 #[async_trait::async_trait]
@@ -52,6 +178,9 @@ impl Bus for JetStreamBus {
                 jetstream::consumer::pull::Config {
                     durable_name: Some(self.durable_name.clone()),
                     filter_subject: subject.to_string(),
+                    ack_wait: self.ack_wait,
+                    max_deliver: self.max_deliver,
+                    max_ack_pending: self.max_ack_pending,
                     ..Default::default()
                 },
             )
@@ -70,7 +199,7 @@ impl Bus for JetStreamBus {
                 let _ = sender
                     .send(BusMessage {
                         payload,
-                        ack: BusAck::Nats(message),
+                        ack: BusAck::Nats(Box::new(message)),
                     })
                     .await;
             }
@@ -81,6 +210,14 @@ impl Bus for JetStreamBus {
         })
     }
 
+    async fn subscribe_ephemeral(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+        self.subscribe_unacked(subject, jetstream::consumer::DeliverPolicy::New).await
+    }
+
+    async fn subscribe_history(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+        self.subscribe_unacked(subject, jetstream::consumer::DeliverPolicy::All).await
+    }
+
     async fn ack(&self, message: BusMessage) -> anyhow::Result<()> {
         match message.ack {
             BusAck::Nats(msg) => {
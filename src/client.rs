@@ -0,0 +1,293 @@
+//! Client SDK over the [`Bus`] trait.
+//!
+//! A single background task consumes the output subject and fans events out to whoever is
+//! waiting: acks are correlated by `request_id`, fills are routed to the subaccount that owns
+//! the maker or taker order, and book deltas feed a per-market watch channel that always holds
+//! the latest full depth view, and cancel confirmations (`OrderUpdate`s) are correlated by order
+//! id. Engines configured with `bus.private_acks` publish acks on
+//! per-subaccount subjects instead; [`ClobClient::follow`] adds those to the same fan-out.
+//!
+//! [`ClobClient::replay_outputs`] serves consumers that restart and need every output since the
+//! last one they processed, rather than only live ones.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+
+use crate::bus::{Bus, BusSubscription};
+use crate::config::WireCodec;
+use crate::engine::router::{decode_output_envelope, decode_output_with, encode_input_with};
+use crate::models::{
+    BookDelta, CancelOrder, Event, EventEnvelope, Fill, MarketId, NewOrder, OrderAck, OrderId, OrderUpdate, OrderUpdateStatus, SubaccountId,
+};
+use crate::persistence::wal::Wal;
+
+#[derive(Default)]
+struct Routes {
+    pending_acks: HashMap<String, (SubaccountId, oneshot::Sender<OrderAck>)>,
+    pending_cancels: HashMap<OrderId, oneshot::Sender<OrderUpdate>>,
+    order_owners: HashMap<OrderId, SubaccountId>,
+    fill_streams: Vec<(SubaccountId, mpsc::UnboundedSender<Fill>)>,
+    books: HashMap<MarketId, watch::Sender<Option<BookDelta>>>,
+}
+
+/// Which outputs [`ClobClient::replay_outputs`] delivers.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayFilter {
+    /// First `engine_seq` to deliver. Each shard numbers its inputs separately, so a consumer
+    /// resuming several shards replays each with its own filter.
+    pub from_engine_seq: u64,
+    pub shard_id: Option<usize>,
+    /// Keeps only outputs for this market; acks carry no market and are dropped.
+    pub market_id: Option<MarketId>,
+    /// The engine's output journal (`persistence.journal_path`), read first so that outputs the
+    /// bus no longer retains are still delivered.
+    pub journal_path: Option<PathBuf>,
+}
+
+impl ReplayFilter {
+    fn matches(&self, envelope: &EventEnvelope) -> bool {
+        envelope.engine_seq >= self.from_engine_seq
+            && self.shard_id.is_none_or(|shard_id| envelope.shard_id == shard_id)
+            && self.market_id.is_none_or(|market_id| output_market(&envelope.event) == Some(market_id))
+    }
+}
+
+pub struct ClobClient {
+    bus: Arc<dyn Bus>,
+    input_subject: String,
+    output_subject: String,
+    codec: WireCodec,
+    routes: Arc<Mutex<Routes>>,
+    dispatcher: JoinHandle<()>,
+    followers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl ClobClient {
+    pub async fn connect(bus: Arc<dyn Bus>, input_subject: String, output_subject: &str) -> anyhow::Result<Self> {
+        Self::connect_with_codec(bus, input_subject, output_subject, WireCodec::Protobuf).await
+    }
+
+    /// Like [`connect`](Self::connect), for an engine configured with `bus.codec`.
+    pub async fn connect_with_codec(
+        bus: Arc<dyn Bus>,
+        input_subject: String,
+        output_subject: &str,
+        codec: WireCodec,
+    ) -> anyhow::Result<Self> {
+        let subscription = bus.subscribe(output_subject).await?;
+        let routes = Arc::new(Mutex::new(Routes::default()));
+        let dispatcher = spawn_dispatcher(Arc::clone(&bus), subscription, codec, Arc::clone(&routes));
+        Ok(Self {
+            bus,
+            input_subject,
+            output_subject: output_subject.to_string(),
+            codec,
+            routes,
+            dispatcher,
+            followers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Also dispatches outputs published on `subject`, e.g. a subaccount's private ack subject
+    /// (see [`account_subject`](crate::engine::router::account_subject)) or a wildcard over them.
+    pub async fn follow(&self, subject: &str) -> anyhow::Result<()> {
+        let subscription = self.bus.subscribe(subject).await?;
+        let follower = spawn_dispatcher(Arc::clone(&self.bus), subscription, self.codec, Arc::clone(&self.routes));
+        self.followers.lock().push(follower);
+        Ok(())
+    }
+
+    /// Publishes `order` and waits for the engine's ack with the same `request_id`.
+    pub async fn submit_order(&self, order: NewOrder, timeout: Duration) -> anyhow::Result<OrderAck> {
+        let request_id = order.request_id.clone();
+        let (tx, rx) = oneshot::channel();
+        self.routes
+            .lock()
+            .pending_acks
+            .insert(request_id.clone(), (order.subaccount_id, tx));
+        let payload = encode_input_with(self.codec, Event::NewOrder(order))?;
+        if let Err(err) = self.bus.publish(&self.input_subject, payload).await {
+            self.routes.lock().pending_acks.remove(&request_id);
+            return Err(err);
+        }
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(ack)) => Ok(ack),
+            Ok(Err(_)) => anyhow::bail!("client dispatcher stopped before ack for {request_id}"),
+            Err(_) => {
+                self.routes.lock().pending_acks.remove(&request_id);
+                anyhow::bail!("timed out waiting for ack for {request_id}")
+            }
+        }
+    }
+
+    pub async fn cancel_order(&self, cancel: CancelOrder) -> anyhow::Result<()> {
+        self.publish(Event::CancelOrder(cancel)).await
+    }
+
+    /// Publishes a cancel of one order and waits for the `Cancelled` order update confirming it.
+    /// The engine sends nothing for an order it does not know, so that ends in a timeout.
+    pub async fn cancel_order_confirmed(&self, cancel: CancelOrder, timeout: Duration) -> anyhow::Result<OrderUpdate> {
+        let order_id = cancel
+            .order_id
+            .ok_or_else(|| anyhow::anyhow!("only cancels of a single order id are confirmed"))?;
+        let (tx, rx) = oneshot::channel();
+        self.routes.lock().pending_cancels.insert(order_id, tx);
+        if let Err(err) = self.publish(Event::CancelOrder(cancel)).await {
+            self.routes.lock().pending_cancels.remove(&order_id);
+            return Err(err);
+        }
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(update)) => Ok(update),
+            Ok(Err(_)) => anyhow::bail!("client dispatcher stopped before cancel of order {order_id} was confirmed"),
+            Err(_) => {
+                self.routes.lock().pending_cancels.remove(&order_id);
+                anyhow::bail!("timed out waiting for cancel of order {order_id}")
+            }
+        }
+    }
+
+    /// Publishes any input on the input subject without waiting for an output, e.g. a price update.
+    pub async fn publish(&self, event: Event) -> anyhow::Result<()> {
+        self.bus.publish(&self.input_subject, encode_input_with(self.codec, event)?).await
+    }
+
+    /// Fills involving orders this client submitted for `subaccount_id`.
+    pub fn fills(&self, subaccount_id: SubaccountId) -> mpsc::UnboundedReceiver<Fill> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.lock().fill_streams.push((subaccount_id, tx));
+        rx
+    }
+
+    /// Every output matching `filter` from `filter.from_engine_seq` on, followed by live outputs as
+    /// they are published. Journaled outputs come first; the bus then supplies, per shard, only
+    /// outputs past the last journaled `engine_seq`, so the stream has no duplicates and no gap as
+    /// long as the journal covers whatever the bus has pruned.
+    pub async fn replay_outputs(&self, filter: ReplayFilter) -> anyhow::Result<mpsc::UnboundedReceiver<EventEnvelope>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut journaled = HashMap::<usize, u64>::new();
+        if let Some(path) = &filter.journal_path {
+            for envelope in Wal::load(path)? {
+                if !is_published_output(&envelope.event) || !filter.matches(&envelope) {
+                    continue;
+                }
+                journaled.insert(envelope.shard_id, envelope.engine_seq);
+                let _ = tx.send(envelope);
+            }
+        }
+        let mut subscription = self.bus.subscribe_history(&self.output_subject).await?;
+        let codec = self.codec;
+        tokio::spawn(async move {
+            while let Some(message) = subscription.stream.next().await {
+                let Ok(envelope) = decode_output_envelope(codec, message.payload) else { continue };
+                let journaled = journaled
+                    .get(&envelope.shard_id)
+                    .is_some_and(|last| envelope.engine_seq <= *last);
+                if journaled || !filter.matches(&envelope) {
+                    continue;
+                }
+                if tx.send(envelope).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    /// Latest depth view for `market_id`. Every `BookDelta` carries the full top-of-book levels,
+    /// so the view resynchronizes on each message; deltas older than the current one are dropped.
+    pub fn book(&self, market_id: MarketId) -> watch::Receiver<Option<BookDelta>> {
+        self.routes
+            .lock()
+            .books
+            .entry(market_id)
+            .or_insert_with(|| watch::channel(None).0)
+            .subscribe()
+    }
+}
+
+impl Drop for ClobClient {
+    fn drop(&mut self) {
+        self.dispatcher.abort();
+        for follower in self.followers.lock().drain(..) {
+            follower.abort();
+        }
+    }
+}
+
+fn spawn_dispatcher(bus: Arc<dyn Bus>, mut subscription: BusSubscription, codec: WireCodec, routes: Arc<Mutex<Routes>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Some(message) = subscription.stream.next().await {
+            if let Ok(event) = decode_output_with(codec, message.payload.clone()) {
+                dispatch(&mut routes.lock(), event);
+            }
+            let _ = bus.ack(message).await;
+        }
+    })
+}
+
+/// Journaled outputs that the engine also publishes on the output subject in a form
+/// [`decode_output_with`] accepts.
+fn is_published_output(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::OrderAck(_) | Event::Fill(_) | Event::BookDelta(_) | Event::AuctionIndicative(_)
+    )
+}
+
+fn output_market(event: &Event) -> Option<MarketId> {
+    match event {
+        Event::Fill(fill) => Some(fill.market_id),
+        Event::BookDelta(delta) => Some(delta.market_id),
+        Event::AuctionIndicative(indicative) => Some(indicative.market_id),
+        _ => None,
+    }
+}
+
+fn dispatch(routes: &mut Routes, event: Event) {
+    match event {
+        Event::OrderAck(ack) => {
+            if let Some((subaccount_id, tx)) = routes.pending_acks.remove(&ack.request_id) {
+                if let Some(order_id) = ack.assigned_order_id {
+                    routes.order_owners.insert(order_id, subaccount_id);
+                }
+                let _ = tx.send(ack);
+            }
+        }
+        Event::Fill(fill) => {
+            routes.fill_streams.retain(|(_, tx)| !tx.is_closed());
+            let maker = routes.order_owners.get(&fill.maker_order_id).copied();
+            let taker = routes.order_owners.get(&fill.taker_order_id).copied();
+            for (subaccount_id, tx) in &routes.fill_streams {
+                if maker == Some(*subaccount_id) || taker == Some(*subaccount_id) {
+                    let _ = tx.send(fill.clone());
+                }
+            }
+        }
+        Event::OrderUpdate(update) if update.status == OrderUpdateStatus::Cancelled => {
+            if let Some(tx) = routes.pending_cancels.remove(&update.order_id) {
+                let _ = tx.send(update);
+            }
+        }
+        Event::BookDelta(delta) => {
+            let sender = routes
+                .books
+                .entry(delta.market_id)
+                .or_insert_with(|| watch::channel(None).0);
+            sender.send_if_modified(|current| {
+                let newer = current.as_ref().is_none_or(|seen| delta.engine_seq >= seen.engine_seq);
+                if newer {
+                    *current = Some(delta);
+                }
+                newer
+            });
+        }
+        _ => {}
+    }
+}
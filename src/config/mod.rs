@@ -1,4 +1,9 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::models::{RiskParameter, RiskParamsUpdate};
+use crate::risk::RiskConfig;
+
+pub use hypermarket_clob_core::BookLayout;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
@@ -6,9 +11,147 @@ pub struct Settings {
     pub shard_count: usize,
     #[serde(default)]
     pub markets: Vec<MarketConfig>,
+    /// Optional seed parent accounts; more can be added through `bus.accounts_bucket`.
+    #[serde(default)]
+    pub accounts: Vec<AccountConfig>,
+    /// Optional seed market allowlists; more can be added through `bus.permissions_bucket`.
+    #[serde(default)]
+    pub permissions: Vec<MarketPermissions>,
+    /// Optional seed order-signing keys; more can be added through `bus.keys_bucket`.
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKeyConfig>,
+    /// Optional seed subaccounts; more can be added through `bus.subaccounts_bucket`.
+    #[serde(default)]
+    pub subaccounts: Vec<SubaccountConfig>,
+    /// Reject orders from subaccounts without a registered signing key. Subaccounts with a key
+    /// must always sign.
+    #[serde(default)]
+    pub require_signatures: bool,
+    /// Subaccount credited with liquidation fees; without one no fee is charged.
+    #[serde(default)]
+    pub insurance_fund_subaccount: Option<u64>,
     pub persistence: PersistenceConfig,
     pub snapshot_interval_secs: u64,
     pub book_delta_levels: usize,
+    /// Publish only the latest `BookDelta` per market for each batch of inputs a shard drains.
+    #[serde(default = "default_true")]
+    pub coalesce_book_deltas: bool,
+    /// Stamp outputs with a sequence shared by all shards and the router's ingest order; see
+    /// [`crate::engine::sequencer`].
+    #[serde(default)]
+    pub global_sequencing: bool,
+    /// Stamp fills and book deltas with a gap-free sequence per market; see
+    /// [`crate::engine::sequencer::MarketSequencer`].
+    #[serde(default)]
+    pub market_sequencing: bool,
+    #[serde(default)]
+    pub risk_metrics: RiskMetricsConfig,
+    #[serde(default)]
+    pub dedupe: DedupeConfig,
+    /// Risk limits applied to every market unless the market overrides them.
+    #[serde(default)]
+    pub risk: RiskConfig,
+    /// Periodic full-depth book publications; `None` publishes none.
+    #[serde(default)]
+    pub book_snapshots: Option<BookSnapshotConfig>,
+    /// Admission control on how long orders queue before their shard applies them; `None` admits
+    /// every order.
+    #[serde(default)]
+    pub latency_budget: Option<LatencyBudgetConfig>,
+}
+
+/// When each continuous market publishes a [`DepthSnapshot`](crate::models::DepthSnapshot) on
+/// `bus.book_snapshot_subject`: after `interval_secs` of engine time, or once `every_deltas` book
+/// deltas were emitted since the last one, whichever comes first. 0 disables either trigger.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BookSnapshotConfig {
+    #[serde(default)]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub every_deltas: u64,
+}
+
+/// Orders that waited longer than `max_queue_delay_ms` between the router taking them off the bus
+/// and their shard applying them are rejected, or with `action: ioc` lose their resting part.
+/// Only orders that can trade on arrival are checked: post-only and triggered orders always pass.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LatencyBudgetConfig {
+    pub max_queue_delay_ms: u64,
+    #[serde(default)]
+    pub action: LatencyBudgetAction,
+}
+
+/// What happens to an order over its [`LatencyBudgetConfig`].
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyBudgetAction {
+    /// Reject it with [`RejectReason::QueueDelay`](crate::models::RejectReason::QueueDelay).
+    #[default]
+    Reject,
+    /// Apply it as immediate-or-cancel, so whatever does not fill at once is not left resting.
+    Ioc,
+}
+
+/// Margin utilization and account health gauges; see [`crate::engine::health`].
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RiskMetricsConfig {
+    /// Minimum engine time between refreshes of each shard's gauges.
+    #[serde(default = "default_risk_metrics_interval_secs")]
+    pub interval_secs: u64,
+    /// Buffer above maintenance margin within which an account counts as near maintenance.
+    #[serde(default = "default_margin_warning_bps")]
+    pub margin_warning_bps: u64,
+    /// Also publish a [`MarginWarning`](crate::models::MarginWarning) on a subaccount's account
+    /// subject whenever its margin changes while it is near maintenance.
+    #[serde(default)]
+    pub margin_warnings: bool,
+}
+
+impl Default for RiskMetricsConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_risk_metrics_interval_secs(),
+            margin_warning_bps: default_margin_warning_bps(),
+            margin_warnings: false,
+        }
+    }
+}
+
+/// Sizing of each shard's request-id [`DedupeWindow`](crate::engine::dedupe::DedupeWindow).
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct DedupeConfig {
+    /// How long a request id is remembered; defaults to twice `bus.redelivery_horizon_secs()`,
+    /// and at least 300.
+    #[serde(default)]
+    pub window_secs: Option<u64>,
+    /// Memory cap on remembered ids; evicting below the window lets redeliveries through.
+    #[serde(default = "default_dedupe_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for DedupeConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: None,
+            max_entries: default_dedupe_max_entries(),
+        }
+    }
+}
+
+fn default_dedupe_max_entries() -> usize {
+    crate::engine::shard::DEFAULT_DEDUPE_MAX_ENTRIES
+}
+
+fn default_risk_metrics_interval_secs() -> u64 {
+    5
+}
+
+fn default_margin_warning_bps() -> u64 {
+    2_000
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -21,6 +164,112 @@ pub struct BusConfig {
     pub durable_name: String,
     #[serde(default = "default_markets_bucket")]
     pub markets_bucket: String,
+    #[serde(default = "default_accounts_bucket")]
+    pub accounts_bucket: String,
+    #[serde(default = "default_permissions_bucket")]
+    pub permissions_bucket: String,
+    #[serde(default = "default_keys_bucket")]
+    pub keys_bucket: String,
+    /// KV bucket of operator risk parameter updates (key = market_id, value = `RiskParamsUpdate`
+    /// JSON), applied to the owning shard as logged inputs.
+    #[serde(default = "default_risk_params_bucket")]
+    pub risk_params_bucket: String,
+    /// KV bucket of subaccount bootstrap records (key = subaccount_id, value = `SubaccountConfig`
+    /// JSON).
+    #[serde(default = "default_subaccounts_bucket")]
+    pub subaccounts_bucket: String,
+    /// Subject for market maker compliance reports, kept off the main output stream.
+    #[serde(default = "default_compliance_subject")]
+    pub compliance_subject: String,
+    /// Subject for inputs the engine cannot decode or process; see [`crate::bus::dead_letter`].
+    #[serde(default = "default_dead_letter_subject")]
+    pub dead_letter_subject: String,
+    /// Outputs and acks a shard may hold while the bus rejects publishes; when full, the shard
+    /// stops taking inputs until publishing recovers.
+    #[serde(default = "default_output_buffer")]
+    pub output_buffer: usize,
+    /// Subject on which consumers request missed outputs; see [`crate::engine::resend`].
+    #[serde(default = "default_resend_subject")]
+    pub resend_subject: String,
+    /// Subject on which users query ADL rankings; see [`crate::engine::adl`].
+    #[serde(default = "default_adl_subject")]
+    pub adl_subject: String,
+    /// Subject on which users query their trade history; see [`crate::persistence::trades`].
+    #[serde(default = "default_trades_subject")]
+    pub trades_subject: String,
+    /// Subject on which users query a subaccount's margin picture; see [`crate::engine::health`].
+    #[serde(default = "default_equity_subject")]
+    pub equity_subject: String,
+    /// Subject for periodic full-depth book snapshots; see [`BookSnapshotConfig`].
+    #[serde(default = "default_book_snapshot_subject")]
+    pub book_snapshot_subject: String,
+    /// Prefix of the per-subaccount subjects for private outputs: a subaccount's fills go to
+    /// `<prefix>.<subaccount_id>.fills`, and with `private_acks` its acks to
+    /// `<prefix>.<subaccount_id>.acks`.
+    #[serde(default = "default_account_subject")]
+    pub account_subject: String,
+    /// Publishes acks of inputs that name a subaccount on its account subject instead of the
+    /// output subject.
+    #[serde(default)]
+    pub private_acks: bool,
+    /// Recent outputs each shard keeps for resend requests.
+    #[serde(default = "default_resend_history")]
+    pub resend_history: usize,
+    /// Payload encoding on the input, output, compliance and dead-letter subjects.
+    #[serde(default)]
+    pub codec: WireCodec,
+    /// JetStream consumer ack wait; unacked inputs are redelivered after this long.
+    #[serde(default = "default_ack_wait_secs")]
+    pub ack_wait_secs: u64,
+    /// JetStream consumer delivery attempts per input.
+    #[serde(default = "default_max_deliver")]
+    pub max_deliver: u32,
+}
+
+/// Encoding of bus payloads. JSON mirrors the protobuf messages field for field, with the
+/// `payload` oneof as an object keyed by the variant's field name.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireCodec {
+    #[default]
+    Protobuf,
+    Json,
+}
+
+impl BusConfig {
+    /// How long after first delivery an input can still be redelivered; the dedupe window must
+    /// cover at least this much.
+    pub fn redelivery_horizon_secs(&self) -> u64 {
+        self.ack_wait_secs.saturating_mul(self.max_deliver as u64)
+    }
+}
+
+fn default_ack_wait_secs() -> u64 {
+    30
+}
+
+fn default_max_deliver() -> u32 {
+    10
+}
+
+fn default_indicative_interval_secs() -> u64 {
+    1
+}
+
+fn default_min_sources() -> usize {
+    1
+}
+
+fn default_source_weight() -> u64 {
+    1
+}
+
+fn default_volatility_window() -> usize {
+    20
+}
+
+fn default_liquidation_tranche_bps() -> u64 {
+    2_500
 }
 
 fn default_stream_name() -> String {
@@ -31,7 +280,166 @@ fn default_markets_bucket() -> String {
     "MARKETS".to_string()
 }
 
+fn default_risk_params_bucket() -> String {
+    "RISK_PARAMS".to_string()
+}
+
+fn default_accounts_bucket() -> String {
+    "ACCOUNTS".to_string()
+}
+
+/// A parent account and the subaccounts it owns. Limits apply to the children's combined
+/// exposure in each market; 0 means unlimited.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AccountConfig {
+    pub parent_id: u64,
+    pub subaccounts: Vec<u64>,
+    /// Cap on the absolute sum of the children's positions in any one market.
+    #[serde(default)]
+    pub max_total_position: i64,
+    /// Cap on the children's combined resting orders in any one market.
+    #[serde(default)]
+    pub max_total_open_orders: u64,
+}
+
+fn default_compliance_subject() -> String {
+    "clob.compliance".to_string()
+}
+
+/// Outputs and acks a shard's outbox holds before it stops taking inputs.
+pub const DEFAULT_OUTBOX_CAPACITY: usize = 65_536;
+
+fn default_output_buffer() -> usize {
+    DEFAULT_OUTBOX_CAPACITY
+}
+
+fn default_resend_subject() -> String {
+    "clob.resend".to_string()
+}
+
+fn default_adl_subject() -> String {
+    "clob.adl".to_string()
+}
+
+fn default_trades_subject() -> String {
+    "clob.trades".to_string()
+}
+
+fn default_equity_subject() -> String {
+    "clob.equity".to_string()
+}
+
+fn default_book_snapshot_subject() -> String {
+    "clob.book".to_string()
+}
+
+fn default_account_subject() -> String {
+    "clob.out.acct".to_string()
+}
+
+fn default_max_trades_per_subaccount() -> usize {
+    1_000
+}
+
+fn default_trade_retention_secs() -> u64 {
+    7 * 86_400
+}
+
+fn default_archive_prefix() -> String {
+    "outputs".to_string()
+}
+
+fn default_archive_max_events() -> usize {
+    10_000
+}
+
+fn default_archive_max_bytes() -> usize {
+    8 * 1024 * 1024
+}
+
+fn default_archive_flush_secs() -> u64 {
+    60
+}
+
+fn default_archive_compression_level() -> i32 {
+    3
+}
+
+fn default_resend_history() -> usize {
+    crate::engine::resend::DEFAULT_RESEND_HISTORY
+}
+
+fn default_dead_letter_subject() -> String {
+    "clob.dead_letter".to_string()
+}
+
+fn default_permissions_bucket() -> String {
+    "PERMISSIONS".to_string()
+}
+
+fn default_keys_bucket() -> String {
+    "SIGNING_KEYS".to_string()
+}
+
+fn default_subaccounts_bucket() -> String {
+    "SUBACCOUNTS".to_string()
+}
+
+/// Restricts a market to an allowlist of subaccounts, e.g. during a guarded launch. Markets
+/// without an entry are open to everyone; writing `restricted: false` lifts a restriction.
 #[derive(Debug, Clone, Deserialize)]
+pub struct MarketPermissions {
+    pub market_id: u64,
+    #[serde(default = "default_true")]
+    pub restricted: bool,
+    #[serde(default)]
+    pub allowed_subaccounts: Vec<u64>,
+}
+
+/// Ed25519 public key a subaccount signs its orders with. An empty `public_key` removes the key.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningKeyConfig {
+    pub subaccount_id: u64,
+    /// Hex-encoded 32-byte public key.
+    #[serde(default)]
+    pub public_key: String,
+}
+
+/// Bootstrap record of a subaccount, applied to every shard as a logged input so replay
+/// reproduces it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SubaccountConfig {
+    pub subaccount_id: u64,
+    /// Collateral to seed. Only credited while the receiving shard has no ledger entry for the
+    /// subaccount, so provisioning it again never resets a live balance.
+    #[serde(default)]
+    pub collateral: Vec<CollateralSeed>,
+    /// Margin mode recorded on the subaccount's ledgers.
+    #[serde(default)]
+    pub cross_margin: bool,
+    /// Fees charged instead of each market's own; `None` charges the market's.
+    #[serde(default)]
+    pub fee_tier: Option<FeeTier>,
+    /// Hex-encoded order-signing key, registered as by `signing_keys`; `None` leaves the key as is.
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+/// Collateral credited to the ledger of the shard owning `market_id`; collateral is held per shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CollateralSeed {
+    pub market_id: u64,
+    pub amount: i64,
+}
+
+/// Maker and taker fees of a subaccount, in basis points, in every market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeeTier {
+    pub maker_fee_bps: i64,
+    pub taker_fee_bps: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MarketConfig {
     pub market_id: u64,
     pub tick_size: u64,
@@ -46,9 +454,126 @@ pub struct MarketConfig {
     pub max_open_orders_per_subaccount: u64,
     pub matching_mode: MatchingMode,
     pub batch_interval_ms: u64,
+    /// Resting orders to pre-allocate book storage for; 0 grows on demand.
+    #[serde(default)]
+    pub order_capacity: usize,
+    #[serde(default)]
+    pub book_layout: BookLayout,
+    /// Minimum spacing, in engine clock units, between indicative auction publications for a
+    /// batch market. 0 publishes after every event that touches a non-empty auction.
+    #[serde(default = "default_indicative_interval_secs")]
+    pub indicative_interval_secs: u64,
+    /// Upper bound on the seeded random delay added to each batch clearing time, so orders racing
+    /// the nominal boundary cannot know which auction they land in. 0 clears on the boundary.
+    #[serde(default)]
+    pub clearing_jitter_ms: u64,
+    /// Quoting obligations for the market's designated makers; `None` tracks nothing.
+    #[serde(default)]
+    pub maker_obligation: Option<MakerObligation>,
+    /// Share of an underwater position closed per liquidation tranche, in basis points. Health is
+    /// re-evaluated between tranches, so a position is only closed as far as needed.
+    #[serde(default = "default_liquidation_tranche_bps")]
+    pub liquidation_tranche_bps: u64,
+    /// Subaccounts that take over liquidation tranches at mark before any size reaches the book,
+    /// in assignment order.
+    #[serde(default)]
+    pub backstop_providers: Vec<u64>,
+    /// Fee charged to a liquidated subaccount on each tranche's liquidated notional, in basis
+    /// points, and credited to the insurance fund.
+    #[serde(default)]
+    pub liquidation_fee_bps: u64,
+    /// Funding the engine schedules itself; `None` leaves funding to `FundingUpdate` inputs.
+    #[serde(default)]
+    pub funding: Option<FundingConfig>,
+    /// Named price sources combined into the market's prices; `None` applies every
+    /// `PriceUpdate` as is.
+    #[serde(default)]
+    pub oracle: Option<OracleConfig>,
+    /// Bounds within which the price band follows realized volatility; `None` keeps
+    /// `price_band_bps` fixed.
+    #[serde(default)]
+    pub dynamic_band: Option<DynamicBandConfig>,
+    /// Bounds within which initial margin follows realized volatility; `None` keeps
+    /// `initial_margin_bps` fixed.
+    #[serde(default)]
+    pub dynamic_margin: Option<DynamicMarginConfig>,
+    /// Overrides the global `risk.max_leverage` for this market; `Some(0)` lifts the cap.
+    #[serde(default)]
+    pub max_leverage: Option<u64>,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+/// Adaptive price band of a market; see [`crate::engine::volatility`].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DynamicBandConfig {
+    /// Mark-to-mark returns the realized volatility is measured over.
+    #[serde(default = "default_volatility_window")]
+    pub window: usize,
+    /// Band width in multiples of the realized volatility per mark update.
+    pub multiplier: u64,
+    pub min_band_bps: u64,
+    pub max_band_bps: u64,
+}
+
+/// Volatility-scaled initial margin of a market; see [`crate::engine::volatility`].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DynamicMarginConfig {
+    /// Mark-to-mark returns the realized volatility is measured over.
+    #[serde(default = "default_volatility_window")]
+    pub window: usize,
+    /// Engine clock units between recomputations.
+    pub interval_secs: u64,
+    /// Initial margin in multiples of the realized volatility per mark update.
+    pub multiplier: u64,
+    pub min_margin_bps: u64,
+    pub max_margin_bps: u64,
+}
+
+/// Oracle sources of a market and the filters applied to them; see [`crate::engine::oracle`].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OracleConfig {
+    pub sources: Vec<OracleSource>,
+    /// Engine clock units after which a source's last quote no longer counts.
+    pub max_staleness_secs: u64,
+    /// Largest distance from the median index price a quote may have, in basis points; 0 keeps
+    /// every fresh quote.
+    pub max_deviation_bps: u64,
+    /// Sources that must survive the filters for the prices to be updated.
+    #[serde(default = "default_min_sources")]
+    pub min_sources: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OracleSource {
+    pub name: String,
+    #[serde(default = "default_source_weight")]
+    pub weight: u64,
+}
+
+/// Per-market funding parameters; see [`crate::engine::funding`].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FundingConfig {
+    /// Engine clock units between funding settlements.
+    pub interval_secs: u64,
+    /// Bound on the absolute funding rate of one period, in basis points.
+    pub max_rate_bps: u64,
+    /// Interest component added to the premium each period, in basis points.
+    #[serde(default)]
+    pub interest_rate_bps: i64,
+}
+
+/// What a designated maker must quote in a market: a two-sided market no wider than
+/// `max_spread_ticks`, at least `min_qty` on each side, for `min_uptime_bps` of every report period.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MakerObligation {
+    pub subaccounts: Vec<u64>,
+    pub max_spread_ticks: u64,
+    pub min_qty: u64,
+    pub min_uptime_bps: u64,
+    /// Length of each compliance report period, in engine clock units.
+    pub report_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MatchingMode {
     Batch,
@@ -58,13 +583,258 @@ pub enum MatchingMode {
 #[derive(Debug, Clone, Deserialize)]
 pub struct PersistenceConfig {
     pub wal_path: String,
+    /// Where outputs are journaled; unset keeps only the input WAL.
+    #[serde(default)]
+    pub journal_path: Option<String>,
+    /// Single snapshot file, for one-shard tools; see `snapshot_dir` for a running engine.
     pub snapshot_path: String,
+    /// Directory each shard writes a snapshot to every `snapshot_interval_secs` of engine time; a
+    /// `{shard}` in it is replaced by the shard id. Unset writes none.
+    #[serde(default)]
+    pub snapshot_dir: Option<String>,
+    #[serde(default)]
+    pub retention: SnapshotRetention,
+    /// Per-subaccount trade history served to queries; unset keeps none.
+    #[serde(default)]
+    pub trade_history: Option<TradeHistoryConfig>,
+    /// Cold storage the `archiver` copies published outputs to; unset archives nothing.
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+}
+
+impl PersistenceConfig {
+    /// `snapshot_dir` for `shard_id`, if snapshots are written.
+    pub fn snapshot_dir(&self, shard_id: usize) -> Option<std::path::PathBuf> {
+        let template = self.snapshot_dir.as_ref()?;
+        Some(template.replace("{shard}", &shard_id.to_string()).into())
+    }
+}
+
+/// Where each shard logs its trade history and how much of it is kept; see
+/// [`crate::persistence::trades`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct TradeHistoryConfig {
+    /// Directory holding one log per shard.
+    pub dir: String,
+    #[serde(default = "default_max_trades_per_subaccount")]
+    pub max_trades_per_subaccount: usize,
+    /// Engine clock units a trade is kept for; 0 keeps trades until the count limit drops them.
+    #[serde(default = "default_trade_retention_secs")]
+    pub retention_secs: u64,
+}
+
+/// Where the archiver writes output segments and when it cuts one; see `archive`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveConfig {
+    pub store: ArchiveStoreConfig,
+    /// Key prefix of segments and their index entries.
+    #[serde(default = "default_archive_prefix")]
+    pub prefix: String,
+    /// Outputs per segment before it is written.
+    #[serde(default = "default_archive_max_events")]
+    pub max_batch_events: usize,
+    /// Uncompressed bytes per segment before it is written.
+    #[serde(default = "default_archive_max_bytes")]
+    pub max_batch_bytes: usize,
+    /// Wall-clock seconds a partial segment may stay open.
+    #[serde(default = "default_archive_flush_secs")]
+    pub flush_interval_secs: u64,
+    /// zstd level segments are compressed with.
+    #[serde(default = "default_archive_compression_level")]
+    pub compression_level: i32,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveStoreConfig {
+    /// A directory, e.g. a mounted bucket; keys become relative paths.
+    Dir { path: String },
+    /// A JetStream object store bucket on `bus.nats_url`.
+    Nats { bucket: String },
 }
 
+/// Which snapshots garbage collection keeps, per shard. The newest snapshot is always kept.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct SnapshotRetention {
+    /// Most recent snapshots to keep.
+    #[serde(default)]
+    pub keep_last: usize,
+    /// Keep the newest snapshot of each of this many most recent hours.
+    #[serde(default)]
+    pub keep_hourly: usize,
+    /// Keep the newest snapshot of each of this many most recent days.
+    #[serde(default)]
+    pub keep_daily: usize,
+}
+
+/// Prefix of environment variables that override the config file; see [`Settings::load`].
+pub const ENV_PREFIX: &str = "CLOB";
+
 impl Settings {
+    /// Reads the config file at `path`, then layers environment variables over it. A variable
+    /// named `CLOB__` followed by the setting's path, with `__` between levels, replaces that
+    /// setting, e.g. `CLOB__BUS__NATS_URL` or `CLOB__SHARD_COUNT`. Names are case-insensitive and
+    /// values that parse as numbers or booleans are read as such.
     pub fn load(path: &str) -> anyhow::Result<Self> {
+        Self::load_with_env(path, None)
+    }
+
+    /// Like [`load`](Self::load), with `env` standing in for the process environment if given.
+    pub fn load_with_env(path: &str, env: Option<config::Map<String, String>>) -> anyhow::Result<Self> {
         let builder = config::Config::builder()
-            .add_source(config::File::with_name(path));
-        Ok(builder.build()?.try_deserialize()?)
+            .add_source(config::File::with_name(path))
+            .add_source(
+                config::Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("__")
+                    .separator("__")
+                    .try_parsing(true)
+                    .source(env),
+            );
+        let settings: Self = builder.build()?.try_deserialize()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Rejects settings the engine cannot run with, naming the offending setting.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.shard_count > 0, "shard_count must be at least 1");
+        let bus = &self.bus;
+        for (name, value) in [
+            ("nats_url", &bus.nats_url),
+            ("input_subject", &bus.input_subject),
+            ("output_subject", &bus.output_subject),
+            ("stream_name", &bus.stream_name),
+            ("durable_name", &bus.durable_name),
+            ("markets_bucket", &bus.markets_bucket),
+            ("accounts_bucket", &bus.accounts_bucket),
+            ("permissions_bucket", &bus.permissions_bucket),
+            ("keys_bucket", &bus.keys_bucket),
+            ("risk_params_bucket", &bus.risk_params_bucket),
+            ("subaccounts_bucket", &bus.subaccounts_bucket),
+            ("compliance_subject", &bus.compliance_subject),
+            ("dead_letter_subject", &bus.dead_letter_subject),
+            ("resend_subject", &bus.resend_subject),
+            ("adl_subject", &bus.adl_subject),
+            ("trades_subject", &bus.trades_subject),
+            ("equity_subject", &bus.equity_subject),
+            ("book_snapshot_subject", &bus.book_snapshot_subject),
+            ("account_subject", &bus.account_subject),
+        ] {
+            anyhow::ensure!(!value.trim().is_empty(), "bus.{name} must not be empty");
+        }
+        let mut market_ids = std::collections::BTreeSet::new();
+        for market in &self.markets {
+            anyhow::ensure!(market_ids.insert(market.market_id), "market {} is listed twice", market.market_id);
+            market.validate().map_err(|err| anyhow::anyhow!("market {}: {err}", market.market_id))?;
+        }
+        let mut subaccount_ids = std::collections::BTreeSet::new();
+        for subaccount in &self.subaccounts {
+            anyhow::ensure!(
+                subaccount_ids.insert(subaccount.subaccount_id),
+                "subaccount {} is listed twice",
+                subaccount.subaccount_id
+            );
+        }
+        if let Some(budget) = &self.latency_budget {
+            anyhow::ensure!(budget.max_queue_delay_ms > 0, "latency_budget.max_queue_delay_ms must be at least 1");
+        }
+        if let Some(archive) = &self.persistence.archive {
+            anyhow::ensure!(archive.max_batch_events > 0, "persistence.archive.max_batch_events must be at least 1");
+            anyhow::ensure!(archive.max_batch_bytes > 0, "persistence.archive.max_batch_bytes must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+impl MarketConfig {
+    /// Sets the parameters `update` carries and returns each one that changed with its previous
+    /// and new value, in declaration order. A market without its own leverage cap is taken to be
+    /// at `default_max_leverage`. Does not validate the result.
+    pub fn apply_risk_params(
+        &mut self,
+        update: &RiskParamsUpdate,
+        default_max_leverage: u64,
+    ) -> Vec<(RiskParameter, i64, i64)> {
+        let mut changes = Vec::new();
+        if let Some(value) = update.initial_margin_bps.filter(|value| *value != self.initial_margin_bps) {
+            changes.push((RiskParameter::InitialMarginBps, self.initial_margin_bps as i64, value as i64));
+            self.initial_margin_bps = value;
+        }
+        if let Some(value) = update.maintenance_margin_bps.filter(|value| *value != self.maintenance_margin_bps) {
+            changes.push((RiskParameter::MaintenanceMarginBps, self.maintenance_margin_bps as i64, value as i64));
+            self.maintenance_margin_bps = value;
+        }
+        if let Some(value) = update.price_band_bps.filter(|value| *value != self.price_band_bps) {
+            changes.push((RiskParameter::PriceBandBps, self.price_band_bps as i64, value as i64));
+            self.price_band_bps = value;
+        }
+        if let Some(value) = update.maker_fee_bps.filter(|value| *value != self.maker_fee_bps) {
+            changes.push((RiskParameter::MakerFeeBps, self.maker_fee_bps, value));
+            self.maker_fee_bps = value;
+        }
+        if let Some(value) = update.taker_fee_bps.filter(|value| *value != self.taker_fee_bps) {
+            changes.push((RiskParameter::TakerFeeBps, self.taker_fee_bps, value));
+            self.taker_fee_bps = value;
+        }
+        if let Some(value) = update.max_position.filter(|value| *value != self.max_position) {
+            changes.push((RiskParameter::MaxPosition, self.max_position, value));
+            self.max_position = value;
+        }
+        if let Some(value) = update.max_leverage {
+            let previous = self.max_leverage.unwrap_or(default_max_leverage);
+            if value != previous {
+                changes.push((RiskParameter::MaxLeverage, previous as i64, value as i64));
+            }
+            self.max_leverage = Some(value);
+        }
+        changes
+    }
+
+    /// Rejects a market whose sizes or margins make no sense.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.tick_size > 0, "tick_size must be at least 1");
+        anyhow::ensure!(self.lot_size > 0, "lot_size must be at least 1");
+        // A market may require no margin at all, but maintenance must stay below initial margin.
+        if self.initial_margin_bps > 0 || self.maintenance_margin_bps > 0 {
+            anyhow::ensure!(
+                self.maintenance_margin_bps < self.initial_margin_bps,
+                "maintenance_margin_bps ({}) must be below initial_margin_bps ({})",
+                self.maintenance_margin_bps,
+                self.initial_margin_bps
+            );
+        }
+        if let Some(dynamic) = &self.dynamic_margin {
+            anyhow::ensure!(
+                dynamic.min_margin_bps <= dynamic.max_margin_bps,
+                "dynamic_margin.min_margin_bps ({}) must not exceed max_margin_bps ({})",
+                dynamic.min_margin_bps,
+                dynamic.max_margin_bps
+            );
+            anyhow::ensure!(
+                self.maintenance_margin_bps < dynamic.min_margin_bps,
+                "maintenance_margin_bps ({}) must be below dynamic_margin.min_margin_bps ({})",
+                self.maintenance_margin_bps,
+                dynamic.min_margin_bps
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Serde adapter writing a value as a JSON string inside a bincode record, for configs bincode
+/// cannot decode directly (a market's internally tagged `book_layout`).
+pub(crate) mod json_string {
+    use serde::de::{DeserializeOwned, Error as _};
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T: Serialize, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        let json = serde_json::to_string(value).map_err(S::Error::custom)?;
+        serializer.serialize_str(&json)
+    }
+
+    pub fn deserialize<'de, T: DeserializeOwned, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        let json = String::deserialize(deserializer)?;
+        serde_json::from_str(&json).map_err(D::Error::custom)
     }
 }
@@ -0,0 +1,62 @@
+//! Parent accounts and the subaccounts they own.
+//!
+//! A shard only sees the markets it owns, so aggregate limits are enforced per market: the sum
+//! over a parent's children in one market, which lives on exactly one shard.
+
+use std::collections::HashMap;
+
+use crate::config::AccountConfig;
+use crate::models::SubaccountId;
+
+#[derive(Debug, Default)]
+pub struct AccountHierarchy {
+    parents: HashMap<u64, AccountConfig>,
+    parent_of: HashMap<SubaccountId, u64>,
+}
+
+impl AccountHierarchy {
+    pub fn new(accounts: Vec<AccountConfig>) -> Self {
+        let mut hierarchy = Self::default();
+        for account in accounts {
+            hierarchy.upsert(account);
+        }
+        hierarchy
+    }
+
+    /// Replaces a parent's configuration, re-pointing children that moved in or out. A child
+    /// claimed by another parent is moved to this one.
+    pub fn upsert(&mut self, account: AccountConfig) {
+        if let Some(previous) = self.parents.remove(&account.parent_id) {
+            for child in previous.subaccounts {
+                if self.parent_of.get(&child) == Some(&account.parent_id) {
+                    self.parent_of.remove(&child);
+                }
+            }
+        }
+        for child in &account.subaccounts {
+            if let Some(old_parent) = self.parent_of.insert(*child, account.parent_id)
+                && old_parent != account.parent_id
+                && let Some(old) = self.parents.get_mut(&old_parent)
+            {
+                old.subaccounts.retain(|id| id != child);
+            }
+        }
+        self.parents.insert(account.parent_id, account);
+    }
+
+    /// Every parent's configuration, by parent id; [`new`](Self::new) rebuilds the hierarchy from it.
+    pub fn configs(&self) -> Vec<AccountConfig> {
+        let mut configs: Vec<AccountConfig> = self.parents.values().cloned().collect();
+        configs.sort_unstable_by_key(|account| account.parent_id);
+        configs
+    }
+
+    pub fn parent(&self, parent_id: u64) -> Option<&AccountConfig> {
+        self.parents.get(&parent_id)
+    }
+
+    /// The parent owning `subaccount_id`, if it belongs to one.
+    pub fn parent_of(&self, subaccount_id: SubaccountId) -> Option<&AccountConfig> {
+        self.parent_of.get(&subaccount_id).and_then(|parent_id| self.parents.get(parent_id))
+    }
+}
@@ -0,0 +1,154 @@
+//! Auto-deleveraging (ADL) candidate ranking.
+//!
+//! When a liquidation leaves a shortfall the insurance fund cannot cover, ADL closes positions of
+//! profitable, highly leveraged accounts on the opposite side. Each market keeps its holders ranked
+//! by the usual profit-and-leverage score, longs and shorts separately, and the shard refreshes a
+//! holder's score whenever it trades and every holder's when the mark moves. Ties rank the lower
+//! subaccount id first, so the order is deterministic and replays identically.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+
+#[cfg(feature = "proto")]
+use bytes::Bytes;
+#[cfg(feature = "proto")]
+use prost::Message;
+
+#[cfg(feature = "proto")]
+use crate::config::WireCodec;
+#[cfg(feature = "proto")]
+use crate::models::pb;
+use crate::models::{AdlRank, PriceTicks, Side, SubaccountId};
+
+/// ADL score of a position: its unrealized PnL as a share of entry notional, multiplied by the
+/// account's effective leverage when in profit and divided by it when not, both in basis points.
+/// `None` for flat positions and accounts without positive equity, which are liquidated rather
+/// than deleveraged.
+pub fn adl_score(size: i64, entry_price: PriceTicks, mark: PriceTicks, equity: i64) -> Option<i64> {
+    if size == 0 || equity <= 0 || entry_price == 0 {
+        return None;
+    }
+    let size = i128::from(size);
+    let pnl = size * (i128::from(mark) - i128::from(entry_price));
+    let pnl_bps = pnl * 10_000 / (size.abs() * i128::from(entry_price));
+    let leverage_bps = (size.abs() * i128::from(mark) * 10_000 / i128::from(equity)).max(1);
+    let score = if pnl >= 0 {
+        pnl_bps * leverage_bps / 10_000
+    } else {
+        pnl_bps * 10_000 / leverage_bps
+    };
+    Some(score.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64)
+}
+
+/// A market's holders ranked per side, highest score first.
+#[derive(Debug, Default)]
+pub struct AdlQueue {
+    scores: HashMap<SubaccountId, (Side, i64)>,
+    longs: BTreeSet<(Reverse<i64>, SubaccountId)>,
+    shorts: BTreeSet<(Reverse<i64>, SubaccountId)>,
+}
+
+impl AdlQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the subaccount's side and score, or drops it from the ranking with `None`.
+    pub fn update(&mut self, subaccount_id: SubaccountId, entry: Option<(Side, i64)>) {
+        if self.scores.get(&subaccount_id) == entry.as_ref() {
+            return;
+        }
+        if let Some((side, score)) = self.scores.remove(&subaccount_id) {
+            self.side_mut(side).remove(&(Reverse(score), subaccount_id));
+        }
+        if let Some((side, score)) = entry {
+            self.side_mut(side).insert((Reverse(score), subaccount_id));
+            self.scores.insert(subaccount_id, (side, score));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.scores.clear();
+        self.longs.clear();
+        self.shorts.clear();
+    }
+
+    /// Holders with positions on `side` (`Buy` for longs), best candidate first.
+    pub fn ranked(&self, side: Side) -> impl Iterator<Item = AdlRank> + '_ {
+        let ranked = match side {
+            Side::Buy => &self.longs,
+            Side::Sell => &self.shorts,
+        };
+        ranked.iter().zip(1..).map(|(&(Reverse(score), subaccount_id), rank)| AdlRank {
+            subaccount_id,
+            rank,
+            score,
+        })
+    }
+
+    /// The subaccount's side and entry in the ranking, if it holds a ranked position.
+    pub fn rank_of(&self, subaccount_id: SubaccountId) -> Option<(Side, AdlRank)> {
+        let (side, _) = self.scores.get(&subaccount_id)?;
+        self.ranked(*side)
+            .find(|entry| entry.subaccount_id == subaccount_id)
+            .map(|entry| (*side, entry))
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeSet<(Reverse<i64>, SubaccountId)> {
+        match side {
+            Side::Buy => &mut self.longs,
+            Side::Sell => &mut self.shorts,
+        }
+    }
+}
+
+#[cfg(feature = "proto")]
+pub fn encode_request(codec: WireCodec, request: &pb::AdlRankingRequest) -> anyhow::Result<Bytes> {
+    Ok(match codec {
+        WireCodec::Protobuf => Bytes::from(request.encode_to_vec()),
+        WireCodec::Json => Bytes::from(serde_json::to_vec(request)?),
+    })
+}
+
+#[cfg(feature = "proto")]
+pub fn decode_request(codec: WireCodec, payload: Bytes) -> anyhow::Result<pb::AdlRankingRequest> {
+    Ok(match codec {
+        WireCodec::Protobuf => pb::AdlRankingRequest::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profit_scales_with_leverage_and_losses_rank_last() {
+        // +10% at 2x leverage outranks +10% at 1x, and any loss ranks below both.
+        let levered = adl_score(10, 100, 110, 550).unwrap();
+        let unlevered = adl_score(10, 100, 110, 1_100).unwrap();
+        let losing = adl_score(10, 100, 90, 900).unwrap();
+        assert_eq!((levered, unlevered), (2_000, 1_000));
+        assert!(losing < 0);
+        assert_eq!(adl_score(-10, 100, 90, 900), Some(1_000));
+        assert_eq!(adl_score(10, 100, 110, 0), None);
+    }
+
+    #[test]
+    fn queue_ranks_each_side_and_breaks_ties_by_subaccount() {
+        let mut queue = AdlQueue::new();
+        queue.update(3, Some((Side::Buy, 500)));
+        queue.update(1, Some((Side::Buy, 500)));
+        queue.update(2, Some((Side::Buy, 900)));
+        queue.update(4, Some((Side::Sell, 100)));
+        let longs: Vec<_> = queue.ranked(Side::Buy).map(|entry| (entry.subaccount_id, entry.rank)).collect();
+        assert_eq!(longs, vec![(2, 1), (1, 2), (3, 3)]);
+
+        queue.update(2, Some((Side::Sell, 50)));
+        queue.update(1, None);
+        let longs: Vec<_> = queue.ranked(Side::Buy).map(|entry| entry.subaccount_id).collect();
+        assert_eq!(longs, vec![3]);
+        assert_eq!(queue.rank_of(2).map(|(side, entry)| (side, entry.rank)), Some((Side::Sell, 2)));
+        assert_eq!(queue.rank_of(1), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{HashMap, VecDeque};
+
+/// Idempotency window over fixed-size keys: request ids, or `(subaccount_id, nonce)` pairs.
+///
+/// Entries are kept for `window` timestamp units after they are first seen, which should cover
+/// the bus's full redelivery horizon (ack wait × max deliveries). `max_entries` is only a memory
+/// safety cap: evicting because of it means a redelivery could slip through, so those evictions
+/// are counted separately from ordinary expiry.
+///
+/// Hits, misses and both kinds of eviction are exported as `dedupe_*` metrics labelled with the
+/// owning shard and the kind of key, and kept in [`DedupeStats`] for inspection.
+#[derive(Debug)]
+pub struct DedupeWindow {
+    window: u64,
+    max_entries: usize,
+    seen: HashMap<u128, u64>,
+    order: VecDeque<(u64, u128)>,
+    shard: String,
+    key: &'static str,
+    stats: DedupeStats,
+}
+
+/// Running totals of a [`DedupeWindow`]'s lookups and evictions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupeStats {
+    /// Keys already inside the window, i.e. duplicates.
+    pub hits: u64,
+    /// Keys seen for the first time.
+    pub misses: u64,
+    /// Entries dropped because they aged out of the window.
+    pub expired: u64,
+    /// Entries dropped early to stay within `max_entries`; non-zero means the cap is too small.
+    pub evicted: u64,
+}
+
+impl DedupeWindow {
+    pub fn new(window: u64, max_entries: usize) -> Self {
+        Self {
+            window,
+            max_entries: max_entries.max(1),
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            shard: String::new(),
+            key: "request_id",
+            stats: DedupeStats::default(),
+        }
+    }
+
+    /// Labels the exported metrics with `shard_id`.
+    pub fn for_shard(mut self, shard_id: usize) -> Self {
+        self.shard = shard_id.to_string();
+        metrics::gauge!("dedupe_window_secs", "shard" => self.shard.clone(), "key" => self.key).set(self.window as f64);
+        metrics::gauge!("dedupe_max_entries", "shard" => self.shard.clone(), "key" => self.key).set(self.max_entries as f64);
+        self
+    }
+
+    /// Labels the exported metrics with the kind of key held, `request_id` unless set.
+    pub fn keyed_by(mut self, key: &'static str) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    pub fn stats(&self) -> DedupeStats {
+        self.stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Records `key` at `ts` and reports whether it was already inside the window.
+    pub fn check_and_insert(&mut self, key: u128, ts: u64) -> bool {
+        self.expire(ts);
+        if self.seen.contains_key(&key) {
+            self.stats.hits += 1;
+            metrics::counter!("dedupe_hits_total", "shard" => self.shard.clone(), "key" => self.key).increment(1);
+            return true;
+        }
+        self.stats.misses += 1;
+        metrics::counter!("dedupe_misses_total", "shard" => self.shard.clone(), "key" => self.key).increment(1);
+        while self.seen.len() >= self.max_entries {
+            let Some((_, oldest)) = self.order.pop_front() else { break };
+            self.seen.remove(&oldest);
+            self.stats.evicted += 1;
+            metrics::counter!("dedupe_evictions_total", "shard" => self.shard.clone(), "key" => self.key, "cause" => "capacity").increment(1);
+        }
+        self.seen.insert(key, ts);
+        self.order.push_back((ts, key));
+        metrics::gauge!("dedupe_entries", "shard" => self.shard.clone(), "key" => self.key).set(self.seen.len() as f64);
+        false
+    }
+
+    fn expire(&mut self, now: u64) {
+        while let Some(&(seen_at, key)) = self.order.front() {
+            if seen_at.saturating_add(self.window) >= now {
+                break;
+            }
+            self.order.pop_front();
+            self.seen.remove(&key);
+            self.stats.expired += 1;
+            metrics::counter!("dedupe_evictions_total", "shard" => self.shard.clone(), "key" => self.key, "cause" => "expired").increment(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_entries_for_the_whole_window() {
+        let mut dedupe = DedupeWindow::new(60, 2);
+        assert!(!dedupe.check_and_insert(1, 0));
+        assert!(!dedupe.check_and_insert(2, 10));
+        assert!(dedupe.check_and_insert(1, 60));
+        assert!(!dedupe.check_and_insert(1, 61));
+        assert_eq!(dedupe.len(), 2);
+    }
+
+    #[test]
+    fn counts_hits_misses_and_both_kinds_of_eviction() {
+        let mut dedupe = DedupeWindow::new(60, 2);
+        dedupe.check_and_insert(1, 0);
+        dedupe.check_and_insert(1, 5);
+        dedupe.check_and_insert(2, 10);
+        dedupe.check_and_insert(3, 20);
+        dedupe.check_and_insert(4, 100);
+        assert_eq!(
+            dedupe.stats(),
+            DedupeStats {
+                hits: 1,
+                misses: 4,
+                expired: 2,
+                evicted: 1,
+            }
+        );
+    }
+}
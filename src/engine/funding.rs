@@ -0,0 +1,67 @@
+//! Internally scheduled funding for markets with a [`FundingConfig`].
+//!
+//! Funding settles at multiples of `interval_secs` of engine time, checked before each input like
+//! expiries and auction clears, so replays settle at the same inputs. The rate for a period is the
+//! premium of mark over index plus the configured interest component, clamped to `max_rate_bps`.
+//! Markets without a config keep relying on external `FundingUpdate` inputs.
+
+use crate::config::FundingConfig;
+use crate::models::PriceTicks;
+
+/// Funding indices are cumulative payments per lot in 1/10_000ths of a tick.
+pub const FUNDING_INDEX_SCALE: i64 = 10_000;
+
+/// Funding rate of one period, in basis points of mark; positive rates make longs pay shorts.
+pub fn funding_rate_bps(config: &FundingConfig, mark: PriceTicks, index: PriceTicks) -> i64 {
+    let premium_bps = if index == 0 {
+        0
+    } else {
+        ((i128::from(mark) - i128::from(index)) * 10_000 / i128::from(index)) as i64
+    };
+    let cap = config.max_rate_bps.min(i64::MAX as u64) as i64;
+    premium_bps.saturating_add(config.interest_rate_bps).clamp(-cap, cap)
+}
+
+/// How far the funding index moves over `periods` periods at `rate_bps` of `mark`.
+pub fn index_delta(rate_bps: i64, mark: PriceTicks, periods: u64) -> i64 {
+    // rate_bps / 10_000 of mark, in 1/FUNDING_INDEX_SCALE ticks.
+    let per_period = i128::from(rate_bps) * i128::from(mark) * i128::from(FUNDING_INDEX_SCALE) / 10_000;
+    let delta = per_period * i128::from(periods);
+    delta.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+}
+
+/// First funding time strictly after `ts`.
+pub fn next_funding_time(interval_secs: u64, ts: u64) -> u64 {
+    let interval = interval_secs.max(1);
+    (ts / interval + 1).saturating_mul(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_rate_bps: u64, interest_rate_bps: i64) -> FundingConfig {
+        FundingConfig {
+            interval_secs: 3_600,
+            max_rate_bps,
+            interest_rate_bps,
+        }
+    }
+
+    #[test]
+    fn rate_adds_interest_to_the_premium_and_clamps() {
+        assert_eq!(funding_rate_bps(&config(200, 1), 101, 100), 101);
+        assert_eq!(funding_rate_bps(&config(50, 1), 101, 100), 50);
+        assert_eq!(funding_rate_bps(&config(50, 1), 90, 100), -50);
+        assert_eq!(funding_rate_bps(&config(50, 1), 100, 100), 1);
+    }
+
+    #[test]
+    fn schedule_and_index_follow_the_interval() {
+        assert_eq!(next_funding_time(3_600, 0), 3_600);
+        assert_eq!(next_funding_time(3_600, 3_600), 7_200);
+        // 1bp of 100 ticks is 0.01 tick, or 100 index units, per period.
+        assert_eq!(index_delta(1, 100, 3), 300);
+        assert_eq!(index_delta(-1, 100, 1), -100);
+    }
+}
@@ -0,0 +1,52 @@
+//! Per-shard margin utilization and account health, exported as Prometheus gauges.
+//!
+//! Computing the figures walks every subaccount and resting order of the shard, so the router
+//! refreshes them at most once per `risk_metrics.interval_secs` of engine time, after a batch of
+//! inputs, rather than on every input.
+//!
+//! Users query a single subaccount's margin picture on `bus.equity_subject`; the shard owning the
+//! request's market answers between inputs, so the reply reflects a single `engine_seq`.
+
+#[cfg(feature = "proto")]
+use bytes::Bytes;
+#[cfg(feature = "proto")]
+use prost::Message;
+
+#[cfg(feature = "proto")]
+use crate::config::WireCodec;
+#[cfg(feature = "proto")]
+use crate::models::pb;
+
+/// Snapshot of a shard's aggregate risk; see [`crate::engine::EngineShard::risk_metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RiskMetrics {
+    /// Sum of every subaccount's collateral.
+    pub total_collateral: i64,
+    /// Initial margin the resting orders would need if they filled at their limit prices.
+    pub open_order_margin: i64,
+    /// Subaccounts with positions whose equity is below their maintenance margin plus the
+    /// configured warning buffer, including those already below maintenance.
+    pub accounts_near_maintenance: u64,
+    /// Largest share of a market's open interest held by one subaccount, in basis points.
+    pub max_position_concentration_bps: u64,
+}
+
+impl RiskMetrics {
+    pub fn publish(&self, shard_id: usize) {
+        let shard = shard_id.to_string();
+        metrics::gauge!("risk_total_collateral", "shard" => shard.clone()).set(self.total_collateral as f64);
+        metrics::gauge!("risk_open_order_margin", "shard" => shard.clone()).set(self.open_order_margin as f64);
+        metrics::gauge!("risk_accounts_near_maintenance", "shard" => shard.clone())
+            .set(self.accounts_near_maintenance as f64);
+        metrics::gauge!("risk_max_position_concentration_bps", "shard" => shard)
+            .set(self.max_position_concentration_bps as f64);
+    }
+}
+
+#[cfg(feature = "proto")]
+pub fn decode_request(codec: WireCodec, payload: Bytes) -> anyhow::Result<pb::AccountEquityRequest> {
+    Ok(match codec {
+        WireCodec::Protobuf => pb::AccountEquityRequest::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    })
+}
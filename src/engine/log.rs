@@ -0,0 +1,38 @@
+//! What a shard needs from its input log and output journal: an append-only sink of envelopes.
+//!
+//! The file-backed [`Wal`](crate::persistence::wal::Wal) implements it with the `persistence`
+//! feature; [`MemoryLog`] keeps the records in memory for embedders without a filesystem.
+
+use std::sync::{Arc, Mutex};
+
+use crate::models::EventEnvelope;
+
+pub trait EventLog: Send {
+    /// Records `event` after every earlier one. An error means it may not have been recorded.
+    fn append(&mut self, event: &EventEnvelope) -> anyhow::Result<()>;
+}
+
+/// In-memory log. Clones share the records, so a caller can keep a handle to a log it gave a
+/// shard and read back what the shard appended.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLog {
+    events: Arc<Mutex<Vec<EventEnvelope>>>,
+}
+
+impl MemoryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every record appended so far, oldest first.
+    pub fn events(&self) -> Vec<EventEnvelope> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+impl EventLog for MemoryLog {
+    fn append(&mut self, event: &EventEnvelope) -> anyhow::Result<()> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(event.clone());
+        Ok(())
+    }
+}
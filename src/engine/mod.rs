@@ -1,4 +1,27 @@
+pub mod accounts;
+pub mod adl;
+pub mod clock;
+pub mod dedupe;
+pub mod funding;
+pub mod health;
+pub mod log;
+pub mod obligations;
+pub mod oracle;
+#[cfg(feature = "bus")]
+pub mod outbox;
+#[cfg(all(feature = "bus", feature = "persistence"))]
+pub mod recovery;
+pub mod reshard;
+pub mod resend;
+#[cfg(feature = "bus")]
+pub mod ring;
+pub mod sequencer;
+// The client SDK decodes outputs with the router's codecs, so it needs no `service` feature.
+#[cfg(all(feature = "bus", feature = "persistence"))]
 pub mod router;
 pub mod shard;
+pub mod signatures;
+pub mod volatility;
 
-pub use shard::{EngineShard, EngineState};
+pub use log::{EventLog, MemoryLog};
+pub use shard::{EngineShard, EngineState, MarketExport};
@@ -0,0 +1,149 @@
+//! Quoting-obligation tracking for designated market makers.
+//!
+//! A maker's quote only changes when the book does, so the tracker records each maker's quote
+//! after every book change and, when the clock next advances, credits the elapsed time to the
+//! quote that was standing through it.
+
+use std::collections::BTreeMap;
+
+use crate::config::MakerObligation;
+use crate::matching::orderbook::OrderBook;
+use crate::models::{PriceTicks, Side, SubaccountId};
+
+/// Time credited to one maker in the current report period.
+#[derive(Debug, Default, Clone, Copy)]
+struct MakerStats {
+    /// Spread of the maker's standing two-sided quote of at least `min_qty`, if any.
+    spread: Option<u64>,
+    quoted_secs: u64,
+    compliant_secs: u64,
+    spread_secs: u128,
+}
+
+/// Period totals for one maker, produced by [`ObligationTracker::take_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MakerReport {
+    pub subaccount_id: SubaccountId,
+    pub period_start: u64,
+    pub period_end: u64,
+    pub quoted_secs: u64,
+    pub compliant_secs: u64,
+    pub uptime_bps: u64,
+    pub avg_spread_ticks: u64,
+    pub compliant: bool,
+}
+
+#[derive(Debug)]
+pub struct ObligationTracker {
+    obligation: MakerObligation,
+    makers: BTreeMap<SubaccountId, MakerStats>,
+    /// Start of the current report period; set by the first `advance`.
+    period_start: Option<u64>,
+    last_ts: u64,
+    observed: bool,
+}
+
+impl ObligationTracker {
+    pub fn new(obligation: MakerObligation) -> Self {
+        let makers = obligation.subaccounts.iter().map(|id| (*id, MakerStats::default())).collect();
+        Self {
+            obligation,
+            makers,
+            period_start: None,
+            last_ts: 0,
+            observed: false,
+        }
+    }
+
+    pub fn obligation(&self) -> &MakerObligation {
+        &self.obligation
+    }
+
+    /// Whether the book has not been read since the tracker was created.
+    pub fn needs_observe(&self) -> bool {
+        !self.observed
+    }
+
+    /// Credits the time since the last call to every maker's standing quote.
+    pub fn advance(&mut self, ts: u64) {
+        if self.period_start.is_none() {
+            self.period_start = Some(ts);
+            self.last_ts = ts;
+            return;
+        }
+        let elapsed = ts.saturating_sub(self.last_ts);
+        self.last_ts = self.last_ts.max(ts);
+        if elapsed == 0 {
+            return;
+        }
+        for stats in self.makers.values_mut() {
+            if let Some(spread) = stats.spread {
+                stats.quoted_secs += elapsed;
+                stats.spread_secs += spread as u128 * elapsed as u128;
+                if spread <= self.obligation.max_spread_ticks {
+                    stats.compliant_secs += elapsed;
+                }
+            }
+        }
+    }
+
+    /// Re-reads every maker's best qualifying bid and ask from `book`.
+    pub fn observe(&mut self, book: &OrderBook) {
+        self.observed = true;
+        let mut quotes: BTreeMap<SubaccountId, (Option<PriceTicks>, Option<PriceTicks>)> = BTreeMap::new();
+        for order in book.order_views() {
+            if order.remaining < self.obligation.min_qty || !self.makers.contains_key(&order.subaccount_id) {
+                continue;
+            }
+            let (bid, ask) = quotes.entry(order.subaccount_id).or_default();
+            match order.side {
+                Side::Buy => *bid = Some(bid.map_or(order.price_ticks, |best| best.max(order.price_ticks))),
+                Side::Sell => *ask = Some(ask.map_or(order.price_ticks, |best| best.min(order.price_ticks))),
+            }
+        }
+        for (subaccount_id, stats) in &mut self.makers {
+            stats.spread = match quotes.get(subaccount_id) {
+                Some((Some(bid), Some(ask))) => Some(ask.saturating_sub(*bid)),
+                _ => None,
+            };
+        }
+    }
+
+    /// Closes the report period if `ts` has reached its end, returning one report per maker in
+    /// subaccount order and starting the next period at `ts`.
+    pub fn take_report(&mut self, ts: u64) -> Option<Vec<MakerReport>> {
+        let period_start = self.period_start?;
+        if ts < period_start.saturating_add(self.obligation.report_interval_secs.max(1)) {
+            return None;
+        }
+        let elapsed = ts - period_start;
+        let reports = self
+            .makers
+            .iter_mut()
+            .map(|(subaccount_id, stats)| {
+                let uptime_bps = stats.compliant_secs * 10_000 / elapsed;
+                let avg_spread_ticks = match stats.quoted_secs {
+                    0 => 0,
+                    quoted => (stats.spread_secs / quoted as u128) as u64,
+                };
+                let report = MakerReport {
+                    subaccount_id: *subaccount_id,
+                    period_start,
+                    period_end: ts,
+                    quoted_secs: stats.quoted_secs,
+                    compliant_secs: stats.compliant_secs,
+                    uptime_bps,
+                    avg_spread_ticks,
+                    compliant: uptime_bps >= self.obligation.min_uptime_bps,
+                };
+                *stats = MakerStats {
+                    spread: stats.spread,
+                    ..MakerStats::default()
+                };
+                report
+            })
+            .collect();
+        self.period_start = Some(ts);
+        Some(reports)
+    }
+}
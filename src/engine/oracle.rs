@@ -0,0 +1,145 @@
+//! Composite index and mark prices from several named oracle sources.
+//!
+//! A market with an [`OracleConfig`] no longer applies each `PriceUpdate` as is. Each update only
+//! refreshes the quote of the source it names, and the prices the engine uses for risk,
+//! triggers, liquidations and funding are recomputed from the latest quotes. Quotes older than
+//! `max_staleness_secs` of engine time are ignored. So are quotes whose index price is more than
+//! `max_deviation_bps` away from the median of the fresh ones. The survivors' weighted medians
+//! become the market's index and mark prices, but only while at least `min_sources` survive.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::OracleConfig;
+use crate::models::PriceTicks;
+
+/// Latest prices reported by one source, stamped with the engine time they arrived at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OracleQuote {
+    pub mark_price: PriceTicks,
+    pub index_price: PriceTicks,
+    pub ts: u64,
+}
+
+/// Outcome of combining a market's quotes at some engine time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Composite {
+    /// Composite prices, or `None` when too few sources survived the filters.
+    pub prices: Option<(PriceTicks, PriceTicks)>,
+    pub used: Vec<String>,
+    pub stale: Vec<String>,
+    pub outliers: Vec<String>,
+}
+
+/// Combines `quotes` (by source name) into composite mark and index prices at engine time `ts`.
+pub fn composite(config: &OracleConfig, quotes: &BTreeMap<String, OracleQuote>, ts: u64) -> Composite {
+    let mut result = Composite {
+        prices: None,
+        used: Vec::new(),
+        stale: Vec::new(),
+        outliers: Vec::new(),
+    };
+    let mut fresh = Vec::new();
+    for source in &config.sources {
+        let Some(quote) = quotes.get(&source.name) else {
+            continue;
+        };
+        if ts.saturating_sub(quote.ts) > config.max_staleness_secs {
+            result.stale.push(source.name.clone());
+        } else {
+            fresh.push((source, *quote));
+        }
+    }
+    if fresh.is_empty() {
+        return result;
+    }
+
+    let mut index_prices: Vec<PriceTicks> = fresh.iter().map(|(_, quote)| quote.index_price).collect();
+    index_prices.sort_unstable();
+    let median = index_prices[(index_prices.len() - 1) / 2];
+    let mut marks = Vec::new();
+    let mut indices = Vec::new();
+    for (source, quote) in fresh {
+        let deviation = u128::from(quote.index_price.abs_diff(median)) * 10_000;
+        if config.max_deviation_bps > 0 && deviation > u128::from(median) * u128::from(config.max_deviation_bps) {
+            result.outliers.push(source.name.clone());
+            continue;
+        }
+        result.used.push(source.name.clone());
+        marks.push((quote.mark_price, source.weight));
+        indices.push((quote.index_price, source.weight));
+    }
+    if result.used.len() >= config.min_sources.max(1) {
+        result.prices = Some((weighted_median(marks), weighted_median(indices)));
+    }
+    result
+}
+
+/// Lowest price at which the cumulative weight reaches half the total; sources with zero weight
+/// count as weight 1.
+fn weighted_median(mut prices: Vec<(PriceTicks, u64)>) -> PriceTicks {
+    prices.sort_unstable();
+    let total: u128 = prices.iter().map(|(_, weight)| u128::from((*weight).max(1))).sum();
+    let mut cumulative = 0u128;
+    for (price, weight) in &prices {
+        cumulative += u128::from((*weight).max(1));
+        if cumulative * 2 >= total {
+            return *price;
+        }
+    }
+    prices.last().map_or(0, |(price, _)| *price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OracleSource;
+
+    fn config() -> OracleConfig {
+        OracleConfig {
+            sources: ["a", "b", "c", "d"]
+                .into_iter()
+                .map(|name| OracleSource {
+                    name: name.to_string(),
+                    weight: if name == "c" { 3 } else { 1 },
+                })
+                .collect(),
+            max_staleness_secs: 10,
+            max_deviation_bps: 500,
+            min_sources: 2,
+        }
+    }
+
+    fn quote(index_price: PriceTicks, ts: u64) -> OracleQuote {
+        OracleQuote {
+            mark_price: index_price + 1,
+            index_price,
+            ts,
+        }
+    }
+
+    #[test]
+    fn drops_stale_and_deviating_sources_before_the_weighted_median() {
+        let quotes = BTreeMap::from([
+            ("a".to_string(), quote(100, 95)),
+            ("b".to_string(), quote(120, 95)),
+            ("c".to_string(), quote(102, 95)),
+            ("d".to_string(), quote(101, 80)),
+        ]);
+        let result = composite(&config(), &quotes, 100);
+        assert_eq!(result.stale, vec!["d"]);
+        assert_eq!(result.outliers, vec!["b"]);
+        assert_eq!(result.used, vec!["a", "c"]);
+        // c carries three times a's weight.
+        assert_eq!(result.prices, Some((103, 102)));
+    }
+
+    #[test]
+    fn too_few_sources_leave_the_price_unset() {
+        let quotes = BTreeMap::from([("a".to_string(), quote(100, 95)), ("b".to_string(), quote(100, 50))]);
+        let result = composite(&config(), &quotes, 100);
+        assert_eq!(result.used, vec!["a"]);
+        assert_eq!(result.prices, None);
+    }
+}
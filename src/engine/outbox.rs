@@ -0,0 +1,169 @@
+//! Per-shard queue of outputs and input acks waiting to go out on the bus.
+//!
+//! Items leave strictly in order, so an input is only acked once every output it produced has
+//! been published. A failed publish stays at the head of the queue and is retried with
+//! exponential backoff while the shard keeps working; once the queue reaches its capacity the
+//! shard stops taking inputs until the bus accepts outputs again, so a bus outage back-pressures
+//! the input consumer instead of dropping fills.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::bus::{Bus, BusMessage};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+enum Outgoing {
+    Publish { subject: String, payload: Bytes },
+    Ack(BusMessage),
+}
+
+pub struct Outbox {
+    queue: VecDeque<Outgoing>,
+    capacity: usize,
+    backoff: Duration,
+    /// Earliest time of the next publish attempt after a failure.
+    retry_at: Option<Instant>,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            capacity: capacity.max(1),
+            backoff: INITIAL_BACKOFF,
+            retry_at: None,
+        }
+    }
+
+    pub fn publish(&mut self, subject: &str, payload: Bytes) {
+        self.queue.push_back(Outgoing::Publish {
+            subject: subject.to_string(),
+            payload,
+        });
+    }
+
+    /// Acks `message` once everything queued before it has been published.
+    pub fn ack(&mut self, message: BusMessage) {
+        self.queue.push_back(Outgoing::Ack(message));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Whether the shard should stop taking inputs. A drained burst may overshoot the capacity.
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+
+    /// Time left before the next attempt is allowed; zero when not backing off.
+    pub fn retry_delay(&self) -> Duration {
+        self.retry_at
+            .map_or(Duration::ZERO, |retry_at| retry_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Sends queued items in order until the queue is empty or a publish fails. Does nothing while
+    /// backing off from an earlier failure. Returns whether the queue is empty.
+    pub async fn flush(&mut self, bus: &dyn Bus) -> bool {
+        if !self.retry_delay().is_zero() {
+            return self.queue.is_empty();
+        }
+        while let Some(item) = self.queue.pop_front() {
+            match item {
+                Outgoing::Publish { subject, payload } => {
+                    if let Err(err) = bus.publish(&subject, payload.clone()).await {
+                        metrics::counter!("output_publish_failures_total").increment(1);
+                        let retry_in_ms = self.backoff.as_millis() as u64;
+                        warn!(subject = %subject, error = %err, retry_in_ms, "output publish failed");
+                        self.queue.push_front(Outgoing::Publish { subject, payload });
+                        self.retry_at = Some(Instant::now() + self.backoff);
+                        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                        metrics::gauge!("output_buffer_len").set(self.queue.len() as f64);
+                        return false;
+                    }
+                }
+                Outgoing::Ack(message) => {
+                    let _ = bus.ack(message).await;
+                }
+            }
+        }
+        self.backoff = INITIAL_BACKOFF;
+        self.retry_at = None;
+        metrics::gauge!("output_buffer_len").set(0.0);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::bus::memory::InMemoryBus;
+    use crate::bus::{BusAck, BusSubscription};
+
+    /// Fails the first `failures` publishes, then forwards to an in-memory bus.
+    struct FlakyBus {
+        failures: AtomicUsize,
+        inner: InMemoryBus,
+    }
+
+    #[async_trait::async_trait]
+    impl Bus for FlakyBus {
+        async fn publish(&self, subject: &str, payload: Bytes) -> anyhow::Result<()> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                anyhow::bail!("bus unavailable");
+            }
+            self.inner.publish(subject, payload).await
+        }
+
+        async fn subscribe(&self, subject: &str) -> anyhow::Result<BusSubscription> {
+            self.inner.subscribe(subject).await
+        }
+
+        async fn ack(&self, message: BusMessage) -> anyhow::Result<()> {
+            self.inner.ack(message).await
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_publishes_are_retried_in_order_before_acking() {
+        let bus = FlakyBus {
+            failures: AtomicUsize::new(2),
+            inner: InMemoryBus::new(),
+        };
+        let mut outbox = Outbox::new(3);
+        outbox.publish("out", Bytes::from_static(b"fill-1"));
+        outbox.publish("out", Bytes::from_static(b"fill-2"));
+        outbox.ack(BusMessage {
+            payload: Bytes::new(),
+            ack: BusAck::None,
+        });
+        assert!(outbox.is_full());
+
+        assert!(!outbox.flush(&bus).await);
+        // Still backing off: no attempt is made.
+        assert!(!outbox.flush(&bus).await);
+        assert_eq!(bus.failures.load(Ordering::SeqCst), 1);
+        tokio::time::sleep(outbox.retry_delay()).await;
+        assert!(!outbox.flush(&bus).await);
+        assert_eq!(bus.inner.acked(), 0);
+        tokio::time::sleep(outbox.retry_delay()).await;
+        assert!(outbox.flush(&bus).await);
+
+        let published = bus.inner.published("out");
+        assert_eq!(published, vec![Bytes::from_static(b"fill-1"), Bytes::from_static(b"fill-2")]);
+        assert_eq!(bus.inner.acked(), 1);
+        assert!(outbox.is_empty() && outbox.retry_delay().is_zero());
+    }
+}
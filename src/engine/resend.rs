@@ -0,0 +1,242 @@
+//! Output sequencing and gap recovery.
+//!
+//! Every output a shard publishes on the output subject is stamped with the engine's `run_id` and a
+//! per-shard `output_seq` that counts up from 1 within the run, so consumers can tell when they
+//! missed one. The shard keeps its most recent encoded outputs in a [`ResendHistory`]; a consumer
+//! that sees a gap publishes a `ResendRequest` on the resend subject and the shard publishes the
+//! retained outputs of the range again, byte for byte, followed by a `ResendComplete`.
+//! [`GapDetector`] is the consumer-side bookkeeping.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use bytes::Bytes;
+#[cfg(feature = "proto")]
+use prost::Message;
+
+#[cfg(feature = "proto")]
+use crate::config::WireCodec;
+#[cfg(feature = "proto")]
+use crate::models::pb;
+
+pub const DEFAULT_RESEND_HISTORY: usize = 100_000;
+
+/// Where an output sits in its shard's output stream. `output_seq` 0 marks an output outside the
+/// stream, such as a reject published by the router itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct OutputSequence {
+    pub run_id: u64,
+    pub shard_id: usize,
+    pub output_seq: u64,
+    /// Position across all shards; 0 unless global sequencing is enabled. See
+    /// [`crate::engine::sequencer`].
+    pub global_seq: u64,
+    /// Position of the input that produced the output among all inputs the router took; 0 unless
+    /// global sequencing is enabled.
+    pub ingest_seq: u64,
+    /// Position among the fills and book deltas of the output's market; 0 for other outputs or
+    /// unless market sequencing is enabled. See [`crate::engine::sequencer::MarketSequencer`].
+    pub market_seq: u64,
+}
+
+/// The last `capacity` encoded outputs of one shard, by output sequence.
+pub struct ResendHistory {
+    outputs: VecDeque<(u64, Bytes)>,
+    capacity: usize,
+}
+
+impl ResendHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            outputs: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Retains `payload` as output `output_seq`, evicting the oldest output when full. Sequences
+    /// must be pushed in increasing order.
+    pub fn push(&mut self, output_seq: u64, payload: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.outputs.len() == self.capacity {
+            self.outputs.pop_front();
+        }
+        self.outputs.push_back((output_seq, payload));
+    }
+
+    /// Oldest retained sequence, if any.
+    pub fn first_seq(&self) -> Option<u64> {
+        self.outputs.front().map(|(seq, _)| *seq)
+    }
+
+    /// Newest retained sequence, if any.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.outputs.back().map(|(seq, _)| *seq)
+    }
+
+    /// Outputs `from..=to`, or why the range cannot be served in full.
+    pub fn range(&self, from: u64, to: u64) -> Result<Vec<Bytes>, String> {
+        if from == 0 || from > to {
+            return Err(format!("invalid range {from}..={to}"));
+        }
+        let (Some(first), Some(last)) = (self.first_seq(), self.last_seq()) else {
+            return Err("no outputs retained".to_string());
+        };
+        if to > last {
+            return Err(format!("sequence {to} not yet published (last is {last})"));
+        }
+        if from < first {
+            return Err(format!("sequence {from} no longer retained (oldest is {first})"));
+        }
+        let start = (from - first) as usize;
+        let end = (to - first) as usize;
+        Ok(self.outputs.range(start..=end).map(|(_, payload)| payload.clone()).collect())
+    }
+}
+
+/// What a consumer should do with an output, per [`GapDetector::observe`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCheck {
+    /// Process it. Also returned for unsequenced outputs.
+    New,
+    /// Already seen; skip it.
+    Duplicate,
+    /// Process it, then request the skipped outputs `from..=to`.
+    Gap { from: u64, to: u64 },
+}
+
+#[derive(Debug)]
+struct StreamState {
+    next_seq: u64,
+    /// Skipped ranges not yet filled, by first sequence.
+    missing: BTreeMap<u64, u64>,
+}
+
+impl StreamState {
+    fn new() -> Self {
+        Self {
+            next_seq: 1,
+            missing: BTreeMap::new(),
+        }
+    }
+}
+
+/// Tracks the output streams a consumer has seen, keyed by engine run and shard. A new `run_id`
+/// for a shard starts a new stream at sequence 1.
+#[derive(Debug, Default)]
+pub struct GapDetector {
+    streams: BTreeMap<usize, (u64, StreamState)>,
+}
+
+impl GapDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, sequence: OutputSequence) -> SequenceCheck {
+        if sequence.output_seq == 0 {
+            return SequenceCheck::New;
+        }
+        let (run_id, state) = self
+            .streams
+            .entry(sequence.shard_id)
+            .or_insert_with(|| (sequence.run_id, StreamState::new()));
+        if *run_id != sequence.run_id {
+            *run_id = sequence.run_id;
+            *state = StreamState::new();
+        }
+        let seq = sequence.output_seq;
+        if seq >= state.next_seq {
+            let gap = (seq > state.next_seq).then(|| (state.next_seq, seq - 1));
+            state.next_seq = seq + 1;
+            return match gap {
+                Some((from, to)) => {
+                    state.missing.insert(from, to);
+                    SequenceCheck::Gap { from, to }
+                }
+                None => SequenceCheck::New,
+            };
+        }
+        // Behind the stream head: new only if it fills part of a known gap.
+        let Some((&from, &to)) = state.missing.range(..=seq).next_back() else {
+            return SequenceCheck::Duplicate;
+        };
+        if seq > to {
+            return SequenceCheck::Duplicate;
+        }
+        state.missing.remove(&from);
+        if from < seq {
+            state.missing.insert(from, seq - 1);
+        }
+        if seq < to {
+            state.missing.insert(seq + 1, to);
+        }
+        SequenceCheck::New
+    }
+
+    /// Ranges of `shard_id`'s current stream that have been skipped and not yet filled.
+    pub fn missing(&self, shard_id: usize) -> Vec<(u64, u64)> {
+        self.streams
+            .get(&shard_id)
+            .map(|(_, state)| state.missing.iter().map(|(from, to)| (*from, *to)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "proto")]
+pub fn encode_request(codec: WireCodec, request: &pb::ResendRequest) -> anyhow::Result<Bytes> {
+    Ok(match codec {
+        WireCodec::Protobuf => Bytes::from(request.encode_to_vec()),
+        WireCodec::Json => Bytes::from(serde_json::to_vec(request)?),
+    })
+}
+
+#[cfg(feature = "proto")]
+pub fn decode_request(codec: WireCodec, payload: Bytes) -> anyhow::Result<pb::ResendRequest> {
+    Ok(match codec {
+        WireCodec::Protobuf => pb::ResendRequest::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seq(run_id: u64, output_seq: u64) -> OutputSequence {
+        OutputSequence {
+            run_id,
+            shard_id: 0,
+            output_seq,
+            ..OutputSequence::default()
+        }
+    }
+
+    #[test]
+    fn history_serves_only_retained_ranges() {
+        let mut history = ResendHistory::new(3);
+        for output_seq in 1..=5 {
+            history.push(output_seq, Bytes::from(vec![output_seq as u8]));
+        }
+        assert_eq!(history.range(3, 4).unwrap(), vec![Bytes::from(vec![3]), Bytes::from(vec![4])]);
+        assert!(history.range(2, 4).unwrap_err().contains("no longer retained"));
+        assert!(history.range(5, 6).unwrap_err().contains("not yet published"));
+        assert!(history.range(4, 3).is_err());
+    }
+
+    #[test]
+    fn gaps_are_reported_once_and_filled_by_resends() {
+        let mut detector = GapDetector::new();
+        assert_eq!(detector.observe(seq(7, 1)), SequenceCheck::New);
+        assert_eq!(detector.observe(seq(7, 4)), SequenceCheck::Gap { from: 2, to: 3 });
+        assert_eq!(detector.observe(seq(7, 4)), SequenceCheck::Duplicate);
+        assert_eq!(detector.observe(seq(7, 3)), SequenceCheck::New);
+        assert_eq!(detector.missing(0), vec![(2, 2)]);
+        assert_eq!(detector.observe(seq(7, 3)), SequenceCheck::Duplicate);
+        assert_eq!(detector.observe(seq(7, 2)), SequenceCheck::New);
+        assert!(detector.missing(0).is_empty());
+        assert_eq!(detector.observe(seq(0, 0)), SequenceCheck::New);
+        // A restarted engine starts a new stream.
+        assert_eq!(detector.observe(seq(8, 1)), SequenceCheck::New);
+    }
+}
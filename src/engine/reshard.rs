@@ -0,0 +1,134 @@
+//! Offline redistribution of shard state across a new shard layout.
+//!
+//! Markets move whole: each market's export, with its orders, every subaccount's position in it,
+//! its prices and its nonce watermarks, goes to the market's new shard. Collateral is per
+//! shard in the risk engine and cannot be split by market, so each subaccount's collateral is
+//! summed and placed on the lowest new shard that holds one of its positions (shard 0 if it has
+//! none).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::config::AccountConfig;
+use crate::engine::shard::{first_order_id, first_trade_id};
+use crate::engine::EngineState;
+use crate::models::{MarketId, OrderId, SubaccountId};
+use crate::risk::{RiskState, Subaccount};
+
+/// Target shard per market: `assignment` if listed, otherwise `market_id % shard_count`.
+pub fn target_shard(market_id: MarketId, shard_count: usize, assignment: &HashMap<MarketId, usize>) -> usize {
+    assignment
+        .get(&market_id)
+        .copied()
+        .unwrap_or((market_id as usize) % shard_count)
+}
+
+pub fn reshard(
+    states: Vec<EngineState>,
+    shard_count: usize,
+    assignment: &HashMap<MarketId, usize>,
+) -> anyhow::Result<Vec<EngineState>> {
+    anyhow::ensure!(shard_count > 0, "shard_count must be > 0");
+    if let Some((market_id, shard_id)) = assignment.iter().find(|(_, shard_id)| **shard_id >= shard_count) {
+        anyhow::bail!("market {market_id} assigned to shard {shard_id}, but shard_count is {shard_count}");
+    }
+
+    let engine_seq = states.iter().map(|state| state.engine_seq).max().unwrap_or(0);
+    let mut out: Vec<EngineState> = (0..shard_count)
+        .map(|shard_id| EngineState {
+            shard_id,
+            engine_seq,
+            next_order_id: first_order_id(shard_id),
+            markets: BTreeMap::new(),
+            risk_state: RiskState {
+                subaccounts: BTreeMap::new(),
+                mark_prices: BTreeMap::new(),
+                funding_indices: BTreeMap::new(),
+            },
+            next_trade_id: first_trade_id(shard_id),
+            clearing_seed: 0,
+            accounts: Vec::new(),
+        })
+        .collect();
+    let shard_of = |market_id: MarketId| target_shard(market_id, shard_count, assignment);
+
+    let mut collateral: BTreeMap<SubaccountId, (i64, bool)> = BTreeMap::new();
+    let mut accounts: BTreeMap<u64, AccountConfig> = BTreeMap::new();
+    let mut seen_orders: BTreeSet<OrderId> = BTreeSet::new();
+    for state in states {
+        // A shard keeps numbering, and its clearing seed, from where its old counterpart stopped.
+        if let Some(target) = out.get_mut(state.shard_id) {
+            target.next_order_id = target.next_order_id.max(state.next_order_id);
+            target.next_trade_id = target.next_trade_id.max(state.next_trade_id);
+            target.clearing_seed = state.clearing_seed;
+        }
+        for (market_id, market) in state.markets {
+            let order_ids = market
+                .orders
+                .iter()
+                .map(|order| order.order_id)
+                .chain(market.batch_pending.iter().map(|order| order.order_id))
+                .chain(market.conditional.iter().map(|(order_id, _)| *order_id));
+            for order_id in order_ids {
+                anyhow::ensure!(
+                    seen_orders.insert(order_id),
+                    "order id {order_id} appears on more than one shard; ids must be unique to reshard"
+                );
+            }
+            anyhow::ensure!(
+                out[shard_of(market_id)].markets.insert(market_id, market).is_none(),
+                "market {market_id} appears on more than one shard"
+            );
+        }
+        // Every shard holds the whole account hierarchy.
+        accounts.extend(state.accounts.into_iter().map(|account| (account.parent_id, account)));
+        for (subaccount_id, account) in state.risk_state.subaccounts {
+            let entry = collateral.entry(subaccount_id).or_insert((0, false));
+            entry.0 += account.collateral;
+            entry.1 |= account.cross_margin;
+            for (market_id, position) in account.positions {
+                ensure_subaccount(&mut out[shard_of(market_id)].risk_state, subaccount_id)
+                    .positions
+                    .insert(market_id, position);
+            }
+        }
+        for (market_id, mark) in state.risk_state.mark_prices {
+            out[shard_of(market_id)].risk_state.mark_prices.insert(market_id, mark);
+        }
+        for (market_id, index) in state.risk_state.funding_indices {
+            out[shard_of(market_id)].risk_state.funding_indices.insert(market_id, index);
+        }
+    }
+
+    for (subaccount_id, (amount, cross_margin)) in collateral {
+        let home = out
+            .iter()
+            .position(|state| {
+                state.risk_state.subaccounts.contains_key(&subaccount_id)
+                    || state.markets.values().any(|market| market.positions.contains_key(&subaccount_id))
+            })
+            .unwrap_or(0);
+        let account = ensure_subaccount(&mut out[home].risk_state, subaccount_id);
+        account.collateral = amount;
+        account.cross_margin = cross_margin;
+    }
+
+    // Ids inside a shard's own range may have been issued by another old shard that owned the
+    // same index; keep the counter above all of them.
+    for state in &mut out {
+        let range_start = first_order_id(state.shard_id);
+        let range_end = first_order_id(state.shard_id + 1);
+        if let Some(max_id) = seen_orders.range(range_start..range_end).next_back() {
+            state.next_order_id = state.next_order_id.max(max_id + 1);
+        }
+        state.accounts = accounts.values().cloned().collect();
+    }
+    Ok(out)
+}
+
+fn ensure_subaccount(state: &mut RiskState, subaccount_id: SubaccountId) -> &mut Subaccount {
+    state.subaccounts.entry(subaccount_id).or_insert(Subaccount {
+        collateral: 0,
+        positions: BTreeMap::new(),
+        cross_margin: false,
+    })
+}
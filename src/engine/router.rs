@@ -1,20 +1,56 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use bytes::Bytes;
 use prost::Message;
 use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
-use crate::bus::Bus;
-use crate::config::Settings;
-use crate::engine::shard::EngineShard;
-use crate::market_registry;
-use crate::models::{pb, Event};
+use crate::bus::dead_letter::{self, DeadLetterStage};
+use crate::bus::{Bus, BusMessage};
+use crate::config::{Settings, WireCodec};
+use crate::engine::adl;
+use crate::engine::clock::{Clock, SystemClock, NANOS_PER_SEC};
+use crate::engine::dedupe::DedupeWindow;
+use crate::engine::health;
+use crate::engine::outbox::Outbox;
+use crate::engine::resend::{self, OutputSequence, ResendHistory};
+use crate::engine::ring;
+use crate::engine::sequencer::{MarketSequencer, Sequencer};
+use crate::engine::shard::{coalesce_book_deltas_by, EngineShard, DEFAULT_DEDUPE_WINDOW_SECS};
+use crate::{account_registry, key_registry, market_registry, permission_registry, subaccount_registry};
+use crate::models::{
+    pb, AccountEquity, AdlRanking, ClearingSeed, Event, EventEnvelope, InvalidNewOrder, MarketId, MarketTransfer, OrderAck,
+    OrderStatus, RejectReason, ResendComplete, RiskParamsUpdate, Side, SubaccountId, TradeHistory, SCHEMA_VERSION,
+};
+use crate::persistence::snapshot::SnapshotStore;
+use crate::persistence::trades::{self, TradeStore};
 use crate::persistence::wal::Wal;
-use crate::risk::{RiskConfig, RiskEngine};
+use crate::risk::RiskEngine;
+
+pub use crate::models::DecodeError;
+
+/// Depth of each router → shard ring. When a ring is full the router stops pulling from the bus.
+const SHARD_RING_CAPACITY: usize = 1024;
+
+/// Upper bound on inputs a shard applies before publishing, bounding output latency in bursts.
+const MAX_DRAIN_BATCH: usize = 256;
+
+/// An input held while its market migrates: the event, its timestamp in nanoseconds, ingest order,
+/// and the bus message to ack once the target shard has it.
+type HeldInput = (Event, u64, u64, crate::bus::BusMessage);
 
 pub async fn run_router(settings: Settings, bus: Arc<dyn Bus>) -> anyhow::Result<()> {
+    run_router_with_clock(settings, bus, Arc::new(SystemClock)).await
+}
+
+/// [`run_router`] with inputs stamped from `clock` instead of the wall clock.
+pub async fn run_router_with_clock(
+    settings: Settings,
+    bus: Arc<dyn Bus>,
+    clock: Arc<dyn Clock>,
+) -> anyhow::Result<()> {
     let mut shard_senders = Vec::new();
     let mut shard_tasks = Vec::new();
 
@@ -29,14 +65,88 @@ pub async fn run_router(settings: Settings, bus: Arc<dyn Bus>) -> anyhow::Result
         }
         markets = by_id.into_values().collect();
     }
+    // Risk tuning made at runtime outlives restarts: the bucket keeps the latest update per market.
+    if let Ok(updates) = market_registry::load_risk_params(&settings.bus.nats_url, &settings.bus.risk_params_bucket).await {
+        for update in updates {
+            let Some(market) = markets.iter_mut().find(|m| m.market_id == update.market_id) else {
+                continue;
+            };
+            let mut tuned = market.clone();
+            tuned.apply_risk_params(&update, settings.risk.max_leverage);
+            match tuned.validate() {
+                Ok(()) => *market = tuned,
+                Err(err) => warn!(market_id = update.market_id, error = %err, "ignoring invalid risk parameters"),
+            }
+        }
+    }
+
+    let mut accounts = settings.accounts.clone();
+    if let Ok(dynamic) = account_registry::load_all(&settings.bus.nats_url, &settings.bus.accounts_bucket).await {
+        accounts.extend(dynamic);
+    }
+
+    let mut permissions = settings.permissions.clone();
+    if let Ok(dynamic) = permission_registry::load_all(&settings.bus.nats_url, &settings.bus.permissions_bucket).await {
+        permissions.extend(dynamic);
+    }
+
+    let mut signing_keys = settings.signing_keys.clone();
+    if let Ok(dynamic) = key_registry::load_all(&settings.bus.nats_url, &settings.bus.keys_bucket).await {
+        signing_keys.extend(dynamic);
+    }
+
+    let mut subaccounts = settings.subaccounts.clone();
+    if let Ok(dynamic) = subaccount_registry::load_all(&settings.bus.nats_url, &settings.bus.subaccounts_bucket).await {
+        let mut by_id: std::collections::BTreeMap<u64, crate::config::SubaccountConfig> =
+            subaccounts.drain(..).map(|subaccount| (subaccount.subaccount_id, subaccount)).collect();
+        for subaccount in dynamic {
+            by_id.insert(subaccount.subaccount_id, subaccount);
+        }
+        subaccounts = by_id.into_values().collect();
+    }
 
     enum ShardMsg {
-        Event { event: Event, ts: u64, message: crate::bus::BusMessage },
-        MarketUpdate(crate::config::MarketConfig),
+        /// `ts_ns` is when the router took the input off the bus: the stamp it is logged with, and
+        /// where its latency budget starts.
+        Event { event: Event, ts_ns: u64, ingest_seq: u64, message: crate::bus::BusMessage },
+        AccountUpdate(crate::config::AccountConfig),
+        PermissionsUpdate(crate::config::MarketPermissions),
+        SigningKeyUpdate(crate::config::SigningKeyConfig),
+        Resend(pb::ResendRequest),
+        AdlQuery(pb::AdlRankingRequest),
+        TradeQuery(pb::TradeHistoryRequest),
+        EquityQuery(pb::AccountEquityRequest),
     }
 
+    // Output sequences restart at 1 every run; consumers tell runs apart by this id.
+    let run_id: u64 = rand::random();
+    let sequencer = settings.global_sequencing.then(|| Arc::new(Sequencer::new()));
+    let market_sequencer = settings.market_sequencing.then(|| Arc::new(MarketSequencer::new()));
+
+    // Source shards report each market export (or its failure) back so the router can finish the
+    // migration.
+    let (migration_tx, mut migration_rx) = mpsc::channel::<(MarketId, Option<MarketTransfer>)>(64);
+    // Shards with a snapshot in `persistence.snapshot_dir` restore it and replay the inputs logged
+    // after it. Orders logged by earlier runs keep their nonces spent, so signed orders cannot be
+    // replayed after a restart, and shards pick up their engine_seq where the last run stopped.
+    // Trade ids continue after the last fill in the output journal.
+    let logged = Wal::load(std::path::Path::new(&settings.persistence.wal_path))?;
+    let journaled = match &settings.persistence.journal_path {
+        Some(journal_path) => Wal::load(std::path::Path::new(journal_path))?,
+        None => Vec::new(),
+    };
+
+    // Keep request ids for twice the redelivery horizon so late redeliveries are still caught.
+    let redelivery_horizon = settings.bus.redelivery_horizon_secs();
+    let dedupe_window = settings
+        .dedupe
+        .window_secs
+        .unwrap_or_else(|| redelivery_horizon.saturating_mul(2).max(DEFAULT_DEDUPE_WINDOW_SECS));
+    if dedupe_window < redelivery_horizon {
+        warn!(dedupe_window, redelivery_horizon, "dedupe window is shorter than the redelivery horizon");
+    }
     for shard_id in 0..settings.shard_count {
-        let (tx, mut rx) = mpsc::channel::<ShardMsg>(1024);
+        let (tx, mut rx) = ring::channel::<ShardMsg>(SHARD_RING_CAPACITY);
         shard_senders.push(tx);
 
         let shard_markets: Vec<_> = markets
@@ -45,86 +155,628 @@ pub async fn run_router(settings: Settings, bus: Arc<dyn Bus>) -> anyhow::Result
             .cloned()
             .collect();
         let wal = Wal::open(std::path::Path::new(&settings.persistence.wal_path))?;
-        let risk = RiskEngine::new(RiskConfig {
-            max_slippage_bps: 50,
-            max_leverage: 10,
-        });
-        let mut shard = EngineShard::new(shard_id, shard_markets, wal, risk);
+        let risk = RiskEngine::new(settings.risk.clone());
+        let snapshot_dir = settings.persistence.snapshot_dir(shard_id);
+        let snapshot = match &snapshot_dir {
+            Some(dir) => SnapshotStore::load_latest(dir, shard_id)?,
+            None => None,
+        };
+        let restored = snapshot.is_some();
+        let shard = match snapshot {
+            Some(snapshot) => {
+                info!(shard_id, last_seq = snapshot.meta.last_seq, "restoring shard from its latest snapshot");
+                EngineShard::restore(snapshot.state, shard_markets.clone(), wal, risk)?
+            }
+            None => EngineShard::new(shard_id, shard_markets.clone(), wal, risk),
+        };
+        let mut shard = shard
+            .with_dedupe(DedupeWindow::new(dedupe_window, settings.dedupe.max_entries).for_shard(shard_id))
+            .with_required_signatures(settings.require_signatures)
+            .with_latency_budget(settings.latency_budget)
+            .with_insurance_fund(settings.insurance_fund_subaccount)
+            .with_book_snapshots(settings.book_snapshots)
+            .with_margin_warnings(settings.risk_metrics.margin_warnings.then_some(settings.risk_metrics.margin_warning_bps));
+        if let Some(journal_path) = &settings.persistence.journal_path {
+            shard = shard.with_journal(Wal::open(std::path::Path::new(journal_path))?);
+        }
+        if let Some(trade_history) = &settings.persistence.trade_history {
+            shard = shard.with_trade_history(TradeStore::open(trade_history.clone(), shard_id)?);
+        }
+        for account in &accounts {
+            shard.upsert_account(account.clone());
+        }
+        for key in &signing_keys {
+            if let Err(err) = shard.upsert_signing_key(key) {
+                warn!(subaccount_id = key.subaccount_id, error = %err, "ignoring invalid signing key");
+            }
+        }
+        for entry in permissions.iter().filter(|p| (p.market_id as usize) % settings.shard_count == shard_id) {
+            shard.upsert_permissions(entry.clone());
+        }
+        // A restored shard catches up on the inputs logged after its snapshot.
+        if restored {
+            shard.replay_log(&logged);
+        }
+        shard.recover_nonces(&logged);
+        shard.recover_engine_seq(&logged);
+        shard.recover_trade_id(&journaled);
+        // Fresh clearing-jitter seed per run; it goes through the WAL so replay reproduces it.
+        shard.handle_event_at(Event::ClearingSeed(ClearingSeed { seed: rand::random() }), clock.now_nanos())?;
+        // Provisioning goes through the WAL too, so replay starts from the same balances and fee tiers.
+        for subaccount in &subaccounts {
+            shard.handle_event_at(Event::ProvisionSubaccount(subaccount.clone()), clock.now_nanos())?;
+        }
+        // And so do market configs, so replay applies the ticks and limits this run started with.
+        for market in shard_markets {
+            shard.handle_event_at(Event::MarketUpdate(Box::new(market)), clock.now_nanos())?;
+        }
         let output_subject = settings.bus.output_subject.clone();
+        let compliance_subject = settings.bus.compliance_subject.clone();
+        let account_subject = settings.bus.account_subject.clone();
+        let book_snapshot_subject = settings.bus.book_snapshot_subject.clone();
+        let private_acks = settings.bus.private_acks;
+        let dead_letter_subject = settings.bus.dead_letter_subject.clone();
+        let max_deliver = u64::from(settings.bus.max_deliver);
+        let output_buffer = settings.bus.output_buffer;
+        let resend_history = settings.bus.resend_history;
+        let codec = settings.bus.codec;
         let bus_clone = Arc::clone(&bus);
+        let coalesce = settings.coalesce_book_deltas;
+        let migration_tx = migration_tx.clone();
+        let sequencer = sequencer.clone();
+        let market_sequencer = market_sequencer.clone();
+        let clock = Arc::clone(&clock);
+        let risk_metrics = settings.risk_metrics;
+        let snapshot_interval_secs = settings.snapshot_interval_secs;
         let handle = tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                match msg {
-                    ShardMsg::Event { event, ts, message } => match shard.handle_event(event, ts) {
-                        Ok(outputs) => {
-                            for output in outputs {
-                                let bytes = encode_output(output);
-                                let _ = bus_clone.publish(&output_subject, bytes).await;
+            let mut outputs = Vec::new();
+            let mut to_ack = Vec::new();
+            let mut outbox = Outbox::new(output_buffer);
+            let mut history = ResendHistory::new(resend_history);
+            let mut next_output_seq = 1u64;
+            let mut next_risk_metrics_at = 0u64;
+            let mut next_snapshot_at = clock.now().saturating_add(snapshot_interval_secs);
+            loop {
+                let first = if outbox.is_full() {
+                    // Leave inputs on the ring, back-pressuring the router, until publishing recovers.
+                    tokio::time::sleep(outbox.retry_delay()).await;
+                    outbox.flush(bus_clone.as_ref()).await;
+                    continue;
+                } else if outbox.is_empty() {
+                    rx.recv().await
+                } else {
+                    tokio::select! {
+                        message = rx.recv() => message,
+                        _ = tokio::time::sleep(outbox.retry_delay()) => {
+                            outbox.flush(bus_clone.as_ref()).await;
+                            continue;
+                        }
+                    }
+                };
+                let Some(first) = first else { break };
+                // Drain whatever is already queued so deltas can be coalesced across the burst.
+                let mut next = Some(first);
+                let mut drained = 0usize;
+                while let Some(msg) = next.take() {
+                    drained += 1;
+                    match msg {
+                        ShardMsg::Event { mut event, ts_ns, ingest_seq, message } => {
+                            let ts = ts_ns / NANOS_PER_SEC;
+                            let migrating = match &event {
+                                Event::MigrateMarket(migrate) => Some(migrate.market_id),
+                                _ => None,
+                            };
+                            let applied_ns = clock.now_nanos();
+                            let handled = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                                let reject = shard
+                                    .authenticate(&event, ts)
+                                    .or_else(|| shard.admit(&mut event, applied_ns.saturating_sub(ts_ns), ts));
+                                match reject {
+                                    Some(reject) => Ok(vec![EventEnvelope { ts_ns, ..reject }]),
+                                    None => shard.handle_event_at(event, ts_ns),
+                                }
+                            }));
+                            let result = match handled {
+                                Ok(result) => result,
+                                Err(panic) => {
+                                    let reason = panic_message(panic.as_ref());
+                                    error!(shard_id, error = %reason, "input handler panicked; dead-lettering input");
+                                    publish_dead_letter(
+                                        bus_clone.as_ref(),
+                                        &dead_letter_subject,
+                                        codec,
+                                        DeadLetterStage::Handler,
+                                        &message,
+                                        &format!("handler panicked: {reason}"),
+                                        ts,
+                                    )
+                                    .await;
+                                    // Redelivery would panic again; ack it with no outputs.
+                                    Ok(Vec::new())
+                                }
+                            };
+                            if let Some(market_id) = migrating {
+                                let transfer = result.as_ref().ok().and_then(|events| {
+                                    events.iter().find_map(|env| match &env.event {
+                                        Event::MarketExported(transfer) => Some(transfer.clone()),
+                                        _ => None,
+                                    })
+                                });
+                                if migration_tx.send((market_id, transfer)).await.is_err() {
+                                    warn!("failed to report market export to router");
+                                }
+                            }
+                            match result {
+                                Ok(events) => {
+                                    outputs.extend(events.into_iter().map(|env| (ingest_seq, env)));
+                                    to_ack.push(message);
+                                }
+                                Err(err) if message.deliveries() >= max_deliver => {
+                                    // Last attempt: park it on the dead-letter subject instead of dropping it.
+                                    publish_dead_letter(
+                                        bus_clone.as_ref(),
+                                        &dead_letter_subject,
+                                        codec,
+                                        DeadLetterStage::Handler,
+                                        &message,
+                                        &format!("{err:#}"),
+                                        ts,
+                                    )
+                                    .await;
+                                    to_ack.push(message);
+                                }
+                                Err(err) => {
+                                    // Do not ack; allow redelivery.
+                                    let deliveries = message.deliveries();
+                                    warn!(shard_id, error = %err, deliveries, "failed to apply input");
+                                }
+                            }
+                        }
+                        ShardMsg::AccountUpdate(account) => {
+                            shard.upsert_account(account);
+                        }
+                        ShardMsg::PermissionsUpdate(permissions) => {
+                            shard.upsert_permissions(permissions);
+                        }
+                        ShardMsg::SigningKeyUpdate(key) => {
+                            if let Err(err) = shard.upsert_signing_key(&key) {
+                                warn!(subaccount_id = key.subaccount_id, error = %err, "ignoring invalid signing key");
                             }
-                            let _ = bus_clone.ack(message).await;
                         }
-                        Err(_) => {
-                            // Do not ack; allow redelivery.
+                        ShardMsg::Resend(request) => {
+                            // Only outputs already queued are retained, so a resend never overtakes
+                            // the original.
+                            let reply_subject = if request.reply_subject.is_empty() {
+                                output_subject.clone()
+                            } else {
+                                request.reply_subject.clone()
+                            };
+                            let (resent, error) = match history.range(request.from_seq, request.to_seq) {
+                                Ok(payloads) => {
+                                    let resent = payloads.len() as u64;
+                                    for payload in payloads {
+                                        outbox.publish(&reply_subject, payload);
+                                    }
+                                    (resent, None)
+                                }
+                                Err(err) => (0, Some(err)),
+                            };
+                            metrics::counter!("output_resends_total").increment(resent);
+                            let complete = resend_complete(shard_id, request, resent, error, clock.now_nanos());
+                            outbox.publish(&reply_subject, encode_output_with(codec, complete));
+                        }
+                        ShardMsg::AdlQuery(request) => {
+                            let reply_subject = if request.reply_subject.is_empty() {
+                                output_subject.clone()
+                            } else {
+                                request.reply_subject.clone()
+                            };
+                            let reply = adl_ranking(&shard, request, clock.now_nanos());
+                            outbox.publish(&reply_subject, encode_output_with(codec, reply));
+                        }
+                        ShardMsg::TradeQuery(request) => {
+                            let reply_subject = if request.reply_subject.is_empty() {
+                                output_subject.clone()
+                            } else {
+                                request.reply_subject.clone()
+                            };
+                            let reply = trade_history(&shard, request, clock.now_nanos());
+                            outbox.publish(&reply_subject, encode_output_with(codec, reply));
+                        }
+                        ShardMsg::EquityQuery(request) => {
+                            let reply_subject = if request.reply_subject.is_empty() {
+                                output_subject.clone()
+                            } else {
+                                request.reply_subject.clone()
+                            };
+                            let reply = account_equity(&shard, request, clock.now_nanos());
+                            outbox.publish(&reply_subject, encode_output_with(codec, reply));
                         }
-                    },
-                    ShardMsg::MarketUpdate(market) => {
-                        shard.upsert_market(market);
                     }
+                    if drained < MAX_DRAIN_BATCH {
+                        next = rx.try_recv();
+                    }
+                }
+                let batch = std::mem::take(&mut outputs);
+                let batch = if coalesce { coalesce_book_deltas_by(batch, |(_, env)| env) } else { batch };
+                for (ingest_seq, output) in batch {
+                    if matches!(output.event, Event::MarketExported(_)) {
+                        continue;
+                    }
+                    if matches!(output.event, Event::MakerCompliance(_)) {
+                        outbox.publish(&compliance_subject, encode_output_with(codec, output));
+                        continue;
+                    }
+                    if matches!(output.event, Event::DepthSnapshot(_)) {
+                        outbox.publish(&book_snapshot_subject, encode_output_with(codec, output));
+                        continue;
+                    }
+                    if let Some(subject) = private_output_subject(&output.event, &account_subject, private_acks) {
+                        outbox.publish(&subject, encode_output_with(codec, output));
+                        continue;
+                    }
+                    if !has_output_payload(&output.event) {
+                        // Nothing a consumer can decode, so it takes no place in the output stream.
+                        outbox.publish(&output_subject, encode_output_with(codec, output));
+                        continue;
+                    }
+                    let sequence = OutputSequence {
+                        run_id,
+                        shard_id,
+                        output_seq: next_output_seq,
+                        global_seq: sequencer.as_ref().map_or(0, |sequencer| sequencer.next()),
+                        ingest_seq: if sequencer.is_some() { ingest_seq } else { 0 },
+                        market_seq: market_sequencer.as_ref().map_or(0, |sequencer| sequencer.next(&output.event)),
+                    };
+                    next_output_seq += 1;
+                    let payload = encode_sequenced_output(codec, output, sequence);
+                    history.push(sequence.output_seq, payload.clone());
+                    outbox.publish(&output_subject, payload);
+                }
+                // Inputs are acked only after the outputs they produced have been published.
+                for message in to_ack.drain(..) {
+                    outbox.ack(message);
+                }
+                outbox.flush(bus_clone.as_ref()).await;
+                let now = clock.now();
+                if now >= next_risk_metrics_at {
+                    shard.risk_metrics(risk_metrics.margin_warning_bps).publish(shard_id);
+                    next_risk_metrics_at = now.saturating_add(risk_metrics.interval_secs);
+                }
+                if let Some(dir) = &snapshot_dir
+                    && now >= next_snapshot_at
+                {
+                    let snapshot = SnapshotStore::build(shard_id, shard.engine_seq, shard.snapshot());
+                    if let Err(err) = SnapshotStore::save_to_dir(dir, &snapshot) {
+                        warn!(shard_id, error = %err, "failed to write snapshot");
+                    }
+                    next_snapshot_at = now.saturating_add(snapshot_interval_secs);
                 }
             }
+            // Shutting down: keep retrying rather than drop buffered outputs.
+            while !outbox.flush(bus_clone.as_ref()).await {
+                tokio::time::sleep(outbox.retry_delay()).await;
+            }
         });
         shard_tasks.push(handle);
     }
+    drop(logged);
 
-    // Watch for dynamic market updates and apply to the owning shard.
-    {
-        let (tx, mut rx) = mpsc::channel::<crate::config::MarketConfig>(1024);
-        tokio::spawn(market_registry::watch_updates_tx(
-            settings.bus.nats_url.clone(),
-            settings.bus.markets_bucket.clone(),
-            tx,
-        ));
+    // Watch for dynamic market updates; the input loop below forwards them to the owning shard so
+    // that it stays the only producer on each shard ring.
+    let (market_tx, mut market_rx) = mpsc::channel::<crate::config::MarketConfig>(1024);
+    tokio::spawn(market_registry::watch_updates_tx(
+        settings.bus.nats_url.clone(),
+        settings.bus.markets_bucket.clone(),
+        market_tx,
+    ));
 
-        let senders = shard_senders.clone();
-        tokio::spawn(async move {
-            while let Some(market) = rx.recv().await {
-                let shard_id = (market.market_id as usize) % senders.len();
-                if let Some(sender) = senders.get(shard_id) {
-                    let _ = sender.send(ShardMsg::MarketUpdate(market)).await;
-                }
-            }
-        });
-    }
+    // Any shard may hold a subaccount's collateral or fills, so provisioning goes to all of them.
+    let (subaccount_tx, mut subaccount_rx) = mpsc::channel::<crate::config::SubaccountConfig>(1024);
+    tokio::spawn(subaccount_registry::watch_updates_tx(
+        settings.bus.nats_url.clone(),
+        settings.bus.subaccounts_bucket.clone(),
+        subaccount_tx,
+    ));
+
+    // Risk parameter updates go to the owning shard as inputs, so they are logged and replayed.
+    let (risk_params_tx, mut risk_params_rx) = mpsc::channel::<RiskParamsUpdate>(1024);
+    tokio::spawn(market_registry::watch_risk_params_tx(
+        settings.bus.nats_url.clone(),
+        settings.bus.risk_params_bucket.clone(),
+        risk_params_tx,
+    ));
+
+    // Every shard enforces parent limits for its own markets, so account updates go to all of them.
+    let (account_tx, mut account_rx) = mpsc::channel::<crate::config::AccountConfig>(1024);
+    tokio::spawn(account_registry::watch_updates_tx(
+        settings.bus.nats_url.clone(),
+        settings.bus.accounts_bucket.clone(),
+        account_tx,
+    ));
+
+    let (permissions_tx, mut permissions_rx) = mpsc::channel::<crate::config::MarketPermissions>(1024);
+    tokio::spawn(permission_registry::watch_updates_tx(
+        settings.bus.nats_url.clone(),
+        settings.bus.permissions_bucket.clone(),
+        permissions_tx,
+    ));
+
+    // Any shard may receive a subaccount's orders, so key updates go to all of them.
+    let (key_tx, mut key_rx) = mpsc::channel::<crate::config::SigningKeyConfig>(1024);
+    tokio::spawn(key_registry::watch_updates_tx(
+        settings.bus.nats_url.clone(),
+        settings.bus.keys_bucket.clone(),
+        key_tx,
+    ));
+
+    let mut routes = MarketRoutes::new(settings.shard_count);
+    // Inputs for markets between export and import, held (unacked) until the target has the state.
+    let mut in_flight: HashMap<MarketId, Vec<HeldInput>> = HashMap::new();
+    // Order in which inputs were taken off the bus, across all shards.
+    let mut ingest_seq = 0u64;
 
     let mut subscription = bus.subscribe(&settings.bus.input_subject).await?;
-    while let Some(message) = subscription.stream.next().await {
+    let mut resend_requests = bus.subscribe_ephemeral(&settings.bus.resend_subject).await?;
+    let mut adl_requests = bus.subscribe_ephemeral(&settings.bus.adl_subject).await?;
+    let mut trade_requests = bus.subscribe_ephemeral(&settings.bus.trades_subject).await?;
+    let mut equity_requests = bus.subscribe_ephemeral(&settings.bus.equity_subject).await?;
+    loop {
+        let message = tokio::select! {
+            Some(request) = trade_requests.stream.next() => {
+                // A subaccount may trade on every shard, so each one answers for its own markets.
+                match trades::decode_request(settings.bus.codec, request.payload) {
+                    Ok(request) => {
+                        for sender in shard_senders.iter_mut() {
+                            if sender.send(ShardMsg::TradeQuery(request.clone())).await.is_err() {
+                                warn!("failed to forward trade history request to shard");
+                            }
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "failed to decode trade history request"),
+                }
+                continue;
+            }
+            Some(request) = adl_requests.stream.next() => {
+                match adl::decode_request(settings.bus.codec, request.payload) {
+                    Ok(request) => {
+                        let shard_id = routes.shard_for_market(request.market_id);
+                        if let Some(sender) = shard_senders.get_mut(shard_id)
+                            && sender.send(ShardMsg::AdlQuery(request)).await.is_err()
+                        {
+                            warn!("failed to forward ADL ranking request to shard");
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "failed to decode ADL ranking request"),
+                }
+                continue;
+            }
+            Some(request) = equity_requests.stream.next() => {
+                match health::decode_request(settings.bus.codec, request.payload) {
+                    Ok(request) => {
+                        let shard_id = routes.shard_for_market(request.market_id);
+                        if let Some(sender) = shard_senders.get_mut(shard_id)
+                            && sender.send(ShardMsg::EquityQuery(request)).await.is_err()
+                        {
+                            warn!("failed to forward account equity request to shard");
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "failed to decode account equity request"),
+                }
+                continue;
+            }
+            Some(request) = resend_requests.stream.next() => {
+                let request = match resend::decode_request(settings.bus.codec, request.payload) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        warn!(error = %err, "failed to decode resend request");
+                        continue;
+                    }
+                };
+                let shard_id = request.shard_id as usize;
+                match shard_senders.get_mut(shard_id) {
+                    Some(sender) => {
+                        if sender.send(ShardMsg::Resend(request)).await.is_err() {
+                            warn!("failed to forward resend request to shard");
+                        }
+                    }
+                    None => {
+                        let subject = if request.reply_subject.is_empty() {
+                            settings.bus.output_subject.clone()
+                        } else {
+                            request.reply_subject.clone()
+                        };
+                        let error = Some(format!("unknown shard {shard_id}"));
+                        let complete = resend_complete(shard_id, request, 0, error, clock.now_nanos());
+                        let _ = bus.publish(&subject, encode_output_with(settings.bus.codec, complete)).await;
+                    }
+                }
+                continue;
+            }
+            Some((market_id, transfer)) = migration_rx.recv() => {
+                let buffered = in_flight.remove(&market_id).unwrap_or_default();
+                if let Some(transfer) = transfer {
+                    routes.assign(market_id, transfer.target_shard);
+                    let message = crate::bus::BusMessage { payload: Bytes::new(), ack: crate::bus::BusAck::None };
+                    let event = Event::MarketImport(transfer);
+                    if let Some(sender) = shard_senders.get_mut(routes.shard_for_market(market_id)) {
+                        let import = ShardMsg::Event { event, ts_ns: clock.now_nanos(), ingest_seq: 0, message };
+                        if sender.send(import).await.is_err() {
+                            warn!("failed to forward market import to shard");
+                        }
+                    }
+                }
+                if let Some(sender) = shard_senders.get_mut(routes.shard_for_market(market_id)) {
+                    for (event, ts_ns, ingest_seq, message) in buffered {
+                        if sender.send(ShardMsg::Event { event, ts_ns, ingest_seq, message }).await.is_err() {
+                            warn!("failed to forward buffered input to shard");
+                        }
+                    }
+                }
+                continue;
+            }
+            Some(market) = market_rx.recv() => {
+                let shard_id = routes.shard_for_market(market.market_id);
+                if let Some(sender) = shard_senders.get_mut(shard_id) {
+                    let message = crate::bus::BusMessage { payload: Bytes::new(), ack: crate::bus::BusAck::None };
+                    let event = Event::MarketUpdate(Box::new(market));
+                    if sender.send(ShardMsg::Event { event, ts_ns: clock.now_nanos(), ingest_seq: 0, message }).await.is_err() {
+                        warn!("failed to forward market update to shard");
+                    }
+                }
+                continue;
+            }
+            Some(subaccount) = subaccount_rx.recv() => {
+                for sender in shard_senders.iter_mut() {
+                    let message = crate::bus::BusMessage { payload: Bytes::new(), ack: crate::bus::BusAck::None };
+                    let event = Event::ProvisionSubaccount(subaccount.clone());
+                    if sender.send(ShardMsg::Event { event, ts_ns: clock.now_nanos(), ingest_seq: 0, message }).await.is_err() {
+                        warn!("failed to forward subaccount provisioning to shard");
+                    }
+                }
+                continue;
+            }
+            Some(update) = risk_params_rx.recv() => {
+                let shard_id = routes.shard_for_market(update.market_id);
+                if let Some(sender) = shard_senders.get_mut(shard_id) {
+                    let message = crate::bus::BusMessage { payload: Bytes::new(), ack: crate::bus::BusAck::None };
+                    let event = Event::RiskParamsUpdate(update);
+                    if sender.send(ShardMsg::Event { event, ts_ns: clock.now_nanos(), ingest_seq: 0, message }).await.is_err() {
+                        warn!("failed to forward risk parameter update to shard");
+                    }
+                }
+                continue;
+            }
+            Some(account) = account_rx.recv() => {
+                for sender in shard_senders.iter_mut() {
+                    if sender.send(ShardMsg::AccountUpdate(account.clone())).await.is_err() {
+                        warn!("failed to forward account update to shard");
+                    }
+                }
+                continue;
+            }
+            Some(key) = key_rx.recv() => {
+                for sender in shard_senders.iter_mut() {
+                    if sender.send(ShardMsg::SigningKeyUpdate(key.clone())).await.is_err() {
+                        warn!("failed to forward signing key update to shard");
+                    }
+                }
+                continue;
+            }
+            Some(permissions) = permissions_rx.recv() => {
+                let shard_id = routes.shard_for_market(permissions.market_id);
+                if let Some(sender) = shard_senders.get_mut(shard_id)
+                    && sender.send(ShardMsg::PermissionsUpdate(permissions)).await.is_err()
+                {
+                    warn!("failed to forward market permissions to shard");
+                }
+                continue;
+            }
+            message = subscription.stream.next() => match message {
+                Some(message) => message,
+                None => break,
+            },
+        };
         let payload = message.payload.clone();
-        let ts = current_ts();
-        if let Ok(event) = decode_input(payload) {
-            let market_id = market_id_for_event(&event).unwrap_or(0);
-            let shard_id = (market_id as usize) % settings.shard_count;
-            if let Some(sender) = shard_senders.get(shard_id) {
-                if sender
-                    .send(ShardMsg::Event {
-                        event,
+        let ts_ns = clock.now_nanos();
+        let ts = ts_ns / NANOS_PER_SEC;
+        ingest_seq += 1;
+        match decode_input_with(settings.bus.codec, payload.clone()) {
+            Ok(Event::MassCancel(cancel)) if cancel.market_id.is_none() => {
+                // An all-markets cancel runs on every shard; the last one carries the bus message and
+                // acks it, the others get an unackable copy.
+                let last = shard_senders.len().saturating_sub(1);
+                let mut message = Some(message);
+                for (shard_id, sender) in shard_senders.iter_mut().enumerate() {
+                    let message = match message.take_if(|_| shard_id == last) {
+                        Some(message) => message,
+                        None => crate::bus::BusMessage { payload: payload.clone(), ack: crate::bus::BusAck::None },
+                    };
+                    let event = Event::MassCancel(cancel.clone());
+                    if sender.send(ShardMsg::Event { event, ts_ns, ingest_seq, message }).await.is_err() {
+                        warn!("failed to forward mass cancel to shard");
+                    }
+                }
+            }
+            Ok(event) if market_id_for_event(&event).is_some_and(|market_id| in_flight.contains_key(&market_id)) => {
+                let market_id = market_id_for_event(&event).expect("checked above");
+                in_flight.entry(market_id).or_default().push((event, ts_ns, ingest_seq, message));
+            }
+            Ok(Event::MigrateMarket(migrate)) if migrate.target_shard >= settings.shard_count => {
+                let reject = EventEnvelope {
+                    shard_id: routes.shard_for_market(migrate.market_id),
+                    engine_seq: 0,
+                    event: Event::OrderAck(OrderAck {
+                        request_id: migrate.request_id,
+                        status: OrderStatus::Rejected,
+                        reject_code: Some(RejectReason::InvalidOrder),
+                        reject_reason: Some("unknown target shard".to_string()),
+                        assigned_order_id: None,
+                        engine_seq: 0,
                         ts,
-                        message,
-                    })
-                    .await
-                    .is_err()
-                {
-                    warn!("failed to forward input event to shard");
+                        filled_qty: 0,
+                        remaining_qty: 0,
+                        disposition: None,
+                        subaccount_id: None,
+                    }),
+                    ts,
+                    ts_ns,
+                    schema_version: SCHEMA_VERSION,
+                };
+                let _ = bus.publish(&settings.bus.output_subject, encode_output_with(settings.bus.codec, reject)).await;
+                let _ = bus.ack(message).await;
+            }
+            Ok(event) => {
+                if let Event::MigrateMarket(migrate) = &event {
+                    in_flight.insert(migrate.market_id, Vec::new());
+                }
+                let shard_id = routes.shard_for_event(&event);
+                if let Some(sender) = shard_senders.get_mut(shard_id) {
+                    if sender
+                        .send(ShardMsg::Event {
+                            event,
+                            ts_ns,
+                            ingest_seq,
+                            message,
+                        })
+                        .await
+                        .is_err()
+                    {
+                        warn!("failed to forward input event to shard");
+                    }
+                } else {
+                    warn!("no shard sender for input event");
+                    let _ = bus.ack(message).await;
+                }
+            }
+            Err(err) => {
+                warn!(error = %err, "failed to decode input event");
+                match invalid_input_ack(&err, settings.shard_count, ts_ns) {
+                    Some(reject) => {
+                        let subject = private_output_subject(&reject.event, &settings.bus.account_subject, settings.bus.private_acks)
+                            .unwrap_or_else(|| settings.bus.output_subject.clone());
+                        let bytes = encode_output_with(settings.bus.codec, reject);
+                        let _ = bus.publish(&subject, bytes).await;
+                    }
+                    None => {
+                        publish_dead_letter(
+                            bus.as_ref(),
+                            &settings.bus.dead_letter_subject,
+                            settings.bus.codec,
+                            DeadLetterStage::Decode,
+                            &message,
+                            &err.to_string(),
+                            ts,
+                        )
+                        .await;
+                    }
                 }
-            } else {
-                warn!("no shard sender for input event");
                 let _ = bus.ack(message).await;
             }
-        } else {
-            warn!("failed to decode input event");
-            let _ = bus.ack(message).await;
         }
     }
 
+    drop(shard_senders);
     info!("router stopped");
     for task in shard_tasks {
         let _ = task.await;
@@ -132,34 +784,337 @@ pub async fn run_router(settings: Settings, bus: Arc<dyn Bus>) -> anyhow::Result
     Ok(())
 }
 
-fn decode_input(payload: Bytes) -> anyhow::Result<Event> {
-    let input = pb::InputEvent::decode(payload)?;
-    let event = match input.payload.ok_or_else(|| anyhow::anyhow!("missing payload"))? {
-        pb::input_event::Payload::NewOrder(order) => Event::NewOrder(order.into()),
+/// Decodes straight from the shared `Bytes` buffer; `bytes` fields are sliced, not copied.
+pub fn decode_input(payload: Bytes) -> Result<Event, DecodeError> {
+    decode_input_with(WireCodec::Protobuf, payload)
+}
+
+pub fn decode_input_with(codec: WireCodec, payload: Bytes) -> Result<Event, DecodeError> {
+    let input: pb::InputEvent = match codec {
+        WireCodec::Protobuf => pb::InputEvent::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    };
+    let event = match input.payload.ok_or(DecodeError::MissingPayload)? {
+        pb::input_event::Payload::NewOrder(order) => {
+            let (market_id, subaccount_id) = (order.market_id, order.subaccount_id);
+            Event::NewOrder(order.try_into().map_err(|err: InvalidNewOrder| DecodeError::InvalidOrder {
+                request_id: err.request_id,
+                market_id,
+                subaccount_id,
+                source: err.error,
+            })?)
+        }
         pb::input_event::Payload::CancelOrder(cancel) => Event::CancelOrder(cancel.into()),
         pb::input_event::Payload::PriceUpdate(update) => Event::PriceUpdate(update.into()),
         pb::input_event::Payload::FundingUpdate(update) => Event::FundingUpdate(update.into()),
+        pb::input_event::Payload::BlockTrade(trade) => Event::BlockTrade(trade.into()),
+        pb::input_event::Payload::MassCancel(cancel) => Event::MassCancel(cancel.into()),
+        pb::input_event::Payload::MigrateMarket(migrate) => Event::MigrateMarket(migrate.into()),
+        pb::input_event::Payload::Transfer(transfer) => Event::Transfer(transfer.into()),
     };
     Ok(event)
 }
 
-fn encode_output(envelope: crate::models::EventEnvelope) -> Bytes {
-    let output = match envelope.event {
-        Event::OrderAck(ack) => pb::OutputEvent {
-            payload: Some(pb::output_event::Payload::OrderAck(ack.into())),
-        },
-        Event::Fill(fill) => pb::OutputEvent {
-            payload: Some(pb::output_event::Payload::Fill(fill.into())),
-        },
-        Event::BookDelta(delta) => pb::OutputEvent {
-            payload: Some(pb::output_event::Payload::BookDelta(delta.into())),
-        },
-        Event::SettlementBatch(batch) => pb::OutputEvent {
-            payload: Some(pb::output_event::Payload::SettlementBatch(batch.into())),
-        },
-        _ => pb::OutputEvent { payload: None },
+/// Explicit reject for inputs that decoded but failed validation, so the sender gets an ack
+/// instead of silence. These never reach a shard and therefore carry `engine_seq` 0.
+pub fn invalid_input_ack(err: &DecodeError, shard_count: usize, ts_ns: u64) -> Option<EventEnvelope> {
+    let ts = ts_ns / NANOS_PER_SEC;
+    let DecodeError::InvalidOrder {
+        request_id,
+        market_id,
+        subaccount_id,
+        source,
+    } = err
+    else {
+        return None;
+    };
+    Some(EventEnvelope {
+        shard_id: (*market_id as usize) % shard_count,
+        engine_seq: 0,
+        event: Event::OrderAck(OrderAck {
+            request_id: request_id.clone(),
+            status: OrderStatus::Rejected,
+            reject_code: Some(RejectReason::InvalidOrder),
+            reject_reason: Some(source.to_string()),
+            assigned_order_id: None,
+            engine_seq: 0,
+            ts,
+            filled_qty: 0,
+            remaining_qty: 0,
+            disposition: None,
+            subaccount_id: Some(*subaccount_id),
+        }),
+        ts,
+        ts_ns,
+        schema_version: SCHEMA_VERSION,
+    })
+}
+
+/// Inverse of [`decode_input`]; fails for events that are not bus inputs.
+pub fn encode_input(event: Event) -> anyhow::Result<Bytes> {
+    encode_input_with(WireCodec::Protobuf, event)
+}
+
+pub fn encode_input_with(codec: WireCodec, event: Event) -> anyhow::Result<Bytes> {
+    let payload = match event {
+        Event::NewOrder(order) => pb::input_event::Payload::NewOrder(order.into()),
+        Event::CancelOrder(cancel) => pb::input_event::Payload::CancelOrder(cancel.into()),
+        Event::PriceUpdate(update) => pb::input_event::Payload::PriceUpdate(update.into()),
+        Event::FundingUpdate(update) => pb::input_event::Payload::FundingUpdate(update.into()),
+        Event::BlockTrade(trade) => pb::input_event::Payload::BlockTrade(trade.into()),
+        Event::MassCancel(cancel) => pb::input_event::Payload::MassCancel(cancel.into()),
+        Event::MigrateMarket(migrate) => pb::input_event::Payload::MigrateMarket(migrate.into()),
+        Event::Transfer(transfer) => pb::input_event::Payload::Transfer(transfer.into()),
+        other => anyhow::bail!("not an input event: {other:?}"),
+    };
+    let input = pb::InputEvent {
+        payload: Some(payload),
     };
-    Bytes::from(output.encode_to_vec())
+    Ok(match codec {
+        WireCodec::Protobuf => Bytes::from(input.encode_to_vec()),
+        WireCodec::Json => Bytes::from(serde_json::to_vec(&input)?),
+    })
+}
+
+pub fn encode_output(envelope: EventEnvelope) -> Bytes {
+    encode_output_with(WireCodec::Protobuf, envelope)
+}
+
+pub fn encode_output_with(codec: WireCodec, envelope: EventEnvelope) -> Bytes {
+    encode_sequenced_output(codec, envelope, OutputSequence::default())
+}
+
+/// Encodes `envelope` stamped with its place in the shard's output stream. The shard comes from
+/// the envelope; `sequence.shard_id` is not used. Its `ts_ns` also stamps a fill or ack payload.
+pub fn encode_sequenced_output(codec: WireCodec, envelope: EventEnvelope, sequence: OutputSequence) -> Bytes {
+    let shard_id = envelope.shard_id as u64;
+    let engine_seq = envelope.engine_seq;
+    let ts = envelope.ts;
+    let ts_ns = envelope.ts_ns;
+    let mut payload = match envelope.event {
+        Event::OrderAck(ack) => Some(pb::output_event::Payload::OrderAck(ack.into())),
+        Event::Fill(fill) => Some(pb::output_event::Payload::Fill(fill.into())),
+        Event::BookDelta(delta) => Some(pb::output_event::Payload::BookDelta(delta.into())),
+        Event::SettlementBatch(batch) => Some(pb::output_event::Payload::SettlementBatch(batch.into())),
+        Event::AuctionIndicative(indicative) => Some(pb::output_event::Payload::AuctionIndicative(indicative.into())),
+        Event::MakerCompliance(report) => Some(pb::output_event::Payload::MakerCompliance(report.into())),
+        Event::ResendComplete(complete) => Some(pb::output_event::Payload::ResendComplete(complete.into())),
+        Event::AdlRanking(ranking) => Some(pb::output_event::Payload::AdlRanking(ranking.into())),
+        Event::TradeHistory(history) => Some(pb::output_event::Payload::TradeHistory(history.into())),
+        Event::UserFill(fill) => Some(pb::output_event::Payload::UserFill(fill.into())),
+        Event::DepthSnapshot(snapshot) => Some(pb::output_event::Payload::DepthSnapshot(snapshot.into())),
+        Event::CollateralTransfer(transfer) => Some(pb::output_event::Payload::CollateralTransfer(transfer.into())),
+        Event::FundingPayment(payment) => Some(pb::output_event::Payload::FundingPayment(payment.into())),
+        Event::PnlRealized(pnl) => Some(pb::output_event::Payload::PnlRealized(pnl.into())),
+        Event::PositionUpdate(update) => Some(pb::output_event::Payload::PositionUpdate(update.into())),
+        Event::AccountEquity(equity) => Some(pb::output_event::Payload::AccountEquity(equity.into())),
+        Event::MarginWarning(warning) => Some(pb::output_event::Payload::MarginWarning(warning.into())),
+        Event::OrderUpdate(update) => Some(pb::output_event::Payload::OrderUpdate(update.into())),
+        Event::OrderTriggered(triggered) => Some(pb::output_event::Payload::OrderTriggered(triggered.into())),
+        Event::Liquidation(liquidation) => Some(pb::output_event::Payload::Liquidation(liquidation.into())),
+        Event::BackstopAssignment(assignment) => Some(pb::output_event::Payload::BackstopAssignment(assignment.into())),
+        Event::FundingRate(rate) => Some(pb::output_event::Payload::FundingRate(rate.into())),
+        Event::IndexPrice(price) => Some(pb::output_event::Payload::IndexPrice(price.into())),
+        Event::RiskParameterChange(change) => Some(pb::output_event::Payload::RiskParameterChange(change.into())),
+        // Inputs, and market state in transit between shards, are never published.
+        _ => None,
+    };
+    match &mut payload {
+        Some(pb::output_event::Payload::OrderAck(ack)) => ack.ts_ns = ts_ns,
+        Some(pb::output_event::Payload::Fill(fill)) => fill.ts_ns = ts_ns,
+        Some(pb::output_event::Payload::UserFill(fill)) => fill.ts_ns = ts_ns,
+        _ => {}
+    }
+    let output = pb::OutputEvent {
+        schema_version: SCHEMA_VERSION,
+        run_id: sequence.run_id,
+        shard_id,
+        output_seq: sequence.output_seq,
+        global_seq: sequence.global_seq,
+        ingest_seq: sequence.ingest_seq,
+        market_seq: sequence.market_seq,
+        engine_seq,
+        ts,
+        ts_ns,
+        payload,
+    };
+    match codec {
+        WireCodec::Protobuf => Bytes::from(output.encode_to_vec()),
+        // Generated messages hold only integers, strings and bytes, which always serialize.
+        WireCodec::Json => Bytes::from(serde_json::to_vec(&output).unwrap_or_default()),
+    }
+}
+
+/// Subject of a subaccount's private outputs of `kind` (`acks`, `fills`, `positions` or `margin`) under `prefix`
+/// (`bus.account_subject`).
+pub fn account_subject(prefix: &str, subaccount_id: SubaccountId, kind: &str) -> String {
+    format!("{prefix}.{subaccount_id}.{kind}")
+}
+
+/// Where `event` goes instead of the shared output stream, if it is private to one subaccount:
+/// user fills, position updates and margin warnings always, acks naming a subaccount only with `private_acks`.
+fn private_output_subject(event: &Event, prefix: &str, private_acks: bool) -> Option<String> {
+    match event {
+        Event::UserFill(fill) => Some(account_subject(prefix, fill.subaccount_id, "fills")),
+        Event::PositionUpdate(update) => Some(account_subject(prefix, update.subaccount_id, "positions")),
+        Event::MarginWarning(warning) => Some(account_subject(prefix, warning.subaccount_id, "margin")),
+        Event::OrderAck(ack) if private_acks => ack
+            .subaccount_id
+            .map(|subaccount_id| account_subject(prefix, subaccount_id, "acks")),
+        _ => None,
+    }
+}
+
+/// Whether [`encode_output_with`] gives `event` a payload.
+fn has_output_payload(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::OrderAck(_)
+            | Event::Fill(_)
+            | Event::BookDelta(_)
+            | Event::SettlementBatch(_)
+            | Event::AuctionIndicative(_)
+            | Event::MakerCompliance(_)
+            | Event::ResendComplete(_)
+            | Event::AdlRanking(_)
+            | Event::TradeHistory(_)
+            | Event::UserFill(_)
+            | Event::DepthSnapshot(_)
+            | Event::CollateralTransfer(_)
+            | Event::FundingPayment(_)
+            | Event::PnlRealized(_)
+            | Event::PositionUpdate(_)
+            | Event::AccountEquity(_)
+            | Event::MarginWarning(_)
+            | Event::OrderUpdate(_)
+            | Event::OrderTriggered(_)
+            | Event::Liquidation(_)
+            | Event::BackstopAssignment(_)
+            | Event::FundingRate(_)
+            | Event::IndexPrice(_)
+            | Event::RiskParameterChange(_)
+    )
+}
+
+/// Decodes an `OutputEvent` published by [`encode_output`]. Settlement batches and empty payloads
+/// are reported as errors since they carry nothing a client correlates on.
+pub fn decode_output(payload: Bytes) -> anyhow::Result<Event> {
+    decode_output_with(WireCodec::Protobuf, payload)
+}
+
+pub fn decode_output_with(codec: WireCodec, payload: Bytes) -> anyhow::Result<Event> {
+    output_event(parse_output(codec, payload)?)
+}
+
+/// Like [`decode_output_with`], keeping the shard, `engine_seq` and timestamp the output was
+/// published with. Outputs from builds that predate them decode with zeros.
+pub fn decode_output_envelope(codec: WireCodec, payload: Bytes) -> anyhow::Result<EventEnvelope> {
+    let output = parse_output(codec, payload)?;
+    let shard_id = output.shard_id as usize;
+    let engine_seq = output.engine_seq;
+    let ts = output.ts;
+    let ts_ns = output.ts_ns;
+    let schema_version = output.schema_version;
+    Ok(EventEnvelope {
+        shard_id,
+        engine_seq,
+        event: output_event(output)?,
+        ts,
+        ts_ns,
+        schema_version,
+    })
+}
+
+/// The place of a published output in its shard's output stream, for gap detection with
+/// [`resend::GapDetector`]. Works for every output, including ones [`decode_output_with`] rejects.
+pub fn decode_output_sequence(codec: WireCodec, payload: Bytes) -> anyhow::Result<OutputSequence> {
+    let output = parse_output(codec, payload)?;
+    Ok(OutputSequence {
+        run_id: output.run_id,
+        shard_id: output.shard_id as usize,
+        output_seq: output.output_seq,
+        global_seq: output.global_seq,
+        ingest_seq: output.ingest_seq,
+        market_seq: output.market_seq,
+    })
+}
+
+/// The published `OutputEvent` as is, for readers that only need its header fields. Works for
+/// every output, including ones [`decode_output_with`] rejects.
+pub fn parse_output(codec: WireCodec, payload: Bytes) -> anyhow::Result<pb::OutputEvent> {
+    Ok(match codec {
+        WireCodec::Protobuf => pb::OutputEvent::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    })
+}
+
+fn output_event(output: pb::OutputEvent) -> anyhow::Result<Event> {
+    // Versions 0 (unversioned) and 1 share every payload layout. Newer producers only add
+    // fields, which protobuf skips, so their outputs decode too.
+    let event = match output.payload.ok_or_else(|| anyhow::anyhow!("missing payload"))? {
+        pb::output_event::Payload::OrderAck(ack) => Event::OrderAck(ack.into()),
+        pb::output_event::Payload::Fill(fill) => Event::Fill(fill.into()),
+        pb::output_event::Payload::BookDelta(delta) => Event::BookDelta(delta.into()),
+        pb::output_event::Payload::SettlementBatch(_) => anyhow::bail!("settlement batches are not decoded"),
+        pb::output_event::Payload::AuctionIndicative(indicative) => Event::AuctionIndicative(indicative.into()),
+        pb::output_event::Payload::MakerCompliance(report) => Event::MakerCompliance(report.into()),
+        pb::output_event::Payload::ResendComplete(complete) => Event::ResendComplete(complete.into()),
+        pb::output_event::Payload::AdlRanking(ranking) => Event::AdlRanking(ranking.into()),
+        pb::output_event::Payload::TradeHistory(history) => Event::TradeHistory(history.into()),
+        pb::output_event::Payload::UserFill(fill) => Event::UserFill(fill.into()),
+        pb::output_event::Payload::DepthSnapshot(snapshot) => Event::DepthSnapshot(snapshot.into()),
+        pb::output_event::Payload::CollateralTransfer(transfer) => Event::CollateralTransfer(transfer.into()),
+        pb::output_event::Payload::FundingPayment(payment) => Event::FundingPayment(payment.into()),
+        pb::output_event::Payload::PnlRealized(pnl) => Event::PnlRealized(pnl.into()),
+        pb::output_event::Payload::PositionUpdate(update) => Event::PositionUpdate(update.into()),
+        pb::output_event::Payload::AccountEquity(equity) => Event::AccountEquity(equity.into()),
+        pb::output_event::Payload::MarginWarning(warning) => Event::MarginWarning(warning.into()),
+        pb::output_event::Payload::OrderUpdate(update) => Event::OrderUpdate(update.into()),
+        pb::output_event::Payload::OrderTriggered(triggered) => Event::OrderTriggered(triggered.into()),
+        pb::output_event::Payload::Liquidation(liquidation) => Event::Liquidation(liquidation.into()),
+        pb::output_event::Payload::BackstopAssignment(assignment) => Event::BackstopAssignment(assignment.into()),
+        pb::output_event::Payload::FundingRate(rate) => Event::FundingRate(rate.into()),
+        pb::output_event::Payload::IndexPrice(price) => Event::IndexPrice(price.into()),
+        pb::output_event::Payload::RiskParameterChange(change) => Event::RiskParameterChange(change.into()),
+    };
+    Ok(event)
+}
+
+/// Shard that owns `event`; events without a market route to shard 0.
+pub fn shard_for_event(event: &Event, shard_count: usize) -> usize {
+    let market_id = market_id_for_event(event).unwrap_or(0);
+    (market_id as usize) % shard_count
+}
+
+/// Market → shard assignment: `market_id % shard_count` unless the market has been migrated.
+#[derive(Debug, Clone)]
+pub struct MarketRoutes {
+    shard_count: usize,
+    migrated: HashMap<MarketId, usize>,
+}
+
+impl MarketRoutes {
+    pub fn new(shard_count: usize) -> Self {
+        Self {
+            shard_count,
+            migrated: HashMap::new(),
+        }
+    }
+
+    pub fn assign(&mut self, market_id: MarketId, shard_id: usize) {
+        self.migrated.insert(market_id, shard_id);
+    }
+
+    pub fn shard_for_market(&self, market_id: MarketId) -> usize {
+        self.migrated
+            .get(&market_id)
+            .copied()
+            .unwrap_or((market_id as usize) % self.shard_count)
+    }
+
+    pub fn shard_for_event(&self, event: &Event) -> usize {
+        self.shard_for_market(market_id_for_event(event).unwrap_or(0))
+    }
 }
 
 fn market_id_for_event(event: &Event) -> Option<u64> {
@@ -168,11 +1123,148 @@ fn market_id_for_event(event: &Event) -> Option<u64> {
         Event::CancelOrder(order) => Some(order.market_id),
         Event::PriceUpdate(update) => Some(update.market_id),
         Event::FundingUpdate(update) => Some(update.market_id),
+        Event::BlockTrade(trade) => Some(trade.market_id),
+        Event::MassCancel(cancel) => cancel.market_id,
+        Event::MigrateMarket(migrate) => Some(migrate.market_id),
+        Event::Transfer(transfer) => Some(transfer.market_id),
+        Event::RiskParamsUpdate(update) => Some(update.market_id),
+        Event::MarketUpdate(market) => Some(market.market_id),
         _ => None,
     }
 }
 
-fn current_ts() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+/// Closes a resend request, reporting how many outputs were published again.
+fn resend_complete(
+    shard_id: usize,
+    request: pb::ResendRequest,
+    resent: u64,
+    error: Option<String>,
+    ts_ns: u64,
+) -> EventEnvelope {
+    EventEnvelope {
+        shard_id,
+        engine_seq: 0,
+        event: Event::ResendComplete(ResendComplete {
+            request_id: request.request_id,
+            shard_id,
+            from_seq: request.from_seq,
+            to_seq: request.to_seq,
+            resent,
+            error,
+        }),
+        ts: ts_ns / NANOS_PER_SEC,
+        ts_ns,
+        schema_version: SCHEMA_VERSION,
+    }
 }
+
+/// Answers an ADL ranking query from the shard holding the market.
+fn adl_ranking(shard: &EngineShard, request: pb::AdlRankingRequest, ts_ns: u64) -> EventEnvelope {
+    let side = match request.side.as_str() {
+        "BUY" => Some(Side::Buy),
+        "SELL" => Some(Side::Sell),
+        _ => None,
+    };
+    let mut ranking = AdlRanking {
+        request_id: request.request_id,
+        market_id: request.market_id,
+        side: side.unwrap_or(Side::Buy),
+        entries: Vec::new(),
+        error: None,
+    };
+    if !shard.has_market(request.market_id) {
+        ranking.error = Some(format!("unknown market {}", request.market_id));
+    } else if request.subaccount_id != 0 {
+        if let Some((side, entry)) = shard.adl_rank(request.market_id, request.subaccount_id) {
+            ranking.side = side;
+            ranking.entries.push(entry);
+        }
+    } else if let Some(side) = side {
+        ranking.entries = shard.adl_ranking(request.market_id, side, request.limit as usize);
+    } else {
+        ranking.error = Some(format!("unknown side {:?}", request.side));
+    }
+    EventEnvelope {
+        shard_id: shard.shard_id,
+        engine_seq: 0,
+        event: Event::AdlRanking(ranking),
+        ts: ts_ns / NANOS_PER_SEC,
+        ts_ns,
+        schema_version: SCHEMA_VERSION,
+    }
+}
+
+/// Answers a trade history query with the trades this shard recorded for the subaccount.
+fn trade_history(shard: &EngineShard, request: pb::TradeHistoryRequest, ts_ns: u64) -> EventEnvelope {
+    let mut history = TradeHistory {
+        request_id: request.request_id,
+        shard_id: shard.shard_id,
+        subaccount_id: request.subaccount_id,
+        trades: Vec::new(),
+        more: false,
+        error: None,
+    };
+    let limit = usize::try_from(request.limit).unwrap_or(usize::MAX);
+    match shard.trade_history(request.subaccount_id, request.since_ts, request.since_seq, limit) {
+        Some((trades, more)) => {
+            history.trades = trades;
+            history.more = more;
+        }
+        None => history.error = Some("trade history is not enabled".to_string()),
+    }
+    EventEnvelope {
+        shard_id: shard.shard_id,
+        engine_seq: 0,
+        event: Event::TradeHistory(history),
+        ts: ts_ns / NANOS_PER_SEC,
+        ts_ns,
+        schema_version: SCHEMA_VERSION,
+    }
+}
+
+/// Answers an account equity query from the shard holding the request's market.
+fn account_equity(shard: &EngineShard, request: pb::AccountEquityRequest, ts_ns: u64) -> EventEnvelope {
+    let mut equity = AccountEquity {
+        request_id: request.request_id,
+        ..shard.account_equity(request.subaccount_id)
+    };
+    if !shard.has_market(request.market_id) {
+        equity.error = Some(format!("unknown market {}", request.market_id));
+    }
+    EventEnvelope {
+        shard_id: shard.shard_id,
+        engine_seq: equity.engine_seq,
+        event: Event::AccountEquity(equity),
+        ts: ts_ns / NANOS_PER_SEC,
+        ts_ns,
+        schema_version: SCHEMA_VERSION,
+    }
+}
+
+/// Parks `message` on the dead-letter subject; a failure to do so is only logged.
+async fn publish_dead_letter(
+    bus: &dyn Bus,
+    subject: &str,
+    codec: WireCodec,
+    stage: DeadLetterStage,
+    message: &BusMessage,
+    error: &str,
+    ts: u64,
+) {
+    let letter = dead_letter::dead_letter(stage, message.payload.clone(), error, message.deliveries(), ts);
+    if let Err(err) = dead_letter::publish(bus, subject, codec, &letter).await {
+        warn!(error = %err, "failed to publish dead letter");
+    }
+}
+
+/// Text of a caught panic payload.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
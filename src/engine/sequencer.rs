@@ -0,0 +1,83 @@
+//! Optional global ordering of outputs across shards.
+//!
+//! Each shard numbers its inputs with its own `engine_seq`, so outputs of different shards cannot
+//! be put in one order. With `global_sequencing` enabled the router numbers inputs in the order it
+//! takes them off the bus (`ingest_seq`), and every shard draws the `global_seq` of the outputs it
+//! publishes from one shared [`Sequencer`]. Both restart at 1 with each run of the engine, like
+//! `output_seq`.
+//!
+//! Shards still publish independently, so a consumer may receive outputs slightly out of
+//! `global_seq` order and reconciles by sorting on it. The sequence has no gaps: once a consumer
+//! holds every number up to N, it has seen every output published before N in the global order.
+//!
+//! With `market_sequencing` enabled, fills and book deltas also carry a `market_seq` drawn from a
+//! [`MarketSequencer`]: one gap-free counter per market, so a consumer following one market can
+//! spot a gap without tracking the shard's other markets. It is shared by all shards, so a market
+//! keeps its count when it migrates, and restarts at 1 with each run like `global_seq`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::models::{Event, MarketId};
+
+/// Hands out a gap-free, strictly increasing sequence shared by all shards.
+#[derive(Debug)]
+pub struct Sequencer {
+    next: AtomicU64,
+}
+
+impl Sequencer {
+    pub fn new() -> Self {
+        Self {
+            next: AtomicU64::new(1),
+        }
+    }
+
+    pub fn next(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Last sequence handed out; 0 if none.
+    pub fn last(&self) -> u64 {
+        self.next.load(Ordering::Relaxed) - 1
+    }
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hands out a gap-free, strictly increasing sequence per market.
+#[derive(Debug, Default)]
+pub struct MarketSequencer {
+    last: Mutex<HashMap<MarketId, u64>>,
+}
+
+impl MarketSequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Next `market_seq` for `event`, or 0 if it is not a market-scoped event (fills and book
+    /// deltas).
+    pub fn next(&self, event: &Event) -> u64 {
+        let market_id = match event {
+            Event::Fill(fill) => fill.market_id,
+            Event::BookDelta(delta) => delta.market_id,
+            _ => return 0,
+        };
+        let mut last = self.last.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let seq = last.entry(market_id).or_insert(0);
+        *seq += 1;
+        *seq
+    }
+
+    /// Last sequence handed out for `market_id`; 0 if none.
+    pub fn last(&self, market_id: MarketId) -> u64 {
+        let last = self.last.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        last.get(&market_id).copied().unwrap_or(0)
+    }
+}
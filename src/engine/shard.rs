@@ -1,47 +1,268 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use lru::LruCache;
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use crate::config::{MarketConfig, MatchingMode};
+use crate::config::{
+    AccountConfig, BookSnapshotConfig, FeeTier, LatencyBudgetAction, LatencyBudgetConfig, MarketConfig, MarketPermissions, MatchingMode,
+    SigningKeyConfig, SubaccountConfig,
+};
+use crate::engine::accounts::AccountHierarchy;
+use crate::engine::adl::{adl_score, AdlQueue};
+use crate::engine::clock::NANOS_PER_SEC;
+use crate::engine::dedupe::DedupeWindow;
+use crate::engine::funding;
+use crate::engine::health::RiskMetrics;
+use crate::engine::log::{EventLog, MemoryLog};
+use crate::engine::obligations::ObligationTracker;
+use crate::engine::oracle::{self, OracleQuote};
+use crate::engine::volatility::{self, RealizedVolatility};
+use crate::engine::signatures;
 use crate::matching::batch::BatchAuction;
 use crate::matching::orderbook::{IncomingOrder, OrderBook};
+use crate::matching::triggers::TriggerIndex;
 use crate::models::{
-    BookDelta, BookLevel, CancelOrder, Event, EventEnvelope, Fill, MarketId, NewOrder, OrderAck,
-    OrderId, OrderStatus, PriceTicks, Side, TimeInForce,
+    AccountEquity, AdlRank, AuctionIndicative, BackstopAssignment, BlockTrade, BookDelta, BookLevel, CancelOrder, CollateralTransfer, DepthSnapshot, Event, EventEnvelope, Fill, FundingPayment, FundingRate, IndexPrice, Liquidation, MakerCompliance, MarginWarning, MarketId, MarketTransfer, MassCancel, MigrateMarket, NewOrder, OrderAck, OrderDisposition, PnlRealized, PositionUpdate,
+    OrderId, OrderStatus, OrderTriggered, OrderType, OrderUpdate, OrderUpdateStatus, PriceTicks, PriceUpdate, Quantity, RejectReason, RiskParameter, RiskParameterChange, RiskParamsUpdate, Side, SubaccountId, Trade, Transfer, UserFill,
+    TimeInForce, TriggerSource, SCHEMA_VERSION,
 };
-use crate::persistence::wal::Wal;
-use crate::risk::{RiskEngine, RiskError, RiskState};
+use crate::persistence::trades::TradeStore;
+use crate::risk::{Position, PositionChange, ProposedOrder, RiskEngine, RiskState};
 
+/// A resting order as submitted, less what has filled.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OrderSnapshot {
     pub order_id: OrderId,
     pub subaccount_id: u64,
     pub side: Side,
+    pub order_type: OrderType,
+    pub tif: TimeInForce,
     pub price_ticks: PriceTicks,
     pub remaining: u64,
+    pub reduce_only: bool,
     pub ingress_seq: u64,
 }
 
+impl OrderSnapshot {
+    /// The book's resting orders in time priority, the order they must be placed back in.
+    fn from_book(book: &OrderBook) -> Vec<Self> {
+        let mut orders: Vec<Self> = book
+            .order_views()
+            .into_iter()
+            .map(|order| Self {
+                order_id: order.order_id,
+                subaccount_id: order.subaccount_id,
+                side: order.side,
+                order_type: order.order_type,
+                tif: order.tif,
+                price_ticks: order.price_ticks,
+                remaining: order.remaining,
+                reduce_only: order.reduce_only,
+                ingress_seq: order.ingress_seq,
+            })
+            .collect();
+        orders.sort_unstable_by_key(|order| (order.ingress_seq, order.order_id));
+        orders
+    }
+
+    pub(crate) fn incoming(&self) -> IncomingOrder {
+        IncomingOrder {
+            order_id: self.order_id,
+            subaccount_id: self.subaccount_id,
+            side: self.side,
+            order_type: self.order_type,
+            tif: self.tif,
+            price_ticks: self.price_ticks,
+            qty: self.remaining,
+            reduce_only: self.reduce_only,
+            ingress_seq: self.ingress_seq,
+        }
+    }
+}
+
+/// An order waiting in a batch auction, with its whole quantity remaining.
+impl From<&IncomingOrder> for OrderSnapshot {
+    fn from(order: &IncomingOrder) -> Self {
+        Self {
+            order_id: order.order_id,
+            subaccount_id: order.subaccount_id,
+            side: order.side,
+            order_type: order.order_type,
+            tif: order.tif,
+            price_ticks: order.price_ticks,
+            remaining: order.qty,
+            reduce_only: order.reduce_only,
+            ingress_seq: order.ingress_seq,
+        }
+    }
+}
+
+/// A shard's full state at `engine_seq`; [`EngineShard::restore`] rebuilds the shard from it and
+/// the WAL is replayed from there.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EngineState {
     pub shard_id: usize,
     pub engine_seq: u64,
     pub next_order_id: u64,
-    pub orderbooks: HashMap<MarketId, Vec<OrderSnapshot>>,
+    /// Every market the shard holds, exported as a migration would move it.
+    pub markets: BTreeMap<MarketId, MarketExport>,
+    /// Collateral and margin mode per subaccount. Positions, marks and funding indices of the
+    /// markets above are kept in their exports instead.
     pub risk_state: RiskState,
+    pub next_trade_id: u64,
+    /// Seed for batch-clearing jitter; see [`EngineShard::clearing_seed`].
+    pub clearing_seed: u64,
+    /// Parent accounts, by parent id.
+    pub accounts: Vec<AccountConfig>,
+}
+
+impl EngineState {
+    /// The subaccount's positions, by market.
+    pub fn positions(&self, subaccount_id: SubaccountId) -> BTreeMap<MarketId, Position> {
+        let mut positions = self
+            .risk_state
+            .subaccounts
+            .get(&subaccount_id)
+            .map(|account| account.positions.clone())
+            .unwrap_or_default();
+        for (market_id, market) in &self.markets {
+            if let Some(position) = market.positions.get(&subaccount_id) {
+                positions.insert(*market_id, position.clone());
+            }
+        }
+        positions
+    }
+}
+
+/// Everything a shard holds for one market: what [`MarketTransfer::state`] carries to another
+/// shard and what a snapshot keeps per market. Order owners, GTD expiries and the reduce-only
+/// index are derived from the orders again when the market is installed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarketExport {
+    /// `None` only in snapshots from before configs were kept, which restore with the configured
+    /// market.
+    #[serde(with = "crate::config::json_string")]
+    pub config: Option<MarketConfig>,
+    /// Book orders in their original time priority.
+    pub orders: Vec<OrderSnapshot>,
+    /// Orders collected for the open batch auction.
+    pub batch_pending: Vec<IncomingOrder>,
+    /// Conditional orders waiting for their trigger.
+    pub conditional: Vec<(OrderId, NewOrder)>,
+    pub positions: BTreeMap<SubaccountId, Position>,
+    pub mark_price: Option<PriceTicks>,
+    pub funding_index: Option<i64>,
+    pub allowlist: Option<BTreeSet<SubaccountId>>,
+    pub prices: ReferencePrices,
+    pub next_clear_at: Option<u64>,
+    pub auction_round: u64,
+    pub nonce_watermarks: BTreeMap<SubaccountId, u64>,
+    /// Marks behind the realized volatility, oldest first.
+    pub volatility_marks: Vec<PriceTicks>,
+    /// Volatility-scaled initial margin in force, if any.
+    pub initial_margin_bps: Option<u64>,
+    /// Latest quote of each oracle source.
+    pub oracle_quotes: BTreeMap<String, OracleQuote>,
+    pub next_funding_at: Option<u64>,
+    pub next_margin_at: Option<u64>,
 }
 
-struct MarketState {
+pub struct MarketState {
     config: MarketConfig,
     book: OrderBook,
     batch: BatchAuction,
-    pending: VecDeque<IncomingOrder>,
     open_orders_by_subaccount: HashMap<u64, u64>,
+    prices: ReferencePrices,
+    /// Accepted conditional orders waiting for their trigger, indexed by `triggers`.
+    conditional: BTreeMap<OrderId, NewOrder>,
+    triggers: TriggerIndex,
+    /// Earliest clock value at which the next indicative auction may be published.
+    next_indicative_at: u64,
+    /// When the open batch auction clears; `None` while no auction is collecting.
+    next_clear_at: Option<u64>,
+    /// Number of auctions cleared so far, feeding the clearing jitter.
+    auction_round: u64,
+    obligations: Option<ObligationTracker>,
+    /// Highest nonce each subaccount has used in this market; see [`MarketState::consume_nonce`].
+    nonce_watermarks: BTreeMap<SubaccountId, u64>,
+    /// Holders ranked for auto-deleveraging; rebuilt from positions rather than snapshotted.
+    adl: AdlQueue,
+    /// Latest quote of each oracle source, for markets with an oracle config.
+    oracle_quotes: BTreeMap<String, OracleQuote>,
+    /// Recent marks, for markets whose price band or initial margin follows realized volatility.
+    volatility: Option<RealizedVolatility>,
+    /// When the volatility-scaled initial margin is next recomputed; set by the first input after
+    /// the market has a dynamic margin config.
+    next_margin_at: Option<u64>,
+    /// When scheduled funding next settles; set by the first input after the market has a
+    /// funding config.
+    next_funding_at: Option<u64>,
+}
+
+/// Latest reference prices a conditional order can trigger on; `None` until first observed.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ReferencePrices {
+    pub mark: Option<PriceTicks>,
+    pub index: Option<PriceTicks>,
+    pub last_trade: Option<PriceTicks>,
+}
+
+impl ReferencePrices {
+    fn get(&self, source: TriggerSource) -> Option<PriceTicks> {
+        match source {
+            TriggerSource::Mark => self.mark,
+            TriggerSource::Index => self.index,
+            TriggerSource::LastTrade => self.last_trade,
+        }
+    }
 }
 
 impl MarketState {
+    fn new(config: MarketConfig) -> Self {
+        let obligations = config.maker_obligation.clone().map(ObligationTracker::new);
+        let volatility = RealizedVolatility::capacity_for(&config).map(RealizedVolatility::new);
+        Self {
+            book: OrderBook::with_layout(config.order_capacity, config.book_layout),
+            config,
+            batch: BatchAuction::default(),
+            open_orders_by_subaccount: HashMap::new(),
+            prices: ReferencePrices::default(),
+            conditional: BTreeMap::new(),
+            triggers: TriggerIndex::new(),
+            next_indicative_at: 0,
+            next_clear_at: None,
+            auction_round: 0,
+            obligations,
+            nonce_watermarks: BTreeMap::new(),
+            adl: AdlQueue::new(),
+            oracle_quotes: BTreeMap::new(),
+            volatility,
+            next_margin_at: None,
+            next_funding_at: None,
+        }
+    }
+
+    /// The band this market currently accepts orders in, when it differs from `price_band_bps`.
+    fn price_band(&self) -> Option<u64> {
+        let config = self.config.dynamic_band.as_ref()?;
+        let vol_bps = self.volatility.as_ref().and_then(|volatility| volatility.vol_bps(config.window));
+        Some(volatility::band_bps(config, self.config.price_band_bps, vol_bps))
+    }
+
+    /// Marks `nonce` used by `subaccount_id`, or returns false if it is not above the highest
+    /// nonce the subaccount used before. Nonce 0 is never tracked.
+    fn consume_nonce(&mut self, subaccount_id: SubaccountId, nonce: u64) -> bool {
+        if nonce == 0 {
+            return true;
+        }
+        let watermark = self.nonce_watermarks.entry(subaccount_id).or_insert(0);
+        if nonce <= *watermark {
+            return false;
+        }
+        *watermark = nonce;
+        true
+    }
+
     fn open_orders_for_subaccount(&self, subaccount_id: u64) -> u64 {
         self.open_orders_by_subaccount
             .get(&subaccount_id)
@@ -63,164 +284,1375 @@ impl MarketState {
     }
 }
 
+/// Used until the router supplies a window derived from the bus redelivery settings.
+pub const DEFAULT_DEDUPE_WINDOW_SECS: u64 = 300;
+pub const DEFAULT_DEDUPE_MAX_ENTRIES: usize = 1_000_000;
+
 pub struct EngineShard {
     pub shard_id: usize,
     pub engine_seq: u64,
+    /// Nanosecond stamp of the input being applied, carried by every output it produces.
+    ts_ns: u64,
     pub next_order_id: u64,
+    /// Id the next fill gets; see [`first_trade_id`].
+    pub next_trade_id: u64,
     pub markets: HashMap<MarketId, MarketState>,
     pub risk: RiskEngine,
-    pub wal: Wal,
-    pub dedupe: LruCache<String, ()>,
+    /// Input log: the only stream replay and recovery read.
+    pub wal: Box<dyn EventLog>,
+    /// Optional journal of every output, for audit and downstream consumers; never replayed.
+    pub journal: Option<Box<dyn EventLog>>,
+    /// Per-subaccount trade history served to queries; `None` keeps none.
+    pub trades: Option<TradeStore>,
+    /// Trades of the current input, recorded once it has been fully applied.
+    pending_trades: Vec<Trade>,
+    pub dedupe: DedupeWindow,
+    /// Recently used `(subaccount, nonce)` pairs, so one signed nonce cannot back two requests
+    /// with different request ids, even in different markets of this shard.
+    pub nonce_dedupe: DedupeWindow,
     pub order_owners: HashMap<OrderId, (u64, Side)>,
+    /// Resting reduce-only orders per (subaccount, market), oldest first. Ids of orders that have
+    /// since left the book are pruned lazily by `enforce_reduce_only`.
+    pub reduce_only_orders: HashMap<(SubaccountId, MarketId), Vec<OrderId>>,
+    /// Resting good-til-date orders keyed by `(expires_at, order_id)` so the sweep at the start of
+    /// every event only touches orders that are due.
+    pub expiries: BTreeMap<(u64, OrderId), MarketId>,
+    /// Seed for batch-clearing jitter, set by a `ClearingSeed` input.
+    pub clearing_seed: u64,
+    pub accounts: AccountHierarchy,
+    /// Allowlists of restricted markets; markets without an entry accept every subaccount.
+    pub market_allowlists: HashMap<MarketId, BTreeSet<SubaccountId>>,
+    /// Order-signing keys; orders from these subaccounts must carry a valid signature.
+    pub signing_keys: HashMap<SubaccountId, ed25519_dalek::VerifyingKey>,
+    /// Also reject orders from subaccounts without a signing key.
+    pub require_signatures: bool,
+    /// Checked by [`admit`](Self::admit); `None` admits every order.
+    pub latency_budget: Option<LatencyBudgetConfig>,
+    /// Subaccounts charged their own fees instead of each market's.
+    pub fee_tiers: HashMap<SubaccountId, FeeTier>,
+    /// Receives liquidation fees; `None` charges none.
+    pub insurance_fund: Option<SubaccountId>,
+    /// Periodic full-depth publications; `None` publishes none.
+    pub book_snapshots: Option<BookSnapshotConfig>,
+    /// Per market, when the next depth snapshot is due and the book deltas emitted since the last.
+    depth_schedule: HashMap<MarketId, (u64, u64)>,
+    /// Buffer above maintenance margin within which [`MarginWarning`]s are published; `None`
+    /// publishes none.
+    pub margin_warning_bps: Option<u64>,
 }
 
 impl EngineShard {
-    pub fn new(shard_id: usize, markets: Vec<MarketConfig>, wal: Wal, mut risk: RiskEngine) -> Self {
-        let mut market_state = HashMap::new();
-        for market in markets {
-            risk.update_mark(market.market_id, market.tick_size);
-            market_state.insert(
-                market.market_id,
-                MarketState {
-                    config: market,
-                    book: OrderBook::new(),
-                    batch: BatchAuction::default(),
-                    pending: VecDeque::new(),
-                    open_orders_by_subaccount: HashMap::new(),
-                },
-            );
-        }
-        Self {
+    pub fn new(shard_id: usize, markets: Vec<MarketConfig>, wal: impl EventLog + 'static, risk: RiskEngine) -> Self {
+        let mut shard = Self {
             shard_id,
             engine_seq: 0,
-            next_order_id: 1,
-            markets: market_state,
+            ts_ns: 0,
+            next_order_id: first_order_id(shard_id),
+            next_trade_id: first_trade_id(shard_id),
+            markets: HashMap::new(),
             risk,
-            wal,
-            dedupe: LruCache::new(std::num::NonZeroUsize::new(10_000).unwrap_or_else(|| std::num::NonZeroUsize::new(1).unwrap())),
+            wal: Box::new(wal),
+            journal: None,
+            trades: None,
+            pending_trades: Vec::new(),
+            dedupe: DedupeWindow::new(DEFAULT_DEDUPE_WINDOW_SECS, DEFAULT_DEDUPE_MAX_ENTRIES),
+            nonce_dedupe: DedupeWindow::new(DEFAULT_DEDUPE_WINDOW_SECS, DEFAULT_DEDUPE_MAX_ENTRIES).keyed_by("nonce"),
             order_owners: HashMap::new(),
+            reduce_only_orders: HashMap::new(),
+            expiries: BTreeMap::new(),
+            clearing_seed: 0,
+            accounts: AccountHierarchy::default(),
+            market_allowlists: HashMap::new(),
+            signing_keys: HashMap::new(),
+            require_signatures: false,
+            latency_budget: None,
+            fee_tiers: HashMap::new(),
+            insurance_fund: None,
+            book_snapshots: None,
+            depth_schedule: HashMap::new(),
+            margin_warning_bps: None,
+        };
+        for market in markets {
+            shard.add_market(market);
         }
+        shard
     }
 
-    pub fn snapshot(&self) -> EngineState {
-        let mut orderbooks = HashMap::new();
-        for (market_id, state) in &self.markets {
-            let orders = state
+    /// Adds an empty market, marked at one tick until the first price update.
+    fn add_market(&mut self, config: MarketConfig) {
+        self.risk.update_mark(config.market_id, config.tick_size);
+        let market = MarketState::new(config);
+        self.risk.update_price_band(market.config.market_id, market.price_band());
+        self.markets.insert(market.config.market_id, market);
+    }
+
+    /// Sets the request-id window; the nonce window is resized to match.
+    pub fn with_dedupe(mut self, dedupe: DedupeWindow) -> Self {
+        self.nonce_dedupe = DedupeWindow::new(dedupe.window(), dedupe.max_entries())
+            .keyed_by("nonce")
+            .for_shard(self.shard_id);
+        self.dedupe = dedupe;
+        self
+    }
+
+    pub fn with_journal(mut self, journal: impl EventLog + 'static) -> Self {
+        self.journal = Some(Box::new(journal));
+        self
+    }
+
+    pub fn with_trade_history(mut self, trades: TradeStore) -> Self {
+        self.trades = Some(trades);
+        self
+    }
+
+    pub fn with_required_signatures(mut self, require_signatures: bool) -> Self {
+        self.require_signatures = require_signatures;
+        self
+    }
+
+    pub fn with_latency_budget(mut self, latency_budget: Option<LatencyBudgetConfig>) -> Self {
+        self.latency_budget = latency_budget;
+        self
+    }
+
+    pub fn with_insurance_fund(mut self, insurance_fund: Option<SubaccountId>) -> Self {
+        self.insurance_fund = insurance_fund;
+        self
+    }
+
+    pub fn with_book_snapshots(mut self, book_snapshots: Option<BookSnapshotConfig>) -> Self {
+        self.book_snapshots = book_snapshots;
+        self
+    }
+
+    pub fn with_margin_warnings(mut self, margin_warning_bps: Option<u64>) -> Self {
+        self.margin_warning_bps = margin_warning_bps;
+        self
+    }
+
+    /// Aggregate margin utilization and account health of this shard. A subaccount is near
+    /// maintenance when its equity is below its maintenance margin raised by `margin_warning_bps`.
+    pub fn risk_metrics(&self, margin_warning_bps: u64) -> RiskMetrics {
+        let mut metrics = RiskMetrics::default();
+        for market in self.markets.values() {
+            let margin_bps = i128::from(self.risk.initial_margin_bps(&market.config));
+            let margin: i128 = market
                 .book
                 .order_views()
-                .into_iter()
-                .map(|order| OrderSnapshot {
-                    order_id: order.order_id,
-                    subaccount_id: order.subaccount_id,
-                    side: order.side,
-                    price_ticks: order.price_ticks,
-                    remaining: order.remaining,
-                    ingress_seq: order.ingress_seq,
+                .iter()
+                .map(|order| i128::from(order.price_ticks) * i128::from(order.remaining) * margin_bps / 10_000)
+                .sum();
+            metrics.open_order_margin = metrics.open_order_margin.saturating_add(margin as i64);
+        }
+
+        let mut open_interest = BTreeMap::<MarketId, u128>::new();
+        for account in self.risk.state.subaccounts.values() {
+            metrics.total_collateral = metrics.total_collateral.saturating_add(account.collateral);
+            for (market_id, position) in &account.positions {
+                if position.size > 0 {
+                    *open_interest.entry(*market_id).or_default() += position.size as u128;
+                }
+            }
+        }
+        for (subaccount_id, account) in &self.risk.state.subaccounts {
+            for (market_id, position) in &account.positions {
+                if let Some(&interest) = open_interest.get(market_id) {
+                    let share = (u128::from(position.size.unsigned_abs()) * 10_000 / interest) as u64;
+                    metrics.max_position_concentration_bps = metrics.max_position_concentration_bps.max(share);
+                }
+            }
+            let maintenance = self.maintenance_margin(*subaccount_id);
+            if maintenance > 0 {
+                let threshold = maintenance * (10_000 + i128::from(margin_warning_bps)) / 10_000;
+                if i128::from(self.risk.equity(*subaccount_id)) < threshold {
+                    metrics.accounts_near_maintenance += 1;
+                }
+            }
+        }
+        metrics
+    }
+
+    /// Collateral the subaccount could take out of this shard without falling under initial
+    /// margin: equity less the initial margin of its positions at mark and of its resting and
+    /// queued orders at their limit prices. Negative when it is already under-margined.
+    pub fn free_collateral(&self, subaccount_id: SubaccountId) -> i64 {
+        let free = i128::from(self.risk.equity(subaccount_id)) - self.initial_margin(subaccount_id);
+        free.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+    }
+
+    /// The subaccount's margin picture on this shard as of the last input, for an account equity
+    /// query; the caller fills in the request id.
+    pub fn account_equity(&self, subaccount_id: SubaccountId) -> AccountEquity {
+        let balance = self.risk.state.subaccounts.get(&subaccount_id).map_or(0, |account| account.collateral);
+        let equity = self.risk.equity(subaccount_id);
+        let clamp = |margin: i128| margin.min(i128::from(i64::MAX)) as i64;
+        AccountEquity {
+            request_id: String::new(),
+            shard_id: self.shard_id,
+            subaccount_id,
+            engine_seq: self.engine_seq,
+            balance,
+            unrealized_pnl: equity - balance,
+            equity,
+            initial_margin: clamp(self.initial_margin(subaccount_id)),
+            maintenance_margin: clamp(self.maintenance_margin(subaccount_id)),
+            free_collateral: self.free_collateral(subaccount_id),
+            error: None,
+        }
+    }
+
+    /// Initial margin of the subaccount's positions in this shard's markets at mark and of its
+    /// resting and queued orders at their limit prices.
+    fn initial_margin(&self, subaccount_id: SubaccountId) -> i128 {
+        let mut required = 0i128;
+        for market in self.markets.values() {
+            let margin_bps = i128::from(self.risk.initial_margin_bps(&market.config));
+            let position = self.risk.position_size(subaccount_id, market.config.market_id);
+            let mark = self.risk.state.mark_prices.get(&market.config.market_id).copied().unwrap_or_default();
+            required += i128::from(position.unsigned_abs()) * i128::from(mark) * margin_bps / 10_000;
+            let resting = market
+                .book
+                .subaccount_orders(subaccount_id)
+                .map(|order| (order.price_ticks, order.remaining));
+            let queued = market
+                .batch
+                .pending
+                .iter()
+                .filter(|order| order.subaccount_id == subaccount_id)
+                .map(|order| (order.price_ticks, order.qty));
+            required += resting
+                .chain(queued)
+                .map(|(price_ticks, qty)| i128::from(price_ticks) * i128::from(qty) * margin_bps / 10_000)
+                .sum::<i128>();
+        }
+        required
+    }
+
+    /// Maintenance margin of every position the subaccount holds in this shard's markets, at mark.
+    fn maintenance_margin(&self, subaccount_id: SubaccountId) -> i128 {
+        let Some(account) = self.risk.state.subaccounts.get(&subaccount_id) else {
+            return 0;
+        };
+        let mut maintenance = 0i128;
+        for (market_id, position) in &account.positions {
+            let Some(market) = self.markets.get(market_id) else {
+                continue;
+            };
+            let mark = self.risk.state.mark_prices.get(market_id).copied().unwrap_or(position.entry_price);
+            maintenance += i128::from(position.size.unsigned_abs())
+                * i128::from(mark)
+                * i128::from(market.config.maintenance_margin_bps)
+                / 10_000;
+        }
+        maintenance
+    }
+
+    /// The subaccount's current position in `market_id`, valued at mark, for its private subject.
+    fn position_update(&self, market_id: MarketId, subaccount_id: SubaccountId, ts: u64) -> EventEnvelope {
+        let (size, entry_price) = self
+            .risk
+            .state
+            .subaccounts
+            .get(&subaccount_id)
+            .and_then(|account| account.positions.get(&market_id))
+            .map_or((0, 0), |position| (position.size, position.entry_price));
+        let mark_price = self.risk.state.mark_prices.get(&market_id).copied().unwrap_or(entry_price);
+        let margin = |bps: u64| {
+            let margin = i128::from(size.unsigned_abs()) * i128::from(mark_price) * i128::from(bps) / 10_000;
+            margin.min(i128::from(i64::MAX)) as i64
+        };
+        let (initial_margin, maintenance_margin) = self
+            .markets
+            .get(&market_id)
+            .map_or((0, 0), |market| {
+                (margin(self.risk.initial_margin_bps(&market.config)), margin(market.config.maintenance_margin_bps))
+            });
+        EventEnvelope {
+            shard_id: self.shard_id,
+            engine_seq: self.engine_seq,
+            event: Event::PositionUpdate(PositionUpdate {
+                market_id,
+                subaccount_id,
+                size,
+                entry_price,
+                mark_price,
+                unrealized_pnl: size.saturating_mul(mark_price as i64 - entry_price as i64),
+                initial_margin,
+                maintenance_margin,
+                engine_seq: self.engine_seq,
+                ts,
+            }),
+            ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+
+    /// A [`MarginWarning`] per subaccount near maintenance among those whose positions `outputs`
+    /// updated or that hold a position in the `repriced` market, in subaccount order.
+    fn margin_warnings(&self, outputs: &[EventEnvelope], repriced: Option<MarketId>, ts: u64) -> Vec<EventEnvelope> {
+        let Some(warning_bps) = self.margin_warning_bps else {
+            return Vec::new();
+        };
+        let mut candidates: BTreeSet<SubaccountId> = outputs
+            .iter()
+            .filter_map(|output| match &output.event {
+                Event::PositionUpdate(update) => Some(update.subaccount_id),
+                _ => None,
+            })
+            .collect();
+        if let Some(market_id) = repriced {
+            candidates.extend(
+                self.risk
+                    .state
+                    .subaccounts
+                    .iter()
+                    .filter(|(_, account)| account.positions.get(&market_id).is_some_and(|position| position.size != 0))
+                    .map(|(subaccount_id, _)| *subaccount_id),
+            );
+        }
+        candidates
+            .into_iter()
+            .filter_map(|subaccount_id| {
+                let maintenance = self.maintenance_margin(subaccount_id);
+                let equity = self.risk.equity(subaccount_id);
+                let threshold = maintenance * (10_000 + i128::from(warning_bps)) / 10_000;
+                (maintenance > 0 && i128::from(equity) < threshold).then(|| EventEnvelope {
+                    shard_id: self.shard_id,
+                    engine_seq: self.engine_seq,
+                    event: Event::MarginWarning(MarginWarning {
+                        subaccount_id,
+                        equity,
+                        maintenance_margin: maintenance.min(i128::from(i64::MAX)) as i64,
+                        warning_bps,
+                        engine_seq: self.engine_seq,
+                        ts,
+                    }),
+                    ts,
+                    ts_ns: self.ts_ns,
+                    schema_version: SCHEMA_VERSION,
                 })
-                .collect();
-            orderbooks.insert(*market_id, orders);
+            })
+            .collect()
+    }
+
+    /// Equity and maintenance margin of a subaccount whose equity is below its maintenance margin.
+    /// Subaccounts with no maintenance requirement are never considered underwater.
+    fn below_maintenance(&self, subaccount_id: SubaccountId) -> Option<(i64, i64)> {
+        let maintenance = self.maintenance_margin(subaccount_id);
+        let equity = self.risk.equity(subaccount_id);
+        (maintenance > 0 && i128::from(equity) < maintenance).then(|| (equity, maintenance.min(i128::from(i64::MAX)) as i64))
+    }
+
+    /// Order book of `market_id`, if the market is on this shard.
+    pub fn book(&self, market_id: MarketId) -> Option<&OrderBook> {
+        self.markets.get(&market_id).map(|market| &market.book)
+    }
+
+    pub fn snapshot(&self) -> EngineState {
+        let mut market_ids: Vec<MarketId> = self.markets.keys().copied().collect();
+        market_ids.sort_unstable();
+        let markets: BTreeMap<MarketId, MarketExport> = market_ids
+            .into_iter()
+            .filter_map(|market_id| Some((market_id, self.export_market(market_id)?)))
+            .collect();
+        // Positions and prices of the exported markets travel in their exports.
+        let mut risk_state = self.risk.state.clone();
+        for market_id in markets.keys() {
+            for account in risk_state.subaccounts.values_mut() {
+                account.positions.remove(market_id);
+            }
+            risk_state.mark_prices.remove(market_id);
+            risk_state.funding_indices.remove(market_id);
         }
         EngineState {
             shard_id: self.shard_id,
             engine_seq: self.engine_seq,
             next_order_id: self.next_order_id,
-            orderbooks,
-            risk_state: self.risk.state.clone(),
+            markets,
+            risk_state,
+            next_trade_id: self.next_trade_id,
+            clearing_seed: self.clearing_seed,
+            accounts: self.accounts.configs(),
         }
     }
 
-    pub fn restore(state: EngineState, markets: Vec<MarketConfig>, wal: Wal, risk: RiskEngine) -> Self {
-        let mut shard = EngineShard::new(state.shard_id, markets, wal, risk.clone());
+    /// Rebuilds a shard from a snapshot. Each market is installed as an imported one is, see
+    /// [`install_market`](Self::install_market), under the config it was snapshotted with; the
+    /// config in `markets` is used for snapshots that predate it. Markets in `markets` that the
+    /// snapshot lacks start empty.
+    ///
+    /// Fails, rather than start from it, on a state whose orders are inconsistent: an order id
+    /// listed twice, an order with nothing left, or an id from this shard's range that it has not
+    /// issued yet.
+    pub fn restore(state: EngineState, markets: Vec<MarketConfig>, wal: impl EventLog + 'static, risk: RiskEngine) -> anyhow::Result<Self> {
+        let own_ids = first_order_id(state.shard_id)..first_order_id(state.shard_id + 1);
+        let mut order_ids = BTreeSet::new();
+        for market in state.markets.values() {
+            let book = market.orders.iter().map(|order| (order.order_id, order.remaining));
+            let batch = market.batch_pending.iter().map(|order| (order.order_id, order.qty));
+            let conditional = market.conditional.iter().map(|(order_id, order)| (*order_id, order.qty));
+            for (order_id, remaining) in book.chain(batch).chain(conditional) {
+                anyhow::ensure!(order_ids.insert(order_id), "order {order_id} is listed twice");
+                anyhow::ensure!(remaining > 0, "order {order_id} has no remaining quantity");
+                anyhow::ensure!(
+                    !own_ids.contains(&order_id) || order_id < state.next_order_id,
+                    "order {order_id} is not below the next order id {}",
+                    state.next_order_id
+                );
+            }
+        }
+
+        let mut configs: BTreeMap<MarketId, MarketConfig> = markets.into_iter().map(|market| (market.market_id, market)).collect();
+        let mut shard = EngineShard::new(state.shard_id, Vec::new(), wal, risk);
         shard.engine_seq = state.engine_seq;
         shard.next_order_id = state.next_order_id;
+        shard.next_trade_id = state.next_trade_id.max(first_trade_id(state.shard_id));
         shard.risk.state = state.risk_state;
-        for (market_id, orders) in state.orderbooks {
-            if let Some(market_state) = shard.markets.get_mut(&market_id) {
-                for order in orders {
-                    let incoming = IncomingOrder {
-                        order_id: order.order_id,
-                        subaccount_id: order.subaccount_id,
-                        side: order.side,
-                        order_type: crate::models::OrderType::Limit,
-                        tif: TimeInForce::Gtc,
-                        price_ticks: order.price_ticks,
-                        qty: order.remaining,
-                        reduce_only: false,
-                        ingress_seq: order.ingress_seq,
-                    };
-                    market_state.book.place_order(incoming, 0);
-                    market_state.track_open_order_add(order.subaccount_id);
-                    shard.order_owners.insert(order.order_id, (order.subaccount_id, order.side));
+        shard.clearing_seed = state.clearing_seed;
+        shard.accounts = AccountHierarchy::new(state.accounts);
+        for (market_id, mut export) in state.markets {
+            let configured = configs.remove(&market_id);
+            if let Some(config) = export.config.take().or(configured) {
+                shard.install_market(config, export, &BTreeSet::new());
+            }
+        }
+        for config in configs.into_values() {
+            shard.add_market(config);
+        }
+        Ok(shard)
+    }
+
+    pub fn has_market(&self, market_id: MarketId) -> bool {
+        self.markets.contains_key(&market_id)
+    }
+
+    /// The first `limit` holders on `side` of the market's ADL ranking (`Buy` for longs; 0 for
+    /// all), best candidate first. Empty for markets this shard does not hold.
+    pub fn adl_ranking(&self, market_id: MarketId, side: Side, limit: usize) -> Vec<AdlRank> {
+        let Some(market) = self.markets.get(&market_id) else {
+            return Vec::new();
+        };
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        market.adl.ranked(side).take(limit).collect()
+    }
+
+    /// Side and ADL ranking entry of the subaccount's position in the market, if it has one.
+    pub fn adl_rank(&self, market_id: MarketId, subaccount_id: SubaccountId) -> Option<(Side, AdlRank)> {
+        self.markets.get(&market_id)?.adl.rank_of(subaccount_id)
+    }
+
+    /// Rescores the subaccount's position in `market_id` for ADL.
+    fn refresh_adl(&mut self, market_id: MarketId, subaccount_id: SubaccountId) {
+        let position = self
+            .risk
+            .state
+            .subaccounts
+            .get(&subaccount_id)
+            .and_then(|account| account.positions.get(&market_id));
+        let entry = position.and_then(|position| {
+            let mark = self.risk.state.mark_prices.get(&market_id).copied().unwrap_or(position.entry_price);
+            let side = if position.size > 0 { Side::Buy } else { Side::Sell };
+            adl_score(position.size, position.entry_price, mark, self.risk.equity(subaccount_id))
+                .map(|score| (side, score))
+        });
+        if let Some(market) = self.markets.get_mut(&market_id) {
+            market.adl.update(subaccount_id, entry);
+        }
+    }
+
+    /// Rescores every position in `market_id`, e.g. after the mark moved.
+    fn refresh_adl_market(&mut self, market_id: MarketId) {
+        let Some(market) = self.markets.get_mut(&market_id) else {
+            return;
+        };
+        market.adl.clear();
+        let holders: Vec<SubaccountId> = self
+            .risk
+            .state
+            .subaccounts
+            .iter()
+            .filter(|(_, account)| account.positions.contains_key(&market_id))
+            .map(|(subaccount_id, _)| *subaccount_id)
+            .collect();
+        for subaccount_id in holders {
+            self.refresh_adl(market_id, subaccount_id);
+        }
+    }
+
+    /// Raises nonce watermarks to every order nonce in `log`, e.g. the WAL of a previous run, so a
+    /// restarted shard rejects orders that were already submitted. Only markets this shard holds
+    /// are considered. Nonces still inside the dedupe window are re-armed there too.
+    pub fn recover_nonces(&mut self, log: &[EventEnvelope]) {
+        for envelope in log {
+            let Event::NewOrder(order) = &envelope.event else {
+                continue;
+            };
+            if let Some(market_state) = self.markets.get_mut(&order.market_id) {
+                market_state.consume_nonce(order.subaccount_id, order.nonce);
+                if order.nonce != 0 {
+                    self.nonce_dedupe.check_and_insert(nonce_key(order.subaccount_id, order.nonce), envelope.ts);
                 }
             }
         }
-        shard
     }
 
-    pub fn upsert_market(&mut self, market: MarketConfig) {
-        self.risk.update_mark(market.market_id, market.tick_size);
-        match self.markets.get_mut(&market.market_id) {
+    /// Re-applies the inputs this shard wrote to `log` after its engine_seq, e.g. the WAL tail after
+    /// the snapshot it was restored from. They are already logged and their outputs already
+    /// journaled and published, so neither is written again.
+    pub fn replay_log(&mut self, log: &[EventEnvelope]) {
+        let wal = std::mem::replace(&mut self.wal, Box::new(MemoryLog::new()));
+        let journal = self.journal.take();
+        let trades = self.trades.take();
+        for envelope in log {
+            if envelope.shard_id != self.shard_id || !envelope.event.is_input() || envelope.engine_seq <= self.engine_seq {
+                continue;
+            }
+            let _ = self.handle_event_at(envelope.event.clone(), envelope.ts_ns);
+        }
+        self.wal = wal;
+        self.journal = journal;
+        self.trades = trades;
+    }
+
+    /// Continues `engine_seq` after the last record this shard wrote to `log`, so sequence numbers
+    /// keep increasing across restarts and downstream consumers can resume from one.
+    pub fn recover_engine_seq(&mut self, log: &[EventEnvelope]) {
+        let logged = log
+            .iter()
+            .filter(|envelope| envelope.shard_id == self.shard_id)
+            .map(|envelope| envelope.engine_seq)
+            .max();
+        if let Some(logged) = logged {
+            self.engine_seq = self.engine_seq.max(logged);
+        }
+    }
+
+    /// Continues trade ids after the last fill this shard wrote to `log`, e.g. the output journal
+    /// of a previous run, so ids stay unique across restarts.
+    pub fn recover_trade_id(&mut self, log: &[EventEnvelope]) {
+        let logged = log
+            .iter()
+            .filter(|envelope| envelope.shard_id == self.shard_id)
+            .filter_map(|envelope| match &envelope.event {
+                Event::Fill(fill) => Some(fill.trade_id),
+                _ => None,
+            })
+            .max();
+        if let Some(logged) = logged {
+            self.next_trade_id = self.next_trade_id.max(logged + 1);
+        }
+    }
+
+    /// Logs and applies a new or updated market config as an [`Event::MarketUpdate`] input, so
+    /// replay reproduces it and any orders it cancels.
+    pub fn upsert_market(&mut self, market: MarketConfig, ts: u64) -> anyhow::Result<Vec<EventEnvelope>> {
+        self.handle_event(Event::MarketUpdate(Box::new(market)), ts)
+    }
+
+    /// Applies a new or updated market config. When an update changes `tick_size`, resting and
+    /// parked orders whose price is no longer a multiple of it are cancelled.
+    fn on_market_update(&mut self, market: MarketConfig, ts: u64) -> Vec<EventEnvelope> {
+        let market_id = market.market_id;
+        let tick_changed = match self.markets.get_mut(&market_id) {
             Some(existing) => {
+                if existing.obligations.as_ref().map(ObligationTracker::obligation) != market.maker_obligation.as_ref() {
+                    existing.obligations = market.maker_obligation.clone().map(ObligationTracker::new);
+                }
+                let capacity = RealizedVolatility::capacity_for(&market);
+                if existing.volatility.as_ref().map(RealizedVolatility::capacity) != capacity {
+                    existing.volatility = capacity.map(RealizedVolatility::new);
+                }
+                if market.dynamic_margin.is_none() {
+                    self.risk.update_initial_margin(market_id, None);
+                }
+                let changed = existing.config.tick_size != market.tick_size;
                 existing.config = market;
+                self.risk.update_price_band(market_id, existing.price_band());
+                changed
             }
             None => {
-                self.markets.insert(
-                    market.market_id,
-                    MarketState {
-                        config: market,
-                        book: OrderBook::new(),
-                        batch: BatchAuction::default(),
-                        pending: VecDeque::new(),
-                        open_orders_by_subaccount: HashMap::new(),
-                    },
-                );
+                // Only a new market is seeded at one tick; an updated one keeps its mark.
+                self.add_market(market);
+                false
             }
+        };
+        if !tick_changed {
+            return Vec::new();
         }
+        self.cancel_off_tick_orders(market_id, ts)
     }
 
-    #[instrument(skip(self))]
+    fn cancel_off_tick_orders(&mut self, market_id: MarketId, ts: u64) -> Vec<EventEnvelope> {
+        let Some(market) = self.markets.get_mut(&market_id) else {
+            return Vec::new();
+        };
+        let tick = market.config.tick_size.max(1);
+        let mut resting: Vec<_> = market
+            .book
+            .order_views()
+            .into_iter()
+            .filter(|order| order.price_ticks % tick != 0)
+            .map(|order| (order.order_id, order.subaccount_id, order.remaining))
+            .collect();
+        resting.sort_unstable();
+        let book_changed = !resting.is_empty();
+        let mut cancelled = Vec::new();
+        for (order_id, subaccount_id, remaining) in resting {
+            if market.book.cancel(order_id) {
+                self.order_owners.remove(&order_id);
+                market.track_open_order_remove(subaccount_id);
+                cancelled.push((order_id, subaccount_id, remaining));
+            }
+        }
+        let parked: Vec<OrderId> = market
+            .conditional
+            .iter()
+            .filter(|(_, order)| order.order_type != OrderType::Market && order.price_ticks % tick != 0)
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        for order_id in parked {
+            if let Some(order) = market.conditional.remove(&order_id) {
+                market.triggers.remove(order_id);
+                cancelled.push((order_id, order.subaccount_id, order.qty));
+            }
+        }
+
+        let mut events: Vec<_> = cancelled
+            .into_iter()
+            .map(|(order_id, subaccount_id, remaining)| {
+                self.order_update(market_id, order_id, subaccount_id, OrderUpdateStatus::Cancelled, remaining, ts)
+            })
+            .collect();
+        if book_changed {
+            let snapshot = self.markets[&market_id].book.snapshot(10);
+            events.push(self.book_delta_from_snapshot(market_id, snapshot, ts));
+        }
+        events
+    }
+
+    pub fn upsert_account(&mut self, account: AccountConfig) {
+        self.accounts.upsert(account);
+    }
+
+    pub fn upsert_permissions(&mut self, permissions: MarketPermissions) {
+        if permissions.restricted {
+            self.market_allowlists
+                .insert(permissions.market_id, permissions.allowed_subaccounts.into_iter().collect());
+        } else {
+            self.market_allowlists.remove(&permissions.market_id);
+        }
+    }
+
+    pub fn upsert_signing_key(&mut self, key: &SigningKeyConfig) -> anyhow::Result<()> {
+        if key.public_key.is_empty() {
+            self.signing_keys.remove(&key.subaccount_id);
+        } else {
+            self.signing_keys.insert(key.subaccount_id, signatures::parse_public_key(&key.public_key)?);
+        }
+        Ok(())
+    }
+
+    /// Applies a subaccount's bootstrap record: seeds collateral for the markets this shard owns
+    /// (only while it has no ledger entry for the subaccount), records the margin mode and fee tier
+    /// and registers the signing key if one is given.
+    fn provision_subaccount(&mut self, subaccount: SubaccountConfig) {
+        let subaccount_id = subaccount.subaccount_id;
+        let seeded = self.risk.state.subaccounts.contains_key(&subaccount_id);
+        let collateral: i64 = subaccount
+            .collateral
+            .iter()
+            .filter(|seed| self.markets.contains_key(&seed.market_id))
+            .map(|seed| seed.amount)
+            .sum();
+        let account = self.risk.ensure_subaccount(subaccount_id);
+        if !seeded {
+            account.collateral = collateral;
+        }
+        account.cross_margin = subaccount.cross_margin;
+        match subaccount.fee_tier {
+            Some(tier) => self.fee_tiers.insert(subaccount_id, tier),
+            None => self.fee_tiers.remove(&subaccount_id),
+        };
+        if let Some(public_key) = subaccount.public_key
+            && let Err(err) = self.upsert_signing_key(&SigningKeyConfig { subaccount_id, public_key })
+        {
+            warn!(subaccount_id, error = %err, "ignoring invalid signing key");
+        }
+    }
+
+    /// Maker or taker fee rate `subaccount_id` pays in `market`: its fee tier's, if it has one.
+    fn fee_bps(&self, market: &MarketConfig, subaccount_id: Option<SubaccountId>, maker: bool) -> i64 {
+        match subaccount_id.and_then(|subaccount_id| self.fee_tiers.get(&subaccount_id)) {
+            Some(tier) if maker => tier.maker_fee_bps,
+            Some(tier) => tier.taker_fee_bps,
+            None if maker => market.maker_fee_bps,
+            None => market.taker_fee_bps,
+        }
+    }
+
+    /// Rejects a new order whose signature does not verify. Call it before
+    /// [`handle_event`](Self::handle_event): a rejected order is never logged, and logged orders
+    /// are not re-verified on replay (the WAL does not keep signatures).
+    pub fn authenticate(&self, event: &Event, ts: u64) -> Option<EventEnvelope> {
+        let Event::NewOrder(order) = event else {
+            return None;
+        };
+        let authentic = match self.signing_keys.get(&order.subaccount_id) {
+            Some(key) => signatures::verify(key, order),
+            None => !self.require_signatures,
+        };
+        (!authentic).then(|| self.reject(order.request_id.clone(), Some(order.subaccount_id), RejectReason::InvalidSignature, ts))
+    }
+
+    /// Applies the latency budget to a new order that queued `queue_delay_ns` before reaching the
+    /// shard: returns its reject, or converts it to IOC in place. Call it before
+    /// [`handle_event`](Self::handle_event), like [`authenticate`](Self::authenticate): a rejected
+    /// order is never logged and a converted one is logged as IOC, so replay needs no delays.
+    pub fn admit(&self, event: &mut Event, queue_delay_ns: u64, ts: u64) -> Option<EventEnvelope> {
+        let budget = self.latency_budget?;
+        let Event::NewOrder(order) = event else {
+            return None;
+        };
+        if order.order_type == OrderType::PostOnly || order.trigger.is_some() {
+            return None;
+        }
+        if queue_delay_ns <= budget.max_queue_delay_ms.saturating_mul(1_000_000) {
+            return None;
+        }
+        match budget.action {
+            LatencyBudgetAction::Reject => {
+                metrics::counter!("latency_budget_exceeded_total", "shard" => self.shard_id.to_string(), "action" => "reject").increment(1);
+                Some(self.reject(order.request_id.clone(), Some(order.subaccount_id), RejectReason::QueueDelay, ts))
+            }
+            LatencyBudgetAction::Ioc => {
+                if order.tif.rests() {
+                    metrics::counter!("latency_budget_exceeded_total", "shard" => self.shard_id.to_string(), "action" => "ioc").increment(1);
+                    order.tif = TimeInForce::Ioc;
+                }
+                None
+            }
+        }
+    }
+
+    fn is_permitted(&self, market_id: MarketId, subaccount_id: SubaccountId) -> bool {
+        self.market_allowlists
+            .get(&market_id)
+            .is_none_or(|allowed| allowed.contains(&subaccount_id))
+    }
+
+    /// Like [`Self::handle_event_at`], stamping the input at the start of engine second `ts`.
     pub fn handle_event(&mut self, event: Event, ts: u64) -> anyhow::Result<Vec<EventEnvelope>> {
+        self.handle_event_at(event, ts.saturating_mul(NANOS_PER_SEC))
+    }
+
+    /// Logs and applies `event` stamped `ts_ns`, nanoseconds since the Unix epoch. Engine time is
+    /// the stamp in whole seconds, and every output carries the stamp itself.
+    #[instrument(skip(self))]
+    pub fn handle_event_at(&mut self, event: Event, ts_ns: u64) -> anyhow::Result<Vec<EventEnvelope>> {
+        let ts = ts_ns / NANOS_PER_SEC;
         self.engine_seq += 1;
+        self.ts_ns = ts_ns;
+        let repriced = match &event {
+            Event::PriceUpdate(update) => Some(update.market_id),
+            _ => None,
+        };
         let input = EventEnvelope {
             shard_id: self.shard_id,
             engine_seq: self.engine_seq,
             event: event.clone(),
             ts,
+            ts_ns,
+            schema_version: SCHEMA_VERSION,
         };
         self.wal.append(&input)?;
-        let outputs = match event {
+        self.pending_trades.clear();
+        for market in self.markets.values_mut() {
+            if let Some(tracker) = market.obligations.as_mut() {
+                tracker.advance(ts);
+            }
+        }
+        let mut outputs = self.expire_orders(ts);
+        outputs.extend(self.clear_auctions(ts));
+        outputs.extend(self.settle_funding(ts));
+        outputs.extend(self.rescale_margins(ts));
+        outputs.extend(match event {
             Event::NewOrder(order) => self.on_new_order(order, ts),
             Event::CancelOrder(cancel) => self.on_cancel(cancel, ts),
-            Event::PriceUpdate(update) => {
-                self.risk.update_mark(update.market_id, update.mark_price);
+            Event::BlockTrade(trade) => self.on_block_trade(trade, ts),
+            Event::MassCancel(cancel) => self.on_mass_cancel(cancel, ts),
+            Event::MigrateMarket(migrate) => self.on_migrate_market(migrate, ts),
+            Event::Transfer(transfer) => self.on_transfer(transfer, ts),
+            Event::MarketImport(transfer) => self.on_market_import(transfer, ts),
+            Event::RiskParamsUpdate(update) => self.on_risk_params_update(update, ts),
+            Event::MarketUpdate(market) => self.on_market_update(*market, ts),
+            Event::ProvisionSubaccount(subaccount) => {
+                self.provision_subaccount(subaccount);
                 Vec::new()
             }
+            Event::PriceUpdate(update) => self.on_price_update(update, ts),
             Event::FundingUpdate(update) => {
                 self.risk.update_funding(update.market_id, update.funding_index);
                 Vec::new()
             }
+            Event::ClearingSeed(seed) => {
+                self.clearing_seed = seed.seed;
+                Vec::new()
+            }
             _ => Vec::new(),
-        };
-        for output in &outputs {
-            self.wal.append(output)?;
+        });
+        outputs.extend(self.publish_indicatives(ts));
+        let snapshots = self.publish_depth_snapshots(&outputs, ts);
+        outputs.extend(snapshots);
+        let compliance = self.track_obligations(&outputs, ts);
+        outputs.extend(compliance);
+        let warnings = self.margin_warnings(&outputs, repriced, ts);
+        outputs.extend(warnings);
+        self.journal_outputs(&outputs)?;
+        if let Some(trades) = &mut self.trades {
+            trades.record(std::mem::take(&mut self.pending_trades))?;
         }
         Ok(outputs)
     }
 
-    fn on_new_order(&mut self, order: NewOrder, ts: u64) -> Vec<EventEnvelope> {
-        if self.dedupe.contains(&order.request_id) {
-            return Vec::new();
+    /// The subaccount's recorded trades after the `(since_ts, since_seq)` cursor, oldest first, and
+    /// whether more matched than `limit`; `None` if the shard keeps no trade history.
+    pub fn trade_history(
+        &self,
+        subaccount_id: SubaccountId,
+        since_ts: u64,
+        since_seq: u64,
+        limit: usize,
+    ) -> Option<(Vec<Trade>, bool)> {
+        self.trades
+            .as_ref()
+            .map(|trades| trades.query(subaccount_id, since_ts, since_seq, limit))
+    }
+
+    /// Queues each side of `fill` whose owner is known for the trade history, if the shard keeps one.
+    /// Envelopes `fill` followed by one private [`UserFill`] per known party, given as its
+    /// subaccount, side and position change, a [`PnlRealized`] per party whose position the fill
+    /// reduced and a private [`PositionUpdate`] per party; records both parties' trades.
+    fn fill_events(
+        &mut self,
+        fill: Fill,
+        maker: Option<(SubaccountId, Side, PositionChange)>,
+        taker: Option<(SubaccountId, Side, PositionChange)>,
+    ) -> Vec<EventEnvelope> {
+        let mut user_fills = Vec::with_capacity(2);
+        let mut realized = Vec::new();
+        for (owner, order_id, fee, remaining_qty, is_maker) in [
+            (maker, fill.maker_order_id, fill.maker_fee, fill.maker_remaining_qty, true),
+            (taker, fill.taker_order_id, fill.taker_fee, fill.taker_remaining_qty, false),
+        ] {
+            if let Some((subaccount_id, side, change)) = owner {
+                if change.closed_qty > 0 {
+                    realized.push(PnlRealized {
+                        market_id: fill.market_id,
+                        subaccount_id,
+                        trade_id: fill.trade_id,
+                        closed_qty: change.closed_qty,
+                        realized_pnl: change.realized_pnl,
+                        remaining_size: change.size,
+                        entry_price: change.entry_price,
+                        engine_seq: fill.engine_seq,
+                        ts: fill.ts,
+                    });
+                }
+                user_fills.push(UserFill {
+                    subaccount_id,
+                    trade_id: fill.trade_id,
+                    market_id: fill.market_id,
+                    order_id,
+                    side,
+                    price_ticks: fill.price_ticks,
+                    qty: fill.qty,
+                    fee,
+                    realized_pnl: change.realized_pnl,
+                    maker: is_maker,
+                    block_trade: fill.block_trade,
+                    remaining_qty,
+                    engine_seq: fill.engine_seq,
+                    ts: fill.ts,
+                });
+            }
         }
-        self.dedupe.put(order.request_id.clone(), ());
-        let Some(market_state) = self.markets.get(&order.market_id) else {
-            return vec![self.reject(order.request_id, "unknown market", ts)];
-        };
-        if let Err(reason) = self.validate_order(&order, market_state) {
-            return vec![self.reject(order.request_id, reason, ts)];
+        if self.trades.is_some() {
+            self.pending_trades.extend(user_fills.iter().map(|user_fill| Trade {
+                subaccount_id: user_fill.subaccount_id,
+                trade_id: user_fill.trade_id,
+                market_id: user_fill.market_id,
+                order_id: user_fill.order_id,
+                side: user_fill.side,
+                price_ticks: user_fill.price_ticks,
+                qty: user_fill.qty,
+                fee: user_fill.fee,
+                maker: user_fill.maker,
+                block_trade: user_fill.block_trade,
+                engine_seq: user_fill.engine_seq,
+                ts: user_fill.ts,
+            }));
+        }
+        let ts = fill.ts;
+        let positions: Vec<_> = user_fills
+            .iter()
+            .map(|user_fill| self.position_update(user_fill.market_id, user_fill.subaccount_id, ts))
+            .collect();
+        std::iter::once(Event::Fill(fill))
+            .chain(user_fills.into_iter().map(Event::UserFill))
+            .chain(realized.into_iter().map(Event::PnlRealized))
+            .map(|event| EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event,
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            })
+            .chain(positions)
+            .collect()
+    }
+
+    fn journal_outputs(&mut self, outputs: &[EventEnvelope]) -> anyhow::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            for output in outputs {
+                journal.append(output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_new_order(&mut self, mut order: NewOrder, ts: u64) -> Vec<EventEnvelope> {
+        if self.dedupe.check_and_insert(request_key(&order.request_id), ts) {
+            return Vec::new();
+        }
+        let Some(market_state) = self.markets.get_mut(&order.market_id) else {
+            return vec![self.reject(order.request_id, Some(order.subaccount_id), RejectReason::UnknownMarket, ts)];
+        };
+        // A nonce is spent even if the order is rejected below, so replaying it can never succeed.
+        if !market_state.consume_nonce(order.subaccount_id, order.nonce) {
+            return vec![self.reject(order.request_id, Some(order.subaccount_id), RejectReason::StaleNonce, ts)];
+        }
+        // Watermarks are per market; the window also catches the nonce reused in another market.
+        if order.nonce != 0 && self.nonce_dedupe.check_and_insert(nonce_key(order.subaccount_id, order.nonce), ts) {
+            return vec![self.reject(order.request_id, Some(order.subaccount_id), RejectReason::DuplicateNonce, ts)];
+        }
+        let market_state = &self.markets[&order.market_id];
+        if let Err(reason) = self.validate_order(&order, market_state) {
+            return vec![self.reject(order.request_id, Some(order.subaccount_id), reason, ts)];
+        }
+        if order.tif.expires_at().is_some_and(|expires_at| expires_at <= ts) {
+            return vec![self.reject(order.request_id, Some(order.subaccount_id), RejectReason::InvalidOrder, ts)];
         }
 
         let order_id = self.next_order_id;
         self.next_order_id += 1;
+        let mut events = vec![EventEnvelope {
+            shard_id: self.shard_id,
+            engine_seq: self.engine_seq,
+            event: Event::OrderAck(OrderAck {
+                request_id: std::mem::take(&mut order.request_id),
+                status: OrderStatus::Accepted,
+                reject_code: None,
+                reject_reason: None,
+                assigned_order_id: Some(order_id),
+                engine_seq: self.engine_seq,
+                ts,
+                filled_qty: 0,
+                remaining_qty: 0,
+                disposition: None,
+                subaccount_id: Some(order.subaccount_id),
+            }),
+            ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
+        }];
+
+        let market_id = order.market_id;
+        if let Some(trigger) = order.trigger {
+            let market = self.markets.get_mut(&market_id).expect("market exists");
+            let reference = market.prices.get(trigger.source);
+            if !reference.is_some_and(|price| trigger.is_triggered(order.side, price)) {
+                if let Some(expires_at) = order.tif.expires_at() {
+                    self.expiries.insert((expires_at, order_id), market_id);
+                }
+                set_ack_outcome(&mut events[0], 0, order.qty, OrderDisposition::Rested);
+                market.triggers.insert(order_id, order.side, trigger);
+                market.conditional.insert(order_id, order);
+                return events;
+            }
+        }
+        events.extend(self.execute_order(order, order_id, ts));
+        let (filled_qty, remaining_qty, disposition) = execution_outcome(&events, order_id);
+        set_ack_outcome(&mut events[0], filled_qty, remaining_qty, disposition);
+        events.extend(self.run_triggers(market_id, ts));
+        events
+    }
+
+    /// Applies new reference prices, then everything that depends on them: triggers, liquidations
+    /// and the ADL ranking. A market with an oracle config only records the update as its source's
+    /// quote and moves to the composite of all quotes, if enough sources pass the filters.
+    fn on_price_update(&mut self, update: PriceUpdate, ts: u64) -> Vec<EventEnvelope> {
+        let market_id = update.market_id;
+        let mut events = Vec::new();
+        let mut prices = (update.mark_price, update.index_price);
+        if let Some(market) = self.markets.get_mut(&market_id)
+            && let Some(config) = &market.config.oracle
+        {
+            if !config.sources.iter().any(|source| source.name == update.source) {
+                return events;
+            }
+            let quote = OracleQuote {
+                mark_price: update.mark_price,
+                index_price: update.index_price,
+                ts,
+            };
+            market.oracle_quotes.insert(update.source, quote);
+            let composite = oracle::composite(config, &market.oracle_quotes, ts);
+            let Some(composite_prices) = composite.prices else {
+                return events;
+            };
+            prices = composite_prices;
+            events.push(EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::IndexPrice(IndexPrice {
+                    market_id,
+                    mark_price: prices.0,
+                    index_price: prices.1,
+                    sources: composite.used,
+                    stale: composite.stale,
+                    outliers: composite.outliers,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            });
+        }
+        let (mark_price, index_price) = prices;
+        self.risk.update_mark(market_id, mark_price);
+        if let Some(market) = self.markets.get_mut(&market_id) {
+            market.prices.mark = Some(mark_price);
+            market.prices.index = Some(index_price);
+            if let Some(volatility) = &mut market.volatility {
+                volatility.record(mark_price);
+                self.risk.update_price_band(market_id, market.price_band());
+            }
+        }
+        events.extend(self.run_triggers(market_id, ts));
+        events.extend(self.run_liquidations(market_id, ts));
+        self.refresh_adl_market(market_id);
+        events
+    }
+
+    /// Activates every parked conditional order in `market_id` whose trigger has been crossed, in
+    /// acceptance order, logging an `OrderTriggered` for each. Activations can trade and move the
+    /// last-trade price, so this repeats until a pass triggers nothing. Orders that no longer pass
+    /// validation are cancelled instead of executed.
+    fn run_triggers(&mut self, market_id: MarketId, ts: u64) -> Vec<EventEnvelope> {
+        let mut events = Vec::new();
+        loop {
+            let Some(market) = self.markets.get_mut(&market_id) else {
+                return events;
+            };
+            let prices = market.prices;
+            let fired = market.triggers.take_triggered(|source| prices.get(source));
+            if fired.is_empty() {
+                return events;
+            }
+            let triggered: Vec<_> = fired
+                .into_iter()
+                .filter_map(|order_id| market.conditional.remove(&order_id).map(|order| (order_id, order)))
+                .collect();
+            for (order_id, order) in triggered {
+                if let Some(trigger) = order.trigger {
+                    events.push(EventEnvelope {
+                        shard_id: self.shard_id,
+                        engine_seq: self.engine_seq,
+                        event: Event::OrderTriggered(OrderTriggered {
+                            market_id,
+                            order_id,
+                            subaccount_id: order.subaccount_id,
+                            trigger,
+                            reference_price: prices.get(trigger.source).unwrap_or_default(),
+                            engine_seq: self.engine_seq,
+                            ts,
+                        }),
+                        ts,
+                        ts_ns: self.ts_ns,
+                        schema_version: SCHEMA_VERSION,
+                    });
+                }
+                let market = self.markets.get(&market_id).expect("market exists");
+                let status = if order.tif.expires_at().is_some_and(|expires_at| expires_at <= ts) {
+                    Some(OrderUpdateStatus::Expired)
+                } else if self.validate_order(&order, market).is_err() {
+                    Some(OrderUpdateStatus::Cancelled)
+                } else {
+                    None
+                };
+                match status {
+                    Some(status) => {
+                        events.push(self.order_update(market_id, order_id, order.subaccount_id, status, order.qty, ts));
+                    }
+                    None => events.extend(self.execute_order(order, order_id, ts)),
+                }
+            }
+        }
+    }
+
+    /// Liquidates the positions in `market_id` of subaccounts whose equity is below maintenance
+    /// margin, one tranche of `liquidation_tranche_bps` of the position at a time. Each tranche is
+    /// offered to the market's backstop providers first (see [`Self::assign_backstop`]) and whatever
+    /// they do not take goes to the book as a reduce-only IOC market order. Losses beyond the
+    /// bankruptcy price are covered from the insurance fund and the tranche's liquidation fee is
+    /// charged, then the subaccount is re-evaluated; liquidation stops once it is back above
+    /// maintenance, the position is closed or nothing could be liquidated. Batch markets are
+    /// skipped, since a tranche would wait for the next clearing.
+    fn run_liquidations(&mut self, market_id: MarketId, ts: u64) -> Vec<EventEnvelope> {
+        let Some(market) = self.markets.get(&market_id) else {
+            return Vec::new();
+        };
+        if matches!(market.config.matching_mode, MatchingMode::Batch) {
+            return Vec::new();
+        }
+        let tranche_bps = market.config.liquidation_tranche_bps.clamp(1, 10_000);
+        let fee_bps = market.config.liquidation_fee_bps;
+        let underwater: Vec<SubaccountId> = self
+            .risk
+            .state
+            .subaccounts
+            .iter()
+            .filter(|(_, account)| account.positions.get(&market_id).is_some_and(|position| position.size != 0))
+            .map(|(subaccount_id, _)| *subaccount_id)
+            .filter(|subaccount_id| self.below_maintenance(*subaccount_id).is_some())
+            .collect();
+
+        let mut events = Vec::new();
+        for subaccount_id in underwater {
+            while let Some((equity, maintenance_margin)) = self.below_maintenance(subaccount_id) {
+                let size = self.risk.position_size(subaccount_id, market_id);
+                if size == 0 {
+                    break;
+                }
+                let qty = (u128::from(size.unsigned_abs()) * u128::from(tranche_bps)).div_ceil(10_000) as Quantity;
+                let side = if size > 0 { Side::Sell } else { Side::Buy };
+                let mark = self.risk.state.mark_prices.get(&market_id).copied().unwrap_or_default();
+                let bankruptcy_price = bankruptcy_price(mark, equity, size);
+                let (assigned, backstop_qty) = self.assign_backstop(market_id, subaccount_id, side, qty, ts);
+                let residual = qty - backstop_qty;
+                let mut executed = Vec::new();
+                let mut order_id = None;
+                let mut filled_qty = 0;
+                let mut notional = u128::from(backstop_qty) * u128::from(mark);
+                let mut shortfall = bankruptcy_shortfall(side, bankruptcy_price, mark, backstop_qty);
+                if residual > 0 {
+                    let id = self.next_order_id;
+                    self.next_order_id += 1;
+                    let order = NewOrder {
+                        request_id: String::new(),
+                        market_id,
+                        subaccount_id,
+                        side,
+                        order_type: OrderType::Market,
+                        tif: TimeInForce::Ioc,
+                        price_ticks: 0,
+                        qty: residual,
+                        reduce_only: true,
+                        expiry_ts: 0,
+                        nonce: 0,
+                        client_ts: 0,
+                        trigger: None,
+                        signature: Default::default(),
+                    };
+                    executed = self.execute_order(order, id, ts);
+                    for envelope in &executed {
+                        if let Event::Fill(fill) = &envelope.event
+                            && fill.taker_order_id == id
+                        {
+                            filled_qty += fill.qty;
+                            notional += u128::from(fill.qty) * u128::from(fill.price_ticks);
+                            shortfall += bankruptcy_shortfall(side, bankruptcy_price, fill.price_ticks, fill.qty);
+                        }
+                    }
+                    order_id = Some(id);
+                }
+                let notional = notional.min(i64::MAX as u128) as i64;
+                let shortfall = shortfall.min(i64::MAX as u128) as i64;
+                let insurance_covered = self.cover_shortfall(subaccount_id, shortfall);
+                let fee = self.charge_liquidation_fee(subaccount_id, notional, fee_bps);
+                let remaining_position = self.risk.position_size(subaccount_id, market_id);
+                events.push(EventEnvelope {
+                    shard_id: self.shard_id,
+                    engine_seq: self.engine_seq,
+                    event: Event::Liquidation(Liquidation {
+                        market_id,
+                        subaccount_id,
+                        order_id,
+                        side,
+                        qty,
+                        backstop_qty,
+                        filled_qty,
+                        remaining_position,
+                        notional,
+                        fee,
+                        insurance_fund_subaccount_id: self.insurance_fund.filter(|_| fee != 0 || insurance_covered != 0),
+                        bankruptcy_price,
+                        shortfall,
+                        insurance_covered,
+                        adl_shortfall: shortfall - insurance_covered,
+                        equity,
+                        maintenance_margin,
+                        engine_seq: self.engine_seq,
+                        ts,
+                    }),
+                    ts,
+                    ts_ns: self.ts_ns,
+                    schema_version: SCHEMA_VERSION,
+                });
+                events.extend(assigned);
+                events.extend(executed);
+                events.push(self.position_update(market_id, subaccount_id, ts));
+                // Matching against the subaccount's own resting orders leaves the position unchanged.
+                if backstop_qty + filled_qty == 0 || remaining_position.unsigned_abs() >= size.unsigned_abs() {
+                    break;
+                }
+            }
+        }
+        events
+    }
+
+    /// Pays as much of a tranche's `shortfall` as the insurance fund's collateral allows to the
+    /// liquidated subaccount and returns the amount covered. The rest is left for auto-deleveraging.
+    fn cover_shortfall(&mut self, subaccount_id: SubaccountId, shortfall: i64) -> i64 {
+        let Some(insurance_fund) = self.insurance_fund else {
+            return 0;
+        };
+        if shortfall <= 0 || insurance_fund == subaccount_id {
+            return 0;
+        }
+        let available = self.risk.state.subaccounts.get(&insurance_fund).map_or(0, |fund| fund.collateral.max(0));
+        let covered = shortfall.min(available);
+        if covered > 0 {
+            self.risk.ensure_subaccount(insurance_fund).collateral -= covered;
+            self.risk.ensure_subaccount(subaccount_id).collateral += covered;
+        }
+        covered
+    }
+
+    /// Moves `fee_bps` of a tranche's liquidated `notional` from the liquidated subaccount to the
+    /// insurance fund and returns the fee; nothing moves without an insurance fund.
+    fn charge_liquidation_fee(&mut self, subaccount_id: SubaccountId, notional: i64, fee_bps: u64) -> i64 {
+        let Some(insurance_fund) = self.insurance_fund else {
+            return 0;
+        };
+        let fee = (i128::from(notional) * i128::from(fee_bps) / 10_000).min(i128::from(i64::MAX)) as i64;
+        if fee == 0 || insurance_fund == subaccount_id {
+            return 0;
+        }
+        self.risk.ensure_subaccount(subaccount_id).collateral -= fee;
+        self.risk.ensure_subaccount(insurance_fund).collateral += fee;
+        fee
+    }
+
+    /// Offers `qty` of a liquidated position to the market's `backstop_providers` at mark, in
+    /// configured order. Each provider in turn is assigned an equal share of what is still
+    /// unassigned, rounded up; a provider whose share would fail its margin, position or parent
+    /// limits takes nothing, and its share rolls over to the providers after it. Returns the
+    /// assignments, any reduce-only cancellations they cause, and the quantity assigned.
+    fn assign_backstop(
+        &mut self,
+        market_id: MarketId,
+        subaccount_id: SubaccountId,
+        side: Side,
+        qty: Quantity,
+        ts: u64,
+    ) -> (Vec<EventEnvelope>, Quantity) {
+        let Some(market) = self.markets.get(&market_id) else {
+            return (Vec::new(), 0);
+        };
+        let Some(&mark) = self.risk.state.mark_prices.get(&market_id) else {
+            return (Vec::new(), 0);
+        };
+        let config = market.config.clone();
+        let providers: Vec<SubaccountId> = config
+            .backstop_providers
+            .iter()
+            .copied()
+            .filter(|provider| *provider != subaccount_id)
+            .collect();
+        let provider_side = match side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+
+        let mut events = Vec::new();
+        let mut touched = Vec::new();
+        let mut unassigned = qty;
+        for (index, provider) in providers.iter().copied().enumerate() {
+            if unassigned == 0 {
+                break;
+            }
+            let share = unassigned.div_ceil((providers.len() - index) as Quantity);
+            let market = &self.markets[&market_id];
+            let permitted = self.is_permitted(market_id, provider)
+                && self
+                    .risk
+                    .validate_order(&config, provider, ProposedOrder::limit(provider_side, mark, share))
+                    .is_ok()
+                && self.check_parent_limits(market, provider, provider_side, share, false).is_ok();
+            if !permitted {
+                continue;
+            }
+            self.risk.apply_fill(&config, subaccount_id, side, mark, share, 0);
+            self.risk.apply_fill(&config, provider, provider_side, mark, share, 0);
+            self.refresh_adl(market_id, subaccount_id);
+            self.refresh_adl(market_id, provider);
+            unassigned -= share;
+            touched.push(provider);
+            events.push(EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::BackstopAssignment(BackstopAssignment {
+                    market_id,
+                    liquidated_subaccount_id: subaccount_id,
+                    provider_subaccount_id: provider,
+                    side: provider_side,
+                    price_ticks: mark,
+                    qty: share,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            });
+        }
+        if touched.is_empty() {
+            return (events, 0);
+        }
+        events.extend(touched.iter().map(|provider| self.position_update(market_id, *provider, ts)));
+
+        touched.push(subaccount_id);
+        let mut enforced = Vec::new();
+        for subaccount_id in touched {
+            enforced.extend(self.enforce_reduce_only(subaccount_id, market_id, ts));
+        }
+        if !enforced.is_empty() {
+            events.extend(enforced);
+            if let Some(market) = self.markets.get(&market_id) {
+                let snapshot = market.book.snapshot(10);
+                events.push(self.book_delta_from_snapshot(market_id, snapshot, ts));
+            }
+        }
+        (events, qty - unassigned)
+    }
+
+    /// Runs an accepted order through matching (or the batch), emitting fills, order updates and
+    /// the resulting book delta. The ack has already been sent by the caller.
+    fn execute_order(&mut self, order: NewOrder, order_id: OrderId, ts: u64) -> Vec<EventEnvelope> {
         self.order_owners.insert(order_id, (order.subaccount_id, order.side));
         let incoming = IncomingOrder {
             order_id,
@@ -235,21 +1667,8 @@ impl EngineShard {
         };
 
         let mut events = Vec::new();
-        events.push(EventEnvelope {
-            shard_id: self.shard_id,
-            engine_seq: self.engine_seq,
-            event: Event::OrderAck(OrderAck {
-                request_id: order.request_id,
-                status: OrderStatus::Accepted,
-                reject_reason: None,
-                assigned_order_id: Some(order_id),
-                engine_seq: self.engine_seq,
-                ts,
-            }),
-            ts,
-        });
 
-        let (matching_mode, market_config, fills, snapshot, closed_maker_ids, taker_rested) = {
+        let (matching_mode, market_config, fills, closed_maker_ids, maker_remaining, taker_rested) = {
             let market = self
                 .markets
                 .get_mut(&order.market_id)
@@ -259,143 +1678,1206 @@ impl EngineShard {
             match mode {
                 MatchingMode::Continuous => {
                     let (fills, resting_id) = market.book.place_order(incoming, 1024);
-                    let snapshot = market.book.snapshot(10);
                     let mut closed_maker_ids = Vec::new();
+                    let mut maker_remaining = Vec::with_capacity(fills.len());
                     for fill in &fills {
-                        if !market.book.has_order(fill.maker_order_id) {
+                        let remaining = market.book.order_remaining(fill.maker_order_id).unwrap_or(0);
+                        if remaining == 0 {
                             closed_maker_ids.push(fill.maker_order_id);
                         }
+                        maker_remaining.push((fill.maker_order_id, remaining));
                     }
                     let taker_rested = resting_id.is_some();
-                    (mode, config, fills, Some(snapshot), closed_maker_ids, taker_rested)
+                    (mode, config, fills, closed_maker_ids, maker_remaining, taker_rested)
                 }
                 MatchingMode::Batch => {
                     market.batch.push(incoming);
-                    (mode, config, Vec::new(), None, Vec::new(), false)
+                    if market.next_clear_at.is_none() {
+                        market.next_clear_at = Some(next_clearing_time(self.clearing_seed, market, ts));
+                    }
+                    (mode, config, Vec::new(), Vec::new(), Vec::new(), false)
                 }
             }
         };
 
         match matching_mode {
             MatchingMode::Continuous => {
-                events.extend(self.emit_fills(fills, &market_config, ts));
+                let taker_filled: Quantity = fills.iter().map(|fill| fill.qty).sum();
+                let mut open_qty: HashMap<OrderId, Quantity> = maker_remaining.iter().copied().collect();
+                for fill in &fills {
+                    *open_qty.entry(fill.maker_order_id).or_default() += fill.qty;
+                }
+                open_qty.insert(order_id, order.qty);
+                events.extend(self.emit_fills(fills, open_qty, &market_config, ts));
+                for &(maker_order_id, remaining) in &maker_remaining {
+                    if let Some((maker_sub, _)) = self.order_owners.get(&maker_order_id).copied() {
+                        let status = if remaining == 0 {
+                            OrderUpdateStatus::Filled
+                        } else {
+                            OrderUpdateStatus::PartiallyFilled
+                        };
+                        events.push(self.order_update(order.market_id, maker_order_id, maker_sub, status, remaining, ts));
+                    }
+                }
+                let taker_remaining = order.qty.saturating_sub(taker_filled);
+                let taker_status = if taker_remaining == 0 {
+                    OrderUpdateStatus::Filled
+                } else if !taker_rested {
+                    OrderUpdateStatus::Cancelled
+                } else if taker_filled == 0 {
+                    OrderUpdateStatus::Open
+                } else {
+                    OrderUpdateStatus::PartiallyFilled
+                };
+                events.push(self.order_update(order.market_id, order_id, order.subaccount_id, taker_status, taker_remaining, ts));
+                let mut touched = vec![order.subaccount_id];
+                for (maker_order_id, _) in &maker_remaining {
+                    if let Some((maker_sub, _)) = self.order_owners.get(maker_order_id)
+                        && !touched.contains(maker_sub)
+                    {
+                        touched.push(*maker_sub);
+                    }
+                }
                 if taker_rested {
                     if let Some(market) = self.markets.get_mut(&order.market_id) {
                         market.track_open_order_add(order.subaccount_id);
                     }
+                    if let Some(expires_at) = order.tif.expires_at() {
+                        self.expiries.insert((expires_at, order_id), order.market_id);
+                    }
+                    if order.reduce_only {
+                        self.reduce_only_orders
+                            .entry((order.subaccount_id, order.market_id))
+                            .or_default()
+                            .push(order_id);
+                    }
                 } else {
                     self.order_owners.remove(&order_id);
                 }
                 for maker_order_id in closed_maker_ids {
-                    if let Some((subaccount_id, _)) = self.order_owners.remove(&maker_order_id) {
-                        if let Some(market) = self.markets.get_mut(&order.market_id) {
-                            market.track_open_order_remove(subaccount_id);
-                        }
+                    if let Some((subaccount_id, _)) = self.order_owners.remove(&maker_order_id)
+                        && let Some(market) = self.markets.get_mut(&order.market_id)
+                    {
+                        market.track_open_order_remove(subaccount_id);
                     }
                 }
-                if let Some(snapshot) = snapshot {
+                for subaccount_id in touched {
+                    events.extend(self.enforce_reduce_only(subaccount_id, order.market_id, ts));
+                }
+                if let Some(market) = self.markets.get(&order.market_id) {
+                    let snapshot = market.book.snapshot(10);
                     events.push(self.book_delta_from_snapshot(order.market_id, snapshot, ts));
                 }
             }
-            MatchingMode::Batch => {}
+            MatchingMode::Batch => {
+                events.push(self.order_update(order.market_id, order_id, order.subaccount_id, OrderUpdateStatus::Open, order.qty, ts));
+            }
         }
 
         events
     }
 
     fn on_cancel(&mut self, cancel: CancelOrder, ts: u64) -> Vec<EventEnvelope> {
-        let mut snapshot = None;
+        let mut events = Vec::new();
         if let Some(order_id) = cancel.order_id {
+            let mut cancelled = None;
             if let Some(market) = self.markets.get_mut(&cancel.market_id) {
+                let remaining = market.book.order_remaining(order_id).unwrap_or(0);
                 if market.book.cancel(order_id) {
-                    if let Some((subaccount_id, _)) = self.order_owners.remove(&order_id) {
+                    let owner = self.order_owners.remove(&order_id);
+                    if let Some((subaccount_id, _)) = owner {
                         market.track_open_order_remove(subaccount_id);
                     }
-                    snapshot = Some(market.book.snapshot(10));
+                    cancelled = Some((owner, remaining, market.book.snapshot(10)));
+                } else if let Some(parked) = market.conditional.remove(&order_id) {
+                    market.triggers.remove(order_id);
+                    events.push(self.order_update(
+                        cancel.market_id,
+                        order_id,
+                        parked.subaccount_id,
+                        OrderUpdateStatus::Cancelled,
+                        parked.qty,
+                        ts,
+                    ));
+                }
+            }
+            if let Some((owner, remaining, snapshot)) = cancelled {
+                if let Some((subaccount_id, _)) = owner {
+                    events.push(self.order_update(cancel.market_id, order_id, subaccount_id, OrderUpdateStatus::Cancelled, remaining, ts));
                 }
+                events.push(self.book_delta_from_snapshot(cancel.market_id, snapshot, ts));
+            }
+        }
+        events
+    }
+
+    /// Removes a market and everything keyed by it (orders, owners, expiries, positions, prices)
+    /// and emits it as a `MarketExported` for the router to hand to the target shard.
+    fn on_migrate_market(&mut self, migrate: MigrateMarket, ts: u64) -> Vec<EventEnvelope> {
+        if migrate.target_shard == self.shard_id {
+            return vec![self.reject(migrate.request_id, None, RejectReason::InvalidOrder, ts)];
+        }
+        let market_id = migrate.market_id;
+        let Some(export) = self.export_market(market_id) else {
+            return vec![self.reject(migrate.request_id, None, RejectReason::UnknownMarket, ts)];
+        };
+        self.remove_market(market_id);
+        vec![
+            EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::OrderAck(OrderAck {
+                    request_id: migrate.request_id,
+                    status: OrderStatus::Accepted,
+                    reject_code: None,
+                    reject_reason: None,
+                    assigned_order_id: None,
+                    engine_seq: self.engine_seq,
+                    ts,
+                    filled_qty: 0,
+                    remaining_qty: 0,
+                    disposition: None,
+                    subaccount_id: None,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            },
+            EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::MarketExported(MarketTransfer {
+                    market_id,
+                    source_shard: self.shard_id,
+                    target_shard: migrate.target_shard,
+                    state: bincode::serialize(&export).expect("market export serializes"),
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            },
+        ]
+    }
+
+    /// Everything the shard holds for `market_id`, left in place.
+    fn export_market(&self, market_id: MarketId) -> Option<MarketExport> {
+        let market = self.markets.get(&market_id)?;
+        let positions = self
+            .risk
+            .state
+            .subaccounts
+            .iter()
+            .filter_map(|(subaccount_id, account)| account.positions.get(&market_id).map(|position| (*subaccount_id, position.clone())))
+            .collect();
+        Some(MarketExport {
+            config: Some(market.config.clone()),
+            orders: OrderSnapshot::from_book(&market.book),
+            batch_pending: market.batch.pending.clone(),
+            conditional: market.conditional.iter().map(|(order_id, order)| (*order_id, order.clone())).collect(),
+            positions,
+            mark_price: self.risk.state.mark_prices.get(&market_id).copied(),
+            funding_index: self.risk.state.funding_indices.get(&market_id).copied(),
+            allowlist: self.market_allowlists.get(&market_id).cloned(),
+            prices: market.prices,
+            next_clear_at: market.next_clear_at,
+            auction_round: market.auction_round,
+            nonce_watermarks: market.nonce_watermarks.clone(),
+            volatility_marks: market.volatility.iter().flat_map(RealizedVolatility::marks).collect(),
+            initial_margin_bps: self.risk.initial_margins.get(&market_id).copied(),
+            oracle_quotes: market.oracle_quotes.clone(),
+            next_funding_at: market.next_funding_at,
+            next_margin_at: market.next_margin_at,
+        })
+    }
+
+    /// Drops `market_id` and everything keyed by it, the inverse of
+    /// [`install_market`](Self::install_market).
+    fn remove_market(&mut self, market_id: MarketId) {
+        let Some(market) = self.markets.remove(&market_id) else {
+            return;
+        };
+        for order in market.book.order_views() {
+            self.order_owners.remove(&order.order_id);
+        }
+        for order in &market.batch.pending {
+            self.order_owners.remove(&order.order_id);
+        }
+        self.reduce_only_orders.retain(|(_, reduce_market), _| *reduce_market != market_id);
+        self.expiries.retain(|_, expiry_market| *expiry_market != market_id);
+        for account in self.risk.state.subaccounts.values_mut() {
+            account.positions.remove(&market_id);
+        }
+        self.risk.state.mark_prices.remove(&market_id);
+        self.risk.state.funding_indices.remove(&market_id);
+        self.risk.update_initial_margin(market_id, None);
+        self.risk.update_price_band(market_id, None);
+        self.market_allowlists.remove(&market_id);
+    }
+
+    /// Installs a market from its export under `config`. Order owners, open-order counts, GTD
+    /// expiries and the reduce-only index are derived from its orders, as when they were placed.
+    /// Orders whose ids are in `live` are left out and returned as
+    /// `(order_id, subaccount_id, remaining)`.
+    fn install_market(&mut self, config: MarketConfig, export: MarketExport, live: &BTreeSet<OrderId>) -> Vec<(OrderId, SubaccountId, Quantity)> {
+        let market_id = config.market_id;
+        let mut collided = Vec::new();
+        let mut market = MarketState::new(config);
+        for order in export.orders {
+            if live.contains(&order.order_id) {
+                collided.push((order.order_id, order.subaccount_id, order.remaining));
+                continue;
+            }
+            market.book.place_order(order.incoming(), 0);
+            market.track_open_order_add(order.subaccount_id);
+            self.order_owners.insert(order.order_id, (order.subaccount_id, order.side));
+            if let Some(expires_at) = order.tif.expires_at() {
+                self.expiries.insert((expires_at, order.order_id), market_id);
+            }
+            if order.reduce_only {
+                self.reduce_only_orders
+                    .entry((order.subaccount_id, market_id))
+                    .or_default()
+                    .push(order.order_id);
+            }
+        }
+        for order in export.batch_pending {
+            if live.contains(&order.order_id) {
+                collided.push((order.order_id, order.subaccount_id, order.qty));
+            } else {
+                self.order_owners.insert(order.order_id, (order.subaccount_id, order.side));
+                market.batch.push(order);
+            }
+        }
+        for (order_id, order) in export.conditional {
+            if live.contains(&order_id) {
+                collided.push((order_id, order.subaccount_id, order.qty));
+                continue;
+            }
+            if let Some(expires_at) = order.tif.expires_at() {
+                self.expiries.insert((expires_at, order_id), market_id);
+            }
+            if let Some(trigger) = order.trigger {
+                market.triggers.insert(order_id, order.side, trigger);
+            }
+            market.conditional.insert(order_id, order);
+        }
+        market.prices = export.prices;
+        market.next_clear_at = export.next_clear_at;
+        market.auction_round = export.auction_round;
+        market.nonce_watermarks = export.nonce_watermarks;
+        market.oracle_quotes = export.oracle_quotes;
+        market.next_funding_at = export.next_funding_at;
+        market.next_margin_at = export.next_margin_at;
+        if let Some(volatility) = &mut market.volatility {
+            for mark in export.volatility_marks {
+                volatility.record(mark);
             }
         }
-        if let Some(snapshot) = snapshot {
-            return vec![self.book_delta_from_snapshot(cancel.market_id, snapshot, ts)];
+        self.risk.update_price_band(market_id, market.price_band());
+        self.risk.update_initial_margin(market_id, export.initial_margin_bps);
+
+        for (subaccount_id, position) in export.positions {
+            self.risk.ensure_subaccount(subaccount_id).positions.insert(market_id, position);
+        }
+        if let Some(mark) = export.mark_price {
+            self.risk.update_mark(market_id, mark);
+        }
+        if let Some(index) = export.funding_index {
+            self.risk.update_funding(market_id, index);
+        }
+        if let Some(allowlist) = export.allowlist {
+            self.market_allowlists.insert(market_id, allowlist);
+        }
+        self.markets.insert(market_id, market);
+        self.refresh_adl_market(market_id);
+        collided
+    }
+
+    /// Installs a market exported by another shard. Shards assign order ids from disjoint ranges,
+    /// so imported ids cannot clash with live ones; should one anyway (e.g. a WAL from before the
+    /// ranges existed), that order is cancelled rather than imported.
+    fn on_market_import(&mut self, transfer: MarketTransfer, ts: u64) -> Vec<EventEnvelope> {
+        let market_id = transfer.market_id;
+        if transfer.target_shard != self.shard_id || self.markets.contains_key(&market_id) {
+            return Vec::new();
+        }
+        let Ok(mut export) = bincode::deserialize::<MarketExport>(&transfer.state) else {
+            return Vec::new();
+        };
+        let Some(config) = export.config.take().filter(|config| config.market_id == market_id) else {
+            return Vec::new();
+        };
+
+        let live: BTreeSet<OrderId> = self
+            .order_owners
+            .keys()
+            .copied()
+            .chain(self.markets.values().flat_map(|market| market.conditional.keys().copied()))
+            .collect();
+        let mut collided = self.install_market(config, export, &live);
+        let snapshot = self.markets[&market_id].book.snapshot(10);
+
+        collided.sort_unstable();
+        let mut events: Vec<_> = collided
+            .into_iter()
+            .map(|(order_id, subaccount_id, remaining)| {
+                self.order_update(market_id, order_id, subaccount_id, OrderUpdateStatus::Cancelled, remaining, ts)
+            })
+            .collect();
+        events.push(self.book_delta_from_snapshot(market_id, snapshot, ts));
+        events
+    }
+
+    /// Refreshes maker quotes for markets whose book changed in `outputs` and emits a compliance
+    /// report per designated maker for every market whose report period has ended.
+    fn track_obligations(&mut self, outputs: &[EventEnvelope], ts: u64) -> Vec<EventEnvelope> {
+        let changed: BTreeSet<MarketId> = outputs
+            .iter()
+            .filter_map(|env| match &env.event {
+                Event::BookDelta(delta) => Some(delta.market_id),
+                _ => None,
+            })
+            .collect();
+        let mut tracked: Vec<MarketId> = self
+            .markets
+            .iter()
+            .filter(|(_, market)| market.obligations.is_some())
+            .map(|(market_id, _)| *market_id)
+            .collect();
+        tracked.sort_unstable();
+
+        let mut events = Vec::new();
+        for market_id in tracked {
+            let market = self.markets.get_mut(&market_id).expect("market exists");
+            let Some(tracker) = market.obligations.as_mut() else {
+                continue;
+            };
+            if changed.contains(&market_id) || tracker.needs_observe() {
+                tracker.observe(&market.book);
+            }
+            let Some(reports) = tracker.take_report(ts) else {
+                continue;
+            };
+            for report in reports {
+                events.push(EventEnvelope {
+                    shard_id: self.shard_id,
+                    engine_seq: self.engine_seq,
+                    event: Event::MakerCompliance(MakerCompliance {
+                        market_id,
+                        subaccount_id: report.subaccount_id,
+                        period_start: report.period_start,
+                        period_end: report.period_end,
+                        quoted_secs: report.quoted_secs,
+                        compliant_secs: report.compliant_secs,
+                        uptime_bps: report.uptime_bps,
+                        avg_spread_ticks: report.avg_spread_ticks,
+                        compliant: report.compliant,
+                        engine_seq: self.engine_seq,
+                        ts,
+                    }),
+                    ts,
+                    ts_ns: self.ts_ns,
+                    schema_version: SCHEMA_VERSION,
+                });
+            }
+        }
+        events
+    }
+
+    /// Cancels every book and parked conditional order of the parent's subaccounts in the requested
+    /// market (or every market on this shard), with one book delta per market that changed.
+    fn on_mass_cancel(&mut self, cancel: MassCancel, ts: u64) -> Vec<EventEnvelope> {
+        let Some(parent) = self.accounts.parent(cancel.parent_id) else {
+            return Vec::new();
+        };
+        let children: BTreeSet<SubaccountId> = parent.subaccounts.iter().copied().collect();
+        let mut market_ids: Vec<MarketId> = match cancel.market_id {
+            Some(market_id) => vec![market_id],
+            None => self.markets.keys().copied().collect(),
+        };
+        market_ids.sort_unstable();
+
+        let mut events = Vec::new();
+        for market_id in market_ids {
+            let Some(market) = self.markets.get_mut(&market_id) else {
+                continue;
+            };
+            let mut cancelled = Vec::new();
+            let mut book_changed = false;
+            let mut resting: Vec<_> = children
+                .iter()
+                .flat_map(|subaccount_id| market.book.subaccount_orders(*subaccount_id))
+                .map(|order| (order.order_id, order.subaccount_id, order.remaining))
+                .collect();
+            resting.sort_unstable();
+            for (order_id, subaccount_id, remaining) in resting {
+                if market.book.cancel(order_id) {
+                    self.order_owners.remove(&order_id);
+                    market.track_open_order_remove(subaccount_id);
+                    cancelled.push((order_id, subaccount_id, remaining));
+                    book_changed = true;
+                }
+            }
+            let parked: Vec<OrderId> = market
+                .conditional
+                .iter()
+                .filter(|(_, order)| children.contains(&order.subaccount_id))
+                .map(|(order_id, _)| *order_id)
+                .collect();
+            for order_id in parked {
+                if let Some(order) = market.conditional.remove(&order_id) {
+                    market.triggers.remove(order_id);
+                    cancelled.push((order_id, order.subaccount_id, order.qty));
+                }
+            }
+            for (order_id, subaccount_id, remaining) in cancelled {
+                events.push(self.order_update(market_id, order_id, subaccount_id, OrderUpdateStatus::Cancelled, remaining, ts));
+            }
+            if book_changed {
+                let snapshot = self.markets[&market_id].book.snapshot(10);
+                events.push(self.book_delta_from_snapshot(market_id, snapshot, ts));
+            }
         }
-        Vec::new()
+        events
     }
 
-    fn validate_order(&self, order: &NewOrder, market: &MarketState) -> Result<(), &'static str> {
+    /// Books a pre-negotiated trade without touching the order book. Both legs pass the same risk
+    /// checks as a limit order at the trade price; each leg gets its own order id (seller first, as
+    /// the fill's maker) and both pay their taker fee since neither provided resting liquidity.
+    fn on_block_trade(&mut self, trade: BlockTrade, ts: u64) -> Vec<EventEnvelope> {
+        if self.dedupe.check_and_insert(request_key(&trade.request_id), ts) {
+            return Vec::new();
+        }
+        let Some(market_state) = self.markets.get(&trade.market_id) else {
+            return vec![self.reject(trade.request_id, None, RejectReason::UnknownMarket, ts)];
+        };
+        if trade.qty == 0 || trade.price_ticks == 0 || trade.buyer_subaccount_id == trade.seller_subaccount_id {
+            return vec![self.reject(trade.request_id, None, RejectReason::InvalidOrder, ts)];
+        }
+        if !self.is_permitted(trade.market_id, trade.buyer_subaccount_id)
+            || !self.is_permitted(trade.market_id, trade.seller_subaccount_id)
+        {
+            return vec![self.reject(trade.request_id, None, RejectReason::Unauthorized, ts)];
+        }
+        let market_config = market_state.config.clone();
+        for (subaccount_id, side) in [(trade.buyer_subaccount_id, Side::Buy), (trade.seller_subaccount_id, Side::Sell)] {
+            if let Err(err) = self.risk.validate_order(&market_config, subaccount_id, ProposedOrder::limit(side, trade.price_ticks, trade.qty)) {
+                return vec![self.reject(trade.request_id, None, err.into(), ts)];
+            }
+            if let Err(reason) = self.check_parent_limits(market_state, subaccount_id, side, trade.qty, false) {
+                return vec![self.reject(trade.request_id, None, reason, ts)];
+            }
+        }
+
+        let seller_order_id = self.next_order_id;
+        let buyer_order_id = self.next_order_id + 1;
+        self.next_order_id += 2;
+        let seller_fee = fee_for(trade.qty, trade.price_ticks, self.fee_bps(&market_config, Some(trade.seller_subaccount_id), false));
+        let buyer_fee = fee_for(trade.qty, trade.price_ticks, self.fee_bps(&market_config, Some(trade.buyer_subaccount_id), false));
+        let seller_change = self.risk.apply_fill(&market_config, trade.seller_subaccount_id, Side::Sell, trade.price_ticks, trade.qty, seller_fee);
+        let buyer_change = self.risk.apply_fill(&market_config, trade.buyer_subaccount_id, Side::Buy, trade.price_ticks, trade.qty, buyer_fee);
+        self.refresh_adl(trade.market_id, trade.seller_subaccount_id);
+        self.refresh_adl(trade.market_id, trade.buyer_subaccount_id);
+
+        let fill = Fill {
+            market_id: trade.market_id,
+            maker_order_id: seller_order_id,
+            taker_order_id: buyer_order_id,
+            price_ticks: trade.price_ticks,
+            qty: trade.qty,
+            maker_fee: seller_fee,
+            taker_fee: buyer_fee,
+            engine_seq: self.engine_seq,
+            ts,
+            block_trade: true,
+            trade_id: self.next_trade_id,
+            maker_subaccount_id: trade.seller_subaccount_id,
+            taker_subaccount_id: trade.buyer_subaccount_id,
+            maker_remaining_qty: 0,
+            taker_remaining_qty: 0,
+        };
+        self.next_trade_id += 1;
+
+        let mut events = vec![EventEnvelope {
+            shard_id: self.shard_id,
+            engine_seq: self.engine_seq,
+            event: Event::OrderAck(OrderAck {
+                request_id: trade.request_id,
+                status: OrderStatus::Accepted,
+                reject_code: None,
+                reject_reason: None,
+                assigned_order_id: Some(buyer_order_id),
+                engine_seq: self.engine_seq,
+                ts,
+                filled_qty: trade.qty,
+                remaining_qty: 0,
+                disposition: Some(OrderDisposition::Filled),
+                subaccount_id: None,
+            }),
+            ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
+        }];
+        events.extend(self.fill_events(
+            fill,
+            Some((trade.seller_subaccount_id, Side::Sell, seller_change)),
+            Some((trade.buyer_subaccount_id, Side::Buy, buyer_change)),
+        ));
+        let mut enforced = self.enforce_reduce_only(trade.buyer_subaccount_id, trade.market_id, ts);
+        enforced.extend(self.enforce_reduce_only(trade.seller_subaccount_id, trade.market_id, ts));
+        if !enforced.is_empty() {
+            events.extend(enforced);
+            if let Some(market) = self.markets.get(&trade.market_id) {
+                let snapshot = market.book.snapshot(10);
+                events.push(self.book_delta_from_snapshot(trade.market_id, snapshot, ts));
+            }
+        }
+        events
+    }
+
+    /// Moves collateral between two subaccounts of one parent in this shard's ledger. The debit
+    /// and credit happen within the same input, so no other event sees only half of it.
+    fn on_transfer(&mut self, transfer: Transfer, ts: u64) -> Vec<EventEnvelope> {
+        if self.dedupe.check_and_insert(request_key(&transfer.request_id), ts) {
+            return Vec::new();
+        }
+        let from = Some(transfer.from_subaccount_id);
+        if !self.markets.contains_key(&transfer.market_id) {
+            return vec![self.reject(transfer.request_id, from, RejectReason::UnknownMarket, ts)];
+        }
+        if transfer.amount == 0 || transfer.from_subaccount_id == transfer.to_subaccount_id {
+            return vec![self.reject(transfer.request_id, from, RejectReason::InvalidOrder, ts)];
+        }
+        let owner = |subaccount_id| self.accounts.parent_of(subaccount_id).map(|parent| parent.parent_id);
+        let source_owner = owner(transfer.from_subaccount_id);
+        if source_owner.is_none() || source_owner != owner(transfer.to_subaccount_id) {
+            return vec![self.reject(transfer.request_id, from, RejectReason::Unauthorized, ts)];
+        }
+        if i128::from(transfer.amount) > i128::from(self.free_collateral(transfer.from_subaccount_id)) {
+            return vec![self.reject(transfer.request_id, from, RejectReason::InsufficientMargin, ts)];
+        }
+
+        let amount = transfer.amount as i64;
+        self.risk.ensure_subaccount(transfer.from_subaccount_id).collateral -= amount;
+        self.risk.ensure_subaccount(transfer.to_subaccount_id).collateral += amount;
+        let envelope = |event| EventEnvelope {
+            shard_id: self.shard_id,
+            engine_seq: self.engine_seq,
+            event,
+            ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
+        };
+        vec![
+            envelope(Event::OrderAck(OrderAck {
+                request_id: transfer.request_id.clone(),
+                status: OrderStatus::Accepted,
+                reject_code: None,
+                reject_reason: None,
+                assigned_order_id: None,
+                engine_seq: self.engine_seq,
+                ts,
+                filled_qty: 0,
+                remaining_qty: 0,
+                disposition: None,
+                subaccount_id: from,
+            })),
+            envelope(Event::CollateralTransfer(CollateralTransfer {
+                request_id: transfer.request_id,
+                shard_id: self.shard_id,
+                from_subaccount_id: transfer.from_subaccount_id,
+                to_subaccount_id: transfer.to_subaccount_id,
+                amount: transfer.amount,
+                engine_seq: self.engine_seq,
+                ts,
+            })),
+        ]
+    }
+
+    fn validate_order(&self, order: &NewOrder, market: &MarketState) -> Result<(), RejectReason> {
+        if !self.is_permitted(market.config.market_id, order.subaccount_id) {
+            return Err(RejectReason::Unauthorized);
+        }
+        if order.order_type != OrderType::Market && !order.price_ticks.is_multiple_of(market.config.tick_size.max(1)) {
+            return Err(RejectReason::InvalidOrder);
+        }
         if order.order_type == crate::models::OrderType::PostOnly && market.book.would_cross(order.side, order.price_ticks) {
-            return Err("post-only would cross");
+            return Err(RejectReason::PostOnlyWouldCross);
         }
-        let rest_can_increase_open_orders = order.tif == TimeInForce::Gtc
+        let rest_can_increase_open_orders = order.tif.rests()
             && order.order_type != crate::models::OrderType::Market;
-        if rest_can_increase_open_orders {
-            if market.config.max_open_orders_per_subaccount > 0
-                && market.open_orders_for_subaccount(order.subaccount_id)
-                    >= market.config.max_open_orders_per_subaccount
-            {
-                return Err("max open orders per subaccount");
-            }
+        if rest_can_increase_open_orders
+            && market.config.max_open_orders_per_subaccount > 0
+            && market.open_orders_for_subaccount(order.subaccount_id) >= market.config.max_open_orders_per_subaccount
+        {
+            return Err(RejectReason::MaxOpenOrders);
         }
+        self.check_parent_limits(market, order.subaccount_id, order.side, order.qty, rest_can_increase_open_orders)?;
         self.risk
-            .validate_order(
-                &market.config,
-                order.subaccount_id,
-                order.side,
-                order.order_type,
-                order.price_ticks,
-                order.qty,
-                order.reduce_only,
-            )
-            .map_err(|err| match err {
-                RiskError::PriceBand => "price band",
-                RiskError::InsufficientMargin => "insufficient margin",
-                RiskError::ReduceOnly => "reduce-only",
-                RiskError::MaxPosition => "max position",
-            })
+            .validate_order(&market.config, order.subaccount_id, ProposedOrder::from(order))
+            .map_err(RejectReason::from)
+    }
+
+    /// Aggregate limits of the subaccount's parent, summed over its children in this market.
+    fn check_parent_limits(
+        &self,
+        market: &MarketState,
+        subaccount_id: SubaccountId,
+        side: Side,
+        qty: Quantity,
+        may_rest: bool,
+    ) -> Result<(), RejectReason> {
+        let Some(parent) = self.accounts.parent_of(subaccount_id) else {
+            return Ok(());
+        };
+        if may_rest && parent.max_total_open_orders > 0 {
+            let open: u64 = parent
+                .subaccounts
+                .iter()
+                .map(|child| market.open_orders_for_subaccount(*child))
+                .sum();
+            if open >= parent.max_total_open_orders {
+                return Err(RejectReason::MaxOpenOrders);
+            }
+        }
+        if parent.max_total_position > 0 {
+            let total: i64 = parent
+                .subaccounts
+                .iter()
+                .map(|child| self.risk.position_size(*child, market.config.market_id))
+                .sum();
+            let qty = i64::try_from(qty).map_err(|_| RejectReason::MaxPosition)?;
+            let projected = match side {
+                Side::Buy => total.saturating_add(qty),
+                Side::Sell => total.saturating_sub(qty),
+            };
+            if projected.unsigned_abs() > parent.max_total_position as u64 {
+                return Err(RejectReason::MaxPosition);
+            }
+        }
+        Ok(())
     }
 
-    fn reject(&self, request_id: String, reason: &str, ts: u64) -> EventEnvelope {
+    fn reject(&self, request_id: String, subaccount_id: Option<SubaccountId>, reason: RejectReason, ts: u64) -> EventEnvelope {
         EventEnvelope {
             shard_id: self.shard_id,
             engine_seq: self.engine_seq,
             event: Event::OrderAck(OrderAck {
                 request_id,
                 status: OrderStatus::Rejected,
+                reject_code: Some(reason),
                 reject_reason: Some(reason.to_string()),
                 assigned_order_id: None,
                 engine_seq: self.engine_seq,
                 ts,
+                filled_qty: 0,
+                remaining_qty: 0,
+                disposition: None,
+                subaccount_id,
+            }),
+            ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+
+    /// Removes every good-til-date order (resting or still waiting on a trigger) whose expiry is at
+    /// or before `ts`, emitting an `Expired` update per order and one book delta per affected book.
+    fn expire_orders(&mut self, ts: u64) -> Vec<EventEnvelope> {
+        let mut expired = Vec::new();
+        while let Some(entry) = self.expiries.first_entry() {
+            if entry.key().0 > ts {
+                break;
+            }
+            let ((_, order_id), market_id) = entry.remove_entry();
+            let Some(market) = self.markets.get_mut(&market_id) else {
+                continue;
+            };
+            let Some(remaining) = market.book.order_remaining(order_id) else {
+                if let Some(parked) = market.conditional.remove(&order_id) {
+                    market.triggers.remove(order_id);
+                    expired.push((market_id, order_id, parked.subaccount_id, parked.qty, false));
+                }
+                continue;
+            };
+            market.book.cancel(order_id);
+            if let Some((subaccount_id, _)) = self.order_owners.remove(&order_id) {
+                market.track_open_order_remove(subaccount_id);
+                expired.push((market_id, order_id, subaccount_id, remaining, true));
+            }
+        }
+
+        let mut events = Vec::new();
+        let mut touched_markets = BTreeSet::new();
+        for (market_id, order_id, subaccount_id, remaining, from_book) in expired {
+            events.push(self.order_update(market_id, order_id, subaccount_id, OrderUpdateStatus::Expired, remaining, ts));
+            if from_book {
+                touched_markets.insert(market_id);
+            }
+        }
+        for market_id in touched_markets {
+            if let Some(market) = self.markets.get(&market_id) {
+                let snapshot = market.book.snapshot(10);
+                events.push(self.book_delta_from_snapshot(market_id, snapshot, ts));
+            }
+        }
+        events
+    }
+
+    /// Settles scheduled funding for every market whose funding time has been reached, in market-id
+    /// order. Periods that elapsed without an input settle together at the current prices; markets
+    /// without both a mark and an index price skip them. Each market's `FundingRate` is followed by
+    /// a `FundingPayment` for every open position it settled, then each position's update.
+    fn settle_funding(&mut self, ts: u64) -> Vec<EventEnvelope> {
+        let mut due = Vec::new();
+        for (market_id, market) in &mut self.markets {
+            let Some(config) = &market.config.funding else {
+                market.next_funding_at = None;
+                continue;
+            };
+            let interval = config.interval_secs.max(1);
+            match market.next_funding_at {
+                None => market.next_funding_at = Some(funding::next_funding_time(interval, ts)),
+                Some(funding_at) if funding_at <= ts => {
+                    let periods = (ts - funding_at) / interval + 1;
+                    market.next_funding_at = Some(funding::next_funding_time(interval, ts));
+                    if let (Some(mark), Some(index)) = (market.prices.mark, market.prices.index) {
+                        due.push((*market_id, funding::funding_rate_bps(config, mark, index), periods, mark, index));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        due.sort_unstable();
+
+        let mut events = Vec::new();
+        for (market_id, rate_bps, periods, mark_price, index_price) in due {
+            let index = self.risk.state.funding_indices.get(&market_id).copied().unwrap_or(0);
+            let funding_index = index.saturating_add(funding::index_delta(rate_bps, mark_price, periods));
+            self.risk.update_funding(market_id, funding_index);
+            let payments = self.risk.settle_funding(market_id);
+            self.refresh_adl_market(market_id);
+            events.push(EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::FundingRate(FundingRate {
+                    market_id,
+                    rate_bps,
+                    periods,
+                    mark_price,
+                    index_price,
+                    funding_index,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            });
+            let positions: Vec<_> = payments
+                .iter()
+                .map(|(subaccount_id, _, _)| self.position_update(market_id, *subaccount_id, ts))
+                .collect();
+            events.extend(payments.into_iter().map(|(subaccount_id, position, amount)| EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::FundingPayment(FundingPayment {
+                    market_id,
+                    subaccount_id,
+                    position_size: position.size,
+                    from_index: position.funding_index,
+                    to_index: funding_index,
+                    amount,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            }));
+            events.extend(positions);
+        }
+        events
+    }
+
+    /// Recomputes the volatility-scaled initial margin of every market with a dynamic margin
+    /// config that reached its next multiple of `interval_secs`, checked before each input like
+    /// funding. Each change is logged as a `RiskParameterChange`, in market-id order;
+    /// recomputations that leave the margin where it was emit nothing.
+    fn rescale_margins(&mut self, ts: u64) -> Vec<EventEnvelope> {
+        let mut changes = Vec::new();
+        for (market_id, market) in &mut self.markets {
+            let Some(config) = &market.config.dynamic_margin else {
+                market.next_margin_at = None;
+                continue;
+            };
+            let interval = config.interval_secs.max(1);
+            let due = market.next_margin_at.is_some_and(|margin_at| margin_at <= ts);
+            if market.next_margin_at.is_none() || due {
+                market.next_margin_at = Some(funding::next_funding_time(interval, ts));
+            }
+            if !due {
+                continue;
+            }
+            let vol_bps = market.volatility.as_ref().and_then(|volatility| volatility.vol_bps(config.window));
+            let value = volatility::initial_margin_bps(config, market.config.initial_margin_bps, vol_bps);
+            let previous = self.risk.initial_margin_bps(&market.config);
+            if value != previous {
+                self.risk.update_initial_margin(*market_id, Some(value));
+                changes.push((*market_id, previous, value, vol_bps));
+            }
+        }
+        changes.sort_unstable();
+
+        changes
+            .into_iter()
+            .map(|(market_id, previous, value, volatility_bps)| EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::RiskParameterChange(RiskParameterChange {
+                    market_id,
+                    parameter: RiskParameter::InitialMarginBps,
+                    previous: previous as i64,
+                    value: value as i64,
+                    volatility_bps,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            })
+            .collect()
+    }
+
+    /// Applies an operator's risk parameter update to a market on this shard. Orders already
+    /// resting are left alone; the new values apply from the next check. An update that would leave
+    /// the market failing [`MarketConfig::validate`] is ignored as a whole. Each parameter that
+    /// changed is logged as a `RiskParameterChange`, in [`RiskParamsUpdate`] field order.
+    fn on_risk_params_update(&mut self, update: RiskParamsUpdate, ts: u64) -> Vec<EventEnvelope> {
+        let market_id = update.market_id;
+        let Some(market) = self.markets.get_mut(&market_id) else {
+            return Vec::new();
+        };
+        let mut config = market.config.clone();
+        let changes = config.apply_risk_params(&update, self.risk.config.max_leverage);
+        if let Err(err) = config.validate() {
+            warn!(market_id, error = %err, "ignoring invalid risk parameter update");
+            return Vec::new();
+        }
+        market.config = config;
+        self.risk.update_price_band(market_id, market.price_band());
+
+        changes
+            .into_iter()
+            .map(|(parameter, previous, value)| EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::RiskParameterChange(RiskParameterChange {
+                    market_id,
+                    parameter,
+                    previous,
+                    value,
+                    volatility_bps: None,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            })
+            .collect()
+    }
+
+    /// Uncrosses every batch auction whose clearing time has been reached, in market-id order.
+    /// Runs before the triggering input is applied, so an order stamped at the boundary joins the
+    /// next auction. Unfilled GTC/GTD remainders carry over; anything else is cancelled.
+    fn clear_auctions(&mut self, ts: u64) -> Vec<EventEnvelope> {
+        let mut due: Vec<MarketId> = self
+            .markets
+            .iter()
+            .filter(|(_, market)| market.next_clear_at.is_some_and(|clear_at| clear_at <= ts))
+            .map(|(market_id, _)| *market_id)
+            .collect();
+        due.sort_unstable();
+
+        let mut events = Vec::new();
+        for market_id in due {
+            let mark_price = self.risk.state.mark_prices.get(&market_id).copied().unwrap_or_default();
+            let Some(market) = self.markets.get_mut(&market_id) else {
+                continue;
+            };
+            let orders = market.batch.pending.clone();
+            let open_qty: HashMap<OrderId, Quantity> = orders.iter().map(|order| (order.order_id, order.qty)).collect();
+            let (_, fills, resting) = market.batch.clear(mark_price);
+            market.auction_round += 1;
+            let config = market.config.clone();
+
+            let mut filled: HashMap<OrderId, Quantity> = HashMap::new();
+            for fill in &fills {
+                *filled.entry(fill.maker_order_id).or_default() += fill.qty;
+                *filled.entry(fill.taker_order_id).or_default() += fill.qty;
+            }
+            let carried: BTreeSet<OrderId> = resting.iter().map(|order| order.order_id).collect();
+            market.batch.pending = resting;
+            let mut outcomes = Vec::with_capacity(orders.len());
+            for order in orders {
+                let (order_id, subaccount_id) = (order.order_id, order.subaccount_id);
+                let done = filled.get(&order_id).copied().unwrap_or(0);
+                let remaining = order.qty.saturating_sub(done);
+                let carries_over = carried.contains(&order_id);
+                let status = match (remaining, carries_over) {
+                    (0, _) => OrderUpdateStatus::Filled,
+                    // Untouched orders simply stay open in the next auction.
+                    (_, true) if done == 0 => continue,
+                    (_, true) => OrderUpdateStatus::PartiallyFilled,
+                    (_, false) => OrderUpdateStatus::Cancelled,
+                };
+                outcomes.push((order_id, subaccount_id, status, remaining));
+            }
+            market.next_clear_at = if market.batch.pending.is_empty() {
+                None
+            } else {
+                Some(next_clearing_time(self.clearing_seed, market, ts))
+            };
+
+            events.extend(self.emit_fills(fills, open_qty, &config, ts));
+            for (order_id, subaccount_id, status, remaining) in outcomes {
+                if status != OrderUpdateStatus::PartiallyFilled {
+                    self.order_owners.remove(&order_id);
+                }
+                events.push(self.order_update(market_id, order_id, subaccount_id, status, remaining, ts));
+            }
+        }
+        events
+    }
+
+    /// Publishes the indicative uncross of every batch market with a non-empty auction whose
+    /// publication interval has elapsed, in market-id order.
+    fn publish_indicatives(&mut self, ts: u64) -> Vec<EventEnvelope> {
+        let mut due: Vec<MarketId> = self
+            .markets
+            .iter()
+            .filter(|(_, market)| {
+                matches!(market.config.matching_mode, MatchingMode::Batch)
+                    && !market.batch.pending.is_empty()
+                    && ts >= market.next_indicative_at
+            })
+            .map(|(market_id, _)| *market_id)
+            .collect();
+        due.sort_unstable();
+
+        let mut events = Vec::with_capacity(due.len());
+        for market_id in due {
+            let mark_price = self.risk.state.mark_prices.get(&market_id).copied().unwrap_or_default();
+            let Some(market) = self.markets.get_mut(&market_id) else {
+                continue;
+            };
+            let Some(indicative) = market.batch.indicative(mark_price) else {
+                continue;
+            };
+            market.next_indicative_at = ts.saturating_add(market.config.indicative_interval_secs);
+            events.push(EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::AuctionIndicative(AuctionIndicative {
+                    market_id,
+                    price_ticks: indicative.price,
+                    matched_qty: indicative.matched_qty,
+                    imbalance_qty: indicative.imbalance_qty,
+                    imbalance_side: indicative.imbalance_side,
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            });
+        }
+        events
+    }
+
+    /// Publishes the full depth of every continuous market whose snapshot is due, by engine time
+    /// or by the book deltas in `outputs` and earlier inputs, in market-id order.
+    fn publish_depth_snapshots(&mut self, outputs: &[EventEnvelope], ts: u64) -> Vec<EventEnvelope> {
+        let Some(config) = self.book_snapshots else {
+            return Vec::new();
+        };
+        for output in outputs {
+            if let Event::BookDelta(delta) = &output.event {
+                self.depth_schedule.entry(delta.market_id).or_default().1 += 1;
+            }
+        }
+        let mut due: Vec<MarketId> = self
+            .markets
+            .iter()
+            .filter(|(_, market)| matches!(market.config.matching_mode, MatchingMode::Continuous))
+            .map(|(market_id, _)| *market_id)
+            .filter(|market_id| {
+                let (next_at, deltas) = self.depth_schedule.get(market_id).copied().unwrap_or_default();
+                (config.interval_secs > 0 && ts >= next_at) || (config.every_deltas > 0 && deltas >= config.every_deltas)
+            })
+            .collect();
+        due.sort_unstable();
+
+        let mut events = Vec::with_capacity(due.len());
+        for market_id in due {
+            self.depth_schedule.insert(market_id, (ts.saturating_add(config.interval_secs), 0));
+            let snapshot = self.markets[&market_id].book.snapshot(usize::MAX);
+            let level = |(price_ticks, qty)| BookLevel { price_ticks, qty };
+            events.push(EventEnvelope {
+                shard_id: self.shard_id,
+                engine_seq: self.engine_seq,
+                event: Event::DepthSnapshot(DepthSnapshot {
+                    market_id,
+                    bids_levels: snapshot.bids.into_iter().map(level).collect(),
+                    asks_levels: snapshot.asks.into_iter().map(level).collect(),
+                    engine_seq: self.engine_seq,
+                    ts,
+                }),
+                ts,
+                ts_ns: self.ts_ns,
+                schema_version: SCHEMA_VERSION,
+            });
+        }
+        events
+    }
+
+    /// Keeps resting reduce-only orders of `subaccount_id` on `market_id` from ever adding
+    /// exposure: orders on the opening side (or with a flat position) are cancelled, and the
+    /// closing-side total is trimmed to the position size, newest orders first.
+    fn enforce_reduce_only(&mut self, subaccount_id: SubaccountId, market_id: MarketId, ts: u64) -> Vec<EventEnvelope> {
+        let key = (subaccount_id, market_id);
+        let Some(order_ids) = self.reduce_only_orders.remove(&key) else {
+            return Vec::new();
+        };
+        let position = self.risk.position_size(subaccount_id, market_id);
+        let closing_side = match position {
+            p if p > 0 => Some(Side::Sell),
+            p if p < 0 => Some(Side::Buy),
+            _ => None,
+        };
+        let Some(market) = self.markets.get_mut(&market_id) else {
+            return Vec::new();
+        };
+
+        let live: Vec<(OrderId, Quantity)> = order_ids
+            .iter()
+            .filter_map(|id| market.book.order_remaining(*id).map(|remaining| (*id, remaining)))
+            .collect();
+        let mut allowance = position.unsigned_abs();
+        let mut keep_qty = vec![0; live.len()];
+        for (slot, (order_id, remaining)) in live.iter().enumerate() {
+            let side = self.order_owners.get(order_id).map(|(_, side)| *side);
+            if side.is_some() && side == closing_side {
+                keep_qty[slot] = (*remaining).min(allowance);
+                allowance -= keep_qty[slot];
+            }
+        }
+
+        let mut changes = Vec::new();
+        let mut kept = Vec::with_capacity(live.len());
+        for ((order_id, remaining), keep) in live.into_iter().zip(keep_qty) {
+            if keep == 0 {
+                if market.book.cancel(order_id) {
+                    if let Some((owner, _)) = self.order_owners.remove(&order_id) {
+                        market.track_open_order_remove(owner);
+                    }
+                    changes.push((order_id, OrderUpdateStatus::Cancelled, remaining));
+                }
+            } else {
+                if keep < remaining && market.book.reduce_qty(order_id, keep) {
+                    changes.push((order_id, OrderUpdateStatus::Open, keep));
+                }
+                kept.push(order_id);
+            }
+        }
+        if !kept.is_empty() {
+            self.reduce_only_orders.insert(key, kept);
+        }
+        changes
+            .into_iter()
+            .map(|(order_id, status, remaining)| self.order_update(market_id, order_id, subaccount_id, status, remaining, ts))
+            .collect()
+    }
+
+    fn order_update(
+        &self,
+        market_id: MarketId,
+        order_id: OrderId,
+        subaccount_id: SubaccountId,
+        status: OrderUpdateStatus,
+        remaining_qty: Quantity,
+        ts: u64,
+    ) -> EventEnvelope {
+        EventEnvelope {
+            shard_id: self.shard_id,
+            engine_seq: self.engine_seq,
+            event: Event::OrderUpdate(OrderUpdate {
+                market_id,
+                order_id,
+                subaccount_id,
+                status,
+                remaining_qty,
+                engine_seq: self.engine_seq,
+                ts,
             }),
             ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
         }
     }
 
-    fn emit_fills(&mut self, fills: Vec<Fill>, market: &MarketConfig, ts: u64) -> Vec<EventEnvelope> {
+    /// Prices, numbers and applies `fills`. `open_qty` holds each order's open quantity before
+    /// them and is drawn down as they apply, so every fill reports what both orders have left.
+    fn emit_fills(&mut self, fills: Vec<Fill>, mut open_qty: HashMap<OrderId, Quantity>, market: &MarketConfig, ts: u64) -> Vec<EventEnvelope> {
         fills
             .into_iter()
-            .map(|mut fill| {
+            .flat_map(|mut fill| {
                 fill.market_id = market.market_id;
                 fill.engine_seq = self.engine_seq;
                 fill.ts = ts;
-                let maker_fee = fee_for(fill.qty, fill.price_ticks, market.maker_fee_bps);
-                let taker_fee = fee_for(fill.qty, fill.price_ticks, market.taker_fee_bps);
+                let maker = self.order_owners.get(&fill.maker_order_id).copied();
+                let taker = self.order_owners.get(&fill.taker_order_id).copied();
+                let maker_bps = self.fee_bps(market, maker.map(|(subaccount_id, _)| subaccount_id), true);
+                let taker_bps = self.fee_bps(market, taker.map(|(subaccount_id, _)| subaccount_id), false);
+                let maker_fee = fee_for(fill.qty, fill.price_ticks, maker_bps);
+                let taker_fee = fee_for(fill.qty, fill.price_ticks, taker_bps);
                 fill.maker_fee = maker_fee;
                 fill.taker_fee = taker_fee;
-                if let Some((maker_sub, maker_side)) = self.order_owners.get(&fill.maker_order_id).copied() {
-                    self.risk.apply_fill(market, maker_sub, maker_side, fill.price_ticks, fill.qty, maker_fee);
+                fill.trade_id = self.next_trade_id;
+                self.next_trade_id += 1;
+                if let Some(state) = self.markets.get_mut(&market.market_id) {
+                    state.prices.last_trade = Some(fill.price_ticks);
                 }
-                if let Some((taker_sub, taker_side)) = self.order_owners.get(&fill.taker_order_id).copied() {
-                    self.risk.apply_fill(market, taker_sub, taker_side, fill.price_ticks, fill.qty, taker_fee);
-                }
-                EventEnvelope {
-                    shard_id: self.shard_id,
-                    engine_seq: self.engine_seq,
-                    event: Event::Fill(fill),
-                    ts,
+                fill.maker_subaccount_id = maker.map_or(0, |(subaccount_id, _)| subaccount_id);
+                fill.taker_subaccount_id = taker.map_or(0, |(subaccount_id, _)| subaccount_id);
+                for (order_id, remaining) in [
+                    (fill.maker_order_id, &mut fill.maker_remaining_qty),
+                    (fill.taker_order_id, &mut fill.taker_remaining_qty),
+                ] {
+                    let open = open_qty.entry(order_id).or_default();
+                    *open = open.saturating_sub(fill.qty);
+                    *remaining = *open;
                 }
+                let maker = maker.map(|(subaccount_id, side)| {
+                    (subaccount_id, side, self.apply_party_fill(market, subaccount_id, side, &fill, maker_fee))
+                });
+                let taker = taker.map(|(subaccount_id, side)| {
+                    (subaccount_id, side, self.apply_party_fill(market, subaccount_id, side, &fill, taker_fee))
+                });
+                self.fill_events(fill, maker, taker)
             })
             .collect()
     }
 
+    /// Books one party's side of `fill` and returns what it did to the party's position.
+    fn apply_party_fill(&mut self, market: &MarketConfig, subaccount_id: SubaccountId, side: Side, fill: &Fill, fee: i64) -> PositionChange {
+        let change = self.risk.apply_fill(market, subaccount_id, side, fill.price_ticks, fill.qty, fee);
+        self.refresh_adl(market.market_id, subaccount_id);
+        change
+    }
+
     fn book_delta_from_snapshot(&self, market_id: MarketId, snapshot: crate::matching::orderbook::BookSnapshot, ts: u64) -> EventEnvelope {
         let bids_levels = snapshot
             .bids
@@ -424,8 +2906,131 @@ impl EngineShard {
                 ts,
             }),
             ts,
+            ts_ns: self.ts_ns,
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+}
+
+/// Keeps only the last `BookDelta` per market (the one with the highest `engine_seq`), leaving it
+/// at its original position; every other event passes through in order. Deltas carry full depth,
+/// so dropping the intermediate ones loses nothing for consumers.
+pub fn coalesce_book_deltas(outputs: Vec<EventEnvelope>) -> Vec<EventEnvelope> {
+    coalesce_book_deltas_by(outputs, |env| env)
+}
+
+/// [`coalesce_book_deltas`] for outputs carried alongside other data.
+pub fn coalesce_book_deltas_by<T>(outputs: Vec<T>, envelope: impl Fn(&T) -> &EventEnvelope) -> Vec<T> {
+    let mut seen = std::collections::HashSet::new();
+    let mut kept: Vec<T> = outputs
+        .into_iter()
+        .rev()
+        .filter(|item| match &envelope(item).event {
+            Event::BookDelta(delta) => seen.insert(delta.market_id),
+            _ => true,
+        })
+        .collect();
+    kept.reverse();
+    kept
+}
+
+/// Fixed-size dedupe key for a request id, so the hot path never clones the id string.
+fn request_key(request_id: &str) -> u128 {
+    let hash = blake3::hash(request_id.as_bytes());
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&hash.as_bytes()[..16]);
+    u128::from_le_bytes(key)
+}
+
+/// Dedupe key for a subaccount's signed nonce.
+fn nonce_key(subaccount_id: SubaccountId, nonce: u64) -> u128 {
+    ((subaccount_id as u128) << 64) | nonce as u128
+}
+
+/// The engine clock ticks in seconds; sub-second config values round up to the next tick.
+/// Each shard numbers orders from its own 2^48-wide range so ids stay unique when a market (and
+/// its resting orders) moves between shards.
+pub(crate) fn first_order_id(shard_id: usize) -> OrderId {
+    ((shard_id as u64) << 48) + 1
+}
+
+/// Trades are numbered from the same per-shard ranges as orders, in their own sequence, so a
+/// `trade_id` is unique across shards and increases with every fill a shard produces.
+pub(crate) fn first_trade_id(shard_id: usize) -> u64 {
+    first_order_id(shard_id)
+}
+
+/// What the order's own fills and last update in `events` say about its immediate execution:
+/// quantity filled, quantity still working and the resulting disposition.
+fn execution_outcome(events: &[EventEnvelope], order_id: OrderId) -> (Quantity, Quantity, OrderDisposition) {
+    let mut filled_qty = 0;
+    let mut outcome = (0, OrderDisposition::Cancelled);
+    for envelope in events {
+        match &envelope.event {
+            Event::Fill(fill) if fill.taker_order_id == order_id => filled_qty += fill.qty,
+            Event::OrderUpdate(update) if update.order_id == order_id => {
+                outcome = match update.status {
+                    OrderUpdateStatus::Open | OrderUpdateStatus::PartiallyFilled => (update.remaining_qty, OrderDisposition::Rested),
+                    OrderUpdateStatus::Filled => (0, OrderDisposition::Filled),
+                    OrderUpdateStatus::Cancelled | OrderUpdateStatus::Expired => (0, OrderDisposition::Cancelled),
+                };
+            }
+            _ => {}
         }
     }
+    (filled_qty, outcome.0, outcome.1)
+}
+
+fn set_ack_outcome(envelope: &mut EventEnvelope, filled_qty: Quantity, remaining_qty: Quantity, disposition: OrderDisposition) {
+    if let Event::OrderAck(ack) = &mut envelope.event {
+        ack.filled_qty = filled_qty;
+        ack.remaining_qty = remaining_qty;
+        ack.disposition = Some(disposition);
+    }
+}
+
+fn ms_to_clock(ms: u64) -> u64 {
+    ms.div_ceil(1000)
+}
+
+/// Clearing time for the auction opening at `ts`: one batch interval later plus a jitter in
+/// `[0, clearing_jitter_ms]` derived from the seed, market and round, so it is unpredictable to
+/// participants but identical on replay.
+fn next_clearing_time(seed: u64, market: &MarketState, ts: u64) -> u64 {
+    let max_jitter = ms_to_clock(market.config.clearing_jitter_ms);
+    let jitter = if max_jitter == 0 {
+        0
+    } else {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&seed.to_le_bytes());
+        hasher.update(&market.config.market_id.to_le_bytes());
+        hasher.update(&market.auction_round.to_le_bytes());
+        let digest = hasher.finalize();
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&digest.as_bytes()[..8]);
+        u64::from_le_bytes(word) % max_jitter.saturating_add(1)
+    };
+    ts.saturating_add(ms_to_clock(market.config.batch_interval_ms)).saturating_add(jitter)
+}
+
+/// Price at which a position of `size` leaves a subaccount with `equity` at `mark` with no equity
+/// at all; 0 if no positive price does.
+fn bankruptcy_price(mark: PriceTicks, equity: i64, size: i64) -> PriceTicks {
+    if size == 0 {
+        return mark;
+    }
+    let price = i128::from(mark) - i128::from(equity) / i128::from(size);
+    price.clamp(0, i128::from(PriceTicks::MAX)) as PriceTicks
+}
+
+/// Loss beyond the bankruptcy price of closing `qty` at `price_ticks`: selling below it when
+/// closing a long, buying above it when closing a short.
+fn bankruptcy_shortfall(side: Side, bankruptcy_price: PriceTicks, price_ticks: PriceTicks, qty: Quantity) -> u128 {
+    let per_unit = match side {
+        Side::Sell => bankruptcy_price.saturating_sub(price_ticks),
+        Side::Buy => price_ticks.saturating_sub(bankruptcy_price),
+    };
+    u128::from(per_unit) * u128::from(qty)
 }
 
 fn fee_for(qty: u64, price_ticks: u64, fee_bps: i64) -> i64 {
@@ -0,0 +1,124 @@
+//! Realized volatility and the risk parameters that follow it.
+//!
+//! A market with a [`DynamicBandConfig`] or [`DynamicMarginConfig`] keeps its most recent marks.
+//! The root mean square of the last `window` mark-to-mark returns, in basis points, is the realized
+//! volatility per mark update. The acceptance band is `multiplier` times that volatility, held
+//! between `min_band_bps` and `max_band_bps`, so a fast market widens its band instead of rejecting
+//! orders at prices it has just traded through. Until the first return is observed the market
+//! keeps its static `price_band_bps`, clamped the same way. Initial margin follows the same rule
+//! with its own window and bounds, but only moves on its schedule.
+
+use std::collections::VecDeque;
+
+use crate::config::{DynamicBandConfig, DynamicMarginConfig, MarketConfig};
+use crate::models::PriceTicks;
+
+/// A market's most recent marks, enough to measure `capacity` returns.
+#[derive(Debug, Clone)]
+pub struct RealizedVolatility {
+    capacity: usize,
+    marks: VecDeque<PriceTicks>,
+}
+
+impl RealizedVolatility {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            marks: VecDeque::new(),
+        }
+    }
+
+    /// Returns the market needs to keep for its dynamic parameters, or `None` if it has none.
+    pub fn capacity_for(config: &MarketConfig) -> Option<usize> {
+        let band = config.dynamic_band.as_ref().map(|band| band.window);
+        let margin = config.dynamic_margin.as_ref().map(|margin| margin.window);
+        band.max(margin).map(|window| window.max(1))
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn record(&mut self, mark: PriceTicks) {
+        if mark == 0 {
+            return;
+        }
+        self.marks.push_back(mark);
+        while self.marks.len() > self.capacity + 1 {
+            self.marks.pop_front();
+        }
+    }
+
+    /// Marks currently held, oldest first.
+    pub fn marks(&self) -> impl Iterator<Item = PriceTicks> + '_ {
+        self.marks.iter().copied()
+    }
+
+    /// Root mean square of the last `window` returns in basis points; `None` before two marks are
+    /// seen.
+    pub fn vol_bps(&self, window: usize) -> Option<u64> {
+        let held = self.marks.len().checked_sub(1).filter(|returns| *returns > 0)?;
+        let returns = held.min(window.max(1));
+        let recent = self.marks.iter().skip(held - returns);
+        let sum_squares: u128 = recent
+            .clone()
+            .zip(recent.skip(1))
+            .map(|(previous, mark)| {
+                let change = u128::from(mark.abs_diff(*previous)) * 10_000 / u128::from(*previous);
+                change * change
+            })
+            .sum();
+        let vol = (sum_squares / returns as u128).isqrt();
+        Some(vol.min(u128::from(u64::MAX)) as u64)
+    }
+}
+
+/// The band a market accepts orders in, given its static band and realized volatility.
+pub fn band_bps(config: &DynamicBandConfig, static_band_bps: u64, vol_bps: Option<u64>) -> u64 {
+    let band = vol_bps.map_or(static_band_bps, |vol| vol.saturating_mul(config.multiplier));
+    band.min(config.max_band_bps).max(config.min_band_bps)
+}
+
+/// Initial margin of a market, given its configured margin and realized volatility.
+pub fn initial_margin_bps(config: &DynamicMarginConfig, static_margin_bps: u64, vol_bps: Option<u64>) -> u64 {
+    let margin = vol_bps.map_or(static_margin_bps, |vol| vol.saturating_mul(config.multiplier));
+    margin.min(config.max_margin_bps).max(config.min_margin_bps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DynamicBandConfig {
+        DynamicBandConfig {
+            window: 3,
+            multiplier: 4,
+            min_band_bps: 50,
+            max_band_bps: 2_000,
+        }
+    }
+
+    #[test]
+    fn volatility_is_the_rms_of_the_latest_returns() {
+        let mut volatility = RealizedVolatility::new(3);
+        volatility.record(100);
+        assert_eq!(volatility.vol_bps(3), None);
+        // Returns of 90, 89 and 88bp once the first 10% move has left the window.
+        for mark in [110, 111, 112, 113, 114] {
+            volatility.record(mark);
+        }
+        assert_eq!(volatility.marks().collect::<Vec<_>>(), vec![111, 112, 113, 114]);
+        assert_eq!(volatility.vol_bps(3), Some(89));
+        // 10_000 / 113 is 88.5bp.
+        assert_eq!(volatility.vol_bps(1), Some(88));
+    }
+
+    #[test]
+    fn band_scales_with_volatility_within_bounds() {
+        assert_eq!(band_bps(&config(), 500, None), 500);
+        assert_eq!(band_bps(&config(), 5_000, None), 2_000);
+        assert_eq!(band_bps(&config(), 500, Some(100)), 400);
+        assert_eq!(band_bps(&config(), 500, Some(0)), 50);
+        assert_eq!(band_bps(&config(), 500, Some(1_000)), 2_000);
+    }
+}
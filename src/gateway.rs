@@ -0,0 +1,285 @@
+//! REST order gateway over the [`ClobClient`].
+//!
+//! `POST /orders` takes a JSON order, validates it with [`NewOrderBuilder`](crate::models::NewOrderBuilder),
+//! publishes it as a protobuf input and answers with the engine's correlated `OrderAck`.
+//! `POST /cancels` publishes a cancel and answers `202 Accepted`; cancels are not acked, their
+//! outcome arrives as an `OrderUpdate`.
+//!
+//! [`private_router`] adds `GET /private`, a WebSocket carrying one subaccount's private outputs
+//! (acks, user fills, position updates and margin warnings) as JSON events. The caller proves it
+//! may read them with a bearer token granted that subaccount, or with the subaccount's order-signing
+//! key over [`stream_auth_payload`](crate::engine::signatures::stream_auth_payload).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use ed25519_dalek::VerifyingKey;
+use futures::StreamExt;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{Bus, BusSubscription};
+use crate::client::ClobClient;
+use crate::config::{SigningKeyConfig, WireCodec};
+use crate::engine::clock::{Clock, SystemClock};
+use crate::engine::router::{account_subject, decode_output_with};
+use crate::engine::signatures::{self, verify_stream_auth};
+use crate::models::{
+    CancelOrder, MarketId, NewOrder, OrderBuildError, OrderId, OrderTrigger, OrderType, PriceTicks, Quantity, Side, SubaccountId,
+    TimeInForce,
+};
+
+/// JSON body of `POST /orders`. Optional fields default as in [`NewOrder::builder`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct OrderRequest {
+    pub request_id: String,
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    pub side: Side,
+    #[serde(default)]
+    pub order_type: Option<OrderType>,
+    #[serde(default)]
+    pub tif: Option<TimeInForce>,
+    #[serde(default)]
+    pub price_ticks: PriceTicks,
+    pub qty: Quantity,
+    #[serde(default)]
+    pub reduce_only: bool,
+    #[serde(default)]
+    pub expiry_ts: u64,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub client_ts: u64,
+    #[serde(default)]
+    pub trigger: Option<OrderTrigger>,
+    /// Ed25519 signature as a byte array, as in the JSON wire codec.
+    #[serde(default)]
+    pub signature: bytes::Bytes,
+}
+
+/// JSON body of `POST /cancels`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CancelRequest {
+    pub request_id: String,
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    #[serde(default)]
+    pub order_id: Option<OrderId>,
+    #[serde(default)]
+    pub nonce_start: Option<u64>,
+    #[serde(default)]
+    pub nonce_end: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Clone)]
+struct GatewayState {
+    client: Arc<ClobClient>,
+    ack_timeout: Duration,
+}
+
+pub fn router(client: Arc<ClobClient>, ack_timeout: Duration) -> Router {
+    Router::new()
+        .route("/orders", post(submit_order))
+        .route("/cancels", post(cancel_order))
+        .with_state(GatewayState { client, ack_timeout })
+}
+
+impl OrderRequest {
+    fn into_order(self) -> Result<NewOrder, OrderBuildError> {
+        let mut builder = NewOrder::builder()
+            .request_id(self.request_id)
+            .market_id(self.market_id)
+            .subaccount_id(self.subaccount_id)
+            .side(self.side)
+            .price_ticks(self.price_ticks)
+            .qty(self.qty)
+            .reduce_only(self.reduce_only)
+            .expiry_ts(self.expiry_ts)
+            .nonce(self.nonce)
+            .client_ts(self.client_ts)
+            .signature(self.signature);
+        if let Some(order_type) = self.order_type {
+            builder = builder.order_type(order_type);
+        }
+        if let Some(tif) = self.tif {
+            builder = builder.tif(tif);
+        }
+        if let Some(trigger) = self.trigger {
+            builder = builder.trigger(trigger);
+        }
+        builder.build()
+    }
+}
+
+async fn submit_order(State(state): State<GatewayState>, Json(request): Json<OrderRequest>) -> Response {
+    let order = match request.into_order() {
+        Ok(order) => order,
+        Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    match state.client.submit_order(order, state.ack_timeout).await {
+        Ok(ack) => Json(ack).into_response(),
+        Err(err) => error(StatusCode::GATEWAY_TIMEOUT, err.to_string()),
+    }
+}
+
+async fn cancel_order(State(state): State<GatewayState>, Json(request): Json<CancelRequest>) -> Response {
+    let cancel = CancelOrder {
+        request_id: request.request_id,
+        market_id: request.market_id,
+        subaccount_id: request.subaccount_id,
+        order_id: request.order_id,
+        nonce_start: request.nonce_start,
+        nonce_end: request.nonce_end,
+    };
+    match state.client.cancel_order(cancel).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(err) => error(StatusCode::BAD_GATEWAY, err.to_string()),
+    }
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    (status, Json(ErrorBody { error })).into_response()
+}
+
+/// Kinds of private output a stream forwards, as named in [`account_subject`].
+const PRIVATE_KINDS: [&str; 4] = ["acks", "fills", "positions", "margin"];
+
+/// Query of `GET /private`: the subaccount, and either `token` or both `ts` and `signature`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StreamRequest {
+    pub subaccount_id: SubaccountId,
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Seconds since the epoch the signature was made at.
+    #[serde(default)]
+    pub ts: Option<u64>,
+    /// Hex-encoded Ed25519 signature over `stream_auth_payload(subaccount_id, ts)`.
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// Who may open which subaccount's private stream.
+#[derive(Debug, Default)]
+pub struct StreamAuth {
+    /// Bearer tokens, each granting one subaccount.
+    pub tokens: HashMap<String, SubaccountId>,
+    /// Order-signing keys, kept current with [`upsert_key`](Self::upsert_key).
+    keys: RwLock<HashMap<SubaccountId, VerifyingKey>>,
+    /// How far a signature's `ts` may be from the gateway's clock.
+    pub max_skew_secs: u64,
+}
+
+impl StreamAuth {
+    pub fn new(tokens: HashMap<String, SubaccountId>, max_skew_secs: u64) -> Self {
+        Self {
+            tokens,
+            keys: RwLock::default(),
+            max_skew_secs,
+        }
+    }
+
+    /// Registers a subaccount's signing key like the engine does; an empty key removes it.
+    pub fn upsert_key(&self, key: &SigningKeyConfig) -> anyhow::Result<()> {
+        if key.public_key.is_empty() {
+            self.keys.write().remove(&key.subaccount_id);
+        } else {
+            self.keys.write().insert(key.subaccount_id, signatures::parse_public_key(&key.public_key)?);
+        }
+        Ok(())
+    }
+
+    /// Why `request` may not open its subaccount's stream at `now` (seconds), if it may not.
+    pub fn authenticate(&self, request: &StreamRequest, now: u64) -> Result<(), String> {
+        if let Some(token) = &request.token {
+            return match self.tokens.get(token) {
+                Some(&subaccount_id) if subaccount_id == request.subaccount_id => Ok(()),
+                _ => Err(format!("token does not grant subaccount {}", request.subaccount_id)),
+            };
+        }
+        let (Some(ts), Some(signature)) = (request.ts, request.signature.as_deref()) else {
+            return Err("pass either token, or ts and signature".to_string());
+        };
+        if ts.abs_diff(now) > self.max_skew_secs {
+            return Err(format!("ts {ts} is more than {}s from the gateway clock", self.max_skew_secs));
+        }
+        let signature: [u8; 64] = signatures::decode_hex(signature).map_err(|_| "signature must be 128 hex characters".to_string())?;
+        let keys = self.keys.read();
+        let key = keys
+            .get(&request.subaccount_id)
+            .ok_or_else(|| format!("subaccount {} has no signing key", request.subaccount_id))?;
+        if !verify_stream_auth(key, request.subaccount_id, ts, &signature) {
+            return Err("signature does not verify".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Where private streams read from: the bus carrying `bus.account_subject` and its codec.
+pub struct PrivateStreams {
+    pub bus: Arc<dyn Bus>,
+    pub account_subject: String,
+    pub codec: WireCodec,
+    pub auth: StreamAuth,
+}
+
+pub fn private_router(streams: Arc<PrivateStreams>) -> Router {
+    Router::new().route("/private", get(open_private_stream)).with_state(streams)
+}
+
+async fn open_private_stream(
+    State(streams): State<Arc<PrivateStreams>>,
+    Query(request): Query<StreamRequest>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    if let Err(err) = streams.auth.authenticate(&request, SystemClock.now()) {
+        return error(StatusCode::UNAUTHORIZED, err);
+    }
+    // Subscribed before the upgrade is answered, so the client misses nothing published after it
+    // sees the connection open.
+    let mut subscriptions = Vec::with_capacity(PRIVATE_KINDS.len());
+    for kind in PRIVATE_KINDS {
+        let subject = account_subject(&streams.account_subject, request.subaccount_id, kind);
+        match streams.bus.subscribe_ephemeral(&subject).await {
+            Ok(subscription) => subscriptions.push(subscription),
+            Err(err) => return error(StatusCode::BAD_GATEWAY, err.to_string()),
+        }
+    }
+    let codec = streams.codec;
+    upgrade.on_upgrade(move |socket| forward_private(socket, subscriptions, codec))
+}
+
+/// Sends each private output as a JSON [`Event`](crate::models::Event) text message until the
+/// client goes away.
+async fn forward_private(mut socket: WebSocket, subscriptions: Vec<BusSubscription>, codec: WireCodec) {
+    let mut outputs = futures::stream::select_all(subscriptions.into_iter().map(|subscription| subscription.stream));
+    loop {
+        tokio::select! {
+            output = outputs.next() => {
+                let Some(output) = output else { break };
+                let Ok(event) = decode_output_with(codec, output.payload) else { continue };
+                let Ok(text) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                if matches!(incoming, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    break;
+                }
+            }
+        }
+    }
+}
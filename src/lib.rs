@@ -1,12 +1,39 @@
+//! Without default features the crate is the deterministic engine alone: matching, risk, the
+//! shard state machine and the domain types, with the input log behind [`engine::EventLog`].
+//! `proto` adds the protobuf wire types, `bus` the NATS and in-memory buses, `persistence` the WAL,
+//! snapshots and retention (the two together also build the router), `prometheus` the metrics
+//! exporter, and `service` all of them plus the simulator and the binaries.
+
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "bus")]
 pub mod bus;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod config;
 pub mod engine;
+#[cfg(feature = "gateway")]
+pub mod gateway;
 pub mod matching;
 pub mod models;
 pub mod persistence;
+#[cfg(feature = "query")]
+pub mod query;
 pub mod risk;
+#[cfg(feature = "service")]
+pub mod sim;
 
+#[cfg(feature = "prometheus")]
 pub mod metrics;
+#[cfg(feature = "bus")]
 pub mod market_registry;
+#[cfg(feature = "bus")]
+pub mod account_registry;
+#[cfg(feature = "bus")]
+pub mod key_registry;
+#[cfg(feature = "bus")]
+pub mod permission_registry;
+#[cfg(feature = "bus")]
+pub mod subaccount_registry;
 
 pub use models::{Event, EventEnvelope, MarketId, OrderId, PriceTicks, Quantity, ShardId, SubaccountId};
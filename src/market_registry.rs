@@ -1,6 +1,8 @@
+use bytes::Bytes;
 use futures::TryStreamExt;
 
 use crate::config::MarketConfig;
+use crate::models::{MarketId, RiskParamsUpdate};
 
 pub async fn load_all(nats_url: &str, bucket: &str) -> anyhow::Result<Vec<MarketConfig>> {
     let client = async_nats::connect(nats_url).await?;
@@ -85,3 +87,140 @@ pub async fn watch_updates_tx(
     }
     Ok(())
 }
+
+/// Risk parameter updates stored in a JetStream KV bucket (key = market_id, value =
+/// `RiskParamsUpdate` JSON), applied over the markets' configs at startup.
+pub async fn load_risk_params(nats_url: &str, bucket: &str) -> anyhow::Result<Vec<RiskParamsUpdate>> {
+    let client = async_nats::connect(nats_url).await?;
+    let jetstream = async_nats::jetstream::new(client);
+    let kv = jetstream
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: bucket.to_string(),
+            history: 1,
+            storage: async_nats::jetstream::stream::StorageType::File,
+            ..Default::default()
+        })
+        .await?;
+
+    let keys = kv.keys().await?.try_collect::<Vec<String>>().await?;
+    let mut out = Vec::with_capacity(keys.len());
+    for key in keys {
+        if let Some(value) = kv.get(key).await? {
+            let update: RiskParamsUpdate = serde_json::from_slice(&value)?;
+            out.push(update);
+        }
+    }
+    Ok(out)
+}
+
+pub async fn watch_risk_params_tx(
+    nats_url: String,
+    bucket: String,
+    tx: tokio::sync::mpsc::Sender<RiskParamsUpdate>,
+) -> anyhow::Result<()> {
+    use futures::StreamExt;
+
+    let client = async_nats::connect(nats_url).await?;
+    let jetstream = async_nats::jetstream::new(client);
+    let kv = jetstream
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket,
+            history: 1,
+            storage: async_nats::jetstream::stream::StorageType::File,
+            ..Default::default()
+        })
+        .await?;
+
+    let mut watch = kv.watch_all().await?;
+    while let Some(entry) = watch.next().await {
+        let entry = entry?;
+        if entry.operation != async_nats::jetstream::kv::Operation::Put {
+            continue;
+        }
+        let update: RiskParamsUpdate = serde_json::from_slice(&entry.value)?;
+        if tx.send(update).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn open_markets(nats_url: &str, bucket: &str) -> anyhow::Result<async_nats::jetstream::kv::Store> {
+    let client = async_nats::connect(nats_url).await?;
+    let jetstream = async_nats::jetstream::new(client);
+    let kv = jetstream
+        .create_key_value(async_nats::jetstream::kv::Config {
+            bucket: bucket.to_string(),
+            history: 1,
+            storage: async_nats::jetstream::stream::StorageType::File,
+            ..Default::default()
+        })
+        .await?;
+    Ok(kv)
+}
+
+/// The market stored under `market_id`, if any.
+pub async fn get_market(nats_url: &str, bucket: &str, market_id: MarketId) -> anyhow::Result<Option<MarketConfig>> {
+    let kv = open_markets(nats_url, bucket).await?;
+    match kv.get(market_id.to_string()).await? {
+        Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Validates `market` and writes it under its id, returning the entry's revision. With `create`
+/// the write fails if the market already exists.
+pub async fn put_market(nats_url: &str, bucket: &str, market: &MarketConfig, create: bool) -> anyhow::Result<u64> {
+    market.validate().map_err(|err| anyhow::anyhow!("market {}: {err}", market.market_id))?;
+    let kv = open_markets(nats_url, bucket).await?;
+    let key = market.market_id.to_string();
+    let value = Bytes::from(serde_json::to_vec(market)?);
+    let revision = if create {
+        kv.create(&key, value).await.map_err(|err| anyhow::anyhow!("market {} not created: {err}", market.market_id))?
+    } else {
+        kv.put(&key, value).await?
+    };
+    Ok(revision)
+}
+
+/// Removes the market's entry; false if there was none. Shards keep a market they already list
+/// until they restart.
+pub async fn delete_market(nats_url: &str, bucket: &str, market_id: MarketId) -> anyhow::Result<bool> {
+    let kv = open_markets(nats_url, bucket).await?;
+    let key = market_id.to_string();
+    if kv.get(&key).await?.is_none() {
+        return Ok(false);
+    }
+    kv.delete(&key).await?;
+    Ok(true)
+}
+
+/// `market` with each `path=value` assignment applied, e.g. `taker_fee_bps=5` or
+/// `funding.interval_secs=3600`. Values are parsed as JSON and fall back to plain strings; dotted
+/// paths reach into nested settings, creating them if unset. The result is not validated.
+pub fn set_fields(market: &MarketConfig, assignments: &[String]) -> anyhow::Result<MarketConfig> {
+    let mut value = serde_json::to_value(market)?;
+    for assignment in assignments {
+        let (path, raw) = assignment
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("`{assignment}` is not of the form field=value"))?;
+        let parsed = serde_json::from_str(raw).unwrap_or_else(|_| serde_json::Value::String(raw.to_string()));
+        let mut target = &mut value;
+        for field in path.split('.') {
+            if target.is_null() {
+                *target = serde_json::Value::Object(Default::default());
+            }
+            let object = target
+                .as_object_mut()
+                .ok_or_else(|| anyhow::anyhow!("`{path}` does not name a market setting"))?;
+            target = object.entry(field.to_string()).or_insert(serde_json::Value::Null);
+        }
+        *target = parsed;
+    }
+    let updated: MarketConfig = serde_json::from_value(value)?;
+    anyhow::ensure!(
+        updated.market_id == market.market_id,
+        "market_id cannot be changed; create the new market and delete this one"
+    );
+    Ok(updated)
+}
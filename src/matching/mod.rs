@@ -1,5 +1,13 @@
-pub mod orderbook;
-pub mod batch;
+//! The engine's only matching implementation: the continuous order book and the batch auction,
+//! plus the trigger index that feeds conditional orders into them. The book and the auction live
+//! in the `no_std` `hypermarket-clob-core` crate and are re-exported here unchanged.
+
+pub use hypermarket_clob_core::{batch, levels, orderbook};
+pub mod triggers;
+
+pub use batch::{BatchAuction, ClearingResult, IndicativeAuction};
+pub use orderbook::{BookSnapshot, IncomingOrder, OrderBook, OrderView};
+pub use triggers::TriggerIndex;
 
 use crate::models::{Fill, OrderId};
 
@@ -0,0 +1,121 @@
+//! Price-ordered index of conditional-order triggers for one market.
+//!
+//! Triggers are bucketed by reference source and direction. Buy stops fire when the reference
+//! rises to their trigger price and sell stops when it falls to it, so each bucket is an ordered
+//! set and a price move only visits the entries it actually crossed.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::models::{OrderId, OrderTrigger, PriceTicks, Side, TriggerSource};
+
+const SOURCES: [TriggerSource; 3] = [TriggerSource::Mark, TriggerSource::Index, TriggerSource::LastTrade];
+
+fn source_slot(source: TriggerSource) -> usize {
+    match source {
+        TriggerSource::Mark => 0,
+        TriggerSource::Index => 1,
+        TriggerSource::LastTrade => 2,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TriggerIndex {
+    /// Buy triggers per source, fired by `reference >= trigger_price`.
+    rising: [BTreeSet<(PriceTicks, OrderId)>; 3],
+    /// Sell triggers per source, fired by `reference <= trigger_price`.
+    falling: [BTreeSet<(PriceTicks, OrderId)>; 3],
+    entries: HashMap<OrderId, (Side, OrderTrigger)>,
+}
+
+impl TriggerIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, order_id: OrderId) -> bool {
+        self.entries.contains_key(&order_id)
+    }
+
+    pub fn insert(&mut self, order_id: OrderId, side: Side, trigger: OrderTrigger) {
+        let key = (trigger.trigger_price, order_id);
+        let slot = source_slot(trigger.source);
+        match side {
+            Side::Buy => self.rising[slot].insert(key),
+            Side::Sell => self.falling[slot].insert(key),
+        };
+        self.entries.insert(order_id, (side, trigger));
+    }
+
+    pub fn remove(&mut self, order_id: OrderId) -> bool {
+        let Some((side, trigger)) = self.entries.remove(&order_id) else {
+            return false;
+        };
+        let key = (trigger.trigger_price, order_id);
+        let slot = source_slot(trigger.source);
+        match side {
+            Side::Buy => self.rising[slot].remove(&key),
+            Side::Sell => self.falling[slot].remove(&key),
+        };
+        true
+    }
+
+    /// Removes and returns every trigger crossed by the current reference prices, ordered by
+    /// order id (acceptance order) so activation does not depend on which source fired first.
+    pub fn take_triggered(&mut self, reference: impl Fn(TriggerSource) -> Option<PriceTicks>) -> Vec<OrderId> {
+        let mut fired = Vec::new();
+        for source in SOURCES {
+            let Some(price) = reference(source) else {
+                continue;
+            };
+            let slot = source_slot(source);
+            fired.extend(self.rising[slot].range(..=(price, OrderId::MAX)).map(|(_, id)| *id));
+            fired.extend(self.falling[slot].range((price, 0)..).map(|(_, id)| *id));
+        }
+        fired.sort_unstable();
+        for order_id in &fired {
+            self.remove(*order_id);
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(trigger_price: PriceTicks, source: TriggerSource) -> OrderTrigger {
+        OrderTrigger { trigger_price, source }
+    }
+
+    fn prices(mark: PriceTicks, last: PriceTicks) -> impl Fn(TriggerSource) -> Option<PriceTicks> {
+        move |source| match source {
+            TriggerSource::Mark => Some(mark),
+            TriggerSource::Index => None,
+            TriggerSource::LastTrade => Some(last),
+        }
+    }
+
+    #[test]
+    fn only_crossed_triggers_fire_in_id_order() {
+        let mut index = TriggerIndex::new();
+        index.insert(3, Side::Buy, trigger(105, TriggerSource::Mark));
+        index.insert(1, Side::Buy, trigger(110, TriggerSource::Mark));
+        index.insert(2, Side::Sell, trigger(95, TriggerSource::LastTrade));
+        index.insert(4, Side::Sell, trigger(90, TriggerSource::Mark));
+
+        assert!(index.take_triggered(prices(100, 100)).is_empty());
+        assert_eq!(index.take_triggered(prices(106, 95)), vec![2, 3]);
+        assert_eq!(index.take_triggered(prices(80, 95)), vec![4]);
+        assert_eq!(index.len(), 1);
+        assert!(index.remove(1));
+        assert!(index.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use bytes::Bytes;
+
+use crate::models::{MarketId, NewOrder, OrderTrigger, OrderType, PriceTicks, Quantity, Side, SubaccountId, TimeInForce};
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum OrderBuildError {
+    #[error("missing required field `{0}`")]
+    MissingField(&'static str),
+    #[error("request_id must not be empty")]
+    EmptyRequestId,
+    #[error("qty must be greater than zero")]
+    ZeroQty,
+    #[error("{0:?} orders require a non-zero price")]
+    MissingPrice(OrderType),
+    #[error("trigger price must be greater than zero")]
+    ZeroTriggerPrice,
+    #[error("good-til-date orders require a non-zero expiry")]
+    MissingExpiry,
+    #[error("{order_type:?} orders cannot use time-in-force {tif:?}")]
+    IncompatibleTif { order_type: OrderType, tif: TimeInForce },
+}
+
+/// Builder for [`NewOrder`] that checks invariants once, at construction time.
+///
+/// When no time-in-force is given it is derived from the order type (`Ioc`/`Market` → IOC,
+/// `Fok` → FOK, otherwise GTC).
+#[derive(Debug, Clone, Default)]
+pub struct NewOrderBuilder {
+    request_id: Option<String>,
+    market_id: Option<MarketId>,
+    subaccount_id: Option<SubaccountId>,
+    side: Option<Side>,
+    order_type: Option<OrderType>,
+    tif: Option<TimeInForce>,
+    price_ticks: PriceTicks,
+    qty: Quantity,
+    reduce_only: bool,
+    expiry_ts: u64,
+    nonce: u64,
+    client_ts: u64,
+    trigger: Option<OrderTrigger>,
+    signature: Bytes,
+}
+
+impl NewOrder {
+    pub fn builder() -> NewOrderBuilder {
+        NewOrderBuilder::default()
+    }
+}
+
+impl NewOrderBuilder {
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    pub fn market_id(mut self, market_id: MarketId) -> Self {
+        self.market_id = Some(market_id);
+        self
+    }
+
+    pub fn subaccount_id(mut self, subaccount_id: SubaccountId) -> Self {
+        self.subaccount_id = Some(subaccount_id);
+        self
+    }
+
+    pub fn side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    pub fn order_type(mut self, order_type: OrderType) -> Self {
+        self.order_type = Some(order_type);
+        self
+    }
+
+    pub fn tif(mut self, tif: TimeInForce) -> Self {
+        self.tif = Some(tif);
+        self
+    }
+
+    pub fn price_ticks(mut self, price_ticks: PriceTicks) -> Self {
+        self.price_ticks = price_ticks;
+        self
+    }
+
+    /// Shorthand for a limit order at `price_ticks`.
+    pub fn limit(self, price_ticks: PriceTicks) -> Self {
+        self.order_type(OrderType::Limit).price_ticks(price_ticks)
+    }
+
+    /// Shorthand for a market order.
+    pub fn market(self) -> Self {
+        self.order_type(OrderType::Market)
+    }
+
+    pub fn qty(mut self, qty: Quantity) -> Self {
+        self.qty = qty;
+        self
+    }
+
+    pub fn reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = reduce_only;
+        self
+    }
+
+    pub fn expiry_ts(mut self, expiry_ts: u64) -> Self {
+        self.expiry_ts = expiry_ts;
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn client_ts(mut self, client_ts: u64) -> Self {
+        self.client_ts = client_ts;
+        self
+    }
+
+    pub fn trigger(mut self, trigger: OrderTrigger) -> Self {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    pub fn signature(mut self, signature: impl Into<Bytes>) -> Self {
+        self.signature = signature.into();
+        self
+    }
+
+    /// Checks every invariant without consuming the builder.
+    pub fn check(&self) -> Result<(), OrderBuildError> {
+        match self.request_id.as_deref() {
+            None => return Err(OrderBuildError::MissingField("request_id")),
+            Some("") => return Err(OrderBuildError::EmptyRequestId),
+            Some(_) => {}
+        }
+        if self.market_id.is_none() {
+            return Err(OrderBuildError::MissingField("market_id"));
+        }
+        if self.subaccount_id.is_none() {
+            return Err(OrderBuildError::MissingField("subaccount_id"));
+        }
+        if self.side.is_none() {
+            return Err(OrderBuildError::MissingField("side"));
+        }
+        if self.qty == 0 {
+            return Err(OrderBuildError::ZeroQty);
+        }
+        let order_type = self.resolved_order_type();
+        if order_type != OrderType::Market && self.price_ticks == 0 {
+            return Err(OrderBuildError::MissingPrice(order_type));
+        }
+        let tif = self.resolved_tif();
+        let compatible = match order_type {
+            OrderType::Limit => true,
+            OrderType::Market => !tif.rests(),
+            OrderType::PostOnly => tif.rests(),
+            OrderType::Ioc => tif == TimeInForce::Ioc,
+            OrderType::Fok => tif == TimeInForce::Fok,
+        };
+        if !compatible {
+            return Err(OrderBuildError::IncompatibleTif { order_type, tif });
+        }
+        if tif.expires_at() == Some(0) {
+            return Err(OrderBuildError::MissingExpiry);
+        }
+        if self.trigger.is_some_and(|trigger| trigger.trigger_price == 0) {
+            return Err(OrderBuildError::ZeroTriggerPrice);
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<NewOrder, OrderBuildError> {
+        self.check()?;
+        let order_type = self.resolved_order_type();
+        let tif = self.resolved_tif();
+        Ok(NewOrder {
+            request_id: self.request_id.unwrap_or_default(),
+            market_id: self.market_id.unwrap_or_default(),
+            subaccount_id: self.subaccount_id.unwrap_or_default(),
+            side: self.side.unwrap_or(Side::Buy),
+            order_type,
+            tif,
+            price_ticks: self.price_ticks,
+            qty: self.qty,
+            reduce_only: self.reduce_only,
+            expiry_ts: self.expiry_ts,
+            nonce: self.nonce,
+            client_ts: self.client_ts,
+            trigger: self.trigger,
+            signature: self.signature,
+        })
+    }
+
+    /// Gives the request id back when a caller needs it to report a failed [`check`](Self::check).
+    pub fn into_request_id(self) -> String {
+        self.request_id.unwrap_or_default()
+    }
+
+    fn resolved_order_type(&self) -> OrderType {
+        self.order_type.unwrap_or(OrderType::Limit)
+    }
+
+    fn resolved_tif(&self) -> TimeInForce {
+        self.tif.unwrap_or(match self.resolved_order_type() {
+            OrderType::Market | OrderType::Ioc => TimeInForce::Ioc,
+            OrderType::Fok => TimeInForce::Fok,
+            OrderType::Limit | OrderType::PostOnly => TimeInForce::Gtc,
+        })
+    }
+}
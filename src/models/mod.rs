@@ -1,36 +1,52 @@
 use serde::{Deserialize, Serialize};
 
+use crate::config::{MarketConfig, SubaccountConfig};
+
+mod builder;
+
+pub use builder::{NewOrderBuilder, OrderBuildError};
+
+#[cfg(feature = "proto")]
 pub mod pb {
     include!(concat!(env!("OUT_DIR"), "/hypermarket.clob.rs"));
 }
 
-pub type MarketId = u64;
-pub type SubaccountId = u64;
-pub type OrderId = u64;
+#[cfg(feature = "proto")]
+mod proto;
+
+#[cfg(feature = "proto")]
+pub use proto::{ConversionError, DecodeError, InvalidNewOrder};
+
+pub use hypermarket_clob_core::models::{Fill, MarketId, OrderId, OrderType, PriceTicks, Quantity, Side, SubaccountId, TimeInForce};
+
 pub type ShardId = usize;
-pub type PriceTicks = u64;
-pub type Quantity = u64;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum Side {
-    Buy,
-    Sell,
+/// Reference price a conditional order watches. Mark and index come from `PriceUpdate`; last trade
+/// is the price of the most recent book fill in the market.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TriggerSource {
+    #[default]
+    Mark,
+    Index,
+    LastTrade,
 }
 
+/// Holds an order back until its reference price crosses `trigger_price`: upwards for buys,
+/// downwards for sells (stop semantics).
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum OrderType {
-    Limit,
-    Market,
-    PostOnly,
-    Ioc,
-    Fok,
+pub struct OrderTrigger {
+    pub trigger_price: PriceTicks,
+    #[serde(default)]
+    pub source: TriggerSource,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-pub enum TimeInForce {
-    Gtc,
-    Ioc,
-    Fok,
+impl OrderTrigger {
+    pub fn is_triggered(&self, side: Side, reference: PriceTicks) -> bool {
+        match side {
+            Side::Buy => reference >= self.trigger_price,
+            Side::Sell => reference <= self.trigger_price,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -39,6 +55,135 @@ pub enum OrderStatus {
     Rejected,
 }
 
+/// Where an accepted order stood once its immediate execution was done.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderDisposition {
+    /// Quantity is still working: resting in the book, queued for the next auction or waiting on
+    /// its trigger.
+    Rested,
+    Filled,
+    /// The unfilled quantity was cancelled, e.g. the rest of an IOC order.
+    Cancelled,
+}
+
+/// Why an order was rejected. The discriminants are the wire `reject_code` values and form a frozen
+/// registry: a code is never renumbered or reused, and new reasons are added at the end with the
+/// next code. Clients should branch on the code; the text in `reject_reason` may change. Code 0
+/// means the ack is not a reject.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    /// The market is not listed on the shard the input reached.
+    UnknownMarket = 1,
+    /// A post-only order would have taken liquidity.
+    PostOnlyWouldCross = 2,
+    /// The subaccount, or its parent, already has the most resting orders allowed in the market.
+    MaxOpenOrders = 3,
+    /// The price is outside the market's price band around the mark.
+    PriceBand = 4,
+    /// Not enough equity or free collateral for the order, block trade or transfer.
+    InsufficientMargin = 5,
+    /// A reduce-only order would not reduce the position.
+    ReduceOnly = 6,
+    /// The fill would take the subaccount, or its parent, past its position limit.
+    MaxPosition = 7,
+    /// The input is malformed: zero or unaligned quantities or prices, unknown enums, or a request
+    /// that makes no sense (e.g. a transfer to the same subaccount).
+    InvalidOrder = 8,
+    /// The subaccount may not trade in the market, or may not act on the other party.
+    Unauthorized = 9,
+    /// The order's signature is missing or does not verify against the subaccount's key.
+    InvalidSignature = 10,
+    /// The order's nonce is not above the subaccount's last one in the market.
+    StaleNonce = 11,
+    /// The position after the order would exceed the market's leverage cap on the subaccount's
+    /// equity.
+    MaxLeverage = 12,
+    /// Another request already used the subaccount's nonce, in this or another market.
+    DuplicateNonce = 13,
+    /// The order queued longer than the latency budget before its shard got to it.
+    QueueDelay = 14,
+}
+
+impl RejectReason {
+    /// Every registered reason, in code order.
+    pub const ALL: [RejectReason; 14] = [
+        Self::UnknownMarket,
+        Self::PostOnlyWouldCross,
+        Self::MaxOpenOrders,
+        Self::PriceBand,
+        Self::InsufficientMargin,
+        Self::ReduceOnly,
+        Self::MaxPosition,
+        Self::InvalidOrder,
+        Self::Unauthorized,
+        Self::InvalidSignature,
+        Self::StaleNonce,
+        Self::MaxLeverage,
+        Self::DuplicateNonce,
+        Self::QueueDelay,
+    ];
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            1 => Self::UnknownMarket,
+            2 => Self::PostOnlyWouldCross,
+            3 => Self::MaxOpenOrders,
+            4 => Self::PriceBand,
+            5 => Self::InsufficientMargin,
+            6 => Self::ReduceOnly,
+            7 => Self::MaxPosition,
+            8 => Self::InvalidOrder,
+            9 => Self::Unauthorized,
+            10 => Self::InvalidSignature,
+            11 => Self::StaleNonce,
+            12 => Self::MaxLeverage,
+            13 => Self::DuplicateNonce,
+            14 => Self::QueueDelay,
+            _ => return None,
+        })
+    }
+
+    /// Human-readable text sent alongside the code.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UnknownMarket => "unknown market",
+            Self::PostOnlyWouldCross => "post-only would cross",
+            Self::MaxOpenOrders => "max open orders per subaccount",
+            Self::PriceBand => "price band",
+            Self::InsufficientMargin => "insufficient margin",
+            Self::ReduceOnly => "reduce-only",
+            Self::MaxPosition => "max position",
+            Self::InvalidOrder => "invalid order",
+            Self::Unauthorized => "subaccount not permitted in market",
+            Self::InvalidSignature => "invalid or missing order signature",
+            Self::StaleNonce => "nonce already used",
+            Self::MaxLeverage => "max leverage",
+            Self::DuplicateNonce => "nonce reused by another request",
+            Self::QueueDelay => "queued past the latency budget",
+        }
+    }
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Lifecycle state carried by [`OrderUpdate`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OrderUpdateStatus {
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewOrder {
     pub request_id: String,
@@ -53,6 +198,31 @@ pub struct NewOrder {
     pub expiry_ts: u64,
     pub nonce: u64,
     pub client_ts: u64,
+    /// Makes this a conditional order that only enters the book once triggered.
+    #[serde(default)]
+    pub trigger: Option<OrderTrigger>,
+    /// Ed25519 signature over [`signing_payload`](Self::signing_payload). Checked before the order
+    /// is logged, so it is not written to the WAL.
+    #[serde(skip)]
+    pub signature: bytes::Bytes,
+}
+
+impl NewOrder {
+    /// Bytes an order signature covers: market, side, price, qty, nonce and expiry, as
+    /// little-endian integers with the side as one byte (0 = buy, 1 = sell).
+    pub fn signing_payload(&self) -> [u8; 41] {
+        let mut payload = [0u8; 41];
+        payload[0..8].copy_from_slice(&self.market_id.to_le_bytes());
+        payload[8] = match self.side {
+            Side::Buy => 0,
+            Side::Sell => 1,
+        };
+        payload[9..17].copy_from_slice(&self.price_ticks.to_le_bytes());
+        payload[17..25].copy_from_slice(&self.qty.to_le_bytes());
+        payload[25..33].copy_from_slice(&self.nonce.to_le_bytes());
+        payload[33..41].copy_from_slice(&self.expiry_ts.to_le_bytes());
+        payload
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,12 +235,67 @@ pub struct CancelOrder {
     pub nonce_end: Option<u64>,
 }
 
+/// A pre-negotiated trade between two subaccounts. It never touches the book but is risk-checked,
+/// charged fees and applied to positions exactly like a matched fill.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockTrade {
+    pub request_id: String,
+    pub market_id: MarketId,
+    pub buyer_subaccount_id: SubaccountId,
+    pub seller_subaccount_id: SubaccountId,
+    pub price_ticks: PriceTicks,
+    pub qty: Quantity,
+}
+
+/// Cancels every order owned by a parent account's subaccounts, in one market or (`market_id` of
+/// `None`) in all of them. The router fans an all-markets cancel out to every shard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MassCancel {
+    pub request_id: String,
+    pub parent_id: u64,
+    pub market_id: Option<MarketId>,
+}
+
+/// Admin request to move a market to `target_shard` without restarting the engine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateMarket {
+    pub request_id: String,
+    pub market_id: MarketId,
+    pub target_shard: ShardId,
+}
+
+/// Moves `amount` of collateral from one subaccount to another of the same parent account. It is
+/// applied to the collateral ledger of the shard owning `market_id`, and the source must keep
+/// enough free collateral there to cover its margin afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub request_id: String,
+    pub market_id: MarketId,
+    pub from_subaccount_id: SubaccountId,
+    pub to_subaccount_id: SubaccountId,
+    pub amount: u64,
+}
+
+/// A market's full engine state in transit between shards: emitted by the source shard as
+/// `MarketExported` and applied on the target as a `MarketImport` input. `state` is opaque to
+/// everything but the shards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketTransfer {
+    pub market_id: MarketId,
+    pub source_shard: ShardId,
+    pub target_shard: ShardId,
+    pub state: Vec<u8>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceUpdate {
     pub market_id: MarketId,
     pub mark_price: PriceTicks,
     pub index_price: PriceTicks,
     pub ts: u64,
+    /// Oracle source reporting the prices; only used by markets with an oracle config.
+    #[serde(default)]
+    pub source: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,25 +309,407 @@ pub struct FundingUpdate {
 pub struct OrderAck {
     pub request_id: String,
     pub status: OrderStatus,
+    pub reject_code: Option<RejectReason>,
     pub reject_reason: Option<String>,
     pub assigned_order_id: Option<OrderId>,
     pub engine_seq: u64,
     pub ts: u64,
+    /// Quantity executed while the order was being accepted.
+    #[serde(default)]
+    pub filled_qty: Quantity,
+    /// Quantity still working afterwards; 0 unless the disposition is `Rested`.
+    #[serde(default)]
+    pub remaining_qty: Quantity,
+    /// Set on accepted orders and block trades.
+    #[serde(default)]
+    pub disposition: Option<OrderDisposition>,
+    /// Subaccount the input was for, if it names one; its private ack subject.
+    #[serde(default)]
+    pub subaccount_id: Option<SubaccountId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderUpdate {
+    pub market_id: MarketId,
+    pub order_id: OrderId,
+    pub subaccount_id: SubaccountId,
+    pub status: OrderUpdateStatus,
+    pub remaining_qty: Quantity,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Records that a conditional order's trigger was crossed and the order was released to matching.
+/// Written to the WAL with the other outputs so replays can be checked activation by activation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderTriggered {
+    pub market_id: MarketId,
+    pub order_id: OrderId,
+    pub subaccount_id: SubaccountId,
+    pub trigger: OrderTrigger,
+    pub reference_price: PriceTicks,
+    pub engine_seq: u64,
+    pub ts: u64,
 }
 
+/// One tranche of a liquidation of a subaccount whose equity fell below its maintenance margin.
+/// `equity` and `maintenance_margin` are the figures that triggered the tranche. Backstop providers
+/// took `backstop_qty` of `qty`; the rest went to the book as the reduce-only market order
+/// `order_id`, which filled `filled_qty`. `fee` is the liquidation fee on the tranche's liquidated
+/// `notional`, credited to the insurance fund. Executions beyond `bankruptcy_price`, where the
+/// subaccount's equity runs out, lose `shortfall`; the insurance fund covers `insurance_covered`
+/// and the remaining `adl_shortfall` is left for auto-deleveraging.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Fill {
+pub struct Liquidation {
     pub market_id: MarketId,
-    pub maker_order_id: OrderId,
-    pub taker_order_id: OrderId,
+    pub subaccount_id: SubaccountId,
+    /// `None` when backstop providers took the whole tranche.
+    pub order_id: Option<OrderId>,
+    pub side: Side,
+    pub qty: Quantity,
+    pub backstop_qty: Quantity,
+    pub filled_qty: Quantity,
+    pub remaining_position: i64,
+    pub notional: i64,
+    pub fee: i64,
+    pub insurance_fund_subaccount_id: Option<SubaccountId>,
+    pub bankruptcy_price: PriceTicks,
+    pub shortfall: i64,
+    pub insurance_covered: i64,
+    pub adl_shortfall: i64,
+    pub equity: i64,
+    pub maintenance_margin: i64,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Liquidation fee charged on one tranche, as reported in a [`SettlementBatch`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidationFee {
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    pub insurance_fund_subaccount_id: SubaccountId,
+    pub notional: i64,
+    pub fee: i64,
+    pub engine_seq: u64,
+}
+
+impl Liquidation {
+    /// The fee this tranche moved to the insurance fund, if any.
+    pub fn fee_record(&self) -> Option<LiquidationFee> {
+        let insurance_fund_subaccount_id = self.insurance_fund_subaccount_id?;
+        Some(LiquidationFee {
+            market_id: self.market_id,
+            subaccount_id: self.subaccount_id,
+            insurance_fund_subaccount_id,
+            notional: self.notional,
+            fee: self.fee,
+            engine_seq: self.engine_seq,
+        })
+    }
+}
+
+/// Part of a liquidation tranche taken over by a backstop provider at mark, outside the book and
+/// without fees. `side` is the provider's side.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackstopAssignment {
+    pub market_id: MarketId,
+    pub liquidated_subaccount_id: SubaccountId,
+    pub provider_subaccount_id: SubaccountId,
+    pub side: Side,
     pub price_ticks: PriceTicks,
     pub qty: Quantity,
-    pub maker_fee: i64,
-    pub taker_fee: i64,
     pub engine_seq: u64,
     pub ts: u64,
 }
 
+/// Composite prices a market with an oracle config moved to, and which sources fed them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexPrice {
+    pub market_id: MarketId,
+    pub mark_price: PriceTicks,
+    pub index_price: PriceTicks,
+    pub sources: Vec<String>,
+    /// Sources left out for being stale or too far from the median.
+    pub stale: Vec<String>,
+    pub outliers: Vec<String>,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// A market risk parameter the engine adjusts by itself or operators update at runtime.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RiskParameter {
+    InitialMarginBps,
+    MaintenanceMarginBps,
+    PriceBandBps,
+    MakerFeeBps,
+    TakerFeeBps,
+    MaxPosition,
+    MaxLeverage,
+}
+
+/// Audit record of a risk parameter change: `parameter` moved from `previous` to `value`. Scheduled
+/// margin changes carry the realized volatility `volatility_bps` behind them (`None` before any
+/// was measured); operator updates carry none.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskParameterChange {
+    pub market_id: MarketId,
+    pub parameter: RiskParameter,
+    pub previous: i64,
+    pub value: i64,
+    pub volatility_bps: Option<u64>,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Operator update of a market's risk parameters, read from the risk-parameters KV bucket (key =
+/// market_id, value = this as JSON) and applied by the owning shard as a logged input. Parameters
+/// left out keep their current value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskParamsUpdate {
+    pub market_id: MarketId,
+    #[serde(default)]
+    pub initial_margin_bps: Option<u64>,
+    #[serde(default)]
+    pub maintenance_margin_bps: Option<u64>,
+    #[serde(default)]
+    pub price_band_bps: Option<u64>,
+    #[serde(default)]
+    pub maker_fee_bps: Option<i64>,
+    #[serde(default)]
+    pub taker_fee_bps: Option<i64>,
+    #[serde(default)]
+    pub max_position: Option<i64>,
+    /// `Some(0)` lifts the market's leverage cap, as in [`crate::config::MarketConfig::max_leverage`].
+    #[serde(default)]
+    pub max_leverage: Option<u64>,
+}
+
+/// Funding the engine settled for a market with scheduled funding: `periods` periods at
+/// `rate_bps` each, moving the market's funding index to `funding_index`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FundingRate {
+    pub market_id: MarketId,
+    pub rate_bps: i64,
+    pub periods: u64,
+    pub mark_price: PriceTicks,
+    pub index_price: PriceTicks,
+    pub funding_index: i64,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// PnL a fill realized by reducing or closing a subaccount's position, with the position left
+/// afterwards (`remaining_size` 0 if closed).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PnlRealized {
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    pub trade_id: u64,
+    pub closed_qty: Quantity,
+    /// Against the entry price before the fill, before fees.
+    pub realized_pnl: i64,
+    pub remaining_size: i64,
+    pub entry_price: PriceTicks,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// A subaccount whose equity on one shard is within `warning_bps` of its maintenance margin, or
+/// already below it, after its positions changed or one of their markets was repriced. It is
+/// published only on that subaccount's own subject.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarginWarning {
+    pub subaccount_id: SubaccountId,
+    pub equity: i64,
+    pub maintenance_margin: i64,
+    pub warning_bps: u64,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// A subaccount's position in one market after a fill, funding settlement or liquidation touched
+/// it, valued at the market's mark price (the entry price if there is none yet). It is published
+/// only on that subaccount's own subject.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PositionUpdate {
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    /// Signed; 0 once the position is closed.
+    pub size: i64,
+    pub entry_price: PriceTicks,
+    pub mark_price: PriceTicks,
+    pub unrealized_pnl: i64,
+    pub initial_margin: i64,
+    pub maintenance_margin: i64,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Funding one subaccount settled on its position in a market, as one line of a [`FundingRate`]
+/// settlement: the position of `position_size` lots accrued from `from_index` to `to_index`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FundingPayment {
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    pub position_size: i64,
+    pub from_index: i64,
+    pub to_index: i64,
+    /// Charged to collateral; negative when the position received funding.
+    pub amount: i64,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Seeds the shard's batch-clearing jitter. The router sends one when a shard starts; it is logged
+/// like any other input so replays reproduce identical clearing times.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ClearingSeed {
+    pub seed: u64,
+}
+
+/// Indicative uncross for a batch market's current collection window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuctionIndicative {
+    pub market_id: MarketId,
+    pub price_ticks: PriceTicks,
+    pub matched_qty: Quantity,
+    pub imbalance_qty: Quantity,
+    pub imbalance_side: Option<Side>,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// A designated maker's quoting record in one market over `[period_start, period_end)`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MakerCompliance {
+    pub market_id: MarketId,
+    pub subaccount_id: SubaccountId,
+    pub period_start: u64,
+    pub period_end: u64,
+    /// Time spent quoting both sides with at least the obligation's minimum size.
+    pub quoted_secs: u64,
+    /// Part of `quoted_secs` where the spread was also within the obligation.
+    pub compliant_secs: u64,
+    pub uptime_bps: u64,
+    /// Time-weighted spread over `quoted_secs`.
+    pub avg_spread_ticks: u64,
+    pub compliant: bool,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Marks the end of a resend; see [`crate::engine::resend`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResendComplete {
+    pub request_id: String,
+    pub shard_id: usize,
+    pub from_seq: u64,
+    pub to_seq: u64,
+    pub resent: u64,
+    /// Why the range could not be resent, if it could not.
+    pub error: Option<String>,
+}
+
+/// One entry of a market's ADL ranking; see [`crate::engine::adl`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdlRank {
+    pub subaccount_id: SubaccountId,
+    /// 1-based position among holders on the same side, best ADL candidate first.
+    pub rank: u64,
+    pub score: i64,
+}
+
+/// One side of a fill, as kept in a subaccount's trade history; see
+/// [`crate::persistence::trades`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub subaccount_id: SubaccountId,
+    pub trade_id: u64,
+    pub market_id: MarketId,
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price_ticks: PriceTicks,
+    pub qty: Quantity,
+    pub fee: i64,
+    /// Whether the subaccount's order was the resting one (the seller leg of a block trade).
+    pub maker: bool,
+    pub block_trade: bool,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// One party's side of a fill. It is published only on that subaccount's own subject, unlike
+/// the public [`Fill`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserFill {
+    pub subaccount_id: SubaccountId,
+    pub trade_id: u64,
+    pub market_id: MarketId,
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price_ticks: PriceTicks,
+    pub qty: Quantity,
+    pub fee: i64,
+    /// PnL of the part of the fill that reduced the subaccount's position, against its entry
+    /// price, before fees.
+    pub realized_pnl: i64,
+    /// Whether the subaccount's order was the resting one (the seller leg of a block trade).
+    pub maker: bool,
+    pub block_trade: bool,
+    /// Quantity of the order still open after this fill.
+    pub remaining_qty: Quantity,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// One shard's reply to a trade history query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeHistory {
+    pub request_id: String,
+    pub shard_id: ShardId,
+    pub subaccount_id: SubaccountId,
+    pub trades: Vec<Trade>,
+    /// Whether more trades matched than the request's limit allowed.
+    pub more: bool,
+    /// Why the query could not be answered, if it could not.
+    pub error: Option<String>,
+}
+
+/// Reply to an account equity query: the subaccount's margin picture on one shard's collateral
+/// ledger, as of the shard's input `engine_seq`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountEquity {
+    pub request_id: String,
+    pub shard_id: ShardId,
+    pub subaccount_id: SubaccountId,
+    pub engine_seq: u64,
+    /// Collateral balance, before unrealized PnL.
+    pub balance: i64,
+    pub unrealized_pnl: i64,
+    pub equity: i64,
+    /// Of positions at mark and of open orders at their limit prices.
+    pub initial_margin: i64,
+    /// Of positions at mark.
+    pub maintenance_margin: i64,
+    pub free_collateral: i64,
+    /// Why the query could not be answered, if it could not.
+    pub error: Option<String>,
+}
+
+/// Reply to an ADL ranking query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdlRanking {
+    pub request_id: String,
+    pub market_id: MarketId,
+    /// Side of the ranked positions: `Buy` for longs.
+    pub side: Side,
+    pub entries: Vec<AdlRank>,
+    /// Why the query could not be answered, if it could not.
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BookLevel {
     pub price_ticks: PriceTicks,
@@ -118,6 +725,30 @@ pub struct BookDelta {
     pub ts: u64,
 }
 
+/// Every price level of a market's book, published periodically so consumers can resynchronize
+/// without a request; see [`crate::config::BookSnapshotConfig`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub market_id: MarketId,
+    pub bids_levels: Vec<BookLevel>,
+    pub asks_levels: Vec<BookLevel>,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// A [`Transfer`] the engine applied, as reported in a [`SettlementBatch`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollateralTransfer {
+    pub request_id: String,
+    pub shard_id: ShardId,
+    pub from_subaccount_id: SubaccountId,
+    pub to_subaccount_id: SubaccountId,
+    pub amount: u64,
+    pub engine_seq: u64,
+    pub ts: u64,
+}
+
+/// Fills settled together; each one keeps its `trade_id`, which settlement records reference.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SettlementBatch {
     pub batch_id: String,
@@ -126,6 +757,10 @@ pub struct SettlementBatch {
     pub price_refs: String,
     pub funding_refs: String,
     pub state_root: Vec<u8>,
+    #[serde(default)]
+    pub liquidation_fees: Vec<LiquidationFee>,
+    #[serde(default)]
+    pub transfers: Vec<CollateralTransfer>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -138,149 +773,130 @@ pub enum Event {
     Fill(Fill),
     BookDelta(BookDelta),
     SettlementBatch(SettlementBatch),
+    OrderUpdate(OrderUpdate),
+    BlockTrade(BlockTrade),
+    OrderTriggered(OrderTriggered),
+    AuctionIndicative(AuctionIndicative),
+    ClearingSeed(ClearingSeed),
+    MassCancel(MassCancel),
+    MakerCompliance(MakerCompliance),
+    MigrateMarket(MigrateMarket),
+    MarketExported(MarketTransfer),
+    MarketImport(MarketTransfer),
+    ResendComplete(ResendComplete),
+    Liquidation(Liquidation),
+    BackstopAssignment(BackstopAssignment),
+    AdlRanking(AdlRanking),
+    FundingRate(FundingRate),
+    IndexPrice(IndexPrice),
+    RiskParameterChange(RiskParameterChange),
+    RiskParamsUpdate(RiskParamsUpdate),
+    /// Subaccount bootstrap from the config or `bus.subaccounts_bucket`, applied by every shard.
+    ProvisionSubaccount(SubaccountConfig),
+    TradeHistory(TradeHistory),
+    UserFill(UserFill),
+    DepthSnapshot(DepthSnapshot),
+    Transfer(Transfer),
+    CollateralTransfer(CollateralTransfer),
+    FundingPayment(FundingPayment),
+    PnlRealized(PnlRealized),
+    PositionUpdate(PositionUpdate),
+    AccountEquity(AccountEquity),
+    MarginWarning(MarginWarning),
+    /// New or updated market config from the config or `bus.markets_bucket`, applied by the shard
+    /// that owns the market. Logged as JSON, since bincode cannot decode the config's internally
+    /// tagged `book_layout`.
+    MarketUpdate(#[serde(with = "crate::config::json_string")] Box<MarketConfig>),
+}
+
+impl Event {
+    /// Whether this event is an engine input (replayed from the WAL) rather than an output.
+    pub fn is_input(&self) -> bool {
+        matches!(
+            self,
+            Event::NewOrder(_)
+                | Event::CancelOrder(_)
+                | Event::PriceUpdate(_)
+                | Event::FundingUpdate(_)
+                | Event::BlockTrade(_)
+                | Event::ClearingSeed(_)
+                | Event::MassCancel(_)
+                | Event::MigrateMarket(_)
+                | Event::MarketImport(_)
+                | Event::Transfer(_)
+                | Event::RiskParamsUpdate(_)
+                | Event::ProvisionSubaccount(_)
+                | Event::MarketUpdate(_)
+        )
+    }
 }
 
+/// Version of the envelope and event layouts written by this build. Bump it whenever a logged or
+/// published event changes shape, and teach the decoders to read the previous version.
+///
+/// - 0: envelopes written before versioning; decoded from [`LegacyEventEnvelope`].
+/// - 1: adds `schema_version` to `EventEnvelope` and `OutputEvent`; decoded from
+///   [`EventEnvelopeV1`].
+/// - 2: adds `ts_ns` to `EventEnvelope`.
+pub const SCHEMA_VERSION: u32 = 2;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventEnvelope {
     pub shard_id: ShardId,
     pub engine_seq: u64,
     pub event: Event,
+    /// Engine time, `ts_ns` in whole seconds.
     pub ts: u64,
+    /// Nanoseconds since the Unix epoch the router stamped on the input; every output of the input
+    /// carries the input's.
+    pub ts_ns: u64,
+    /// Layout version the envelope was written with. Kept last so an older, shorter record fails
+    /// to decode as the current layout instead of being misread.
+    pub schema_version: u32,
 }
 
-impl From<pb::NewOrder> for NewOrder {
-    fn from(value: pb::NewOrder) -> Self {
-        Self {
-            request_id: value.request_id,
-            market_id: value.market_id,
-            subaccount_id: value.subaccount_id,
-            side: match value.side.as_str() {
-                "SELL" => Side::Sell,
-                _ => Side::Buy,
-            },
-            order_type: match value.order_type.as_str() {
-                "MARKET" => OrderType::Market,
-                "POST_ONLY" => OrderType::PostOnly,
-                "IOC" => OrderType::Ioc,
-                "FOK" => OrderType::Fok,
-                _ => OrderType::Limit,
-            },
-            tif: match value.tif.as_str() {
-                "IOC" => TimeInForce::Ioc,
-                "FOK" => TimeInForce::Fok,
-                _ => TimeInForce::Gtc,
-            },
-            price_ticks: value.price_ticks,
-            qty: value.qty,
-            reduce_only: value.reduce_only,
-            expiry_ts: value.expiry_ts,
-            nonce: value.nonce,
-            client_ts: value.client_ts,
-        }
-    }
-}
-
-impl From<pb::CancelOrder> for CancelOrder {
-    fn from(value: pb::CancelOrder) -> Self {
-        Self {
-            request_id: value.request_id,
-            market_id: value.market_id,
-            subaccount_id: value.subaccount_id,
-            order_id: if value.order_id == 0 { None } else { Some(value.order_id) },
-            nonce_start: if value.nonce_start == 0 { None } else { Some(value.nonce_start) },
-            nonce_end: if value.nonce_end == 0 { None } else { Some(value.nonce_end) },
-        }
-    }
-}
-
-impl From<pb::PriceUpdate> for PriceUpdate {
-    fn from(value: pb::PriceUpdate) -> Self {
-        Self {
-            market_id: value.market_id,
-            mark_price: value.mark_price,
-            index_price: value.index_price,
-            ts: value.ts,
-        }
-    }
-}
-
-impl From<pb::FundingUpdate> for FundingUpdate {
-    fn from(value: pb::FundingUpdate) -> Self {
-        Self {
-            market_id: value.market_id,
-            funding_index: value.funding_index,
-            ts: value.ts,
-        }
-    }
+/// Envelope layout of schema version 0, as found in WALs written before versioning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyEventEnvelope {
+    pub shard_id: ShardId,
+    pub engine_seq: u64,
+    pub event: Event,
+    pub ts: u64,
 }
 
-impl From<OrderAck> for pb::OrderAck {
-    fn from(value: OrderAck) -> Self {
+impl From<LegacyEventEnvelope> for EventEnvelope {
+    fn from(value: LegacyEventEnvelope) -> Self {
         Self {
-            request_id: value.request_id,
-            status: match value.status {
-                OrderStatus::Accepted => "ACCEPTED".to_string(),
-                OrderStatus::Rejected => "REJECTED".to_string(),
-            },
-            reject_reason: value.reject_reason.unwrap_or_default(),
-            assigned_order_id: value.assigned_order_id.unwrap_or_default(),
+            shard_id: value.shard_id,
             engine_seq: value.engine_seq,
+            event: value.event,
             ts: value.ts,
+            ts_ns: value.ts.saturating_mul(crate::engine::clock::NANOS_PER_SEC),
+            schema_version: 0,
         }
     }
 }
 
-impl From<Fill> for pb::Fill {
-    fn from(value: Fill) -> Self {
-        Self {
-            market_id: value.market_id,
-            maker_order_id: value.maker_order_id,
-            taker_order_id: value.taker_order_id,
-            price_ticks: value.price_ticks,
-            qty: value.qty,
-            maker_fee: value.maker_fee,
-            taker_fee: value.taker_fee,
-            engine_seq: value.engine_seq,
-            ts: value.ts,
-        }
-    }
+/// Envelope layout of schema version 1, before `ts_ns`. Its inputs decode at the start of their
+/// second.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelopeV1 {
+    pub shard_id: ShardId,
+    pub engine_seq: u64,
+    pub event: Event,
+    pub ts: u64,
+    pub schema_version: u32,
 }
 
-impl From<BookDelta> for pb::BookDelta {
-    fn from(value: BookDelta) -> Self {
+impl From<EventEnvelopeV1> for EventEnvelope {
+    fn from(value: EventEnvelopeV1) -> Self {
         Self {
-            market_id: value.market_id,
-            bids_levels: value
-                .bids_levels
-                .into_iter()
-                .map(|level| pb::BookLevel {
-                    price_ticks: level.price_ticks,
-                    qty: level.qty,
-                })
-                .collect(),
-            asks_levels: value
-                .asks_levels
-                .into_iter()
-                .map(|level| pb::BookLevel {
-                    price_ticks: level.price_ticks,
-                    qty: level.qty,
-                })
-                .collect(),
+            shard_id: value.shard_id,
             engine_seq: value.engine_seq,
+            event: value.event,
             ts: value.ts,
-        }
-    }
-}
-
-impl From<SettlementBatch> for pb::SettlementBatch {
-    fn from(value: SettlementBatch) -> Self {
-        Self {
-            batch_id: value.batch_id,
-            ts: value.ts,
-            fills: value.fills.into_iter().map(Into::into).collect(),
-            price_refs: value.price_refs,
-            funding_refs: value.funding_refs,
-            state_root: value.state_root,
+            ts_ns: value.ts.saturating_mul(crate::engine::clock::NANOS_PER_SEC),
+            schema_version: value.schema_version,
         }
     }
 }
@@ -0,0 +1,1246 @@
+//! Conversions between the domain types and the protobuf wire messages in [`pb`].
+
+use super::{
+    pb, AccountEquity, AdlRank, AdlRanking, AuctionIndicative, BackstopAssignment, BlockTrade, BookDelta, BookLevel, CancelOrder, CollateralTransfer,
+    DepthSnapshot, Fill, FundingPayment, FundingRate, FundingUpdate, IndexPrice, Liquidation, LiquidationFee, MakerCompliance, MarginWarning, MassCancel,
+    MigrateMarket, NewOrder, OrderAck, OrderBuildError, OrderDisposition, OrderStatus, OrderTrigger, OrderTriggered, OrderType, OrderUpdate,
+    OrderUpdateStatus, PnlRealized, PositionUpdate, PriceUpdate, RejectReason, ResendComplete, RiskParameter, RiskParameterChange, SettlementBatch,
+    ShardId, Side, SubaccountId, TimeInForce, Trade, TradeHistory, Transfer, TriggerSource, UserFill,
+};
+
+/// Why a protobuf input could not be turned into a domain type.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConversionError {
+    #[error("unknown side `{0}`")]
+    UnknownSide(String),
+    #[error("unknown order type `{0}`")]
+    UnknownOrderType(String),
+    #[error("unknown time-in-force `{0}`")]
+    UnknownTif(String),
+    #[error("unknown trigger source `{0}`")]
+    UnknownTriggerSource(String),
+    #[error(transparent)]
+    Invalid(#[from] OrderBuildError),
+}
+
+/// Why a wire payload could not be decoded into an input, output or dead letter.
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("malformed input: {0}")]
+    Malformed(#[from] prost::DecodeError),
+    #[error("malformed json input: {0}")]
+    MalformedJson(#[from] serde_json::Error),
+    #[error("missing payload")]
+    MissingPayload,
+    #[error("invalid order {request_id}: {source}")]
+    InvalidOrder {
+        request_id: String,
+        market_id: u64,
+        subaccount_id: SubaccountId,
+        #[source]
+        source: ConversionError,
+    },
+}
+
+/// A rejected protobuf order, keeping the request id so the sender can still be acked.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid order {request_id}: {error}")]
+pub struct InvalidNewOrder {
+    pub request_id: String,
+    #[source]
+    pub error: ConversionError,
+}
+
+/// Strict conversion: unknown enum strings are errors. An empty `order_type` means LIMIT and an
+/// empty `tif` lets the builder derive it from the order type; `side` is always required.
+impl TryFrom<pb::NewOrder> for NewOrder {
+    type Error = InvalidNewOrder;
+
+    fn try_from(value: pb::NewOrder) -> Result<Self, Self::Error> {
+        let invalid = |request_id: String, error: ConversionError| InvalidNewOrder { request_id, error };
+        let side = match value.side.as_str() {
+            "BUY" => Side::Buy,
+            "SELL" => Side::Sell,
+            other => return Err(invalid(value.request_id, ConversionError::UnknownSide(other.to_string()))),
+        };
+        let order_type = match value.order_type.as_str() {
+            "" | "LIMIT" => OrderType::Limit,
+            "MARKET" => OrderType::Market,
+            "POST_ONLY" => OrderType::PostOnly,
+            "IOC" => OrderType::Ioc,
+            "FOK" => OrderType::Fok,
+            other => return Err(invalid(value.request_id, ConversionError::UnknownOrderType(other.to_string()))),
+        };
+        let tif = match value.tif.as_str() {
+            "" => None,
+            "GTC" => Some(TimeInForce::Gtc),
+            "IOC" => Some(TimeInForce::Ioc),
+            "FOK" => Some(TimeInForce::Fok),
+            "GTD" => Some(TimeInForce::Gtd {
+                expires_at: value.expiry_ts,
+            }),
+            other => return Err(invalid(value.request_id, ConversionError::UnknownTif(other.to_string()))),
+        };
+        let trigger_source = match value.trigger_source.as_str() {
+            "" | "MARK" => TriggerSource::Mark,
+            "INDEX" => TriggerSource::Index,
+            "LAST_TRADE" => TriggerSource::LastTrade,
+            other => {
+                return Err(invalid(value.request_id, ConversionError::UnknownTriggerSource(other.to_string())));
+            }
+        };
+        let mut builder = NewOrder::builder()
+            .request_id(value.request_id)
+            .market_id(value.market_id)
+            .subaccount_id(value.subaccount_id)
+            .side(side)
+            .order_type(order_type)
+            .price_ticks(value.price_ticks)
+            .qty(value.qty)
+            .reduce_only(value.reduce_only)
+            .expiry_ts(value.expiry_ts)
+            .nonce(value.nonce)
+            .client_ts(value.client_ts)
+            .signature(value.signature);
+        if let Some(tif) = tif {
+            builder = builder.tif(tif);
+        }
+        if value.trigger_price != 0 {
+            builder = builder.trigger(OrderTrigger {
+                trigger_price: value.trigger_price,
+                source: trigger_source,
+            });
+        }
+        if let Err(err) = builder.check() {
+            return Err(invalid(builder.into_request_id(), err.into()));
+        }
+        builder
+            .build()
+            .map_err(|err| invalid(String::new(), err.into()))
+    }
+}
+
+impl From<pb::CancelOrder> for CancelOrder {
+    fn from(value: pb::CancelOrder) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            order_id: if value.order_id == 0 { None } else { Some(value.order_id) },
+            nonce_start: if value.nonce_start == 0 { None } else { Some(value.nonce_start) },
+            nonce_end: if value.nonce_end == 0 { None } else { Some(value.nonce_end) },
+        }
+    }
+}
+
+impl From<AuctionIndicative> for pb::AuctionIndicative {
+    fn from(value: AuctionIndicative) -> Self {
+        Self {
+            market_id: value.market_id,
+            price_ticks: value.price_ticks,
+            matched_qty: value.matched_qty,
+            imbalance_qty: value.imbalance_qty,
+            imbalance_side: match value.imbalance_side {
+                None => String::new(),
+                Some(Side::Buy) => "BUY".to_string(),
+                Some(Side::Sell) => "SELL".to_string(),
+            },
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::AuctionIndicative> for AuctionIndicative {
+    fn from(value: pb::AuctionIndicative) -> Self {
+        Self {
+            market_id: value.market_id,
+            price_ticks: value.price_ticks,
+            matched_qty: value.matched_qty,
+            imbalance_qty: value.imbalance_qty,
+            imbalance_side: match value.imbalance_side.as_str() {
+                "BUY" => Some(Side::Buy),
+                "SELL" => Some(Side::Sell),
+                _ => None,
+            },
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<MakerCompliance> for pb::MakerCompliance {
+    fn from(value: MakerCompliance) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            period_start: value.period_start,
+            period_end: value.period_end,
+            quoted_secs: value.quoted_secs,
+            compliant_secs: value.compliant_secs,
+            uptime_bps: value.uptime_bps,
+            avg_spread_ticks: value.avg_spread_ticks,
+            compliant: value.compliant,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::MakerCompliance> for MakerCompliance {
+    fn from(value: pb::MakerCompliance) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            period_start: value.period_start,
+            period_end: value.period_end,
+            quoted_secs: value.quoted_secs,
+            compliant_secs: value.compliant_secs,
+            uptime_bps: value.uptime_bps,
+            avg_spread_ticks: value.avg_spread_ticks,
+            compliant: value.compliant,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<ResendComplete> for pb::ResendComplete {
+    fn from(value: ResendComplete) -> Self {
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as u64,
+            from_seq: value.from_seq,
+            to_seq: value.to_seq,
+            resent: value.resent,
+            error: value.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<pb::ResendComplete> for ResendComplete {
+    fn from(value: pb::ResendComplete) -> Self {
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as usize,
+            from_seq: value.from_seq,
+            to_seq: value.to_seq,
+            resent: value.resent,
+            error: if value.error.is_empty() { None } else { Some(value.error) },
+        }
+    }
+}
+
+impl From<AccountEquity> for pb::AccountEquity {
+    fn from(value: AccountEquity) -> Self {
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as u64,
+            subaccount_id: value.subaccount_id,
+            engine_seq: value.engine_seq,
+            balance: value.balance,
+            unrealized_pnl: value.unrealized_pnl,
+            equity: value.equity,
+            initial_margin: value.initial_margin,
+            maintenance_margin: value.maintenance_margin,
+            free_collateral: value.free_collateral,
+            error: value.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<pb::AccountEquity> for AccountEquity {
+    fn from(value: pb::AccountEquity) -> Self {
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as ShardId,
+            subaccount_id: value.subaccount_id,
+            engine_seq: value.engine_seq,
+            balance: value.balance,
+            unrealized_pnl: value.unrealized_pnl,
+            equity: value.equity,
+            initial_margin: value.initial_margin,
+            maintenance_margin: value.maintenance_margin,
+            free_collateral: value.free_collateral,
+            error: if value.error.is_empty() { None } else { Some(value.error) },
+        }
+    }
+}
+
+impl From<AdlRanking> for pb::AdlRanking {
+    fn from(value: AdlRanking) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            side: match value.side {
+                Side::Buy => "BUY".to_string(),
+                Side::Sell => "SELL".to_string(),
+            },
+            entries: value
+                .entries
+                .into_iter()
+                .map(|entry| pb::AdlRankEntry {
+                    subaccount_id: entry.subaccount_id,
+                    rank: entry.rank,
+                    score: entry.score,
+                })
+                .collect(),
+            error: value.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<pb::AdlRanking> for AdlRanking {
+    fn from(value: pb::AdlRanking) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            side: if value.side == "SELL" { Side::Sell } else { Side::Buy },
+            entries: value
+                .entries
+                .into_iter()
+                .map(|entry| AdlRank {
+                    subaccount_id: entry.subaccount_id,
+                    rank: entry.rank,
+                    score: entry.score,
+                })
+                .collect(),
+            error: if value.error.is_empty() { None } else { Some(value.error) },
+        }
+    }
+}
+
+impl From<TradeHistory> for pb::TradeHistory {
+    fn from(value: TradeHistory) -> Self {
+        let subaccount_id = value.subaccount_id;
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as u64,
+            subaccount_id,
+            trades: value
+                .trades
+                .into_iter()
+                .map(|trade| pb::Trade {
+                    trade_id: trade.trade_id,
+                    market_id: trade.market_id,
+                    order_id: trade.order_id,
+                    side: match trade.side {
+                        Side::Buy => "BUY".to_string(),
+                        Side::Sell => "SELL".to_string(),
+                    },
+                    price_ticks: trade.price_ticks,
+                    qty: trade.qty,
+                    fee: trade.fee,
+                    maker: trade.maker,
+                    block_trade: trade.block_trade,
+                    engine_seq: trade.engine_seq,
+                    ts: trade.ts,
+                })
+                .collect(),
+            more: value.more,
+            error: value.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<UserFill> for pb::UserFill {
+    fn from(value: UserFill) -> Self {
+        Self {
+            subaccount_id: value.subaccount_id,
+            trade_id: value.trade_id,
+            market_id: value.market_id,
+            order_id: value.order_id,
+            side: match value.side {
+                Side::Buy => "BUY".to_string(),
+                Side::Sell => "SELL".to_string(),
+            },
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            fee: value.fee,
+            realized_pnl: value.realized_pnl,
+            maker: value.maker,
+            block_trade: value.block_trade,
+            remaining_qty: value.remaining_qty,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+            ts_ns: 0,
+        }
+    }
+}
+
+impl From<pb::UserFill> for UserFill {
+    fn from(value: pb::UserFill) -> Self {
+        Self {
+            subaccount_id: value.subaccount_id,
+            trade_id: value.trade_id,
+            market_id: value.market_id,
+            order_id: value.order_id,
+            side: if value.side == "SELL" { Side::Sell } else { Side::Buy },
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            fee: value.fee,
+            realized_pnl: value.realized_pnl,
+            maker: value.maker,
+            block_trade: value.block_trade,
+            remaining_qty: value.remaining_qty,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::TradeHistory> for TradeHistory {
+    fn from(value: pb::TradeHistory) -> Self {
+        let subaccount_id = value.subaccount_id;
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as usize,
+            subaccount_id,
+            trades: value
+                .trades
+                .into_iter()
+                .map(|trade| Trade {
+                    subaccount_id,
+                    trade_id: trade.trade_id,
+                    market_id: trade.market_id,
+                    order_id: trade.order_id,
+                    side: if trade.side == "SELL" { Side::Sell } else { Side::Buy },
+                    price_ticks: trade.price_ticks,
+                    qty: trade.qty,
+                    fee: trade.fee,
+                    maker: trade.maker,
+                    block_trade: trade.block_trade,
+                    engine_seq: trade.engine_seq,
+                    ts: trade.ts,
+                })
+                .collect(),
+            more: value.more,
+            error: if value.error.is_empty() { None } else { Some(value.error) },
+        }
+    }
+}
+
+impl From<pb::BlockTrade> for BlockTrade {
+    fn from(value: pb::BlockTrade) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            buyer_subaccount_id: value.buyer_subaccount_id,
+            seller_subaccount_id: value.seller_subaccount_id,
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+        }
+    }
+}
+
+impl From<pb::Transfer> for Transfer {
+    fn from(value: pb::Transfer) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            from_subaccount_id: value.from_subaccount_id,
+            to_subaccount_id: value.to_subaccount_id,
+            amount: value.amount,
+        }
+    }
+}
+
+impl From<pb::MassCancel> for MassCancel {
+    fn from(value: pb::MassCancel) -> Self {
+        Self {
+            request_id: value.request_id,
+            parent_id: value.parent_id,
+            market_id: (value.market_id != 0).then_some(value.market_id),
+        }
+    }
+}
+
+impl From<pb::MigrateMarket> for MigrateMarket {
+    fn from(value: pb::MigrateMarket) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            target_shard: value.target_shard as ShardId,
+        }
+    }
+}
+
+impl From<pb::PriceUpdate> for PriceUpdate {
+    fn from(value: pb::PriceUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            mark_price: value.mark_price,
+            index_price: value.index_price,
+            ts: value.ts,
+            source: value.source,
+        }
+    }
+}
+
+impl From<pb::FundingUpdate> for FundingUpdate {
+    fn from(value: pb::FundingUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            funding_index: value.funding_index,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<NewOrder> for pb::NewOrder {
+    fn from(value: NewOrder) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            side: match value.side {
+                Side::Buy => "BUY".to_string(),
+                Side::Sell => "SELL".to_string(),
+            },
+            order_type: match value.order_type {
+                OrderType::Limit => "LIMIT".to_string(),
+                OrderType::Market => "MARKET".to_string(),
+                OrderType::PostOnly => "POST_ONLY".to_string(),
+                OrderType::Ioc => "IOC".to_string(),
+                OrderType::Fok => "FOK".to_string(),
+            },
+            tif: match value.tif {
+                TimeInForce::Gtc => "GTC".to_string(),
+                TimeInForce::Ioc => "IOC".to_string(),
+                TimeInForce::Fok => "FOK".to_string(),
+                TimeInForce::Gtd { .. } => "GTD".to_string(),
+            },
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            reduce_only: value.reduce_only,
+            expiry_ts: value.tif.expires_at().unwrap_or(value.expiry_ts),
+            nonce: value.nonce,
+            signature: value.signature,
+            client_ts: value.client_ts,
+            trigger_price: value.trigger.map(|trigger| trigger.trigger_price).unwrap_or_default(),
+            trigger_source: match value.trigger.map(|trigger| trigger.source) {
+                None => String::new(),
+                Some(TriggerSource::Mark) => "MARK".to_string(),
+                Some(TriggerSource::Index) => "INDEX".to_string(),
+                Some(TriggerSource::LastTrade) => "LAST_TRADE".to_string(),
+            },
+        }
+    }
+}
+
+impl From<CancelOrder> for pb::CancelOrder {
+    fn from(value: CancelOrder) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            order_id: value.order_id.unwrap_or_default(),
+            nonce_start: value.nonce_start.unwrap_or_default(),
+            nonce_end: value.nonce_end.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<BlockTrade> for pb::BlockTrade {
+    fn from(value: BlockTrade) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            buyer_subaccount_id: value.buyer_subaccount_id,
+            seller_subaccount_id: value.seller_subaccount_id,
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+        }
+    }
+}
+
+impl From<Transfer> for pb::Transfer {
+    fn from(value: Transfer) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            from_subaccount_id: value.from_subaccount_id,
+            to_subaccount_id: value.to_subaccount_id,
+            amount: value.amount,
+        }
+    }
+}
+
+impl From<MassCancel> for pb::MassCancel {
+    fn from(value: MassCancel) -> Self {
+        Self {
+            request_id: value.request_id,
+            parent_id: value.parent_id,
+            market_id: value.market_id.unwrap_or(0),
+        }
+    }
+}
+
+impl From<MigrateMarket> for pb::MigrateMarket {
+    fn from(value: MigrateMarket) -> Self {
+        Self {
+            request_id: value.request_id,
+            market_id: value.market_id,
+            target_shard: value.target_shard as u64,
+        }
+    }
+}
+
+impl From<PriceUpdate> for pb::PriceUpdate {
+    fn from(value: PriceUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            mark_price: value.mark_price,
+            index_price: value.index_price,
+            ts: value.ts,
+            source: value.source,
+        }
+    }
+}
+
+impl From<FundingUpdate> for pb::FundingUpdate {
+    fn from(value: FundingUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            funding_index: value.funding_index,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<OrderAck> for pb::OrderAck {
+    fn from(value: OrderAck) -> Self {
+        Self {
+            request_id: value.request_id,
+            status: match value.status {
+                OrderStatus::Accepted => "ACCEPTED".to_string(),
+                OrderStatus::Rejected => "REJECTED".to_string(),
+            },
+            reject_code: value.reject_code.map(RejectReason::code).unwrap_or_default(),
+            reject_reason: value.reject_reason.unwrap_or_default(),
+            assigned_order_id: value.assigned_order_id.unwrap_or_default(),
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+            filled_qty: value.filled_qty,
+            remaining_qty: value.remaining_qty,
+            disposition: match value.disposition {
+                Some(OrderDisposition::Rested) => "RESTED".to_string(),
+                Some(OrderDisposition::Filled) => "FILLED".to_string(),
+                Some(OrderDisposition::Cancelled) => "CANCELLED".to_string(),
+                None => String::new(),
+            },
+            subaccount_id: value.subaccount_id.unwrap_or_default(),
+            ts_ns: 0,
+        }
+    }
+}
+
+impl From<Fill> for pb::Fill {
+    fn from(value: Fill) -> Self {
+        Self {
+            market_id: value.market_id,
+            maker_order_id: value.maker_order_id,
+            taker_order_id: value.taker_order_id,
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            maker_fee: value.maker_fee,
+            taker_fee: value.taker_fee,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+            block_trade: value.block_trade,
+            trade_id: value.trade_id,
+            maker_subaccount_id: value.maker_subaccount_id,
+            taker_subaccount_id: value.taker_subaccount_id,
+            maker_remaining_qty: value.maker_remaining_qty,
+            taker_remaining_qty: value.taker_remaining_qty,
+            ts_ns: 0,
+        }
+    }
+}
+
+impl From<pb::OrderAck> for OrderAck {
+    fn from(value: pb::OrderAck) -> Self {
+        Self {
+            request_id: value.request_id,
+            status: match value.status.as_str() {
+                "REJECTED" => OrderStatus::Rejected,
+                _ => OrderStatus::Accepted,
+            },
+            reject_code: RejectReason::from_code(value.reject_code),
+            reject_reason: if value.reject_reason.is_empty() { None } else { Some(value.reject_reason) },
+            assigned_order_id: if value.assigned_order_id == 0 { None } else { Some(value.assigned_order_id) },
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+            filled_qty: value.filled_qty,
+            remaining_qty: value.remaining_qty,
+            disposition: match value.disposition.as_str() {
+                "RESTED" => Some(OrderDisposition::Rested),
+                "FILLED" => Some(OrderDisposition::Filled),
+                "CANCELLED" => Some(OrderDisposition::Cancelled),
+                _ => None,
+            },
+            subaccount_id: if value.subaccount_id == 0 { None } else { Some(value.subaccount_id) },
+        }
+    }
+}
+
+impl From<pb::Fill> for Fill {
+    fn from(value: pb::Fill) -> Self {
+        Self {
+            market_id: value.market_id,
+            maker_order_id: value.maker_order_id,
+            taker_order_id: value.taker_order_id,
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            maker_fee: value.maker_fee,
+            taker_fee: value.taker_fee,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+            block_trade: value.block_trade,
+            trade_id: value.trade_id,
+            maker_subaccount_id: value.maker_subaccount_id,
+            taker_subaccount_id: value.taker_subaccount_id,
+            maker_remaining_qty: value.maker_remaining_qty,
+            taker_remaining_qty: value.taker_remaining_qty,
+        }
+    }
+}
+
+impl From<pb::BookDelta> for BookDelta {
+    fn from(value: pb::BookDelta) -> Self {
+        Self {
+            market_id: value.market_id,
+            bids_levels: value
+                .bids_levels
+                .into_iter()
+                .map(|level| BookLevel {
+                    price_ticks: level.price_ticks,
+                    qty: level.qty,
+                })
+                .collect(),
+            asks_levels: value
+                .asks_levels
+                .into_iter()
+                .map(|level| BookLevel {
+                    price_ticks: level.price_ticks,
+                    qty: level.qty,
+                })
+                .collect(),
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<DepthSnapshot> for pb::DepthSnapshot {
+    fn from(value: DepthSnapshot) -> Self {
+        let level = |level: BookLevel| pb::BookLevel {
+            price_ticks: level.price_ticks,
+            qty: level.qty,
+        };
+        Self {
+            market_id: value.market_id,
+            bids_levels: value.bids_levels.into_iter().map(level).collect(),
+            asks_levels: value.asks_levels.into_iter().map(level).collect(),
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::DepthSnapshot> for DepthSnapshot {
+    fn from(value: pb::DepthSnapshot) -> Self {
+        let level = |level: pb::BookLevel| BookLevel {
+            price_ticks: level.price_ticks,
+            qty: level.qty,
+        };
+        Self {
+            market_id: value.market_id,
+            bids_levels: value.bids_levels.into_iter().map(level).collect(),
+            asks_levels: value.asks_levels.into_iter().map(level).collect(),
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<CollateralTransfer> for pb::CollateralTransfer {
+    fn from(value: CollateralTransfer) -> Self {
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as u64,
+            from_subaccount_id: value.from_subaccount_id,
+            to_subaccount_id: value.to_subaccount_id,
+            amount: value.amount,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::CollateralTransfer> for CollateralTransfer {
+    fn from(value: pb::CollateralTransfer) -> Self {
+        Self {
+            request_id: value.request_id,
+            shard_id: value.shard_id as ShardId,
+            from_subaccount_id: value.from_subaccount_id,
+            to_subaccount_id: value.to_subaccount_id,
+            amount: value.amount,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<FundingPayment> for pb::FundingPayment {
+    fn from(value: FundingPayment) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            position_size: value.position_size,
+            from_index: value.from_index,
+            to_index: value.to_index,
+            amount: value.amount,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::FundingPayment> for FundingPayment {
+    fn from(value: pb::FundingPayment) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            position_size: value.position_size,
+            from_index: value.from_index,
+            to_index: value.to_index,
+            amount: value.amount,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<PnlRealized> for pb::PnlRealized {
+    fn from(value: PnlRealized) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            trade_id: value.trade_id,
+            closed_qty: value.closed_qty,
+            realized_pnl: value.realized_pnl,
+            remaining_size: value.remaining_size,
+            entry_price: value.entry_price,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::PnlRealized> for PnlRealized {
+    fn from(value: pb::PnlRealized) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            trade_id: value.trade_id,
+            closed_qty: value.closed_qty,
+            realized_pnl: value.realized_pnl,
+            remaining_size: value.remaining_size,
+            entry_price: value.entry_price,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<PositionUpdate> for pb::PositionUpdate {
+    fn from(value: PositionUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            size: value.size,
+            entry_price: value.entry_price,
+            mark_price: value.mark_price,
+            unrealized_pnl: value.unrealized_pnl,
+            initial_margin: value.initial_margin,
+            maintenance_margin: value.maintenance_margin,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::PositionUpdate> for PositionUpdate {
+    fn from(value: pb::PositionUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            size: value.size,
+            entry_price: value.entry_price,
+            mark_price: value.mark_price,
+            unrealized_pnl: value.unrealized_pnl,
+            initial_margin: value.initial_margin,
+            maintenance_margin: value.maintenance_margin,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<MarginWarning> for pb::MarginWarning {
+    fn from(value: MarginWarning) -> Self {
+        Self {
+            subaccount_id: value.subaccount_id,
+            equity: value.equity,
+            maintenance_margin: value.maintenance_margin,
+            warning_bps: value.warning_bps,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::MarginWarning> for MarginWarning {
+    fn from(value: pb::MarginWarning) -> Self {
+        Self {
+            subaccount_id: value.subaccount_id,
+            equity: value.equity,
+            maintenance_margin: value.maintenance_margin,
+            warning_bps: value.warning_bps,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<OrderUpdate> for pb::OrderUpdate {
+    fn from(value: OrderUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            status: match value.status {
+                OrderUpdateStatus::Open => "OPEN".to_string(),
+                OrderUpdateStatus::PartiallyFilled => "PARTIALLY_FILLED".to_string(),
+                OrderUpdateStatus::Filled => "FILLED".to_string(),
+                OrderUpdateStatus::Cancelled => "CANCELLED".to_string(),
+                OrderUpdateStatus::Expired => "EXPIRED".to_string(),
+            },
+            remaining_qty: value.remaining_qty,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::OrderUpdate> for OrderUpdate {
+    fn from(value: pb::OrderUpdate) -> Self {
+        Self {
+            market_id: value.market_id,
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            status: match value.status.as_str() {
+                "PARTIALLY_FILLED" => OrderUpdateStatus::PartiallyFilled,
+                "FILLED" => OrderUpdateStatus::Filled,
+                "CANCELLED" => OrderUpdateStatus::Cancelled,
+                "EXPIRED" => OrderUpdateStatus::Expired,
+                _ => OrderUpdateStatus::Open,
+            },
+            remaining_qty: value.remaining_qty,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<OrderTriggered> for pb::OrderTriggered {
+    fn from(value: OrderTriggered) -> Self {
+        Self {
+            market_id: value.market_id,
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            trigger_price: value.trigger.trigger_price,
+            trigger_source: match value.trigger.source {
+                TriggerSource::Mark => "MARK".to_string(),
+                TriggerSource::Index => "INDEX".to_string(),
+                TriggerSource::LastTrade => "LAST_TRADE".to_string(),
+            },
+            reference_price: value.reference_price,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::OrderTriggered> for OrderTriggered {
+    fn from(value: pb::OrderTriggered) -> Self {
+        Self {
+            market_id: value.market_id,
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            trigger: OrderTrigger {
+                trigger_price: value.trigger_price,
+                source: match value.trigger_source.as_str() {
+                    "INDEX" => TriggerSource::Index,
+                    "LAST_TRADE" => TriggerSource::LastTrade,
+                    _ => TriggerSource::Mark,
+                },
+            },
+            reference_price: value.reference_price,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<Liquidation> for pb::Liquidation {
+    fn from(value: Liquidation) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            order_id: value.order_id.unwrap_or_default(),
+            side: match value.side {
+                Side::Buy => "BUY".to_string(),
+                Side::Sell => "SELL".to_string(),
+            },
+            qty: value.qty,
+            backstop_qty: value.backstop_qty,
+            filled_qty: value.filled_qty,
+            remaining_position: value.remaining_position,
+            notional: value.notional,
+            fee: value.fee,
+            insurance_fund_subaccount_id: value.insurance_fund_subaccount_id.unwrap_or_default(),
+            bankruptcy_price: value.bankruptcy_price,
+            shortfall: value.shortfall,
+            insurance_covered: value.insurance_covered,
+            adl_shortfall: value.adl_shortfall,
+            equity: value.equity,
+            maintenance_margin: value.maintenance_margin,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::Liquidation> for Liquidation {
+    fn from(value: pb::Liquidation) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            order_id: if value.order_id == 0 { None } else { Some(value.order_id) },
+            side: if value.side == "SELL" { Side::Sell } else { Side::Buy },
+            qty: value.qty,
+            backstop_qty: value.backstop_qty,
+            filled_qty: value.filled_qty,
+            remaining_position: value.remaining_position,
+            notional: value.notional,
+            fee: value.fee,
+            insurance_fund_subaccount_id: if value.insurance_fund_subaccount_id == 0 {
+                None
+            } else {
+                Some(value.insurance_fund_subaccount_id)
+            },
+            bankruptcy_price: value.bankruptcy_price,
+            shortfall: value.shortfall,
+            insurance_covered: value.insurance_covered,
+            adl_shortfall: value.adl_shortfall,
+            equity: value.equity,
+            maintenance_margin: value.maintenance_margin,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<BackstopAssignment> for pb::BackstopAssignment {
+    fn from(value: BackstopAssignment) -> Self {
+        Self {
+            market_id: value.market_id,
+            liquidated_subaccount_id: value.liquidated_subaccount_id,
+            provider_subaccount_id: value.provider_subaccount_id,
+            side: match value.side {
+                Side::Buy => "BUY".to_string(),
+                Side::Sell => "SELL".to_string(),
+            },
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::BackstopAssignment> for BackstopAssignment {
+    fn from(value: pb::BackstopAssignment) -> Self {
+        Self {
+            market_id: value.market_id,
+            liquidated_subaccount_id: value.liquidated_subaccount_id,
+            provider_subaccount_id: value.provider_subaccount_id,
+            side: if value.side == "SELL" { Side::Sell } else { Side::Buy },
+            price_ticks: value.price_ticks,
+            qty: value.qty,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<FundingRate> for pb::FundingRate {
+    fn from(value: FundingRate) -> Self {
+        Self {
+            market_id: value.market_id,
+            rate_bps: value.rate_bps,
+            periods: value.periods,
+            mark_price: value.mark_price,
+            index_price: value.index_price,
+            funding_index: value.funding_index,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::FundingRate> for FundingRate {
+    fn from(value: pb::FundingRate) -> Self {
+        Self {
+            market_id: value.market_id,
+            rate_bps: value.rate_bps,
+            periods: value.periods,
+            mark_price: value.mark_price,
+            index_price: value.index_price,
+            funding_index: value.funding_index,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<IndexPrice> for pb::IndexPrice {
+    fn from(value: IndexPrice) -> Self {
+        Self {
+            market_id: value.market_id,
+            mark_price: value.mark_price,
+            index_price: value.index_price,
+            sources: value.sources,
+            stale: value.stale,
+            outliers: value.outliers,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::IndexPrice> for IndexPrice {
+    fn from(value: pb::IndexPrice) -> Self {
+        Self {
+            market_id: value.market_id,
+            mark_price: value.mark_price,
+            index_price: value.index_price,
+            sources: value.sources,
+            stale: value.stale,
+            outliers: value.outliers,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<RiskParameterChange> for pb::RiskParameterChange {
+    fn from(value: RiskParameterChange) -> Self {
+        Self {
+            market_id: value.market_id,
+            parameter: match value.parameter {
+                RiskParameter::InitialMarginBps => "INITIAL_MARGIN_BPS",
+                RiskParameter::MaintenanceMarginBps => "MAINTENANCE_MARGIN_BPS",
+                RiskParameter::PriceBandBps => "PRICE_BAND_BPS",
+                RiskParameter::MakerFeeBps => "MAKER_FEE_BPS",
+                RiskParameter::TakerFeeBps => "TAKER_FEE_BPS",
+                RiskParameter::MaxPosition => "MAX_POSITION",
+                RiskParameter::MaxLeverage => "MAX_LEVERAGE",
+            }
+            .to_string(),
+            previous: value.previous,
+            value: value.value,
+            volatility_bps: value.volatility_bps,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<pb::RiskParameterChange> for RiskParameterChange {
+    fn from(value: pb::RiskParameterChange) -> Self {
+        Self {
+            market_id: value.market_id,
+            parameter: match value.parameter.as_str() {
+                "MAINTENANCE_MARGIN_BPS" => RiskParameter::MaintenanceMarginBps,
+                "PRICE_BAND_BPS" => RiskParameter::PriceBandBps,
+                "MAKER_FEE_BPS" => RiskParameter::MakerFeeBps,
+                "TAKER_FEE_BPS" => RiskParameter::TakerFeeBps,
+                "MAX_POSITION" => RiskParameter::MaxPosition,
+                "MAX_LEVERAGE" => RiskParameter::MaxLeverage,
+                _ => RiskParameter::InitialMarginBps,
+            },
+            previous: value.previous,
+            value: value.value,
+            volatility_bps: value.volatility_bps,
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<BookDelta> for pb::BookDelta {
+    fn from(value: BookDelta) -> Self {
+        Self {
+            market_id: value.market_id,
+            bids_levels: value
+                .bids_levels
+                .into_iter()
+                .map(|level| pb::BookLevel {
+                    price_ticks: level.price_ticks,
+                    qty: level.qty,
+                })
+                .collect(),
+            asks_levels: value
+                .asks_levels
+                .into_iter()
+                .map(|level| pb::BookLevel {
+                    price_ticks: level.price_ticks,
+                    qty: level.qty,
+                })
+                .collect(),
+            engine_seq: value.engine_seq,
+            ts: value.ts,
+        }
+    }
+}
+
+impl From<SettlementBatch> for pb::SettlementBatch {
+    fn from(value: SettlementBatch) -> Self {
+        Self {
+            batch_id: value.batch_id,
+            ts: value.ts,
+            fills: value.fills.into_iter().map(Into::into).collect(),
+            price_refs: value.price_refs,
+            funding_refs: value.funding_refs,
+            state_root: value.state_root.into(),
+            liquidation_fees: value.liquidation_fees.into_iter().map(Into::into).collect(),
+            transfers: value.transfers.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<LiquidationFee> for pb::LiquidationFee {
+    fn from(value: LiquidationFee) -> Self {
+        Self {
+            market_id: value.market_id,
+            subaccount_id: value.subaccount_id,
+            insurance_fund_subaccount_id: value.insurance_fund_subaccount_id,
+            notional: value.notional,
+            fee: value.fee,
+            engine_seq: value.engine_seq,
+        }
+    }
+}
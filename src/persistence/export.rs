@@ -0,0 +1,320 @@
+//! Export of engine history to Parquet datasets for offline analysis.
+//!
+//! Inputs (from the WAL) and outputs (from the output journal) are turned into three datasets,
+//! each partitioned Hive-style by market and UTC date so columnar tools can prune on both:
+//!
+//! - `fills/market_id=<m>/date=<YYYY-MM-DD>/part-<shard>-<first_seq>.parquet`, one row per fill;
+//! - `orders/...`, one row per `NewOrder` input as submitted;
+//! - `book/...`, one row per price level of each `DepthSnapshot` (and of each `BookDelta`, whose
+//!   levels are also the full top of book, with `include_deltas`).
+//!
+//! File names derive from the shard and first `engine_seq` of the rows they hold, so exporting the
+//! same log again rewrites the same files.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, BooleanArray, Int64Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::models::{BookLevel, Event, EventEnvelope, MarketId, OrderType, ShardId, Side, TimeInForce};
+
+/// Where rows of one dataset go: one file per market, day and shard.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Partition {
+    market_id: MarketId,
+    date: String,
+    shard_id: ShardId,
+}
+
+#[derive(Debug, Clone)]
+struct FillRow {
+    engine_seq: u64,
+    ts: u64,
+    trade_id: u64,
+    maker_order_id: u64,
+    taker_order_id: u64,
+    maker_subaccount_id: u64,
+    taker_subaccount_id: u64,
+    price_ticks: u64,
+    qty: u64,
+    maker_fee: i64,
+    taker_fee: i64,
+    block_trade: bool,
+}
+
+#[derive(Debug, Clone)]
+struct OrderRow {
+    engine_seq: u64,
+    ts: u64,
+    request_id: String,
+    subaccount_id: u64,
+    side: &'static str,
+    order_type: &'static str,
+    tif: &'static str,
+    price_ticks: u64,
+    qty: u64,
+    reduce_only: bool,
+    expiry_ts: u64,
+    nonce: u64,
+    client_ts: u64,
+    conditional: bool,
+}
+
+#[derive(Debug, Clone)]
+struct BookRow {
+    engine_seq: u64,
+    ts: u64,
+    kind: &'static str,
+    side: &'static str,
+    level: u32,
+    price_ticks: u64,
+    qty: u64,
+}
+
+/// Rows collected so far, per dataset and partition.
+#[derive(Debug, Default)]
+pub struct ParquetExport {
+    include_deltas: bool,
+    fills: BTreeMap<Partition, Vec<FillRow>>,
+    orders: BTreeMap<Partition, Vec<OrderRow>>,
+    book: BTreeMap<Partition, Vec<BookRow>>,
+}
+
+/// Rows and files written by [`ParquetExport::write`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExportReport {
+    pub fills: usize,
+    pub orders: usize,
+    pub book_levels: usize,
+    pub files: Vec<PathBuf>,
+}
+
+impl ParquetExport {
+    /// With `include_deltas`, book deltas are exported alongside depth snapshots.
+    pub fn new(include_deltas: bool) -> Self {
+        Self {
+            include_deltas,
+            ..Self::default()
+        }
+    }
+
+    /// Adds the rows `records` contribute; inputs and outputs may be mixed, other events are skipped.
+    pub fn extend<'a>(&mut self, records: impl IntoIterator<Item = &'a EventEnvelope>) {
+        for record in records {
+            self.push(record);
+        }
+    }
+
+    pub fn push(&mut self, record: &EventEnvelope) {
+        let partition = |market_id| Partition {
+            market_id,
+            date: utc_date(record.ts),
+            shard_id: record.shard_id,
+        };
+        match &record.event {
+            Event::Fill(fill) => self.fills.entry(partition(fill.market_id)).or_default().push(FillRow {
+                engine_seq: record.engine_seq,
+                ts: fill.ts,
+                trade_id: fill.trade_id,
+                maker_order_id: fill.maker_order_id,
+                taker_order_id: fill.taker_order_id,
+                maker_subaccount_id: fill.maker_subaccount_id,
+                taker_subaccount_id: fill.taker_subaccount_id,
+                price_ticks: fill.price_ticks,
+                qty: fill.qty,
+                maker_fee: fill.maker_fee,
+                taker_fee: fill.taker_fee,
+                block_trade: fill.block_trade,
+            }),
+            Event::NewOrder(order) => self.orders.entry(partition(order.market_id)).or_default().push(OrderRow {
+                engine_seq: record.engine_seq,
+                ts: record.ts,
+                request_id: order.request_id.clone(),
+                subaccount_id: order.subaccount_id,
+                side: side_name(order.side),
+                order_type: order_type_name(order.order_type),
+                tif: tif_name(order.tif),
+                price_ticks: order.price_ticks,
+                qty: order.qty,
+                reduce_only: order.reduce_only,
+                expiry_ts: order.expiry_ts,
+                nonce: order.nonce,
+                client_ts: order.client_ts,
+                conditional: order.trigger.is_some(),
+            }),
+            Event::DepthSnapshot(snapshot) => {
+                let rows = book_rows(record, "snapshot", &snapshot.bids_levels, &snapshot.asks_levels);
+                self.book.entry(partition(snapshot.market_id)).or_default().extend(rows);
+            }
+            Event::BookDelta(delta) if self.include_deltas => {
+                let rows = book_rows(record, "delta", &delta.bids_levels, &delta.asks_levels);
+                self.book.entry(partition(delta.market_id)).or_default().extend(rows);
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes every partition under `dir` as a Snappy-compressed Parquet file.
+    pub fn write(&self, dir: &Path) -> anyhow::Result<ExportReport> {
+        let mut report = ExportReport::default();
+        for (partition, rows) in &self.fills {
+            report.fills += rows.len();
+            report.files.push(write_partition(dir, "fills", partition, rows[0].engine_seq, fill_batch(rows)?)?);
+        }
+        for (partition, rows) in &self.orders {
+            report.orders += rows.len();
+            report.files.push(write_partition(dir, "orders", partition, rows[0].engine_seq, order_batch(rows)?)?);
+        }
+        for (partition, rows) in &self.book {
+            report.book_levels += rows.len();
+            report.files.push(write_partition(dir, "book", partition, rows[0].engine_seq, book_batch(rows)?)?);
+        }
+        Ok(report)
+    }
+}
+
+fn book_rows(record: &EventEnvelope, kind: &'static str, bids: &[BookLevel], asks: &[BookLevel]) -> Vec<BookRow> {
+    let side = |name: &'static str, levels: &[BookLevel]| {
+        levels
+            .iter()
+            .enumerate()
+            .map(|(level, book_level)| BookRow {
+                engine_seq: record.engine_seq,
+                ts: record.ts,
+                kind,
+                side: name,
+                level: level as u32,
+                price_ticks: book_level.price_ticks,
+                qty: book_level.qty,
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut rows = side("BUY", bids);
+    rows.extend(side("SELL", asks));
+    rows
+}
+
+fn write_partition(dir: &Path, dataset: &str, partition: &Partition, first_seq: u64, batch: RecordBatch) -> anyhow::Result<PathBuf> {
+    let partition_dir = dir
+        .join(dataset)
+        .join(format!("market_id={}", partition.market_id))
+        .join(format!("date={}", partition.date));
+    std::fs::create_dir_all(&partition_dir)?;
+    let path = partition_dir.join(format!("part-{}-{first_seq:020}.parquet", partition.shard_id));
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(File::create(&path)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(path)
+}
+
+fn u64_column<T>(rows: &[T], value: impl Fn(&T) -> u64) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter_values(rows.iter().map(value)))
+}
+
+fn i64_column<T>(rows: &[T], value: impl Fn(&T) -> i64) -> ArrayRef {
+    Arc::new(Int64Array::from_iter_values(rows.iter().map(value)))
+}
+
+fn bool_column<T>(rows: &[T], value: impl Fn(&T) -> bool) -> ArrayRef {
+    Arc::new(BooleanArray::from(rows.iter().map(value).collect::<Vec<_>>()))
+}
+
+fn str_column<'a, T>(rows: &'a [T], value: impl Fn(&'a T) -> &'a str) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(rows.iter().map(value)))
+}
+
+fn fill_batch(rows: &[FillRow]) -> anyhow::Result<RecordBatch> {
+    Ok(RecordBatch::try_from_iter([
+        ("engine_seq", u64_column(rows, |row| row.engine_seq)),
+        ("ts", u64_column(rows, |row| row.ts)),
+        ("trade_id", u64_column(rows, |row| row.trade_id)),
+        ("maker_order_id", u64_column(rows, |row| row.maker_order_id)),
+        ("taker_order_id", u64_column(rows, |row| row.taker_order_id)),
+        ("maker_subaccount_id", u64_column(rows, |row| row.maker_subaccount_id)),
+        ("taker_subaccount_id", u64_column(rows, |row| row.taker_subaccount_id)),
+        ("price_ticks", u64_column(rows, |row| row.price_ticks)),
+        ("qty", u64_column(rows, |row| row.qty)),
+        ("maker_fee", i64_column(rows, |row| row.maker_fee)),
+        ("taker_fee", i64_column(rows, |row| row.taker_fee)),
+        ("block_trade", bool_column(rows, |row| row.block_trade)),
+    ])?)
+}
+
+fn order_batch(rows: &[OrderRow]) -> anyhow::Result<RecordBatch> {
+    Ok(RecordBatch::try_from_iter([
+        ("engine_seq", u64_column(rows, |row| row.engine_seq)),
+        ("ts", u64_column(rows, |row| row.ts)),
+        ("request_id", str_column(rows, |row| row.request_id.as_str())),
+        ("subaccount_id", u64_column(rows, |row| row.subaccount_id)),
+        ("side", str_column(rows, |row| row.side)),
+        ("order_type", str_column(rows, |row| row.order_type)),
+        ("tif", str_column(rows, |row| row.tif)),
+        ("price_ticks", u64_column(rows, |row| row.price_ticks)),
+        ("qty", u64_column(rows, |row| row.qty)),
+        ("reduce_only", bool_column(rows, |row| row.reduce_only)),
+        ("expiry_ts", u64_column(rows, |row| row.expiry_ts)),
+        ("nonce", u64_column(rows, |row| row.nonce)),
+        ("client_ts", u64_column(rows, |row| row.client_ts)),
+        ("conditional", bool_column(rows, |row| row.conditional)),
+    ])?)
+}
+
+fn book_batch(rows: &[BookRow]) -> anyhow::Result<RecordBatch> {
+    Ok(RecordBatch::try_from_iter([
+        ("engine_seq", u64_column(rows, |row| row.engine_seq)),
+        ("ts", u64_column(rows, |row| row.ts)),
+        ("kind", str_column(rows, |row| row.kind)),
+        ("side", str_column(rows, |row| row.side)),
+        ("level", Arc::new(UInt32Array::from_iter_values(rows.iter().map(|row| row.level))) as ArrayRef),
+        ("price_ticks", u64_column(rows, |row| row.price_ticks)),
+        ("qty", u64_column(rows, |row| row.qty)),
+    ])?)
+}
+
+/// The wire codec's names, so exported columns read like the JSON inputs.
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "BUY",
+        Side::Sell => "SELL",
+    }
+}
+
+fn order_type_name(order_type: OrderType) -> &'static str {
+    match order_type {
+        OrderType::Limit => "LIMIT",
+        OrderType::Market => "MARKET",
+        OrderType::PostOnly => "POST_ONLY",
+        OrderType::Ioc => "IOC",
+        OrderType::Fok => "FOK",
+    }
+}
+
+fn tif_name(tif: TimeInForce) -> &'static str {
+    match tif {
+        TimeInForce::Gtc => "GTC",
+        TimeInForce::Ioc => "IOC",
+        TimeInForce::Fok => "FOK",
+        TimeInForce::Gtd { .. } => "GTD",
+    }
+}
+
+/// `YYYY-MM-DD` of an engine timestamp (seconds since the epoch) in UTC.
+pub fn utc_date(ts: u64) -> String {
+    // Civil-from-days over the proleptic Gregorian calendar, in 400-year eras.
+    let days = (ts / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -1,2 +1,16 @@
+//! Trade history is always available; the write-ahead log, snapshots and their retention need the
+//! `persistence` feature, and the Parquet exporter the `export` feature.
+
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "persistence")]
+pub mod retention;
+#[cfg(feature = "persistence")]
 pub mod snapshot;
+pub mod trades;
+#[cfg(feature = "persistence")]
 pub mod wal;
+
+/// Largest record a length-prefixed log accepts. A length prefix above this cannot come from a
+/// torn append, so it is reported as corruption rather than truncated.
+pub const MAX_RECORD_LEN: usize = 64 * 1024 * 1024;
@@ -0,0 +1,144 @@
+//! Garbage collection of obsolete snapshots and the WAL records they cover.
+//!
+//! Snapshots are grouped by shard and pruned according to a [`SnapshotRetention`] policy. The
+//! oldest snapshot kept for a shard bounds how far back that shard can be rebuilt, so every WAL
+//! input at or below its `last_seq` can be dropped as well.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::config::SnapshotRetention;
+use crate::persistence::snapshot::SnapshotStore;
+use crate::persistence::wal::Wal;
+
+const SECS_PER_HOUR: u64 = 3_600;
+const SECS_PER_DAY: u64 = 86_400;
+
+/// One snapshot file found in a snapshot directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotFile {
+    pub path: PathBuf,
+    pub shard_id: usize,
+    pub last_seq: u64,
+    /// Unix seconds the file was written.
+    pub taken_at: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed: Vec<PathBuf>,
+    pub reclaimed_bytes: u64,
+    /// Per shard, the `last_seq` of the oldest snapshot kept.
+    pub covered: BTreeMap<usize, u64>,
+}
+
+/// Indices of the snapshots `policy` keeps.
+pub fn select(snapshots: &[SnapshotFile], policy: &SnapshotRetention) -> BTreeSet<usize> {
+    let mut by_shard: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        by_shard.entry(snapshot.shard_id).or_default().push(index);
+    }
+    let mut keep = BTreeSet::new();
+    for mut indices in by_shard.into_values() {
+        // Newest first.
+        indices.sort_by_key(|&index| std::cmp::Reverse((snapshots[index].last_seq, snapshots[index].taken_at)));
+        keep.extend(indices.iter().take(policy.keep_last.max(1)));
+        for (bucket_secs, buckets) in [(SECS_PER_HOUR, policy.keep_hourly), (SECS_PER_DAY, policy.keep_daily)] {
+            let mut seen = BTreeSet::new();
+            for &index in &indices {
+                if seen.len() == buckets {
+                    break;
+                }
+                if seen.insert(snapshots[index].taken_at / bucket_secs) {
+                    keep.insert(index);
+                }
+            }
+        }
+    }
+    keep
+}
+
+/// Lists the snapshots in `dir`. Latest pointers are ignored, and files that do not decode as
+/// snapshots, such as an in-progress temporary file, are skipped.
+pub fn list_snapshots(dir: &Path) -> anyhow::Result<Vec<SnapshotFile>> {
+    let mut snapshots = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().is_some_and(|extension| extension == "latest") {
+            continue;
+        }
+        let snapshot = match SnapshotStore::load(&path) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!(path = %path.display(), %err, "skipping unreadable snapshot");
+                continue;
+            }
+        };
+        let metadata = std::fs::metadata(&path)?;
+        let taken_at = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        snapshots.push(SnapshotFile {
+            path,
+            shard_id: snapshot.meta.shard_id,
+            last_seq: snapshot.meta.last_seq,
+            taken_at,
+            size: metadata.len(),
+        });
+    }
+    Ok(snapshots)
+}
+
+/// Deletes the snapshots in `dir` that `policy` does not keep.
+pub fn gc_snapshots(dir: &Path, policy: &SnapshotRetention) -> anyhow::Result<GcReport> {
+    let snapshots = list_snapshots(dir)?;
+    let keep = select(&snapshots, policy);
+    let mut report = GcReport::default();
+    for (index, snapshot) in snapshots.into_iter().enumerate() {
+        if keep.contains(&index) {
+            let covered = report.covered.entry(snapshot.shard_id).or_insert(snapshot.last_seq);
+            *covered = (*covered).min(snapshot.last_seq);
+            continue;
+        }
+        std::fs::remove_file(&snapshot.path)?;
+        report.reclaimed_bytes += snapshot.size;
+        report.removed.push(snapshot.path);
+    }
+    metrics::counter!("snapshot_gc_removed_total").increment(report.removed.len() as u64);
+    metrics::counter!("snapshot_gc_reclaimed_bytes_total").increment(report.reclaimed_bytes);
+    Ok(report)
+}
+
+/// Rewrites the WAL at `path` without the records each shard's oldest kept snapshot already
+/// covers, returning the bytes reclaimed. Records of shards missing from `covered` are kept. The
+/// file is replaced by rename, so the engine must not have the WAL open.
+pub fn compact_wal(path: &Path, covered: &BTreeMap<usize, u64>) -> anyhow::Result<u64> {
+    let before = std::fs::metadata(path)?.len();
+    let records = Wal::load(path)?;
+    let retained: Vec<_> = records
+        .into_iter()
+        .filter(|envelope| covered.get(&envelope.shard_id).is_none_or(|seq| envelope.engine_seq > *seq))
+        .collect();
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".compact");
+    let tmp_path = path.with_file_name(tmp_name);
+    let _ = std::fs::remove_file(&tmp_path);
+    let mut wal = Wal::open(&tmp_path)?;
+    for envelope in &retained {
+        wal.append(envelope)?;
+    }
+    wal.sync()?;
+    drop(wal);
+    std::fs::rename(&tmp_path, path)?;
+
+    let reclaimed = before.saturating_sub(std::fs::metadata(path)?.len());
+    metrics::counter!("wal_gc_reclaimed_bytes_total").increment(reclaimed);
+    Ok(reclaimed)
+}
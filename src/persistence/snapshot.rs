@@ -1,10 +1,22 @@
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use prost::Message;
 use serde::{Deserialize, Serialize};
 
+use crate::config::{AccountConfig, MarketConfig};
+use crate::engine::oracle::OracleQuote;
+use crate::engine::shard::{first_trade_id, MarketExport, OrderSnapshot, ReferencePrices};
 use crate::engine::EngineState;
+use crate::models::{pb, MarketId, NewOrder, OrderId, OrderType, PriceTicks, Side, TimeInForce};
+use crate::risk::{Position, RiskState, Subaccount};
+
+/// Version written by [`SnapshotStore::build`]. Version 1 predates nonce watermarks, version 2
+/// trade ids, version 3 order types, TIFs and reduce-only flags, version 4 everything else a
+/// market holds besides its orders.
+pub const SNAPSHOT_VERSION: u32 = 5;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SnapshotMeta {
@@ -20,24 +32,307 @@ pub struct Snapshot {
     pub state: EngineState,
 }
 
+/// Resting order in versions 1 to 3, restored as a plain GTC limit order.
+#[derive(Deserialize)]
+struct OrderSnapshotV3 {
+    order_id: OrderId,
+    subaccount_id: u64,
+    side: Side,
+    price_ticks: PriceTicks,
+    remaining: u64,
+    ingress_seq: u64,
+}
+
+impl From<OrderSnapshotV3> for OrderSnapshot {
+    fn from(value: OrderSnapshotV3) -> Self {
+        Self {
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            side: value.side,
+            order_type: OrderType::Limit,
+            tif: TimeInForce::Gtc,
+            price_ticks: value.price_ticks,
+            remaining: value.remaining,
+            reduce_only: false,
+            ingress_seq: value.ingress_seq,
+        }
+    }
+}
+
+fn upgrade_orderbooks(orderbooks: BTreeMap<MarketId, Vec<OrderSnapshotV3>>) -> BTreeMap<MarketId, Vec<OrderSnapshot>> {
+    orderbooks
+        .into_iter()
+        .map(|(market_id, orders)| (market_id, orders.into_iter().map(OrderSnapshot::from).collect()))
+        .collect()
+}
+
+/// Version 1 layout, read by [`SnapshotStore::load`] and upgraded with empty nonce watermarks.
+#[derive(Deserialize)]
+struct SnapshotV1 {
+    meta: SnapshotMeta,
+    state: EngineStateV1,
+}
+
+#[derive(Deserialize)]
+struct EngineStateV1 {
+    shard_id: usize,
+    engine_seq: u64,
+    next_order_id: u64,
+    orderbooks: BTreeMap<MarketId, Vec<OrderSnapshotV3>>,
+    risk_state: RiskState,
+}
+
+impl From<SnapshotV1> for SnapshotV4 {
+    fn from(value: SnapshotV1) -> Self {
+        let state = value.state;
+        Self {
+            meta: value.meta,
+            state: EngineStateV4 {
+                shard_id: state.shard_id,
+                engine_seq: state.engine_seq,
+                next_order_id: state.next_order_id,
+                orderbooks: upgrade_orderbooks(state.orderbooks),
+                risk_state: state.risk_state,
+                nonce_watermarks: BTreeMap::new(),
+                next_trade_id: first_trade_id(state.shard_id),
+            },
+        }
+    }
+}
+
+/// Version 2 layout, upgraded with trade ids numbered from the start of the shard's range.
+#[derive(Deserialize)]
+struct SnapshotV2 {
+    meta: SnapshotMeta,
+    state: EngineStateV2,
+}
+
+#[derive(Deserialize)]
+struct EngineStateV2 {
+    shard_id: usize,
+    engine_seq: u64,
+    next_order_id: u64,
+    orderbooks: BTreeMap<MarketId, Vec<OrderSnapshotV3>>,
+    risk_state: RiskState,
+    nonce_watermarks: BTreeMap<MarketId, BTreeMap<u64, u64>>,
+}
+
+impl From<SnapshotV2> for SnapshotV4 {
+    fn from(value: SnapshotV2) -> Self {
+        let state = value.state;
+        Self {
+            meta: value.meta,
+            state: EngineStateV4 {
+                shard_id: state.shard_id,
+                engine_seq: state.engine_seq,
+                next_order_id: state.next_order_id,
+                orderbooks: upgrade_orderbooks(state.orderbooks),
+                risk_state: state.risk_state,
+                nonce_watermarks: state.nonce_watermarks,
+                next_trade_id: first_trade_id(state.shard_id),
+            },
+        }
+    }
+}
+
+/// Version 3 layout, upgraded with every resting order a plain GTC limit order.
+#[derive(Deserialize)]
+struct SnapshotV3 {
+    meta: SnapshotMeta,
+    state: EngineStateV3,
+}
+
+#[derive(Deserialize)]
+struct EngineStateV3 {
+    shard_id: usize,
+    engine_seq: u64,
+    next_order_id: u64,
+    orderbooks: BTreeMap<MarketId, Vec<OrderSnapshotV3>>,
+    risk_state: RiskState,
+    nonce_watermarks: BTreeMap<MarketId, BTreeMap<u64, u64>>,
+    next_trade_id: u64,
+}
+
+impl From<SnapshotV3> for SnapshotV4 {
+    fn from(value: SnapshotV3) -> Self {
+        let state = value.state;
+        Self {
+            meta: value.meta,
+            state: EngineStateV4 {
+                shard_id: state.shard_id,
+                engine_seq: state.engine_seq,
+                next_order_id: state.next_order_id,
+                orderbooks: upgrade_orderbooks(state.orderbooks),
+                risk_state: state.risk_state,
+                nonce_watermarks: state.nonce_watermarks,
+                next_trade_id: state.next_trade_id,
+            },
+        }
+    }
+}
+
+/// Version 4 layout, which kept only each market's orders and nonce watermarks. It is upgraded
+/// to exports without a config, so restore uses the configured markets, and with positions and
+/// prices left in the risk state.
+#[derive(Deserialize)]
+struct SnapshotV4 {
+    meta: SnapshotMeta,
+    state: EngineStateV4,
+}
+
+#[derive(Deserialize)]
+struct EngineStateV4 {
+    shard_id: usize,
+    engine_seq: u64,
+    next_order_id: u64,
+    orderbooks: BTreeMap<MarketId, Vec<OrderSnapshot>>,
+    risk_state: RiskState,
+    nonce_watermarks: BTreeMap<MarketId, BTreeMap<u64, u64>>,
+    next_trade_id: u64,
+}
+
+impl From<EngineStateV4> for EngineState {
+    fn from(state: EngineStateV4) -> Self {
+        Self {
+            shard_id: state.shard_id,
+            engine_seq: state.engine_seq,
+            next_order_id: state.next_order_id,
+            markets: legacy_markets(state.orderbooks, state.nonce_watermarks),
+            risk_state: state.risk_state,
+            next_trade_id: state.next_trade_id,
+            clearing_seed: 0,
+            accounts: Vec::new(),
+        }
+    }
+}
+
+impl From<SnapshotV4> for Snapshot {
+    fn from(value: SnapshotV4) -> Self {
+        Self {
+            meta: value.meta,
+            state: value.state.into(),
+        }
+    }
+}
+
+/// Exports holding only the orders and nonce watermarks that layouts before version 5 kept.
+fn legacy_markets(
+    orderbooks: BTreeMap<MarketId, Vec<OrderSnapshot>>,
+    nonce_watermarks: BTreeMap<MarketId, BTreeMap<u64, u64>>,
+) -> BTreeMap<MarketId, MarketExport> {
+    let mut markets: BTreeMap<MarketId, MarketExport> = BTreeMap::new();
+    for (market_id, orders) in orderbooks {
+        markets.entry(market_id).or_default().orders = orders;
+    }
+    for (market_id, watermarks) in nonce_watermarks {
+        markets.entry(market_id).or_default().nonce_watermarks = watermarks;
+    }
+    markets
+}
+
 pub struct SnapshotStore;
 
 impl SnapshotStore {
+    /// Replaces `path` with the bincode-encoded snapshot; a crash never leaves a partial file.
     pub fn save(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
-        let bytes = bincode::serialize(snapshot)?;
-        let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
-        file.write_all(&bytes)?;
-        Ok(())
+        write_atomic(path, &bincode::serialize(snapshot)?)
+    }
+
+    /// Like [`save`](Self::save), in the portable protobuf form of `proto/snapshot.proto`.
+    pub fn save_proto(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+        write_atomic(path, &pb::Snapshot::from(snapshot).encode_to_vec())
+    }
+
+    /// File name of the snapshot of `shard_id` up to `last_seq` in a snapshot directory. The
+    /// sequence is zero-padded so names sort in sequence order.
+    pub fn file_name(shard_id: usize, last_seq: u64) -> String {
+        format!("snapshot-{shard_id}-{last_seq:020}.bin")
+    }
+
+    /// File in `dir` holding the file name of the shard's latest snapshot there.
+    pub fn latest_pointer(dir: &Path, shard_id: usize) -> PathBuf {
+        dir.join(format!("snapshot-{shard_id}.latest"))
+    }
+
+    /// Saves `snapshot` into `dir` under [`file_name`](Self::file_name), then points the shard's
+    /// [`latest_pointer`](Self::latest_pointer) at it. Both writes are atomic, so the pointer
+    /// always names a complete snapshot. Returns the snapshot's path.
+    pub fn save_to_dir(dir: &Path, snapshot: &Snapshot) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let file_name = Self::file_name(snapshot.meta.shard_id, snapshot.meta.last_seq);
+        let path = dir.join(&file_name);
+        Self::save(&path, snapshot)?;
+        write_atomic(&Self::latest_pointer(dir, snapshot.meta.shard_id), file_name.as_bytes())?;
+        Ok(path)
+    }
+
+    /// Loads the snapshot the shard's latest pointer in `dir` names; `None` before the first save.
+    pub fn load_latest(dir: &Path, shard_id: usize) -> anyhow::Result<Option<Snapshot>> {
+        let pointer = Self::latest_pointer(dir, shard_id);
+        if !pointer.exists() {
+            return Ok(None);
+        }
+        let file_name = std::fs::read_to_string(&pointer)?;
+        let path = dir.join(file_name.trim());
+        let snapshot = Self::load(&path)?.ok_or_else(|| anyhow::anyhow!("{} names missing snapshot {}", pointer.display(), path.display()))?;
+        anyhow::ensure!(
+            snapshot.meta.shard_id == shard_id,
+            "{} names a snapshot of shard {}",
+            pointer.display(),
+            snapshot.meta.shard_id
+        );
+        Ok(Some(snapshot))
     }
 
+    /// Loads a snapshot, failing if its state does not match the recorded checksum, shard id or
+    /// last sequence.
     pub fn load(path: &Path) -> anyhow::Result<Option<Snapshot>> {
-        if !path.exists() {
+        let Some(buf) = read(path)? else {
             return Ok(None);
+        };
+        let meta: SnapshotMeta = bincode::deserialize(&buf)?;
+        // The state is encoded right after the meta; the checksum covers its bytes as written, in
+        // whichever layout the version had.
+        let state_bytes = &buf[bincode::serialized_size(&meta)? as usize..];
+        let checksum = blake3::hash(state_bytes).to_hex().to_string();
+        anyhow::ensure!(
+            checksum == meta.checksum,
+            "snapshot {} is corrupt: checksum {checksum} does not match the recorded {}",
+            path.display(),
+            meta.checksum
+        );
+        let snapshot = decode(&buf, meta.version)?;
+        snapshot.check_meta()?;
+        Ok(Some(snapshot))
+    }
+
+    /// Like [`load`](Self::load) without any check, for inspecting a snapshot that may be corrupt.
+    pub fn load_unchecked(path: &Path) -> anyhow::Result<Option<Snapshot>> {
+        let Some(buf) = read(path)? else {
+            return Ok(None);
+        };
+        let meta: SnapshotMeta = bincode::deserialize(&buf)?;
+        Ok(Some(decode(&buf, meta.version)?))
+    }
+
+    /// Loads a snapshot written by [`save_proto`](Self::save_proto) or by any other protobuf
+    /// producer of `proto/snapshot.proto`, with the same checks as [`load`](Self::load).
+    pub fn load_proto(path: &Path) -> anyhow::Result<Option<Snapshot>> {
+        let Some(bytes) = read(path)? else {
+            return Ok(None);
+        };
+        let snapshot = pb::Snapshot::decode(bytes.as_slice())?;
+        if let (Some(meta), Some(state)) = (&snapshot.meta, &snapshot.state) {
+            let checksum = blake3::hash(&state.encode_to_vec()).to_hex().to_string();
+            anyhow::ensure!(
+                checksum == meta.checksum,
+                "snapshot {} is corrupt: checksum {checksum} does not match the recorded {}",
+                path.display(),
+                meta.checksum
+            );
         }
-        let mut file = File::open(path)?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let snapshot: Snapshot = bincode::deserialize(&buf)?;
+        let snapshot = Snapshot::try_from(snapshot)?;
+        snapshot.check_meta()?;
         Ok(Some(snapshot))
     }
 
@@ -45,7 +340,7 @@ impl SnapshotStore {
         let checksum = blake3::hash(&bincode::serialize(&state).unwrap_or_default()).to_hex().to_string();
         Snapshot {
             meta: SnapshotMeta {
-                version: 1,
+                version: SNAPSHOT_VERSION,
                 shard_id,
                 last_seq,
                 checksum,
@@ -54,3 +349,493 @@ impl SnapshotStore {
         }
     }
 }
+
+impl Snapshot {
+    /// Whether the meta describes the state it was saved with.
+    fn check_meta(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.meta.shard_id == self.state.shard_id,
+            "snapshot meta is for shard {} but its state for shard {}",
+            self.meta.shard_id,
+            self.state.shard_id
+        );
+        anyhow::ensure!(
+            self.meta.last_seq == self.state.engine_seq,
+            "snapshot meta ends at sequence {} but its state at {}",
+            self.meta.last_seq,
+            self.state.engine_seq
+        );
+        Ok(())
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut file = File::open(path)?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(Some(buf))
+}
+
+/// Decodes a bincode snapshot in the layout of its `version`; the meta leads every layout.
+fn decode(buf: &[u8], version: u32) -> anyhow::Result<Snapshot> {
+    Ok(match version {
+        1 => SnapshotV4::from(bincode::deserialize::<SnapshotV1>(buf)?).into(),
+        2 => SnapshotV4::from(bincode::deserialize::<SnapshotV2>(buf)?).into(),
+        3 => SnapshotV4::from(bincode::deserialize::<SnapshotV3>(buf)?).into(),
+        4 => bincode::deserialize::<SnapshotV4>(buf)?.into(),
+        _ => bincode::deserialize::<Snapshot>(buf)?,
+    })
+}
+
+/// Writes `bytes` to a temporary file next to `path`, syncs it and renames it over `path`, then
+/// syncs the directory. The previous file stays intact until the rename, so a crash at any point
+/// leaves either the old or the new contents, never a partial file.
+fn write_atomic(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("snapshot path {} has no file name", path.display()))?;
+    let mut tmp_name = file_name.to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp_path, path)?;
+    sync_dir(path)?;
+    Ok(())
+}
+
+/// Makes a rename in the directory holding `path` durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// The protobuf checksum covers the encoded `state` message, so non-Rust producers can compute it.
+impl From<&Snapshot> for pb::Snapshot {
+    fn from(value: &Snapshot) -> Self {
+        let state = pb::EngineState::from(&value.state);
+        let checksum = blake3::hash(&state.encode_to_vec()).to_hex().to_string();
+        Self {
+            meta: Some(pb::SnapshotMeta {
+                version: value.meta.version,
+                shard_id: value.meta.shard_id as u64,
+                last_seq: value.meta.last_seq,
+                checksum,
+            }),
+            state: Some(state),
+        }
+    }
+}
+
+/// The converted snapshot carries the checksum [`SnapshotStore::build`] computes for its state.
+impl TryFrom<pb::Snapshot> for Snapshot {
+    type Error = anyhow::Error;
+
+    fn try_from(value: pb::Snapshot) -> anyhow::Result<Self> {
+        let meta = value.meta.ok_or_else(|| anyhow::anyhow!("snapshot has no meta"))?;
+        let state = EngineState::try_from(value.state.ok_or_else(|| anyhow::anyhow!("snapshot has no state"))?)?;
+        let mut snapshot = SnapshotStore::build(meta.shard_id as usize, meta.last_seq, state);
+        snapshot.meta.version = meta.version;
+        Ok(snapshot)
+    }
+}
+
+impl From<&EngineState> for pb::EngineState {
+    fn from(value: &EngineState) -> Self {
+        Self {
+            shard_id: value.shard_id as u64,
+            engine_seq: value.engine_seq,
+            next_order_id: value.next_order_id,
+            orderbooks: Vec::new(),
+            risk_state: Some(pb::RiskState::from(&value.risk_state)),
+            nonce_watermarks: Vec::new(),
+            next_trade_id: value.next_trade_id,
+            markets: value
+                .markets
+                .iter()
+                .map(|(market_id, market)| market_to_pb(*market_id, market))
+                .collect(),
+            clearing_seed: value.clearing_seed,
+            accounts: value
+                .accounts
+                .iter()
+                .map(|account| pb::Account {
+                    parent_id: account.parent_id,
+                    subaccounts: account.subaccounts.clone(),
+                    max_total_position: account.max_total_position,
+                    max_total_open_orders: account.max_total_open_orders,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// States from before version 5 list orders and nonce watermarks outside `markets`; they are read
+/// into exports as [`SnapshotStore::load`] reads a version 4 state.
+impl TryFrom<pb::EngineState> for EngineState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: pb::EngineState) -> anyhow::Result<Self> {
+        let mut orderbooks = BTreeMap::new();
+        for book in value.orderbooks {
+            let orders = book
+                .orders
+                .into_iter()
+                .map(OrderSnapshot::try_from)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            anyhow::ensure!(
+                orderbooks.insert(book.market_id, orders).is_none(),
+                "market {} listed twice",
+                book.market_id
+            );
+        }
+        let mut nonce_watermarks: BTreeMap<u64, BTreeMap<u64, u64>> = BTreeMap::new();
+        for watermark in value.nonce_watermarks {
+            nonce_watermarks
+                .entry(watermark.market_id)
+                .or_default()
+                .insert(watermark.subaccount_id, watermark.nonce);
+        }
+        let mut markets = legacy_markets(orderbooks, nonce_watermarks);
+        for market in value.markets {
+            let market_id = market.market_id;
+            anyhow::ensure!(
+                markets.insert(market_id, market_from_pb(market)?).is_none(),
+                "market {market_id} listed twice"
+            );
+        }
+        let shard_id = value.shard_id as usize;
+        Ok(Self {
+            shard_id,
+            engine_seq: value.engine_seq,
+            next_order_id: value.next_order_id,
+            markets,
+            risk_state: value.risk_state.unwrap_or_default().into(),
+            next_trade_id: value.next_trade_id.max(first_trade_id(shard_id)),
+            clearing_seed: value.clearing_seed,
+            accounts: value
+                .accounts
+                .into_iter()
+                .map(|account| AccountConfig {
+                    parent_id: account.parent_id,
+                    subaccounts: account.subaccounts,
+                    max_total_position: account.max_total_position,
+                    max_total_open_orders: account.max_total_open_orders,
+                })
+                .collect(),
+        })
+    }
+}
+
+fn market_to_pb(market_id: MarketId, market: &MarketExport) -> pb::MarketExport {
+    pb::MarketExport {
+        market_id,
+        config_json: market
+            .config
+            .as_ref()
+            .map(|config| serde_json::to_string(config).expect("market config serializes"))
+            .unwrap_or_default(),
+        orders: market.orders.iter().map(pb::RestingOrder::from).collect(),
+        batch_pending: market
+            .batch_pending
+            .iter()
+            .map(|order| pb::RestingOrder::from(&OrderSnapshot::from(order)))
+            .collect(),
+        conditional: market
+            .conditional
+            .iter()
+            .map(|(order_id, order)| pb::ConditionalOrder {
+                order_id: *order_id,
+                order: Some(pb::NewOrder::from(order.clone())),
+            })
+            .collect(),
+        positions: market
+            .positions
+            .iter()
+            .map(|(subaccount_id, position)| pb::MarketPosition {
+                subaccount_id: *subaccount_id,
+                size: position.size,
+                entry_price: position.entry_price,
+                funding_index: position.funding_index,
+            })
+            .collect(),
+        mark_price: market.mark_price.unwrap_or(0),
+        funding_index: market.funding_index.unwrap_or(0),
+        restricted: market.allowlist.is_some(),
+        allowlist: market.allowlist.iter().flatten().copied().collect(),
+        prices: Some(pb::ReferencePrices {
+            mark: market.prices.mark.unwrap_or(0),
+            index: market.prices.index.unwrap_or(0),
+            last_trade: market.prices.last_trade.unwrap_or(0),
+        }),
+        next_clear_at: market.next_clear_at.unwrap_or(0),
+        auction_round: market.auction_round,
+        nonce_watermarks: market
+            .nonce_watermarks
+            .iter()
+            .map(|(subaccount_id, nonce)| pb::SubaccountNonce {
+                subaccount_id: *subaccount_id,
+                nonce: *nonce,
+            })
+            .collect(),
+        volatility_marks: market.volatility_marks.clone(),
+        initial_margin_bps: market.initial_margin_bps.unwrap_or(0),
+        oracle_quotes: market
+            .oracle_quotes
+            .iter()
+            .map(|(source, quote)| pb::OracleQuote {
+                source: source.clone(),
+                mark_price: quote.mark_price,
+                index_price: quote.index_price,
+                ts: quote.ts,
+            })
+            .collect(),
+        next_funding_at: market.next_funding_at.unwrap_or(0),
+        next_margin_at: market.next_margin_at.unwrap_or(0),
+    }
+}
+
+fn market_from_pb(value: pb::MarketExport) -> anyhow::Result<MarketExport> {
+    let market_id = value.market_id;
+    let config = match value.config_json.as_str() {
+        "" => None,
+        json => Some(serde_json::from_str::<MarketConfig>(json).map_err(|err| anyhow::anyhow!("market {market_id} has an invalid config: {err}"))?),
+    };
+    let conditional = value
+        .conditional
+        .into_iter()
+        .map(|conditional| {
+            let order = conditional
+                .order
+                .ok_or_else(|| anyhow::anyhow!("conditional order {} has no order", conditional.order_id))?;
+            Ok((conditional.order_id, NewOrder::try_from(order)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let nonzero = |value: u64| (value != 0).then_some(value);
+    let prices = value.prices.unwrap_or_default();
+    Ok(MarketExport {
+        config,
+        orders: value
+            .orders
+            .into_iter()
+            .map(OrderSnapshot::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        batch_pending: value
+            .batch_pending
+            .into_iter()
+            .map(|order| OrderSnapshot::try_from(order).map(|order| order.incoming()))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        conditional,
+        positions: value
+            .positions
+            .into_iter()
+            .map(|position| {
+                let state = Position {
+                    size: position.size,
+                    entry_price: position.entry_price,
+                    funding_index: position.funding_index,
+                };
+                (position.subaccount_id, state)
+            })
+            .collect(),
+        mark_price: nonzero(value.mark_price),
+        funding_index: (value.funding_index != 0).then_some(value.funding_index),
+        allowlist: value.restricted.then(|| value.allowlist.into_iter().collect()),
+        prices: ReferencePrices {
+            mark: nonzero(prices.mark),
+            index: nonzero(prices.index),
+            last_trade: nonzero(prices.last_trade),
+        },
+        next_clear_at: nonzero(value.next_clear_at),
+        auction_round: value.auction_round,
+        nonce_watermarks: value
+            .nonce_watermarks
+            .into_iter()
+            .map(|watermark| (watermark.subaccount_id, watermark.nonce))
+            .collect(),
+        volatility_marks: value.volatility_marks,
+        initial_margin_bps: nonzero(value.initial_margin_bps),
+        oracle_quotes: value
+            .oracle_quotes
+            .into_iter()
+            .map(|quote| {
+                let state = OracleQuote {
+                    mark_price: quote.mark_price,
+                    index_price: quote.index_price,
+                    ts: quote.ts,
+                };
+                (quote.source, state)
+            })
+            .collect(),
+        next_funding_at: nonzero(value.next_funding_at),
+        next_margin_at: nonzero(value.next_margin_at),
+    })
+}
+
+impl From<&OrderSnapshot> for pb::RestingOrder {
+    fn from(value: &OrderSnapshot) -> Self {
+        Self {
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            side: match value.side {
+                Side::Buy => "BUY".to_string(),
+                Side::Sell => "SELL".to_string(),
+            },
+            price_ticks: value.price_ticks,
+            remaining: value.remaining,
+            ingress_seq: value.ingress_seq,
+            order_type: match value.order_type {
+                OrderType::Limit => "LIMIT",
+                OrderType::Market => "MARKET",
+                OrderType::PostOnly => "POST_ONLY",
+                OrderType::Ioc => "IOC",
+                OrderType::Fok => "FOK",
+            }
+            .to_string(),
+            tif: match value.tif {
+                TimeInForce::Gtc => "GTC",
+                TimeInForce::Ioc => "IOC",
+                TimeInForce::Fok => "FOK",
+                TimeInForce::Gtd { .. } => "GTD",
+            }
+            .to_string(),
+            expires_at: value.tif.expires_at().unwrap_or(0),
+            reduce_only: value.reduce_only,
+        }
+    }
+}
+
+impl TryFrom<pb::RestingOrder> for OrderSnapshot {
+    type Error = anyhow::Error;
+
+    fn try_from(value: pb::RestingOrder) -> anyhow::Result<Self> {
+        let side = match value.side.as_str() {
+            "BUY" => Side::Buy,
+            "SELL" => Side::Sell,
+            other => anyhow::bail!("order {} has unknown side `{other}`", value.order_id),
+        };
+        let order_type = match value.order_type.as_str() {
+            "" | "LIMIT" => OrderType::Limit,
+            "MARKET" => OrderType::Market,
+            "POST_ONLY" => OrderType::PostOnly,
+            "IOC" => OrderType::Ioc,
+            "FOK" => OrderType::Fok,
+            other => anyhow::bail!("order {} has unknown order type `{other}`", value.order_id),
+        };
+        let tif = match value.tif.as_str() {
+            "" | "GTC" => TimeInForce::Gtc,
+            "IOC" => TimeInForce::Ioc,
+            "FOK" => TimeInForce::Fok,
+            "GTD" => TimeInForce::Gtd {
+                expires_at: value.expires_at,
+            },
+            other => anyhow::bail!("order {} has unknown time in force `{other}`", value.order_id),
+        };
+        Ok(Self {
+            order_id: value.order_id,
+            subaccount_id: value.subaccount_id,
+            side,
+            order_type,
+            tif,
+            price_ticks: value.price_ticks,
+            remaining: value.remaining,
+            reduce_only: value.reduce_only,
+            ingress_seq: value.ingress_seq,
+        })
+    }
+}
+
+impl From<&RiskState> for pb::RiskState {
+    fn from(value: &RiskState) -> Self {
+        Self {
+            subaccounts: value
+                .subaccounts
+                .iter()
+                .map(|(subaccount_id, account)| pb::SubaccountState {
+                    subaccount_id: *subaccount_id,
+                    collateral: account.collateral,
+                    cross_margin: account.cross_margin,
+                    positions: account
+                        .positions
+                        .iter()
+                        .map(|(market_id, position)| pb::PositionState {
+                            market_id: *market_id,
+                            size: position.size,
+                            entry_price: position.entry_price,
+                            funding_index: position.funding_index,
+                        })
+                        .collect(),
+                })
+                .collect(),
+            mark_prices: value
+                .mark_prices
+                .iter()
+                .map(|(market_id, price_ticks)| pb::MarketPrice {
+                    market_id: *market_id,
+                    price_ticks: *price_ticks,
+                })
+                .collect(),
+            funding_indices: value
+                .funding_indices
+                .iter()
+                .map(|(market_id, funding_index)| pb::MarketFundingIndex {
+                    market_id: *market_id,
+                    funding_index: *funding_index,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<pb::RiskState> for RiskState {
+    fn from(value: pb::RiskState) -> Self {
+        Self {
+            subaccounts: value
+                .subaccounts
+                .into_iter()
+                .map(|account| {
+                    let positions = account
+                        .positions
+                        .into_iter()
+                        .map(|position| {
+                            let state = Position {
+                                size: position.size,
+                                entry_price: position.entry_price,
+                                funding_index: position.funding_index,
+                            };
+                            (position.market_id, state)
+                        })
+                        .collect();
+                    let state = Subaccount {
+                        collateral: account.collateral,
+                        positions,
+                        cross_margin: account.cross_margin,
+                    };
+                    (account.subaccount_id, state)
+                })
+                .collect(),
+            mark_prices: value.mark_prices.into_iter().map(|price| (price.market_id, price.price_ticks)).collect(),
+            funding_indices: value
+                .funding_indices
+                .into_iter()
+                .map(|index| (index.market_id, index.funding_index))
+                .collect(),
+        }
+    }
+}
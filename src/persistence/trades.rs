@@ -0,0 +1,270 @@
+//! Per-subaccount trade history with bounded retention.
+//!
+//! Each shard records both sides of every fill it produces, keyed by subaccount, in memory and in
+//! a length-prefixed log of its own under the configured directory. A subaccount keeps at most
+//! `max_trades_per_subaccount` trades, none older than `retention_secs` of engine time. The log
+//! is rewritten with only the retained trades once it holds twice as many records as are
+//! retained, and is replayed when the shard starts. Trades from inputs older than the newest one
+//! recorded are ignored, so re-applying logged inputs does not duplicate them.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "proto")]
+use bytes::Bytes;
+#[cfg(feature = "proto")]
+use prost::Message;
+
+use crate::config::TradeHistoryConfig;
+#[cfg(feature = "proto")]
+use crate::config::WireCodec;
+#[cfg(feature = "proto")]
+use crate::models::pb;
+use crate::models::{SubaccountId, Trade};
+use crate::persistence::MAX_RECORD_LEN;
+
+/// Logs below this many records are never compacted.
+const MIN_COMPACT_RECORDS: usize = 1_024;
+
+#[derive(Debug)]
+pub struct TradeStore {
+    config: TradeHistoryConfig,
+    path: Option<PathBuf>,
+    file: Option<File>,
+    by_subaccount: HashMap<SubaccountId, VecDeque<Trade>>,
+    retained: usize,
+    logged: usize,
+    last_seq: u64,
+}
+
+impl TradeStore {
+    /// A store that keeps trades in memory only.
+    pub fn in_memory(config: TradeHistoryConfig) -> Self {
+        Self {
+            config,
+            path: None,
+            file: None,
+            by_subaccount: HashMap::new(),
+            retained: 0,
+            logged: 0,
+            last_seq: 0,
+        }
+    }
+
+    /// Opens the log of `shard_id` under the configured directory and replays it.
+    pub fn open(config: TradeHistoryConfig, shard_id: usize) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(&config.dir);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("trades-{shard_id}.log"));
+        let mut store = Self::in_memory(config);
+        let mut newest_ts = 0;
+        for trade in load(&path)? {
+            newest_ts = newest_ts.max(trade.ts);
+            store.last_seq = store.last_seq.max(trade.engine_seq);
+            store.retain(trade);
+        }
+        store.expire(newest_ts);
+        store.path = Some(path);
+        store.compact()?;
+        Ok(store)
+    }
+
+    /// Records `trades`, all from inputs after the ones already recorded, and prunes the history
+    /// of their subaccounts.
+    pub fn record(&mut self, trades: Vec<Trade>) -> anyhow::Result<()> {
+        let mut record = Vec::new();
+        for trade in trades {
+            if trade.engine_seq < self.last_seq {
+                continue;
+            }
+            self.last_seq = trade.engine_seq;
+            if self.file.is_some() {
+                let bytes = bincode::serialize(&trade)?;
+                anyhow::ensure!(bytes.len() <= MAX_RECORD_LEN, "trade record of {} bytes exceeds limit", bytes.len());
+                record.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                record.extend_from_slice(&bytes);
+                self.logged += 1;
+            }
+            let ts = trade.ts;
+            let subaccount_id = trade.subaccount_id;
+            self.retain(trade);
+            self.prune(subaccount_id, ts);
+        }
+        if let Some(file) = &mut self.file
+            && !record.is_empty()
+        {
+            file.write_all(&record)?;
+            file.flush()?;
+        }
+        if self.logged > MIN_COMPACT_RECORDS.max(self.retained * 2) {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// The subaccount's retained trades after the `(since_ts, since_seq)` cursor, oldest first, at
+    /// most `limit` of them (0 for all), and whether more matched.
+    pub fn query(&self, subaccount_id: SubaccountId, since_ts: u64, since_seq: u64, limit: usize) -> (Vec<Trade>, bool) {
+        let Some(trades) = self.by_subaccount.get(&subaccount_id) else {
+            return (Vec::new(), false);
+        };
+        let mut matched = trades.iter().filter(|trade| (trade.ts, trade.engine_seq) > (since_ts, since_seq));
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let page: Vec<Trade> = matched.by_ref().take(limit).cloned().collect();
+        (page, matched.next().is_some())
+    }
+
+    fn retain(&mut self, trade: Trade) {
+        let subaccount_id = trade.subaccount_id;
+        self.by_subaccount.entry(subaccount_id).or_default().push_back(trade);
+        self.retained += 1;
+        self.prune(subaccount_id, 0);
+    }
+
+    /// Drops the subaccount's trades beyond the count limit or older than the retention at `ts`.
+    fn prune(&mut self, subaccount_id: SubaccountId, ts: u64) {
+        let Some(trades) = self.by_subaccount.get_mut(&subaccount_id) else {
+            return;
+        };
+        let cutoff = ts.saturating_sub(self.config.retention_secs);
+        let before = trades.len();
+        while trades.len() > self.config.max_trades_per_subaccount.max(1)
+            || trades.front().is_some_and(|trade| self.config.retention_secs > 0 && trade.ts < cutoff)
+        {
+            trades.pop_front();
+        }
+        self.retained -= before - trades.len();
+        if trades.is_empty() {
+            self.by_subaccount.remove(&subaccount_id);
+        }
+    }
+
+    fn expire(&mut self, ts: u64) {
+        let subaccounts: Vec<SubaccountId> = self.by_subaccount.keys().copied().collect();
+        for subaccount_id in subaccounts {
+            self.prune(subaccount_id, ts);
+        }
+    }
+
+    /// Rewrites the log with only the retained trades, in recording order.
+    fn compact(&mut self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut trades: Vec<&Trade> = self.by_subaccount.values().flatten().collect();
+        trades.sort_by_key(|trade| (trade.engine_seq, trade.ts));
+        let mut bytes = Vec::new();
+        for trade in &trades {
+            let record = bincode::serialize(trade)?;
+            bytes.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&record);
+        }
+        let tmp = path.with_extension("log.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        self.file = Some(OpenOptions::new().append(true).open(path)?);
+        self.logged = trades.len();
+        Ok(())
+    }
+}
+
+/// Reads every complete record of a trade log; a torn final record is dropped.
+fn load(path: &Path) -> anyhow::Result<Vec<Trade>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let mut trades = Vec::new();
+    let mut offset = 0usize;
+    while bytes.len() - offset >= 4 {
+        let mut len_bytes = [0u8; 4];
+        len_bytes.copy_from_slice(&bytes[offset..offset + 4]);
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        anyhow::ensure!(len <= MAX_RECORD_LEN, "trade record at offset {offset} has invalid length {len}");
+        if len > bytes.len() - offset - 4 {
+            break;
+        }
+        let trade = bincode::deserialize(&bytes[offset + 4..offset + 4 + len])
+            .map_err(|err| anyhow::anyhow!("trade record at offset {offset} is corrupt: {err}"))?;
+        trades.push(trade);
+        offset += 4 + len;
+    }
+    Ok(trades)
+}
+
+#[cfg(feature = "proto")]
+pub fn encode_request(codec: WireCodec, request: &pb::TradeHistoryRequest) -> anyhow::Result<Bytes> {
+    Ok(match codec {
+        WireCodec::Protobuf => Bytes::from(request.encode_to_vec()),
+        WireCodec::Json => Bytes::from(serde_json::to_vec(request)?),
+    })
+}
+
+#[cfg(feature = "proto")]
+pub fn decode_request(codec: WireCodec, payload: Bytes) -> anyhow::Result<pb::TradeHistoryRequest> {
+    Ok(match codec {
+        WireCodec::Protobuf => pb::TradeHistoryRequest::decode(payload)?,
+        WireCodec::Json => serde_json::from_slice(&payload)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Side;
+
+    fn config(dir: &Path) -> TradeHistoryConfig {
+        TradeHistoryConfig {
+            dir: dir.display().to_string(),
+            max_trades_per_subaccount: 3,
+            retention_secs: 100,
+        }
+    }
+
+    fn trade(subaccount_id: SubaccountId, engine_seq: u64, ts: u64) -> Trade {
+        Trade {
+            subaccount_id,
+            trade_id: engine_seq,
+            market_id: 1,
+            order_id: engine_seq,
+            side: Side::Buy,
+            price_ticks: 100,
+            qty: 1,
+            fee: 0,
+            maker: false,
+            block_trade: false,
+            engine_seq,
+            ts,
+        }
+    }
+
+    fn seqs(trades: &[Trade]) -> Vec<u64> {
+        trades.iter().map(|trade| trade.engine_seq).collect()
+    }
+
+    #[test]
+    fn keeps_the_latest_trades_within_retention_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("trade_store_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut store = TradeStore::open(config(&dir), 0).unwrap();
+        store.record((1..=5).map(|seq| trade(1, seq, seq * 10)).collect()).unwrap();
+        store.record(vec![trade(2, 6, 60)]).unwrap();
+        let (trades, more) = store.query(1, 0, 0, 0);
+        assert_eq!((seqs(&trades), more), (vec![3, 4, 5], false));
+        let (trades, more) = store.query(1, 30, 3, 1);
+        assert_eq!((seqs(&trades), more), (vec![4], true));
+
+        // Replayed inputs are not recorded twice, and by 165 only trades from 65 on are kept.
+        store.record(vec![trade(1, 5, 50), trade(1, 7, 165)]).unwrap();
+        drop(store);
+        let store = TradeStore::open(config(&dir), 0).unwrap();
+        assert_eq!(seqs(&store.query(1, 0, 0, 0).0), vec![7]);
+        assert!(store.query(2, 0, 0, 0).0.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -2,7 +2,14 @@ use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
-use crate::models::EventEnvelope;
+use tracing::warn;
+
+use crate::engine::log::EventLog;
+use crate::models::{EventEnvelope, EventEnvelopeV1, LegacyEventEnvelope, SCHEMA_VERSION};
+use crate::persistence::MAX_RECORD_LEN;
+
+/// Bytes ahead of each record's payload: its length and checksum, both little-endian `u32`s.
+const HEADER_LEN: usize = 8;
 
 #[derive(Debug)]
 pub struct Wal {
@@ -10,38 +17,105 @@ pub struct Wal {
 }
 
 impl Wal {
+    /// Opens the log for appending. A partial or mangled record left at the end by a crash
+    /// mid-append is cut off first, so new records never land behind it; corruption earlier in the
+    /// file is an error.
     pub fn open(path: &Path) -> anyhow::Result<Self> {
-        let file = OpenOptions::new().create(true).append(true).read(true).open(path)?;
+        let mut file = OpenOptions::new().create(true).append(true).read(true).open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let (_, valid_len) = Self::decode_prefix(&buf)?;
+        if valid_len < buf.len() {
+            warn!(
+                path = %path.display(),
+                valid_len,
+                dropped = buf.len() - valid_len,
+                "truncating torn wal record"
+            );
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
         Ok(Self { file })
     }
 
     pub fn append(&mut self, event: &EventEnvelope) -> anyhow::Result<()> {
         let bytes = bincode::serialize(event)?;
-        let len = bytes.len() as u32;
-        self.file.write_all(&len.to_le_bytes())?;
-        self.file.write_all(&bytes)?;
+        anyhow::ensure!(bytes.len() <= MAX_RECORD_LEN, "wal record of {} bytes exceeds limit", bytes.len());
+        // One write per record keeps the window for a torn append as small as possible.
+        self.file.write_all(&Self::frame(&bytes))?;
         self.file.flush()?;
         Ok(())
     }
 
+    /// Frames an encoded envelope as one log record: its length, checksum and the payload.
+    pub fn frame(payload: &[u8]) -> Vec<u8> {
+        let len_bytes = (payload.len() as u32).to_le_bytes();
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(&len_bytes);
+        record.extend_from_slice(&checksum(len_bytes, payload).to_le_bytes());
+        record.extend_from_slice(payload);
+        record
+    }
+
     pub fn load(path: &Path) -> anyhow::Result<Vec<EventEnvelope>> {
         if !path.exists() {
             return Ok(Vec::new());
         }
         let mut file = File::open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Self::decode(&buf)
+    }
+
+    /// Decodes a length-prefixed record stream, ignoring a trailing partial record. The length
+    /// prefix is untrusted and is checked against the bytes actually available before anything is
+    /// allocated.
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Vec<EventEnvelope>> {
+        Ok(Self::decode_prefix(bytes)?.0)
+    }
+
+    /// Decodes every complete record and returns them with the byte length they span. Each record
+    /// carries a checksum over its length and payload. Only the last record may be incomplete or
+    /// fail its checksum (a torn append); a bad record with more bytes after it, an oversized
+    /// length prefix, or a record that passes its checksum but fails to decode is corruption and an
+    /// error.
+    pub fn decode_prefix(bytes: &[u8]) -> anyhow::Result<(Vec<EventEnvelope>, usize)> {
         let mut events = Vec::new();
-        loop {
+        let mut offset = 0usize;
+        while bytes.len() - offset >= HEADER_LEN {
             let mut len_bytes = [0u8; 4];
-            if file.read_exact(&mut len_bytes).is_err() {
+            len_bytes.copy_from_slice(&bytes[offset..offset + 4]);
+            let mut sum_bytes = [0u8; 4];
+            sum_bytes.copy_from_slice(&bytes[offset + 4..offset + HEADER_LEN]);
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            if len > MAX_RECORD_LEN {
+                anyhow::bail!("wal record at offset {offset} has invalid length {len}");
+            }
+            if len > bytes.len() - offset - HEADER_LEN {
                 break;
             }
-            let len = u32::from_le_bytes(len_bytes) as usize;
-            let mut buf = vec![0u8; len];
-            file.read_exact(&mut buf)?;
-            let event: EventEnvelope = bincode::deserialize(&buf)?;
+            let end = offset + HEADER_LEN + len;
+            let record = &bytes[offset + HEADER_LEN..end];
+            if checksum(len_bytes, record) != u32::from_le_bytes(sum_bytes) {
+                anyhow::ensure!(end == bytes.len(), "wal record at offset {offset} fails its checksum with {} bytes after it", bytes.len() - end);
+                break;
+            }
+            let event = decode_record(record).map_err(|err| anyhow::anyhow!("wal record at offset {offset} is corrupt: {err}"))?;
+            anyhow::ensure!(
+                event.schema_version <= SCHEMA_VERSION,
+                "wal record at offset {offset} has schema version {}, newer than supported {SCHEMA_VERSION}",
+                event.schema_version
+            );
+            offset = end;
             events.push(event);
         }
-        Ok(events)
+        Ok((events, offset))
+    }
+
+    /// Flushes appended records to stable storage.
+    pub fn sync(&mut self) -> anyhow::Result<()> {
+        self.file.sync_all()?;
+        Ok(())
     }
 
     pub fn truncate(&mut self) -> anyhow::Result<()> {
@@ -50,3 +124,27 @@ impl Wal {
         Ok(())
     }
 }
+
+impl EventLog for Wal {
+    fn append(&mut self, event: &EventEnvelope) -> anyhow::Result<()> {
+        Wal::append(self, event)
+    }
+}
+
+/// First four bytes of the BLAKE3 hash of a record's length prefix and payload.
+fn checksum(len_bytes: [u8; 4], payload: &[u8]) -> u32 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&len_bytes);
+    hasher.update(payload);
+    let mut sum = [0u8; 4];
+    sum.copy_from_slice(&hasher.finalize().as_bytes()[..4]);
+    u32::from_le_bytes(sum)
+}
+
+/// Decodes one record in the current layout, falling back to the version 1 and pre-versioning
+/// layouts. Each is shorter than the next, so a record never decodes as a newer layout.
+fn decode_record(record: &[u8]) -> bincode::Result<EventEnvelope> {
+    bincode::deserialize::<EventEnvelope>(record)
+        .or_else(|err| bincode::deserialize::<EventEnvelopeV1>(record).map(EventEnvelope::from).map_err(|_| err))
+        .or_else(|err| bincode::deserialize::<LegacyEventEnvelope>(record).map(EventEnvelope::from).map_err(|_| err))
+}
@@ -0,0 +1,242 @@
+//! Read-only queries over the shards' latest snapshots, for dashboards and support tooling.
+//!
+//! [`SnapshotView`] holds each shard's most recent snapshot from `persistence.snapshot_dir`, and
+//! [`router`] serves it over HTTP: book depth per market, open orders and balances per
+//! subaccount. Answers are as of each shard's `last_seq`, which every response reports; nothing
+//! here touches a running shard, so the view lags the engine by up to `snapshot_interval_secs`
+//! plus the refresh interval.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::config::PersistenceConfig;
+use crate::engine::shard::OrderSnapshot;
+use crate::engine::EngineState;
+use crate::models::{BookLevel, MarketId, ShardId, Side, SubaccountId};
+use crate::persistence::snapshot::SnapshotStore;
+use crate::risk::Position;
+
+/// One shard's latest snapshot.
+#[derive(Debug)]
+pub struct ShardView {
+    pub last_seq: u64,
+    pub state: EngineState,
+}
+
+/// The latest snapshot of every shard that has written one.
+#[derive(Debug, Default)]
+pub struct SnapshotView {
+    pub shards: BTreeMap<ShardId, ShardView>,
+}
+
+/// A market's aggregated levels, best first.
+#[derive(Debug, Clone, Serialize)]
+pub struct Depth {
+    pub market_id: MarketId,
+    pub shard_id: ShardId,
+    pub last_seq: u64,
+    pub mark_price: Option<u64>,
+    pub bids: Vec<BookLevel>,
+    pub asks: Vec<BookLevel>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenOrder {
+    pub market_id: MarketId,
+    pub shard_id: ShardId,
+    pub last_seq: u64,
+    #[serde(flatten)]
+    pub order: OrderSnapshot,
+}
+
+/// A subaccount's collateral and positions on one shard's ledger.
+#[derive(Debug, Clone, Serialize)]
+pub struct Balance {
+    pub shard_id: ShardId,
+    pub last_seq: u64,
+    pub collateral: i64,
+    pub cross_margin: bool,
+    pub positions: BTreeMap<MarketId, Position>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShardStatus {
+    pub shard_id: ShardId,
+    pub last_seq: u64,
+    pub markets: Vec<MarketId>,
+    pub subaccounts: usize,
+}
+
+impl SnapshotView {
+    /// Reads the latest snapshot of shards `0..shard_count`. A shard whose snapshot cannot be read
+    /// keeps the one in `self`, if any, and its error is returned alongside.
+    pub fn reload(&self, persistence: &PersistenceConfig, shard_count: usize) -> (Self, Vec<(ShardId, anyhow::Error)>) {
+        let mut shards = BTreeMap::new();
+        let mut errors = Vec::new();
+        for shard_id in 0..shard_count {
+            let Some(dir) = persistence.snapshot_dir(shard_id) else { continue };
+            match SnapshotStore::load_latest(&dir, shard_id) {
+                Ok(Some(snapshot)) => {
+                    shards.insert(
+                        shard_id,
+                        ShardView {
+                            last_seq: snapshot.meta.last_seq,
+                            state: snapshot.state,
+                        },
+                    );
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    if let Some(previous) = self.shards.get(&shard_id) {
+                        shards.insert(
+                            shard_id,
+                            ShardView {
+                                last_seq: previous.last_seq,
+                                state: previous.state.clone(),
+                            },
+                        );
+                    }
+                    errors.push((shard_id, err));
+                }
+            }
+        }
+        (Self { shards }, errors)
+    }
+
+    /// Up to `levels` levels per side of `market_id`, if a shard's snapshot holds the market.
+    pub fn depth(&self, market_id: MarketId, levels: usize) -> Option<Depth> {
+        let (shard_id, shard) = self.shards.iter().find(|(_, shard)| shard.state.markets.contains_key(&market_id))?;
+        let market = &shard.state.markets[&market_id];
+        let orders = &market.orders;
+        let side_levels = |side: Side| {
+            let mut by_price = BTreeMap::<u64, u64>::new();
+            for order in orders.iter().filter(|order| order.side == side) {
+                *by_price.entry(order.price_ticks).or_default() += order.remaining;
+            }
+            let levels_iter = by_price.into_iter().map(|(price_ticks, qty)| BookLevel { price_ticks, qty });
+            match side {
+                Side::Buy => levels_iter.rev().take(levels).collect(),
+                Side::Sell => levels_iter.take(levels).collect::<Vec<_>>(),
+            }
+        };
+        Some(Depth {
+            market_id,
+            shard_id: *shard_id,
+            last_seq: shard.last_seq,
+            mark_price: market.mark_price.or_else(|| shard.state.risk_state.mark_prices.get(&market_id).copied()),
+            bids: side_levels(Side::Buy),
+            asks: side_levels(Side::Sell),
+        })
+    }
+
+    /// The subaccount's resting orders across shards, by market and then time priority.
+    pub fn open_orders(&self, subaccount_id: SubaccountId) -> Vec<OpenOrder> {
+        self.shards
+            .iter()
+            .flat_map(move |(shard_id, shard)| {
+                shard.state.markets.iter().flat_map(move |(market_id, market)| {
+                    market.orders.iter().filter(move |order| order.subaccount_id == subaccount_id).map(move |order| OpenOrder {
+                        market_id: *market_id,
+                        shard_id: *shard_id,
+                        last_seq: shard.last_seq,
+                        order: order.clone(),
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// The subaccount's ledger entry on each shard that has one; collateral is held per shard.
+    pub fn balances(&self, subaccount_id: SubaccountId) -> Vec<Balance> {
+        self.shards
+            .iter()
+            .filter_map(|(shard_id, shard)| {
+                let account = shard.state.risk_state.subaccounts.get(&subaccount_id)?;
+                Some(Balance {
+                    shard_id: *shard_id,
+                    last_seq: shard.last_seq,
+                    collateral: account.collateral,
+                    cross_margin: account.cross_margin,
+                    positions: shard.state.positions(subaccount_id),
+                })
+            })
+            .collect()
+    }
+
+    pub fn status(&self) -> Vec<ShardStatus> {
+        self.shards
+            .iter()
+            .map(|(shard_id, shard)| ShardStatus {
+                shard_id: *shard_id,
+                last_seq: shard.last_seq,
+                markets: shard.state.markets.keys().copied().collect(),
+                subaccounts: shard.state.risk_state.subaccounts.len(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthQuery {
+    #[serde(default = "default_levels")]
+    levels: usize,
+}
+
+fn default_levels() -> usize {
+    20
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+type ViewState = watch::Receiver<Arc<SnapshotView>>;
+
+/// `GET /status`, `GET /markets/:market_id/depth?levels=N`, `GET /subaccounts/:id/orders` and
+/// `GET /subaccounts/:id/balances` over whatever view `view` currently holds.
+pub fn router(view: ViewState) -> Router {
+    Router::new()
+        .route("/status", get(status))
+        .route("/markets/:market_id/depth", get(depth))
+        .route("/subaccounts/:subaccount_id/orders", get(open_orders))
+        .route("/subaccounts/:subaccount_id/balances", get(balances))
+        .with_state(view)
+}
+
+async fn status(State(view): State<ViewState>) -> Response {
+    let view = Arc::clone(&view.borrow());
+    Json(view.status()).into_response()
+}
+
+async fn depth(State(view): State<ViewState>, Path(market_id): Path<MarketId>, Query(query): Query<DepthQuery>) -> Response {
+    let view = Arc::clone(&view.borrow());
+    match view.depth(market_id, query.levels) {
+        Some(depth) => Json(depth).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorBody {
+                error: format!("no snapshot holds market {market_id}"),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+async fn open_orders(State(view): State<ViewState>, Path(subaccount_id): Path<SubaccountId>) -> Response {
+    let view = Arc::clone(&view.borrow());
+    Json(view.open_orders(subaccount_id)).into_response()
+}
+
+async fn balances(State(view): State<ViewState>, Path(subaccount_id): Path<SubaccountId>) -> Response {
+    let view = Arc::clone(&view.borrow());
+    Json(view.balances(subaccount_id)).into_response()
+}
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::config::MarketConfig;
-use crate::models::{MarketId, OrderType, PriceTicks, Side, SubaccountId};
+use crate::engine::funding::FUNDING_INDEX_SCALE;
+use crate::models::{MarketId, NewOrder, OrderType, PriceTicks, RejectReason, Side, SubaccountId};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Position {
@@ -10,26 +11,88 @@ pub struct Position {
     pub funding_index: i64,
 }
 
+/// What one fill did to a position: the PnL it realized on `closed_qty` lots and the position
+/// left afterwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PositionChange {
+    pub realized_pnl: i64,
+    pub closed_qty: u64,
+    pub size: i64,
+    pub entry_price: PriceTicks,
+}
+
+/// The parts of an order the pre-trade checks look at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProposedOrder {
+    pub side: Side,
+    pub order_type: OrderType,
+    pub price_ticks: PriceTicks,
+    pub qty: u64,
+    pub reduce_only: bool,
+}
+
+impl ProposedOrder {
+    /// A plain limit order, as block trades and backstop assignments are checked.
+    pub fn limit(side: Side, price_ticks: PriceTicks, qty: u64) -> Self {
+        Self {
+            side,
+            order_type: OrderType::Limit,
+            price_ticks,
+            qty,
+            reduce_only: false,
+        }
+    }
+}
+
+impl From<&NewOrder> for ProposedOrder {
+    fn from(order: &NewOrder) -> Self {
+        Self {
+            side: order.side,
+            order_type: order.order_type,
+            price_ticks: order.price_ticks,
+            qty: order.qty,
+            reduce_only: order.reduce_only,
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Subaccount {
     pub collateral: i64,
-    pub positions: HashMap<MarketId, Position>,
+    pub positions: BTreeMap<MarketId, Position>,
     pub cross_margin: bool,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct RiskState {
-    pub subaccounts: HashMap<SubaccountId, Subaccount>,
-    pub mark_prices: HashMap<MarketId, PriceTicks>,
-    pub funding_indices: HashMap<MarketId, i64>,
+    pub subaccounts: BTreeMap<SubaccountId, Subaccount>,
+    pub mark_prices: BTreeMap<MarketId, PriceTicks>,
+    pub funding_indices: BTreeMap<MarketId, i64>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct RiskConfig {
+    #[serde(default = "default_max_slippage_bps")]
     pub max_slippage_bps: u64,
+    /// Largest position notional, per market, as a multiple of the subaccount's equity; 0 for no
+    /// cap. Markets can override it with their own `max_leverage`.
+    #[serde(default)]
     pub max_leverage: u64,
 }
 
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            max_slippage_bps: default_max_slippage_bps(),
+            max_leverage: 0,
+        }
+    }
+}
+
+fn default_max_slippage_bps() -> u64 {
+    50
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RiskError {
     #[error("price band violation")]
@@ -40,23 +103,43 @@ pub enum RiskError {
     ReduceOnly,
     #[error("max position exceeded")]
     MaxPosition,
+    #[error("max leverage exceeded")]
+    MaxLeverage,
+}
+
+impl From<RiskError> for RejectReason {
+    fn from(value: RiskError) -> Self {
+        match value {
+            RiskError::PriceBand => RejectReason::PriceBand,
+            RiskError::InsufficientMargin => RejectReason::InsufficientMargin,
+            RiskError::ReduceOnly => RejectReason::ReduceOnly,
+            RiskError::MaxPosition => RejectReason::MaxPosition,
+            RiskError::MaxLeverage => RejectReason::MaxLeverage,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RiskEngine {
     pub state: RiskState,
     pub config: RiskConfig,
+    /// Price bands and initial margins that replace a market's configured ones, set by the shard
+    /// from realized volatility. Not part of [`RiskState`]; the shard recomputes them.
+    pub(crate) price_bands: BTreeMap<MarketId, u64>,
+    pub(crate) initial_margins: BTreeMap<MarketId, u64>,
 }
 
 impl RiskEngine {
     pub fn new(config: RiskConfig) -> Self {
         Self {
             state: RiskState {
-                subaccounts: HashMap::new(),
-                mark_prices: HashMap::new(),
-                funding_indices: HashMap::new(),
+                subaccounts: BTreeMap::new(),
+                mark_prices: BTreeMap::new(),
+                funding_indices: BTreeMap::new(),
             },
             config,
+            price_bands: BTreeMap::new(),
+            initial_margins: BTreeMap::new(),
         }
     }
 
@@ -64,6 +147,35 @@ impl RiskEngine {
         self.state.mark_prices.insert(market_id, mark);
     }
 
+    /// Overrides the market's configured price band; `None` restores it.
+    pub fn update_price_band(&mut self, market_id: MarketId, band_bps: Option<u64>) {
+        match band_bps {
+            Some(band_bps) => self.price_bands.insert(market_id, band_bps),
+            None => self.price_bands.remove(&market_id),
+        };
+    }
+
+    pub fn price_band_bps(&self, market: &MarketConfig) -> u64 {
+        self.price_bands.get(&market.market_id).copied().unwrap_or(market.price_band_bps)
+    }
+
+    /// Overrides the market's configured initial margin; `None` restores it.
+    pub fn update_initial_margin(&mut self, market_id: MarketId, margin_bps: Option<u64>) {
+        match margin_bps {
+            Some(margin_bps) => self.initial_margins.insert(market_id, margin_bps),
+            None => self.initial_margins.remove(&market_id),
+        };
+    }
+
+    pub fn initial_margin_bps(&self, market: &MarketConfig) -> u64 {
+        self.initial_margins.get(&market.market_id).copied().unwrap_or(market.initial_margin_bps)
+    }
+
+    /// The market's own leverage cap if it sets one, otherwise the global one; 0 for no cap.
+    pub fn max_leverage(&self, market: &MarketConfig) -> u64 {
+        market.max_leverage.unwrap_or(self.config.max_leverage)
+    }
+
     pub fn update_funding(&mut self, market_id: MarketId, index: i64) {
         self.state.funding_indices.insert(market_id, index);
     }
@@ -71,26 +183,25 @@ impl RiskEngine {
     pub fn ensure_subaccount(&mut self, subaccount_id: SubaccountId) -> &mut Subaccount {
         self.state.subaccounts.entry(subaccount_id).or_insert(Subaccount {
             collateral: 0,
-            positions: HashMap::new(),
+            positions: BTreeMap::new(),
             cross_margin: false,
         })
     }
 
-    pub fn validate_order(
-        &self,
-        market: &MarketConfig,
-        subaccount_id: SubaccountId,
-        side: Side,
-        order_type: OrderType,
-        price_ticks: PriceTicks,
-        qty: u64,
-        reduce_only: bool,
-    ) -> Result<(), RiskError> {
+    pub fn validate_order(&self, market: &MarketConfig, subaccount_id: SubaccountId, order: ProposedOrder) -> Result<(), RiskError> {
+        let ProposedOrder {
+            side,
+            order_type,
+            price_ticks,
+            qty,
+            reduce_only,
+        } = order;
         let mark = self.state.mark_prices.get(&market.market_id).copied().unwrap_or(price_ticks);
-        let band = market.price_band_bps;
+        let band = self.price_band_bps(market);
         if order_type != OrderType::Market {
-            let lower = mark.saturating_sub(mark * band / 10_000);
-            let upper = mark + mark * band / 10_000;
+            let width = (mark as u128 * band as u128 / 10_000) as u64;
+            let lower = mark.saturating_sub(width);
+            let upper = mark.saturating_add(width);
             if price_ticks < lower || price_ticks > upper {
                 return Err(RiskError::PriceBand);
             }
@@ -101,27 +212,39 @@ impl RiskEngine {
             .and_then(|acc| acc.positions.get(&market.market_id))
             .map(|pos| pos.size)
             .unwrap_or(0);
+        let qty = i64::try_from(qty).map_err(|_| RiskError::MaxPosition)?;
         let delta = match side {
-            Side::Buy => qty as i64,
-            Side::Sell => -(qty as i64),
+            Side::Buy => qty,
+            Side::Sell => -qty,
         };
-        let projected = position + delta;
-        if reduce_only && projected.abs() > position.abs() {
+        let projected = position.saturating_add(delta);
+        if reduce_only && projected.unsigned_abs() > position.unsigned_abs() {
             return Err(RiskError::ReduceOnly);
         }
-        if projected.abs() > market.max_position {
+        if projected.unsigned_abs() > market.max_position.max(0) as u64 {
             return Err(RiskError::MaxPosition);
         }
 
         let equity = self.equity(subaccount_id);
-        let notional = price_ticks.saturating_mul(qty);
-        let im_required = (notional as u128 * market.initial_margin_bps as u128 / 10_000) as i64;
+        let max_leverage = self.max_leverage(market);
+        if max_leverage > 0 && projected.unsigned_abs() > position.unsigned_abs() {
+            // Market orders have no limit price; value them at the mark.
+            let price = if order_type == OrderType::Market { mark } else { price_ticks };
+            let position_notional = u128::from(projected.unsigned_abs()) * u128::from(price);
+            if position_notional > equity.max(0) as u128 * u128::from(max_leverage) {
+                return Err(RiskError::MaxLeverage);
+            }
+        }
+        let notional = price_ticks.saturating_mul(qty as u64);
+        let im_required = (notional as u128 * self.initial_margin_bps(market) as u128 / 10_000) as i64;
         if equity < im_required {
             return Err(RiskError::InsufficientMargin);
         }
         Ok(())
     }
 
+    /// Books a fill against the subaccount's position and charges its fee. The realized PnL is that
+    /// of the part that reduced the position, against the entry price it had before the fill.
     pub fn apply_fill(
         &mut self,
         market: &MarketConfig,
@@ -130,7 +253,8 @@ impl RiskEngine {
         price_ticks: PriceTicks,
         qty: u64,
         fee: i64,
-    ) {
+    ) -> PositionChange {
+        let funding_index = self.state.funding_indices.get(&market.market_id).copied().unwrap_or(0);
         let subaccount = self.ensure_subaccount(subaccount_id);
         let position = subaccount
             .positions
@@ -138,12 +262,22 @@ impl RiskEngine {
             .or_insert(Position {
                 size: 0,
                 entry_price: price_ticks,
-                funding_index: 0,
+                funding_index,
             });
+        if position.size == 0 {
+            // A position opened from flat owes no funding accrued before it.
+            position.funding_index = funding_index;
+        }
         let delta = match side {
             Side::Buy => qty as i64,
             Side::Sell => -(qty as i64),
         };
+        let closed = if position.size.signum() == -delta.signum() {
+            position.size.unsigned_abs().min(qty) as i64
+        } else {
+            0
+        };
+        let realized_pnl = (price_ticks as i64 - position.entry_price as i64) * closed * position.size.signum();
         let new_size = position.size + delta;
         if new_size == 0 {
             position.size = 0;
@@ -153,6 +287,44 @@ impl RiskEngine {
             position.size = new_size;
         }
         subaccount.collateral -= fee;
+        PositionChange {
+            realized_pnl,
+            closed_qty: closed as u64,
+            size: position.size,
+            entry_price: position.entry_price,
+        }
+    }
+
+    /// Charges every position in `market_id` the funding accrued since its last settlement, up to
+    /// the market's current funding index, against collateral. Returns, in subaccount order, each
+    /// open position as it was before settling and the amount it paid (negative if received).
+    pub fn settle_funding(&mut self, market_id: MarketId) -> Vec<(SubaccountId, Position, i64)> {
+        let index = self.state.funding_indices.get(&market_id).copied().unwrap_or(0);
+        let mut payments = Vec::new();
+        for (subaccount_id, account) in &mut self.state.subaccounts {
+            let Some(position) = account.positions.get_mut(&market_id) else {
+                continue;
+            };
+            let accrued = i128::from(position.size) * i128::from(index.saturating_sub(position.funding_index));
+            // Rounded against the position, so payers never pay less than receivers are credited.
+            let scale = i128::from(FUNDING_INDEX_SCALE);
+            let payment = (if accrued > 0 { (accrued + scale - 1) / scale } else { accrued / scale }) as i64;
+            account.collateral -= payment;
+            if position.size != 0 {
+                payments.push((*subaccount_id, position.clone(), payment));
+            }
+            position.funding_index = index;
+        }
+        payments
+    }
+
+    pub fn position_size(&self, subaccount_id: SubaccountId, market_id: MarketId) -> i64 {
+        self.state
+            .subaccounts
+            .get(&subaccount_id)
+            .and_then(|account| account.positions.get(&market_id))
+            .map(|position| position.size)
+            .unwrap_or(0)
     }
 
     pub fn equity(&self, subaccount_id: SubaccountId) -> i64 {
@@ -162,7 +334,7 @@ impl RiskEngine {
         let mut equity = account.collateral;
         for (market_id, position) in &account.positions {
             let mark = self.state.mark_prices.get(market_id).copied().unwrap_or(position.entry_price);
-            let pnl = (position.size as i128 * (mark as i128 - position.entry_price as i128)) / 1;
+            let pnl = position.size as i128 * (mark as i128 - position.entry_price as i128);
             equity += pnl as i64;
         }
         equity
@@ -200,15 +372,27 @@ mod tests {
             max_open_orders_per_subaccount: 0,
             matching_mode: crate::config::MatchingMode::Continuous,
             batch_interval_ms: 2000,
+            order_capacity: 0,
+            book_layout: crate::config::BookLayout::Tree,
+            indicative_interval_secs: 1,
+            clearing_jitter_ms: 0,
+            maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
         };
         let res = engine.validate_order(
             &market,
             1,
-            Side::Buy,
-            OrderType::Limit,
-            100,
-            5,
-            true,
+            ProposedOrder {
+                reduce_only: true,
+                ..ProposedOrder::limit(Side::Buy, 100, 5)
+            },
         );
         assert!(matches!(res, Err(RiskError::ReduceOnly)));
     }
@@ -0,0 +1,111 @@
+//! Steady-state matching on a pre-sized ladder book must not touch the heap. A counting global
+//! allocator records allocations made by this test's thread while the loop runs; other harness
+//! threads are ignored.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use hypermarket_clob::config::BookLayout;
+use hypermarket_clob::matching::orderbook::{IncomingOrder, OrderBook};
+use hypermarket_clob::models::{OrderType, Side, TimeInForce};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn record() {
+    if COUNTING.with(Cell::get) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn order(order_id: u64, subaccount_id: u64, side: Side, tif: TimeInForce, price_ticks: u64, qty: u64) -> IncomingOrder {
+    IncomingOrder {
+        order_id,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif,
+        price_ticks,
+        qty,
+        reduce_only: false,
+        ingress_seq: order_id,
+    }
+}
+
+/// One round: two makers rest on each side at a price level that is empty beforehand, takers
+/// sweep the asks across two fills, and a cancel and a fill clear the bids, so every level opens
+/// and closes again.
+fn round(book: &mut OrderBook, fills: &mut Vec<hypermarket_clob::models::Fill>, next_id: &mut u64, i: u64) -> usize {
+    let mut id = || {
+        *next_id += 1;
+        *next_id
+    };
+    let ask = 100 + i % 5;
+    let bid = 95 - i % 5;
+    let mut filled = 0;
+    book.place_order_into(order(id(), 1, Side::Sell, TimeInForce::Gtc, ask, 2), 10, fills);
+    book.place_order_into(order(id(), 2, Side::Sell, TimeInForce::Gtc, ask, 1), 10, fills);
+    let first_bid = id();
+    book.place_order_into(order(first_bid, 1, Side::Buy, TimeInForce::Gtc, bid, 3), 10, fills);
+    book.place_order_into(order(id(), 2, Side::Buy, TimeInForce::Gtc, bid, 1), 10, fills);
+
+    book.place_order_into(order(id(), 3, Side::Buy, TimeInForce::Ioc, ask, 3), 10, fills);
+    filled += fills.len();
+    fills.clear();
+    assert!(book.cancel(first_bid));
+    book.place_order_into(order(id(), 3, Side::Sell, TimeInForce::Ioc, bid, 1), 10, fills);
+    filled += fills.len();
+    fills.clear();
+    filled
+}
+
+#[test]
+fn steady_state_matching_on_a_ladder_book_does_not_allocate() {
+    let layout = BookLayout::Ladder {
+        min_price_ticks: 50,
+        max_price_ticks: 150,
+    };
+    let mut book = OrderBook::with_layout(64, layout);
+    let mut fills = Vec::with_capacity(16);
+    let mut next_id = 0;
+    // Warm-up: the first round creates each subaccount's index entry.
+    for i in 0..10 {
+        round(&mut book, &mut fills, &mut next_id, i);
+    }
+
+    COUNTING.with(|counting| counting.set(true));
+    let mut filled = 0;
+    for i in 10..10_010 {
+        filled += round(&mut book, &mut fills, &mut next_id, i);
+    }
+    COUNTING.with(|counting| counting.set(false));
+
+    assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 0);
+    assert_eq!(filled, 3 * 10_000);
+    assert_eq!(book.resting_orders(), 0);
+}
@@ -0,0 +1,118 @@
+#![cfg(feature = "archive")]
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use hypermarket_clob::archive::{self, ArchiveIndex, Archiver, MemoryObjectStore, ObjectStore};
+use hypermarket_clob::bus::memory::InMemoryBus;
+use hypermarket_clob::bus::{BusAck, BusMessage, BusSubscription};
+use hypermarket_clob::config::{ArchiveConfig, ArchiveStoreConfig, WireCodec};
+use hypermarket_clob::engine::router::{decode_output_envelope, encode_output_with};
+use hypermarket_clob::models::{Event, EventEnvelope, Fill, SCHEMA_VERSION};
+
+fn output(shard_id: usize, engine_seq: u64, ts: u64) -> Bytes {
+    let fill = Fill {
+        market_id: shard_id as u64,
+        maker_order_id: 1,
+        taker_order_id: 2,
+        price_ticks: 100,
+        qty: 1,
+        maker_fee: 0,
+        taker_fee: 0,
+        engine_seq,
+        ts,
+        block_trade: false,
+        trade_id: engine_seq,
+        maker_subaccount_id: 1,
+        taker_subaccount_id: 2,
+        maker_remaining_qty: 0,
+        taker_remaining_qty: 0,
+    };
+    encode_output_with(
+        WireCodec::Protobuf,
+        EventEnvelope {
+            shard_id,
+            engine_seq,
+            event: Event::Fill(fill),
+            ts,
+            ts_ns: ts * 1_000_000_000,
+            schema_version: SCHEMA_VERSION,
+        },
+    )
+}
+
+fn config() -> ArchiveConfig {
+    ArchiveConfig {
+        store: ArchiveStoreConfig::Dir { path: String::new() },
+        prefix: "outputs".to_string(),
+        max_batch_events: 2,
+        max_batch_bytes: 1024 * 1024,
+        flush_interval_secs: 3600,
+        compression_level: 3,
+    }
+}
+
+#[tokio::test]
+async fn archiver_writes_indexed_segments_and_acks_after_storing() {
+    let store = Arc::new(MemoryObjectStore::new());
+    let bus = InMemoryBus::new();
+    let outputs = vec![
+        output(0, 1, 100),
+        output(1, 1, 100),
+        output(0, 2, 101),
+        output(0, 3, 205),
+        Bytes::from_static(b"not an output"),
+    ];
+    let (tx, rx) = mpsc::channel(16);
+    for payload in &outputs {
+        tx.send(BusMessage {
+            payload: payload.clone(),
+            ack: BusAck::None,
+        })
+        .await
+        .unwrap();
+    }
+    drop(tx);
+
+    let mut archiver = Archiver::new(store.clone(), config(), WireCodec::Protobuf);
+    let subscription = BusSubscription {
+        stream: ReceiverStream::new(rx),
+    };
+    archive::run(&bus, subscription, &mut archiver).await.unwrap();
+    assert_eq!(bus.acked(), 5);
+
+    let index = ArchiveIndex::load(store.as_ref(), "outputs").await.unwrap();
+    let ranges: Vec<_> = index.segments.iter().map(|segment| (segment.shard_id, segment.first_seq, segment.last_seq)).collect();
+    assert_eq!(ranges, vec![(0, 1, 2), (0, 3, 3), (1, 1, 1)]);
+    assert_eq!(index.segments[0].key, "outputs/shard=0/00000000000000000001-00000000000000000002.zst");
+    assert!(store.get("outputs/index/shard=0/00000000000000000003.json").await.unwrap().is_some());
+
+    assert_eq!(index.by_seq(0, 2).len(), 1);
+    assert!(index.by_seq(1, 2).is_empty());
+    let late: Vec<_> = index.by_time(200, 300).iter().map(|segment| (segment.shard_id, segment.first_seq)).collect();
+    assert_eq!(late, vec![(0, 3)]);
+
+    let payloads = archive::read_segment(store.as_ref(), &index.segments[0]).await.unwrap();
+    assert_eq!(payloads, vec![outputs[0].clone(), outputs[2].clone()]);
+    let envelope = decode_output_envelope(index.segments[0].codec, payloads[1].clone()).unwrap();
+    assert_eq!((envelope.shard_id, envelope.engine_seq, envelope.ts), (0, 2, 101));
+}
+
+#[tokio::test]
+async fn dir_store_round_trips_nested_keys() {
+    let dir = std::env::temp_dir().join(format!(
+        "archive_{:x}",
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos()
+    ));
+    let store = archive::DirObjectStore::new(&dir);
+    store.put("outputs/shard=0/a.zst", Bytes::from_static(b"segment")).await.unwrap();
+    store.put("outputs/index/shard=0/a.json", Bytes::from_static(b"{}")).await.unwrap();
+
+    assert_eq!(store.get("outputs/shard=0/a.zst").await.unwrap(), Some(Bytes::from_static(b"segment")));
+    assert_eq!(store.get("outputs/shard=0/b.zst").await.unwrap(), None);
+    assert_eq!(store.list("outputs/index/").await.unwrap(), vec!["outputs/index/shard=0/a.json".to_string()]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,177 @@
+#![cfg(feature = "client")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::bus::memory::InMemoryBus;
+use hypermarket_clob::client::{ClobClient, ReplayFilter};
+use hypermarket_clob::engine::router::{account_subject, decode_input, encode_output};
+use hypermarket_clob::models::{
+    Event, EventEnvelope, Fill, NewOrder, OrderAck, OrderDisposition, OrderStatus, Side, SCHEMA_VERSION,
+};
+use hypermarket_clob::persistence::wal::Wal;
+
+#[tokio::test]
+async fn submit_order_returns_correlated_ack() {
+    let bus = Arc::new(InMemoryBus::new());
+    let mut inputs = bus.subscribe("in").await.unwrap();
+    let engine_bus = Arc::clone(&bus);
+    tokio::spawn(async move {
+        let mut next_order_id = 10;
+        while let Some(message) = inputs.stream.next().await {
+            let Ok(Event::NewOrder(order)) = decode_input(message.payload) else { continue };
+            next_order_id += 1;
+            let ack = EventEnvelope {
+                shard_id: 0,
+                engine_seq: next_order_id,
+                event: Event::OrderAck(OrderAck {
+                    request_id: order.request_id,
+                    status: OrderStatus::Accepted,
+                    reject_code: None,
+                    reject_reason: None,
+                    assigned_order_id: Some(next_order_id),
+                    engine_seq: next_order_id,
+                    ts: 0,
+                    filled_qty: 0,
+                    remaining_qty: 0,
+                    disposition: None,
+                    subaccount_id: None,
+                }),
+                ts: 0,
+                ts_ns: 0,
+                schema_version: SCHEMA_VERSION,
+            };
+            engine_bus.publish("out", encode_output(ack)).await.unwrap();
+        }
+    });
+
+    let client = ClobClient::connect(bus.clone(), "in".to_string(), "out").await.unwrap();
+    let order = NewOrder::builder()
+        .request_id("client-1")
+        .market_id(1)
+        .subaccount_id(7)
+        .side(Side::Buy)
+        .limit(100)
+        .qty(1)
+        .build()
+        .unwrap();
+    let ack = client.submit_order(order, Duration::from_secs(5)).await.unwrap();
+    assert_eq!(ack.request_id, "client-1");
+    assert_eq!(ack.assigned_order_id, Some(11));
+}
+
+#[tokio::test]
+async fn followed_account_subjects_deliver_private_acks() {
+    let bus = Arc::new(InMemoryBus::new());
+    let mut inputs = bus.subscribe("in").await.unwrap();
+    let engine_bus = Arc::clone(&bus);
+    tokio::spawn(async move {
+        while let Some(message) = inputs.stream.next().await {
+            let Ok(Event::NewOrder(order)) = decode_input(message.payload) else { continue };
+            let subject = account_subject("acct", order.subaccount_id, "acks");
+            let ack = EventEnvelope {
+                shard_id: 0,
+                engine_seq: 1,
+                event: Event::OrderAck(OrderAck {
+                    request_id: order.request_id,
+                    status: OrderStatus::Accepted,
+                    reject_code: None,
+                    reject_reason: None,
+                    assigned_order_id: Some(5),
+                    engine_seq: 1,
+                    ts: 0,
+                    filled_qty: 0,
+                    remaining_qty: 1,
+                    disposition: Some(OrderDisposition::Rested),
+                    subaccount_id: Some(order.subaccount_id),
+                }),
+                ts: 0,
+                ts_ns: 0,
+                schema_version: SCHEMA_VERSION,
+            };
+            engine_bus.publish(&subject, encode_output(ack)).await.unwrap();
+        }
+    });
+
+    let client = ClobClient::connect(bus.clone(), "in".to_string(), "out").await.unwrap();
+    client.follow("acct.7.acks").await.unwrap();
+    let order = NewOrder::builder()
+        .request_id("private-1")
+        .market_id(1)
+        .subaccount_id(7)
+        .side(Side::Buy)
+        .limit(100)
+        .qty(1)
+        .build()
+        .unwrap();
+    let ack = client.submit_order(order, Duration::from_secs(5)).await.unwrap();
+    assert_eq!((ack.assigned_order_id, ack.subaccount_id), (Some(5), Some(7)));
+}
+
+fn fill(shard_id: usize, engine_seq: u64) -> EventEnvelope {
+    EventEnvelope {
+        shard_id,
+        engine_seq,
+        event: Event::Fill(Fill {
+            market_id: 1,
+            maker_order_id: 1,
+            taker_order_id: 2,
+            price_ticks: 100,
+            qty: 1,
+            maker_fee: 0,
+            taker_fee: 0,
+            engine_seq,
+            ts: engine_seq,
+            block_trade: false,
+            trade_id: engine_seq,
+            maker_subaccount_id: 1,
+            taker_subaccount_id: 2,
+            maker_remaining_qty: 0,
+            taker_remaining_qty: 0,
+        }),
+        ts: engine_seq,
+        ts_ns: engine_seq * 1_000_000_000,
+        schema_version: SCHEMA_VERSION,
+    }
+}
+
+#[tokio::test]
+async fn replay_resumes_from_engine_seq_across_journal_and_bus() {
+    let journal_path = std::env::temp_dir().join(format!("client_replay_{}.journal", std::process::id()));
+    let mut journal = Wal::open(&journal_path).unwrap();
+    journal.truncate().unwrap();
+    for engine_seq in 1..=2 {
+        journal.append(&fill(0, engine_seq)).unwrap();
+    }
+    journal.sync().unwrap();
+
+    // The bus has pruned everything before engine_seq 2 and overlaps the journal there.
+    let bus = Arc::new(InMemoryBus::new());
+    for output in [fill(0, 2), fill(1, 7), fill(0, 3)] {
+        bus.publish("out", encode_output(output)).await.unwrap();
+    }
+    let client = ClobClient::connect(bus.clone(), "in".to_string(), "out").await.unwrap();
+    let mut replay = client
+        .replay_outputs(ReplayFilter {
+            from_engine_seq: 2,
+            shard_id: Some(0),
+            journal_path: Some(journal_path.clone()),
+            ..ReplayFilter::default()
+        })
+        .await
+        .unwrap();
+    bus.publish("out", encode_output(fill(0, 4))).await.unwrap();
+
+    let mut seen = Vec::new();
+    while seen.len() < 3 {
+        let output = tokio::time::timeout(Duration::from_secs(5), replay.recv()).await.unwrap().unwrap();
+        assert_eq!(output.shard_id, 0);
+        assert!(matches!(output.event, Event::Fill(_)));
+        seen.push(output.engine_seq);
+    }
+    assert_eq!(seen, vec![2, 3, 4]);
+    let _ = std::fs::remove_file(&journal_path);
+}
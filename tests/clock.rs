@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use hypermarket_clob::bus::memory::InMemoryBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::config::Settings;
+use hypermarket_clob::config::WireCodec;
+use hypermarket_clob::engine::clock::{Clock, ManualClock, SystemClock};
+use hypermarket_clob::engine::router::{decode_output, encode_input, parse_output, run_router_with_clock};
+use hypermarket_clob::models::{Event, NewOrder, Side};
+use hypermarket_clob::persistence::wal::Wal;
+
+fn settings(dir: &std::path::Path) -> Settings {
+    let yaml = format!(
+        r#"
+bus:
+  nats_url: "nats://127.0.0.1:1"
+  input_subject: "in"
+  output_subject: "out"
+  durable_name: "test"
+shard_count: 1
+persistence:
+  wal_path: "{wal}"
+  snapshot_path: "{snapshot}"
+snapshot_interval_secs: 30
+book_delta_levels: 10
+"#,
+        wal = dir.join("engine.wal").display(),
+        snapshot = dir.join("snapshot.bin").display(),
+    );
+    let path = dir.join("config.yaml");
+    std::fs::write(&path, yaml).unwrap();
+    Settings::load(path.to_str().unwrap()).unwrap()
+}
+
+fn order(request_id: &str) -> Bytes {
+    let order = NewOrder::builder()
+        .request_id(request_id)
+        .market_id(1)
+        .subaccount_id(7)
+        .side(Side::Buy)
+        .limit(100)
+        .qty(1)
+        .build()
+        .unwrap();
+    encode_input(Event::NewOrder(order)).unwrap()
+}
+
+fn ack_ts(payload: &Bytes) -> u64 {
+    match decode_output(payload.clone()).unwrap() {
+        Event::OrderAck(ack) => ack.ts,
+        other => panic!("expected an ack, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn inputs_are_stamped_from_the_injected_clock() {
+    let dir = std::env::temp_dir().join(format!("clock_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let clock = Arc::new(ManualClock::new(1_000));
+    let bus = Arc::new(InMemoryBus::new());
+    tokio::spawn(run_router_with_clock(settings(&dir), bus.clone(), clock.clone()));
+
+    for attempt in 0..100 {
+        bus.publish("in", order(&format!("first-{attempt}"))).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if !bus.published("out").is_empty() {
+            break;
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let before = bus.published("out");
+    assert!(before.iter().all(|payload| ack_ts(payload) == 1_000));
+
+    assert_eq!(clock.advance(3_600), 4_600);
+    bus.publish("in", order("later")).await.unwrap();
+    for _ in 0..100 {
+        if bus.published("out").len() > before.len() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let after = bus.published("out");
+    assert_eq!(ack_ts(&after[before.len()]), clock.now());
+    // A manual clock has no sub-second part, so the nanosecond stamp is the start of the second.
+    let output = parse_output(WireCodec::Protobuf, after[before.len()].clone()).unwrap();
+    assert_eq!(output.ts_ns, 4_600_000_000_000);
+    match output.payload {
+        Some(hypermarket_clob::models::pb::output_event::Payload::OrderAck(ack)) => assert_eq!(ack.ts_ns, output.ts_ns),
+        other => panic!("expected an ack, got {other:?}"),
+    }
+
+    // The WAL records the same timestamps, so replay sees the same time.
+    let logged = Wal::load(&dir.join("engine.wal")).unwrap();
+    assert!(logged.iter().all(|envelope| envelope.ts == 1_000 || envelope.ts == 4_600));
+    assert_eq!(logged.last().unwrap().ts, 4_600);
+    assert_eq!(logged.last().unwrap().ts_ns, output.ts_ns);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn system_clock_nanos_agree_with_its_seconds() {
+    let clock = SystemClock;
+    let secs = clock.now();
+    let nanos = clock.now_nanos();
+    assert!(nanos / 1_000_000_000 >= secs && nanos / 1_000_000_000 <= secs + 1);
+    assert_eq!(ManualClock::new(7).now_nanos(), 7_000_000_000);
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use hypermarket_clob::bus::dead_letter::{self, DeadLetterStage};
+use hypermarket_clob::bus::memory::InMemoryBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::config::{Settings, WireCodec};
+use hypermarket_clob::engine::router::run_router;
+
+fn settings(dir: &std::path::Path) -> Settings {
+    let yaml = format!(
+        r#"
+bus:
+  nats_url: "nats://127.0.0.1:1"
+  input_subject: "in"
+  output_subject: "out"
+  durable_name: "test"
+  dead_letter_subject: "dlq"
+shard_count: 1
+persistence:
+  wal_path: "{wal}"
+  snapshot_path: "{snapshot}"
+snapshot_interval_secs: 30
+book_delta_levels: 10
+"#,
+        wal = dir.join("engine.wal").display(),
+        snapshot = dir.join("snapshot.bin").display(),
+    );
+    let path = dir.join("config.yaml");
+    std::fs::write(&path, yaml).unwrap();
+    Settings::load(path.to_str().unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn undecodable_inputs_are_dead_lettered_and_replayable() {
+    let dir = std::env::temp_dir().join(format!("dead_letters_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bus = Arc::new(InMemoryBus::new());
+    let mut dead_letters = bus.subscribe("dlq").await.unwrap();
+    tokio::spawn(run_router(settings(&dir), bus.clone()));
+
+    // The router subscribes asynchronously; keep sending the poison payload until it is seen.
+    let poison = Bytes::from_static(b"\xff\xff\xff");
+    for _ in 0..100 {
+        bus.publish("in", poison.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if !bus.published("dlq").is_empty() {
+            break;
+        }
+    }
+    let published = bus.published("dlq");
+    assert!(!published.is_empty(), "poison input was not dead-lettered");
+    let letter = dead_letter::decode(WireCodec::Protobuf, published[0].clone()).unwrap();
+    assert_eq!(letter.stage, DeadLetterStage::Decode.as_str());
+    assert_eq!(letter.payload, poison);
+    assert!(letter.error.starts_with("malformed input"));
+    assert!(bus.acked() >= 1);
+
+    let replayed = dead_letter::drain(
+        bus.as_ref(),
+        &mut dead_letters,
+        WireCodec::Protobuf,
+        Some("replay"),
+        Duration::from_millis(100),
+    )
+    .await
+    .unwrap();
+    assert_eq!(replayed.len(), bus.published("dlq").len());
+    assert_eq!(bus.published("replay"), vec![poison; replayed.len()]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,275 @@
+//! Differential test of `OrderBook` against a reference book that keeps resting orders in a plain
+//! list and finds the best maker by scanning it. The same random stream of orders, cancels and
+//! size reductions goes to both, and their fills, return values and end states must agree.
+
+use proptest::prelude::*;
+
+use hypermarket_clob::config::BookLayout;
+use hypermarket_clob::matching::orderbook::{IncomingOrder, OrderBook};
+use hypermarket_clob::models::{OrderId, OrderType, PriceTicks, Quantity, Side, TimeInForce};
+
+/// A fill as `(maker, taker, price, qty)`.
+type ModelFill = (OrderId, OrderId, PriceTicks, Quantity);
+
+#[derive(Debug, Clone)]
+struct Resting {
+    order_id: OrderId,
+    subaccount_id: u64,
+    side: Side,
+    price_ticks: PriceTicks,
+    remaining: Quantity,
+}
+
+/// Resting orders in arrival order; price-time priority is a scan for the best price, taking the
+/// earliest arrival among equals.
+#[derive(Debug, Default)]
+struct ReferenceBook {
+    resting: Vec<Resting>,
+}
+
+impl ReferenceBook {
+    fn crosses(order: &IncomingOrder, price: PriceTicks) -> bool {
+        order.order_type == OrderType::Market
+            || match order.side {
+                Side::Buy => order.price_ticks >= price,
+                Side::Sell => order.price_ticks <= price,
+            }
+    }
+
+    /// Index of the resting order `incoming` would trade with first, skipping its own subaccount's.
+    fn best_maker(&self, incoming: &IncomingOrder) -> Option<usize> {
+        let makers = self
+            .resting
+            .iter()
+            .enumerate()
+            .filter(|(_, order)| order.side != incoming.side && order.subaccount_id != incoming.subaccount_id);
+        let best = match incoming.side {
+            Side::Buy => makers.min_by_key(|(idx, order)| (order.price_ticks, *idx)),
+            Side::Sell => makers.min_by_key(|(idx, order)| (std::cmp::Reverse(order.price_ticks), *idx)),
+        };
+        best.map(|(idx, _)| idx)
+    }
+
+    /// The order's fills and its id if it rested.
+    fn place(&mut self, incoming: &IncomingOrder, max_matches: usize) -> (Vec<ModelFill>, Option<OrderId>) {
+        if incoming.tif == TimeInForce::Fok {
+            let available: Quantity = self
+                .resting
+                .iter()
+                .filter(|order| {
+                    order.side != incoming.side
+                        && order.subaccount_id != incoming.subaccount_id
+                        && Self::crosses(incoming, order.price_ticks)
+                })
+                .map(|order| order.remaining)
+                .sum();
+            if available < incoming.qty {
+                return (Vec::new(), None);
+            }
+        }
+        let mut fills = Vec::new();
+        let mut remaining = incoming.qty;
+        while remaining > 0 && fills.len() < max_matches {
+            let Some(idx) = self.best_maker(incoming) else { break };
+            let maker = &mut self.resting[idx];
+            if !Self::crosses(incoming, maker.price_ticks) {
+                break;
+            }
+            let qty = remaining.min(maker.remaining);
+            remaining -= qty;
+            maker.remaining -= qty;
+            fills.push((maker.order_id, incoming.order_id, maker.price_ticks, qty));
+            if maker.remaining == 0 {
+                self.resting.remove(idx);
+            }
+        }
+        // A remainder left crossing its own orders once every other maker is taken is cancelled.
+        let crosses_own = fills.len() < max_matches
+            && self.resting.iter().any(|order| {
+                order.subaccount_id == incoming.subaccount_id && order.side != incoming.side && Self::crosses(incoming, order.price_ticks)
+            });
+        let rests = remaining > 0
+            && incoming.order_type != OrderType::Market
+            && incoming.tif.rests()
+            && (incoming.order_type != OrderType::PostOnly || fills.is_empty())
+            && !crosses_own;
+        if !rests {
+            return (fills, None);
+        }
+        self.resting.push(Resting {
+            order_id: incoming.order_id,
+            subaccount_id: incoming.subaccount_id,
+            side: incoming.side,
+            price_ticks: incoming.price_ticks,
+            remaining,
+        });
+        (fills, Some(incoming.order_id))
+    }
+
+    fn cancel(&mut self, order_id: OrderId) -> bool {
+        let before = self.resting.len();
+        self.resting.retain(|order| order.order_id != order_id);
+        self.resting.len() < before
+    }
+
+    fn reduce_qty(&mut self, order_id: OrderId, new_qty: Quantity) -> bool {
+        match self.resting.iter_mut().find(|order| order.order_id == order_id) {
+            Some(order) if new_qty > 0 && new_qty < order.remaining => {
+                order.remaining = new_qty;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Aggregated levels, best first.
+    fn levels(&self, side: Side) -> Vec<(PriceTicks, Quantity)> {
+        let mut levels: Vec<(PriceTicks, Quantity)> = Vec::new();
+        for order in self.resting.iter().filter(|order| order.side == side) {
+            match levels.iter_mut().find(|(price, _)| *price == order.price_ticks) {
+                Some((_, qty)) => *qty += order.remaining,
+                None => levels.push((order.price_ticks, order.remaining)),
+            }
+        }
+        levels.sort_by_key(|(price, _)| *price);
+        if side == Side::Buy {
+            levels.reverse();
+        }
+        levels
+    }
+
+    fn orders(&self) -> Vec<(OrderId, u64, Side, PriceTicks, Quantity)> {
+        let mut orders: Vec<_> = self
+            .resting
+            .iter()
+            .map(|order| (order.order_id, order.subaccount_id, order.side, order.price_ticks, order.remaining))
+            .collect();
+        orders.sort_by_key(|order| order.0);
+        orders
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Place {
+        subaccount_id: u64,
+        side: Side,
+        order_type: OrderType,
+        tif: TimeInForce,
+        price_ticks: PriceTicks,
+        qty: Quantity,
+        max_matches: usize,
+    },
+    /// Cancels the `n`th order id issued so far, modulo the count, which may be long gone.
+    Cancel(u64),
+    Reduce(u64, Quantity),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let side = prop_oneof![Just(Side::Buy), Just(Side::Sell)];
+    let order_type = prop_oneof![
+        6 => Just(OrderType::Limit),
+        1 => Just(OrderType::Market),
+        1 => Just(OrderType::PostOnly),
+    ];
+    let tif = prop_oneof![
+        6 => Just(TimeInForce::Gtc),
+        1 => Just(TimeInForce::Ioc),
+        1 => Just(TimeInForce::Fok),
+        1 => Just(TimeInForce::Gtd { expires_at: 1_000 }),
+    ];
+    prop_oneof![
+        6 => (1u64..5, side, order_type, tif, 90u64..111, 1u64..20, 1usize..8).prop_map(
+            |(subaccount_id, side, order_type, tif, price_ticks, qty, max_matches)| Op::Place {
+                subaccount_id,
+                side,
+                order_type,
+                tif,
+                price_ticks,
+                qty,
+                max_matches,
+            }
+        ),
+        2 => any::<u64>().prop_map(Op::Cancel),
+        1 => (any::<u64>(), 0u64..20).prop_map(|(n, qty)| Op::Reduce(n, qty)),
+    ]
+}
+
+fn run(ops: &[Op], layout: BookLayout) -> Result<(), TestCaseError> {
+    let mut book = OrderBook::with_layout(0, layout);
+    let mut reference = ReferenceBook::default();
+    let mut next_id: OrderId = 0;
+    for op in ops {
+        match *op {
+            Op::Place {
+                subaccount_id,
+                side,
+                order_type,
+                tif,
+                price_ticks,
+                qty,
+                max_matches,
+            } => {
+                next_id += 1;
+                let incoming = IncomingOrder {
+                    order_id: next_id,
+                    subaccount_id,
+                    side,
+                    order_type,
+                    tif,
+                    price_ticks,
+                    qty,
+                    reduce_only: false,
+                    ingress_seq: next_id,
+                };
+                let (expected_fills, expected_rest) = reference.place(&incoming, max_matches);
+                let (fills, rested) = book.place_order(incoming, max_matches);
+                let fills: Vec<_> = fills
+                    .iter()
+                    .map(|fill| (fill.maker_order_id, fill.taker_order_id, fill.price_ticks, fill.qty))
+                    .collect();
+                prop_assert_eq!(fills, expected_fills, "fills of order {}", next_id);
+                prop_assert_eq!(rested, expected_rest, "rest of order {}", next_id);
+            }
+            Op::Cancel(n) if next_id > 0 => {
+                let order_id = n % next_id + 1;
+                prop_assert_eq!(book.cancel(order_id), reference.cancel(order_id), "cancel of order {}", order_id);
+            }
+            Op::Reduce(n, qty) if next_id > 0 => {
+                let order_id = n % next_id + 1;
+                prop_assert_eq!(book.reduce_qty(order_id, qty), reference.reduce_qty(order_id, qty), "reduction of order {}", order_id);
+            }
+            Op::Cancel(_) | Op::Reduce(..) => {}
+        }
+    }
+
+    let snapshot = book.snapshot(usize::MAX);
+    prop_assert_eq!(snapshot.bids, reference.levels(Side::Buy));
+    prop_assert_eq!(snapshot.asks, reference.levels(Side::Sell));
+    let mut orders: Vec<_> = book
+        .order_views()
+        .into_iter()
+        .map(|order| (order.order_id, order.subaccount_id, order.side, order.price_ticks, order.remaining))
+        .collect();
+    orders.sort_by_key(|order| order.0);
+    prop_assert_eq!(&orders, &reference.orders());
+    for subaccount_id in 1..5 {
+        let ids: Vec<OrderId> = book.subaccount_orders(subaccount_id).map(|order| order.order_id).collect();
+        let expected: Vec<OrderId> = orders.iter().filter(|order| order.1 == subaccount_id).map(|order| order.0).collect();
+        prop_assert_eq!(ids, expected, "resting orders of subaccount {}", subaccount_id);
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn tree_book_matches_reference(ops in prop::collection::vec(op(), 1..200)) {
+        run(&ops, BookLayout::Tree)?;
+    }
+
+    /// A ladder narrower than the generated prices also exercises the overflow levels.
+    #[test]
+    fn ladder_book_matches_reference(ops in prop::collection::vec(op(), 1..200)) {
+        run(&ops, BookLayout::Ladder { min_price_ticks: 95, max_price_ticks: 105 })?;
+    }
+}
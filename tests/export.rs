@@ -0,0 +1,112 @@
+#![cfg(feature = "export")]
+
+use hypermarket_clob::models::{
+    BookLevel, DepthSnapshot, Event, EventEnvelope, Fill, NewOrder, OrderType, Side, TimeInForce, SCHEMA_VERSION,
+};
+use hypermarket_clob::persistence::export::{utc_date, ParquetExport};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+
+// 2024-03-01T12:00:00Z and the first second of the next day.
+const DAY_ONE: u64 = 1_709_294_400;
+const DAY_TWO: u64 = 1_709_337_600;
+
+fn envelope(engine_seq: u64, ts: u64, event: Event) -> EventEnvelope {
+    EventEnvelope {
+        shard_id: 0,
+        engine_seq,
+        event,
+        ts,
+        ts_ns: ts * 1_000_000_000,
+        schema_version: SCHEMA_VERSION,
+    }
+}
+
+fn fill(market_id: u64, trade_id: u64, ts: u64) -> Event {
+    Event::Fill(Fill {
+        market_id,
+        maker_order_id: 1,
+        taker_order_id: 2,
+        price_ticks: 100,
+        qty: 3,
+        maker_fee: -1,
+        taker_fee: 2,
+        engine_seq: trade_id,
+        ts,
+        block_trade: false,
+        trade_id,
+        maker_subaccount_id: 10,
+        taker_subaccount_id: 11,
+        maker_remaining_qty: 0,
+        taker_remaining_qty: 0,
+    })
+}
+
+fn rows(path: &std::path::Path) -> i64 {
+    let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+    reader.metadata().file_metadata().num_rows()
+}
+
+#[test]
+fn utc_date_handles_epoch_and_leap_days() {
+    assert_eq!(utc_date(0), "1970-01-01");
+    assert_eq!(utc_date(DAY_ONE), "2024-03-01");
+    assert_eq!(utc_date(1_709_164_800), "2024-02-29");
+    assert_eq!(utc_date(951_782_400), "2000-02-29");
+}
+
+#[test]
+fn export_partitions_by_market_and_date() {
+    let dir = std::env::temp_dir().join(format!(
+        "export_{:x}",
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos()
+    ));
+    let order = Event::NewOrder(NewOrder {
+        request_id: "o-1".to_string(),
+        market_id: 1,
+        subaccount_id: 10,
+        side: Side::Buy,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks: 100,
+        qty: 3,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 1,
+        client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
+    });
+    let snapshot = Event::DepthSnapshot(DepthSnapshot {
+        market_id: 1,
+        bids_levels: vec![BookLevel { price_ticks: 99, qty: 5 }, BookLevel { price_ticks: 98, qty: 1 }],
+        asks_levels: vec![BookLevel { price_ticks: 101, qty: 2 }],
+        engine_seq: 4,
+        ts: DAY_ONE,
+    });
+    let records = vec![
+        envelope(1, DAY_ONE, order),
+        envelope(2, DAY_ONE, fill(1, 2, DAY_ONE)),
+        envelope(3, DAY_TWO, fill(1, 3, DAY_TWO)),
+        envelope(4, DAY_ONE, snapshot),
+        envelope(5, DAY_ONE, fill(2, 5, DAY_ONE)),
+    ];
+
+    let mut export = ParquetExport::new(false);
+    export.extend(&records);
+    let report = export.write(&dir).unwrap();
+    assert_eq!((report.fills, report.orders, report.book_levels), (3, 1, 3));
+    assert_eq!(report.files.len(), 5);
+
+    let fills = dir.join("fills/market_id=1/date=2024-03-01/part-0-00000000000000000002.parquet");
+    assert!(report.files.contains(&fills));
+    assert_eq!(rows(&fills), 1);
+    assert!(dir.join("fills/market_id=1/date=2024-03-02/part-0-00000000000000000003.parquet").exists());
+    assert!(dir.join("fills/market_id=2/date=2024-03-01/part-0-00000000000000000005.parquet").exists());
+    assert_eq!(rows(&dir.join("orders/market_id=1/date=2024-03-01/part-0-00000000000000000001.parquet")), 1);
+    assert_eq!(rows(&dir.join("book/market_id=1/date=2024-03-01/part-0-00000000000000000004.parquet")), 3);
+
+    // The same records export to the same files.
+    let again = export.write(&dir).unwrap();
+    assert_eq!(again, report);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,200 @@
+#![cfg(feature = "gateway")]
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ed25519_dalek::{Signer, SigningKey};
+
+use tokio_stream::StreamExt;
+
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::bus::memory::InMemoryBus;
+use hypermarket_clob::client::ClobClient;
+use hypermarket_clob::config::{SigningKeyConfig, WireCodec};
+use hypermarket_clob::engine::router::{decode_input, encode_output};
+use hypermarket_clob::engine::signatures::stream_auth_payload;
+use hypermarket_clob::gateway::{self, PrivateStreams, StreamAuth};
+use hypermarket_clob::models::{Event, EventEnvelope, OrderAck, OrderStatus, PositionUpdate, SCHEMA_VERSION};
+
+async fn post(addr: std::net::SocketAddr, path: &str, body: &str) -> String {
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn orders_are_forwarded_and_answered_with_their_ack() {
+    let bus = Arc::new(InMemoryBus::new());
+    let mut inputs = bus.subscribe("in").await.unwrap();
+    let engine_bus = Arc::clone(&bus);
+    tokio::spawn(async move {
+        while let Some(message) = inputs.stream.next().await {
+            let Ok(Event::NewOrder(order)) = decode_input(message.payload) else { continue };
+            let ack = EventEnvelope {
+                shard_id: 0,
+                engine_seq: 1,
+                event: Event::OrderAck(OrderAck {
+                    request_id: order.request_id,
+                    status: OrderStatus::Accepted,
+                    reject_code: None,
+                    reject_reason: None,
+                    assigned_order_id: Some(42),
+                    engine_seq: 1,
+                    ts: 0,
+                    filled_qty: 0,
+                    remaining_qty: 0,
+                    disposition: None,
+                    subaccount_id: None,
+                }),
+                ts: 0,
+                ts_ns: 0,
+                schema_version: SCHEMA_VERSION,
+            };
+            engine_bus.publish("out", encode_output(ack)).await.unwrap();
+        }
+    });
+
+    let client = ClobClient::connect(bus.clone(), "in".to_string(), "out").await.unwrap();
+    let app = gateway::router(Arc::new(client), Duration::from_secs(5));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let accepted = post(
+        addr,
+        "/orders",
+        r#"{"request_id":"rest-1","market_id":1,"subaccount_id":7,"side":"Buy","price_ticks":100,"qty":1}"#,
+    )
+    .await;
+    assert!(accepted.starts_with("HTTP/1.1 200"), "{accepted}");
+    assert!(accepted.contains(r#""assigned_order_id":42"#), "{accepted}");
+
+    let invalid = post(
+        addr,
+        "/orders",
+        r#"{"request_id":"rest-2","market_id":1,"subaccount_id":7,"side":"Buy","price_ticks":100,"qty":0}"#,
+    )
+    .await;
+    assert!(invalid.starts_with("HTTP/1.1 400"), "{invalid}");
+
+    let cancelled = post(addr, "/cancels", r#"{"request_id":"rest-3","market_id":1,"subaccount_id":7,"order_id":42}"#).await;
+    assert!(cancelled.starts_with("HTTP/1.1 202"), "{cancelled}");
+}
+
+/// Opens a WebSocket on `path` and returns the status line, with the connection if it upgraded.
+async fn open_websocket(addr: std::net::SocketAddr, path: &str) -> (String, Option<std::net::TcpStream>) {
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+    );
+    tokio::task::spawn_blocking(move || {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        let upgraded = status.starts_with("HTTP/1.1 101");
+        (status, upgraded.then_some(stream))
+    })
+    .await
+    .unwrap()
+}
+
+/// Reads one unfragmented text frame from the server.
+async fn read_text_frame(mut stream: std::net::TcpStream) -> String {
+    tokio::task::spawn_blocking(move || {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0], 0x81, "expected a final text frame");
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0u8; 2];
+                stream.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn private_streams_require_auth_and_forward_the_subaccounts_outputs() {
+    let bus = Arc::new(InMemoryBus::new());
+    let key = SigningKey::from_bytes(&[9u8; 32]);
+    let auth = StreamAuth::new(HashMap::from([("secret".to_string(), 7)]), 30);
+    auth.upsert_key(&SigningKeyConfig {
+        subaccount_id: 8,
+        public_key: key.verifying_key().to_bytes().iter().map(|byte| format!("{byte:02x}")).collect(),
+    })
+    .unwrap();
+    let streams = Arc::new(PrivateStreams {
+        bus: bus.clone(),
+        account_subject: "acct".to_string(),
+        codec: WireCodec::Protobuf,
+        auth,
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, gateway::private_router(streams)).await.unwrap() });
+
+    let (status, _) = open_websocket(addr, "/private?subaccount_id=8&token=secret").await;
+    assert!(status.starts_with("HTTP/1.1 401"), "{status}");
+    let (status, _) = open_websocket(addr, "/private?subaccount_id=7").await;
+    assert!(status.starts_with("HTTP/1.1 401"), "{status}");
+
+    let ts = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let signature: String = key.sign(&stream_auth_payload(8, ts)).to_bytes().iter().map(|byte| format!("{byte:02x}")).collect();
+    let (status, _) = open_websocket(addr, &format!("/private?subaccount_id=8&ts={ts}&signature={signature}")).await;
+    assert!(status.starts_with("HTTP/1.1 101"), "{status}");
+    let (status, _) = open_websocket(addr, &format!("/private?subaccount_id=8&ts={}&signature={signature}", ts - 120)).await;
+    assert!(status.starts_with("HTTP/1.1 401"), "{status}");
+
+    let (status, stream) = open_websocket(addr, "/private?subaccount_id=7&token=secret").await;
+    assert!(status.starts_with("HTTP/1.1 101"), "{status}");
+    let update = PositionUpdate {
+        market_id: 1,
+        subaccount_id: 7,
+        size: 5,
+        entry_price: 100,
+        mark_price: 101,
+        unrealized_pnl: 5,
+        initial_margin: 50,
+        maintenance_margin: 25,
+        engine_seq: 3,
+        ts: 0,
+    };
+    let envelope = EventEnvelope {
+        shard_id: 0,
+        engine_seq: 3,
+        event: Event::PositionUpdate(update.clone()),
+        ts: 0,
+        ts_ns: 0,
+        schema_version: SCHEMA_VERSION,
+    };
+    bus.publish("acct.7.positions", encode_output(envelope)).await.unwrap();
+    let text = read_text_frame(stream.unwrap()).await;
+    let event: Event = serde_json::from_str(&text).unwrap();
+    assert!(matches!(event, Event::PositionUpdate(received) if received == update), "{text}");
+}
@@ -1,8 +1,8 @@
-use std::path::PathBuf;
-
-use hypermarket_clob::config::{MarketConfig, MatchingMode};
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode};
 use hypermarket_clob::engine::EngineShard;
-use hypermarket_clob::models::{CancelOrder, Event, EventEnvelope, NewOrder, OrderAck, OrderStatus, OrderType, Side, TimeInForce};
+use hypermarket_clob::models::{
+    CancelOrder, Event, EventEnvelope, NewOrder, OrderAck, OrderStatus, OrderType, RejectReason, Side, TimeInForce,
+};
 use hypermarket_clob::persistence::wal::Wal;
 use hypermarket_clob::risk::{RiskConfig, RiskEngine};
 
@@ -20,21 +20,34 @@ fn market_config(max_subaccount: u64) -> MarketConfig {
         max_open_orders_per_subaccount: max_subaccount,
         matching_mode: MatchingMode::Continuous,
         batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
     }
 }
 
 fn new_shard(max_subaccount: u64) -> EngineShard {
-    let wal_path = PathBuf::from(std::env::temp_dir().join(format!(
+    let wal_path = std::env::temp_dir().join(format!(
         "open_order_limits_{:x}.wal",
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default()
             .as_nanos()
-    )));
+    ));
     let wal = Wal::open(&wal_path).unwrap();
     let risk = RiskEngine::new(RiskConfig {
         max_slippage_bps: 50,
-        max_leverage: 10,
+        max_leverage: 0,
     });
     EngineShard::new(0, vec![market_config(max_subaccount)], wal, risk)
 }
@@ -62,6 +75,8 @@ fn gtc_order(request_id: &str, subaccount_id: u64, side: Side) -> NewOrder {
         expiry_ts: 0,
         nonce: 0,
         client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
     }
 }
 
@@ -79,6 +94,8 @@ fn ioc_order(request_id: &str, subaccount_id: u64, side: Side) -> NewOrder {
         expiry_ts: 0,
         nonce: 0,
         client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
     }
 }
 
@@ -92,6 +109,7 @@ fn enforces_max_open_orders_per_subaccount() {
     let a2 = ack_from_outputs(&shard.handle_event(Event::NewOrder(gtc_order("r2", 1, Side::Buy)), 2).unwrap());
     assert_eq!(a2.status, OrderStatus::Rejected);
     assert_eq!(a2.reject_reason.as_deref(), Some("max open orders per subaccount"));
+    assert_eq!(a2.reject_code, Some(RejectReason::MaxOpenOrders));
 
     let a3 = ack_from_outputs(&shard.handle_event(Event::NewOrder(gtc_order("r3", 2, Side::Buy)), 3).unwrap());
     assert_eq!(a3.status, OrderStatus::Accepted);
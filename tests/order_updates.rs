@@ -0,0 +1,1998 @@
+use hypermarket_clob::config::{
+    AccountConfig, BookLayout, BookSnapshotConfig, CollateralSeed, DynamicBandConfig, DynamicMarginConfig, FeeTier, FundingConfig, LatencyBudgetAction, LatencyBudgetConfig,
+    MakerObligation, MarketConfig, MarketPermissions, MatchingMode, OracleConfig, OracleSource, SigningKeyConfig, SubaccountConfig, TradeHistoryConfig,
+};
+use hypermarket_clob::engine::health::RiskMetrics;
+use hypermarket_clob::engine::{EngineShard, MemoryLog};
+use hypermarket_clob::models::{
+    AccountEquity, AuctionIndicative, BackstopAssignment, BlockTrade, CancelOrder, ClearingSeed, Event, EventEnvelope, Liquidation, MarginWarning, MassCancel, NewOrder,
+    OrderDisposition, OrderTrigger, OrderType, OrderUpdate, OrderUpdateStatus, PnlRealized,
+    PriceUpdate, RejectReason, RiskParameter, RiskParamsUpdate, Side, TimeInForce,
+    Transfer, TriggerSource, UserFill,
+};
+use hypermarket_clob::persistence::trades::TradeStore;
+use hypermarket_clob::persistence::wal::Wal;
+use hypermarket_clob::risk::{Position, RiskConfig, RiskEngine};
+
+fn market_config(matching_mode: MatchingMode) -> MarketConfig {
+    MarketConfig {
+        market_id: 1,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 0,
+        maintenance_margin_bps: 0,
+        max_position: 1_000_000,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode,
+        batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    }
+}
+
+fn new_shard() -> EngineShard {
+    let wal_path = std::env::temp_dir().join(format!(
+        "order_updates_{:x}.wal",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+    let wal = Wal::open(&wal_path).unwrap();
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let mut shard = EngineShard::new(0, vec![market_config(MatchingMode::Continuous)], wal, risk);
+    // The shard seeds the mark at one tick; centre the price band on the prices these tests use.
+    shard.risk.update_mark(1, 100);
+    shard
+}
+
+fn order(request_id: &str, subaccount_id: u64, side: Side, tif: TimeInForce, qty: u64) -> NewOrder {
+    NewOrder {
+        request_id: request_id.to_string(),
+        market_id: 1,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif,
+        price_ticks: 100,
+        qty,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 0,
+        client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
+    }
+}
+
+fn updates(outputs: &[EventEnvelope]) -> Vec<OrderUpdate> {
+    outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::OrderUpdate(update) => Some(update.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn emits_updates_for_rest_partial_fill_and_cancel() {
+    let mut shard = new_shard();
+
+    let rest = updates(&shard.handle_event(Event::NewOrder(order("maker", 1, Side::Sell, TimeInForce::Gtc, 10)), 1).unwrap());
+    assert_eq!(rest.len(), 1);
+    assert_eq!(rest[0].status, OrderUpdateStatus::Open);
+    assert_eq!(rest[0].remaining_qty, 10);
+    let maker_id = rest[0].order_id;
+
+    let taker = updates(&shard.handle_event(Event::NewOrder(order("taker", 2, Side::Buy, TimeInForce::Ioc, 4)), 2).unwrap());
+    assert_eq!(taker.len(), 2);
+    assert_eq!(taker[0].order_id, maker_id);
+    assert_eq!(taker[0].status, OrderUpdateStatus::PartiallyFilled);
+    assert_eq!(taker[0].remaining_qty, 6);
+    assert_eq!(taker[1].status, OrderUpdateStatus::Filled);
+
+    let cancel = CancelOrder {
+        request_id: "cancel".to_string(),
+        market_id: 1,
+        subaccount_id: 1,
+        order_id: Some(maker_id),
+        nonce_start: None,
+        nonce_end: None,
+    };
+    let cancelled = updates(&shard.handle_event(Event::CancelOrder(cancel), 3).unwrap());
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].status, OrderUpdateStatus::Cancelled);
+    assert_eq!(cancelled[0].remaining_qty, 6);
+}
+
+#[test]
+fn unfilled_ioc_is_reported_cancelled() {
+    let mut shard = new_shard();
+    let out = updates(&shard.handle_event(Event::NewOrder(order("ioc", 1, Side::Buy, TimeInForce::Ioc, 3)), 1).unwrap());
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].status, OrderUpdateStatus::Cancelled);
+    assert_eq!(out[0].remaining_qty, 3);
+}
+
+#[test]
+fn acks_and_fills_report_executed_and_remaining_quantity() {
+    let mut shard = new_shard();
+    let ack = |outputs: &[EventEnvelope]| {
+        outputs
+            .iter()
+            .find_map(|env| match &env.event {
+                Event::OrderAck(ack) => Some((ack.filled_qty, ack.remaining_qty, ack.disposition)),
+                _ => None,
+            })
+            .unwrap()
+    };
+
+    let outputs = shard.handle_event(Event::NewOrder(order("maker", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    assert_eq!(ack(&outputs), (0, 5, Some(OrderDisposition::Rested)));
+
+    let outputs = shard.handle_event(Event::NewOrder(order("filled", 2, Side::Buy, TimeInForce::Ioc, 3)), 2).unwrap();
+    assert_eq!(ack(&outputs), (3, 0, Some(OrderDisposition::Filled)));
+    let fill = &fills(&outputs)[0];
+    assert_eq!((fill.maker_subaccount_id, fill.taker_subaccount_id), (1, 2));
+    assert_eq!((fill.maker_remaining_qty, fill.taker_remaining_qty), (2, 0));
+
+    // The IOC fills the last 2 of the ask and its other 2 are cancelled.
+    let outputs = shard.handle_event(Event::NewOrder(order("partial", 3, Side::Buy, TimeInForce::Ioc, 4)), 3).unwrap();
+    assert_eq!(ack(&outputs), (2, 0, Some(OrderDisposition::Cancelled)));
+    let fill = &fills(&outputs)[0];
+    assert_eq!((fill.maker_remaining_qty, fill.taker_remaining_qty), (0, 2));
+
+    let mut rejected = order("rejected", 3, Side::Buy, TimeInForce::Gtc, 1);
+    rejected.market_id = 99;
+    let outputs = shard.handle_event(Event::NewOrder(rejected), 4).unwrap();
+    assert_eq!(ack(&outputs), (0, 0, None));
+}
+
+#[test]
+fn each_party_gets_a_private_fill_with_its_realized_pnl() {
+    let mut shard = new_shard();
+    let user_fills = |outputs: &[EventEnvelope]| -> Vec<UserFill> {
+        outputs
+            .iter()
+            .filter_map(|env| match &env.event {
+                Event::UserFill(fill) => Some(fill.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    shard.handle_event(Event::NewOrder(order("ask", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    let outputs = shard.handle_event(Event::NewOrder(order("open", 2, Side::Buy, TimeInForce::Ioc, 5)), 2).unwrap();
+    let opened = user_fills(&outputs);
+    assert_eq!(opened.len(), 2);
+    assert_eq!((opened[0].subaccount_id, opened[0].side, opened[0].maker), (1, Side::Sell, true));
+    assert_eq!((opened[1].subaccount_id, opened[1].side, opened[1].maker), (2, Side::Buy, false));
+    assert!(opened.iter().all(|fill| fill.realized_pnl == 0 && fill.trade_id == fills(&outputs)[0].trade_id));
+
+    // Subaccount 2 sells 3 of its 5 at 105, realizing 5 ticks on each.
+    let mut bid = order("bid", 3, Side::Buy, TimeInForce::Gtc, 4);
+    bid.price_ticks = 105;
+    shard.handle_event(Event::NewOrder(bid), 3).unwrap();
+    let mut close = order("close", 2, Side::Sell, TimeInForce::Ioc, 3);
+    close.price_ticks = 105;
+    let closed = user_fills(&shard.handle_event(Event::NewOrder(close), 4).unwrap());
+    assert_eq!((closed[0].subaccount_id, closed[0].realized_pnl, closed[0].remaining_qty), (3, 0, 1));
+    assert_eq!((closed[1].subaccount_id, closed[1].realized_pnl, closed[1].remaining_qty), (2, 15, 0));
+}
+
+#[test]
+fn fills_that_reduce_a_position_report_the_pnl_realized() {
+    let mut shard = new_shard();
+    let realized = |outputs: &[EventEnvelope]| -> Vec<PnlRealized> {
+        outputs
+            .iter()
+            .filter_map(|env| match &env.event {
+                Event::PnlRealized(pnl) => Some(pnl.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    shard.handle_event(Event::NewOrder(order("ask", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    let opened = shard.handle_event(Event::NewOrder(order("open", 2, Side::Buy, TimeInForce::Ioc, 5)), 2).unwrap();
+    assert!(realized(&opened).is_empty());
+
+    // Subaccount 2 sells 3 of its 5 at 95 to subaccount 1, which covers 3 of its short 5.
+    let mut bid = order("bid", 1, Side::Buy, TimeInForce::Gtc, 3);
+    bid.price_ticks = 95;
+    shard.handle_event(Event::NewOrder(bid), 3).unwrap();
+    let mut reduce = order("reduce", 2, Side::Sell, TimeInForce::Ioc, 3);
+    reduce.price_ticks = 95;
+    let outputs = shard.handle_event(Event::NewOrder(reduce), 4).unwrap();
+    let pnl: Vec<_> = realized(&outputs)
+        .iter()
+        .map(|pnl| (pnl.subaccount_id, pnl.closed_qty, pnl.realized_pnl, pnl.remaining_size, pnl.entry_price))
+        .collect();
+    assert_eq!(pnl, vec![(1, 3, 15, -2, 95), (2, 3, -15, 2, 95)]);
+    assert!(realized(&outputs).iter().all(|pnl| pnl.trade_id == fills(&outputs)[0].trade_id));
+}
+
+#[test]
+fn fills_publish_each_partys_position_valued_at_mark() {
+    let mut shard = new_shard();
+    let margined = MarketConfig {
+        initial_margin_bps: 1_000,
+        maintenance_margin_bps: 500,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    for subaccount_id in [1, 2] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = 1_000;
+    }
+    shard.handle_event(Event::NewOrder(order("ask", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    let mut bid = order("bid", 2, Side::Buy, TimeInForce::Gtc, 5);
+    bid.price_ticks = 90;
+    shard.handle_event(Event::NewOrder(bid), 2).unwrap();
+    let mut sell = order("sell", 1, Side::Sell, TimeInForce::Ioc, 5);
+    sell.price_ticks = 90;
+    let outputs = shard.handle_event(Event::NewOrder(sell), 3).unwrap();
+    let positions: Vec<_> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::PositionUpdate(update) => Some((
+                update.subaccount_id,
+                update.size,
+                update.entry_price,
+                update.unrealized_pnl,
+                update.initial_margin,
+                update.maintenance_margin,
+            )),
+            _ => None,
+        })
+        .collect();
+    // Both entered at 90 against a mark of 100.
+    assert_eq!(positions, vec![(2, 5, 90, 50, 50, 25), (1, -5, 90, -50, 50, 25)]);
+}
+
+#[test]
+fn accounts_near_maintenance_get_margin_warnings() {
+    let warnings = |outputs: &[EventEnvelope]| -> Vec<MarginWarning> {
+        outputs
+            .iter()
+            .filter_map(|env| match &env.event {
+                Event::MarginWarning(warning) => Some(warning.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let mut shard = new_shard().with_margin_warnings(Some(2_000));
+    let margined = MarketConfig {
+        initial_margin_bps: 1_000,
+        maintenance_margin_bps: 500,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    shard.risk.update_mark(1, 90);
+    shard.risk.ensure_subaccount(1).collateral = 60;
+    shard.risk.ensure_subaccount(2).collateral = 1_000;
+    let mut bid = order("bid", 2, Side::Buy, TimeInForce::Gtc, 5);
+    bid.price_ticks = 90;
+    shard.handle_event(Event::NewOrder(bid), 1).unwrap();
+    let mut sell = order("sell", 1, Side::Sell, TimeInForce::Ioc, 5);
+    sell.price_ticks = 90;
+    let outputs = shard.handle_event(Event::NewOrder(sell), 2).unwrap();
+    // Equity 60 against a maintenance margin of 22 is well clear of the 20% buffer.
+    assert!(warnings(&outputs).is_empty());
+
+    let outputs = shard
+        .handle_event(
+            Event::PriceUpdate(PriceUpdate {
+                market_id: 1,
+                mark_price: 97,
+                index_price: 97,
+                ts: 3,
+                source: String::new(),
+            }),
+            3,
+        )
+        .unwrap();
+    // The short lost 35: equity 25 is above maintenance (24) but inside its 20% buffer (28).
+    let warned: Vec<_> = warnings(&outputs)
+        .iter()
+        .map(|warning| (warning.subaccount_id, warning.equity, warning.maintenance_margin, warning.warning_bps))
+        .collect();
+    assert_eq!(warned, vec![(1, 25, 24, 2_000)]);
+}
+
+#[test]
+fn depth_snapshots_follow_the_interval_and_delta_count() {
+    let depth = |outputs: &[EventEnvelope]| {
+        outputs.iter().find_map(|env| match &env.event {
+            Event::DepthSnapshot(snapshot) => Some(snapshot.clone()),
+            _ => None,
+        })
+    };
+    let mut shard = new_shard().with_book_snapshots(Some(BookSnapshotConfig {
+        interval_secs: 10,
+        every_deltas: 0,
+    }));
+    assert!(depth(&shard.handle_event(Event::NewOrder(order("a", 1, Side::Sell, TimeInForce::Gtc, 2)), 1).unwrap()).is_some());
+    assert!(depth(&shard.handle_event(Event::NewOrder(order("b", 1, Side::Sell, TimeInForce::Gtc, 3)), 5).unwrap()).is_none());
+    let snapshot = depth(&shard.handle_event(Event::NewOrder(order("c", 2, Side::Buy, TimeInForce::Gtc, 1)), 11).unwrap()).unwrap();
+    assert_eq!((snapshot.market_id, snapshot.engine_seq), (1, 3));
+    assert!(snapshot.bids_levels.is_empty());
+    assert_eq!((snapshot.asks_levels[0].price_ticks, snapshot.asks_levels[0].qty), (100, 4));
+
+    let mut shard = new_shard().with_book_snapshots(Some(BookSnapshotConfig {
+        interval_secs: 0,
+        every_deltas: 2,
+    }));
+    assert!(depth(&shard.handle_event(Event::NewOrder(order("a", 1, Side::Sell, TimeInForce::Gtc, 2)), 1).unwrap()).is_none());
+    assert!(depth(&shard.handle_event(Event::NewOrder(order("b", 1, Side::Sell, TimeInForce::Gtc, 3)), 1).unwrap()).is_some());
+    assert!(depth(&shard.handle_event(Event::NewOrder(order("c", 1, Side::Sell, TimeInForce::Gtc, 3)), 1).unwrap()).is_none());
+}
+
+#[test]
+fn coalescing_keeps_latest_delta_per_market() {
+    use hypermarket_clob::engine::shard::coalesce_book_deltas;
+
+    let mut shard = new_shard();
+    let mut outputs = Vec::new();
+    for i in 0..3 {
+        outputs.extend(
+            shard
+                .handle_event(Event::NewOrder(order(&format!("o{i}"), 1, Side::Buy, TimeInForce::Gtc, 1)), i)
+                .unwrap(),
+        );
+    }
+    let coalesced = coalesce_book_deltas(outputs.clone());
+    let deltas: Vec<u64> = coalesced
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::BookDelta(delta) => Some(delta.engine_seq),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(deltas, vec![shard.engine_seq]);
+    assert_eq!(updates(&coalesced).len(), updates(&outputs).len());
+}
+
+#[test]
+fn reduce_only_orders_shrink_and_cancel_as_position_changes() {
+    let mut shard = new_shard();
+    shard.risk.ensure_subaccount(1).collateral = 1_000;
+    shard.handle_event(Event::NewOrder(order("seed-ask", 2, Side::Sell, TimeInForce::Gtc, 10)), 1).unwrap();
+    shard.handle_event(Event::NewOrder(order("open-long", 1, Side::Buy, TimeInForce::Ioc, 10)), 2).unwrap();
+    assert_eq!(shard.risk.position_size(1, 1), 10);
+
+    let reduce_only = |request_id: &str| NewOrder {
+        price_ticks: 110,
+        reduce_only: true,
+        ..order(request_id, 1, Side::Sell, TimeInForce::Gtc, 6)
+    };
+    let first = updates(&shard.handle_event(Event::NewOrder(reduce_only("ro-1")), 3).unwrap())[0].order_id;
+    let placed = updates(&shard.handle_event(Event::NewOrder(reduce_only("ro-2")), 4).unwrap());
+    let second = placed[0].order_id;
+    // The first order already covers 6 of the 10 lots, so the second rests trimmed to 4.
+    assert_eq!(placed.last().unwrap().remaining_qty, 4);
+
+    // A fill against the first order drops the position to 7, which both orders still fit.
+    let lift = NewOrder {
+        price_ticks: 110,
+        ..order("lift", 3, Side::Buy, TimeInForce::Ioc, 3)
+    };
+    let lifted = updates(&shard.handle_event(Event::NewOrder(lift), 5).unwrap());
+    assert!(lifted.iter().any(|update| update.order_id == first && update.remaining_qty == 3));
+    assert!(lifted.iter().all(|update| update.order_id != second));
+
+    // Selling 5 elsewhere leaves 2: the first order is trimmed to it and the second cancelled.
+    shard.handle_event(Event::NewOrder(order("bid", 4, Side::Buy, TimeInForce::Gtc, 7)), 6).unwrap();
+    let reduced = updates(&shard.handle_event(Event::NewOrder(order("reduce", 1, Side::Sell, TimeInForce::Ioc, 5)), 7).unwrap());
+    assert_eq!(shard.risk.position_size(1, 1), 2);
+    let first_update = reduced.iter().find(|update| update.order_id == first).unwrap();
+    assert_eq!((first_update.status, first_update.remaining_qty), (OrderUpdateStatus::Open, 2));
+    let second_update = reduced.iter().find(|update| update.order_id == second).unwrap();
+    assert_eq!(second_update.status, OrderUpdateStatus::Cancelled);
+
+    // Flattening the position cancels the last reduce-only order.
+    let flatten = updates(&shard.handle_event(Event::NewOrder(order("flatten", 1, Side::Sell, TimeInForce::Ioc, 2)), 8).unwrap());
+    assert_eq!(shard.risk.position_size(1, 1), 0);
+    let update = flatten.iter().find(|update| update.order_id == first).unwrap();
+    assert_eq!(update.status, OrderUpdateStatus::Cancelled);
+    assert!(shard.snapshot().markets[&1].orders.iter().all(|resting| resting.subaccount_id != 1));
+}
+
+#[test]
+fn gtd_orders_expire_once_the_clock_passes_their_expiry() {
+    let mut shard = new_shard();
+    let gtd = order("gtd", 1, Side::Sell, TimeInForce::Gtd { expires_at: 10 }, 5);
+    let order_id = updates(&shard.handle_event(Event::NewOrder(gtd), 1).unwrap())[0].order_id;
+
+    let before = shard.handle_event(Event::NewOrder(order("other", 2, Side::Sell, TimeInForce::Gtc, 1)), 9).unwrap();
+    assert!(updates(&before).iter().all(|update| update.order_id != order_id));
+
+    let after = updates(&shard.handle_event(Event::NewOrder(order("late", 3, Side::Sell, TimeInForce::Gtc, 1)), 10).unwrap());
+    assert_eq!(after[0].order_id, order_id);
+    assert_eq!(after[0].status, OrderUpdateStatus::Expired);
+    assert_eq!(after[0].remaining_qty, 5);
+    assert!(shard.snapshot().markets[&1].orders.iter().all(|resting| resting.order_id != order_id));
+
+    let stale = order("stale", 1, Side::Sell, TimeInForce::Gtd { expires_at: 10 }, 5);
+    let outputs = shard.handle_event(Event::NewOrder(stale), 11).unwrap();
+    assert!(updates(&outputs).is_empty());
+}
+
+#[test]
+fn block_trade_updates_positions_without_touching_the_book() {
+    let mut shard = new_shard();
+    shard.handle_event(Event::NewOrder(order("resting", 3, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+
+    let trade = BlockTrade {
+        request_id: "block-1".to_string(),
+        market_id: 1,
+        buyer_subaccount_id: 1,
+        seller_subaccount_id: 2,
+        price_ticks: 100,
+        qty: 40,
+    };
+    let outputs = shard.handle_event(Event::BlockTrade(trade.clone()), 2).unwrap();
+    let fills: Vec<_> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::Fill(fill) => Some(fill.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(fills.len(), 1);
+    assert!(fills[0].block_trade);
+    assert_eq!(fills[0].qty, 40);
+    assert_eq!(shard.risk.position_size(1, 1), 40);
+    assert_eq!(shard.risk.position_size(2, 1), -40);
+    assert_eq!(shard.snapshot().markets[&1].orders.len(), 1);
+    assert!(!outputs.iter().any(|env| matches!(env.event, Event::BookDelta(_))));
+
+    let replayed = shard.handle_event(Event::BlockTrade(trade), 3).unwrap();
+    assert!(replayed.is_empty());
+}
+
+#[test]
+fn conditional_orders_wait_for_their_trigger_source() {
+    let mut shard = new_shard();
+    shard.handle_event(Event::NewOrder(order("ask-100", 2, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+
+    let stop_buy = NewOrder {
+        price_ticks: 110,
+        trigger: Some(OrderTrigger {
+            trigger_price: 100,
+            source: TriggerSource::LastTrade,
+        }),
+        ..order("stop-buy", 1, Side::Buy, TimeInForce::Ioc, 3)
+    };
+    let parked = shard.handle_event(Event::NewOrder(stop_buy), 2).unwrap();
+    assert!(updates(&parked).is_empty());
+    assert_eq!(shard.risk.position_size(1, 1), 0);
+
+    // A trade at 100 moves the last-trade price onto the trigger and the stop executes.
+    let trade = updates(&shard.handle_event(Event::NewOrder(order("lift", 3, Side::Buy, TimeInForce::Ioc, 1)), 3).unwrap());
+    assert!(trade.iter().any(|update| update.subaccount_id == 1 && update.status == OrderUpdateStatus::Filled));
+    assert_eq!(shard.risk.position_size(1, 1), 3);
+
+    let stop_sell = NewOrder {
+        trigger: Some(OrderTrigger {
+            trigger_price: 90,
+            source: TriggerSource::Mark,
+        }),
+        ..order("stop-sell", 4, Side::Sell, TimeInForce::Gtc, 2)
+    };
+    shard.handle_event(Event::NewOrder(stop_sell), 4).unwrap();
+    let price = |mark_price| PriceUpdate {
+        market_id: 1,
+        mark_price,
+        index_price: 100,
+        ts: 0,
+        source: String::new(),
+    };
+    assert!(updates(&shard.handle_event(Event::PriceUpdate(price(95)), 5).unwrap()).is_empty());
+    let activated = updates(&shard.handle_event(Event::PriceUpdate(price(90)), 6).unwrap());
+    assert_eq!(activated.len(), 1);
+    assert_eq!(activated[0].subaccount_id, 4);
+    assert_eq!(activated[0].status, OrderUpdateStatus::Open);
+}
+
+#[test]
+fn trigger_activations_are_logged_before_execution() {
+    let mut shard = new_shard();
+    let stop = NewOrder {
+        trigger: Some(OrderTrigger {
+            trigger_price: 105,
+            source: TriggerSource::Index,
+        }),
+        ..order("stop", 1, Side::Buy, TimeInForce::Gtc, 1)
+    };
+    shard.handle_event(Event::NewOrder(stop), 1).unwrap();
+    let update = PriceUpdate {
+        market_id: 1,
+        mark_price: 100,
+        index_price: 106,
+        ts: 0,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(update), 2).unwrap();
+    let triggered: Vec<_> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::OrderTriggered(triggered) => Some(triggered.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(triggered.len(), 1);
+    assert_eq!(triggered[0].reference_price, 106);
+    assert!(matches!(outputs.last().map(|env| &env.event), Some(Event::BookDelta(_))));
+}
+
+#[test]
+fn batch_markets_publish_indicative_uncross_at_most_once_per_interval() {
+    let mut shard = batch_shard(0, 0);
+    let indicatives = |outputs: Vec<EventEnvelope>| -> Vec<AuctionIndicative> {
+        outputs
+            .into_iter()
+            .filter_map(|env| match env.event {
+                Event::AuctionIndicative(indicative) => Some(indicative),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let first = indicatives(shard.handle_event(Event::NewOrder(batch_order("b1", 1, Side::Buy, 101, 5)), 10).unwrap());
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].matched_qty, 0);
+
+    // Same clock tick: throttled by the one-unit interval.
+    let throttled = shard.handle_event(Event::NewOrder(batch_order("s1", 2, Side::Sell, 99, 3)), 10).unwrap();
+    assert!(indicatives(throttled).is_empty());
+
+    let next = indicatives(shard.handle_event(Event::NewOrder(batch_order("s2", 3, Side::Sell, 100, 1)), 11).unwrap());
+    assert_eq!(next.len(), 1);
+    assert_eq!(next[0].market_id, 2);
+    assert_eq!(next[0].matched_qty, 4);
+    assert_eq!(next[0].imbalance_qty, 1);
+    assert_eq!(next[0].imbalance_side, Some(Side::Buy));
+}
+
+fn fills(outputs: &[EventEnvelope]) -> Vec<hypermarket_clob::models::Fill> {
+    outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::Fill(fill) => Some(fill.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn batch_shard(clearing_jitter_ms: u64, seed: u64) -> EngineShard {
+    let mut shard = new_shard();
+    shard.upsert_market(MarketConfig {
+        market_id: 2,
+        clearing_jitter_ms,
+        ..market_config(MatchingMode::Batch)
+    }, 0).unwrap();
+    shard.risk.update_mark(2, 100);
+    shard.handle_event(Event::ClearingSeed(ClearingSeed { seed }), 0).unwrap();
+    shard
+}
+
+fn batch_order(request_id: &str, subaccount_id: u64, side: Side, price_ticks: u64, qty: u64) -> NewOrder {
+    NewOrder {
+        market_id: 2,
+        price_ticks,
+        ..order(request_id, subaccount_id, side, TimeInForce::Gtc, qty)
+    }
+}
+
+#[test]
+fn batch_auction_clears_at_the_interval_and_carries_remainders() {
+    let mut shard = batch_shard(0, 7);
+    shard.handle_event(Event::NewOrder(batch_order("b1", 1, Side::Buy, 101, 5)), 10).unwrap();
+    shard.handle_event(Event::NewOrder(batch_order("s1", 2, Side::Sell, 99, 3)), 11).unwrap();
+
+    // The 2000ms interval is two clock units, so the auction opened at 10 clears at 12, before
+    // the input stamped 12 is applied.
+    let late = batch_order("s2", 3, Side::Sell, 99, 1);
+    let outputs = shard.handle_event(Event::NewOrder(late), 12).unwrap();
+    let cleared = fills(&outputs);
+    assert_eq!(cleared.len(), 1);
+    assert_eq!(cleared[0].qty, 3);
+    let statuses: Vec<_> = updates(&outputs).into_iter().map(|update| (update.subaccount_id, update.status)).collect();
+    assert!(statuses.contains(&(2, OrderUpdateStatus::Filled)));
+    assert!(statuses.contains(&(1, OrderUpdateStatus::PartiallyFilled)));
+    assert_eq!(shard.risk.position_size(1, 2), 3);
+
+    // The buy remainder and the late sell meet in the next auction.
+    let next = shard.handle_event(Event::NewOrder(batch_order("s3", 4, Side::Sell, 120, 1)), 14).unwrap();
+    assert_eq!(fills(&next).iter().map(|fill| fill.qty).sum::<u64>(), 1);
+}
+
+#[test]
+fn batch_ioc_orders_take_one_clear_and_fok_orders_only_fill_in_full() {
+    let mut shard = batch_shard(0, 7);
+    let batch_taker = |request_id, subaccount_id, tif, qty| NewOrder {
+        tif,
+        ..batch_order(request_id, subaccount_id, Side::Buy, 101, qty)
+    };
+    shard.handle_event(Event::NewOrder(batch_order("s1", 1, Side::Sell, 99, 3)), 10).unwrap();
+    shard.handle_event(Event::NewOrder(batch_taker("fok", 2, TimeInForce::Fok, 5)), 10).unwrap();
+    shard.handle_event(Event::NewOrder(batch_taker("ioc", 3, TimeInForce::Ioc, 2)), 10).unwrap();
+
+    // Only 3 are offered, so the FOK buy of 5 sits the auction out and the IOC buy fills in full.
+    let outputs = shard.handle_event(Event::NewOrder(batch_order("s2", 4, Side::Sell, 120, 1)), 12).unwrap();
+    let cleared: Vec<_> = fills(&outputs).iter().map(|fill| (fill.taker_subaccount_id, fill.qty)).collect();
+    assert_eq!(cleared, vec![(3, 2)]);
+    let statuses: Vec<_> = updates(&outputs).into_iter().map(|update| (update.subaccount_id, update.status)).collect();
+    assert!(statuses.contains(&(2, OrderUpdateStatus::Cancelled)));
+    assert!(statuses.contains(&(3, OrderUpdateStatus::Filled)));
+
+    // An IOC buy of 5 takes the 1 left at 99 and is dropped instead of carrying over.
+    shard.handle_event(Event::NewOrder(batch_taker("ioc2", 5, TimeInForce::Ioc, 5)), 13).unwrap();
+    let outputs = shard.handle_event(Event::NewOrder(batch_order("s3", 6, Side::Sell, 130, 1)), 14).unwrap();
+    assert_eq!(fills(&outputs).iter().map(|fill| fill.qty).sum::<u64>(), 1);
+    let dropped = updates(&outputs).into_iter().find(|update| update.subaccount_id == 5).unwrap();
+    assert_eq!((dropped.status, dropped.remaining_qty), (OrderUpdateStatus::Cancelled, 4));
+    shard.handle_event(Event::NewOrder(batch_order("s4", 7, Side::Sell, 99, 5)), 15).unwrap();
+    let next = shard.handle_event(Event::NewOrder(batch_order("s5", 8, Side::Sell, 140, 1)), 16).unwrap();
+    assert!(fills(&next).is_empty());
+}
+
+#[test]
+fn clearing_jitter_is_reproducible_from_the_seed() {
+    let clear_time = |seed| {
+        let mut shard = batch_shard(10_000, seed);
+        shard.handle_event(Event::NewOrder(batch_order("b1", 1, Side::Buy, 100, 1)), 10).unwrap();
+        shard.handle_event(Event::NewOrder(batch_order("s1", 2, Side::Sell, 100, 1)), 10).unwrap();
+        (11..=30)
+            .find(|ts| {
+                let tick = PriceUpdate {
+                    market_id: 1,
+                    mark_price: 100,
+                    index_price: 100,
+                    ts: 0,
+                    source: String::new(),
+                };
+                !fills(&shard.handle_event(Event::PriceUpdate(tick), *ts).unwrap()).is_empty()
+            })
+            .unwrap()
+    };
+    let first = clear_time(42);
+    assert!((12..=22).contains(&first));
+    assert_eq!(clear_time(42), first);
+}
+
+#[test]
+fn parent_limits_span_subaccounts_and_mass_cancel_clears_them() {
+    let mut shard = new_shard();
+    shard.upsert_account(AccountConfig {
+        parent_id: 7,
+        subaccounts: vec![1, 2],
+        max_total_position: 10,
+        max_total_open_orders: 2,
+    });
+    let reject_code = |outputs: &[EventEnvelope]| {
+        outputs.iter().find_map(|env| match &env.event {
+            Event::OrderAck(ack) => ack.reject_code,
+            _ => None,
+        })
+    };
+
+    shard.handle_event(Event::NewOrder(order("a", 1, Side::Buy, TimeInForce::Gtc, 4)), 1).unwrap();
+    shard.handle_event(Event::NewOrder(order("b", 2, Side::Buy, TimeInForce::Gtc, 4)), 2).unwrap();
+    let third = shard.handle_event(Event::NewOrder(order("c", 2, Side::Buy, TimeInForce::Gtc, 1)), 3).unwrap();
+    assert_eq!(reject_code(&third), Some(RejectReason::MaxOpenOrders));
+
+    // Both children are long 8 in total once their bids fill; another 3 would breach the cap.
+    shard.handle_event(Event::NewOrder(order("fill", 3, Side::Sell, TimeInForce::Ioc, 8)), 4).unwrap();
+    assert_eq!(shard.risk.position_size(1, 1) + shard.risk.position_size(2, 1), 8);
+    let over = shard.handle_event(Event::NewOrder(order("over", 1, Side::Buy, TimeInForce::Ioc, 3)), 5).unwrap();
+    assert_eq!(reject_code(&over), Some(RejectReason::MaxPosition));
+
+    shard.handle_event(Event::NewOrder(order("d", 1, Side::Sell, TimeInForce::Gtc, 1)), 6).unwrap();
+    shard.handle_event(Event::NewOrder(order("e", 2, Side::Sell, TimeInForce::Gtc, 1)), 7).unwrap();
+    shard.handle_event(Event::NewOrder(order("other", 5, Side::Sell, TimeInForce::Gtc, 1)), 8).unwrap();
+    let cancel = MassCancel {
+        request_id: "mass".to_string(),
+        parent_id: 7,
+        market_id: None,
+    };
+    let outputs = shard.handle_event(Event::MassCancel(cancel), 9).unwrap();
+    let cancelled = updates(&outputs);
+    assert_eq!(cancelled.len(), 2);
+    assert!(cancelled.iter().all(|update| update.status == OrderUpdateStatus::Cancelled));
+    let resting = &shard.snapshot().markets[&1].orders;
+    assert_eq!(resting.len(), 1);
+    assert_eq!(resting[0].subaccount_id, 5);
+}
+
+#[test]
+fn transfers_move_free_collateral_between_subaccounts_of_one_parent() {
+    let mut shard = new_shard();
+    let margined = MarketConfig {
+        initial_margin_bps: 1_000,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    shard.upsert_account(AccountConfig {
+        parent_id: 7,
+        subaccounts: vec![1, 2],
+        max_total_position: 0,
+        max_total_open_orders: 0,
+    });
+    shard.upsert_account(AccountConfig {
+        parent_id: 8,
+        subaccounts: vec![3],
+        max_total_position: 0,
+        max_total_open_orders: 0,
+    });
+    shard.risk.ensure_subaccount(1).collateral = 1_000;
+    // Resting 10 @ 100 ties up 100 of subaccount 1's collateral.
+    shard.handle_event(Event::NewOrder(order("rest", 1, Side::Sell, TimeInForce::Gtc, 10)), 2).unwrap();
+    let transfer = |request_id: &str, to_subaccount_id, amount| Transfer {
+        request_id: request_id.to_string(),
+        market_id: 1,
+        from_subaccount_id: 1,
+        to_subaccount_id,
+        amount,
+    };
+    let reject_code = |outputs: &[EventEnvelope]| {
+        outputs.iter().find_map(|env| match &env.event {
+            Event::OrderAck(ack) => ack.reject_code,
+            _ => None,
+        })
+    };
+
+    let over = shard.handle_event(Event::Transfer(transfer("over", 2, 901)), 3).unwrap();
+    assert_eq!(reject_code(&over), Some(RejectReason::InsufficientMargin));
+    let foreign = shard.handle_event(Event::Transfer(transfer("foreign", 3, 100)), 4).unwrap();
+    assert_eq!(reject_code(&foreign), Some(RejectReason::Unauthorized));
+
+    let outputs = shard.handle_event(Event::Transfer(transfer("move", 2, 900)), 5).unwrap();
+    assert_eq!(reject_code(&outputs), None);
+    let applied = outputs
+        .iter()
+        .find_map(|env| match &env.event {
+            Event::CollateralTransfer(applied) => Some(applied.clone()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!((applied.from_subaccount_id, applied.to_subaccount_id, applied.amount), (1, 2, 900));
+    assert_eq!(shard.risk.ensure_subaccount(1).collateral, 100);
+    assert_eq!(shard.risk.ensure_subaccount(2).collateral, 900);
+    assert_eq!(shard.free_collateral(1), 0);
+
+    // A redelivered transfer is not applied twice.
+    let replayed = shard.handle_event(Event::Transfer(transfer("move", 2, 900)), 6).unwrap();
+    assert!(replayed.iter().all(|env| !matches!(env.event, Event::OrderAck(_) | Event::CollateralTransfer(_))));
+    assert_eq!(shard.risk.ensure_subaccount(2).collateral, 900);
+}
+
+#[test]
+fn restricted_markets_only_accept_allowlisted_subaccounts() {
+    let mut shard = new_shard();
+    shard.upsert_permissions(MarketPermissions {
+        market_id: 1,
+        restricted: true,
+        allowed_subaccounts: vec![1],
+    });
+    let ack_code = |outputs: Vec<EventEnvelope>| {
+        outputs.into_iter().find_map(|env| match env.event {
+            Event::OrderAck(ack) => Some(ack.reject_code),
+            _ => None,
+        })
+    };
+
+    let allowed = shard.handle_event(Event::NewOrder(order("ok", 1, Side::Sell, TimeInForce::Gtc, 1)), 1).unwrap();
+    assert_eq!(ack_code(allowed), Some(None));
+    let denied = shard.handle_event(Event::NewOrder(order("no", 2, Side::Buy, TimeInForce::Gtc, 1)), 2).unwrap();
+    assert_eq!(ack_code(denied), Some(Some(RejectReason::Unauthorized)));
+    let trade = BlockTrade {
+        request_id: "block".to_string(),
+        market_id: 1,
+        buyer_subaccount_id: 2,
+        seller_subaccount_id: 1,
+        price_ticks: 100,
+        qty: 1,
+    };
+    let denied = shard.handle_event(Event::BlockTrade(trade), 3).unwrap();
+    assert_eq!(ack_code(denied), Some(Some(RejectReason::Unauthorized)));
+
+    shard.upsert_permissions(MarketPermissions {
+        market_id: 1,
+        restricted: false,
+        allowed_subaccounts: Vec::new(),
+    });
+    let reopened = shard.handle_event(Event::NewOrder(order("later", 2, Side::Buy, TimeInForce::Gtc, 1)), 4).unwrap();
+    assert_eq!(ack_code(reopened), Some(None));
+}
+
+#[test]
+fn signed_orders_are_verified_against_the_subaccount_key() {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let key = SigningKey::from_bytes(&[7u8; 32]);
+    let public_key: String = key.verifying_key().to_bytes().iter().map(|byte| format!("{byte:02x}")).collect();
+    let mut shard = new_shard();
+    shard
+        .upsert_signing_key(&SigningKeyConfig {
+            subaccount_id: 1,
+            public_key,
+        })
+        .unwrap();
+    let signed = |request_id: &str, subaccount_id| {
+        let mut order = order(request_id, subaccount_id, Side::Sell, TimeInForce::Gtc, 1);
+        order.signature = bytes::Bytes::copy_from_slice(&key.sign(&order.signing_payload()).to_bytes());
+        order
+    };
+    let reject_code = |reject: Option<EventEnvelope>| {
+        reject.and_then(|env| match env.event {
+            Event::OrderAck(ack) => ack.reject_code,
+            _ => None,
+        })
+    };
+
+    assert!(shard.authenticate(&Event::NewOrder(signed("ok", 1)), 1).is_none());
+    let tampered = NewOrder {
+        qty: 2,
+        ..signed("tampered", 1)
+    };
+    assert_eq!(reject_code(shard.authenticate(&Event::NewOrder(tampered), 2)), Some(RejectReason::InvalidSignature));
+    let unsigned = order("unsigned", 1, Side::Sell, TimeInForce::Gtc, 1);
+    assert_eq!(reject_code(shard.authenticate(&Event::NewOrder(unsigned), 3)), Some(RejectReason::InvalidSignature));
+
+    // Subaccounts without a key pass unless signatures are required.
+    let keyless = Event::NewOrder(order("keyless", 2, Side::Buy, TimeInForce::Gtc, 1));
+    assert!(shard.authenticate(&keyless, 4).is_none());
+    let shard = new_shard().with_required_signatures(true);
+    assert_eq!(reject_code(shard.authenticate(&keyless, 5)), Some(RejectReason::InvalidSignature));
+}
+
+#[test]
+fn order_nonces_cannot_be_reused_after_a_restart() {
+    let mut shard = new_shard();
+    let ack_code = |outputs: Vec<EventEnvelope>| {
+        outputs.into_iter().find_map(|env| match env.event {
+            Event::OrderAck(ack) => Some(ack.reject_code),
+            _ => None,
+        })
+    };
+    let nonced = |request_id: &str, nonce| NewOrder {
+        nonce,
+        ..order(request_id, 1, Side::Sell, TimeInForce::Gtc, 1)
+    };
+
+    assert_eq!(ack_code(shard.handle_event(Event::NewOrder(nonced("a", 5)), 1).unwrap()), Some(None));
+    let replayed = shard.handle_event(Event::NewOrder(nonced("b", 5)), 2).unwrap();
+    assert_eq!(ack_code(replayed), Some(Some(RejectReason::StaleNonce)));
+    let older = shard.handle_event(Event::NewOrder(nonced("c", 4)), 3).unwrap();
+    assert_eq!(ack_code(older), Some(Some(RejectReason::StaleNonce)));
+    assert_eq!(ack_code(shard.handle_event(Event::NewOrder(nonced("d", 0)), 4).unwrap()), Some(None));
+
+    let state = shard.snapshot();
+    assert_eq!(state.markets[&1].nonce_watermarks[&1], 5);
+    let wal_path = std::env::temp_dir().join(format!("order_updates_nonces_{}.wal", std::process::id()));
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let markets = vec![market_config(MatchingMode::Continuous)];
+    let mut restored = EngineShard::restore(state, markets, Wal::open(&wal_path).unwrap(), risk).unwrap();
+    let replayed = restored.handle_event(Event::NewOrder(nonced("e", 5)), 5).unwrap();
+    assert_eq!(ack_code(replayed), Some(Some(RejectReason::StaleNonce)));
+    let _ = std::fs::remove_file(&wal_path);
+
+    // Without a snapshot, the previous run's logged orders spend the nonces.
+    let logged = EventEnvelope {
+        shard_id: 0,
+        engine_seq: 1,
+        event: Event::NewOrder(nonced("f", 9)),
+        ts: 1,
+        ts_ns: 1_000_000_000,
+        schema_version: hypermarket_clob::models::SCHEMA_VERSION,
+    };
+    let mut restarted = new_shard();
+    restarted.recover_nonces(&[logged]);
+    let replayed = restarted.handle_event(Event::NewOrder(nonced("g", 9)), 2).unwrap();
+    assert_eq!(ack_code(replayed), Some(Some(RejectReason::StaleNonce)));
+    assert_eq!(ack_code(restarted.handle_event(Event::NewOrder(nonced("h", 10)), 3).unwrap()), Some(None));
+}
+
+#[test]
+fn a_nonce_cannot_back_two_requests_across_markets() {
+    let wal_path = std::env::temp_dir().join(format!("order_updates_nonce_markets_{}.wal", std::process::id()));
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let markets = vec![
+        market_config(MatchingMode::Continuous),
+        MarketConfig {
+            market_id: 2,
+            ..market_config(MatchingMode::Continuous)
+        },
+    ];
+    let new_shard = || {
+        let mut shard = EngineShard::new(0, markets.clone(), Wal::open(&wal_path).unwrap(), risk.clone());
+        shard.risk.update_mark(1, 100);
+        shard.risk.update_mark(2, 100);
+        shard
+    };
+    let ack_code = |outputs: Vec<EventEnvelope>| {
+        outputs.into_iter().find_map(|env| match env.event {
+            Event::OrderAck(ack) => Some(ack.reject_code),
+            _ => None,
+        })
+    };
+    let nonced = |request_id: &str, market_id, nonce| NewOrder {
+        market_id,
+        nonce,
+        ..order(request_id, 1, Side::Sell, TimeInForce::Gtc, 1)
+    };
+
+    let mut shard = new_shard();
+    assert_eq!(ack_code(shard.handle_event(Event::NewOrder(nonced("a", 1, 5)), 1).unwrap()), Some(None));
+    let reused = shard.handle_event(Event::NewOrder(nonced("b", 2, 5)), 2).unwrap();
+    assert_eq!(ack_code(reused), Some(Some(RejectReason::DuplicateNonce)));
+    assert_eq!(ack_code(shard.handle_event(Event::NewOrder(nonced("c", 2, 6)), 3).unwrap()), Some(None));
+    assert_eq!(shard.nonce_dedupe.stats().hits, 1);
+
+    // A restart re-arms the window from the logged orders.
+    let logged = EventEnvelope {
+        shard_id: 0,
+        engine_seq: 1,
+        event: Event::NewOrder(nonced("a", 1, 5)),
+        ts: 1,
+        ts_ns: 1_000_000_000,
+        schema_version: hypermarket_clob::models::SCHEMA_VERSION,
+    };
+    let mut restarted = new_shard();
+    restarted.recover_nonces(&[logged]);
+    let reused = restarted.handle_event(Event::NewOrder(nonced("d", 2, 5)), 2).unwrap();
+    assert_eq!(ack_code(reused), Some(Some(RejectReason::DuplicateNonce)));
+    let _ = std::fs::remove_file(&wal_path);
+}
+
+#[test]
+fn orders_queued_past_the_latency_budget_are_rejected_or_made_ioc() {
+    const MS: u64 = 1_000_000;
+    let budget = |action| {
+        Some(LatencyBudgetConfig {
+            max_queue_delay_ms: 50,
+            action,
+        })
+    };
+    let ack = |outputs: &[EventEnvelope]| {
+        outputs
+            .iter()
+            .find_map(|env| match &env.event {
+                Event::OrderAck(ack) => Some((ack.reject_code, ack.disposition)),
+                _ => None,
+            })
+            .unwrap()
+    };
+
+    let shard = new_shard().with_latency_budget(budget(LatencyBudgetAction::Reject));
+    let mut on_time = Event::NewOrder(order("on-time", 1, Side::Buy, TimeInForce::Gtc, 1));
+    assert!(shard.admit(&mut on_time, 50 * MS, 1).is_none());
+    let mut late = Event::NewOrder(order("late", 1, Side::Buy, TimeInForce::Gtc, 1));
+    let reject = shard.admit(&mut late, 51 * MS, 1).expect("over budget");
+    assert_eq!(ack(&[reject]), (Some(RejectReason::QueueDelay), None));
+    // Post-only orders cannot take liquidity, so a backlog cannot make them trade at a stale price.
+    let mut post_only = Event::NewOrder(NewOrder {
+        order_type: OrderType::PostOnly,
+        ..order("post-only", 1, Side::Buy, TimeInForce::Gtc, 1)
+    });
+    assert!(shard.admit(&mut post_only, 500 * MS, 1).is_none());
+    let mut cancel = Event::CancelOrder(CancelOrder {
+        request_id: "cancel".to_string(),
+        market_id: 1,
+        subaccount_id: 1,
+        order_id: Some(1),
+        nonce_start: None,
+        nonce_end: None,
+    });
+    assert!(shard.admit(&mut cancel, 500 * MS, 1).is_none());
+
+    let mut shard = new_shard().with_latency_budget(budget(LatencyBudgetAction::Ioc));
+    shard.handle_event(Event::NewOrder(order("maker", 1, Side::Sell, TimeInForce::Gtc, 1)), 1).unwrap();
+    let mut late = Event::NewOrder(order("late", 2, Side::Buy, TimeInForce::Gtc, 3));
+    assert!(shard.admit(&mut late, 500 * MS, 2).is_none());
+    // Converted in place, so the WAL logs the IOC it ran as.
+    assert!(matches!(&late, Event::NewOrder(order) if order.tif == TimeInForce::Ioc));
+    let outputs = shard.handle_event(late, 2).unwrap();
+    assert_eq!(ack(&outputs), (None, Some(OrderDisposition::Cancelled)));
+    assert_eq!(fills(&outputs).len(), 1);
+}
+
+#[test]
+fn restored_shards_keep_each_orders_tif_and_reduce_only_flag() {
+    let mut shard = new_shard();
+    shard.handle_event(Event::NewOrder(order("seed-ask", 2, Side::Sell, TimeInForce::Gtc, 10)), 1).unwrap();
+    shard.handle_event(Event::NewOrder(order("open-long", 1, Side::Buy, TimeInForce::Ioc, 10)), 2).unwrap();
+    let reduce_only = NewOrder {
+        price_ticks: 110,
+        reduce_only: true,
+        ..order("ro", 1, Side::Sell, TimeInForce::Gtc, 6)
+    };
+    let reduce_only_id = updates(&shard.handle_event(Event::NewOrder(reduce_only), 3).unwrap())[0].order_id;
+    let gtd = NewOrder {
+        price_ticks: 110,
+        order_type: OrderType::PostOnly,
+        ..order("gtd", 3, Side::Sell, TimeInForce::Gtd { expires_at: 10 }, 5)
+    };
+    let gtd_id = updates(&shard.handle_event(Event::NewOrder(gtd), 4).unwrap())[0].order_id;
+
+    let state = shard.snapshot();
+    let resting: Vec<_> = state.markets[&1].orders.iter().map(|order| (order.order_id, order.order_type, order.tif, order.reduce_only)).collect();
+    assert_eq!(
+        resting,
+        vec![
+            (reduce_only_id, OrderType::Limit, TimeInForce::Gtc, true),
+            (gtd_id, OrderType::PostOnly, TimeInForce::Gtd { expires_at: 10 }, false),
+        ]
+    );
+    let wal_path = std::env::temp_dir().join(format!("order_updates_restore_{}.wal", std::process::id()));
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let markets = vec![market_config(MatchingMode::Continuous)];
+    let mut restored = EngineShard::restore(state.clone(), markets, Wal::open(&wal_path).unwrap(), risk).unwrap();
+    let _ = std::fs::remove_file(&wal_path);
+    assert_eq!(bincode::serialize(&restored.snapshot()).unwrap(), bincode::serialize(&state).unwrap());
+
+    // Flattening the position still cancels the reduce-only order, and the GTD order still expires.
+    restored.handle_event(Event::NewOrder(order("bid", 4, Side::Buy, TimeInForce::Gtc, 10)), 5).unwrap();
+    let flatten = updates(&restored.handle_event(Event::NewOrder(order("flatten", 1, Side::Sell, TimeInForce::Ioc, 10)), 6).unwrap());
+    let cancelled = flatten.iter().find(|update| update.order_id == reduce_only_id).unwrap();
+    assert_eq!((cancelled.subaccount_id, cancelled.status), (1, OrderUpdateStatus::Cancelled));
+    let expired = updates(&restored.handle_event(Event::NewOrder(order("late", 5, Side::Buy, TimeInForce::Gtc, 1)), 10).unwrap());
+    assert_eq!((expired[0].order_id, expired[0].subaccount_id, expired[0].status), (gtd_id, 3, OrderUpdateStatus::Expired));
+}
+
+#[test]
+fn restored_shards_keep_conditional_orders_and_the_open_batch_auction() {
+    let mut shard = batch_shard(10_000, 7);
+    let stop = NewOrder {
+        trigger: Some(OrderTrigger {
+            trigger_price: 105,
+            source: TriggerSource::Index,
+        }),
+        ..order("stop", 1, Side::Buy, TimeInForce::Gtc, 2)
+    };
+    shard.handle_event(Event::NewOrder(stop), 1).unwrap();
+    shard.handle_event(Event::NewOrder(order("ask", 2, Side::Sell, TimeInForce::Gtc, 2)), 2).unwrap();
+    shard.handle_event(Event::NewOrder(batch_order("b1", 3, Side::Buy, 101, 5)), 10).unwrap();
+    shard.handle_event(Event::NewOrder(batch_order("s1", 4, Side::Sell, 99, 3)), 11).unwrap();
+
+    let state = shard.snapshot();
+    assert_eq!(state.markets[&1].conditional.len(), 1);
+    assert_eq!(state.markets[&2].batch_pending.len(), 2);
+    assert!(state.markets[&2].next_clear_at.is_some());
+    assert_eq!(state.clearing_seed, 7);
+
+    // Market 2 is only in the snapshot: restore takes its config from there.
+    let wal_path = std::env::temp_dir().join(format!("order_updates_restore_pending_{}.wal", std::process::id()));
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let mut restored = EngineShard::restore(state.clone(), vec![market_config(MatchingMode::Continuous)], Wal::open(&wal_path).unwrap(), risk).unwrap();
+    let _ = std::fs::remove_file(&wal_path);
+    assert_eq!(bincode::serialize(&restored.snapshot()).unwrap(), bincode::serialize(&state).unwrap());
+
+    // At 20 the jittered auction is due and the index crosses the stop's trigger: both shards fill
+    // the same orders on both markets.
+    let input = Event::PriceUpdate(PriceUpdate {
+        market_id: 1,
+        mark_price: 100,
+        index_price: 106,
+        ts: 0,
+        source: String::new(),
+    });
+    let expected = shard.handle_event(input.clone(), 20).unwrap();
+    let outputs = restored.handle_event(input, 20).unwrap();
+    let filled: Vec<_> = fills(&expected).iter().map(|fill| (fill.market_id, fill.taker_order_id, fill.qty)).collect();
+    assert_eq!(filled, vec![(2, 3, 3), (1, 1, 2)]);
+    assert_eq!(bincode::serialize(&outputs).unwrap(), bincode::serialize(&expected).unwrap());
+}
+
+#[test]
+fn risk_metrics_summarize_margin_and_account_health() {
+    let mut shard = new_shard();
+    let margined = MarketConfig {
+        initial_margin_bps: 1_000,
+        maintenance_margin_bps: 500,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    for (subaccount_id, collateral) in [(1, 1_000), (2, 10), (3, 0), (4, 10_000)] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = collateral;
+    }
+    // Resting 10 @ 100 at 10% initial margin.
+    shard.handle_event(Event::NewOrder(order("rest", 1, Side::Sell, TimeInForce::Gtc, 10)), 2).unwrap();
+    for (subaccount_id, size) in [(1, -25), (2, 30), (3, 10), (4, -15)] {
+        let position = Position {
+            size,
+            entry_price: 100,
+            funding_index: 0,
+        };
+        shard.risk.ensure_subaccount(subaccount_id).positions.insert(1, position);
+    }
+
+    // Subaccounts 2 and 3 hold less equity than 120% of their maintenance margin (150 and 50);
+    // subaccount 2 holds 30 of the 40 lots of open interest.
+    let metrics = shard.risk_metrics(2_000);
+    assert_eq!(
+        metrics,
+        RiskMetrics {
+            total_collateral: 11_010,
+            open_order_margin: 100,
+            accounts_near_maintenance: 2,
+            max_position_concentration_bps: 7_500,
+        }
+    );
+    // Subaccount 1 margins 250 for its position and 100 for its resting order; 3 is short of it.
+    assert_eq!(shard.free_collateral(1), 650);
+    assert_eq!(shard.free_collateral(3), -100);
+    assert_eq!(
+        shard.account_equity(1),
+        AccountEquity {
+            shard_id: 0,
+            subaccount_id: 1,
+            engine_seq: shard.engine_seq,
+            balance: 1_000,
+            unrealized_pnl: 0,
+            equity: 1_000,
+            initial_margin: 350,
+            maintenance_margin: 125,
+            free_collateral: 650,
+            ..AccountEquity::default()
+        }
+    );
+}
+
+#[test]
+fn underwater_positions_are_liquidated_in_tranches() {
+    let mut shard = new_shard();
+    let margined = MarketConfig {
+        maintenance_margin_bps: 500,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    shard.risk.ensure_subaccount(1).collateral = 1_000_000;
+    shard.risk.ensure_subaccount(2).collateral = 300;
+    let bid = NewOrder {
+        price_ticks: 90,
+        ..order("bid", 1, Side::Buy, TimeInForce::Gtc, 100)
+    };
+    shard.handle_event(Event::NewOrder(bid), 2).unwrap();
+    let position = Position {
+        size: 100,
+        entry_price: 100,
+        funding_index: 0,
+    };
+    shard.risk.ensure_subaccount(2).positions.insert(1, position);
+
+    let update = PriceUpdate {
+        market_id: 1,
+        mark_price: 90,
+        index_price: 90,
+        ts: 3,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(update), 3).unwrap();
+    let tranches: Vec<Liquidation> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::Liquidation(liquidation) => Some(liquidation.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // 25 of 100 lots leaves 75 needing 337 of maintenance against 300 of equity, so a second
+    // tranche of 19 follows; at 56 lots the subaccount is healthy again and keeps its position.
+    let summary: Vec<_> = tranches
+        .iter()
+        .map(|t| (t.side, t.qty, t.filled_qty, t.remaining_position, t.equity, t.maintenance_margin))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![(Side::Sell, 25, 25, 75, -700, 450), (Side::Sell, 19, 19, 56, 300, 337)]
+    );
+    assert!(tranches.iter().all(|t| t.subaccount_id == 2));
+    assert_eq!(shard.risk.position_size(2, 1), 56);
+    assert_eq!(shard.risk.position_size(1, 1), 44);
+}
+
+#[test]
+fn updating_a_market_keeps_its_mark() {
+    let mut shard = new_shard();
+    let margined = MarketConfig {
+        maintenance_margin_bps: 500,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    assert_eq!(shard.risk.state.mark_prices.get(&1), Some(&100));
+
+    // A market the shard has not seen yet starts at one tick.
+    let added = MarketConfig {
+        market_id: 2,
+        tick_size: 5,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(added, 1).unwrap();
+    assert_eq!(shard.risk.state.mark_prices.get(&2), Some(&5));
+}
+
+#[test]
+fn liquidation_fees_go_to_the_insurance_fund() {
+    let mut shard = new_shard().with_insurance_fund(Some(9));
+    let margined = MarketConfig {
+        maintenance_margin_bps: 500,
+        liquidation_fee_bps: 100,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    shard.risk.ensure_subaccount(1).collateral = 1_000_000;
+    shard.risk.ensure_subaccount(2).collateral = 300;
+    let bid = NewOrder {
+        price_ticks: 90,
+        ..order("bid", 1, Side::Buy, TimeInForce::Gtc, 100)
+    };
+    shard.handle_event(Event::NewOrder(bid), 2).unwrap();
+    let position = Position {
+        size: 100,
+        entry_price: 100,
+        funding_index: 0,
+    };
+    shard.risk.ensure_subaccount(2).positions.insert(1, position);
+
+    let update = PriceUpdate {
+        market_id: 1,
+        mark_price: 90,
+        index_price: 90,
+        ts: 3,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(update), 3).unwrap();
+    let tranches: Vec<Liquidation> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::Liquidation(liquidation) => Some(liquidation.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // 1% of 25 and then 19 lots at 90; the second fee leaves 261 of equity against 252 of
+    // maintenance, so liquidation stops there.
+    let fees: Vec<_> = tranches.iter().map(|t| (t.qty, t.notional, t.fee)).collect();
+    assert_eq!(fees, vec![(25, 2_250, 22), (19, 1_710, 17)]);
+    assert_eq!(shard.risk.state.subaccounts[&2].collateral, 261);
+    assert_eq!(shard.risk.state.subaccounts[&9].collateral, 39);
+    let record = tranches[0].fee_record().unwrap();
+    assert_eq!((record.subaccount_id, record.insurance_fund_subaccount_id, record.fee), (2, 9, 22));
+}
+
+#[test]
+fn losses_beyond_the_bankruptcy_price_are_covered_by_the_insurance_fund() {
+    let mut shard = new_shard().with_insurance_fund(Some(9));
+    let margined = MarketConfig {
+        maintenance_margin_bps: 500,
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    for (subaccount_id, collateral) in [(1, 1_000_000), (2, 300), (9, 100)] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = collateral;
+    }
+    let bid = NewOrder {
+        price_ticks: 90,
+        ..order("bid", 1, Side::Buy, TimeInForce::Gtc, 100)
+    };
+    shard.handle_event(Event::NewOrder(bid), 2).unwrap();
+    let position = Position {
+        size: 100,
+        entry_price: 100,
+        funding_index: 0,
+    };
+    shard.risk.ensure_subaccount(2).positions.insert(1, position);
+
+    let update = PriceUpdate {
+        market_id: 1,
+        mark_price: 90,
+        index_price: 90,
+        ts: 3,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(update), 3).unwrap();
+    let tranches: Vec<Liquidation> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::Liquidation(liquidation) => Some(liquidation.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // -700 of equity on 100 lots at 90 runs out at 97, so selling 25 at 90 loses 175 more than
+    // the subaccount has. The fund covers the 100 it holds; the other 75 is left for ADL.
+    assert_eq!(tranches.len(), 1);
+    let tranche = &tranches[0];
+    assert_eq!(
+        (tranche.bankruptcy_price, tranche.shortfall, tranche.insurance_covered, tranche.adl_shortfall),
+        (97, 175, 100, 75)
+    );
+    assert_eq!(tranche.insurance_fund_subaccount_id, Some(9));
+    assert_eq!(shard.risk.state.subaccounts[&2].collateral, 400);
+    assert_eq!(shard.risk.state.subaccounts[&9].collateral, 0);
+}
+
+#[test]
+fn backstop_providers_take_liquidations_before_the_book() {
+    let mut shard = new_shard();
+    let margined = MarketConfig {
+        initial_margin_bps: 1_000,
+        maintenance_margin_bps: 500,
+        backstop_providers: vec![3, 4],
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(margined, 1).unwrap();
+    // Provider 4 cannot margin any share, so its shares roll over to the book.
+    for (subaccount_id, collateral) in [(1, 1_000_000), (2, 300), (3, 1_000_000), (4, 0)] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = collateral;
+    }
+    let bid = NewOrder {
+        price_ticks: 90,
+        ..order("bid", 1, Side::Buy, TimeInForce::Gtc, 100)
+    };
+    shard.handle_event(Event::NewOrder(bid), 2).unwrap();
+    let position = Position {
+        size: 100,
+        entry_price: 100,
+        funding_index: 0,
+    };
+    shard.risk.ensure_subaccount(2).positions.insert(1, position);
+
+    let update = PriceUpdate {
+        market_id: 1,
+        mark_price: 90,
+        index_price: 90,
+        ts: 3,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(update), 3).unwrap();
+    let tranches: Vec<_> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::Liquidation(t) => Some((t.qty, t.backstop_qty, t.filled_qty, t.remaining_position)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(tranches, vec![(25, 13, 12, 75), (19, 10, 9, 56)]);
+    let assignments: Vec<BackstopAssignment> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::BackstopAssignment(assignment) => Some(assignment.clone()),
+            _ => None,
+        })
+        .collect();
+    let summary: Vec<_> = assignments
+        .iter()
+        .map(|a| (a.provider_subaccount_id, a.side, a.price_ticks, a.qty))
+        .collect();
+    assert_eq!(summary, vec![(3, Side::Buy, 90, 13), (3, Side::Buy, 90, 10)]);
+    assert!(assignments.iter().all(|a| a.liquidated_subaccount_id == 2));
+    assert_eq!(shard.risk.position_size(3, 1), 23);
+    assert_eq!(shard.risk.position_size(1, 1), 21);
+    assert_eq!(shard.risk.position_size(4, 1), 0);
+}
+
+#[test]
+fn adl_ranking_follows_fills_and_price_updates() {
+    let mut shard = new_shard();
+    for (subaccount_id, collateral) in [(1, 500), (2, 1_000), (3, 1_000), (4, 2_000)] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = collateral;
+    }
+    for (maker, taker) in [(2, 1), (4, 3)] {
+        let ask = order(&format!("ask-{maker}"), maker, Side::Sell, TimeInForce::Gtc, 10);
+        shard.handle_event(Event::NewOrder(ask), 2).unwrap();
+        let bid = order(&format!("bid-{taker}"), taker, Side::Buy, TimeInForce::Ioc, 10);
+        shard.handle_event(Event::NewOrder(bid), 2).unwrap();
+    }
+    let ranked = |shard: &EngineShard, side| -> Vec<_> {
+        shard.adl_ranking(1, side, 0).iter().map(|entry| (entry.subaccount_id, entry.score)).collect()
+    };
+    // Flat PnL scores zero; ties go to the lower subaccount.
+    assert_eq!(ranked(&shard, Side::Buy), vec![(1, 0), (3, 0)]);
+
+    let update = PriceUpdate {
+        market_id: 1,
+        mark_price: 110,
+        index_price: 110,
+        ts: 3,
+        source: String::new(),
+    };
+    shard.handle_event(Event::PriceUpdate(update), 3).unwrap();
+    // Both longs are up 10%; subaccount 1 is at 1.83x leverage, subaccount 3 at 1x. The shorts
+    // lose 10%, divided by their leverage.
+    assert_eq!(ranked(&shard, Side::Buy), vec![(1, 1_833), (3, 1_000)]);
+    assert_eq!(ranked(&shard, Side::Sell), vec![(2, -818), (4, -1_727)]);
+    assert_eq!(shard.adl_ranking(1, Side::Buy, 1).len(), 1);
+    let (side, entry) = shard.adl_rank(1, 4).unwrap();
+    assert_eq!((side, entry.rank), (Side::Sell, 2));
+}
+
+#[test]
+fn scheduled_funding_settles_positions_each_interval() {
+    let mut shard = new_shard();
+    let funded = MarketConfig {
+        funding: Some(FundingConfig {
+            interval_secs: 100,
+            max_rate_bps: 100,
+            interest_rate_bps: 1,
+        }),
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(funded, 1).unwrap();
+    for subaccount_id in [1, 2] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = 1_000;
+    }
+    shard.handle_event(Event::NewOrder(order("ask", 2, Side::Sell, TimeInForce::Gtc, 10)), 2).unwrap();
+    shard.handle_event(Event::NewOrder(order("bid", 1, Side::Buy, TimeInForce::Ioc, 10)), 2).unwrap();
+    let prices = |ts| PriceUpdate {
+        market_id: 1,
+        mark_price: 101,
+        index_price: 100,
+        ts,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(prices(10)), 10).unwrap();
+    assert!(!outputs.iter().any(|env| matches!(env.event, Event::FundingRate(_))));
+
+    // Two periods (100 and 200) elapsed by 250. A 1% premium plus 1bp of interest is capped at
+    // 100bp of 101 ticks per period: 20.2 ticks on 10 lots, which the long pays rounded up.
+    let outputs = shard.handle_event(Event::PriceUpdate(prices(250)), 250).unwrap();
+    let rates: Vec<_> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::FundingRate(rate) => Some((rate.rate_bps, rate.periods, rate.funding_index)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rates, vec![(100, 2, 20_200)]);
+    assert_eq!(shard.risk.state.subaccounts[&1].collateral, 979);
+    assert_eq!(shard.risk.state.subaccounts[&2].collateral, 1_020);
+    let payments: Vec<_> = outputs
+        .iter()
+        .filter_map(|env| match &env.event {
+            Event::FundingPayment(payment) => Some((
+                payment.subaccount_id,
+                payment.position_size,
+                payment.from_index,
+                payment.to_index,
+                payment.amount,
+            )),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(payments, vec![(1, 10, 0, 20_200, 21), (2, -10, 0, 20_200, -20)]);
+
+    // Nothing more is owed until the next boundary.
+    shard.handle_event(Event::PriceUpdate(prices(299)), 299).unwrap();
+    assert_eq!(shard.risk.state.subaccounts[&1].collateral, 979);
+}
+
+#[test]
+fn oracle_markets_price_off_the_filtered_composite() {
+    let mut shard = new_shard();
+    let oracle = OracleConfig {
+        sources: ["a", "b", "c"]
+            .into_iter()
+            .map(|name| OracleSource {
+                name: name.to_string(),
+                weight: 1,
+            })
+            .collect(),
+        max_staleness_secs: 30,
+        max_deviation_bps: 500,
+        min_sources: 2,
+    };
+    let config = MarketConfig {
+        oracle: Some(oracle),
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(config, 1).unwrap();
+    let quote = |source: &str, price| PriceUpdate {
+        market_id: 1,
+        mark_price: price,
+        index_price: price,
+        ts: 0,
+        source: source.to_string(),
+    };
+    let index = |outputs: &[EventEnvelope]| {
+        outputs.iter().find_map(|env| match &env.event {
+            Event::IndexPrice(index) => Some(index.clone()),
+            _ => None,
+        })
+    };
+
+    // Unknown sources are ignored, and one source alone is not enough.
+    assert!(shard.handle_event(Event::PriceUpdate(quote("rogue", 500)), 2).unwrap().is_empty());
+    assert!(shard.handle_event(Event::PriceUpdate(quote("a", 100)), 2).unwrap().is_empty());
+    assert_eq!(shard.risk.state.mark_prices.get(&1), Some(&100));
+
+    let composite = index(&shard.handle_event(Event::PriceUpdate(quote("b", 102)), 3).unwrap()).unwrap();
+    assert_eq!((composite.mark_price, composite.index_price), (100, 100));
+    assert_eq!(composite.sources, vec!["a", "b"]);
+
+    // c is more than 5% away from the median and does not move the price.
+    let composite = index(&shard.handle_event(Event::PriceUpdate(quote("c", 150)), 4).unwrap()).unwrap();
+    assert_eq!(composite.outliers, vec!["c"]);
+    assert_eq!(composite.index_price, 100);
+
+    // By 40 both a and c have gone stale, and b alone is not enough.
+    let outputs = shard.handle_event(Event::PriceUpdate(quote("b", 103)), 40).unwrap();
+    assert!(index(&outputs).is_none());
+}
+
+#[test]
+fn price_band_follows_realized_volatility() {
+    let mut shard = new_shard();
+    let config = MarketConfig {
+        dynamic_band: Some(DynamicBandConfig {
+            window: 4,
+            multiplier: 4,
+            min_band_bps: 100,
+            max_band_bps: 2_000,
+        }),
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(config, 1).unwrap();
+    let mark = |mark_price| PriceUpdate {
+        market_id: 1,
+        mark_price,
+        index_price: mark_price,
+        ts: 0,
+        source: String::new(),
+    };
+    let place = |shard: &mut EngineShard, request_id: &str, price_ticks, ts| {
+        let order = NewOrder {
+            price_ticks,
+            ..order(request_id, 1, Side::Sell, TimeInForce::Gtc, 1)
+        };
+        let outputs = shard.handle_event(Event::NewOrder(order), ts).unwrap();
+        outputs.iter().find_map(|env| match &env.event {
+            Event::OrderAck(ack) => Some(ack.reject_code),
+            _ => None,
+        })
+    };
+
+    // A flat mark narrows the band to its 1% floor.
+    shard.handle_event(Event::PriceUpdate(mark(100)), 2).unwrap();
+    shard.handle_event(Event::PriceUpdate(mark(100)), 3).unwrap();
+    assert_eq!(place(&mut shard, "calm", 105, 4), Some(Some(RejectReason::PriceBand)));
+
+    // A 10% jump widens it, up to the 20% ceiling.
+    shard.handle_event(Event::PriceUpdate(mark(110)), 5).unwrap();
+    assert_eq!(place(&mut shard, "fast", 125, 6), Some(None));
+    assert_eq!(place(&mut shard, "far", 140, 7), Some(Some(RejectReason::PriceBand)));
+}
+
+#[test]
+fn initial_margin_is_rescaled_from_volatility_on_schedule() {
+    let mut shard = new_shard();
+    let config = MarketConfig {
+        dynamic_margin: Some(DynamicMarginConfig {
+            window: 4,
+            interval_secs: 100,
+            multiplier: 2,
+            min_margin_bps: 100,
+            max_margin_bps: 5_000,
+        }),
+        ..market_config(MatchingMode::Continuous)
+    };
+    shard.upsert_market(config, 1).unwrap();
+    shard.risk.ensure_subaccount(1).collateral = 100;
+    let mark = |mark_price| PriceUpdate {
+        market_id: 1,
+        mark_price,
+        index_price: mark_price,
+        ts: 0,
+        source: String::new(),
+    };
+    let buy = |shard: &mut EngineShard, request_id: &str, ts| {
+        let order = order(request_id, 1, Side::Buy, TimeInForce::Ioc, 10);
+        let outputs = shard.handle_event(Event::NewOrder(order), ts).unwrap();
+        outputs.iter().find_map(|env| match &env.event {
+            Event::OrderAck(ack) => Some(ack.reject_code),
+            _ => None,
+        })
+    };
+
+    shard.handle_event(Event::PriceUpdate(mark(100)), 10).unwrap();
+    shard.handle_event(Event::PriceUpdate(mark(110)), 20).unwrap();
+    // The 10% move only counts from the next recomputation, at 100.
+    assert_eq!(buy(&mut shard, "before", 30), Some(None));
+
+    let changes = |outputs: Vec<EventEnvelope>| -> Vec<_> {
+        outputs
+            .into_iter()
+            .filter_map(|env| match env.event {
+                Event::RiskParameterChange(change) => Some(change),
+                _ => None,
+            })
+            .collect()
+    };
+    // Recomputed before the input at 150 applies, from the single 1000bp return so far.
+    let rescaled = changes(shard.handle_event(Event::PriceUpdate(mark(110)), 150).unwrap());
+    assert_eq!(rescaled.len(), 1);
+    assert_eq!(rescaled[0].parameter, RiskParameter::InitialMarginBps);
+    assert_eq!((rescaled[0].previous, rescaled[0].value, rescaled[0].volatility_bps), (0, 2_000, Some(1_000)));
+    // 20% of 1000 ticks of notional is more than the 100 of collateral.
+    assert_eq!(buy(&mut shard, "after", 160), Some(Some(RejectReason::InsufficientMargin)));
+    assert!(changes(shard.handle_event(Event::PriceUpdate(mark(110)), 199).unwrap()).is_empty());
+
+    // Returns of 1000bp, 0bp and 0bp by 200: 577bp of volatility, doubled.
+    let rescaled = changes(shard.handle_event(Event::PriceUpdate(mark(110)), 250).unwrap());
+    assert_eq!((rescaled[0].previous, rescaled[0].value), (2_000, 1_154));
+}
+
+#[test]
+fn risk_parameter_updates_apply_as_logged_inputs() {
+    let mut shard = new_shard();
+    shard.risk.ensure_subaccount(1).collateral = 1_000;
+    let buy = |shard: &mut EngineShard, request_id: &str, ts| {
+        let order = order(request_id, 1, Side::Buy, TimeInForce::Ioc, 10);
+        let outputs = shard.handle_event(Event::NewOrder(order), ts).unwrap();
+        outputs.iter().find_map(|env| match &env.event {
+            Event::OrderAck(ack) => Some(ack.reject_code),
+            _ => None,
+        })
+    };
+    let update = |shard: &mut EngineShard, update: RiskParamsUpdate, ts| -> Vec<_> {
+        let event = Event::RiskParamsUpdate(update);
+        assert!(event.is_input());
+        shard
+            .handle_event(event, ts)
+            .unwrap()
+            .into_iter()
+            .filter_map(|env| match env.event {
+                Event::RiskParameterChange(change) => Some((change.parameter, change.previous, change.value, change.volatility_bps)),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(buy(&mut shard, "before", 1), Some(None));
+
+    let changes = update(
+        &mut shard,
+        RiskParamsUpdate {
+            market_id: 1,
+            maker_fee_bps: Some(-1),
+            max_position: Some(5),
+            max_leverage: Some(0),
+            price_band_bps: Some(10_000),
+            ..Default::default()
+        },
+        2,
+    );
+    // Unchanged values are not reported, and a market without its own cap starts at the global one.
+    assert_eq!(
+        changes,
+        vec![(RiskParameter::MakerFeeBps, 0, -1, None), (RiskParameter::MaxPosition, 1_000_000, 5, None)]
+    );
+    assert_eq!(buy(&mut shard, "capped", 3), Some(Some(RejectReason::MaxPosition)));
+
+    // Maintenance margin above initial margin would leave the market invalid: nothing changes.
+    let invalid = RiskParamsUpdate {
+        market_id: 1,
+        maintenance_margin_bps: Some(600),
+        max_position: Some(1_000),
+        ..Default::default()
+    };
+    assert!(update(&mut shard, invalid, 4).is_empty());
+    assert_eq!(buy(&mut shard, "still capped", 5), Some(Some(RejectReason::MaxPosition)));
+    // Markets owned by another shard are left to it.
+    let elsewhere = RiskParamsUpdate {
+        market_id: 2,
+        max_position: Some(1),
+        ..Default::default()
+    };
+    assert!(update(&mut shard, elsewhere, 6).is_empty());
+}
+
+#[test]
+fn provisioned_subaccounts_get_seed_collateral_and_fee_tiers() {
+    let mut shard = new_shard();
+    let provision = |shard: &mut EngineShard, subaccount: SubaccountConfig, ts| {
+        let event = Event::ProvisionSubaccount(subaccount);
+        assert!(event.is_input());
+        assert!(shard.handle_event(event, ts).unwrap().is_empty());
+    };
+    let maker = SubaccountConfig {
+        subaccount_id: 2,
+        // Market 2 is not on this shard, so its seed goes to another ledger.
+        collateral: vec![CollateralSeed { market_id: 1, amount: 10_000 }, CollateralSeed { market_id: 2, amount: 500 }],
+        cross_margin: true,
+        fee_tier: Some(FeeTier { maker_fee_bps: -10, taker_fee_bps: 20 }),
+        public_key: None,
+    };
+    provision(&mut shard, maker.clone(), 1);
+    provision(&mut shard, SubaccountConfig { subaccount_id: 1, collateral: vec![CollateralSeed { market_id: 1, amount: 10_000 }], ..Default::default() }, 2);
+    assert_eq!(shard.risk.state.subaccounts[&2].collateral, 10_000);
+    assert!(shard.risk.state.subaccounts[&2].cross_margin);
+
+    shard.handle_event(Event::NewOrder(order("ask", 2, Side::Sell, TimeInForce::Gtc, 10)), 3).unwrap();
+    let outputs = shard.handle_event(Event::NewOrder(order("bid", 1, Side::Buy, TimeInForce::Ioc, 10)), 4).unwrap();
+    let fill = outputs
+        .iter()
+        .find_map(|env| match &env.event {
+            Event::Fill(fill) => Some(fill.clone()),
+            _ => None,
+        })
+        .unwrap();
+    // The maker pays its tier's rebate on 1000 of notional; the taker the market's zero fee.
+    assert_eq!((fill.maker_fee, fill.taker_fee), (-1, 0));
+    let balance = shard.risk.state.subaccounts[&2].collateral;
+
+    // Provisioning again updates the fee tier but leaves the live balance alone.
+    provision(&mut shard, SubaccountConfig { fee_tier: None, ..maker }, 5);
+    assert_eq!(shard.risk.state.subaccounts[&2].collateral, balance);
+    assert!(shard.fee_tiers.is_empty());
+}
+
+#[test]
+fn fills_are_recorded_in_each_subaccounts_trade_history() {
+    let config = TradeHistoryConfig {
+        dir: String::new(),
+        max_trades_per_subaccount: 10,
+        retention_secs: 0,
+    };
+    let mut shard = new_shard().with_trade_history(TradeStore::in_memory(config));
+    shard.handle_event(Event::NewOrder(order("ask", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    shard.handle_event(Event::NewOrder(order("first", 2, Side::Buy, TimeInForce::Ioc, 3)), 2).unwrap();
+    shard.handle_event(Event::NewOrder(order("second", 2, Side::Buy, TimeInForce::Ioc, 2)), 3).unwrap();
+
+    let (maker, more) = shard.trade_history(1, 0, 0, 0).unwrap();
+    assert!(!more);
+    assert_eq!(maker.len(), 2);
+    assert!(maker.iter().all(|trade| trade.maker && trade.side == Side::Sell));
+
+    let (taker, more) = shard.trade_history(2, 0, 0, 1).unwrap();
+    assert!(more);
+    assert_eq!((taker[0].side, taker[0].qty, taker[0].maker), (Side::Buy, 3, false));
+    // Resuming from the first trade returns only the second.
+    let (rest, more) = shard.trade_history(2, taker[0].ts, taker[0].engine_seq, 0).unwrap();
+    assert!(!more);
+    assert_eq!(rest.iter().map(|trade| trade.qty).collect::<Vec<_>>(), vec![2]);
+    assert!(shard.trade_history(3, 0, 0, 0).unwrap().0.is_empty());
+}
+
+#[test]
+fn engine_seq_continues_after_a_restart() {
+    let logged = |shard_id, engine_seq| EventEnvelope {
+        shard_id,
+        engine_seq,
+        event: Event::NewOrder(order("logged", 1, Side::Sell, TimeInForce::Gtc, 1)),
+        ts: 1,
+        ts_ns: 1_000_000_000,
+        schema_version: hypermarket_clob::models::SCHEMA_VERSION,
+    };
+    let mut restarted = new_shard();
+    // Records of other shards in the shared log do not count.
+    restarted.recover_engine_seq(&[logged(0, 41), logged(1, 90), logged(0, 42)]);
+    let next = order("next", 1, Side::Sell, TimeInForce::Gtc, 1);
+    let outputs = restarted.handle_event(Event::NewOrder(next), 2).unwrap();
+    assert!(outputs.iter().all(|env| env.engine_seq == 43));
+}
+
+#[test]
+fn fills_get_increasing_trade_ids_that_continue_after_a_restart() {
+    let mut shard = new_shard();
+    shard.handle_event(Event::NewOrder(order("ask", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    let mut ids = Vec::new();
+    for request_id in ["first", "second"] {
+        let outputs = shard.handle_event(Event::NewOrder(order(request_id, 2, Side::Buy, TimeInForce::Ioc, 2)), 2).unwrap();
+        ids.extend(fills(&outputs).iter().map(|fill| fill.trade_id));
+    }
+    // Shard 0 numbers trades from 1.
+    assert_eq!(ids, vec![1, 2]);
+
+    let journaled = |shard_id, trade_id| EventEnvelope {
+        shard_id,
+        engine_seq: 1,
+        event: Event::Fill(hypermarket_clob::models::Fill {
+            market_id: 1,
+            maker_order_id: 1,
+            taker_order_id: 2,
+            price_ticks: 100,
+            qty: 1,
+            maker_fee: 0,
+            taker_fee: 0,
+            engine_seq: 1,
+            ts: 1,
+            block_trade: false,
+            trade_id,
+            maker_subaccount_id: 1,
+            taker_subaccount_id: 2,
+            maker_remaining_qty: 0,
+            taker_remaining_qty: 0,
+        }),
+        ts: 1,
+        ts_ns: 1_000_000_000,
+        schema_version: hypermarket_clob::models::SCHEMA_VERSION,
+    };
+    let mut restarted = new_shard();
+    // Fills of other shards in the shared journal do not count.
+    restarted.recover_trade_id(&[journaled(0, 7), journaled(1, 1 << 48)]);
+    restarted.handle_event(Event::NewOrder(order("ask", 1, Side::Sell, TimeInForce::Gtc, 5)), 1).unwrap();
+    let outputs = restarted.handle_event(Event::NewOrder(order("bid", 2, Side::Buy, TimeInForce::Ioc, 1)), 2).unwrap();
+    assert_eq!(fills(&outputs)[0].trade_id, 8);
+}
+
+#[test]
+fn maker_compliance_reports_time_weighted_quoting() {
+    let mut shard = new_shard();
+    shard.upsert_market(MarketConfig {
+        maker_obligation: Some(MakerObligation {
+            subaccounts: vec![1],
+            max_spread_ticks: 5,
+            min_qty: 5,
+            min_uptime_bps: 5_000,
+            report_interval_secs: 10,
+        }),
+        ..market_config(MatchingMode::Continuous)
+    }, 0).unwrap();
+    shard.risk.update_mark(1, 100);
+    let quote = |request_id: &str, side, price_ticks| NewOrder {
+        price_ticks,
+        ..order(request_id, 1, side, TimeInForce::Gtc, 5)
+    };
+
+    shard.handle_event(Event::NewOrder(quote("bid", Side::Buy, 99)), 0).unwrap();
+    let ask = updates(&shard.handle_event(Event::NewOrder(quote("ask", Side::Sell, 101)), 1).unwrap())[0].order_id;
+    let cancel = CancelOrder {
+        request_id: "pull".to_string(),
+        market_id: 1,
+        subaccount_id: 1,
+        order_id: Some(ask),
+        nonce_start: None,
+        nonce_end: None,
+    };
+    let early = shard.handle_event(Event::CancelOrder(cancel), 6).unwrap();
+    assert!(!early.iter().any(|env| matches!(env.event, Event::MakerCompliance(_))));
+
+    let tick = PriceUpdate {
+        market_id: 1,
+        mark_price: 100,
+        index_price: 100,
+        ts: 10,
+        source: String::new(),
+    };
+    let outputs = shard.handle_event(Event::PriceUpdate(tick), 10).unwrap();
+    let report = outputs
+        .iter()
+        .find_map(|env| match &env.event {
+            Event::MakerCompliance(report) => Some(report.clone()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!((report.period_start, report.period_end), (0, 10));
+    assert_eq!(report.quoted_secs, 5);
+    assert_eq!(report.compliant_secs, 5);
+    assert_eq!(report.uptime_bps, 5_000);
+    assert_eq!(report.avg_spread_ticks, 2);
+    assert!(report.compliant);
+}
+
+#[test]
+fn tick_size_change_cancels_off_tick_orders() {
+    let mut shard = new_shard();
+    let priced = |request_id: &str, subaccount_id, price_ticks| NewOrder {
+        price_ticks,
+        ..order(request_id, subaccount_id, Side::Sell, TimeInForce::Gtc, 2)
+    };
+    let on_tick = updates(&shard.handle_event(Event::NewOrder(priced("on", 1, 100)), 1).unwrap())[0].order_id;
+    let off_tick = updates(&shard.handle_event(Event::NewOrder(priced("off", 2, 105)), 2).unwrap())[0].order_id;
+
+    let outputs = shard
+        .upsert_market(MarketConfig { tick_size: 10, ..market_config(MatchingMode::Continuous) }, 3)
+        .unwrap();
+    let cancelled = updates(&outputs);
+    assert_eq!(cancelled.len(), 1);
+    assert_eq!(cancelled[0].order_id, off_tick);
+    assert_eq!(cancelled[0].status, OrderUpdateStatus::Cancelled);
+    assert!(outputs.iter().any(|env| matches!(env.event, Event::BookDelta(_))));
+    let resting = &shard.snapshot().markets[&1].orders;
+    assert_eq!(resting.len(), 1);
+    assert_eq!(resting[0].order_id, on_tick);
+
+    shard.risk.update_mark(1, 100);
+    let rejected = shard.handle_event(Event::NewOrder(priced("late", 3, 103)), 4).unwrap();
+    assert!(rejected.iter().any(|env| matches!(
+        &env.event,
+        Event::OrderAck(ack) if ack.reject_code == Some(RejectReason::InvalidOrder)
+    )));
+}
+
+#[test]
+fn tick_size_changes_are_replayed_from_the_log() {
+    let risk = || RiskEngine::new(RiskConfig { max_slippage_bps: 50, max_leverage: 0 });
+    let wal_path = std::env::temp_dir().join(format!("order_updates_tick_replay_{}.wal", std::process::id()));
+    let _ = std::fs::remove_file(&wal_path);
+    let mut shard = EngineShard::new(0, vec![market_config(MatchingMode::Continuous)], Wal::open(&wal_path).unwrap(), risk());
+    let mark = PriceUpdate { market_id: 1, mark_price: 100, index_price: 100, ts: 1, source: String::new() };
+    shard.handle_event(Event::PriceUpdate(mark), 1).unwrap();
+    for (request_id, subaccount_id, price_ticks) in [("on", 1, 100), ("off", 2, 105)] {
+        let order = NewOrder {
+            price_ticks,
+            ..order(request_id, subaccount_id, Side::Sell, TimeInForce::Gtc, 2)
+        };
+        shard.handle_event(Event::NewOrder(order), 2).unwrap();
+    }
+    let cancelled = updates(&shard.upsert_market(MarketConfig { tick_size: 10, ..market_config(MatchingMode::Continuous) }, 3).unwrap());
+    assert_eq!(cancelled.len(), 1);
+
+    // The update is an input like any other: replaying the log cancels the same order again.
+    let logged = Wal::load(&wal_path).unwrap();
+    let _ = std::fs::remove_file(&wal_path);
+    assert!(logged.iter().any(|env| matches!(&env.event, Event::MarketUpdate(market) if market.tick_size == 10)));
+    let mut replayed = EngineShard::new(0, vec![market_config(MatchingMode::Continuous)], MemoryLog::new(), risk());
+    let mut outputs = Vec::new();
+    for env in logged {
+        outputs.extend(replayed.handle_event(env.event, env.ts).unwrap());
+    }
+    let recancelled = updates(&outputs)
+        .into_iter()
+        .filter(|update| update.status == OrderUpdateStatus::Cancelled)
+        .collect::<Vec<_>>();
+    assert_eq!(recancelled.len(), 1);
+    assert_eq!(recancelled[0].order_id, cancelled[0].order_id);
+    let ids = |shard: &EngineShard| shard.snapshot().markets[&1].orders.iter().map(|order| order.order_id).collect::<Vec<_>>();
+    assert_eq!(ids(&replayed), ids(&shard));
+    assert_eq!(ids(&replayed).len(), 1);
+}
+
+#[test]
+fn wal_logs_inputs_and_journal_logs_outputs() {
+    let dir = std::env::temp_dir().join(format!("order_updates_journal_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let wal_path = dir.join("engine.wal");
+    let journal_path = dir.join("outputs.journal");
+    let _ = std::fs::remove_file(&wal_path);
+    let _ = std::fs::remove_file(&journal_path);
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let mut shard = EngineShard::new(0, vec![market_config(MatchingMode::Continuous)], Wal::open(&wal_path).unwrap(), risk)
+        .with_journal(Wal::open(&journal_path).unwrap());
+    shard.risk.update_mark(1, 100);
+
+    let mut outputs = shard.handle_event(Event::NewOrder(order("s", 1, Side::Sell, TimeInForce::Gtc, 2)), 1).unwrap();
+    outputs.extend(shard.handle_event(Event::NewOrder(order("b", 2, Side::Buy, TimeInForce::Gtc, 2)), 2).unwrap());
+
+    let inputs = Wal::load(&wal_path).unwrap();
+    assert_eq!(inputs.len(), 2);
+    assert!(inputs.iter().all(|env| env.event.is_input()));
+    let journal = Wal::load(&journal_path).unwrap();
+    assert_eq!(journal.len(), outputs.len());
+    assert!(journal.iter().zip(&outputs).all(|(logged, output)| logged.engine_seq == output.engine_seq));
+    assert!(journal.iter().all(|env| !env.event.is_input()));
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -1,8 +1,6 @@
-use std::path::PathBuf;
-
 use proptest::prelude::*;
 
-use hypermarket_clob::config::{MarketConfig, MatchingMode};
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode};
 use hypermarket_clob::engine::shard::EngineShard;
 use hypermarket_clob::models::{Event, NewOrder, OrderType, Side, TimeInForce};
 use hypermarket_clob::persistence::wal::Wal;
@@ -22,15 +20,28 @@ fn market() -> MarketConfig {
         max_open_orders_per_subaccount: 0,
         matching_mode: MatchingMode::Continuous,
         batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
     }
 }
 
 proptest! {
     #[test]
     fn determinism_replay(seq in 1u64..100u64) {
-        let wal_path = PathBuf::from(std::env::temp_dir().join("prop.wal"));
+        let wal_path = std::env::temp_dir().join("prop.wal");
         let wal = Wal::open(&wal_path).unwrap();
-        let risk = RiskEngine::new(RiskConfig { max_slippage_bps: 50, max_leverage: 10 });
+        let risk = RiskEngine::new(RiskConfig { max_slippage_bps: 50, max_leverage: 0 });
         let mut shard = EngineShard::new(0, vec![market()], wal, risk);
         for i in 0..seq {
             let order = NewOrder {
@@ -46,6 +57,8 @@ proptest! {
                 expiry_ts: 0,
                 nonce: i,
                 client_ts: 0,
+                trigger: None,
+                signature: bytes::Bytes::new(),
             };
             let _ = shard.handle_event(Event::NewOrder(order), 0);
         }
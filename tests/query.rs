@@ -0,0 +1,117 @@
+#![cfg(feature = "query")]
+
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode, PersistenceConfig};
+use hypermarket_clob::engine::shard::EngineShard;
+use hypermarket_clob::engine::MemoryLog;
+use hypermarket_clob::models::{Event, NewOrder, OrderType, Side, TimeInForce};
+use hypermarket_clob::persistence::snapshot::SnapshotStore;
+use hypermarket_clob::query::SnapshotView;
+use hypermarket_clob::risk::{RiskConfig, RiskEngine};
+
+fn market() -> MarketConfig {
+    MarketConfig {
+        market_id: 1,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 1,
+        maintenance_margin_bps: 1,
+        max_position: 1000,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode: MatchingMode::Continuous,
+        batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    }
+}
+
+fn order(request_id: &str, subaccount_id: u64, side: Side, price_ticks: u64, qty: u64) -> Event {
+    Event::NewOrder(NewOrder {
+        request_id: request_id.to_string(),
+        market_id: 1,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks,
+        qty,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 0,
+        client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
+    })
+}
+
+#[test]
+fn snapshot_view_answers_depth_orders_and_balances() {
+    let dir = std::env::temp_dir().join(format!(
+        "query_{:x}",
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos()
+    ));
+    let persistence = PersistenceConfig {
+        wal_path: String::new(),
+        journal_path: None,
+        snapshot_path: String::new(),
+        snapshot_dir: Some(dir.join("{shard}").to_string_lossy().into_owned()),
+        retention: Default::default(),
+        trade_history: None,
+        archive: None,
+    };
+
+    let risk = RiskEngine::new(RiskConfig { max_slippage_bps: 10_000, max_leverage: 0 });
+    let mut shard = EngineShard::new(0, vec![market()], MemoryLog::new(), risk);
+    shard.risk.update_mark(1, 100);
+    for subaccount_id in [1, 2, 3] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = 10_000;
+    }
+    for (ts, event) in [
+        order("b1", 1, Side::Buy, 99, 2),
+        order("b2", 2, Side::Buy, 99, 3),
+        order("b3", 1, Side::Buy, 98, 1),
+        order("a1", 2, Side::Sell, 101, 4),
+        order("a2", 2, Side::Sell, 102, 5),
+        order("take", 3, Side::Sell, 99, 1),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        shard.handle_event(event, ts as u64 + 1).unwrap();
+    }
+    let snapshot = SnapshotStore::build(0, 6, shard.snapshot());
+    SnapshotStore::save_to_dir(&persistence.snapshot_dir(0).unwrap(), &snapshot).unwrap();
+
+    let (view, errors) = SnapshotView::default().reload(&persistence, 2);
+    assert!(errors.is_empty());
+    let depth = view.depth(1, 1).unwrap();
+    assert_eq!((depth.shard_id, depth.last_seq, depth.mark_price), (0, 6, Some(100)));
+    // The sell took one lot from subaccount 1's bid at 99, first in time priority.
+    let levels = |levels: &[hypermarket_clob::models::BookLevel]| levels.iter().map(|level| (level.price_ticks, level.qty)).collect::<Vec<_>>();
+    assert_eq!(levels(&depth.bids), vec![(99, 4)]);
+    assert_eq!(levels(&depth.asks), vec![(101, 4)]);
+    assert_eq!(levels(&view.depth(1, 10).unwrap().bids), vec![(99, 4), (98, 1)]);
+    assert!(view.depth(2, 10).is_none());
+
+    let mut orders: Vec<_> = view.open_orders(1).iter().map(|open| (open.order.price_ticks, open.order.remaining)).collect();
+    orders.sort();
+    assert_eq!(orders, vec![(98, 1), (99, 1)]);
+    let balances = view.balances(1);
+    assert_eq!(balances.len(), 1);
+    assert_eq!((balances[0].shard_id, balances[0].positions[&1].size), (0, 1));
+    assert_eq!(view.balances(3)[0].positions[&1].size, -1);
+    assert!(view.balances(4).is_empty());
+}
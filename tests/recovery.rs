@@ -0,0 +1,199 @@
+use bytes::Bytes;
+
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode, WireCodec};
+use hypermarket_clob::engine::recovery::StreamRecovery;
+use hypermarket_clob::engine::router::encode_input_with;
+use hypermarket_clob::engine::shard::EngineShard;
+use hypermarket_clob::engine::MemoryLog;
+use hypermarket_clob::models::{
+    CancelOrder, Event, MassCancel, MigrateMarket, NewOrder, OrderTrigger, OrderType, Side, TimeInForce, TriggerSource,
+};
+use hypermarket_clob::risk::{RiskConfig, RiskEngine};
+
+fn market(market_id: u64) -> MarketConfig {
+    MarketConfig {
+        market_id,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 1,
+        maintenance_margin_bps: 0,
+        max_position: 1000,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode: MatchingMode::Continuous,
+        batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    }
+}
+
+fn order(request_id: &str, market_id: u64, subaccount_id: u64, side: Side, price_ticks: u64, qty: u64) -> Event {
+    Event::NewOrder(NewOrder {
+        request_id: request_id.to_string(),
+        market_id,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks,
+        qty,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 0,
+        client_ts: 0,
+        trigger: None,
+        signature: Bytes::new(),
+    })
+}
+
+fn risk() -> RiskEngine {
+    RiskEngine::new(RiskConfig { max_slippage_bps: 10_000, max_leverage: 0 })
+}
+
+fn shard(shard_id: usize, market_id: u64) -> EngineShard {
+    let mut shard = EngineShard::new(shard_id, vec![market(market_id)], MemoryLog::new(), risk());
+    shard.risk.update_mark(market_id, 100);
+    for subaccount_id in [1, 2] {
+        shard.risk.ensure_subaccount(subaccount_id).collateral = 100_000;
+    }
+    shard
+}
+
+fn state_bytes(shard: &EngineShard) -> Vec<u8> {
+    bincode::serialize(&shard.snapshot()).unwrap()
+}
+
+#[test]
+fn stream_recovery_matches_the_shard_that_handled_every_input() {
+    let inputs = [
+        order("b1", 1, 1, Side::Buy, 99, 5),
+        order("a1", 1, 2, Side::Sell, 101, 5),
+        order("take", 1, 2, Side::Sell, 99, 2),
+        Event::CancelOrder(CancelOrder {
+            request_id: "c1".to_string(),
+            market_id: 1,
+            subaccount_id: 2,
+            order_id: None,
+            nonce_start: Some(0),
+            nonce_end: Some(0),
+        }),
+        Event::MassCancel(MassCancel {
+            request_id: "m1".to_string(),
+            parent_id: 0,
+            market_id: None,
+        }),
+        order("b2", 1, 1, Side::Buy, 98, 1),
+    ];
+    let mut reference = shard(0, 1);
+    for (ts, event) in inputs.iter().enumerate() {
+        reference.handle_event(event.clone(), ts as u64 + 1).unwrap();
+    }
+
+    // The snapshot covers the first two inputs, stored at stream sequences 10 and 11; the stream
+    // also holds an undecodable payload and redelivers one record.
+    let mut restored = shard(0, 1);
+    for (ts, event) in inputs[..2].iter().enumerate() {
+        restored.handle_event(event.clone(), ts as u64 + 1).unwrap();
+    }
+    let restored = EngineShard::restore(restored.snapshot(), vec![market(1)], MemoryLog::new(), risk()).unwrap();
+    let mut recovery = StreamRecovery::new([(0, restored)].into(), 1, WireCodec::Protobuf, 11);
+    recovery.apply(11, 2_000_000_000, encode_input_with(WireCodec::Protobuf, inputs[1].clone()).unwrap()).unwrap();
+    recovery.apply(12, 3_000_000_000, encode_input_with(WireCodec::Protobuf, inputs[2].clone()).unwrap()).unwrap();
+    recovery.apply(13, 3_000_000_000, Bytes::from_static(b"\xff\xff")).unwrap();
+    recovery.apply(12, 3_000_000_000, encode_input_with(WireCodec::Protobuf, inputs[2].clone()).unwrap()).unwrap();
+    for (offset, event) in inputs[3..].iter().enumerate() {
+        let ts = offset as u64 + 4;
+        recovery.apply(ts + 10, ts * 1_000_000_000, encode_input_with(WireCodec::Protobuf, event.clone()).unwrap()).unwrap();
+    }
+
+    assert_eq!((recovery.last_stream_seq(), recovery.applied(), recovery.skipped()), (16, 4, 1));
+    let shards = recovery.into_shards();
+    assert_eq!(shards[&0].engine_seq, reference.engine_seq);
+    assert_eq!(state_bytes(&shards[&0]), state_bytes(&reference));
+}
+
+#[test]
+fn stream_recovery_routes_by_market_and_stops_at_migrations() {
+    // Market 1 lives on shard 1 of two; only shard 1 is being rebuilt.
+    let mut recovery = StreamRecovery::new([(1, shard(1, 1))].into(), 2, WireCodec::Json, 0);
+    recovery.apply(1, 1_000_000_000, encode_input_with(WireCodec::Json, order("b1", 1, 1, Side::Buy, 99, 5)).unwrap()).unwrap();
+    recovery.apply(2, 1_000_000_000, encode_input_with(WireCodec::Json, order("b2", 2, 1, Side::Buy, 99, 5)).unwrap()).unwrap();
+    assert_eq!(recovery.applied(), 2);
+    assert_eq!(recovery.shards()[&1].snapshot().markets[&1].orders.len(), 1);
+
+    let migrate = Event::MigrateMarket(MigrateMarket {
+        request_id: "mig".to_string(),
+        market_id: 1,
+        target_shard: 0,
+    });
+    let err = recovery.apply(3, 2_000_000_000, encode_input_with(WireCodec::Json, migrate).unwrap()).unwrap_err();
+    assert!(err.to_string().contains("migrates market 1"));
+}
+
+#[test]
+fn restored_shards_replay_the_log_after_their_snapshot_without_logging_it_again() {
+    let log = MemoryLog::new();
+    let mut reference = EngineShard::new(0, vec![market(1)], log.clone(), risk());
+    reference.risk.update_mark(1, 100);
+    for subaccount_id in [1, 2] {
+        reference.risk.ensure_subaccount(subaccount_id).collateral = 100_000;
+    }
+    reference.handle_event(order("b1", 1, 1, Side::Buy, 99, 5), 1).unwrap();
+    reference.handle_event(order("a1", 1, 2, Side::Sell, 101, 5), 2).unwrap();
+    let state = reference.snapshot();
+
+    let mut stop = order("stop", 1, 1, Side::Buy, 101, 2);
+    if let Event::NewOrder(stop) = &mut stop {
+        stop.trigger = Some(OrderTrigger {
+            trigger_price: 101,
+            source: TriggerSource::LastTrade,
+        });
+    }
+    let inputs = [stop, order("take", 1, 2, Side::Sell, 99, 2), order("lift", 1, 1, Side::Buy, 101, 1)];
+    for (ts, event) in inputs.into_iter().enumerate() {
+        reference.handle_event(event, ts as u64 + 3).unwrap();
+    }
+    // The lift trades at 101 and fires the stop.
+    assert_eq!(reference.risk.position_size(1, 1), 5);
+
+    let relogged = MemoryLog::new();
+    let mut restored = EngineShard::restore(state, vec![market(1)], relogged.clone(), risk()).unwrap();
+    restored.replay_log(&log.events());
+    assert_eq!(restored.engine_seq, reference.engine_seq);
+    assert_eq!(state_bytes(&restored), state_bytes(&reference));
+    assert!(relogged.events().is_empty());
+}
+
+#[test]
+fn replayed_outputs_carry_the_nanosecond_stamp_of_their_logged_input() {
+    let log = MemoryLog::new();
+    let mut reference = EngineShard::new(0, vec![market(1)], log.clone(), risk());
+    reference.risk.update_mark(1, 100);
+    reference.handle_event_at(order("a1", 1, 2, Side::Sell, 100, 5), 7_000_000_001).unwrap();
+    let outputs = reference.handle_event_at(order("b1", 1, 1, Side::Buy, 100, 2), 7_250_000_000).unwrap();
+    assert!(outputs.iter().all(|output| (output.ts, output.ts_ns) == (7, 7_250_000_000)));
+    let logged = log.events();
+    let stamps: Vec<_> = logged.iter().map(|envelope| (envelope.ts, envelope.ts_ns)).collect();
+    assert_eq!(stamps, vec![(7, 7_000_000_001), (7, 7_250_000_000)]);
+
+    let mut replayed = EngineShard::new(0, vec![market(1)], MemoryLog::new(), risk());
+    replayed.risk.update_mark(1, 100);
+    let mut outputs_again = Vec::new();
+    for envelope in logged {
+        outputs_again = replayed.handle_event_at(envelope.event, envelope.ts_ns).unwrap();
+    }
+    assert_eq!(bincode::serialize(&outputs_again).unwrap(), bincode::serialize(&outputs).unwrap());
+}
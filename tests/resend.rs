@@ -0,0 +1,259 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use hypermarket_clob::bus::memory::InMemoryBus;
+use hypermarket_clob::bus::Bus;
+use hypermarket_clob::config::{Settings, WireCodec};
+use hypermarket_clob::engine::adl;
+use hypermarket_clob::engine::resend::{self, GapDetector, SequenceCheck};
+use hypermarket_clob::engine::router::{decode_output_sequence, decode_output_with, encode_input, run_router};
+use hypermarket_clob::models::{pb, Event, NewOrder, OrderType, Side, TimeInForce};
+
+fn settings(dir: &std::path::Path, extra: &str) -> Settings {
+    let yaml = format!(
+        r#"
+bus:
+  nats_url: "nats://127.0.0.1:1"
+  input_subject: "in"
+  output_subject: "out"
+  durable_name: "test"
+  resend_subject: "resend"
+  adl_subject: "adl"
+  trades_subject: "trades"
+{extra}
+persistence:
+  wal_path: "{wal}"
+  snapshot_path: "{snapshot}"
+snapshot_interval_secs: 30
+book_delta_levels: 10
+"#,
+        wal = dir.join("engine.wal").display(),
+        snapshot = dir.join("snapshot.bin").display(),
+    );
+    let path = dir.join("config.yaml");
+    std::fs::write(&path, yaml).unwrap();
+    Settings::load(path.to_str().unwrap()).unwrap()
+}
+
+fn order(request_id: &str, market_id: u64) -> Bytes {
+    encode_input(Event::NewOrder(NewOrder {
+        request_id: request_id.to_string(),
+        market_id,
+        subaccount_id: 1,
+        side: Side::Buy,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks: 100,
+        qty: 1,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 0,
+        client_ts: 0,
+        trigger: None,
+        signature: Bytes::new(),
+    }))
+    .unwrap()
+}
+
+async fn wait_for(bus: &InMemoryBus, subject: &str, count: usize) -> Vec<Bytes> {
+    for _ in 0..100 {
+        let published = bus.published(subject);
+        if published.len() >= count {
+            return published;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("expected {count} payloads on {subject}");
+}
+
+async fn request(bus: &InMemoryBus, shard_id: u64, from_seq: u64, to_seq: u64) {
+    let request = pb::ResendRequest {
+        request_id: format!("resend-{shard_id}-{from_seq}-{to_seq}"),
+        shard_id,
+        from_seq,
+        to_seq,
+        reply_subject: "replay".to_string(),
+    };
+    let payload = resend::encode_request(WireCodec::Protobuf, &request).unwrap();
+    bus.publish("resend", payload).await.unwrap();
+}
+
+fn completion(payload: &Bytes) -> hypermarket_clob::models::ResendComplete {
+    match decode_output_with(WireCodec::Protobuf, payload.clone()).unwrap() {
+        Event::ResendComplete(complete) => complete,
+        other => panic!("expected a resend completion, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn missed_outputs_are_resent_from_the_shard_history() {
+    let dir = std::env::temp_dir().join(format!("resend_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bus = Arc::new(InMemoryBus::new());
+    tokio::spawn(run_router(settings(&dir, "shard_count: 1"), bus.clone()));
+
+    // The router subscribes asynchronously; keep sending until an output appears.
+    for attempt in 0..100 {
+        bus.publish("in", order(&format!("o-{attempt}"), 1)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if !bus.published("out").is_empty() {
+            break;
+        }
+    }
+    bus.publish("in", order("last", 1)).await.unwrap();
+    let outputs = wait_for(&bus, "out", 2).await;
+
+    // Pretend the first output was lost.
+    let mut detector = GapDetector::new();
+    let first = decode_output_sequence(WireCodec::Protobuf, outputs[0].clone()).unwrap();
+    let second = decode_output_sequence(WireCodec::Protobuf, outputs[1].clone()).unwrap();
+    assert_eq!((first.output_seq, second.output_seq), (1, 2));
+    assert_eq!(first.run_id, second.run_id);
+    assert_eq!(detector.observe(second), SequenceCheck::Gap { from: 1, to: 1 });
+
+    request(&bus, 0, 1, 1).await;
+    let replayed = wait_for(&bus, "replay", 2).await;
+    assert_eq!(replayed[0], outputs[0]);
+    let complete = completion(&replayed[1]);
+    assert_eq!((complete.resent, complete.error), (1, None));
+    let resent = decode_output_sequence(WireCodec::Protobuf, replayed[0].clone()).unwrap();
+    assert_eq!(detector.observe(resent), SequenceCheck::New);
+    assert!(detector.missing(0).is_empty());
+
+    // Ranges the shard cannot serve are answered with an error instead of silence.
+    request(&bus, 0, 1, 1_000_000).await;
+    let replayed = wait_for(&bus, "replay", 3).await;
+    assert!(completion(&replayed[2]).error.unwrap().contains("not yet published"));
+    request(&bus, 5, 1, 1).await;
+    let replayed = wait_for(&bus, "replay", 4).await;
+    assert_eq!(completion(&replayed[3]).error.as_deref(), Some("unknown shard 5"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn global_sequencing_orders_outputs_across_shards() {
+    let dir = std::env::temp_dir().join(format!("global_seq_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bus = Arc::new(InMemoryBus::new());
+    tokio::spawn(run_router(settings(&dir, "shard_count: 2\nglobal_sequencing: true"), bus.clone()));
+
+    for attempt in 0..100 {
+        bus.publish("in", order(&format!("warmup-{attempt}"), 0)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if !bus.published("out").is_empty() {
+            break;
+        }
+    }
+    // Let the last warmup orders drain before counting their acks.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let warmup = bus.published("out").len();
+    // Markets 1 and 2 belong to different shards; every order is rejected with one ack.
+    for index in 0..6 {
+        bus.publish("in", order(&format!("o-{index}"), 1 + index % 2)).await.unwrap();
+    }
+    let outputs = wait_for(&bus, "out", warmup + 6).await;
+
+    let sequences: Vec<_> = outputs
+        .iter()
+        .map(|payload| decode_output_sequence(WireCodec::Protobuf, payload.clone()).unwrap())
+        .collect();
+    let mut global: Vec<u64> = sequences.iter().map(|sequence| sequence.global_seq).collect();
+    global.sort_unstable();
+    assert_eq!(global, (1..=outputs.len() as u64).collect::<Vec<_>>());
+    for shard_id in 0..2 {
+        let ingest: Vec<u64> = sequences
+            .iter()
+            .filter(|sequence| sequence.shard_id == shard_id)
+            .map(|sequence| sequence.ingest_seq)
+            .collect();
+        assert!(ingest.len() >= 3);
+        assert!(ingest.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn market_sequencing_numbers_each_markets_book_outputs() {
+    let dir = std::env::temp_dir().join(format!("market_seq_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bus = Arc::new(InMemoryBus::new());
+    let market = |market_id: u64| {
+        format!(
+            "  - {{market_id: {market_id}, tick_size: 1, lot_size: 1, maker_fee_bps: 0, taker_fee_bps: 0, initial_margin_bps: 0, maintenance_margin_bps: 0, max_position: 100, price_band_bps: 1000000, max_open_orders_per_subaccount: 0, matching_mode: continuous, batch_interval_ms: 0}}\n"
+        )
+    };
+    let extra = format!("shard_count: 2\nmarket_sequencing: true\ncoalesce_book_deltas: false\nmarkets:\n{}{}", market(1), market(2));
+    tokio::spawn(run_router(settings(&dir, &extra), bus.clone()));
+
+    // Market 3 is not listed, so warmup orders only produce rejects.
+    for attempt in 0..100 {
+        bus.publish("in", order(&format!("warmup-{attempt}"), 3)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if !bus.published("out").is_empty() {
+            break;
+        }
+    }
+    // Each resting order acks and moves its market's book; markets 1 and 2 are on different shards.
+    for index in 0..6 {
+        bus.publish("in", order(&format!("o-{index}"), 1 + index % 2)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let mut by_market: [Vec<u64>; 2] = Default::default();
+    for _ in 0..100 {
+        by_market = Default::default();
+        for payload in bus.published("out") {
+            let sequence = decode_output_sequence(WireCodec::Protobuf, payload.clone()).unwrap();
+            match decode_output_with(WireCodec::Protobuf, payload) {
+                Ok(Event::BookDelta(delta)) => by_market[delta.market_id as usize - 1].push(sequence.market_seq),
+                _ => assert_eq!(sequence.market_seq, 0),
+            }
+        }
+        if by_market.iter().all(|seqs| seqs.len() >= 3) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    for seqs in &by_market {
+        assert_eq!(*seqs, (1..=seqs.len() as u64).collect::<Vec<_>>());
+        assert!(seqs.len() >= 3);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn adl_ranking_queries_are_answered_on_the_reply_subject() {
+    let dir = std::env::temp_dir().join(format!("adl_query_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bus = Arc::new(InMemoryBus::new());
+    tokio::spawn(run_router(settings(&dir, "shard_count: 1"), bus.clone()));
+
+    let request = pb::AdlRankingRequest {
+        request_id: "adl-1".to_string(),
+        market_id: 7,
+        side: "BUY".to_string(),
+        subaccount_id: 0,
+        limit: 10,
+        reply_subject: "adl-reply".to_string(),
+    };
+    let payload = adl::encode_request(WireCodec::Protobuf, &request).unwrap();
+    // The router subscribes asynchronously; keep asking until it answers.
+    for _ in 0..100 {
+        bus.publish("adl", payload.clone()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        if !bus.published("adl-reply").is_empty() {
+            break;
+        }
+    }
+    let replies = wait_for(&bus, "adl-reply", 1).await;
+    match decode_output_with(WireCodec::Protobuf, replies[0].clone()).unwrap() {
+        Event::AdlRanking(ranking) => {
+            assert_eq!(ranking.request_id, "adl-1");
+            assert!(ranking.entries.is_empty());
+            assert_eq!(ranking.error.as_deref(), Some("unknown market 7"));
+        }
+        other => panic!("expected an ADL ranking, got {other:?}"),
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
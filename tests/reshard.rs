@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode};
+use hypermarket_clob::engine::reshard::reshard;
+use hypermarket_clob::engine::EngineShard;
+use hypermarket_clob::models::{Event, NewOrder, OrderType, Side, TimeInForce};
+use hypermarket_clob::persistence::wal::Wal;
+use hypermarket_clob::risk::{RiskConfig, RiskEngine};
+
+fn market(market_id: u64) -> MarketConfig {
+    MarketConfig {
+        market_id,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 0,
+        maintenance_margin_bps: 0,
+        max_position: 1_000_000,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode: MatchingMode::Continuous,
+        batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    }
+}
+
+fn shard(shard_id: usize, market_id: u64) -> EngineShard {
+    let path = std::env::temp_dir().join(format!("reshard_{}_{shard_id}.wal", std::process::id()));
+    let mut wal = Wal::open(&path).unwrap();
+    wal.truncate().unwrap();
+    let risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 0,
+    });
+    let mut shard = EngineShard::new(shard_id, vec![market(market_id)], wal, risk);
+    shard.risk.update_mark(market_id, 100);
+    shard
+}
+
+fn order(request_id: &str, market_id: u64, subaccount_id: u64, side: Side, tif: TimeInForce) -> NewOrder {
+    NewOrder {
+        request_id: request_id.to_string(),
+        market_id,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif,
+        price_ticks: 100,
+        qty: 5,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 0,
+        client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
+    }
+}
+
+#[test]
+fn reshard_moves_markets_with_their_orders_positions_and_marks() {
+    let mut even = shard(0, 2);
+    let mut odd = shard(1, 1);
+    for (shard, market_id) in [(&mut even, 2), (&mut odd, 1)] {
+        shard.risk.ensure_subaccount(7).collateral = 1_000;
+        shard.handle_event(Event::NewOrder(order("rest", market_id, 7, Side::Sell, TimeInForce::Gtc)), 1).unwrap();
+        shard.handle_event(Event::NewOrder(order("take", market_id, 8, Side::Buy, TimeInForce::Ioc)), 2).unwrap();
+        shard.handle_event(Event::NewOrder(order("bid", market_id, 9, Side::Buy, TimeInForce::Gtc)), 3).unwrap();
+    }
+    let states = vec![even.snapshot(), odd.snapshot()];
+
+    let assignment = HashMap::from([(1, 2)]);
+    let resharded = reshard(states, 3, &assignment).unwrap();
+    assert_eq!(resharded.len(), 3);
+    assert!(resharded[0].markets.is_empty());
+    assert!(resharded[1].markets.is_empty());
+    assert_eq!(resharded[2].markets[&1].orders.len(), 1);
+    assert_eq!(resharded[2].markets[&2].orders.len(), 1);
+    assert_eq!(resharded[2].markets[&1].mark_price, Some(100));
+
+    let positions = resharded[2].positions(8);
+    assert_eq!((positions[&1].size, positions[&2].size), (5, 5));
+    // Collateral from both old shards is kept whole on the shard that now holds the positions.
+    assert_eq!(resharded[2].risk_state.subaccounts[&7].collateral, 2_000);
+    for state in &resharded {
+        assert!(state.next_order_id > (state.shard_id as u64) << 48);
+        assert!(state.next_order_id < ((state.shard_id as u64 + 1) << 48));
+    }
+    assert!(reshard(Vec::new(), 2, &HashMap::from([(1, 5)])).is_err());
+}
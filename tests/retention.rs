@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use hypermarket_clob::config::SnapshotRetention;
+use hypermarket_clob::models::{ClearingSeed, Event, EventEnvelope, SCHEMA_VERSION};
+use hypermarket_clob::persistence::retention::{compact_wal, select, SnapshotFile};
+use hypermarket_clob::persistence::wal::Wal;
+
+fn snapshot(shard_id: usize, last_seq: u64, taken_at: u64) -> SnapshotFile {
+    SnapshotFile {
+        path: PathBuf::from(format!("snapshot-{shard_id}-{last_seq}.bin")),
+        shard_id,
+        last_seq,
+        taken_at,
+        size: 100,
+    }
+}
+
+#[test]
+fn retention_keeps_last_n_plus_hourly_and_daily_per_shard() {
+    let hour = 3_600;
+    let day = 86_400;
+    let snapshots = vec![
+        snapshot(0, 10, 2 * day),
+        snapshot(0, 20, 3 * day),
+        snapshot(0, 30, 3 * day + hour),
+        snapshot(0, 40, 3 * day + hour + 60),
+        snapshot(0, 50, 3 * day + 2 * hour),
+        snapshot(1, 5, 3 * day),
+    ];
+
+    let only_latest = select(&snapshots, &SnapshotRetention::default());
+    assert_eq!(only_latest.into_iter().collect::<Vec<_>>(), vec![4, 5]);
+
+    let policy = SnapshotRetention {
+        keep_last: 1,
+        keep_hourly: 2,
+        keep_daily: 2,
+    };
+    // Hours: seq 50 and 40 (newest of its hour, beating 30). Days: seq 50 and 10.
+    let kept = select(&snapshots, &policy);
+    assert_eq!(kept.into_iter().collect::<Vec<_>>(), vec![0, 3, 4, 5]);
+}
+
+#[test]
+fn wal_compaction_drops_only_covered_records() {
+    let path = std::env::temp_dir().join(format!("retention_{}.wal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut wal = Wal::open(&path).unwrap();
+    for (shard_id, engine_seq) in [(0, 1), (1, 1), (0, 2), (1, 2), (0, 3), (2, 1)] {
+        wal.append(&EventEnvelope {
+            shard_id,
+            engine_seq,
+            event: Event::ClearingSeed(ClearingSeed { seed: engine_seq }),
+            ts: engine_seq,
+            ts_ns: engine_seq * 1_000_000_000,
+            schema_version: SCHEMA_VERSION,
+        })
+        .unwrap();
+    }
+    drop(wal);
+
+    let covered = BTreeMap::from([(0, 2), (1, 1)]);
+    let reclaimed = compact_wal(&path, &covered).unwrap();
+    assert!(reclaimed > 0);
+    let remaining: Vec<_> = Wal::load(&path)
+        .unwrap()
+        .iter()
+        .map(|env| (env.shard_id, env.engine_seq))
+        .collect();
+    assert_eq!(remaining, vec![(1, 2), (0, 3), (2, 1)]);
+    let _ = std::fs::remove_file(&path);
+}
@@ -1,7 +1,6 @@
-use std::path::PathBuf;
-
-use hypermarket_clob::config::{MarketConfig, MatchingMode};
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode};
 use hypermarket_clob::engine::shard::EngineShard;
+use hypermarket_clob::engine::MemoryLog;
 use hypermarket_clob::models::{Event, NewOrder, OrderType, PriceUpdate, Side, TimeInForce};
 use hypermarket_clob::persistence::wal::Wal;
 use hypermarket_clob::risk::{RiskConfig, RiskEngine};
@@ -20,15 +19,28 @@ fn market(mode: MatchingMode) -> MarketConfig {
         max_open_orders_per_subaccount: 0,
         matching_mode: mode,
         batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
     }
 }
 
 #[test]
 fn oracle_price_jump() {
-    let wal = Wal::open(&PathBuf::from(std::env::temp_dir().join("sim.wal"))).unwrap();
-    let risk = RiskEngine::new(RiskConfig { max_slippage_bps: 50, max_leverage: 10 });
+    let wal = Wal::open(&std::env::temp_dir().join("sim.wal")).unwrap();
+    let risk = RiskEngine::new(RiskConfig { max_slippage_bps: 50, max_leverage: 0 });
     let mut shard = EngineShard::new(0, vec![market(MatchingMode::Continuous)], wal, risk);
-    let update = PriceUpdate { market_id: 1, mark_price: 200, index_price: 200, ts: 1 };
+    let update = PriceUpdate { market_id: 1, mark_price: 200, index_price: 200, ts: 1, source: String::new() };
     let _ = shard.handle_event(Event::PriceUpdate(update), 1);
     let order = NewOrder {
         request_id: "req-1".to_string(),
@@ -43,7 +55,41 @@ fn oracle_price_jump() {
         expiry_ts: 0,
         nonce: 1,
         client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
     };
     let outputs = shard.handle_event(Event::NewOrder(order), 2).unwrap();
     assert!(!outputs.is_empty());
 }
+
+#[test]
+fn shard_runs_on_an_in_memory_log() {
+    let wal = MemoryLog::new();
+    let journal = MemoryLog::new();
+    let risk = RiskEngine::new(RiskConfig { max_slippage_bps: 50, max_leverage: 0 });
+    let mut shard = EngineShard::new(0, vec![market(MatchingMode::Continuous)], wal.clone(), risk).with_journal(journal.clone());
+    let update = PriceUpdate { market_id: 1, mark_price: 200, index_price: 200, ts: 1, source: String::new() };
+    shard.handle_event(Event::PriceUpdate(update), 1).unwrap();
+    let order = NewOrder {
+        request_id: "req-1".to_string(),
+        market_id: 1,
+        subaccount_id: 1,
+        side: Side::Buy,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks: 200,
+        qty: 1,
+        reduce_only: false,
+        expiry_ts: 0,
+        nonce: 1,
+        client_ts: 0,
+        trigger: None,
+        signature: bytes::Bytes::new(),
+    };
+    let outputs = shard.handle_event(Event::NewOrder(order), 2).unwrap();
+
+    assert_eq!(wal.events().iter().map(|input| input.engine_seq).collect::<Vec<_>>(), vec![1, 2]);
+    // The journal holds the outputs of both inputs.
+    assert!(!outputs.is_empty());
+    assert!(journal.events().len() >= outputs.len());
+}
@@ -1,7 +1,7 @@
 use hypermarket_clob::matching::orderbook::{IncomingOrder, OrderBook};
 use hypermarket_clob::models::{OrderType, Side, TimeInForce};
-use hypermarket_clob::risk::{RiskConfig, RiskEngine, RiskError};
-use hypermarket_clob::config::{MarketConfig, MatchingMode};
+use hypermarket_clob::risk::{ProposedOrder, RiskConfig, RiskEngine, RiskError};
+use hypermarket_clob::config::{BookLayout, MarketConfig, MatchingMode};
 
 #[test]
 fn ioc_rejects_rest() {
@@ -83,7 +83,7 @@ fn cancel_by_order_id() {
 fn reduce_only_validation() {
     let mut risk = RiskEngine::new(RiskConfig {
         max_slippage_bps: 50,
-        max_leverage: 10,
+        max_leverage: 0,
     });
     let market = MarketConfig {
         market_id: 1,
@@ -98,6 +98,19 @@ fn reduce_only_validation() {
         max_open_orders_per_subaccount: 0,
         matching_mode: MatchingMode::Continuous,
         batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
     };
     risk.ensure_subaccount(1).positions.insert(
         1,
@@ -110,11 +123,946 @@ fn reduce_only_validation() {
     let result = risk.validate_order(
         &market,
         1,
-        Side::Buy,
-        OrderType::Limit,
-        100,
-        10,
-        true,
+        ProposedOrder {
+            reduce_only: true,
+            ..ProposedOrder::limit(Side::Buy, 100, 10)
+        },
     );
     assert!(matches!(result, Err(RiskError::ReduceOnly)));
 }
+
+#[test]
+fn leverage_is_capped_globally_unless_the_market_overrides_it() {
+    let mut risk = RiskEngine::new(RiskConfig {
+        max_slippage_bps: 50,
+        max_leverage: 5,
+    });
+    let market = MarketConfig {
+        market_id: 1,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 0,
+        maintenance_margin_bps: 0,
+        max_position: 1_000,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode: MatchingMode::Continuous,
+        batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    };
+    risk.update_mark(1, 100);
+    risk.ensure_subaccount(1).collateral = 100;
+    let buy = |risk: &RiskEngine, market: &MarketConfig, qty| risk.validate_order(market, 1, ProposedOrder::limit(Side::Buy, 100, qty));
+
+    // 5x on 100 of equity allows 500 of notional at 100.
+    assert!(buy(&risk, &market, 5).is_ok());
+    assert!(matches!(buy(&risk, &market, 6), Err(RiskError::MaxLeverage)));
+    let tighter = MarketConfig {
+        max_leverage: Some(2),
+        ..market.clone()
+    };
+    assert!(matches!(buy(&risk, &tighter, 3), Err(RiskError::MaxLeverage)));
+    let uncapped = MarketConfig {
+        max_leverage: Some(0),
+        ..market.clone()
+    };
+    assert!(buy(&risk, &uncapped, 50).is_ok());
+
+    // Orders that shrink a position over the cap are still accepted.
+    risk.ensure_subaccount(1).positions.insert(
+        1,
+        hypermarket_clob::risk::Position {
+            size: 10,
+            entry_price: 100,
+            funding_index: 0,
+        },
+    );
+    assert!(risk.validate_order(&tighter, 1, ProposedOrder::limit(Side::Sell, 100, 3)).is_ok());
+    assert!(matches!(buy(&risk, &tighter, 1), Err(RiskError::MaxLeverage)));
+}
+
+#[test]
+fn wal_decode_rejects_oversized_length_prefix() {
+    use hypermarket_clob::persistence::wal::Wal;
+    let mut bytes = u32::MAX.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&[0u8; 8]);
+    assert!(Wal::decode(&bytes).is_err());
+    assert!(Wal::decode(&[1, 2]).unwrap().is_empty());
+}
+
+#[test]
+fn reject_codes_round_trip() {
+    use hypermarket_clob::models::RejectReason;
+    for code in 1..=11 {
+        let reason = RejectReason::from_code(code).expect("registered code");
+        assert_eq!(reason.code(), code);
+    }
+    assert!(RejectReason::from_code(0).is_none());
+}
+
+#[test]
+fn reject_code_registry_is_frozen() {
+    use hypermarket_clob::models::RejectReason;
+    let registry: Vec<(u32, RejectReason)> = RejectReason::ALL.iter().map(|reason| (reason.code(), *reason)).collect();
+    assert_eq!(
+        registry,
+        vec![
+            (1, RejectReason::UnknownMarket),
+            (2, RejectReason::PostOnlyWouldCross),
+            (3, RejectReason::MaxOpenOrders),
+            (4, RejectReason::PriceBand),
+            (5, RejectReason::InsufficientMargin),
+            (6, RejectReason::ReduceOnly),
+            (7, RejectReason::MaxPosition),
+            (8, RejectReason::InvalidOrder),
+            (9, RejectReason::Unauthorized),
+            (10, RejectReason::InvalidSignature),
+            (11, RejectReason::StaleNonce),
+            (12, RejectReason::MaxLeverage),
+            (13, RejectReason::DuplicateNonce),
+            (14, RejectReason::QueueDelay),
+        ]
+    );
+    assert!(RejectReason::ALL.iter().all(|reason| RejectReason::from_code(reason.code()) == Some(*reason)));
+}
+
+#[test]
+fn new_order_builder_enforces_invariants() {
+    use hypermarket_clob::models::{NewOrder, OrderBuildError};
+
+    let order = NewOrder::builder()
+        .request_id("r1")
+        .market_id(1)
+        .subaccount_id(1)
+        .side(Side::Buy)
+        .limit(100)
+        .qty(5)
+        .build()
+        .unwrap();
+    assert_eq!(order.tif, TimeInForce::Gtc);
+
+    let base = NewOrder::builder().request_id("r2").market_id(1).subaccount_id(1).side(Side::Sell);
+    assert_eq!(base.clone().limit(100).build().unwrap_err(), OrderBuildError::ZeroQty);
+    assert_eq!(
+        base.clone().qty(1).build().unwrap_err(),
+        OrderBuildError::MissingPrice(OrderType::Limit)
+    );
+    assert_eq!(
+        base.clone().market().tif(TimeInForce::Gtc).qty(1).build().unwrap_err(),
+        OrderBuildError::IncompatibleTif { order_type: OrderType::Market, tif: TimeInForce::Gtc }
+    );
+    assert!(base.market().qty(1).build().is_ok());
+}
+
+#[test]
+fn pb_new_order_conversion_rejects_unknown_enums() {
+    use hypermarket_clob::models::{pb, ConversionError, NewOrder, OrderBuildError};
+
+    let valid = pb::NewOrder {
+        request_id: "r1".to_string(),
+        market_id: 1,
+        subaccount_id: 1,
+        side: "SELL".to_string(),
+        price_ticks: 100,
+        qty: 1,
+        ..Default::default()
+    };
+    let order = NewOrder::try_from(valid.clone()).unwrap();
+    assert_eq!(order.side, Side::Sell);
+    assert_eq!(order.tif, TimeInForce::Gtc);
+
+    let bad_side = pb::NewOrder { side: "SHORT".to_string(), ..valid.clone() };
+    let err = NewOrder::try_from(bad_side).unwrap_err();
+    assert_eq!(err.request_id, "r1");
+    assert_eq!(err.error, ConversionError::UnknownSide("SHORT".to_string()));
+    let bad_tif = pb::NewOrder { tif: "GTX".to_string(), ..valid.clone() };
+    assert!(matches!(NewOrder::try_from(bad_tif).unwrap_err().error, ConversionError::UnknownTif(_)));
+    let gtd = pb::NewOrder { tif: "GTD".to_string(), expiry_ts: 50, ..valid.clone() };
+    assert_eq!(NewOrder::try_from(gtd).unwrap().tif, TimeInForce::Gtd { expires_at: 50 });
+    let gtd_without_expiry = pb::NewOrder { tif: "GTD".to_string(), ..valid.clone() };
+    assert!(matches!(
+        NewOrder::try_from(gtd_without_expiry).unwrap_err().error,
+        ConversionError::Invalid(OrderBuildError::MissingExpiry)
+    ));
+    let stop = pb::NewOrder { trigger_price: 90, trigger_source: "INDEX".to_string(), ..valid.clone() };
+    let trigger = NewOrder::try_from(stop).unwrap().trigger.unwrap();
+    assert_eq!(trigger.trigger_price, 90);
+    assert_eq!(trigger.source, hypermarket_clob::models::TriggerSource::Index);
+    let bad_source = pb::NewOrder { trigger_price: 90, trigger_source: "LAST".to_string(), ..valid.clone() };
+    assert!(matches!(
+        NewOrder::try_from(bad_source).unwrap_err().error,
+        ConversionError::UnknownTriggerSource(_)
+    ));
+    let zero_qty = pb::NewOrder { qty: 0, ..valid };
+    assert_eq!(NewOrder::try_from(zero_qty).unwrap_err().request_id, "r1");
+}
+
+#[test]
+fn ladder_book_matches_tree_book() {
+    let mut tree = OrderBook::new();
+    let mut ladder = OrderBook::with_layout(0, BookLayout::Ladder { min_price_ticks: 95, max_price_ticks: 105 });
+    // Prices on both sides of the ladder range exercise the overflow map.
+    let prices = [100u64, 97, 103, 90, 120, 101, 99, 105, 95];
+    for (i, price) in prices.iter().enumerate() {
+        for book in [&mut tree, &mut ladder] {
+            let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+            let order = IncomingOrder {
+                order_id: i as u64 + 1,
+                subaccount_id: 1,
+                side,
+                order_type: OrderType::Limit,
+                tif: TimeInForce::Gtc,
+                price_ticks: *price,
+                qty: 3,
+                reduce_only: false,
+                ingress_seq: i as u64,
+            };
+            book.place_order(order, 10);
+        }
+    }
+    let a = tree.snapshot(20);
+    let b = ladder.snapshot(20);
+    assert_eq!(a.bids, b.bids);
+    assert_eq!(a.asks, b.asks);
+    assert_eq!(ladder.cancel(9), tree.cancel(9));
+    assert_eq!(tree.snapshot(20).bids, ladder.snapshot(20).bids);
+}
+
+#[test]
+fn batch_indicative_reports_price_volume_and_imbalance() {
+    use hypermarket_clob::matching::BatchAuction;
+
+    let order = |order_id, side, price_ticks, qty| IncomingOrder {
+        order_id,
+        subaccount_id: order_id,
+        side,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks,
+        qty,
+        reduce_only: false,
+        ingress_seq: order_id,
+    };
+    let mut auction = BatchAuction::default();
+    assert!(auction.indicative(100).is_none());
+    auction.push(order(1, Side::Buy, 102, 5));
+    auction.push(order(2, Side::Sell, 98, 2));
+    let indicative = auction.indicative(100).unwrap();
+    assert_eq!(indicative.matched_qty, 2);
+    assert_eq!(indicative.imbalance_qty, 3);
+    assert_eq!(indicative.imbalance_side, Some(Side::Buy));
+    assert_eq!(auction.pending.len(), 2);
+}
+
+#[test]
+fn batch_auctions_never_match_a_subaccount_against_itself() {
+    use hypermarket_clob::matching::BatchAuction;
+
+    let order = |order_id, subaccount_id, side, qty| IncomingOrder {
+        order_id,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks: 100,
+        qty,
+        reduce_only: false,
+        ingress_seq: order_id,
+    };
+    let mut auction = BatchAuction::default();
+    auction.push(order(1, 7, Side::Sell, 5));
+    auction.push(order(2, 8, Side::Sell, 5));
+    auction.push(order(3, 7, Side::Buy, 8));
+    assert_eq!(auction.indicative(100).unwrap().matched_qty, 5);
+
+    // Subaccount 7's buy skips its own earlier sell and takes subaccount 8's instead.
+    let (result, fills, resting) = auction.clear(100);
+    assert_eq!(result.volume, 5);
+    let matched: Vec<_> = fills.iter().map(|fill| (fill.maker_order_id, fill.taker_order_id, fill.qty)).collect();
+    assert_eq!(matched, vec![(2, 3, 5)]);
+    // Only what is left of each order carries over: all of 7's sell and 3 of its buy.
+    let carried: Vec<_> = resting.iter().map(|order| (order.order_id, order.qty)).collect();
+    assert_eq!(carried, vec![(1, 5), (3, 3)]);
+}
+
+#[test]
+fn continuous_matching_never_fills_a_subaccount_against_itself() {
+    let order = |order_id, subaccount_id, side, price_ticks, qty| IncomingOrder {
+        order_id,
+        subaccount_id,
+        side,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks,
+        qty,
+        reduce_only: false,
+        ingress_seq: order_id,
+    };
+    let mut book = OrderBook::new();
+    book.place_order(order(1, 7, Side::Sell, 100, 5), 10);
+    book.place_order(order(2, 8, Side::Sell, 100, 3), 10);
+    book.place_order(order(3, 7, Side::Sell, 101, 5), 10);
+    book.place_order(order(4, 9, Side::Sell, 102, 2), 10);
+
+    // Subaccount 7's buy skips its own sells at 100 and 101 and takes 8's and 9's behind them.
+    let (fills, rested) = book.place_order(order(5, 7, Side::Buy, 102, 8), 10);
+    let matched: Vec<_> = fills.iter().map(|fill| (fill.maker_order_id, fill.price_ticks, fill.qty)).collect();
+    assert_eq!(matched, vec![(2, 100, 3), (4, 102, 2)]);
+    // The rest would cross its own sells, so it is cancelled instead of resting.
+    assert_eq!(rested, None);
+    assert_eq!(book.snapshot(10).asks, vec![(100, 5), (101, 5)]);
+    assert!(book.snapshot(10).bids.is_empty());
+
+    // FOK counts only other subaccounts' liquidity.
+    let fok = IncomingOrder {
+        tif: TimeInForce::Fok,
+        ..order(6, 7, Side::Buy, 101, 1)
+    };
+    assert!(book.place_order(fok, 10).0.is_empty());
+}
+
+#[test]
+fn wal_open_truncates_a_torn_tail_but_rejects_corruption() {
+    use hypermarket_clob::models::{ClearingSeed, Event, EventEnvelope, SCHEMA_VERSION};
+    use hypermarket_clob::persistence::wal::Wal;
+    let envelope = |engine_seq| EventEnvelope {
+        shard_id: 0,
+        engine_seq,
+        event: Event::ClearingSeed(ClearingSeed { seed: engine_seq }),
+        ts: engine_seq,
+        ts_ns: engine_seq * 1_000_000_000,
+        schema_version: SCHEMA_VERSION,
+    };
+    let path = std::env::temp_dir().join(format!("unit_torn_{}.wal", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut wal = Wal::open(&path).unwrap();
+    wal.append(&envelope(1)).unwrap();
+    wal.append(&envelope(2)).unwrap();
+    drop(wal);
+    let intact = std::fs::read(&path).unwrap();
+
+    // A crash mid-append leaves a length prefix promising more bytes than were written.
+    let mut torn = intact.clone();
+    torn.extend_from_slice(&40u32.to_le_bytes());
+    torn.extend_from_slice(&[7u8; 10]);
+    std::fs::write(&path, &torn).unwrap();
+    assert_eq!(Wal::load(&path).unwrap().len(), 2);
+    let mut wal = Wal::open(&path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), intact);
+    wal.append(&envelope(3)).unwrap();
+    let seqs: Vec<u64> = Wal::load(&path).unwrap().iter().map(|env| env.engine_seq).collect();
+    assert_eq!(seqs, vec![1, 2, 3]);
+    drop(wal);
+
+    // The last record mangled in place is a torn write too, and is cut off.
+    let mut mangled = intact.clone();
+    let last = mangled.len() - 1;
+    mangled[last] ^= 0xff;
+    std::fs::write(&path, &mangled).unwrap();
+    assert_eq!(Wal::load(&path).unwrap().len(), 1);
+    drop(Wal::open(&path).unwrap());
+    assert_eq!(std::fs::read(&path).unwrap().len(), intact.len() / 2);
+
+    // A bad record with records after it is corruption, whether its length or payload was hit.
+    let mut corrupt = intact.clone();
+    corrupt[..4].copy_from_slice(&0u32.to_le_bytes());
+    std::fs::write(&path, &corrupt).unwrap();
+    assert!(Wal::load(&path).is_err());
+    assert!(Wal::open(&path).is_err());
+    let mut flipped = intact;
+    flipped[10] ^= 0x01;
+    std::fs::write(&path, &flipped).unwrap();
+    assert!(Wal::load(&path).is_err());
+    assert!(Wal::open(&path).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), flipped);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn snapshot_save_replaces_the_previous_snapshot_atomically() {
+    use std::collections::BTreeMap;
+
+    use hypermarket_clob::engine::EngineState;
+    use hypermarket_clob::persistence::snapshot::SnapshotStore;
+    use hypermarket_clob::risk::RiskState;
+    let state = |engine_seq| EngineState {
+        shard_id: 0,
+        engine_seq,
+        next_order_id: 1,
+        markets: BTreeMap::new(),
+        risk_state: RiskState {
+            subaccounts: BTreeMap::new(),
+            mark_prices: BTreeMap::new(),
+            funding_indices: BTreeMap::new(),
+        },
+        next_trade_id: 1,
+        clearing_seed: 0,
+        accounts: Vec::new(),
+    };
+    let dir = std::env::temp_dir().join(format!("unit_snapshot_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("snapshot.bin");
+    SnapshotStore::save(&path, &SnapshotStore::build(0, 5, state(5))).unwrap();
+    SnapshotStore::save(&path, &SnapshotStore::build(0, 9, state(9))).unwrap();
+
+    let loaded = SnapshotStore::load(&path).unwrap().expect("snapshot saved");
+    assert_eq!(loaded.meta.last_seq, 9);
+    assert_eq!(loaded.state.engine_seq, 9);
+    assert!(!dir.join("snapshot.bin.tmp").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn snapshot_directories_name_files_by_shard_and_sequence_and_point_at_the_latest() {
+    use std::collections::BTreeMap;
+
+    use hypermarket_clob::engine::EngineState;
+    use hypermarket_clob::persistence::retention::list_snapshots;
+    use hypermarket_clob::persistence::snapshot::SnapshotStore;
+    use hypermarket_clob::risk::RiskState;
+    let state = |shard_id, engine_seq| EngineState {
+        shard_id,
+        engine_seq,
+        next_order_id: 1,
+        markets: BTreeMap::new(),
+        risk_state: RiskState {
+            subaccounts: BTreeMap::new(),
+            mark_prices: BTreeMap::new(),
+            funding_indices: BTreeMap::new(),
+        },
+        next_trade_id: 1,
+        clearing_seed: 0,
+        accounts: Vec::new(),
+    };
+    let dir = std::env::temp_dir().join(format!("unit_snapshot_dir_{}", std::process::id()));
+    assert!(SnapshotStore::load_latest(&dir, 0).unwrap().is_none());
+    for (shard_id, engine_seq) in [(0, 5), (1, 7), (0, 12)] {
+        SnapshotStore::save_to_dir(&dir, &SnapshotStore::build(shard_id, engine_seq, state(shard_id, engine_seq))).unwrap();
+    }
+
+    assert_eq!(SnapshotStore::file_name(0, 12), "snapshot-0-00000000000000000012.bin");
+    assert_eq!(SnapshotStore::load_latest(&dir, 0).unwrap().unwrap().meta.last_seq, 12);
+    assert_eq!(SnapshotStore::load_latest(&dir, 1).unwrap().unwrap().meta.last_seq, 7);
+    let mut listed: Vec<_> = list_snapshots(&dir).unwrap().into_iter().map(|file| (file.shard_id, file.last_seq)).collect();
+    listed.sort_unstable();
+    assert_eq!(listed, vec![(0, 5), (0, 12), (1, 7)]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn corrupt_or_inconsistent_snapshots_are_refused() {
+    use std::collections::BTreeMap;
+
+    use hypermarket_clob::config::AccountConfig;
+    use hypermarket_clob::engine::EngineState;
+    use hypermarket_clob::persistence::snapshot::SnapshotStore;
+    use hypermarket_clob::risk::RiskState;
+    let state = EngineState {
+        shard_id: 0,
+        engine_seq: 5,
+        next_order_id: 1,
+        markets: BTreeMap::new(),
+        risk_state: RiskState {
+            subaccounts: BTreeMap::new(),
+            mark_prices: BTreeMap::from([(1, 100)]),
+            funding_indices: BTreeMap::new(),
+        },
+        next_trade_id: 1,
+        clearing_seed: 0,
+        // The body ends in this cap, so flipping its last byte below still decodes.
+        accounts: vec![AccountConfig {
+            parent_id: 100,
+            subaccounts: vec![1],
+            max_total_position: 0,
+            max_total_open_orders: 10,
+        }],
+    };
+    let path = std::env::temp_dir().join(format!("unit_snapshot_corrupt_{}.bin", std::process::id()));
+    SnapshotStore::save(&path, &SnapshotStore::build(0, 5, state.clone())).unwrap();
+    assert!(SnapshotStore::load(&path).unwrap().is_some());
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(SnapshotStore::load(&path).unwrap_err().to_string().contains("checksum"));
+    assert!(SnapshotStore::load_unchecked(&path).unwrap().is_some());
+
+    SnapshotStore::save(&path, &SnapshotStore::build(0, 6, state)).unwrap();
+    assert!(SnapshotStore::load(&path).unwrap_err().to_string().contains("sequence"));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn version_1_snapshots_load_without_nonce_watermarks() {
+    use std::collections::BTreeMap;
+
+    use hypermarket_clob::engine::shard::OrderSnapshot;
+    use hypermarket_clob::persistence::snapshot::{SnapshotMeta, SnapshotStore, SNAPSHOT_VERSION};
+    use hypermarket_clob::risk::RiskState;
+    let risk_state = RiskState {
+        subaccounts: BTreeMap::new(),
+        mark_prices: BTreeMap::from([(1, 100)]),
+        funding_indices: BTreeMap::new(),
+    };
+    // The version 1 state: shard_id, engine_seq, next_order_id, orderbooks, risk_state.
+    let orderbooks: BTreeMap<u64, Vec<OrderSnapshot>> = BTreeMap::new();
+    let state = (0usize, 3u64, 1u64, orderbooks, risk_state);
+    let meta = SnapshotMeta {
+        version: 1,
+        shard_id: 0,
+        last_seq: 3,
+        checksum: blake3::hash(&bincode::serialize(&state).unwrap()).to_hex().to_string(),
+    };
+    let path = std::env::temp_dir().join(format!("unit_snapshot_v1_{}.bin", std::process::id()));
+    std::fs::write(&path, bincode::serialize(&(meta, state)).unwrap()).unwrap();
+    let loaded = SnapshotStore::load(&path).unwrap().expect("snapshot written");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!((loaded.meta.version, loaded.state.engine_seq), (1, 3));
+    assert_eq!(loaded.state.risk_state.mark_prices[&1], 100);
+    assert!(loaded.state.markets.values().all(|market| market.nonce_watermarks.is_empty()));
+    assert_eq!(SnapshotStore::build(0, 3, loaded.state).meta.version, SNAPSHOT_VERSION);
+}
+
+#[test]
+fn json_codec_mirrors_the_protobuf_messages() {
+    use bytes::Bytes;
+    use hypermarket_clob::config::WireCodec;
+    use hypermarket_clob::engine::router::{decode_input_with, decode_output_with, encode_input_with, encode_output_with};
+    use hypermarket_clob::models::{Event, EventEnvelope, NewOrder, OrderAck, OrderStatus, SCHEMA_VERSION};
+
+    let payload = Bytes::from_static(br#"{"payload":{"cancel_order":{"request_id":"c1","market_id":1,"subaccount_id":2,"order_id":5}}}"#);
+    let Event::CancelOrder(cancel) = decode_input_with(WireCodec::Json, payload).unwrap() else {
+        panic!("expected a cancel");
+    };
+    assert_eq!((cancel.request_id.as_str(), cancel.order_id, cancel.nonce_start), ("c1", Some(5), None));
+
+    let order = NewOrder::builder()
+        .request_id("j1")
+        .market_id(1)
+        .subaccount_id(7)
+        .side(Side::Sell)
+        .limit(100)
+        .qty(3)
+        .build()
+        .unwrap();
+    let encoded = encode_input_with(WireCodec::Json, Event::NewOrder(order)).unwrap();
+    assert!(std::str::from_utf8(&encoded).unwrap().contains(r#""new_order":{"#));
+    let Event::NewOrder(decoded) = decode_input_with(WireCodec::Json, encoded).unwrap() else {
+        panic!("expected an order");
+    };
+    assert_eq!((decoded.request_id.as_str(), decoded.side, decoded.qty), ("j1", Side::Sell, 3));
+    assert!(decode_input_with(WireCodec::Json, Bytes::from_static(b"{not json")).is_err());
+
+    let ack = EventEnvelope {
+        shard_id: 0,
+        engine_seq: 4,
+        event: Event::OrderAck(OrderAck {
+            request_id: "j1".to_string(),
+            status: OrderStatus::Accepted,
+            reject_code: None,
+            reject_reason: None,
+            assigned_order_id: Some(9),
+            engine_seq: 4,
+            ts: 1,
+            filled_qty: 0,
+            remaining_qty: 0,
+            disposition: None,
+            subaccount_id: None,
+        }),
+        ts: 1,
+        ts_ns: 1_000_000_000,
+        schema_version: SCHEMA_VERSION,
+    };
+    let encoded = encode_output_with(WireCodec::Json, ack);
+    let Event::OrderAck(decoded) = decode_output_with(WireCodec::Json, encoded).unwrap() else {
+        panic!("expected an ack");
+    };
+    assert_eq!((decoded.assigned_order_id, decoded.engine_seq), (Some(9), 4));
+}
+
+#[test]
+fn order_lifecycle_and_liquidation_outputs_are_published() {
+    use hypermarket_clob::engine::router::{decode_output, encode_output};
+    use hypermarket_clob::models::{Event, EventEnvelope, Liquidation, OrderUpdate, OrderUpdateStatus, SCHEMA_VERSION};
+
+    let envelope = |event| EventEnvelope {
+        shard_id: 0,
+        engine_seq: 6,
+        event,
+        ts: 2,
+        ts_ns: 2_000_000_000,
+        schema_version: SCHEMA_VERSION,
+    };
+    let update = OrderUpdate {
+        market_id: 1,
+        order_id: 9,
+        subaccount_id: 3,
+        status: OrderUpdateStatus::Cancelled,
+        remaining_qty: 4,
+        engine_seq: 6,
+        ts: 2,
+    };
+    let Event::OrderUpdate(decoded) = decode_output(encode_output(envelope(Event::OrderUpdate(update)))).unwrap() else {
+        panic!("expected an order update");
+    };
+    assert_eq!((decoded.order_id, decoded.status, decoded.remaining_qty), (9, OrderUpdateStatus::Cancelled, 4));
+
+    let liquidation = Liquidation {
+        market_id: 1,
+        subaccount_id: 3,
+        order_id: None,
+        side: Side::Sell,
+        qty: 5,
+        backstop_qty: 5,
+        filled_qty: 0,
+        remaining_position: 5,
+        notional: 500,
+        fee: 5,
+        insurance_fund_subaccount_id: Some(99),
+        bankruptcy_price: 90,
+        shortfall: 0,
+        insurance_covered: 0,
+        adl_shortfall: 0,
+        equity: 40,
+        maintenance_margin: 50,
+        engine_seq: 6,
+        ts: 2,
+    };
+    let Event::Liquidation(decoded) = decode_output(encode_output(envelope(Event::Liquidation(liquidation)))).unwrap() else {
+        panic!("expected a liquidation");
+    };
+    assert_eq!((decoded.order_id, decoded.side, decoded.insurance_fund_subaccount_id), (None, Side::Sell, Some(99)));
+    assert_eq!((decoded.backstop_qty, decoded.fee, decoded.equity), (5, 5, 40));
+}
+
+#[test]
+fn protobuf_snapshots_round_trip_engine_state() {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use hypermarket_clob::config::AccountConfig;
+    use hypermarket_clob::engine::oracle::OracleQuote;
+    use hypermarket_clob::engine::shard::{OrderSnapshot, ReferencePrices};
+    use hypermarket_clob::engine::{EngineState, MarketExport};
+    use hypermarket_clob::models::{NewOrder, OrderTrigger, TriggerSource};
+    use hypermarket_clob::persistence::snapshot::SnapshotStore;
+    use hypermarket_clob::risk::{Position, RiskState, Subaccount};
+    let order = OrderSnapshot {
+        order_id: 7,
+        subaccount_id: 3,
+        side: Side::Sell,
+        order_type: OrderType::PostOnly,
+        tif: TimeInForce::Gtd { expires_at: 90 },
+        price_ticks: 101,
+        remaining: 4,
+        reduce_only: true,
+        ingress_seq: 12,
+    };
+    let pending = IncomingOrder {
+        order_id: 9,
+        subaccount_id: 4,
+        side: Side::Buy,
+        order_type: OrderType::Limit,
+        tif: TimeInForce::Gtc,
+        price_ticks: 99,
+        qty: 3,
+        reduce_only: false,
+        ingress_seq: 14,
+    };
+    let stop = NewOrder::builder()
+        .request_id("stop")
+        .market_id(1)
+        .subaccount_id(3)
+        .side(Side::Buy)
+        .order_type(OrderType::Limit)
+        .price_ticks(110)
+        .qty(2)
+        .nonce(17)
+        .trigger(OrderTrigger {
+            trigger_price: 105,
+            source: TriggerSource::Index,
+        })
+        .build()
+        .unwrap();
+    let position = Position {
+        size: -2,
+        entry_price: 99,
+        funding_index: -8,
+    };
+    let market = MarketExport {
+        config: Some(MarketConfig {
+            market_id: 1,
+            tick_size: 1,
+            lot_size: 1,
+            maker_fee_bps: 0,
+            taker_fee_bps: 0,
+            initial_margin_bps: 1_000,
+            maintenance_margin_bps: 500,
+            max_position: 1_000,
+            price_band_bps: 10_000,
+            max_open_orders_per_subaccount: 0,
+            matching_mode: MatchingMode::Batch,
+            batch_interval_ms: 2000,
+            order_capacity: 0,
+            book_layout: BookLayout::Tree,
+            indicative_interval_secs: 1,
+            clearing_jitter_ms: 0,
+            maker_obligation: None,
+            liquidation_tranche_bps: 2_500,
+            backstop_providers: Vec::new(),
+            liquidation_fee_bps: 0,
+            funding: None,
+            oracle: None,
+            dynamic_band: None,
+            dynamic_margin: None,
+            max_leverage: None,
+        }),
+        orders: vec![order],
+        batch_pending: vec![pending],
+        conditional: vec![(8, stop)],
+        positions: BTreeMap::from([(3, position)]),
+        mark_price: Some(100),
+        funding_index: Some(-8),
+        allowlist: Some(BTreeSet::from([3, 4])),
+        prices: ReferencePrices {
+            mark: Some(100),
+            index: Some(101),
+            last_trade: None,
+        },
+        next_clear_at: Some(2_000),
+        auction_round: 6,
+        nonce_watermarks: BTreeMap::from([(3, 17), (4, 2)]),
+        volatility_marks: vec![98, 100, 101],
+        initial_margin_bps: Some(1_200),
+        oracle_quotes: BTreeMap::from([(
+            "a".to_string(),
+            OracleQuote {
+                mark_price: 100,
+                index_price: 101,
+                ts: 30,
+            },
+        )]),
+        next_funding_at: Some(3_600),
+        next_margin_at: None,
+    };
+    let account = Subaccount {
+        collateral: 5_000,
+        positions: BTreeMap::new(),
+        cross_margin: true,
+    };
+    let state = EngineState {
+        shard_id: 1,
+        engine_seq: 40,
+        next_order_id: 8,
+        markets: BTreeMap::from([(1, market), (3, MarketExport::default())]),
+        risk_state: RiskState {
+            subaccounts: BTreeMap::from([(3, account)]),
+            mark_prices: BTreeMap::new(),
+            funding_indices: BTreeMap::new(),
+        },
+        next_trade_id: (1 << 48) + 5,
+        clearing_seed: 42,
+        accounts: vec![AccountConfig {
+            parent_id: 1,
+            subaccounts: vec![3, 4],
+            max_total_position: 10,
+            max_total_open_orders: 0,
+        }],
+    };
+    let snapshot = SnapshotStore::build(1, 40, state);
+    let path = std::env::temp_dir().join(format!("unit_snapshot_{}.pb", std::process::id()));
+    SnapshotStore::save_proto(&path, &snapshot).unwrap();
+    let loaded = SnapshotStore::load_proto(&path).unwrap().expect("snapshot saved");
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!((loaded.meta.shard_id, loaded.meta.last_seq), (1, 40));
+    assert_eq!(loaded.meta.checksum, snapshot.meta.checksum);
+    assert_eq!(bincode::serialize(&loaded.state).unwrap(), bincode::serialize(&snapshot.state).unwrap());
+}
+
+#[test]
+fn wal_reads_unversioned_records_and_rejects_newer_ones() {
+    use hypermarket_clob::models::{ClearingSeed, Event, EventEnvelope, EventEnvelopeV1, LegacyEventEnvelope, SCHEMA_VERSION};
+    use hypermarket_clob::persistence::wal::Wal;
+    let record = |bytes: Vec<u8>| Wal::frame(&bytes);
+    let legacy = LegacyEventEnvelope {
+        shard_id: 0,
+        engine_seq: 1,
+        event: Event::ClearingSeed(ClearingSeed { seed: 5 }),
+        ts: 1,
+    };
+    let v1 = EventEnvelopeV1 {
+        shard_id: 0,
+        engine_seq: 2,
+        event: Event::ClearingSeed(ClearingSeed { seed: 6 }),
+        ts: 2,
+        schema_version: 1,
+    };
+    let current = EventEnvelope {
+        shard_id: 0,
+        engine_seq: 3,
+        event: Event::ClearingSeed(ClearingSeed { seed: 7 }),
+        ts: 3,
+        ts_ns: 3_000_000_123,
+        schema_version: SCHEMA_VERSION,
+    };
+    let mut bytes = record(bincode::serialize(&legacy).unwrap());
+    bytes.extend(record(bincode::serialize(&v1).unwrap()));
+    bytes.extend(record(bincode::serialize(&current).unwrap()));
+    let decoded = Wal::decode(&bytes).unwrap();
+    let versions: Vec<_> = decoded.iter().map(|env| (env.engine_seq, env.schema_version, env.ts_ns)).collect();
+    // Records from before `ts_ns` are stamped at the start of their second.
+    assert_eq!(versions, vec![(1, 0, 1_000_000_000), (2, 1, 2_000_000_000), (3, SCHEMA_VERSION, 3_000_000_123)]);
+
+    let newer = EventEnvelope {
+        schema_version: SCHEMA_VERSION + 1,
+        ..current
+    };
+    assert!(Wal::decode(&record(bincode::serialize(&newer).unwrap())).is_err());
+}
+
+#[test]
+fn environment_variables_override_the_config_file() {
+    use hypermarket_clob::config::Settings;
+    let dir = std::env::temp_dir().join(format!("unit_settings_env_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.yaml");
+    let yaml = r#"
+bus:
+  nats_url: "nats://127.0.0.1:4222"
+  input_subject: "in"
+  output_subject: "out"
+  durable_name: "test"
+shard_count: 1
+persistence:
+  wal_path: "./data/engine.wal"
+  snapshot_path: "./data/snapshot.bin"
+snapshot_interval_secs: 30
+book_delta_levels: 10
+"#;
+    std::fs::write(&path, yaml).unwrap();
+    let env = [
+        ("CLOB__BUS__NATS_URL", "nats://nats.internal:4222"),
+        ("CLOB__SHARD_COUNT", "4"),
+        ("CLOB__PERSISTENCE__WAL_PATH", "/var/lib/clob/engine.wal"),
+        ("CLOB__RISK__MAX_LEVERAGE", "3"),
+        ("OTHER__SHARD_COUNT", "9"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect();
+    let settings = Settings::load_with_env(path.to_str().unwrap(), Some(env)).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(settings.bus.nats_url, "nats://nats.internal:4222");
+    assert_eq!(settings.shard_count, 4);
+    assert_eq!(settings.persistence.wal_path, "/var/lib/clob/engine.wal");
+    assert_eq!(settings.risk.max_leverage, 3);
+    assert_eq!(settings.bus.input_subject, "in");
+}
+
+#[test]
+fn nonsense_settings_are_rejected_with_the_offending_setting_named() {
+    use hypermarket_clob::config::Settings;
+    let dir = std::env::temp_dir().join(format!("unit_settings_validate_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.yaml");
+    let load_env = |extra: &str, env: Vec<(&str, &str)>| {
+        let yaml = format!(
+            r#"
+bus:
+  nats_url: "nats://127.0.0.1:4222"
+  input_subject: "in"
+  output_subject: "out"
+  durable_name: "test"
+persistence:
+  wal_path: "./data/engine.wal"
+  snapshot_path: "./data/snapshot.bin"
+snapshot_interval_secs: 30
+book_delta_levels: 10
+{extra}
+"#
+        );
+        std::fs::write(&path, yaml).unwrap();
+        let env = env.into_iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        Settings::load_with_env(path.to_str().unwrap(), Some(env)).map_err(|err| err.to_string())
+    };
+    let load = |extra: &str| load_env(extra, Vec::new());
+    let market = |id: u64, tick: u64, initial: u64, maintenance: u64| {
+        format!(
+            "  - {{market_id: {id}, tick_size: {tick}, lot_size: 1, maker_fee_bps: 0, taker_fee_bps: 0, initial_margin_bps: {initial}, maintenance_margin_bps: {maintenance}, max_position: 100, price_band_bps: 0, max_open_orders_per_subaccount: 10, matching_mode: continuous, batch_interval_ms: 0}}\n"
+        )
+    };
+
+    assert!(load(&format!("shard_count: 1\nmarkets:\n{}", market(1, 1, 500, 250))).is_ok());
+    assert!(load("shard_count: 0").unwrap_err().contains("shard_count"));
+    let duplicate = format!("shard_count: 1\nmarkets:\n{}{}", market(1, 1, 500, 250), market(1, 1, 500, 250));
+    assert!(load(&duplicate).unwrap_err().contains("market 1 is listed twice"));
+    let zero_tick = load(&format!("shard_count: 1\nmarkets:\n{}", market(2, 0, 500, 250))).unwrap_err();
+    assert!(zero_tick.contains("market 2") && zero_tick.contains("tick_size"), "{zero_tick}");
+    let margins = load(&format!("shard_count: 1\nmarkets:\n{}", market(3, 1, 250, 250))).unwrap_err();
+    assert!(margins.contains("maintenance_margin_bps"), "{margins}");
+    let budget = load("shard_count: 1\nlatency_budget: {max_queue_delay_ms: 0}").unwrap_err();
+    assert!(budget.contains("latency_budget.max_queue_delay_ms"), "{budget}");
+    let subject = load_env("shard_count: 1", vec![("CLOB__BUS__TRADES_SUBJECT", " ")]).unwrap_err();
+    assert!(subject.contains("bus.trades_subject"), "{subject}");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn marketctl_field_assignments_update_a_stored_market() {
+    use hypermarket_clob::market_registry::set_fields;
+    let market = MarketConfig {
+        market_id: 7,
+        tick_size: 1,
+        lot_size: 1,
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+        initial_margin_bps: 500,
+        maintenance_margin_bps: 250,
+        max_position: 1_000,
+        price_band_bps: 10_000,
+        max_open_orders_per_subaccount: 0,
+        matching_mode: MatchingMode::Continuous,
+        batch_interval_ms: 2000,
+        order_capacity: 0,
+        book_layout: BookLayout::Tree,
+        indicative_interval_secs: 1,
+        clearing_jitter_ms: 0,
+        maker_obligation: None,
+        liquidation_tranche_bps: 2_500,
+        backstop_providers: Vec::new(),
+        liquidation_fee_bps: 0,
+        funding: None,
+        oracle: None,
+        dynamic_band: None,
+        dynamic_margin: None,
+        max_leverage: None,
+    };
+    let set = |assignments: &[&str]| set_fields(&market, &assignments.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+
+    let updated = set(&["taker_fee_bps=5", "matching_mode=batch", "funding.interval_secs=3600", "funding.max_rate_bps=50"]).unwrap();
+    assert_eq!(updated.taker_fee_bps, 5);
+    assert!(matches!(updated.matching_mode, MatchingMode::Batch));
+    assert_eq!(updated.funding.map(|funding| (funding.interval_secs, funding.max_rate_bps)), Some((3600, 50)));
+
+    assert!(set(&["taker_fee_bps"]).is_err());
+    assert!(set(&["tick_size=fast"]).is_err());
+    assert!(set(&["market_id=8"]).is_err());
+    // Assignments are not validated; writes are.
+    assert!(set(&["maintenance_margin_bps=900"]).unwrap().validate().is_err());
+}